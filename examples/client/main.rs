@@ -7,6 +7,7 @@ use rsipstack::dialog::dialog_layer::DialogLayer;
 use rsipstack::dialog::invitation::InviteOption;
 use rsipstack::dialog::server_dialog::ServerInviteDialog;
 use rsipstack::transaction::endpoint::EndpointInnerRef;
+pub use rsipstack::transport::connection::get_first_non_loopback_interface;
 use rsipstack::Result;
 use rsipstack::{
     dialog::{authenticate::Credential, registration::Registration},
@@ -14,7 +15,6 @@ use rsipstack::{
     transport::{udp::UdpConnection, TransportLayer},
     EndpointBuilder, Error,
 };
-use std::net::IpAddr;
 use std::{env, sync::Arc, time::Duration};
 use tokio::time::timeout;
 use tokio::{select, time::sleep};
@@ -23,6 +23,7 @@ use tracing::{debug, error, info};
 
 use crate::play_file::play_echo;
 mod play_file;
+mod stun;
 #[derive(Debug, Clone)]
 struct MediaSessionOption {
     pub random_reject: u32,
@@ -52,6 +53,10 @@ struct Args {
     #[arg(long)]
     external_ip: Option<String>,
 
+    /// STUN server used to find the external SIP address, e.g. `restsend.com:3478`
+    #[arg(long)]
+    stun_server: Option<String>,
+
     /// SIP server address
     #[arg(long)]
     sip_server: Option<String>,
@@ -119,17 +124,6 @@ async fn handle_user_input(
     }
     Ok(())
 }
-pub fn get_first_non_loopback_interface() -> Result<IpAddr> {
-    for i in get_if_addrs::get_if_addrs()? {
-        if !i.is_loopback() {
-            match i.addr {
-                get_if_addrs::IfAddr::V4(ref addr) => return Ok(std::net::IpAddr::V4(addr.ip)),
-                _ => continue,
-            }
-        }
-    }
-    Err(Error::Error("No IPV4 interface found".to_string()))
-}
 // A sip client example, that sends a REGISTER request to a sip server.
 #[tokio::main]
 async fn main() -> rsipstack::Result<()> {
@@ -189,27 +183,42 @@ async fn main() -> rsipstack::Result<()> {
 
     let addr = get_first_non_loopback_interface().expect("get first non loopback interface");
     let connection = UdpConnection::create_connection(
-        format!("{}:{}", addr, args.port).parse()?,
+        std::net::SocketAddr::new(addr, args.port),
         external.clone(),
         Some(token.child_token()),
     )
     .await?;
 
+    // ask before the socket starts serving SIP, Via and Contact then carry
+    // the mapping while the socket stays bound locally
+    let mapped = match &args.stun_server {
+        Some(stun_server) => {
+            Some(stun::external_by_stun(&connection, stun_server, Duration::from_secs(5)).await?)
+        }
+        None => None,
+    };
+
     transport_layer.add_transport(connection.into());
 
-    let endpoint = EndpointBuilder::new()
+    let mut builder = EndpointBuilder::new();
+    builder
         .with_cancel_token(token.clone())
-        .with_transport_layer(transport_layer)
-        .build();
+        .with_transport_layer(transport_layer);
+    if let Some(mapped) = mapped {
+        builder.with_external_addr(mapped);
+    }
+    let endpoint = builder.build();
 
     let credential = Credential {
         username: sip_username.clone(),
         password: sip_password,
         realm: None,
+        algorithm: None,
     };
 
     let incoming = endpoint.incoming_transactions()?;
     let dialog_layer = Arc::new(DialogLayer::new(endpoint.inner.clone()));
+    dialog_layer.route_in_dialog_requests();
 
     let (state_sender, state_receiver) = dialog_layer.new_dialog_state_channel();
 
@@ -388,6 +397,9 @@ async fn process_dialog(
                     Dialog::ClientInvite(_) => {
                         info!("Client invite dialog {}", id);
                     }
+                    Dialog::Subscribe(_) => {
+                        info!("Subscribe dialog {}", id);
+                    }
                 }
             }
             DialogState::Early(id, resp) => {
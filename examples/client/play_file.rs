@@ -1,4 +1,5 @@
 use crate::{get_first_non_loopback_interface, MediaSessionOption};
+use rsipstack::dialog::sdp::{Codec, Direction, MediaDescription, SessionDescription};
 use rsipstack::transport::udp::UdpConnection;
 use rsipstack::Result;
 use rsipstack::{transport::SipAddr, Error};
@@ -20,7 +21,7 @@ pub async fn build_rtp_conn(
     for p in 0..100 {
         let port = opt.rtp_start_port + p * 2;
         if let Ok(c) = UdpConnection::create_connection(
-            format!("{:?}:{}", addr, port).parse()?,
+            SocketAddr::new(addr, port),
             opt.external_ip
                 .as_ref()
                 .map(|ip| ip.parse::<SocketAddr>().expect("Invalid external IP")),
@@ -40,27 +41,20 @@ pub async fn build_rtp_conn(
     }
 
     let conn = conn.unwrap();
-    let codec = payload_type;
-    let codec_name = match codec {
-        0 => "PCMU",
-        8 => "PCMA",
-        _ => "Unknown",
+    let codec = match payload_type {
+        8 => Codec::pcma(),
+        _ => Codec::pcmu(),
     };
     let socketaddr: SocketAddr = conn.get_addr().addr.to_owned().try_into()?;
-    let sdp = format!(
-        "v=0\r\n\
-        o=- 0 0 IN IP4 {}\r\n\
-        s=rsipstack example\r\n\
-        c=IN IP4 {}\r\n\
-        t=0 0\r\n\
-        m=audio {} RTP/AVP {codec}\r\n\
-        a=rtpmap:{codec} {codec_name}/8000\r\n\
-        a=ssrc:{ssrc}\r\n\
-        a=sendrecv\r\n",
-        socketaddr.ip(),
-        socketaddr.ip(),
-        socketaddr.port(),
-    );
+    let media = MediaDescription::audio(socketaddr.port(), vec![codec])
+        .with_attribute(&format!("ssrc:{}", ssrc))
+        .with_direction(Direction::SendRecv);
+    let sdp = SessionDescription::new(socketaddr.ip())
+        .with_origin("-", 0, 0)
+        .with_session_name("rsipstack example")
+        .with_media(media)
+        .to_bytes();
+    let sdp = String::from_utf8_lossy(&sdp).to_string();
     info!("RTP socket: {:?} {}", conn.get_addr(), sdp);
     Ok((conn, sdp))
 }
@@ -1,7 +1,6 @@
-use get_if_addrs::get_if_addrs;
 use rsipstack::transport::{udp::UdpConnection, SipAddr};
 use rsipstack::{Error, Result};
-use std::net::{IpAddr, SocketAddr};
+use std::net::SocketAddr;
 use std::time::Duration;
 use stun_rs::{
     attributes::stun::XorMappedAddress, methods::BINDING, MessageClass, MessageDecoderBuilder,
@@ -12,20 +11,8 @@ use tokio::select;
 use tokio::time::sleep;
 use tracing::info;
 
-pub fn get_first_non_loopback_interface() -> Result<IpAddr> {
-    for i in get_if_addrs()? {
-        if !i.is_loopback() {
-            match i.addr {
-                get_if_addrs::IfAddr::V4(ref addr) => return Ok(std::net::IpAddr::V4(addr.ip)),
-                _ => continue,
-            }
-        }
-    }
-    Err(Error::Error("No IPV4 interface found".to_string()))
-}
-
 pub async fn external_by_stun(
-    conn: &mut UdpConnection,
+    conn: &UdpConnection,
     stun_server: &str,
     expires: Duration,
 ) -> Result<SocketAddr> {
@@ -77,11 +64,7 @@ pub async fn external_by_stun(
         .map_err(|e| crate::Error::Error(e.to_string()))?;
     let socket: &SocketAddr = xor_addr.socket_address();
     info!("external IP: {}", socket);
-    conn.external = Some(SipAddr {
-        r#type: Some(rsip::transport::Transport::Udp),
-        addr: socket.to_owned().into(),
-    });
-    Ok(socket.clone())
+    Ok(*socket)
 }
 
 #[tokio::test]
@@ -90,9 +73,9 @@ async fn test_external_with_stun() -> Result<()> {
     for addr in addrs {
         info!("stun server: {}", addr);
     }
-    let mut peer_bob = UdpConnection::create_connection("0.0.0.0:0".parse()?, None).await?;
+    let peer_bob = UdpConnection::create_connection("0.0.0.0:0".parse()?, None, None).await?;
     let expires = Duration::from_secs(5);
-    external_by_stun(&mut peer_bob, "restsend.com:3478", expires).await?;
-    info!("external IP: {:?}", peer_bob.get_addr());
+    let external = external_by_stun(&peer_bob, "restsend.com:3478", expires).await?;
+    info!("external IP: {} local: {:?}", external, peer_bob.get_addr());
     Ok(())
 }
@@ -11,7 +11,6 @@ use axum::{
 };
 use clap::Parser;
 use futures::{SinkExt, StreamExt};
-use get_if_addrs::get_if_addrs;
 use rsip::headers::UntypedHeader;
 use rsip::prelude::{HeadersExt, ToTypedHeader};
 use rsip::SipMessage;
@@ -22,6 +21,7 @@ use rsipstack::transaction::key::{TransactionKey, TransactionRole};
 use rsipstack::transaction::transaction::Transaction;
 use rsipstack::transaction::TransactionReceiver;
 use rsipstack::transport::channel::ChannelConnection;
+use rsipstack::transport::connection::get_first_non_loopback_interface;
 use rsipstack::transport::tcp_listener::TcpListenerConnection;
 use rsipstack::transport::udp::UdpConnection;
 #[cfg(feature = "websocket")]
@@ -115,18 +115,11 @@ async fn main() -> Result<()> {
     };
     let addr = match args.addr {
         Some(addr) => addr.parse::<std::net::IpAddr>()?,
-        None => get_if_addrs()?
-            .iter()
-            .find(|i| !i.is_loopback())
-            .map(|i| match i.addr {
-                get_if_addrs::IfAddr::V4(ref addr) => Ok(std::net::IpAddr::V4(addr.ip)),
-                _ => Err(Error::Error("No IPv4 address found".to_string())),
-            })
-            .unwrap_or(Err(Error::Error("No interface found".to_string())))?,
+        None => get_first_non_loopback_interface()?,
     };
 
     let connection = UdpConnection::create_connection(
-        format!("{}:{}", addr, args.port).parse()?,
+        std::net::SocketAddr::new(addr, args.port),
         external.clone(),
         None,
     )
@@ -136,9 +129,7 @@ async fn main() -> Result<()> {
 
     if let Some(tcp_port) = args.tcp_port {
         let local_addr = SipAddr {
-            addr: format!("{}:{}", addr, tcp_port)
-                .parse::<std::net::SocketAddr>()?
-                .into(),
+            addr: std::net::SocketAddr::new(addr, tcp_port).into(),
             r#type: Some(rsip::transport::Transport::Tcp),
         };
         let external_addr = if !external_ip.is_empty() {
@@ -185,9 +176,7 @@ async fn main() -> Result<()> {
         #[cfg(feature = "websocket")]
         {
             let local_addr = SipAddr {
-                addr: format!("{}:{}", addr, ws_port)
-                    .parse::<std::net::SocketAddr>()?
-                    .into(),
+                addr: std::net::SocketAddr::new(addr, ws_port).into(),
                 r#type: Some(rsip::transport::Transport::Ws),
             };
             let external_addr = if !external_ip.is_empty() {
@@ -749,6 +738,7 @@ async fn handle_websocket(client_addr: ClientAddr, socket: WebSocket, _state: Ap
                         info!("Transport connection closed");
                         break;
                     }
+                    Some(TransportEvent::BadRequest(..)) => {}
                     None => {
                         info!("Transport channel closed");
                         break;
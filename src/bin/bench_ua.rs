@@ -380,6 +380,7 @@ async fn main() -> Result<()> {
 
     let incoming = endpoint.incoming_transactions()?;
     let dialog_layer = Arc::new(DialogLayer::new(endpoint.inner.clone()));
+    dialog_layer.route_in_dialog_requests();
     let (state_sender, state_receiver) = dialog_layer.new_dialog_state_channel();
     let stats = Stats::new();
 
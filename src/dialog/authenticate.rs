@@ -1,13 +1,14 @@
 use super::DialogId;
 use crate::transaction::key::{TransactionKey, TransactionRole};
 use crate::transaction::transaction::Transaction;
-use crate::transaction::{make_via_branch, random_text, CNONCE_LEN};
+use crate::transaction::{random_text, rebranch_top_via, CNONCE_LEN};
 use crate::Result;
-use rsip::headers::auth::{AuthQop, Qop};
-use rsip::prelude::{HasHeaders, HeadersExt, ToTypedHeader};
-use rsip::services::DigestGenerator;
-use rsip::typed::{Authorization, ProxyAuthorization};
-use rsip::{Header, Param, Response};
+use md5::Md5;
+use rsip::headers::auth::{Algorithm, AuthQop, Qop};
+use rsip::prelude::{HasHeaders, HeadersExt, ToTypedHeader, UntypedHeader};
+use rsip::typed::{Authorization, WwwAuthenticate};
+use rsip::{Header, Response};
+use sha2::{Digest, Sha256, Sha512_256};
 
 /// SIP Authentication Credentials
 ///
@@ -21,6 +22,8 @@ use rsip::{Header, Param, Response};
 /// * `username` - The username for authentication
 /// * `password` - The password for authentication
 /// * `realm` - Optional authentication realm (extracted from challenge)
+/// * `algorithm` - Digest algorithm to answer with when the server offers
+///   several; otherwise the strongest offered one is used
 ///
 /// # Examples
 ///
@@ -33,6 +36,7 @@ use rsip::{Header, Param, Response};
 ///     username: "alice".to_string(),
 ///     password: "secret123".to_string(),
 ///     realm: Some("example.com".to_string()),
+///     algorithm: None,
 /// };
 /// # Ok(())
 /// # }
@@ -47,6 +51,7 @@ use rsip::{Header, Param, Response};
 ///     username: "alice".to_string(),
 ///     password: "secret123".to_string(),
 ///     realm: None, // Will be extracted from server challenge
+///     algorithm: None,
 /// };
 ///
 /// // Use credential with registration
@@ -66,6 +71,7 @@ use rsip::{Header, Param, Response};
 /// #     username: "alice".to_string(),
 /// #     password: "secret123".to_string(),
 /// #     realm: Some("example.com".to_string()),
+/// #     algorithm: None,
 /// # };
 /// let invite_option = InviteOption {
 ///     caller: rsip::Uri::try_from("sip:alice@example.com")?,
@@ -84,6 +90,197 @@ pub struct Credential {
     pub username: String,
     pub password: String,
     pub realm: Option<String>,
+    pub algorithm: Option<Algorithm>,
+}
+
+/// Input of a digest `response` computation (RFC 7616 §3.4.1)
+///
+/// Covers MD5, SHA-256 and SHA-512-256 with their `-sess` variants
+/// (RFC 8760), and `qop=auth-int`, which also hashes `body`.
+///
+/// # Examples
+///
+/// The SHA-256 example of RFC 7616 §3.9.1:
+///
+/// ```rust
+/// use rsip::headers::auth::{Algorithm, AuthQop};
+/// use rsipstack::dialog::authenticate::DigestInput;
+///
+/// let qop = AuthQop::Auth {
+///     cnonce: "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ".to_string(),
+///     nc: 1,
+/// };
+/// let response = DigestInput {
+///     algorithm: Algorithm::Sha256,
+///     username: "Mufasa",
+///     password: "Circle of Life",
+///     realm: "http-auth@example.org",
+///     nonce: "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v",
+///     method: "GET",
+///     uri: "/dir/index.html",
+///     qop: Some(&qop),
+///     body: &[],
+/// }
+/// .compute();
+/// assert_eq!(
+///     response,
+///     "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1"
+/// );
+/// ```
+pub struct DigestInput<'a> {
+    pub algorithm: Algorithm,
+    pub username: &'a str,
+    pub password: &'a str,
+    pub realm: &'a str,
+    pub nonce: &'a str,
+    pub method: &'a str,
+    pub uri: &'a str,
+    pub qop: Option<&'a AuthQop>,
+    pub body: &'a [u8],
+}
+
+impl DigestInput<'_> {
+    pub fn compute(&self) -> String {
+        let hash = |data: &str| digest_hex(self.algorithm, data.as_bytes());
+        let (qop, cnonce, nc) = match self.qop {
+            Some(AuthQop::Auth { cnonce, nc }) => (Some("auth"), cnonce.as_str(), *nc),
+            Some(AuthQop::AuthInt { cnonce, nc }) => (Some("auth-int"), cnonce.as_str(), *nc),
+            None => (None, "", 0),
+        };
+
+        let mut ha1 = hash(&format!(
+            "{}:{}:{}",
+            self.username, self.realm, self.password
+        ));
+        if matches!(
+            self.algorithm,
+            Algorithm::Md5Sess | Algorithm::Sha256Sess | Algorithm::Sha512Sess
+        ) {
+            ha1 = hash(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = match qop {
+            Some("auth-int") => hash(&format!(
+                "{}:{}:{}",
+                self.method,
+                self.uri,
+                digest_hex(self.algorithm, self.body)
+            )),
+            _ => hash(&format!("{}:{}", self.method, self.uri)),
+        };
+        match qop {
+            Some(qop) => hash(&format!(
+                "{}:{}:{:08x}:{}:{}:{}",
+                ha1, self.nonce, nc, cnonce, qop, ha2
+            )),
+            None => hash(&format!("{}:{}:{}", ha1, self.nonce, ha2)),
+        }
+    }
+}
+
+fn digest_hex(algorithm: Algorithm, data: &[u8]) -> String {
+    let digest = match algorithm {
+        Algorithm::Md5 | Algorithm::Md5Sess => Md5::digest(data).to_vec(),
+        Algorithm::Sha256 | Algorithm::Sha256Sess => Sha256::digest(data).to_vec(),
+        Algorithm::Sha512 | Algorithm::Sha512Sess => Sha512_256::digest(data).to_vec(),
+    };
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Digest algorithm named by an `algorithm` token: `MD5`, `SHA-256` or
+/// `SHA-512-256`, each with a `-sess` form (RFC 7616 §6.1, RFC 8760)
+///
+/// rsip only parses its own `SHA256`/`SHA512` spelling, so the tokens are
+/// matched here. [`Algorithm::Sha512`] stands for SHA-512-256.
+pub fn parse_algorithm(token: &str) -> Option<Algorithm> {
+    let algorithm = match token.to_ascii_uppercase().as_str() {
+        "MD5" => Algorithm::Md5,
+        "MD5-SESS" => Algorithm::Md5Sess,
+        "SHA-256" => Algorithm::Sha256,
+        "SHA-256-SESS" => Algorithm::Sha256Sess,
+        "SHA-512-256" => Algorithm::Sha512,
+        "SHA-512-256-SESS" => Algorithm::Sha512Sess,
+        _ => return None,
+    };
+    Some(algorithm)
+}
+
+/// RFC 7616 name of `algorithm`, the inverse of [`parse_algorithm`]
+pub fn algorithm_name(algorithm: Algorithm) -> &'static str {
+    match algorithm {
+        Algorithm::Md5 => "MD5",
+        Algorithm::Md5Sess => "MD5-sess",
+        Algorithm::Sha256 => "SHA-256",
+        Algorithm::Sha256Sess => "SHA-256-sess",
+        Algorithm::Sha512 => "SHA-512-256",
+        Algorithm::Sha512Sess => "SHA-512-256-sess",
+    }
+}
+
+// parse a WWW-/Proxy-Authenticate value, reading `algorithm` with
+// `parse_algorithm`; a challenge with an unknown algorithm is skipped
+fn parse_challenge(value: &str) -> Option<WwwAuthenticate> {
+    let mut params = Vec::new();
+    for param in value.split(',') {
+        match param.split_once('=') {
+            Some((name, token))
+                if name
+                    .split_whitespace()
+                    .last()
+                    .is_some_and(|n| n.eq_ignore_ascii_case("algorithm")) =>
+            {
+                let algorithm = parse_algorithm(token.trim().trim_matches('"'))?;
+                // rsip's spelling, which its typed parser reads back
+                params.push(format!("{}={}", name, algorithm));
+            }
+            _ => params.push(param.to_string()),
+        }
+    }
+    rsip::headers::WwwAuthenticate::new(params.join(","))
+        .typed()
+        .ok()
+}
+
+fn algorithm_strength(algorithm: Algorithm) -> u8 {
+    match algorithm {
+        Algorithm::Md5 | Algorithm::Md5Sess => 0,
+        Algorithm::Sha256 | Algorithm::Sha256Sess => 1,
+        Algorithm::Sha512 | Algorithm::Sha512Sess => 2,
+    }
+}
+
+/// Challenge to answer among those of a 401/407, with whether it came in
+/// `WWW-Authenticate`
+///
+/// The credential's preferred algorithm wins when offered, otherwise the
+/// strongest algorithm does (RFC 8760 §2.4); a challenge without
+/// `algorithm` is MD5.
+fn select_challenge(
+    resp: &Response,
+    preferred: Option<Algorithm>,
+) -> Option<(bool, WwwAuthenticate)> {
+    let mut challenges = resp
+        .headers()
+        .iter()
+        .filter_map(|h| match h {
+            Header::WwwAuthenticate(h) => parse_challenge(h.value()).map(|c| (true, c)),
+            Header::ProxyAuthenticate(h) => parse_challenge(h.value()).map(|c| (false, c)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let algorithm = |c: &WwwAuthenticate| c.algorithm.unwrap_or(Algorithm::Md5);
+    if let Some(preferred) = preferred {
+        if let Some(idx) = challenges
+            .iter()
+            .position(|(_, c)| algorithm(c) == preferred)
+        {
+            return Some(challenges.swap_remove(idx));
+        }
+    }
+    // `max_by_key` keeps the last of equals, so the first listed wins ties
+    challenges
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, c)| algorithm_strength(algorithm(c)))
 }
 
 /// Handle client-side authentication challenge
@@ -120,6 +317,7 @@ pub struct Credential {
 /// #     username: "alice".to_string(),
 /// #     password: "secret123".to_string(),
 /// #     realm: Some("example.com".to_string()),
+/// #     algorithm: None,
 /// # };
 /// // This is typically called automatically by dialog methods
 /// let new_tx = handle_client_authenticate(
@@ -147,6 +345,7 @@ pub struct Credential {
 /// #     username: "alice".to_string(),
 /// #     password: "secret123".to_string(),
 /// #     realm: Some("example.com".to_string()),
+/// #     algorithm: None,
 /// # };
 /// # let new_seq = 2u32;
 /// // Send initial request
@@ -190,29 +389,17 @@ pub async fn handle_client_authenticate(
     resp: Response,
     cred: &Credential,
 ) -> Result<Transaction> {
-    let header = match resp.www_authenticate_header() {
-        Some(h) => Header::WwwAuthenticate(h.clone()),
-        None => {
-            let code = resp.status_code.clone();
-            let proxy_header = rsip::header_opt!(resp.headers().iter(), Header::ProxyAuthenticate);
-            let proxy_header = proxy_header.ok_or(crate::Error::DialogError(
-                "missing proxy/www authenticate".to_string(),
-                DialogId::try_from(&tx.original)?,
-                code,
-            ))?;
-            Header::ProxyAuthenticate(proxy_header.clone())
-        }
+    let Some((www_authenticate, challenge)) = select_challenge(&resp, cred.algorithm) else {
+        return Err(crate::Error::DialogError(
+            "missing proxy/www authenticate".to_string(),
+            DialogId::try_from(&tx.original)?,
+            resp.status_code.clone(),
+        ));
     };
 
     let mut new_req = tx.original.clone();
     new_req.cseq_header_mut()?.mut_seq(new_seq)?;
 
-    let challenge = match &header {
-        Header::WwwAuthenticate(h) => h.typed()?,
-        Header::ProxyAuthenticate(h) => h.typed()?.0,
-        _ => unreachable!(),
-    };
-
     let cnonce = random_text(CNONCE_LEN);
     let auth_qop = match challenge.qop {
         Some(Qop::Auth) => Some(AuthQop::Auth { cnonce, nc: 1 }),
@@ -225,15 +412,16 @@ pub async fn handle_client_authenticate(
         .algorithm
         .unwrap_or(rsip::headers::auth::Algorithm::Md5);
 
-    let response = DigestGenerator {
+    let response = DigestInput {
+        algorithm,
         username: cred.username.as_str(),
         password: cred.password.as_str(),
-        algorithm,
+        realm: challenge.realm.as_str(),
         nonce: challenge.nonce.as_str(),
-        method: &tx.original.method,
+        method: &tx.original.method.to_string(),
+        uri: &tx.original.uri.to_string(),
         qop: auth_qop.as_ref(),
-        uri: &tx.original.uri,
-        realm: challenge.realm.as_str(),
+        body: &tx.original.body,
     }
     .compute();
 
@@ -244,17 +432,18 @@ pub async fn handle_client_authenticate(
         nonce: challenge.nonce,
         uri: tx.original.uri.clone(),
         response,
-        algorithm: Some(algorithm),
+        // written below under its RFC 7616 name
+        algorithm: None,
         opaque: challenge.opaque,
         qop: auth_qop,
     };
+    let auth = format!(
+        "{}, algorithm={}",
+        rsip::headers::Authorization::from(auth).value(),
+        algorithm_name(algorithm)
+    );
 
-    let mut via_header = tx.original.via_header()?.clone().typed()?;
-    let params = &mut via_header.params;
-    params.retain(|p| !matches!(p, rsip::Param::Branch(_)));
-    params.push(make_via_branch());
-    params.push(Param::Other("rport".into(), None));
-    new_req.headers_mut().unique_push(via_header.into());
+    rebranch_top_via(&mut new_req)?;
 
     new_req.headers_mut().retain(|h| {
         !matches!(
@@ -266,16 +455,14 @@ pub async fn handle_client_authenticate(
         )
     });
 
-    match header {
-        Header::WwwAuthenticate(_) => {
-            new_req.headers_mut().unique_push(auth.into());
-        }
-        Header::ProxyAuthenticate(_) => {
-            new_req
-                .headers_mut()
-                .unique_push(ProxyAuthorization(auth).into());
-        }
-        _ => unreachable!(),
+    if www_authenticate {
+        new_req
+            .headers_mut()
+            .unique_push(rsip::headers::Authorization::new(auth).into());
+    } else {
+        new_req
+            .headers_mut()
+            .unique_push(rsip::headers::ProxyAuthorization::new(auth).into());
     }
     let key = TransactionKey::from_request(&new_req, TransactionRole::Client)?;
     let mut new_tx = Transaction::new_client(
@@ -0,0 +1,140 @@
+use crate::rsip_ext::{header_values_case_insensitive, split_header_list, split_header_params};
+use rsip::Header;
+
+/// A caller preference from `Accept-Contact` or `Reject-Contact` (RFC 3841)
+///
+/// Each preference lists feature tags (`audio`, `video`,
+/// `methods="INVITE,BYE"`, ...) that a registered Contact has to carry.
+/// `require` and `explicit` only have a meaning in `Accept-Contact`.
+///
+/// # Examples
+///
+/// ```rust
+/// use rsipstack::dialog::caller_preferences::ContactPreference;
+///
+/// let pref = ContactPreference::default()
+///     .with_feature("audio", None)
+///     .with_require()
+///     .with_explicit();
+/// assert_eq!(pref.to_string(), "*;audio;require;explicit");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContactPreference {
+    pub feature_tags: Vec<(String, Option<String>)>,
+    pub require: bool,
+    pub explicit: bool,
+}
+
+impl ContactPreference {
+    pub fn with_feature(mut self, name: &str, value: Option<&str>) -> Self {
+        self.feature_tags
+            .push((name.to_string(), value.map(|v| v.to_string())));
+        self
+    }
+
+    pub fn with_require(mut self) -> Self {
+        self.require = true;
+        self
+    }
+
+    pub fn with_explicit(mut self) -> Self {
+        self.explicit = true;
+        self
+    }
+
+    /// Parse a single `ac-value`/`rc-value` such as `*;audio;require`
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut params = split_header_params(value).into_iter();
+        if params.next()?.trim() != "*" {
+            return None;
+        }
+        let mut pref = ContactPreference::default();
+        for param in params {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().to_string())),
+                None => (param.trim(), None),
+            };
+            if name.is_empty() {
+                continue;
+            }
+            match (name.to_ascii_lowercase().as_str(), &value) {
+                ("require", None) => pref.require = true,
+                ("explicit", None) => pref.explicit = true,
+                _ => pref.feature_tags.push((name.to_string(), value)),
+            }
+        }
+        Some(pref)
+    }
+
+    /// Check the preference against the feature tags of a registered Contact
+    ///
+    /// Every feature tag of the preference must be present; tags with a
+    /// value must match it, ignoring quotes and case.
+    pub fn matches(&self, contact_tags: &[(String, Option<String>)]) -> bool {
+        self.feature_tags.iter().all(|(name, value)| {
+            contact_tags.iter().any(|(tag, tag_value)| {
+                tag.eq_ignore_ascii_case(name)
+                    && match value {
+                        Some(value) => tag_value
+                            .as_deref()
+                            .is_some_and(|v| unquote(v).eq_ignore_ascii_case(unquote(value))),
+                        None => true,
+                    }
+            })
+        })
+    }
+}
+
+impl std::fmt::Display for ContactPreference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "*")?;
+        for (name, value) in &self.feature_tags {
+            match value {
+                Some(value) => write!(f, ";{}={}", name, value)?,
+                None => write!(f, ";{}", name)?,
+            }
+        }
+        if self.require {
+            write!(f, ";require")?;
+        }
+        if self.explicit {
+            write!(f, ";explicit")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn accept_contact_header(pref: &ContactPreference) -> Header {
+    Header::Other("Accept-Contact".into(), pref.to_string())
+}
+
+pub fn reject_contact_header(pref: &ContactPreference) -> Header {
+    Header::Other("Reject-Contact".into(), pref.to_string())
+}
+
+fn parse_preferences(headers: &rsip::Headers, name: &str, compact: &str) -> Vec<ContactPreference> {
+    header_values_case_insensitive(headers, name)
+        .into_iter()
+        .chain(header_values_case_insensitive(headers, compact))
+        .flat_map(|value| {
+            split_header_list(&value)
+                .into_iter()
+                .filter_map(ContactPreference::parse)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// All preferences from `Accept-Contact` (or its compact form `a`)
+pub fn parse_accept_contact(headers: &rsip::Headers) -> Vec<ContactPreference> {
+    parse_preferences(headers, "Accept-Contact", "a")
+}
+
+/// All preferences from `Reject-Contact` (or its compact form `j`)
+pub fn parse_reject_contact(headers: &rsip::Headers) -> Vec<ContactPreference> {
+    parse_preferences(headers, "Reject-Contact", "j")
+}
+
+fn unquote(value: &str) -> &str {
+    value.trim().trim_matches('"')
+}
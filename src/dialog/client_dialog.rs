@@ -1,16 +1,22 @@
-use super::dialog::DialogInnerRef;
+use super::dialog::{DialogInner, DialogInnerRef, DialogRequest};
+use super::refer::ReplacesInfo;
 use super::DialogId;
 use crate::dialog::{
     authenticate::handle_client_authenticate,
     dialog::{DialogState, TerminatedReason},
+    session_timer::handle_session_interval_too_small,
 };
-use crate::rsip_ext::RsipResponseExt;
-use crate::transaction::transaction::Transaction;
+use crate::rsip_ext::{Reason, RsipResponseExt};
+use crate::transaction::{key::TransactionRole, transaction::Transaction};
+use crate::transport::SipAddr;
 use crate::Result;
-use rsip::prelude::HasHeaders;
 use rsip::{prelude::HeadersExt, Header};
-use rsip::{Response, SipMessage, StatusCode};
-use std::sync::atomic::Ordering;
+use rsip::{Request, Response, SipMessage, StatusCode};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{atomic::Ordering, Arc};
+use std::time::Duration;
+use tokio::sync::{broadcast::error::RecvError, mpsc::UnboundedReceiver};
 use tokio_util::sync::CancellationToken;
 use tracing::{info, trace};
 
@@ -23,7 +29,7 @@ use tracing::{info, trace};
 /// # Key Features
 ///
 /// * **Session Initiation** - Initiates INVITE transactions to establish calls
-/// * **In-dialog Requests** - Sends UPDATE, INFO, OPTIONS within established dialogs
+/// * **In-dialog Requests** - Sends UPDATE, INFO, OPTIONS, REFER within established dialogs
 /// * **Session Termination** - Handles BYE and CANCEL for ending sessions
 /// * **Re-INVITE Support** - Supports session modification via re-INVITE
 /// * **Authentication** - Handles 401/407 authentication challenges
@@ -105,6 +111,129 @@ impl ClientInviteDialog {
         self.inner.state.lock().unwrap().clone()
     }
 
+    /// Wait for the next state transition of this dialog
+    ///
+    /// The returned future sees every transition made after `next_state`
+    /// is called, the same states sent to the dialog's state channel, and
+    /// resolves with the first one. It resolves to `None` once the dialog
+    /// is dropped. It does not borrow the dialog, so it can be raced
+    /// against a timeout in `select!`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::client_dialog::ClientInviteDialog;
+    /// # use std::time::Duration;
+    /// # async fn example() -> rsipstack::Result<()> {
+    /// # let dialog: ClientInviteDialog = todo!();
+    /// tokio::select! {
+    ///     Some(state) = dialog.next_state() => println!("dialog moved to {}", state),
+    ///     _ = tokio::time::sleep(Duration::from_secs(5)) => println!("no change"),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn next_state(&self) -> impl Future<Output = Option<DialogState>> + Send + 'static {
+        let mut receiver = self.inner.state_events.subscribe();
+        async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(state) => return Some(state),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }
+    }
+
+    /// Wait until the dialog is confirmed
+    ///
+    /// Resolves once the dialog reaches `Confirmed`. Fails if the dialog is
+    /// terminated first, or if it is still not confirmed after `timeout`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::client_dialog::ClientInviteDialog;
+    /// # use std::time::Duration;
+    /// # async fn example() -> rsipstack::Result<()> {
+    /// # let dialog: ClientInviteDialog = todo!();
+    /// dialog.await_confirmed(Duration::from_secs(30)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn await_confirmed(&self, timeout: Duration) -> Result<()> {
+        let wait_confirmed = async {
+            loop {
+                let notified = self.inner.state_notify.notified();
+                tokio::pin!(notified);
+                // register before checking the state so no transition is missed
+                notified.as_mut().enable();
+                match self.state() {
+                    DialogState::Confirmed(_, _) => return Ok(()),
+                    DialogState::Terminated(id, reason) => {
+                        let code = match &reason {
+                            TerminatedReason::Timeout => StatusCode::RequestTimeout,
+                            TerminatedReason::UacBusy | TerminatedReason::UasBusy => {
+                                StatusCode::BusyHere
+                            }
+                            TerminatedReason::UasDecline => StatusCode::Decline,
+                            TerminatedReason::ProxyAuthRequired => {
+                                StatusCode::ProxyAuthenticationRequired
+                            }
+                            TerminatedReason::SubscriptionTerminated(_) => {
+                                StatusCode::CallTransactionDoesNotExist
+                            }
+                            TerminatedReason::SessionTimerNegotiationFailed => {
+                                StatusCode::SessionIntervalTooSmall
+                            }
+                            TerminatedReason::ConnectionClosed => StatusCode::ServiceUnavailable,
+                            TerminatedReason::PeerUnreachable => StatusCode::RequestTimeout,
+                            TerminatedReason::ProxyError(code)
+                            | TerminatedReason::UacOther(code)
+                            | TerminatedReason::UasOther(code) => code.clone(),
+                            TerminatedReason::UacCancel => StatusCode::RequestTerminated,
+                            TerminatedReason::UacBye | TerminatedReason::UasBye => {
+                                StatusCode::CallTransactionDoesNotExist
+                            }
+                        };
+                        return Err(crate::Error::DialogError(
+                            format!("dialog terminated: {:?}", reason),
+                            id,
+                            code,
+                        ));
+                    }
+                    _ => {}
+                }
+                notified.await;
+            }
+        };
+        match tokio::time::timeout(timeout, wait_confirmed).await {
+            Ok(r) => r,
+            Err(_) => Err(crate::Error::DialogError(
+                "timeout waiting for dialog confirmation".to_string(),
+                self.id(),
+                StatusCode::RequestTimeout,
+            )),
+        }
+    }
+
+    /// Local address and transport of the dialog's initial INVITE
+    ///
+    /// For TCP and TLS this is the local end of the connection, for UDP the
+    /// socket the INVITE was sent from. `None` before the INVITE is sent.
+    pub fn local_addr(&self) -> Option<SipAddr> {
+        self.inner.local_addr.lock().unwrap().clone()
+    }
+
+    /// Remote address and transport of the dialog's initial INVITE
+    ///
+    /// For TCP and TLS this is the peer of the connection, for UDP the
+    /// destination the INVITE was resolved to.
+    pub fn remote_addr(&self) -> Option<SipAddr> {
+        self.inner.remote_addr.lock().unwrap().clone()
+    }
+
     /// Get the cancellation token for this dialog
     ///
     /// Returns a reference to the CancellationToken that can be used to
@@ -124,6 +253,18 @@ impl ClientInviteDialog {
         }
     }
 
+    /// Hang up the call with a `Reason` header (RFC 3326)
+    ///
+    /// Like [`Self::hangup`], with `reason` on the CANCEL or BYE. A UAS
+    /// built on this stack repeats the Reason of a CANCEL on its 487.
+    pub async fn hangup_with_reason(&self, reason: Reason) -> Result<()> {
+        if self.inner.can_cancel() {
+            self.send_cancel(vec![reason.into()]).await
+        } else {
+            self.send_bye(Some(vec![reason.into()])).await
+        }
+    }
+
     /// Send a BYE request to terminate the dialog
     ///
     /// Sends a BYE request to gracefully terminate an established dialog.
@@ -147,12 +288,16 @@ impl ClientInviteDialog {
     /// # }
     /// ```
     pub async fn bye(&self) -> Result<()> {
+        self.send_bye(None).await
+    }
+
+    async fn send_bye(&self, headers: Option<Vec<Header>>) -> Result<()> {
         if !self.inner.is_confirmed() {
             return Ok(());
         }
-        let request = self
-            .inner
-            .make_request(rsip::Method::Bye, None, None, None, None, None)?;
+        let request =
+            self.inner
+                .make_request(rsip::Method::Bye, None, None, None, headers, None)?;
 
         match self.inner.do_request(request).await {
             Ok(_) => {}
@@ -171,10 +316,15 @@ impl ClientInviteDialog {
     /// yet been answered with a final response. This is used to abort
     /// call setup before the call is established.
     ///
+    /// The CANCEL is built from the INVITE as RFC 3261 §9.1 requires: same
+    /// Request-URI, Call-ID, From, To and top Via branch, and the INVITE's
+    /// CSeq number with the CANCEL method. It is only sent while the dialog
+    /// is calling, trying or early.
+    ///
     /// # Returns
     ///
-    /// * `Ok(())` - CANCEL was sent successfully
-    /// * `Err(Error)` - Failed to send CANCEL request
+    /// * `Ok(())` - CANCEL was sent, or the dialog already ended
+    /// * `Err(Error)` - A 2xx already arrived, or sending the CANCEL failed
     ///
     /// # Examples
     ///
@@ -188,27 +338,36 @@ impl ClientInviteDialog {
     /// # }
     /// ```
     pub async fn cancel(&self) -> Result<()> {
-        if self.inner.is_confirmed() {
-            return Ok(());
+        self.send_cancel(vec![]).await
+    }
+
+    async fn send_cancel(&self, headers: Vec<Header>) -> Result<()> {
+        let state = self.inner.state.lock().unwrap().clone();
+        match state {
+            DialogState::Calling(_) | DialogState::Trying(_) | DialogState::Early(_, _) => {}
+            DialogState::WaitAck(_, _) | DialogState::Confirmed(_, _) => {
+                return Err(crate::Error::DialogError(
+                    "invite already answered, send BYE instead".to_string(),
+                    self.id(),
+                    StatusCode::CallTransactionDoesNotExist,
+                ));
+            }
+            _ => {
+                info!(id=%self.id(), "nothing to cancel in state {}", state);
+                return Ok(());
+            }
         }
         info!(id=%self.id(),"sending cancel request");
-        let mut cancel_request = self
+        let invite = self
             .inner
             .initial_request
             .lock()
             .expect("cancel mutext poisoned")
             .clone();
-        let invite_seq = cancel_request.cseq_header()?.seq()?;
-        cancel_request
-            .headers_mut()
-            .retain(|h| !matches!(h, Header::ContentLength(_) | Header::ContentType(_)));
-
-        cancel_request.method = rsip::Method::Cancel;
-        cancel_request
-            .cseq_header_mut()?
-            .mut_seq(invite_seq)?
-            .mut_method(rsip::Method::Cancel)?;
-        cancel_request.body = vec![];
+        let mut cancel_request = self.inner.endpoint_inner.make_cancel(&invite)?;
+        for header in headers {
+            cancel_request.headers.push(header);
+        }
         self.inner.do_request(cancel_request).await?;
         Ok(())
     }
@@ -226,9 +385,13 @@ impl ClientInviteDialog {
     ///
     /// # Returns
     ///
-    /// * `Ok(Some(Response))` - Response to the re-INVITE
+    /// * `Ok(Some(Response))` - Response to the re-INVITE; a 2xx is ACKed
     /// * `Ok(None)` - Dialog not confirmed, no request sent
-    /// * `Err(Error)` - Failed to send re-INVITE
+    /// * `Err(Error)` - Failed to send re-INVITE, or 491 Request Pending
+    ///   while another re-INVITE of this dialog is outstanding
+    ///
+    /// A 491 from the peer, whose re-INVITE crossed ours, is retried after
+    /// a random backoff (RFC 3261 §14.1).
     ///
     /// # Examples
     ///
@@ -250,27 +413,50 @@ impl ClientInviteDialog {
             return Ok(None);
         }
         info!(id=%self.id(),"sending re-invite request, body:\n{:?}", body);
-        let request =
-            self.inner
-                .make_request(rsip::Method::Invite, None, None, None, headers, body)?;
-        let resp = self.inner.do_request(request.clone()).await;
-        match resp {
-            Ok(Some(ref resp)) => {
-                if resp.status_code == StatusCode::OK {
-                    self.inner
-                        .transition(DialogState::Updated(self.id(), request))?;
-                }
-            }
-            _ => {}
-        }
-        resp
+        self.inner.send_reinvite(headers, body).await
+    }
+
+    /// Send a re-INVITE carrying `offer`, described by `content_type`
+    /// (`application/sdp` by default)
+    ///
+    /// Same as [`ClientInviteDialog::reinvite`] with the Content-Type filled
+    /// in, e.g. to put the call on hold with an `a=sendonly` offer and resume
+    /// it later with `a=sendrecv`. The SDP answer is the body of the 2xx.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::client_dialog::ClientInviteDialog;
+    /// # use rsipstack::dialog::sdp::{Direction, SessionDescription};
+    /// # async fn example() -> rsipstack::Result<()> {
+    /// # let dialog: ClientInviteDialog = todo!();
+    /// # let mut sdp: SessionDescription = todo!();
+    /// sdp.set_direction(Direction::SendOnly);
+    /// let answer = dialog
+    ///     .reinvite_offer(sdp.to_bytes(), None)
+    ///     .await?
+    ///     .filter(|resp| resp.status_code.kind() == rsip::StatusCodeKind::Successful)
+    ///     .map(|resp| resp.body);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn reinvite_offer(
+        &self,
+        offer: Vec<u8>,
+        content_type: Option<String>,
+    ) -> Result<Option<rsip::Response>> {
+        let headers = vec![Header::ContentType(
+            content_type.unwrap_or("application/sdp".to_string()).into(),
+        )];
+        self.reinvite(Some(headers), Some(offer)).await
     }
 
     /// Send an UPDATE request to modify session parameters
     ///
-    /// Sends an UPDATE request within an established dialog to modify
-    /// session parameters without the complexity of a re-INVITE.
-    /// This is typically used for smaller session modifications.
+    /// Sends an UPDATE request within an established or early dialog to
+    /// modify session parameters without the complexity of a re-INVITE.
+    /// In an early dialog this renegotiates media before the 200 OK
+    /// (RFC 3311).
     ///
     /// # Parameters
     ///
@@ -280,7 +466,7 @@ impl ClientInviteDialog {
     /// # Returns
     ///
     /// * `Ok(Some(Response))` - Response to the UPDATE
-    /// * `Ok(None)` - Dialog not confirmed, no request sent
+    /// * `Ok(None)` - Dialog neither confirmed nor early, no request sent
     /// * `Err(Error)` - Failed to send UPDATE
     ///
     /// # Examples
@@ -299,14 +485,49 @@ impl ClientInviteDialog {
         headers: Option<Vec<rsip::Header>>,
         body: Option<Vec<u8>>,
     ) -> Result<Option<rsip::Response>> {
-        if !self.inner.is_confirmed() {
+        if !self.inner.can_update() {
             return Ok(None);
         }
         info!(id=%self.id(),"sending update request, body:\n{:?}", body);
         let request =
             self.inner
                 .make_request(rsip::Method::Update, None, None, None, headers, body)?;
-        self.inner.do_request(request.clone()).await
+        let resp = self.inner.do_request(request.clone()).await;
+        if let Ok(Some(ref resp)) = resp {
+            if resp.status_code.kind() == rsip::StatusCodeKind::Successful {
+                self.inner.set_local_sdp(&request.body);
+                self.inner.set_remote_sdp(&resp.body);
+            }
+        }
+        resp
+    }
+
+    /// Send an UPDATE carrying `offer`, described by `content_type`
+    /// (`application/sdp` by default)
+    ///
+    /// Same as [`ClientInviteDialog::update`] with the Content-Type filled in.
+    pub async fn update_offer(
+        &self,
+        offer: Option<Vec<u8>>,
+        content_type: Option<String>,
+    ) -> Result<Option<rsip::Response>> {
+        let headers = offer.as_ref().map(|_| {
+            vec![Header::ContentType(
+                content_type.unwrap_or("application/sdp".to_string()).into(),
+            )]
+        });
+        self.update(headers, offer).await
+    }
+
+    /// Receive the BYE, INFO, UPDATE and re-INVITE requests of this dialog
+    /// to answer them
+    ///
+    /// Each arrives as a [`DialogRequest`] after the usual `DialogState`
+    /// update, and the dialog waits for its reply. A BYE answered with 2xx
+    /// terminates the dialog. Requests are answered 200 OK when no receiver
+    /// was taken or a `DialogRequest` is dropped unanswered.
+    pub fn incoming_requests(&self) -> UnboundedReceiver<DialogRequest> {
+        self.inner.incoming_requests()
     }
 
     /// Send an INFO request for mid-dialog information
@@ -356,6 +577,40 @@ impl ClientInviteDialog {
         self.inner.do_request(request.clone()).await
     }
 
+    /// Send an INFO carrying `body` of type `content_type`
+    pub async fn send_info(
+        &self,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> Result<Option<rsip::Response>> {
+        self.inner.send_info(content_type, body).await
+    }
+
+    /// Send a key press as an `application/dtmf-relay` INFO
+    ///
+    /// `digit` is one of `0`-`9`, `*`, `#` or `A`-`D`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::client_dialog::ClientInviteDialog;
+    /// # use std::time::Duration;
+    /// # async fn example() -> rsipstack::Result<()> {
+    /// # let dialog: ClientInviteDialog = todo!();
+    /// for digit in "1234#".chars() {
+    ///     dialog.send_dtmf(digit, Duration::from_millis(160)).await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_dtmf(
+        &self,
+        digit: char,
+        duration: Duration,
+    ) -> Result<Option<rsip::Response>> {
+        self.inner.send_dtmf(digit, duration).await
+    }
+
     pub async fn options(
         &self,
         headers: Option<Vec<rsip::Header>>,
@@ -370,6 +625,123 @@ impl ClientInviteDialog {
                 .make_request(rsip::Method::Options, None, None, None, headers, body)?;
         self.inner.do_request(request.clone()).await
     }
+
+    /// Ping the peer with an in-dialog OPTIONS every `interval`
+    ///
+    /// A ping that fails, times out or is answered with 481 counts as a
+    /// miss, any other response resets the count. After `max_misses` misses
+    /// in a row the dialog is terminated with
+    /// `TerminatedReason::PeerUnreachable`, without sending a BYE. Each ping
+    /// is its own transaction with the next CSeq, so requests sent by the
+    /// application are not held up. Pinging stops when the dialog ends.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::client_dialog::ClientInviteDialog;
+    /// # use std::time::Duration;
+    /// # fn example(dialog: ClientInviteDialog) {
+    /// // give up on the call after three unanswered pings, 30s apart
+    /// dialog.enable_session_ping(Duration::from_secs(30), 3);
+    /// # }
+    /// ```
+    pub fn enable_session_ping(&self, interval: Duration, max_misses: u32) {
+        let dialog = self.clone();
+        tokio::spawn(async move {
+            let mut misses = 0;
+            loop {
+                tokio::select! {
+                    _ = dialog.inner.cancel_token.cancelled() => break,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+                if dialog.inner.is_terminated() {
+                    break;
+                }
+                if !dialog.inner.is_confirmed() {
+                    continue;
+                }
+                let answered = match dialog.options(None, None).await {
+                    Ok(Some(resp)) => !matches!(
+                        resp.status_code,
+                        StatusCode::RequestTimeout | StatusCode::CallTransactionDoesNotExist
+                    ),
+                    Ok(None) => continue,
+                    Err(e) => {
+                        info!(id=%dialog.id(), "session ping error: {}", e);
+                        false
+                    }
+                };
+                if answered {
+                    misses = 0;
+                    continue;
+                }
+                misses += 1;
+                info!(id=%dialog.id(), misses, "session ping unanswered");
+                if misses >= max_misses {
+                    dialog
+                        .inner
+                        .transition(DialogState::Terminated(
+                            dialog.id(),
+                            TerminatedReason::PeerUnreachable,
+                        ))
+                        .ok();
+                    dialog.inner.cancel_token.cancel();
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Ask the peer to transfer the call with an in-dialog REFER (RFC 3515)
+    ///
+    /// With `replaces` set the peer is asked to replace that dialog at the
+    /// transfer target (attended transfer, RFC 3891). Progress reported by
+    /// the peer arrives as `DialogState::ReferProgress`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Some(Response))` - Response to the REFER, 202 when accepted
+    /// * `Ok(None)` - Dialog not confirmed, no request sent
+    /// * `Err(Error)` - Failed to send REFER
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::client_dialog::ClientInviteDialog;
+    /// # async fn example() -> rsipstack::Result<()> {
+    /// # let dialog: ClientInviteDialog = todo!();
+    /// let target = rsip::Uri::try_from("sip:carol@example.com")?;
+    /// let response = dialog.refer(target, None).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn refer(
+        &self,
+        refer_to: rsip::Uri,
+        replaces: Option<ReplacesInfo>,
+    ) -> Result<Option<rsip::Response>> {
+        if !self.inner.is_confirmed() {
+            return Ok(None);
+        }
+        info!(id=%self.id(), %refer_to, "sending refer request");
+        self.inner
+            .send_refer(&refer_to, replaces.as_ref(), None)
+            .await
+    }
+
+    /// Report the progress of a transfer we were asked to make
+    ///
+    /// Sends a NOTIFY of the implicit REFER subscription with `status` as
+    /// its sipfrag, e.g. 100 Trying while calling the target and the final
+    /// answer of the target afterwards, which ends the subscription.
+    pub async fn notify_refer(&self, status: StatusCode) -> Result<Option<rsip::Response>> {
+        if !self.inner.is_confirmed() {
+            return Ok(None);
+        }
+        info!(id=%self.id(), %status, "sending refer notify");
+        self.inner.send_refer_notify(status).await
+    }
+
     /// Handle incoming transaction for this dialog
     ///
     /// Processes incoming SIP requests that are routed to this dialog.
@@ -390,7 +762,9 @@ impl ClientInviteDialog {
     /// * `BYE` - Terminates the dialog
     /// * `INFO` - Handles information exchange
     /// * `OPTIONS` - Handles capability queries
-    /// * `UPDATE` - Handles session updates
+    /// * `UPDATE` - Handles session updates, also in an early dialog
+    /// * `REFER` - Accepts a call transfer request
+    /// * `NOTIFY` - Handles transfer progress and other notifications
     /// * `INVITE` - Handles re-INVITE (when confirmed)
     pub async fn handle(&mut self, tx: &mut Transaction) -> Result<()> {
         trace!(
@@ -401,25 +775,23 @@ impl ClientInviteDialog {
         );
 
         let cseq = tx.original.cseq_header()?.seq()?;
-        let remote_seq = self.inner.remote_seq.load(Ordering::Relaxed);
-        if remote_seq > 0 && cseq < remote_seq {
-            info!(id=%self.id(),"received old request remote_seq: {} > {}", remote_seq, cseq);
-            tx.reply(rsip::StatusCode::ServerInternalError).await?;
+        if !self.inner.accept_remote_seq(cseq) {
+            info!(id=%self.id(),"received old request remote_seq: {} > {}", self.inner.remote_seq.load(Ordering::Relaxed), cseq);
+            if tx.original.method != rsip::Method::Ack {
+                tx.reply(rsip::StatusCode::ServerInternalError).await?;
+            }
             return Ok(());
         }
 
-        self.inner
-            .remote_seq
-            .compare_exchange(remote_seq, cseq, Ordering::Relaxed, Ordering::Relaxed)
-            .ok();
-
         if self.inner.is_confirmed() {
             match tx.original.method {
                 rsip::Method::Invite => return self.handle_reinvite(tx).await,
                 rsip::Method::Bye => return self.handle_bye(tx).await,
-                rsip::Method::Info => return self.handle_info(tx).await,
+                rsip::Method::Info => return self.inner.handle_info(tx).await,
                 rsip::Method::Options => return self.handle_options(tx).await,
-                rsip::Method::Update => return self.handle_update(tx).await,
+                rsip::Method::Update => return self.inner.handle_update(tx).await,
+                rsip::Method::Refer => return self.inner.handle_refer(tx).await,
+                rsip::Method::Notify => return self.inner.handle_notify(tx).await,
                 _ => {
                     info!(id=%self.id(), "invalid request method: {:?}", tx.original.method);
                     tx.reply(rsip::StatusCode::MethodNotAllowed).await?;
@@ -430,6 +802,8 @@ impl ClientInviteDialog {
                     ));
                 }
             }
+        } else if tx.original.method == rsip::Method::Update && self.inner.can_update() {
+            return self.inner.handle_update(tx).await;
         } else {
             info!(id=%self.id(),
                 "received request not confirmed: {:?}",
@@ -441,17 +815,11 @@ impl ClientInviteDialog {
 
     async fn handle_bye(&mut self, tx: &mut Transaction) -> Result<()> {
         info!(id=%self.id(), "received bye {}", tx.original.uri);
-        self.inner
-            .transition(DialogState::Terminated(self.id(), TerminatedReason::UasBye))?;
-        tx.reply(rsip::StatusCode::OK).await?;
-        Ok(())
-    }
-
-    async fn handle_info(&mut self, tx: &mut Transaction) -> Result<()> {
-        info!(id=%self.id(),"received info {}", tx.original.uri);
-        self.inner
-            .transition(DialogState::Info(self.id(), tx.original.clone()))?;
-        tx.reply(rsip::StatusCode::OK).await?;
+        let resp = self.inner.answer_request(tx).await?;
+        if resp.status_code.kind() == rsip::StatusCodeKind::Successful {
+            self.inner
+                .transition(DialogState::Terminated(self.id(), TerminatedReason::UasBye))?;
+        }
         Ok(())
     }
 
@@ -463,19 +831,24 @@ impl ClientInviteDialog {
         Ok(())
     }
 
-    async fn handle_update(&mut self, tx: &mut Transaction) -> Result<()> {
-        info!(id=%self.id(),"received update {}", tx.original.uri);
-        self.inner
-            .transition(DialogState::Updated(self.id(), tx.original.clone()))?;
-        tx.reply(rsip::StatusCode::OK).await?;
-        Ok(())
-    }
-
     async fn handle_reinvite(&mut self, tx: &mut Transaction) -> Result<()> {
         info!(id=%self.id(),"received reinvite {}", tx.original.uri);
+        // an INVITE of ours is still outstanding (RFC 3261 §14.2)
+        let Ok(_pending) = self.inner.begin_invite() else {
+            info!(id = %self.id(), "re-invite glare, replying 491");
+            tx.reply(rsip::StatusCode::RequestPending).await?;
+            return Ok(());
+        };
+        if self.inner.is_session_refresh(&tx.original) {
+            return self.inner.answer_session_refresh(tx).await;
+        }
+        self.inner.set_remote_sdp(&tx.original.body);
         self.inner
             .transition(DialogState::Updated(self.id(), tx.original.clone()))?;
-        tx.reply(rsip::StatusCode::OK).await?;
+        let resp = self.inner.answer_request(tx).await?;
+        if resp.status_code.kind() == rsip::StatusCodeKind::Successful {
+            self.inner.set_local_sdp(&resp.body);
+        }
 
         // wait for ACK
         while let Some(msg) = tx.receive().await {
@@ -490,15 +863,40 @@ impl ClientInviteDialog {
         Ok(())
     }
 
-    pub async fn process_invite(
+    pub async fn process_invite(&self, tx: Transaction) -> Result<(DialogId, Option<Response>)> {
+        self.process_invite_with_forks(tx, |_| {}).await
+    }
+
+    /// Run the INVITE transaction like [`process_invite`](Self::process_invite),
+    /// keeping a separate dialog for each UAS that answers a forked request
+    ///
+    /// This dialog follows the first branch to answer and is confirmed by
+    /// the first 2xx. Every other To tag seen in a 1xx or 2xx gets its own
+    /// `ClientInviteDialog`, handed to `on_fork` when it is created and
+    /// reported with its own id through the same state channel. Every 2xx is
+    /// ACKed by the dialog it confirms, and a retransmitted 2xx gets the same
+    /// ACK again; the TU keeps the call it wants and sends BYE to the others.
+    /// Forks that are still early when the transaction ends are terminated.
+    pub async fn process_invite_with_forks<F>(
         &self,
         mut tx: Transaction,
-    ) -> Result<(DialogId, Option<Response>)> {
+        on_fork: F,
+    ) -> Result<(DialogId, Option<Response>)>
+    where
+        F: Fn(&ClientInviteDialog) + Send + Sync + 'static,
+    {
         self.inner.transition(DialogState::Calling(self.id()))?;
         let mut auth_sent = false;
+        let mut session_timer_retried = false;
+        tx.tu_acks_2xx = true;
         tx.send().await?;
+        self.inner
+            .bind_connection(tx.connection.as_ref(), tx.destination.as_ref());
         let mut dialog_id = self.id();
         let mut final_response = None;
+        let mut forks = HashMap::new();
+        // ACK sent for the 2xx of each branch, by To tag
+        let mut acks = HashMap::new();
         while let Some(msg) = tx.receive().await {
             match msg {
                 SipMessage::Request(_) => {}
@@ -511,6 +909,11 @@ impl ClientInviteDialog {
                     }
 
                     if matches!(status.kind(), rsip::StatusCodeKind::Provisional) {
+                        if let Some(fork) = self.fork_for(&resp, &mut forks, &on_fork)? {
+                            fork.inner.handle_provisional_response(&resp).await?;
+                            fork.inner.transition(DialogState::Early(fork.id(), resp))?;
+                            continue;
+                        }
                         self.inner.handle_provisional_response(&resp).await?;
                         self.inner.transition(DialogState::Early(self.id(), resp))?;
                         continue;
@@ -538,7 +941,10 @@ impl ClientInviteDialog {
                                 credential,
                             )
                             .await?;
+                            tx.tu_acks_2xx = true;
                             tx.send().await?;
+                            self.inner
+                                .bind_connection(tx.connection.as_ref(), tx.destination.as_ref());
                             self.inner.update_remote_tag("").ok();
                             // Update initial_request with the new invite request
                             {
@@ -559,9 +965,58 @@ impl ClientInviteDialog {
                             continue;
                         }
                     }
+                    if status == StatusCode::SessionIntervalTooSmall {
+                        // retry once with the peer's Min-SE (RFC 4028 §7.4); a
+                        // second 422 means the negotiation does not converge
+                        let retry = if session_timer_retried {
+                            None
+                        } else {
+                            handle_session_interval_too_small(
+                                self.inner.increment_local_seq(),
+                                &tx,
+                                &resp,
+                            )
+                            .ok()
+                        };
+                        match retry {
+                            Some(new_tx) => {
+                                session_timer_retried = true;
+                                tx = new_tx;
+                                tx.tu_acks_2xx = true;
+                                tx.send().await?;
+                                self.inner.bind_connection(
+                                    tx.connection.as_ref(),
+                                    tx.destination.as_ref(),
+                                );
+                                self.inner.update_remote_tag("").ok();
+                                {
+                                    let mut req = self
+                                        .inner
+                                        .initial_request
+                                        .lock()
+                                        .expect("update initial request mutex poisoned");
+                                    *req = tx.original.clone();
+                                }
+                                continue;
+                            }
+                            None => {
+                                info!(id=%self.id(),"session timer negotiation failed");
+                                final_response = Some(resp);
+                                self.inner.transition(DialogState::Terminated(
+                                    self.id(),
+                                    TerminatedReason::SessionTimerNegotiationFailed,
+                                ))?;
+                                break;
+                            }
+                        }
+                    }
                     final_response = Some(resp.clone());
                     match resp.to_header()?.tag()? {
-                        Some(tag) => self.inner.update_remote_tag(tag.value())?,
+                        Some(tag) => {
+                            // this dialog takes over the branch that answered first
+                            forks.remove(tag.value());
+                            self.inner.update_remote_tag(tag.value())?
+                        }
                         None => {}
                     }
 
@@ -579,31 +1034,168 @@ impl ClientInviteDialog {
                             self.inner.update_route_set_from_response(&resp);
                         }
                         StatusCode::OK => {
-                            self.inner.update_route_set_from_response(&resp);
-                            // 200 response to INVITE always contains Contact header
-                            let contact = resp.contact_header()?;
-                            self.inner
-                                .remote_contact
-                                .lock()
-                                .unwrap()
-                                .replace(contact.clone());
-
-                            *self.inner.remote_uri.lock().unwrap() =
-                                resp.remote_uri(tx.destination.as_ref())?;
-                            self.inner
-                                .transition(DialogState::Confirmed(dialog_id.clone(), resp))?;
+                            self.confirm(dialog_id.clone(), resp.clone(), tx.destination.as_ref())?;
                         }
                         _ => {
-                            self.inner.transition(DialogState::Terminated(
-                                self.id(),
-                                TerminatedReason::UasOther(resp.status_code.clone()),
-                            ))?;
+                            // a followed 3xx goes on in a new INVITE of this call
+                            let redirect = self.inner.redirects.lock().unwrap().target(&resp);
+                            let state = match redirect {
+                                Some(target) => DialogState::Redirecting(self.id(), target),
+                                None => DialogState::Terminated(
+                                    self.id(),
+                                    TerminatedReason::UasOther(resp.status_code.clone()),
+                                ),
+                            };
+                            self.inner.transition(state)?;
                         }
                     }
+                    if status.kind() == rsip::StatusCodeKind::Successful {
+                        let ack = self.make_ack(&tx.original, &resp)?;
+                        send_ack(&mut tx, &ack).await.ok();
+                        acks.insert(to_tag(&resp), ack);
+                    }
                     break;
                 }
             }
         }
+
+        match final_response {
+            Some(ref resp) if resp.status_code.kind() == rsip::StatusCodeKind::Successful => {
+                // other branches may still answer until the transaction ends
+                let dialog = self.clone();
+                tokio::spawn(async move {
+                    while let Some(msg) = tx.receive().await {
+                        let SipMessage::Response(resp) = msg else {
+                            continue;
+                        };
+                        if resp.status_code.kind() != rsip::StatusCodeKind::Successful {
+                            continue;
+                        }
+                        // a retransmitted 2xx gets the ACK already sent for its branch
+                        if let Some(ack) = acks.get(&to_tag(&resp)) {
+                            send_ack(&mut tx, ack).await.ok();
+                            continue;
+                        }
+                        let fork = match dialog.fork_for(&resp, &mut forks, &on_fork) {
+                            Ok(Some(fork)) => fork,
+                            _ => continue,
+                        };
+                        if fork
+                            .confirm(fork.id(), resp.clone(), tx.destination.as_ref())
+                            .is_err()
+                        {
+                            continue;
+                        }
+                        if let Ok(ack) = fork.make_ack(&tx.original, &resp) {
+                            send_ack(&mut tx, &ack).await.ok();
+                            acks.insert(to_tag(&resp), ack);
+                        }
+                    }
+                    terminate_forks(forks, TerminatedReason::Timeout);
+                });
+            }
+            Some(ref resp) => {
+                terminate_forks(forks, TerminatedReason::UasOther(resp.status_code.clone()))
+            }
+            None => terminate_forks(forks, TerminatedReason::Timeout),
+        }
         Ok((dialog_id, final_response))
     }
+
+    /// Take the remote target, route set and answer from a 2xx to the INVITE
+    fn confirm(
+        &self,
+        dialog_id: DialogId,
+        resp: Response,
+        destination: Option<&SipAddr>,
+    ) -> Result<()> {
+        self.inner.update_route_set_from_response(&resp);
+        // 200 response to INVITE always contains Contact header
+        let contact = resp.contact_header()?;
+        self.inner
+            .remote_contact
+            .lock()
+            .unwrap()
+            .replace(contact.clone());
+
+        *self.inner.remote_uri.lock().unwrap() = resp.remote_uri(destination)?;
+        self.inner.set_remote_sdp(&resp.body);
+        self.inner
+            .transition(DialogState::Confirmed(dialog_id, resp))
+    }
+
+    /// ACK for a 2xx to `invite`, once the 2xx has confirmed this dialog
+    ///
+    /// The ACK is sent to the remote target (the Contact of the 2xx) along
+    /// the route set from its Record-Route, with the To tag of the 2xx and
+    /// the CSeq number of the INVITE.
+    fn make_ack(&self, invite: &Request, resp: &Response) -> Result<Request> {
+        self.inner.make_ack(invite, resp)
+    }
+
+    /// Dialog of another UAS answering a forked INVITE, created on the first
+    /// response from its branch; `None` for responses from this dialog's branch
+    fn fork_for<F>(
+        &self,
+        resp: &Response,
+        forks: &mut HashMap<String, ClientInviteDialog>,
+        on_fork: &F,
+    ) -> Result<Option<ClientInviteDialog>>
+    where
+        F: Fn(&ClientInviteDialog),
+    {
+        let Some(tag) = resp.to_header()?.tag()? else {
+            return Ok(None);
+        };
+        let own_tag = self.id().to_tag;
+        if own_tag.is_empty() || own_tag == tag.value() {
+            return Ok(None);
+        }
+        if let Some(fork) = forks.get(tag.value()) {
+            return Ok(Some(fork.clone()));
+        }
+        let initial_request = self.inner.initial_request.lock().unwrap().clone();
+        let inner = DialogInner::new(
+            TransactionRole::Client,
+            DialogId::try_from(resp)?,
+            initial_request,
+            self.inner.endpoint_inner.clone(),
+            self.inner.state_sender.clone(),
+            self.inner.credential.clone(),
+            self.inner.local_contact.clone(),
+            self.inner.tu_sender.clone(),
+        )?;
+        *inner.connection.lock().unwrap() = self.inner.connection.lock().unwrap().clone();
+        let fork = ClientInviteDialog {
+            inner: Arc::new(inner),
+        };
+        info!(id = %fork.id(), "forked invite answered by another UAS");
+        on_fork(&fork);
+        forks.insert(tag.value().to_string(), fork.clone());
+        Ok(Some(fork))
+    }
+}
+
+/// Send `ack` through the INVITE transaction, which resolves its target
+async fn send_ack(tx: &mut Transaction, ack: &Request) -> Result<()> {
+    tx.last_ack.replace(ack.clone());
+    tx.send_ack(None).await
+}
+
+fn to_tag(resp: &Response) -> String {
+    resp.to_header()
+        .ok()
+        .and_then(|to| to.tag().ok().flatten())
+        .map(|tag| tag.value().to_string())
+        .unwrap_or_default()
+}
+
+fn terminate_forks(forks: HashMap<String, ClientInviteDialog>, reason: TerminatedReason) {
+    for fork in forks.into_values() {
+        if !fork.inner.is_confirmed() {
+            fork.inner
+                .transition(DialogState::Terminated(fork.id(), reason.clone()))
+                .ok();
+        }
+    }
 }
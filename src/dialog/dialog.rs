@@ -1,33 +1,47 @@
 use super::{
     authenticate::{handle_client_authenticate, Credential},
     client_dialog::ClientInviteDialog,
+    dtmf::Dtmf,
+    invitation::Redirects,
+    refer::ReferTo,
+    sdp::{sdp_media_changed, sdp_rtp_target},
     server_dialog::ServerInviteDialog,
+    subscription::SubscribeDialog,
     DialogId,
 };
 use crate::{
-    rsip_ext::{extract_uri_from_contact, header_contains_token, parse_rseq_header},
+    rsip_ext::{extract_uri_from_contact, header_contains_token, parse_rseq_header, Reason},
     transaction::{
         endpoint::EndpointInnerRef,
         key::{TransactionKey, TransactionRole},
         make_via_branch,
+        message::max_forwards_header,
+        route_set::RouteSet,
         transaction::{Transaction, TransactionEventSender},
     },
-    transport::SipAddr,
+    transport::{SipAddr, SipConnection},
     Result,
 };
 use futures::FutureExt;
 use rsip::{
-    headers::Route,
     message::HasHeaders,
     prelude::{HeadersExt, ToTypedHeader, UntypedHeader},
     typed::{CSeq, Contact, Via},
     Header, Method, Param, Request, Response, SipMessage, StatusCode, StatusCodeKind,
 };
-use std::sync::{
-    atomic::{AtomicU32, Ordering},
-    Arc, Mutex,
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::{
+    broadcast,
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    oneshot, watch, Notify,
 };
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
 
@@ -46,7 +60,15 @@ use tracing::{debug, info, warn};
 /// * `Updated` - Dialog received an UPDATE request
 /// * `Notify` - Dialog received a NOTIFY request  
 /// * `Info` - Dialog received an INFO request
+/// * `Dtmf` - An INFO carried a key press as `application/dtmf-relay`
 /// * `Options` - Dialog received an OPTIONS request
+/// * `MediaTarget` - The remote RTP address moved, the media must be rebound
+/// * `Prack` - A reliable provisional response was acknowledged by PRACK
+/// * `Refer` - Dialog received a REFER, with its parsed `Refer-To` target
+/// * `ReferProgress` - A NOTIFY reported the status of a transfer we requested
+/// * `Active` - A NOTIFY reported the subscription active (RFC 6665)
+/// * `Pending` - A NOTIFY reported the subscription pending authorization
+/// * `Redirecting` - A 3xx to our INVITE is followed to the given target
 /// * `Terminated` - Dialog has been terminated
 ///
 /// # Examples
@@ -77,7 +99,15 @@ pub enum DialogState {
     Updated(DialogId, rsip::Request),
     Notify(DialogId, rsip::Request),
     Info(DialogId, rsip::Request),
+    Dtmf(DialogId, Dtmf),
     Options(DialogId, rsip::Request),
+    MediaTarget(DialogId, SocketAddr),
+    Prack(DialogId, rsip::Request),
+    Refer(DialogId, ReferTo, rsip::Request),
+    ReferProgress(DialogId, rsip::StatusCode),
+    Active(DialogId, rsip::Request),
+    Pending(DialogId, rsip::Request),
+    Redirecting(DialogId, rsip::Uri),
     Terminated(DialogId, TerminatedReason),
 }
 
@@ -92,6 +122,16 @@ pub enum TerminatedReason {
     UasDecline,
     ProxyError(rsip::StatusCode),
     ProxyAuthRequired,
+    /// Session timers could not be agreed on, even after a 422 retry
+    SessionTimerNegotiationFailed,
+    /// The notifier ended the subscription, with the `reason` of its
+    /// `Subscription-State` header
+    SubscriptionTerminated(Option<String>),
+    /// The TCP/TLS connection the dialog ran over closed
+    ConnectionClosed,
+    /// In-dialog OPTIONS pings went unanswered, see
+    /// [`ClientInviteDialog::enable_session_ping`](super::client_dialog::ClientInviteDialog::enable_session_ping)
+    PeerUnreachable,
     UacOther(rsip::StatusCode),
     UasOther(rsip::StatusCode),
 }
@@ -106,6 +146,7 @@ pub enum TerminatedReason {
 ///
 /// * `ServerInvite` - Server-side INVITE dialog (UAS)
 /// * `ClientInvite` - Client-side INVITE dialog (UAC)
+/// * `Subscribe` - Subscriber side of a SUBSCRIBE/NOTIFY dialog
 ///
 /// # Examples
 ///
@@ -120,6 +161,9 @@ pub enum TerminatedReason {
 ///     Dialog::ClientInvite(client_dialog) => {
 ///         // Handle client dialog  
 ///     }
+///     Dialog::Subscribe(subscribe_dialog) => {
+///         // Handle subscription
+///     }
 /// }
 /// # }
 /// ```
@@ -127,6 +171,7 @@ pub enum TerminatedReason {
 pub enum Dialog {
     ServerInvite(ServerInviteDialog),
     ClientInvite(ClientInviteDialog),
+    Subscribe(SubscribeDialog),
 }
 
 #[derive(Clone)]
@@ -184,7 +229,7 @@ pub struct DialogInner {
     pub to: Mutex<rsip::typed::To>,
 
     pub credential: Option<Credential>,
-    pub route_set: Mutex<Vec<Route>>,
+    pub route_set: Mutex<RouteSet>,
 
     pub(super) endpoint_inner: EndpointInnerRef,
     pub(super) state_sender: DialogStateSender,
@@ -193,6 +238,67 @@ pub struct DialogInner {
     pub(super) initial_request: Mutex<Request>,
     pub(super) supports_100rel: bool,
     pub(super) remote_reliable: Mutex<Option<RemoteReliableState>>,
+    // wakes tasks waiting for the stored state to change
+    pub(super) state_notify: Notify,
+    // every transition, for callers awaiting a single one with `next_state`
+    pub(super) state_events: broadcast::Sender<DialogState>,
+    // stored state, for subscribers that attach late, see `state_watch`
+    pub(super) state_current: watch::Sender<DialogState>,
+    // last SDP sent and received, used to auto-answer session refreshes
+    pub(super) local_sdp: Mutex<Option<Vec<u8>>>,
+    pub(super) remote_sdp: Mutex<Option<Vec<u8>>>,
+    // remote address of the stream connection the dialog was set up over
+    pub(super) connection: Mutex<Option<SipAddr>>,
+    // local and remote address the initial transaction used
+    pub(super) local_addr: Mutex<Option<SipAddr>>,
+    pub(super) remote_addr: Mutex<Option<SipAddr>>,
+    // set while a re-INVITE sent or received in this dialog is in progress
+    pub(super) invite_pending: AtomicBool,
+    // 3xx to our INVITE followed rather than terminating the dialog, see
+    // `InviteOption::follow_redirects`
+    pub(super) redirects: Mutex<Redirects>,
+    // set while an UPDATE offer received in this dialog waits for its answer
+    pub(super) update_pending: AtomicBool,
+    // BYE, INFO, UPDATE and re-INVITE for the TU to answer, once it asked for them
+    pub(super) request_sender: Mutex<Option<UnboundedSender<DialogRequest>>>,
+}
+
+const STATE_EVENTS_CAPACITY: usize = 16;
+
+/// Times a re-INVITE refused with 491 Request Pending is sent again
+const REINVITE_GLARE_RETRIES: usize = 3;
+
+/// Outstanding re-INVITE of a dialog, cleared when dropped
+pub(super) struct PendingInvite<'a>(&'a AtomicBool);
+
+impl Drop for PendingInvite<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Status, headers and body the TU answers an in-dialog request with
+pub(super) type RequestAnswer = (StatusCode, Option<Vec<Header>>, Option<Vec<u8>>);
+
+/// In-dialog BYE, INFO, UPDATE or re-INVITE waiting for the TU to answer it
+///
+/// Handed out by `incoming_requests` on the dialog. The request is answered
+/// with what is given to [`DialogRequest::reply`], or with 200 OK when it is
+/// dropped unanswered.
+pub struct DialogRequest {
+    pub request: Request,
+    answer: oneshot::Sender<RequestAnswer>,
+}
+
+impl DialogRequest {
+    pub fn method(&self) -> &Method {
+        &self.request.method
+    }
+
+    /// Answer the request with `status`, extra `headers` and `body`
+    pub fn reply(self, status: StatusCode, headers: Option<Vec<Header>>, body: Option<Vec<u8>>) {
+        self.answer.send((status, headers, body)).ok();
+    }
 }
 
 pub type DialogStateReceiver = UnboundedReceiver<DialogState>;
@@ -211,7 +317,15 @@ impl DialogState {
             | DialogState::Updated(id, _)
             | DialogState::Notify(id, _)
             | DialogState::Info(id, _)
+            | DialogState::Dtmf(id, _)
             | DialogState::Options(id, _)
+            | DialogState::MediaTarget(id, _)
+            | DialogState::Prack(id, _)
+            | DialogState::Refer(id, _, _)
+            | DialogState::ReferProgress(id, _)
+            | DialogState::Active(id, _)
+            | DialogState::Pending(id, _)
+            | DialogState::Redirecting(id, _)
             | DialogState::Terminated(id, _) => id,
         }
     }
@@ -259,14 +373,18 @@ impl DialogInner {
             to.params.push(rsip::Param::Tag(id.to_tag.clone().into()));
         }
 
-        let mut route_set = vec![];
-        for h in initial_request.headers.iter() {
-            if let Header::RecordRoute(rr) = h {
-                route_set.push(Route::from(rr.value()));
+        let route_set = match role {
+            TransactionRole::Client => {
+                RouteSet::from_record_route_reversed(&initial_request.headers)
             }
-        }
-        route_set.reverse();
+            TransactionRole::Server => RouteSet::from_record_route(&initial_request.headers),
+        };
 
+        let initial_sdp = (!initial_request.body.is_empty()).then(|| initial_request.body.clone());
+        let (local_sdp, remote_sdp) = match role {
+            TransactionRole::Client => (initial_sdp, None),
+            TransactionRole::Server => (None, initial_sdp),
+        };
         let supports_100rel =
             header_contains_token(&initial_request.headers, "Supported", "100rel")
                 || header_contains_token(&initial_request.headers, "Require", "100rel");
@@ -285,14 +403,44 @@ impl DialogInner {
             endpoint_inner,
             state_sender,
             tu_sender,
-            state: Mutex::new(DialogState::Calling(id)),
+            state: Mutex::new(DialogState::Calling(id.clone())),
             initial_request: Mutex::new(initial_request),
             local_contact,
             remote_contact: Mutex::new(None),
             supports_100rel,
             remote_reliable: Mutex::new(None),
+            state_notify: Notify::new(),
+            state_events: broadcast::channel(STATE_EVENTS_CAPACITY).0,
+            state_current: watch::channel(DialogState::Calling(id)).0,
+            local_sdp: Mutex::new(local_sdp),
+            remote_sdp: Mutex::new(remote_sdp),
+            connection: Mutex::new(None),
+            local_addr: Mutex::new(None),
+            remote_addr: Mutex::new(None),
+            invite_pending: AtomicBool::new(false),
+            update_pending: AtomicBool::new(false),
+            redirects: Mutex::new(Redirects::default()),
+            request_sender: Mutex::new(None),
         })
     }
+
+    /// Tie the dialog to the TCP/TLS connection its initial transaction
+    /// used, so it terminates when that connection closes, and remember
+    /// the addresses the transaction ran between
+    pub(super) fn bind_connection(
+        &self,
+        connection: Option<&SipConnection>,
+        destination: Option<&SipAddr>,
+    ) {
+        let Some(connection) = connection else {
+            return;
+        };
+        if connection.is_reliable() {
+            *self.connection.lock().unwrap() = Some(connection.get_addr().clone());
+        }
+        *self.local_addr.lock().unwrap() = connection.local_addr();
+        *self.remote_addr.lock().unwrap() = connection.remote_addr().or(destination.cloned());
+    }
     pub fn can_cancel(&self) -> bool {
         self.state.lock().unwrap().can_cancel()
     }
@@ -305,6 +453,230 @@ impl DialogInner {
     pub fn waiting_ack(&self) -> bool {
         self.state.lock().unwrap().waiting_ack()
     }
+    pub fn is_early(&self) -> bool {
+        matches!(*self.state.lock().unwrap(), DialogState::Early(_, _))
+    }
+
+    /// Current state of the dialog, updated on every change
+    ///
+    /// A new receiver sees the current state right away, so one that
+    /// subscribes after the dialog is confirmed does not wait for a
+    /// transition that already happened. Informational states such as
+    /// `Info` or `Updated` leave the stored state alone and are not
+    /// published; they only go to the state channel.
+    pub fn state_watch(&self) -> watch::Receiver<DialogState> {
+        self.state_current.subscribe()
+    }
+
+    /// Whether an UPDATE can be sent or accepted: once confirmed, or in an
+    /// early dialog whose remote tag is known (RFC 3311 §5.1)
+    pub(super) fn can_update(&self) -> bool {
+        self.is_confirmed() || (self.is_early() && !self.id.lock().unwrap().to_tag.is_empty())
+    }
+
+    pub(super) fn set_local_sdp(&self, body: &[u8]) {
+        if !body.is_empty() {
+            self.local_sdp.lock().unwrap().replace(body.to_vec());
+        }
+    }
+
+    /// Store the latest remote SDP
+    ///
+    /// When it moves the remote RTP address of an established session, a
+    /// `DialogState::MediaTarget` is emitted so the media can be rebound.
+    pub(super) fn set_remote_sdp(&self, body: &[u8]) {
+        if body.is_empty() {
+            return;
+        }
+        let previous = self.remote_sdp.lock().unwrap().replace(body.to_vec());
+        let previous_target = previous.as_deref().and_then(sdp_rtp_target);
+        match sdp_rtp_target(body) {
+            Some(target) if previous_target.is_some_and(|prev| prev != target) => {
+                let id = self.id.lock().unwrap().clone();
+                info!(%id, %target, "remote media target changed");
+                self.transition(DialogState::MediaTarget(id, target)).ok();
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether a re-INVITE only refreshes the session without changing media
+    ///
+    /// An offerless re-INVITE counts as a refresh when we have an SDP to
+    /// re-offer; otherwise the offer is compared with the last remote SDP.
+    pub(super) fn is_session_refresh(&self, req: &Request) -> bool {
+        if req.body.is_empty() {
+            return self.local_sdp.lock().unwrap().is_some();
+        }
+        match self.remote_sdp.lock().unwrap().as_ref() {
+            Some(previous) => !sdp_media_changed(previous, &req.body),
+            None => false,
+        }
+    }
+
+    /// Answer a session refresh re-INVITE with the current local SDP
+    ///
+    /// The dialog state is left untouched, so the application is not notified.
+    pub(super) async fn answer_session_refresh(&self, tx: &mut Transaction) -> Result<()> {
+        let id = self.id.lock().unwrap().clone();
+        info!(%id, "auto-answering session refresh re-invite");
+        let body = self.local_sdp.lock().unwrap().clone();
+        let headers = body
+            .as_ref()
+            .map(|_| vec![Header::ContentType("application/sdp".into())]);
+        let resp = self.make_response(&tx.original, StatusCode::OK, headers, body);
+        tx.respond(resp).await?;
+
+        while let Some(msg) = tx.receive().await {
+            match msg {
+                SipMessage::Request(req) if req.method == Method::Ack => {
+                    debug!(%id, "received ack for session refresh");
+                    break;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark a re-INVITE as outstanding until the returned guard is dropped
+    ///
+    /// Fails with 491 Request Pending while another INVITE transaction of
+    /// this dialog is still in progress (RFC 3261 §14.1).
+    pub(super) fn begin_invite(&self) -> Result<PendingInvite<'_>> {
+        if self.invite_pending.swap(true, Ordering::AcqRel) {
+            return Err(crate::Error::DialogError(
+                "an INVITE is already pending in the dialog".to_string(),
+                self.id.lock().unwrap().clone(),
+                StatusCode::RequestPending,
+            ));
+        }
+        Ok(PendingInvite(&self.invite_pending))
+    }
+
+    /// Wait before retrying a re-INVITE refused with 491 (RFC 3261 §14.1)
+    ///
+    /// The owner of the Call-ID, the UAC of the initial INVITE, waits 2.1 to
+    /// 4 seconds and the other side up to 2 seconds, in units of 10 ms.
+    pub(super) fn glare_backoff(&self) -> Duration {
+        use rand::Rng;
+        let units = match self.role {
+            TransactionRole::Client => rand::rng().random_range(210..=400),
+            TransactionRole::Server => rand::rng().random_range(0..=200),
+        };
+        Duration::from_millis(units * 10)
+    }
+
+    /// In-dialog request with the Via convention of this dialog's role
+    pub(super) fn make_in_dialog_request(
+        &self,
+        method: Method,
+        headers: Option<Vec<Header>>,
+        body: Option<Vec<u8>>,
+    ) -> Result<Request> {
+        match self.role {
+            TransactionRole::Client => self.make_request(method, None, None, None, headers, body),
+            TransactionRole::Server => self.make_request_with_vias(
+                method,
+                None,
+                self.build_vias_from_request()?,
+                headers,
+                body,
+            ),
+        }
+    }
+
+    /// Send a re-INVITE and take the new session from its 2xx
+    ///
+    /// A 491 Request Pending from the peer, whose own re-INVITE crossed
+    /// ours, is retried with a new CSeq after [`Self::glare_backoff`]. The
+    /// peer's INVITE is accepted while we wait.
+    pub(super) async fn send_reinvite(
+        &self,
+        headers: Option<Vec<Header>>,
+        body: Option<Vec<u8>>,
+    ) -> Result<Option<Response>> {
+        let mut retries = 0;
+        loop {
+            let pending = self.begin_invite()?;
+            let request = self.make_in_dialog_request(
+                Method::Invite,
+                self.with_identity_headers(headers.clone()),
+                body.clone(),
+            )?;
+            let resp = self.do_request(request.clone()).await?;
+            drop(pending);
+            match resp {
+                Some(ref resp)
+                    if resp.status_code == StatusCode::RequestPending
+                        && retries < REINVITE_GLARE_RETRIES =>
+                {
+                    retries += 1;
+                    let backoff = self.glare_backoff();
+                    let id = self.id.lock().unwrap().clone();
+                    info!(%id, ?backoff, "re-invite glare, retrying");
+                    tokio::time::sleep(backoff).await;
+                }
+                Some(ref resp) if resp.status_code == StatusCode::OK => {
+                    self.set_local_sdp(&request.body);
+                    self.set_remote_sdp(&resp.body);
+                    let id = self.id.lock().unwrap().clone();
+                    self.transition(DialogState::Updated(id, request))?;
+                    return Ok(Some(resp.clone()));
+                }
+                _ => return Ok(resp),
+            }
+        }
+    }
+
+    /// `P-Asserted-Identity` and `Privacy` of the INVITE that created a
+    /// client dialog, added to `headers` unless they already set them
+    fn with_identity_headers(&self, headers: Option<Vec<Header>>) -> Option<Vec<Header>> {
+        if !matches!(self.role, TransactionRole::Client) {
+            return headers;
+        }
+        let mut headers = headers.unwrap_or_default();
+        let initial_request = self.initial_request.lock().unwrap();
+        for name in ["P-Asserted-Identity", "Privacy"] {
+            let is_named =
+                |h: &Header| matches!(h, Header::Other(n, _) if n.eq_ignore_ascii_case(name));
+            if headers.iter().any(is_named) {
+                continue;
+            }
+            headers.extend(
+                initial_request
+                    .headers
+                    .iter()
+                    .filter(|h| is_named(h))
+                    .cloned(),
+            );
+        }
+        (!headers.is_empty()).then_some(headers)
+    }
+
+    /// ACK for a 2xx to `invite` sent in this dialog
+    ///
+    /// The ACK is sent to the remote target along the route set, with the To
+    /// tag of the 2xx and the CSeq number of the INVITE (RFC 3261 §13.2.2.4).
+    pub(super) fn make_ack(&self, invite: &Request, resp: &Response) -> Result<Request> {
+        let cseq = invite.cseq_header()?.seq()?;
+        let mut ack = match self.role {
+            TransactionRole::Client => {
+                self.make_request(Method::Ack, Some(cseq), None, None, None, None)?
+            }
+            TransactionRole::Server => self.make_request_with_vias(
+                Method::Ack,
+                Some(cseq),
+                self.build_vias_from_request()?,
+                None,
+                None,
+            )?,
+        };
+        ack.headers
+            .unique_push(Header::To(resp.to_header()?.clone()));
+        Ok(ack)
+    }
+
     pub fn get_local_seq(&self) -> u32 {
         self.local_seq.load(Ordering::Relaxed)
     }
@@ -313,6 +685,82 @@ impl DialogInner {
         self.local_seq.load(Ordering::Relaxed)
     }
 
+    /// Record the CSeq of an incoming in-dialog request
+    ///
+    /// Returns false for a request older than the highest CSeq seen so
+    /// far, which must not be processed (RFC 3261 §12.2.2). The ACK and
+    /// CANCEL of an INVITE share its CSeq and are accepted.
+    pub fn accept_remote_seq(&self, cseq: u32) -> bool {
+        let highest = self.remote_seq.fetch_max(cseq, Ordering::Relaxed);
+        highest == 0 || cseq >= highest
+    }
+
+    /// Handle an incoming UPDATE
+    ///
+    /// The UPDATE is passed to the TU as `DialogState::Updated` and answered
+    /// like the other in-dialog requests, see `answer_request`. An offer
+    /// arriving while an earlier one is still unanswered gets 491 Request
+    /// Pending (RFC 3311 §5.2).
+    pub(super) async fn handle_update(&self, tx: &mut Transaction) -> Result<()> {
+        let id = self.id.lock().unwrap().clone();
+        info!(%id, "received update {}", tx.original.uri);
+        let offer = !tx.original.body.is_empty();
+        if offer && self.update_pending.swap(true, Ordering::AcqRel) {
+            info!(%id, "update offer while another is unanswered");
+            tx.reply(StatusCode::RequestPending).await?;
+            return Ok(());
+        }
+        if offer {
+            self.set_remote_sdp(&tx.original.body);
+        }
+        self.transition(DialogState::Updated(id, tx.original.clone()))?;
+        let resp = self.answer_request(tx).await;
+        if offer {
+            self.update_pending.store(false, Ordering::Release);
+        }
+        let resp = resp?;
+        if offer && resp.status_code.kind() == StatusCodeKind::Successful && !resp.body.is_empty() {
+            self.set_local_sdp(&resp.body);
+        }
+        Ok(())
+    }
+
+    /// Hand the BYE, INFO, UPDATE and re-INVITE requests of this dialog to the TU
+    /// from now on, replacing any earlier receiver
+    pub(super) fn incoming_requests(&self) -> UnboundedReceiver<DialogRequest> {
+        let (sender, receiver) = unbounded_channel();
+        self.request_sender.lock().unwrap().replace(sender);
+        receiver
+    }
+
+    /// Answer an in-dialog request as the TU decides
+    ///
+    /// The request is answered with 200 OK when nobody took a receiver from
+    /// `incoming_requests`, or when the TU drops it or gives no answer
+    /// within 64*T1. Returns the response sent.
+    pub(super) async fn answer_request(&self, tx: &mut Transaction) -> Result<Response> {
+        let sender = self.request_sender.lock().unwrap().clone();
+        let mut answer = None;
+        if let Some(sender) = sender {
+            let (answer_sender, answer_receiver) = oneshot::channel();
+            let request = DialogRequest {
+                request: tx.original.clone(),
+                answer: answer_sender,
+            };
+            if sender.send(request).is_ok() {
+                answer = tokio::select! {
+                    answer = answer_receiver => answer.ok(),
+                    _ = tokio::time::sleep(self.endpoint_inner.option.t1x64) => None,
+                    _ = self.cancel_token.cancelled() => None,
+                };
+            }
+        }
+        let (status, headers, body) = answer.unwrap_or((StatusCode::OK, None, None));
+        let resp = self.make_response(&tx.original, status, headers, body);
+        tx.respond(resp.clone()).await?;
+        Ok(resp)
+    }
+
     pub fn update_remote_tag(&self, tag: &str) -> Result<()> {
         self.id.lock().unwrap().to_tag = tag.to_string();
         let mut to = self.to.lock().unwrap();
@@ -386,6 +834,14 @@ impl DialogInner {
         let to_header = resp.to_header()?;
         if let Ok(Some(tag)) = to_header.tag() {
             self.update_remote_tag(tag.value())?;
+            // the early dialog takes its remote target and route set from
+            // the provisional response (RFC 3261 §12.1.2)
+            if let Ok(contact) = resp.contact_header() {
+                if let Ok(uri) = extract_uri_from_contact(contact.value()) {
+                    self.set_remote_target(uri, Some(contact.clone()));
+                }
+            }
+            self.update_route_set_from_response(resp);
         }
 
         if let Some(prack) = self.prepare_prack_request(resp)? {
@@ -498,17 +954,7 @@ impl DialogInner {
             return;
         }
 
-        let mut new_route_set: Vec<Route> = resp
-            .headers()
-            .iter()
-            .filter_map(|header| match header {
-                Header::RecordRoute(rr) => Some(Route::from(rr.value())),
-                _ => None,
-            })
-            .collect();
-
-        new_route_set.reverse();
-        *self.route_set.lock().unwrap() = new_route_set;
+        *self.route_set.lock().unwrap() = RouteSet::from_record_route_reversed(resp.headers());
     }
 
     pub(super) fn build_vias_from_request(&self) -> Result<Vec<Via>> {
@@ -577,9 +1023,12 @@ impl DialogInner {
             }
         }
         headers.push(Header::CSeq(cseq_header.into()));
-        headers.push(Header::UserAgent(
-            self.endpoint_inner.user_agent.clone().into(),
-        ));
+        // a User-Agent passed by the caller wins over the endpoint's
+        if !headers.iter().any(|h| matches!(h, Header::UserAgent(_))) {
+            headers.push(Header::UserAgent(
+                self.endpoint_inner.user_agent.clone().into(),
+            ));
+        }
 
         self.local_contact
             .as_ref()
@@ -587,9 +1036,11 @@ impl DialogInner {
 
         {
             let route_set = self.route_set.lock().unwrap();
-            headers.extend(route_set.iter().cloned().map(Header::Route));
+            headers.extend(route_set.to_headers());
         }
-        headers.push(Header::MaxForwards(70.into()));
+        headers.push(Header::MaxForwards(max_forwards_header(
+            self.endpoint_inner.option.max_forwards,
+        )));
 
         headers.push(Header::ContentLength(
             body.as_ref().map_or(0u32, |b| b.len() as u32).into(),
@@ -677,15 +1128,18 @@ impl DialogInner {
             }
         }
 
-        resp_headers.retain(|h| !matches!(h, Header::ContentLength(_) | Header::UserAgent(_)));
+        resp_headers.retain(|h| !matches!(h, Header::ContentLength(_)));
 
         resp_headers.push(Header::ContentLength(
             body.as_ref().map_or(0u32, |b| b.len() as u32).into(),
         ));
 
-        resp_headers.push(Header::UserAgent(
-            self.endpoint_inner.user_agent.clone().into(),
-        ));
+        // a Server passed by the caller wins over the endpoint's
+        if !resp_headers.iter().any(|h| matches!(h, Header::Server(_))) {
+            resp_headers.push(Header::Server(
+                self.endpoint_inner.user_agent.clone().into(),
+            ));
+        }
 
         Response {
             status_code: status,
@@ -699,6 +1153,8 @@ impl DialogInner {
         let method = request.method().to_owned();
         let key = TransactionKey::from_request(&request, TransactionRole::Client)?;
         let mut tx = Transaction::new_client(key, request, self.endpoint_inner.clone(), None);
+        // a re-INVITE 2xx is ACKed along the dialog's route set below
+        tx.tu_acks_2xx = method == Method::Invite;
 
         if let Some(route) = tx.original.route_header() {
             if let Some(first_route) = route.typed().ok().and_then(|r| r.uris().first().cloned()) {
@@ -766,6 +1222,7 @@ impl DialogInner {
                                 _ => self.increment_local_seq(),
                             };
                             tx = handle_client_authenticate(new_seq, tx, resp, cred).await?;
+                            tx.tu_acks_2xx = method == Method::Invite;
                             tx.send().await?;
                             continue;
                         } else {
@@ -789,6 +1246,9 @@ impl DialogInner {
                     if !matches!(method, Method::PRack) {
                         self.clear_remote_reliable();
                     }
+                    if method == Method::Invite && status.kind() == StatusCodeKind::Successful {
+                        self.ack_reinvite(tx, &resp).await?;
+                    }
                     return Ok(Some(resp));
                 }
                 _ => break,
@@ -797,6 +1257,25 @@ impl DialogInner {
         Ok(None)
     }
 
+    /// ACK the 2xx to a re-INVITE, then keep ACKing its retransmissions
+    /// until the transaction ends
+    async fn ack_reinvite(&self, mut tx: Transaction, resp: &Response) -> Result<()> {
+        let ack = self.make_ack(&tx.original, resp)?;
+        tx.last_ack.replace(ack.clone());
+        tx.send_ack(None).await?;
+        tokio::spawn(async move {
+            while let Some(msg) = tx.receive().await {
+                if let SipMessage::Response(resp) = msg {
+                    if resp.status_code.kind() == StatusCodeKind::Successful {
+                        tx.last_ack.replace(ack.clone());
+                        tx.send_ack(None).await.ok();
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
     pub(super) async fn do_request(&self, request: Request) -> Result<Option<Response>> {
         self.send_dialog_request(request).boxed().await
     }
@@ -804,12 +1283,20 @@ impl DialogInner {
     pub(super) fn transition(&self, state: DialogState) -> Result<()> {
         // Try to send state update, but don't fail if channel is closed
         self.state_sender.send(state.clone()).ok();
+        // no receiver unless someone awaits `next_state`
+        self.state_events.send(state.clone()).ok();
 
         match state {
             DialogState::Updated(_, _)
             | DialogState::Notify(_, _)
             | DialogState::Info(_, _)
-            | DialogState::Options(_, _) => {
+            | DialogState::Dtmf(_, _)
+            | DialogState::Options(_, _)
+            | DialogState::MediaTarget(_, _)
+            | DialogState::Prack(_, _)
+            | DialogState::Refer(_, _, _)
+            | DialogState::ReferProgress(_, _)
+            | DialogState::Redirecting(_, _) => {
                 return Ok(());
             }
             _ => {}
@@ -830,7 +1317,10 @@ impl DialogInner {
             _ => {}
         }
         debug!("transitioning state: {} -> {}", old_state, state);
-        *old_state = state;
+        *old_state = state.clone();
+        drop(old_state);
+        self.state_current.send_replace(state);
+        self.state_notify.notify_waiters();
         Ok(())
     }
 }
@@ -846,7 +1336,17 @@ impl std::fmt::Display for DialogState {
             DialogState::Updated(id, _) => write!(f, "{}(Updated)", id),
             DialogState::Notify(id, _) => write!(f, "{}(Notify)", id),
             DialogState::Info(id, _) => write!(f, "{}(Info)", id),
+            DialogState::Dtmf(id, dtmf) => write!(f, "{}(Dtmf {})", id, dtmf.digit),
             DialogState::Options(id, _) => write!(f, "{}(Options)", id),
+            DialogState::MediaTarget(id, addr) => write!(f, "{}(MediaTarget {})", id, addr),
+            DialogState::Prack(id, _) => write!(f, "{}(Prack)", id),
+            DialogState::Refer(id, refer_to, _) => write!(f, "{}(Refer {})", id, refer_to.uri),
+            DialogState::ReferProgress(id, status) => {
+                write!(f, "{}(ReferProgress {})", id, status)
+            }
+            DialogState::Active(id, _) => write!(f, "{}(Active)", id),
+            DialogState::Pending(id, _) => write!(f, "{}(Pending)", id),
+            DialogState::Redirecting(id, target) => write!(f, "{}(Redirecting {})", id, target),
             DialogState::Terminated(id, reason) => write!(f, "{}(Terminated {:?})", id, reason),
         }
     }
@@ -857,6 +1357,7 @@ impl Dialog {
         match self {
             Dialog::ServerInvite(d) => d.inner.id.lock().unwrap().clone(),
             Dialog::ClientInvite(d) => d.inner.id.lock().unwrap().clone(),
+            Dialog::Subscribe(d) => d.inner.id.lock().unwrap().clone(),
         }
     }
 
@@ -864,6 +1365,7 @@ impl Dialog {
         match self {
             Dialog::ServerInvite(d) => &d.inner.from,
             Dialog::ClientInvite(d) => &d.inner.from,
+            Dialog::Subscribe(d) => &d.inner.from,
         }
     }
 
@@ -871,6 +1373,7 @@ impl Dialog {
         match self {
             Dialog::ServerInvite(d) => d.inner.to.lock().unwrap().clone(),
             Dialog::ClientInvite(d) => d.inner.to.lock().unwrap().clone(),
+            Dialog::Subscribe(d) => d.inner.to.lock().unwrap().clone(),
         }
     }
 
@@ -892,6 +1395,13 @@ impl Dialog {
                 .as_ref()
                 .map(|c| extract_uri_from_contact(c.value()).ok())
                 .flatten(),
+            Dialog::Subscribe(d) => d
+                .inner
+                .remote_contact
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(|c| extract_uri_from_contact(c.value()).ok()),
         }
     }
 
@@ -899,8 +1409,36 @@ impl Dialog {
         match self {
             Dialog::ServerInvite(d) => d.handle(tx).await,
             Dialog::ClientInvite(d) => d.handle(tx).await,
+            Dialog::Subscribe(d) => d.handle(tx).await,
         }
     }
+    /// Remote address of the TCP/TLS connection the dialog runs over
+    pub fn connection(&self) -> Option<SipAddr> {
+        match self {
+            Dialog::ServerInvite(d) => d.inner.connection.lock().unwrap().clone(),
+            Dialog::ClientInvite(d) => d.inner.connection.lock().unwrap().clone(),
+            Dialog::Subscribe(d) => d.inner.connection.lock().unwrap().clone(),
+        }
+    }
+
+    /// Terminate the dialog after its connection closed, without sending
+    /// anything
+    pub(super) fn on_connection_closed(&self) {
+        let inner = match self {
+            Dialog::ServerInvite(d) => &d.inner,
+            Dialog::ClientInvite(d) => &d.inner,
+            Dialog::Subscribe(d) => &d.inner,
+        };
+        let id = inner.id.lock().unwrap().clone();
+        inner
+            .transition(DialogState::Terminated(
+                id,
+                TerminatedReason::ConnectionClosed,
+            ))
+            .ok();
+        inner.cancel_token.cancel();
+    }
+
     pub fn on_remove(&self) {
         match self {
             Dialog::ServerInvite(d) => {
@@ -909,6 +1447,9 @@ impl Dialog {
             Dialog::ClientInvite(d) => {
                 d.inner.cancel_token.cancel();
             }
+            Dialog::Subscribe(d) => {
+                d.inner.cancel_token.cancel();
+            }
         }
     }
 
@@ -916,6 +1457,18 @@ impl Dialog {
         match self {
             Dialog::ServerInvite(d) => d.bye().await,
             Dialog::ClientInvite(d) => d.hangup().await,
+            Dialog::Subscribe(d) => d.unsubscribe().await.map(|_| ()),
+        }
+    }
+
+    /// Hang up with a `Reason` header (RFC 3326) on the CANCEL or BYE
+    ///
+    /// Subscriptions have no Reason to carry and are simply unsubscribed.
+    pub async fn hangup_with_reason(&self, reason: Reason) -> Result<()> {
+        match self {
+            Dialog::ServerInvite(d) => d.hangup_with_reason(reason).await,
+            Dialog::ClientInvite(d) => d.hangup_with_reason(reason).await,
+            Dialog::Subscribe(d) => d.unsubscribe().await.map(|_| ()),
         }
     }
 
@@ -923,6 +1476,7 @@ impl Dialog {
         match self {
             Dialog::ServerInvite(d) => d.inner.can_cancel(),
             Dialog::ClientInvite(d) => d.inner.can_cancel(),
+            Dialog::Subscribe(_) => false,
         }
     }
 
@@ -936,6 +1490,7 @@ impl Dialog {
         match self {
             Dialog::ServerInvite(d) => d.inner.set_remote_target(uri, contact),
             Dialog::ClientInvite(d) => d.inner.set_remote_target(uri, contact),
+            Dialog::Subscribe(d) => d.inner.set_remote_target(uri, contact),
         }
     }
 }
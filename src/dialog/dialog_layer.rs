@@ -1,19 +1,26 @@
 use super::authenticate::Credential;
 use super::dialog::DialogStateSender;
-use super::{dialog::Dialog, server_dialog::ServerInviteDialog, DialogId};
+use super::{dialog::Dialog, refer::ReplacesInfo, server_dialog::ServerInviteDialog, DialogId};
 use crate::dialog::client_dialog::ClientInviteDialog;
 use crate::dialog::dialog::{DialogInner, DialogStateReceiver};
-use crate::transaction::key::TransactionRole;
+use crate::transaction::key::{TransactionKey, TransactionRole};
 use crate::transaction::make_tag;
-use crate::transaction::{endpoint::EndpointInnerRef, transaction::Transaction};
+use crate::transaction::{
+    endpoint::{DialogRouter, EndpointInnerRef},
+    transaction::Transaction,
+};
+use crate::transport::SipAddr;
 use crate::Result;
+use futures::future::join_all;
 use rsip::prelude::HeadersExt;
 use rsip::Request;
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
 use std::{
     collections::HashMap,
-    sync::{Arc, RwLock},
+    sync::{Arc, RwLock, Weak},
 };
+use tokio::sync::broadcast;
 use tracing::info;
 
 /// Internal Dialog Layer State
@@ -38,6 +45,30 @@ pub struct DialogLayerInner {
 }
 pub type DialogLayerInnerRef = Arc<DialogLayerInner>;
 
+impl DialogLayerInner {
+    /// Remove and terminate the dialogs set up over the connection to `addr`
+    pub fn on_connection_closed(&self, addr: &SipAddr) -> usize {
+        let closed = match self.dialogs.write() {
+            Ok(mut dialogs) => {
+                let ids = dialogs
+                    .iter()
+                    .filter(|(_, dialog)| dialog.connection().as_ref() == Some(addr))
+                    .map(|(id, _)| id.clone())
+                    .collect::<Vec<_>>();
+                ids.iter()
+                    .filter_map(|id| dialogs.remove(id))
+                    .collect::<Vec<_>>()
+            }
+            Err(_) => return 0,
+        };
+        for dialog in closed.iter() {
+            info!(id = %dialog.id(), %addr, "connection of dialog closed");
+            dialog.on_connection_closed();
+        }
+        closed.len()
+    }
+}
+
 /// SIP Dialog Layer
 ///
 /// `DialogLayer` provides high-level dialog management functionality for SIP
@@ -137,13 +168,43 @@ pub struct DialogLayer {
 
 impl DialogLayer {
     pub fn new(endpoint: EndpointInnerRef) -> Self {
-        Self {
-            endpoint,
-            inner: Arc::new(DialogLayerInner {
-                last_seq: AtomicU32::new(0),
-                dialogs: RwLock::new(HashMap::new()),
-            }),
+        let inner = Arc::new(DialogLayerInner {
+            last_seq: AtomicU32::new(0),
+            dialogs: RwLock::new(HashMap::new()),
+        });
+        // dialogs over a TCP/TLS connection end when it closes
+        if tokio::runtime::Handle::try_current().is_ok() {
+            let mut closed = endpoint.subscribe_closed_connections();
+            let inner = Arc::downgrade(&inner);
+            tokio::spawn(async move {
+                loop {
+                    let addr = match closed.recv().await {
+                        Ok(addr) => addr,
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    let Some(inner) = inner.upgrade() else {
+                        break;
+                    };
+                    inner.on_connection_closed(&addr);
+                }
+            });
         }
+        Self { endpoint, inner }
+    }
+
+    /// Make this layer the endpoint's dialog router
+    ///
+    /// The endpoint then answers in-dialog requests for dialogs this layer
+    /// doesn't know with `481`, and, with an
+    /// [`IncomingRequestHandler`](crate::transaction::endpoint::IncomingRequestHandler)
+    /// set, hands the others to their dialog instead of the handler.
+    ///
+    /// An endpoint has a single router: the last layer to call this wins.
+    /// Without a router the TU sees every in-dialog request.
+    pub fn route_in_dialog_requests(&self) {
+        self.endpoint
+            .set_dialog_router(Box::new(LayerRouter(Arc::downgrade(&self.inner))));
     }
 
     pub fn get_or_create_server_invite(
@@ -194,6 +255,7 @@ impl DialogLayer {
         )?;
 
         *dlg_inner.remote_contact.lock().unwrap() = tx.original.contact_header().ok().cloned();
+        dlg_inner.bind_connection(tx.connection.as_ref(), tx.destination.as_ref());
 
         let dialog = ServerInviteDialog {
             inner: Arc::new(dlg_inner),
@@ -267,6 +329,84 @@ impl DialogLayer {
             .collect()
     }
 
+    /// Tear down every dialog and transaction of the call `call_id`
+    ///
+    /// Confirmed dialogs are ended with BYE and early client dialogs with
+    /// CANCEL; early server dialogs are rejected with 480 carrying `reason`
+    /// in a `Reason` header. Transactions of the call that were running
+    /// before are aborted afterwards. Returns the number of dialogs
+    /// terminated.
+    pub async fn terminate_call_id(&self, call_id: &str, reason: Option<String>) -> usize {
+        let dialogs = match self.inner.dialogs.read() {
+            Ok(dialogs) => dialogs
+                .values()
+                .filter(|dialog| dialog.id().call_id == call_id)
+                .cloned()
+                .collect::<Vec<_>>(),
+            Err(_) => return 0,
+        };
+        let running = self.endpoint.get_transactions_by_call_id(call_id);
+        info!(
+            call_id,
+            dialogs = dialogs.len(),
+            transactions = running.len(),
+            "terminating call"
+        );
+
+        join_all(dialogs.iter().map(|dialog| async {
+            let result = match dialog {
+                Dialog::ServerInvite(d) if d.inner.can_cancel() => d.reject(
+                    Some(rsip::StatusCode::TemporarilyUnavailable),
+                    reason.clone(),
+                ),
+                _ => dialog.hangup().await,
+            };
+            if let Err(e) = result {
+                info!(id = %dialog.id(), "failed to terminate dialog: {}", e);
+            }
+            self.remove_dialog(&dialog.id());
+        }))
+        .await;
+
+        self.endpoint.terminate_transactions(&running);
+        dialogs.len()
+    }
+
+    /// Hang up the established calls, then shut the endpoint down
+    ///
+    /// Confirmed INVITE dialogs are ended with BYE first, within `grace`;
+    /// the endpoint then rejects new out-of-dialog requests and unanswered
+    /// server transactions with 503 and drains for the rest of `grace`, see
+    /// [`EndpointInner::shutdown`](crate::transaction::endpoint::EndpointInner::shutdown).
+    /// Returns the keys of the transactions still unfinished at the deadline.
+    pub async fn shutdown(&self, grace: Duration) -> Vec<TransactionKey> {
+        self.endpoint.stop_accepting();
+        let deadline = tokio::time::Instant::now() + grace;
+        let confirmed = match self.inner.dialogs.read() {
+            Ok(dialogs) => dialogs
+                .values()
+                .filter(|dialog| match dialog {
+                    Dialog::ServerInvite(d) => d.inner.is_confirmed(),
+                    Dialog::ClientInvite(d) => d.inner.is_confirmed(),
+                    Dialog::Subscribe(_) => false,
+                })
+                .cloned()
+                .collect::<Vec<_>>(),
+            Err(_) => Vec::new(),
+        };
+        info!(dialogs = confirmed.len(), "hanging up before shutdown");
+        let hangups = join_all(confirmed.iter().map(|dialog| async {
+            if let Err(e) = dialog.hangup().await {
+                info!(id = %dialog.id(), "failed to hang up dialog: {}", e);
+            }
+            self.remove_dialog(&dialog.id());
+        }));
+        tokio::time::timeout_at(deadline, hangups).await.ok();
+        self.endpoint
+            .shutdown(deadline.saturating_duration_since(tokio::time::Instant::now()))
+            .await
+    }
+
     pub fn remove_dialog(&self, id: &DialogId) {
         info!(%id, "remove dialog");
         self.inner
@@ -277,9 +417,85 @@ impl DialogLayer {
             .map(|d| d.on_remove());
     }
 
+    /// Dialog an INVITE with `Replaces` takes over (RFC 3891 §3)
+    ///
+    /// The Replaces `to-tag` must be our tag in the dialog and `from-tag`
+    /// the peer's. Errors carry the status to reject the INVITE with:
+    /// `481` when no INVITE dialog matches or it is an early dialog we did
+    /// not initiate, `603` when it already ended, and `486` for
+    /// `early-only` when it is already confirmed. On success the TU answers
+    /// the new INVITE and hangs up the returned dialog.
+    pub fn match_replaces(&self, replaces: &ReplacesInfo) -> Result<Dialog> {
+        let id = DialogId {
+            call_id: replaces.call_id.clone(),
+            from_tag: replaces.from_tag.clone(),
+            to_tag: replaces.to_tag.clone(),
+        };
+        let reject = |reason: &str, status: rsip::StatusCode| {
+            Err(crate::Error::DialogError(
+                reason.to_string(),
+                id.clone(),
+                status,
+            ))
+        };
+        let (dialog, inner) = match self.get_dialog(&id) {
+            Some(Dialog::ServerInvite(d)) if d.id().to_tag == replaces.to_tag => {
+                let inner = d.inner.clone();
+                (Dialog::ServerInvite(d), inner)
+            }
+            Some(Dialog::ClientInvite(d)) if d.id().from_tag == replaces.to_tag => {
+                let inner = d.inner.clone();
+                (Dialog::ClientInvite(d), inner)
+            }
+            _ => {
+                return reject(
+                    "no dialog to replace",
+                    rsip::StatusCode::CallTransactionDoesNotExist,
+                )
+            }
+        };
+        if inner.is_terminated() {
+            return reject("replaced dialog already ended", rsip::StatusCode::Decline);
+        }
+        if replaces.early_only && inner.is_confirmed() {
+            return reject("replaced dialog is confirmed", rsip::StatusCode::BusyHere);
+        }
+        if matches!(dialog, Dialog::ServerInvite(_)) && inner.can_cancel() {
+            return reject(
+                "cannot replace an early dialog we did not initiate",
+                rsip::StatusCode::CallTransactionDoesNotExist,
+            );
+        }
+        Ok(dialog)
+    }
+
     pub fn match_dialog(&self, req: &Request) -> Option<Dialog> {
         let id = DialogId::try_from(req).ok()?;
         self.get_dialog(&id)
+            .or_else(|| self.match_early_notify(req, &id))
+            .or_else(|| self.match_early_update(req, &id))
+    }
+
+    /// UPDATE in the early dialog of an INVITE we sent
+    ///
+    /// The client dialog stays registered without a remote tag until the
+    /// INVITE completes, so match it by Call-ID and our tag and check the
+    /// remote tag it learned from the provisional response.
+    fn match_early_update(&self, req: &Request, id: &DialogId) -> Option<Dialog> {
+        if req.method != rsip::Method::Update {
+            return None;
+        }
+        let pending_id = DialogId {
+            call_id: id.call_id.clone(),
+            from_tag: id.to_tag.clone(),
+            to_tag: String::new(),
+        };
+        match self.get_dialog(&pending_id)? {
+            Dialog::ClientInvite(dialog) if dialog.id().to_tag == id.from_tag => {
+                Some(Dialog::ClientInvite(dialog))
+            }
+            _ => None,
+        }
     }
 
     pub fn new_dialog_state_channel(&self) -> (DialogStateSender, DialogStateReceiver) {
@@ -298,7 +514,58 @@ impl DialogLayer {
             .first()
             .ok_or(crate::Error::EndpointError("not sipaddrs".to_string()))?
             .clone();
+        let addr = self.endpoint.advertised_addr(addr);
+        Ok(Self::contact_at(addr, username, params))
+    }
+
+    /// Contact URI for `user` at the address this endpoint listens on
+    ///
+    /// The host and port come from the first bound transport, the one the
+    /// Via of requests is built from, so the Contact is reachable the same
+    /// way. An external address set on the endpoint replaces them. A TLS
+    /// transport gives a `sips:` URI with `;transport=tls`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::dialog_layer::DialogLayer;
+    /// # use rsipstack::dialog::invitation::InviteOption;
+    /// # fn example(dialog_layer: DialogLayer) -> rsipstack::Result<()> {
+    /// let invite_option = InviteOption {
+    ///     caller: "sip:alice@example.com".try_into()?,
+    ///     callee: "sip:bob@example.com".try_into()?,
+    ///     contact: dialog_layer.default_contact("alice")?,
+    ///     ..Default::default()
+    /// };
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn default_contact(&self, user: &str) -> Result<rsip::Uri> {
+        self.build_local_contact(Some(user.to_string()), None)
+    }
+
+    /// Like [`default_contact`](Self::default_contact), on the bound
+    /// transport a request to `target` is sent over
+    ///
+    /// A `sips:` target or one with a `transport` parameter selects the
+    /// listener of that transport; otherwise the first one is used.
+    pub fn default_contact_for(&self, user: &str, target: &rsip::Uri) -> Result<rsip::Uri> {
+        let addrs = self.endpoint.transport_layer.get_addrs();
+        let transport = SipAddr::try_from(target)?.r#type;
+        let addr = transport
+            .and_then(|transport| addrs.iter().find(|a| a.r#type.as_ref() == Some(&transport)))
+            .or(addrs.first())
+            .ok_or(crate::Error::EndpointError("not sipaddrs".to_string()))?
+            .clone();
+        let addr = self.endpoint.advertised_addr(addr);
+        Ok(Self::contact_at(addr, Some(user.to_string()), None))
+    }
 
+    fn contact_at(
+        addr: SipAddr,
+        username: Option<String>,
+        params: Option<Vec<rsip::Param>>,
+    ) -> rsip::Uri {
         let scheme = if matches!(addr.r#type, Some(rsip::Transport::Tls)) {
             rsip::Scheme::Sips
         } else {
@@ -313,12 +580,47 @@ impl DialogLayer {
             user,
             password: None,
         });
-        Ok(rsip::Uri {
+        rsip::Uri {
             scheme: Some(scheme),
             auth,
-            host_with_port: addr.addr.clone().into(),
+            host_with_port: addr.addr,
             params,
             ..Default::default()
-        })
+        }
+    }
+}
+
+struct LayerRouter(Weak<DialogLayerInner>);
+
+impl DialogRouter for LayerRouter {
+    fn route(&self, endpoint: &EndpointInnerRef, mut tx: Transaction) -> Option<Transaction> {
+        let Some(inner) = self.0.upgrade() else {
+            return Some(tx);
+        };
+        let layer = DialogLayer {
+            endpoint: endpoint.clone(),
+            inner,
+        };
+        let Some(mut dialog) = layer.match_dialog(&tx.original) else {
+            return Some(tx);
+        };
+        tokio::spawn(async move {
+            if let Err(e) = dialog.handle(&mut tx).await {
+                info!(key = %tx.key, "failed to handle in-dialog request: {}", e);
+            }
+        });
+        None
+    }
+
+    fn knows(&self, endpoint: &EndpointInnerRef, req: &Request) -> bool {
+        // a layer that is gone leaves the decision to the TU
+        let Some(inner) = self.0.upgrade() else {
+            return true;
+        };
+        let layer = DialogLayer {
+            endpoint: endpoint.clone(),
+            inner,
+        };
+        layer.match_dialog(req).is_some()
     }
 }
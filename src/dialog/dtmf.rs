@@ -0,0 +1,139 @@
+use super::dialog::{DialogInner, DialogState};
+use crate::rsip_ext::header_value_case_insensitive;
+use crate::transaction::{key::TransactionRole, transaction::Transaction};
+use crate::Result;
+use rsip::Header;
+use std::time::Duration;
+use tracing::info;
+
+/// Content type of DTMF carried in INFO requests
+pub const DTMF_RELAY_CONTENT_TYPE: &str = "application/dtmf-relay";
+
+/// Duration assumed for a tone whose body has no `Duration` line
+pub const DEFAULT_DTMF_DURATION: u32 = 250;
+
+/// A key press sent in an `application/dtmf-relay` INFO body
+///
+/// # Examples
+///
+/// ```rust
+/// use rsipstack::dialog::dtmf::Dtmf;
+///
+/// let dtmf = Dtmf::new('5', 160).unwrap();
+/// assert_eq!(dtmf.to_string(), "Signal=5\r\nDuration=160\r\n");
+/// assert_eq!(Dtmf::parse(b"signal= #\r\nDuration= 100\r\n"), Dtmf::new('#', 100));
+/// assert!(Dtmf::new('x', 160).is_none());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dtmf {
+    /// One of `0`-`9`, `*`, `#` or `A`-`D`
+    pub digit: char,
+    /// Tone length in milliseconds
+    pub duration: u32,
+}
+
+impl Dtmf {
+    pub fn new(digit: char, duration: u32) -> Option<Self> {
+        let digit = digit.to_ascii_uppercase();
+        matches!(digit, '0'..='9' | '*' | '#' | 'A'..='D').then_some(Self { digit, duration })
+    }
+
+    /// Parse a `Signal=`/`Duration=` body
+    pub fn parse(body: &[u8]) -> Option<Self> {
+        let body = std::str::from_utf8(body).ok()?;
+        let mut signal = None;
+        let mut duration = DEFAULT_DTMF_DURATION;
+        for line in body.lines() {
+            let Some((name, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "signal" => signal = value.chars().next().filter(|_| value.len() == 1),
+                "duration" => duration = value.parse().ok()?,
+                _ => {}
+            }
+        }
+        Self::new(signal?, duration)
+    }
+}
+
+impl std::fmt::Display for Dtmf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Signal={}\r\nDuration={}\r\n", self.digit, self.duration)
+    }
+}
+
+/// Whether a request carries an `application/dtmf-relay` body
+pub fn is_dtmf_relay(headers: &rsip::Headers) -> bool {
+    header_value_case_insensitive(headers, "Content-Type")
+        .and_then(|value| {
+            value
+                .split(';')
+                .next()
+                .map(|media| media.trim().eq_ignore_ascii_case(DTMF_RELAY_CONTENT_TYPE))
+        })
+        .unwrap_or(false)
+}
+
+impl DialogInner {
+    /// Handle an in-dialog INFO
+    ///
+    /// The request is reported as `DialogState::Info`, followed by
+    /// `DialogState::Dtmf` when it carries a DTMF relay body, and answered
+    /// by the TU or with 200 OK.
+    pub(super) async fn handle_info(&self, tx: &mut Transaction) -> Result<()> {
+        let id = self.id.lock().unwrap().clone();
+        info!(%id, "received info {}", tx.original.uri);
+        self.transition(DialogState::Info(id.clone(), tx.original.clone()))?;
+        if is_dtmf_relay(&tx.original.headers) {
+            match Dtmf::parse(&tx.original.body) {
+                Some(dtmf) => {
+                    info!(%id, digit = %dtmf.digit, duration = dtmf.duration, "received dtmf");
+                    self.transition(DialogState::Dtmf(id, dtmf))?;
+                }
+                None => info!(%id, "invalid dtmf-relay body"),
+            }
+        }
+        self.answer_request(tx).await?;
+        Ok(())
+    }
+
+    /// Send an INFO carrying `body` of type `content_type`, `None` when
+    /// the dialog is not confirmed
+    pub(super) async fn send_info(
+        &self,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> Result<Option<rsip::Response>> {
+        if !self.is_confirmed() {
+            return Ok(None);
+        }
+        let headers = Some(vec![Header::ContentType(content_type.into())]);
+        let request = match self.role {
+            TransactionRole::Client => {
+                self.make_request(rsip::Method::Info, None, None, None, headers, Some(body))?
+            }
+            TransactionRole::Server => self.make_request_with_vias(
+                rsip::Method::Info,
+                None,
+                self.build_vias_from_request()?,
+                headers,
+                Some(body),
+            )?,
+        };
+        self.do_request(request).await
+    }
+
+    /// Send a key press as an `application/dtmf-relay` INFO
+    pub(super) async fn send_dtmf(
+        &self,
+        digit: char,
+        duration: Duration,
+    ) -> Result<Option<rsip::Response>> {
+        let dtmf = Dtmf::new(digit, duration.as_millis() as u32)
+            .ok_or_else(|| crate::Error::Error(format!("invalid DTMF digit {:?}", digit)))?;
+        self.send_info(DTMF_RELAY_CONTENT_TYPE, dtmf.to_string().into_bytes())
+            .await
+    }
+}
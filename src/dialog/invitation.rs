@@ -1,25 +1,32 @@
 use super::{
     authenticate::Credential,
+    caller_preferences::{accept_contact_header, reject_contact_header, ContactPreference},
     client_dialog::ClientInviteDialog,
     dialog::{DialogInner, DialogStateSender},
     dialog_layer::DialogLayer,
+    priority::{priority_header, resource_priority_header, Priority, ResourcePriority},
+    refer::ReplacesInfo,
+    session_timer::session_expires_header,
 };
 use crate::{
     dialog::{dialog::Dialog, dialog_layer::DialogLayerInnerRef, DialogId},
+    rsip_ext::split_header_list,
     transaction::{
         key::{TransactionKey, TransactionRole},
         make_tag,
         transaction::Transaction,
+        via_branch,
     },
     transport::SipAddr,
     Result,
 };
 use futures::FutureExt;
 use rsip::{
-    prelude::{HeadersExt, ToTypedHeader},
+    prelude::{HeadersExt, ToTypedHeader, UntypedHeader},
     Request, Response,
 };
 use std::sync::Arc;
+use tokio::task::JoinHandle;
 use tracing::{debug, info, warn};
 
 /// INVITE Request Options
@@ -108,6 +115,7 @@ use tracing::{debug, info, warn};
 ///     username: "alice".to_string(),
 ///     password: "secret123".to_string(),
 ///     realm: Some("example.com".to_string()),
+///     algorithm: None,
 /// };
 ///
 /// let invite_option = InviteOption {
@@ -121,6 +129,24 @@ use tracing::{debug, info, warn};
 /// # Ok(())
 /// # }
 /// ```
+///
+/// ## Anonymous Call
+///
+/// ```rust,no_run
+/// # use rsipstack::dialog::invitation::InviteOption;
+/// # fn example() -> rsipstack::Result<()> {
+/// let invite_option = InviteOption {
+///     caller: "sip:alice@example.com".try_into()?,
+///     callee: "sip:bob@example.com".try_into()?,
+///     contact: "sip:alice@192.168.1.100:5060".try_into()?,
+///     // still known to the trusted network
+///     asserted_identity: Some("sip:alice@example.com".try_into()?),
+///     ..Default::default()
+/// }
+/// .anonymous();
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Default, Clone)]
 pub struct InviteOption {
     pub caller_display_name: Option<String>,
@@ -135,6 +161,69 @@ pub struct InviteOption {
     pub headers: Option<Vec<rsip::Header>>,
     pub support_prack: bool,
     pub call_id: Option<String>,
+    /// Tag of the From header, and so the local tag of the dialog; a
+    /// random one when `None`
+    pub from_tag: Option<String>,
+    /// Via branch of the INVITE, starting with `z9hG4bK`; a random one when
+    /// `None`
+    pub branch: Option<String>,
+    /// Caller preferences sent as `Accept-Contact` (RFC 3841)
+    pub accept_contact: Vec<ContactPreference>,
+    /// Caller preferences sent as `Reject-Contact` (RFC 3841)
+    pub reject_contact: Vec<ContactPreference>,
+    /// Session interval in seconds requested with `Session-Expires` (RFC 4028)
+    pub session_expires: Option<u32>,
+    /// Urgency sent as `Priority`, e.g. `Priority::Emergency`
+    pub priority: Option<Priority>,
+    /// Preemption levels sent as `Resource-Priority` (RFC 4412)
+    pub resource_priority: Vec<ResourcePriority>,
+    /// Identity sent as `P-Asserted-Identity` (RFC 3325), for use within a
+    /// trusted network
+    ///
+    /// Trunks take the caller ID from it rather than from From, which may
+    /// be anonymized to `sip:anonymous@anonymous.invalid`. It is repeated in
+    /// re-INVITEs of the dialog.
+    pub asserted_identity: Option<rsip::Uri>,
+    /// Privacy values sent as `Privacy` (RFC 3323), e.g. `id` to ask the
+    /// trusted network to strip `P-Asserted-Identity` before the callee.
+    /// Repeated in re-INVITEs of the dialog.
+    pub privacy: Option<String>,
+    /// Seconds the callee has to answer, sent as `Expires`. Falls back to
+    /// the endpoint's `default_invite_expires`.
+    pub expires: Option<u32>,
+    /// Dialog this call takes over, sent as `Replaces` (RFC 3891), for
+    /// attended transfer and call pickup
+    pub replaces: Option<ReplacesInfo>,
+    /// Redirects (300-305) [`DialogLayer::do_invite`] follows before
+    /// returning the 3xx; none by default
+    pub follow_redirects: u8,
+}
+
+impl InviteOption {
+    /// Hide the caller's identity from the callee (RFC 3323)
+    ///
+    /// From becomes `"Anonymous" <sip:anonymous@anonymous.invalid>`, the
+    /// Contact user-part becomes `anonymous` and `privacy` is set to `id`. A
+    /// configured `asserted_identity` is kept, so the trusted network still
+    /// knows the real caller and strips it before the callee.
+    pub fn anonymous(mut self) -> Self {
+        self.caller_display_name = Some("Anonymous".to_string());
+        self.caller = rsip::Uri {
+            scheme: Some(rsip::Scheme::Sip),
+            auth: Some(rsip::Auth {
+                user: "anonymous".to_string(),
+                password: None,
+            }),
+            host_with_port: rsip::Domain::from("anonymous.invalid").into(),
+            ..Default::default()
+        };
+        self.caller_params.clear();
+        if let Some(auth) = self.contact.auth.as_mut() {
+            auth.user = "anonymous".to_string();
+        }
+        self.privacy = Some("id".to_string());
+        self
+    }
 }
 
 pub struct DialogGuard {
@@ -249,14 +338,20 @@ impl DialogLayer {
             uri: opt.caller.clone(),
             params: opt.caller_params.clone(),
         }
-        .with_tag(make_tag());
+        .with_tag(
+            opt.from_tag
+                .clone()
+                .map(rsip::param::Tag::from)
+                .unwrap_or_else(make_tag),
+        );
 
         let call_id = opt
             .call_id
             .as_ref()
             .map(|id| rsip::headers::CallId::from(id.clone()));
 
-        let via = self.endpoint.get_via(None, None)?;
+        let branch = opt.branch.as_deref().map(via_branch).transpose()?;
+        let via = self.endpoint.get_via(None, branch)?;
         let mut request = self.endpoint.make_request(
             rsip::Method::Invite,
             recipient,
@@ -265,6 +360,7 @@ impl DialogLayer {
             to,
             last_seq,
             call_id,
+            None,
         );
 
         let contact = rsip::typed::Contact {
@@ -284,20 +380,77 @@ impl DialogLayer {
                 .into(),
         ));
 
-        if opt.support_prack {
+        let supported = [
+            (opt.support_prack, "100rel"),
+            (opt.session_expires.is_some(), "timer"),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, tag)| *tag)
+        .collect::<Vec<_>>();
+        if !supported.is_empty() {
+            request
+                .headers
+                .unique_push(rsip::Header::Supported(supported.join(", ").into()));
+        }
+        if let Some(seconds) = opt.session_expires {
+            request.headers.push(session_expires_header(seconds));
+        }
+        for pref in &opt.accept_contact {
+            request.headers.push(accept_contact_header(pref));
+        }
+        for pref in &opt.reject_contact {
+            request.headers.push(reject_contact_header(pref));
+        }
+        if let Some(identity) = &opt.asserted_identity {
+            request.headers.push(rsip::Header::Other(
+                "P-Asserted-Identity".into(),
+                format!("<{}>", identity),
+            ));
+        }
+        if let Some(privacy) = &opt.privacy {
+            request
+                .headers
+                .push(rsip::Header::Other("Privacy".into(), privacy.clone()));
+        }
+        if let Some(replaces) = &opt.replaces {
+            request.headers.push(replaces.into());
+        }
+        if let Some(expires) = opt.expires.or(self.endpoint.option.default_invite_expires) {
+            request
+                .headers
+                .unique_push(rsip::headers::Expires::from(expires).into());
+        }
+        if let Some(priority) = &opt.priority {
+            request.headers.push(priority_header(priority));
+        }
+        if !opt.resource_priority.is_empty() {
+            if let Some(invalid) = opt.resource_priority.iter().find(|rp| !rp.is_valid()) {
+                return Err(crate::Error::Error(format!(
+                    "invalid Resource-Priority: {}",
+                    invalid
+                )));
+            }
             request
                 .headers
-                .unique_push(rsip::Header::Supported("100rel".into()));
+                .push(resource_priority_header(&opt.resource_priority));
         }
         // can't override default headers
         if let Some(headers) = opt.headers.as_ref() {
             for header in headers {
-                // only override if it is a "max-forwards" header
-                // so as not to duplicate it; this is important because
-                // some clients consider messages with duplicate "max-forwards"
-                // headers as malformed and may silently ignore invites
+                // only override "max-forwards" and "user-agent" so as not to
+                // duplicate them; this is important because some clients
+                // consider messages with duplicate "max-forwards" headers as
+                // malformed and may silently ignore invites
                 match header {
-                    rsip::Header::MaxForwards(_) => request.headers.unique_push(header.clone()),
+                    rsip::Header::MaxForwards(_) | rsip::Header::UserAgent(_) => {
+                        request.headers.unique_push(header.clone())
+                    }
+                    // already sent from `asserted_identity` and `privacy`
+                    rsip::Header::Other(name, _)
+                        if (opt.asserted_identity.is_some()
+                            && name.eq_ignore_ascii_case("P-Asserted-Identity"))
+                            || (opt.privacy.is_some() && name.eq_ignore_ascii_case("Privacy")) => {}
                     _ => request.headers.push(header.clone()),
                 }
             }
@@ -410,65 +563,88 @@ impl DialogLayer {
     /// If credentials are provided in the options, the method will
     /// automatically handle 401/407 authentication challenges by
     /// resending the request with proper authentication headers.
+    ///
+    /// # Redirects
+    ///
+    /// With `follow_redirects` set, a 3xx is retried at its highest-q
+    /// Contact not tried yet, in a new INVITE with the same Call-ID and From
+    /// tag and a higher CSeq (RFC 3261 §8.1.3.4). Instead of terminating,
+    /// the redirected dialog reports `DialogState::Redirecting` with the new
+    /// target; the dialog returned is the last one tried.
     pub async fn do_invite(
         &self,
-        opt: InviteOption,
+        mut opt: InviteOption,
         state_sender: DialogStateSender,
     ) -> Result<(ClientInviteDialog, Option<Response>)> {
-        let (dialog, tx) = self.create_client_invite_dialog(opt, state_sender)?;
-        let id = dialog.id();
-
-        self.inner
-            .dialogs
-            .write()
-            .as_mut()
-            .map(|ds| ds.insert(id.to_string(), Dialog::ClientInvite(dialog.clone())))
-            .ok();
-
-        info!(%id, "client invite dialog created");
-        let _guard = DialogGuardForUnconfirmed {
-            dialog_layer_inner: &self.inner,
-            id: &id,
+        let mut redirects = Redirects {
+            hops: opt.follow_redirects,
+            visited: vec![opt.callee.clone()],
         };
-
-        let r = dialog.process_invite(tx).boxed().await;
-        self.inner
-            .dialogs
-            .write()
-            .as_mut()
-            .map(|ds| ds.remove(&id.to_string()))
-            .ok();
-
-        match r {
-            Ok((new_dialog_id, resp)) => {
-                match resp {
-                    Some(ref r) if r.status_code.kind() == rsip::StatusCodeKind::Successful => {
-                        debug!(
-                            "client invite dialog confirmed: {} => {}",
-                            id, new_dialog_id
-                        );
-                        self.inner
-                            .dialogs
-                            .write()
-                            .as_mut()
-                            .map(|ds| {
-                                ds.insert(
-                                    new_dialog_id.to_string(),
-                                    Dialog::ClientInvite(dialog.clone()),
-                                )
-                            })
-                            .ok();
-                    }
-                    _ => {}
-                }
+        loop {
+            let (dialog, tx) =
+                self.create_client_invite_dialog(opt.clone(), state_sender.clone())?;
+            *dialog.inner.redirects.lock().unwrap() = redirects.clone();
+            let resp = run_client_invite(self.inner.clone(), dialog.clone(), tx).await?;
+            let Some(target) = resp.as_ref().and_then(|resp| redirects.target(resp)) else {
                 return Ok((dialog, resp));
-            }
-            Err(e) => {
-                return Err(e);
-            }
+            };
+            info!(id = %dialog.id(), %target, "following redirect");
+            redirects.hops -= 1;
+            redirects.visited.push(target.clone());
+            let id = dialog.id();
+            opt.callee = target;
+            opt.call_id = Some(id.call_id);
+            opt.from_tag = Some(id.from_tag);
+            opt.branch = None;
+            // the redirect target is resolved afresh
+            opt.destination = None;
         }
     }
 
+    /// Send an INVITE request without waiting for the final response
+    ///
+    /// Unlike [`DialogLayer::do_invite`], the INVITE transaction runs on a
+    /// spawned task and this returns immediately. The returned [`InviteHandle`]
+    /// can cancel the call or await the final response; progress is also
+    /// reported through `state_sender` as usual.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::dialog_layer::DialogLayer;
+    /// # use rsipstack::dialog::invitation::InviteOption;
+    /// # async fn example() -> rsipstack::Result<()> {
+    /// # let dialog_layer: DialogLayer = todo!();
+    /// # let invite_option: InviteOption = todo!();
+    /// let (state_tx, _state_rx) = tokio::sync::mpsc::unbounded_channel();
+    /// let (dialog, handle) = dialog_layer.start_invite(invite_option, state_tx)?;
+    ///
+    /// // give up if nobody answers in time
+    /// if dialog
+    ///     .await_confirmed(std::time::Duration::from_secs(30))
+    ///     .await
+    ///     .is_err()
+    /// {
+    ///     handle.cancel().await?;
+    /// }
+    /// let final_response = handle.await_final().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn start_invite(
+        &self,
+        opt: InviteOption,
+        state_sender: DialogStateSender,
+    ) -> Result<(ClientInviteDialog, InviteHandle)> {
+        let (dialog, tx) = self.create_client_invite_dialog(opt, state_sender)?;
+        let task = tokio::spawn(run_client_invite(self.inner.clone(), dialog.clone(), tx));
+        let handle = InviteHandle {
+            dialog: dialog.clone(),
+            task,
+        };
+        Ok((dialog, handle))
+    }
+
     pub fn create_client_invite_dialog(
         &self,
         opt: InviteOption,
@@ -482,8 +658,8 @@ impl DialogLayer {
         let key = TransactionKey::from_request(&request, TransactionRole::Client)?;
         let mut tx = Transaction::new_client(key, request.clone(), self.endpoint.clone(), None);
 
-        if opt.destination.is_some() {
-            tx.destination = opt.destination;
+        if let Some(destination) = opt.destination {
+            tx = tx.with_destination(destination);
         } else {
             if let Some(route) = tx.original.route_header() {
                 if let Some(first_route) =
@@ -512,3 +688,135 @@ impl DialogLayer {
         Ok((dialog, tx))
     }
 }
+
+/// Handle to an INVITE started with [`DialogLayer::start_invite`]
+pub struct InviteHandle {
+    dialog: ClientInviteDialog,
+    task: JoinHandle<Result<Option<Response>>>,
+}
+
+impl InviteHandle {
+    /// Cancel the INVITE if it has not been answered yet
+    pub async fn cancel(&self) -> Result<()> {
+        self.dialog.cancel().await
+    }
+
+    /// Wait for the INVITE transaction to finish and return its final response
+    pub async fn await_final(self) -> Result<Option<Response>> {
+        self.task
+            .await
+            .map_err(|e| crate::Error::Error(format!("invite task failed: {}", e)))?
+    }
+}
+
+async fn run_client_invite(
+    dialog_layer_inner: DialogLayerInnerRef,
+    dialog: ClientInviteDialog,
+    tx: Transaction,
+) -> Result<Option<Response>> {
+    let id = dialog.id();
+
+    dialog_layer_inner
+        .dialogs
+        .write()
+        .as_mut()
+        .map(|ds| ds.insert(id.to_string(), Dialog::ClientInvite(dialog.clone())))
+        .ok();
+
+    info!(%id, "client invite dialog created");
+    let _guard = DialogGuardForUnconfirmed {
+        dialog_layer_inner: &dialog_layer_inner,
+        id: &id,
+    };
+
+    // other branches of a forked INVITE are dialogs of their own
+    let forks_inner = dialog_layer_inner.clone();
+    let on_fork = move |fork: &ClientInviteDialog| {
+        forks_inner
+            .dialogs
+            .write()
+            .as_mut()
+            .map(|ds| ds.insert(fork.id().to_string(), Dialog::ClientInvite(fork.clone())))
+            .ok();
+    };
+    let r = dialog.process_invite_with_forks(tx, on_fork).boxed().await;
+    dialog_layer_inner
+        .dialogs
+        .write()
+        .as_mut()
+        .map(|ds| ds.remove(&id.to_string()))
+        .ok();
+
+    let (new_dialog_id, resp) = r?;
+    match resp {
+        Some(ref r) if r.status_code.kind() == rsip::StatusCodeKind::Successful => {
+            debug!(
+                "client invite dialog confirmed: {} => {}",
+                id, new_dialog_id
+            );
+            dialog_layer_inner
+                .dialogs
+                .write()
+                .as_mut()
+                .map(|ds| ds.insert(new_dialog_id.to_string(), Dialog::ClientInvite(dialog)))
+                .ok();
+        }
+        _ => {}
+    }
+    Ok(resp)
+}
+
+/// Redirects an INVITE may still follow, and the targets tried so far
+#[derive(Clone, Default)]
+pub(super) struct Redirects {
+    pub hops: u8,
+    pub visited: Vec<rsip::Uri>,
+}
+
+impl Redirects {
+    /// Target to follow `resp` to, if it is a 3xx and hops are left
+    pub fn target(&self, resp: &Response) -> Option<rsip::Uri> {
+        if self.hops == 0 {
+            return None;
+        }
+        redirect_target(resp, &self.visited)
+    }
+}
+
+/// Contact of a 300-305 to retry the INVITE at, the one with the highest
+/// `q` not in `visited`
+fn redirect_target(resp: &Response, visited: &[rsip::Uri]) -> Option<rsip::Uri> {
+    if !(300..=305).contains(&resp.status_code.code()) {
+        return None;
+    }
+    let q = |contact: &rsip::typed::Contact| {
+        contact
+            .params
+            .iter()
+            .find_map(|param| match param {
+                rsip::Param::Q(q) => q.value().parse::<f32>().ok(),
+                rsip::Param::Other(key, Some(value)) if key.value().eq_ignore_ascii_case("q") => {
+                    value.value().parse::<f32>().ok()
+                }
+                _ => None,
+            })
+            .unwrap_or(1.0)
+    };
+    resp.headers
+        .iter()
+        .filter_map(|header| match header {
+            rsip::Header::Contact(contact) => Some(contact),
+            _ => None,
+        })
+        .flat_map(|contact| split_header_list(contact.value()))
+        .filter_map(|value| rsip::headers::Contact::new(value).typed().ok())
+        .filter(|contact| !visited.contains(&contact.uri))
+        .fold(
+            None,
+            |best: Option<rsip::typed::Contact>, contact| match best {
+                Some(best) if q(&best) >= q(&contact) => Some(best),
+                _ => Some(contact),
+            },
+        )
+        .map(|contact| contact.uri)
+}
@@ -5,12 +5,19 @@ use rsip::{
 };
 
 pub mod authenticate;
+pub mod caller_preferences;
 pub mod client_dialog;
 pub mod dialog;
 pub mod dialog_layer;
+pub mod dtmf;
 pub mod invitation;
+pub mod priority;
+pub mod refer;
 pub mod registration;
+pub mod sdp;
 pub mod server_dialog;
+pub mod session_timer;
+pub mod subscription;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,139 @@
+use crate::rsip_ext::{header_value_case_insensitive, header_values_case_insensitive};
+use rsip::Header;
+
+/// Urgency of a request from its `Priority` header (RFC 3261 §20.26)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Priority {
+    Emergency,
+    Urgent,
+    Normal,
+    NonUrgent,
+    Other(String),
+}
+
+impl Priority {
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if !is_token(value) {
+            return None;
+        }
+        Some(match value.to_ascii_lowercase().as_str() {
+            "emergency" => Priority::Emergency,
+            "urgent" => Priority::Urgent,
+            "normal" => Priority::Normal,
+            "non-urgent" => Priority::NonUrgent,
+            _ => Priority::Other(value.to_string()),
+        })
+    }
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Priority::Emergency => write!(f, "emergency"),
+            Priority::Urgent => write!(f, "urgent"),
+            Priority::Normal => write!(f, "normal"),
+            Priority::NonUrgent => write!(f, "non-urgent"),
+            Priority::Other(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// A `namespace.priority` value of `Resource-Priority` (RFC 4412)
+///
+/// Namespaces such as `dsn`, `drsn`, `q735`, `ets` and `wps` define the
+/// ordered priority levels used to preempt lower priority calls.
+///
+/// # Examples
+///
+/// ```rust
+/// use rsipstack::dialog::priority::ResourcePriority;
+///
+/// let rp = ResourcePriority::parse("DSN.Flash").unwrap();
+/// assert_eq!(rp.namespace, "dsn");
+/// assert_eq!(rp.priority, "flash");
+/// assert_eq!(rp.to_string(), "dsn.flash");
+/// assert!(ResourcePriority::parse("dsn").is_none());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourcePriority {
+    pub namespace: String,
+    pub priority: String,
+}
+
+impl ResourcePriority {
+    /// Both parts are case-insensitive and kept in lower case
+    pub fn new(namespace: &str, priority: &str) -> Option<Self> {
+        if !is_resource_token(namespace) || !is_resource_token(priority) {
+            return None;
+        }
+        Some(Self {
+            namespace: namespace.to_ascii_lowercase(),
+            priority: priority.to_ascii_lowercase(),
+        })
+    }
+
+    /// Parse a single `r-value` such as `dsn.flash`
+    pub fn parse(value: &str) -> Option<Self> {
+        let (namespace, priority) = value.trim().split_once('.')?;
+        Self::new(namespace, priority)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        is_resource_token(&self.namespace) && is_resource_token(&self.priority)
+    }
+}
+
+impl std::fmt::Display for ResourcePriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.namespace, self.priority)
+    }
+}
+
+// namespace and r-priority characters, RFC 4412 §3.1
+fn is_resource_token(value: &str) -> bool {
+    !value.is_empty()
+        && value.chars().all(|c| {
+            c.is_ascii_alphanumeric()
+                || matches!(c, '-' | '!' | '%' | '*' | '_' | '+' | '`' | '\'' | '~')
+        })
+}
+
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value.chars().all(|c| {
+            c.is_ascii_alphanumeric()
+                || matches!(
+                    c,
+                    '-' | '.' | '!' | '%' | '*' | '_' | '+' | '`' | '\'' | '~'
+                )
+        })
+}
+
+pub fn priority_header(priority: &Priority) -> Header {
+    Header::Other("Priority".into(), priority.to_string())
+}
+
+pub fn resource_priority_header(values: &[ResourcePriority]) -> Header {
+    let values = values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>();
+    Header::Other("Resource-Priority".into(), values.join(", "))
+}
+
+/// `Priority` of a request, if present and well formed
+pub fn parse_priority(headers: &rsip::Headers) -> Option<Priority> {
+    header_value_case_insensitive(headers, "Priority").and_then(|value| Priority::parse(&value))
+}
+
+/// All valid values of the `Resource-Priority` headers, in order
+///
+/// Values that are not `namespace.priority` are skipped.
+pub fn parse_resource_priority(headers: &rsip::Headers) -> Vec<ResourcePriority> {
+    header_values_case_insensitive(headers, "Resource-Priority")
+        .iter()
+        .flat_map(|value| value.split(','))
+        .filter_map(ResourcePriority::parse)
+        .collect()
+}
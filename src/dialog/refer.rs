@@ -0,0 +1,256 @@
+use super::dialog::{DialogInner, DialogState};
+use crate::rsip_ext::header_value_case_insensitive;
+use crate::transaction::{key::TransactionRole, transaction::Transaction};
+use crate::Result;
+use rsip::{Header, Method, Response, StatusCode};
+use tracing::info;
+
+/// Implicit subscription duration announced in `Subscription-State`
+/// while a transfer is in progress (RFC 3515 §2.4.4)
+pub const REFER_SUBSCRIPTION_EXPIRES: u32 = 60;
+
+/// Dialog to replace at the transfer target, for attended transfer (RFC 3891)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplacesInfo {
+    pub call_id: String,
+    pub to_tag: String,
+    pub from_tag: String,
+    pub early_only: bool,
+}
+
+impl ReplacesInfo {
+    /// Parse a `Replaces` value: `call-id;to-tag=..;from-tag=..[;early-only]`
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(';').map(str::trim);
+        let call_id = parts.next().filter(|id| !id.is_empty())?.to_string();
+        let mut to_tag = None;
+        let mut from_tag = None;
+        let mut early_only = false;
+        for part in parts {
+            let (name, value) = part.split_once('=').unwrap_or((part, ""));
+            match name.trim().to_ascii_lowercase().as_str() {
+                "to-tag" => to_tag = Some(value.trim().to_string()),
+                "from-tag" => from_tag = Some(value.trim().to_string()),
+                "early-only" => early_only = true,
+                _ => {}
+            }
+        }
+        Some(Self {
+            call_id,
+            to_tag: to_tag?,
+            from_tag: from_tag?,
+            early_only,
+        })
+    }
+}
+
+impl std::fmt::Display for ReplacesInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{};to-tag={};from-tag={}",
+            self.call_id, self.to_tag, self.from_tag
+        )?;
+        if self.early_only {
+            write!(f, ";early-only")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&ReplacesInfo> for Header {
+    fn from(replaces: &ReplacesInfo) -> Self {
+        Header::Other("Replaces".into(), replaces.to_string())
+    }
+}
+
+/// `Replaces` header of an INVITE taking over an existing dialog
+pub fn parse_replaces_header(headers: &rsip::Headers) -> Option<ReplacesInfo> {
+    header_value_case_insensitive(headers, "Replaces").and_then(|value| ReplacesInfo::parse(&value))
+}
+
+/// Target of a REFER, from its `Refer-To` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReferTo {
+    pub uri: rsip::Uri,
+    pub replaces: Option<ReplacesInfo>,
+}
+
+fn escape_uri_header(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'_'
+            | b'.'
+            | b'!'
+            | b'~'
+            | b'*'
+            | b'\''
+            | b'('
+            | b')' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    escaped
+}
+
+fn unescape_uri_header(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                unescaped.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        unescaped.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+/// `Refer-To` header for `refer_to`, with `replaces` embedded as an escaped
+/// `Replaces` URI header
+pub fn refer_to_header(refer_to: &rsip::Uri, replaces: Option<&ReplacesInfo>) -> Header {
+    let mut target = refer_to.to_string();
+    if let Some(replaces) = replaces {
+        let separator = if target.contains('?') { '&' } else { '?' };
+        target.push(separator);
+        target.push_str("Replaces=");
+        target.push_str(&escape_uri_header(&replaces.to_string()));
+    }
+    Header::Other("Refer-To".into(), format!("<{}>", target))
+}
+
+/// `Refer-To` of a REFER (or its compact form `r`)
+pub fn parse_refer_to(headers: &rsip::Headers) -> Option<ReferTo> {
+    let value = header_value_case_insensitive(headers, "Refer-To")
+        .or_else(|| header_value_case_insensitive(headers, "r"))?;
+    let value = value.trim();
+    let target = match (value.find('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value.split(';').next().unwrap_or(value),
+    };
+    let (uri, uri_headers) = target.split_once('?').unwrap_or((target, ""));
+    let replaces = uri_headers.split('&').find_map(|header| {
+        let (name, value) = header.split_once('=')?;
+        if name.eq_ignore_ascii_case("Replaces") {
+            ReplacesInfo::parse(&unescape_uri_header(value))
+        } else {
+            None
+        }
+    });
+    Some(ReferTo {
+        uri: rsip::Uri::try_from(uri).ok()?,
+        replaces,
+    })
+}
+
+/// Status line of a `message/sipfrag` body, e.g. `SIP/2.0 200 OK`
+pub fn parse_sipfrag_status(body: &[u8]) -> Option<StatusCode> {
+    let body = std::str::from_utf8(body).ok()?;
+    let mut parts = body.lines().next()?.split_whitespace();
+    if !parts.next()?.eq_ignore_ascii_case("SIP/2.0") {
+        return None;
+    }
+    parts.next()?.parse::<u16>().ok().map(StatusCode::from)
+}
+
+fn is_refer_event(headers: &rsip::Headers) -> bool {
+    header_value_case_insensitive(headers, "Event")
+        .or_else(|| header_value_case_insensitive(headers, "o"))
+        .and_then(|event| {
+            event
+                .split(';')
+                .next()
+                .map(|name| name.trim().eq_ignore_ascii_case("refer"))
+        })
+        .unwrap_or(false)
+}
+
+impl DialogInner {
+    /// Send an in-dialog REFER asking the peer to call `refer_to`
+    pub(super) async fn send_refer(
+        &self,
+        refer_to: &rsip::Uri,
+        replaces: Option<&ReplacesInfo>,
+        headers: Option<Vec<Header>>,
+    ) -> Result<Option<Response>> {
+        let mut headers = headers.unwrap_or_default();
+        headers.push(refer_to_header(refer_to, replaces));
+        let referrer = match self.role {
+            TransactionRole::Client => self.from.uri.clone(),
+            TransactionRole::Server => self.to.lock().unwrap().uri.clone(),
+        };
+        headers.push(Header::Other(
+            "Referred-By".into(),
+            format!("<{}>", referrer),
+        ));
+        let request = self.make_in_dialog_request(Method::Refer, Some(headers), None)?;
+        self.do_request(request).await
+    }
+
+    /// Report transfer progress to the referrer with a NOTIFY carrying a
+    /// `message/sipfrag` status line (RFC 3515 §2.4.4)
+    ///
+    /// A final `status` ends the implicit subscription.
+    pub(super) async fn send_refer_notify(&self, status: StatusCode) -> Result<Option<Response>> {
+        let subscription_state = if status.code() >= 200 {
+            "terminated;reason=noresource".to_string()
+        } else {
+            format!("active;expires={}", REFER_SUBSCRIPTION_EXPIRES)
+        };
+        let body = format!("SIP/2.0 {}\r\n", status).into_bytes();
+        let headers = vec![
+            Header::Other("Event".into(), "refer".into()),
+            Header::Other("Subscription-State".into(), subscription_state),
+            Header::ContentType("message/sipfrag;version=2.0".into()),
+        ];
+        let request = self.make_in_dialog_request(Method::Notify, Some(headers), Some(body))?;
+        self.do_request(request).await
+    }
+
+    /// Accept an incoming REFER with 202 and expose its target
+    pub(super) async fn handle_refer(&self, tx: &mut Transaction) -> Result<()> {
+        let id = self.id.lock().unwrap().clone();
+        let Some(refer_to) = parse_refer_to(&tx.original.headers) else {
+            info!(%id, "received refer without a valid Refer-To");
+            tx.reply(StatusCode::BadRequest).await?;
+            return Ok(());
+        };
+        info!(%id, refer_to = %refer_to.uri, "received refer");
+        tx.reply(StatusCode::Accepted).await?;
+        self.transition(DialogState::Refer(id, refer_to, tx.original.clone()))
+    }
+
+    /// Handle an in-dialog NOTIFY
+    ///
+    /// NOTIFYs of the implicit REFER subscription report the transfer
+    /// status from their sipfrag body as `DialogState::ReferProgress`;
+    /// other NOTIFYs are passed on as `DialogState::Notify`.
+    pub(super) async fn handle_notify(&self, tx: &mut Transaction) -> Result<()> {
+        let id = self.id.lock().unwrap().clone();
+        tx.reply(StatusCode::OK).await?;
+        if !is_refer_event(&tx.original.headers) {
+            return self.transition(DialogState::Notify(id, tx.original.clone()));
+        }
+        match parse_sipfrag_status(&tx.original.body) {
+            Some(status) => {
+                info!(%id, %status, "transfer progress");
+                self.transition(DialogState::ReferProgress(id, status))
+            }
+            None => {
+                info!(%id, "refer notify without a sipfrag status line");
+                Ok(())
+            }
+        }
+    }
+}
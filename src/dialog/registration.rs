@@ -3,7 +3,7 @@ use super::{
     DialogId,
 };
 use crate::{
-    rsip_ext::RsipResponseExt,
+    rsip_ext::{header_value_case_insensitive, split_header_list, RsipResponseExt},
     transaction::{
         endpoint::EndpointInnerRef,
         key::{TransactionKey, TransactionRole},
@@ -14,10 +14,36 @@ use crate::{
     Result,
 };
 use rsip::{
-    prelude::{HeadersExt, ToTypedHeader},
+    prelude::{HeadersExt, ToTypedHeader, UntypedHeader},
     Response, SipMessage, StatusCode,
 };
-use tracing::{debug, info};
+use std::time::Duration;
+use tokio::{
+    select,
+    sync::{broadcast, mpsc::UnboundedSender},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// First retry delay after a failed registration, doubled on each failure
+const RETRY_MIN_INTERVAL: Duration = Duration::from_secs(5);
+const RETRY_MAX_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Progress of the refresh loop run by [`Registration::serve`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistrationState {
+    /// Registered for `expires` seconds
+    Registered(u32),
+    /// A refresh REGISTER is on its way
+    Refreshing,
+    /// The last attempt failed with this final response, or an error
+    Failed(Option<StatusCode>, String),
+    /// The stream connection to the registrar closed and is dialed again;
+    /// carries the number of reconnects so far
+    Reconnecting(u64),
+}
+
+pub type RegistrationStateSender = UnboundedSender<RegistrationState>;
 
 /// SIP Registration Client
 ///
@@ -56,6 +82,7 @@ use tracing::{debug, info};
 ///     username: "alice".to_string(),
 ///     password: "secret123".to_string(),
 ///     realm: Some("example.com".to_string()),
+///     algorithm: None,
 /// };
 ///
 /// let mut registration = Registration::new(endpoint.inner.clone(), Some(credential));
@@ -116,10 +143,53 @@ pub struct Registration {
     pub endpoint: EndpointInnerRef,
     pub credential: Option<Credential>,
     pub contact: Option<rsip::typed::Contact>,
+    /// Bindings registered together instead of the derived `contact`, see
+    /// [`add_contact`](Self::add_contact)
+    pub contacts: Vec<rsip::typed::Contact>,
     pub allow: rsip::headers::Allow,
     /// Public address detected by the server (IP and port)
     pub public_address: Option<rsip::HostWithPort>,
     pub call_id: rsip::headers::CallId,
+    /// From tag of every REGISTER, see [`with_from_tag`](Self::with_from_tag);
+    /// a new random one per request when `None`
+    pub from_tag: Option<String>,
+    /// Shortest registration interval the client is willing to refresh at
+    ///
+    /// Sent as a `Min-Expires` hint, and the requested expires never goes
+    /// below it, even when the registrar would accept less.
+    pub min_expires: Option<u32>,
+    /// Learn the NAT mapping from the `received`/`rport` of the Via in
+    /// responses and register it as the Contact host and port on the next
+    /// refresh, see [`update_contact_from_response`](Self::update_contact_from_response).
+    /// On by default.
+    pub rewrite_contact: bool,
+    /// Interval requested when [`register`](Self::register) is called
+    /// without one, see [`with_expires`](Self::with_expires)
+    pub requested_expires: Option<u32>,
+    /// Registrar address to send to instead of resolving the server URI,
+    /// see [`with_destination`](Self::with_destination)
+    pub destination: Option<SipAddr>,
+    retry_after: Option<Duration>,
+    granted_expires: Option<u32>,
+    // registrar end of the TCP/TLS connection the last REGISTER went over
+    flow: Option<SipAddr>,
+    reconnects: u64,
+    redialing: bool,
+}
+
+/// Resolves once the connection to `flow` is reported closed, never when
+/// there is none
+async fn flow_closed(closed: &mut broadcast::Receiver<SipAddr>, flow: Option<SipAddr>) {
+    let Some(flow) = flow else {
+        return std::future::pending().await;
+    };
+    loop {
+        match closed.recv().await {
+            Ok(addr) if addr == flow => return,
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return std::future::pending().await,
+        }
+    }
 }
 
 impl Registration {
@@ -154,6 +224,7 @@ impl Registration {
     ///     username: "alice".to_string(),
     ///     password: "secret123".to_string(),
     ///     realm: Some("example.com".to_string()),
+    ///     algorithm: None,
     /// };
     /// let registration = Registration::new(endpoint.inner.clone(), Some(credential));
     /// # }
@@ -165,12 +236,87 @@ impl Registration {
             endpoint,
             credential,
             contact: None,
+            contacts: Vec::new(),
             allow: Default::default(),
             public_address: None,
             call_id,
+            from_tag: None,
+            min_expires: None,
+            rewrite_contact: true,
+            requested_expires: None,
+            destination: None,
+            retry_after: None,
+            granted_expires: None,
+            flow: None,
+            reconnects: 0,
+            redialing: false,
         }
     }
 
+    /// Ask the registrar for `seconds` on every REGISTER
+    ///
+    /// The value is sent both as the `Expires` header and as the `expires`
+    /// parameter of each Contact. A `423 Interval Too Brief` is retried with
+    /// the registrar's `Min-Expires`.
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::registration::Registration;
+    /// # use rsipstack::transaction::endpoint::Endpoint;
+    /// # fn example() {
+    /// # let endpoint: Endpoint = todo!();
+    /// let registration = Registration::new(endpoint.inner.clone(), None).with_expires(3600);
+    /// # }
+    /// ```
+    pub fn with_expires(mut self, seconds: u32) -> Self {
+        self.requested_expires = Some(seconds);
+        self
+    }
+
+    /// Use `tag` as the From tag of every REGISTER
+    ///
+    /// Keeps the requests of this registration recognizable across
+    /// refreshes and re-registrations, like its Call-ID.
+    pub fn with_from_tag(mut self, tag: impl Into<String>) -> Self {
+        self.from_tag = Some(tag.into());
+        self
+    }
+
+    /// Send every REGISTER to `destination`, skipping DNS
+    ///
+    /// The server URI still goes in the Request-URI and To. The Via uses
+    /// the transport of `destination`.
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::registration::Registration;
+    /// # use rsipstack::transaction::endpoint::Endpoint;
+    /// # use rsipstack::transport::SipAddr;
+    /// # fn example() {
+    /// # let endpoint: Endpoint = todo!();
+    /// let registrar = SipAddr::new(
+    ///     rsip::Transport::Tcp,
+    ///     rsip::HostWithPort::try_from("192.0.2.10:5060").unwrap(),
+    /// );
+    /// let registration =
+    ///     Registration::new(endpoint.inner.clone(), None).with_destination(registrar);
+    /// # }
+    /// ```
+    pub fn with_destination(mut self, destination: SipAddr) -> Self {
+        self.destination = Some(destination);
+        self
+    }
+
+    /// Pick the expires value to request
+    ///
+    /// Combines the caller's desired value with the client minimum and, after a
+    /// `423 Interval Too Brief`, the registrar's `Min-Expires`. The result is
+    /// the largest of them; `None` means no explicit expires is requested.
+    pub fn negotiate_expires(&self, desired: Option<u32>, server_min: Option<u32>) -> Option<u32> {
+        [desired, self.min_expires, server_min]
+            .into_iter()
+            .flatten()
+            .max()
+    }
+
     /// Get the discovered public address
     ///
     /// Returns the public IP address and port discovered during the registration
@@ -204,6 +350,46 @@ impl Registration {
         self.public_address.clone()
     }
 
+    /// Delay the registrar asked for with `Retry-After` in the last
+    /// rejection, e.g. a `503 Service Unavailable` during maintenance
+    ///
+    /// Reset by every [`register`](Self::register) call, so it only ever
+    /// describes the latest response.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    /// Times the TCP/TLS connection to the registrar was found closed and
+    /// dialed again
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects
+    }
+
+    /// Forget the closed connection and what was learned over it, so the
+    /// next REGISTER dials again and starts from scratch
+    fn reset_flow(&mut self) {
+        if let Some(addr) = self.flow.take() {
+            self.endpoint.transport_layer.del_connection(&addr);
+        }
+        self.reconnects += 1;
+        self.public_address = None;
+        if self.rewrite_contact {
+            self.contact = None;
+        }
+    }
+
+    /// Whether to register again over a new connection after the one in
+    /// use closed; done once per [`register`](Self::register) call
+    fn begin_redial(&mut self, redialing: bool) -> bool {
+        if redialing || self.flow.is_none() {
+            return false;
+        }
+        info!(flow = ?self.flow, "registrar connection closed, dialing again");
+        self.reset_flow();
+        self.redialing = true;
+        true
+    }
+
     /// Get the registration expiration time
     ///
     /// Returns the expiration time in seconds for the current registration.
@@ -212,7 +398,12 @@ impl Registration {
     ///
     /// # Returns
     ///
-    /// Expiration time in seconds (default: 50 if not set)
+    /// Expiration time in seconds. A registrar that grants no `expires`
+    /// parameter is taken at the `Expires` header of its 2xx, or else at
+    /// the interval requested. Before the first registration this is the
+    /// requested interval, or the endpoint's `default_register_expires`.
+    /// With several bindings this is the shortest expiry granted to any of
+    /// them.
     ///
     /// # Examples
     ///
@@ -230,11 +421,82 @@ impl Registration {
     /// # }
     /// ```
     pub fn expires(&self) -> u32 {
-        self.contact
-            .as_ref()
-            .and_then(|c| c.expires())
-            .map(|e| e.seconds().unwrap_or(50))
-            .unwrap_or(50)
+        let default = self
+            .granted_expires
+            .or(self.requested_expires)
+            .unwrap_or(self.endpoint.option.default_register_expires);
+        let binding_expires = |c: &rsip::typed::Contact| {
+            c.expires()
+                .and_then(|e| e.seconds().ok())
+                .unwrap_or(default)
+        };
+        match self.contacts.is_empty() {
+            true => self
+                .contact
+                .as_ref()
+                .map(binding_expires)
+                .unwrap_or(default),
+            false => self
+                .contacts
+                .iter()
+                .map(binding_expires)
+                .min()
+                .unwrap_or(default),
+        }
+    }
+
+    /// Register `contact` as one more binding in the same REGISTER
+    ///
+    /// Once a binding is added, the REGISTER carries exactly the added
+    /// bindings instead of the single Contact derived from the transport,
+    /// e.g. one UDP and one TCP contact on a multi-homed host.
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::registration::Registration;
+    /// # use rsip::prelude::{ToTypedHeader, UntypedHeader};
+    /// # fn example(mut registration: Registration) {
+    /// registration.add_contact(
+    ///     rsip::headers::Contact::new("<sip:alice@192.0.2.10:5060>")
+    ///         .typed()
+    ///         .unwrap(),
+    /// );
+    /// registration.add_contact(
+    ///     rsip::headers::Contact::new("<sip:alice@192.0.2.10:5060;transport=tcp>")
+    ///         .typed()
+    ///         .unwrap(),
+    /// );
+    /// # }
+    /// ```
+    pub fn add_contact(&mut self, contact: rsip::typed::Contact) {
+        self.contacts.push(contact);
+    }
+
+    /// Take the expiry the registrar granted to each of our bindings from
+    /// the Contact list of a 2xx
+    pub fn update_bindings_from_response(&mut self, resp: &Response) {
+        let granted = resp
+            .headers
+            .iter()
+            .filter_map(|h| match h {
+                rsip::Header::Contact(contact) => Some(contact),
+                _ => None,
+            })
+            .flat_map(|contact| split_header_list(contact.value()))
+            .filter_map(|value| rsip::headers::Contact::new(value).typed().ok())
+            .collect::<Vec<_>>();
+        for binding in self.contacts.iter_mut() {
+            let Some(expires) = granted
+                .iter()
+                .find(|c| same_binding(&c.uri, &binding.uri))
+                .and_then(|c| c.expires().cloned())
+            else {
+                continue;
+            };
+            binding
+                .params
+                .retain(|p| !matches!(p, rsip::Param::Expires(_)));
+            binding.params.push(rsip::Param::Expires(expires));
+        }
     }
 
     /// Perform SIP registration with the server
@@ -346,7 +608,10 @@ impl Registration {
     /// before calling this method.
     ///
     pub async fn register(&mut self, server: rsip::Uri, expires: Option<u32>) -> Result<Response> {
+        let expires_arg = expires;
+        let redialing = std::mem::take(&mut self.redialing);
         self.last_seq += 1;
+        self.retry_after = None;
 
         let mut to = rsip::typed::To {
             display_name: None,
@@ -366,7 +631,12 @@ impl Registration {
             uri: to.uri.clone(),
             params: vec![],
         }
-        .with_tag(make_tag());
+        .with_tag(
+            self.from_tag
+                .clone()
+                .map(rsip::param::Tag::from)
+                .unwrap_or_else(make_tag),
+        );
 
         let via = self.endpoint.get_via(None, None)?;
 
@@ -382,6 +652,7 @@ impl Registration {
             let contact_host_with_port = self
                 .public_address
                 .clone()
+                .filter(|_| self.rewrite_contact)
                 .unwrap_or_else(|| via.uri.host_with_port.clone());
             rsip::typed::Contact {
                 display_name: None,
@@ -397,28 +668,118 @@ impl Registration {
         });
         let mut request = self.endpoint.make_request(
             rsip::Method::Register,
-            server,
+            server.clone(),
             via,
             from,
             to,
             self.last_seq,
             None,
+            None,
         );
 
+        // without an expires of their own, the bindings ask for the
+        // endpoint's default
+        let bindings_expire = match self.contacts.is_empty() {
+            true => contact.expires().is_some(),
+            false => self.contacts.iter().all(|c| c.expires().is_some()),
+        };
+        let requested = expires.or(self.requested_expires);
+        let expires = requested.or_else(|| {
+            (!bindings_expire).then_some(self.endpoint.option.default_register_expires)
+        });
+
         // Thanks to https://github.com/restsend/rsipstack/issues/32
         request.headers.unique_push(self.call_id.clone().into());
-        request.headers.unique_push(contact.into());
+        if self.contacts.is_empty() {
+            request.headers.unique_push(contact.into());
+        } else {
+            for binding in &self.contacts {
+                request.headers.push(binding.clone().into());
+            }
+        }
         request.headers.unique_push(self.allow.clone().into());
+        // a de-registration sets expires=0 on the stored Contact, or removes
+        // every binding with `Contact: *` when nothing was registered yet
+        let expires = match expires {
+            Some(0) => {
+                let unregister = |contact: &rsip::typed::Contact| {
+                    let mut contact = contact.clone();
+                    contact
+                        .params
+                        .retain(|p| !matches!(p, rsip::Param::Expires(_)));
+                    contact
+                        .params
+                        .push(rsip::Param::Expires(rsip::param::Expires::new("0")));
+                    rsip::Header::from(contact)
+                };
+                request
+                    .headers
+                    .retain(|h| !matches!(h, rsip::Header::Contact(_)));
+                match (&self.contact, self.contacts.is_empty()) {
+                    (_, false) => {
+                        for binding in &self.contacts {
+                            request.headers.push(unregister(binding));
+                        }
+                    }
+                    (Some(contact), true) => request.headers.push(unregister(contact)),
+                    (None, true) => request
+                        .headers
+                        .push(rsip::headers::Contact::new("*").into()),
+                }
+                Some(0)
+            }
+            _ => self.negotiate_expires(expires, None),
+        };
         if let Some(expires) = expires {
             request
                 .headers
                 .unique_push(rsip::headers::Expires::from(expires).into());
         }
+        // an interval asked for explicitly goes in every binding too, so a
+        // stale granted value does not contradict the Expires header
+        if let (Some(_), Some(seconds)) = (requested, expires.filter(|e| *e > 0)) {
+            for header in request.headers.iter_mut() {
+                let rsip::Header::Contact(contact) = header else {
+                    continue;
+                };
+                let Ok(mut contact) = contact.typed() else {
+                    continue;
+                };
+                contact
+                    .params
+                    .retain(|p| !matches!(p, rsip::Param::Expires(_)));
+                contact
+                    .params
+                    .push(rsip::Param::Expires(rsip::param::Expires::new(
+                        seconds.to_string(),
+                    )));
+                *header = contact.into();
+            }
+        }
+        if let Some(min_expires) = self.min_expires {
+            request
+                .headers
+                .unique_push(rsip::Header::MinExpires(min_expires.to_string().into()));
+        }
 
         let key = TransactionKey::from_request(&request, TransactionRole::Client)?;
         let mut tx = Transaction::new_client(key, request, self.endpoint.clone(), None);
+        if let Some(destination) = self.destination.clone() {
+            tx = tx.with_destination(destination);
+        }
 
-        tx.send().await?;
+        let sent = tx.send().await;
+        self.flow = tx
+            .connection
+            .as_ref()
+            .filter(|c| c.is_reliable())
+            .map(|c| c.get_addr().clone());
+        if let Err(e) = sent {
+            if self.begin_redial(redialing) {
+                return Box::pin(self.register(server, expires_arg)).await;
+            }
+            return Err(e);
+        }
         let mut auth_sent = false;
 
         while let Some(msg) = tx.receive().await {
@@ -458,6 +819,26 @@ impl Registration {
                             return Ok(resp);
                         }
                     }
+                    StatusCode::IntervalTooBrief => {
+                        let server_min =
+                            header_value_case_insensitive(&resp.headers, "Min-Expires")
+                                .and_then(|v| v.trim().parse::<u32>().ok());
+                        let retry_expires = self.negotiate_expires(expires, server_min);
+                        // only retry when it asks for a longer interval, so this terminates
+                        match retry_expires {
+                            Some(retry) if expires.is_none_or(|e| retry > e) => {
+                                info!(
+                                    "registrar requires longer interval: {:?} -> {}",
+                                    expires, retry
+                                );
+                                return Box::pin(self.register(server, Some(retry))).await;
+                            }
+                            _ => {
+                                info!("registration do_request done: {:?}", resp.status_code);
+                                return Ok(resp);
+                            }
+                        }
+                    }
                     StatusCode::OK => {
                         // Check if server indicated our public IP in Via header
                         let received = resp.via_received();
@@ -476,6 +857,12 @@ impl Registration {
                             );
                             self.public_address = received;
                         }
+                        self.update_contact_from_response(&resp);
+                        self.update_bindings_from_response(&resp);
+                        self.granted_expires =
+                            header_value_case_insensitive(&resp.headers, "Expires")
+                                .and_then(|v| v.trim().parse::<u32>().ok())
+                                .or(expires);
                         info!(
                             "registration do_request done: {:?} {:?}",
                             resp.status_code,
@@ -484,13 +871,21 @@ impl Registration {
                         return Ok(resp);
                     }
                     _ => {
-                        info!("registration do_request done: {:?}", resp.status_code);
+                        self.retry_after = resp.retry_after();
+                        info!(
+                            "registration do_request done: {:?} retry after {:?}",
+                            resp.status_code, self.retry_after
+                        );
                         return Ok(resp);
                     }
                 },
                 _ => break,
             }
         }
+        // the transaction ends without a response when its connection closes
+        if self.begin_redial(redialing) {
+            return Box::pin(self.register(server, expires_arg)).await;
+        }
         return Err(crate::Error::DialogError(
             "registration transaction is already terminated".to_string(),
             DialogId::try_from(&tx.original)?,
@@ -498,6 +893,175 @@ impl Registration {
         ));
     }
 
+    /// Remove the registration from the server
+    ///
+    /// Sends a REGISTER with `Expires: 0` and the stored Contact carrying
+    /// `expires=0`, answering authentication challenges like
+    /// [`register`](Self::register). Without a stored Contact all bindings of
+    /// the address of record are removed with `Contact: *`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::registration::Registration;
+    /// # async fn example() -> rsipstack::Result<()> {
+    /// # let mut registration: Registration = todo!();
+    /// let server = rsip::Uri::try_from("sip:sip.example.com").unwrap();
+    /// let response = registration.unregister(server).await?;
+    /// assert_eq!(response.status_code, rsip::StatusCode::OK);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn unregister(&mut self, server: rsip::Uri) -> Result<Response> {
+        self.register(server, Some(0)).await
+    }
+
+    /// Keep the registration alive until `token` is cancelled
+    ///
+    /// Registers with `server`, then refreshes at 90% of the granted
+    /// [`expires`](Self::expires). `423 Interval Too Brief` is handled by
+    /// [`register`](Self::register); other failures are retried after the
+    /// response's `Retry-After`, or else with a backoff doubling from 5 seconds
+    /// up to 5 minutes. On cancellation the binding is removed with an
+    /// `Expires: 0` REGISTER before returning.
+    ///
+    /// Over TCP or TLS, a registrar connection that closes between refreshes
+    /// is reported as `Reconnecting` and dialed again right away, with a new
+    /// REGISTER, instead of at the next refresh.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::registration::{Registration, RegistrationState};
+    /// # use rsipstack::transaction::endpoint::Endpoint;
+    /// # use tokio_util::sync::CancellationToken;
+    /// # async fn example() {
+    /// # let endpoint: Endpoint = todo!();
+    /// let registration = Registration::new(endpoint.inner.clone(), None);
+    /// let server = rsip::Uri::try_from("sip:sip.example.com").unwrap();
+    /// let token = CancellationToken::new();
+    /// let (state_sender, mut state_receiver) = tokio::sync::mpsc::unbounded_channel();
+    ///
+    /// tokio::spawn(registration.serve(server, Some(3600), token.clone(), Some(state_sender)));
+    /// while let Some(state) = state_receiver.recv().await {
+    ///     if let RegistrationState::Failed(status, reason) = state {
+    ///         eprintln!("registration failed: {:?} {}", status, reason);
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub async fn serve(
+        mut self,
+        server: rsip::Uri,
+        expires: Option<u32>,
+        token: CancellationToken,
+        state_sender: Option<RegistrationStateSender>,
+    ) -> Result<()> {
+        let emit = |state: RegistrationState| {
+            if let Some(sender) = &state_sender {
+                sender.send(state).ok();
+            }
+        };
+        // the current delay, doubled for the next failure
+        let backoff = |interval: &mut Duration| {
+            let wait = *interval;
+            *interval = (*interval * 2).min(RETRY_MAX_INTERVAL);
+            wait
+        };
+        let mut retry_interval = RETRY_MIN_INTERVAL;
+        let mut registered = false;
+        let mut closed_connections = self.endpoint.subscribe_closed_connections();
+        loop {
+            if registered {
+                emit(RegistrationState::Refreshing);
+            }
+            let wait = select! {
+                _ = token.cancelled() => break,
+                result = self.register(server.clone(), expires) => match result {
+                    Ok(resp) if resp.status_code == StatusCode::OK => {
+                        registered = true;
+                        retry_interval = RETRY_MIN_INTERVAL;
+                        let granted = self.expires();
+                        emit(RegistrationState::Registered(granted));
+                        Duration::from_millis(granted as u64 * 900).max(Duration::from_secs(1))
+                    }
+                    Ok(resp) => {
+                        registered = false;
+                        warn!(%server, status = %resp.status_code, "registration failed");
+                        emit(RegistrationState::Failed(
+                            Some(resp.status_code.clone()),
+                            resp.status_code.to_string(),
+                        ));
+                        match self.retry_after() {
+                            Some(retry_after) => retry_after.max(Duration::from_secs(1)),
+                            None => backoff(&mut retry_interval),
+                        }
+                    }
+                    Err(e) => {
+                        registered = false;
+                        warn!(%server, "registration error: {}", e);
+                        emit(RegistrationState::Failed(None, e.to_string()));
+                        backoff(&mut retry_interval)
+                    }
+                },
+            };
+            select! {
+                _ = token.cancelled() => break,
+                _ = tokio::time::sleep(wait) => {}
+                _ = flow_closed(&mut closed_connections, self.flow.clone()) => {
+                    self.reset_flow();
+                    emit(RegistrationState::Reconnecting(self.reconnects));
+                }
+            }
+        }
+
+        if registered {
+            info!(%server, "unregistering");
+            self.unregister(server).await?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the stored Contact with the address the server saw
+    ///
+    /// The top Via of a response carries `received`/`rport` when the server
+    /// observed a different source address than the one we advertised, which
+    /// is the case behind NAT. When present, they replace the host and port of
+    /// the stored Contact so the next REGISTER (and any dialog using
+    /// `contact`) points at the reachable address.
+    ///
+    /// Nothing changes unless [`rewrite_contact`](Self::rewrite_contact) is
+    /// set. Returns `true` when the Contact was changed.
+    pub fn update_contact_from_response(&mut self, resp: &Response) -> bool {
+        if !self.rewrite_contact {
+            return false;
+        }
+        let (received, rport) = match resp.via_received_rport() {
+            Some(v) => v,
+            None => return false,
+        };
+        let contact = match self.contact.as_mut() {
+            Some(contact) => contact,
+            None => return false,
+        };
+        let mut host_with_port = contact.uri.host_with_port.clone();
+        if let Some(host) = received {
+            host_with_port.host = host;
+        }
+        if let Some(port) = rport {
+            host_with_port.port = Some(port.into());
+        }
+        if contact.uri.host_with_port == host_with_port {
+            return false;
+        }
+        info!(
+            "rewriting contact with discovered address: {} -> {}",
+            contact.uri.host_with_port, host_with_port
+        );
+        contact.uri.host_with_port = host_with_port;
+        true
+    }
+
     /// Create a NAT-aware Contact header with public address
     ///
     /// Creates a Contact header suitable for use in SIP dialogs that takes into
@@ -562,3 +1126,15 @@ impl Registration {
         }
     }
 }
+
+/// Registrars may reorder or add URI parameters, so bindings are matched on
+/// user, host, port and transport
+fn same_binding(a: &rsip::Uri, b: &rsip::Uri) -> bool {
+    let transport = |uri: &rsip::Uri| {
+        uri.params.iter().find_map(|p| match p {
+            rsip::Param::Transport(t) => Some(*t),
+            _ => None,
+        })
+    };
+    a.user() == b.user() && a.host_with_port == b.host_with_port && transport(a) == transport(b)
+}
@@ -0,0 +1,680 @@
+use std::net::{IpAddr, SocketAddr};
+
+/// Lines of an SDP body that describe the negotiated media
+///
+/// The origin (`o=`) line is skipped: its session version is bumped by some
+/// user agents on every refresh even when nothing else changes. Blank lines
+/// and trailing whitespace are ignored as well.
+fn media_lines(sdp: &[u8]) -> Vec<&str> {
+    std::str::from_utf8(sdp)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| line.trim_end())
+        .filter(|line| !line.is_empty() && !line.starts_with("o="))
+        .collect()
+}
+
+/// Check whether `current` changes the media session described by `previous`
+///
+/// Used to tell session refresh re-INVITEs (RFC 4028), which repeat the
+/// previous offer, from re-INVITEs that actually renegotiate media such as a
+/// codec change or hold.
+pub fn sdp_media_changed(previous: &[u8], current: &[u8]) -> bool {
+    media_lines(previous) != media_lines(current)
+}
+
+fn connection_address(value: &str) -> Option<IpAddr> {
+    // c=<nettype> <addrtype> <connection-address>[/ttl]
+    let address = value.split_whitespace().nth(2)?;
+    address.split('/').next()?.parse().ok()
+}
+
+/// Remote RTP address of the first active media stream of an SDP body,
+/// see [`SessionDescription::rtp_address`]
+///
+/// Returns `None` when the body does not parse or has no active media.
+pub fn sdp_rtp_target(sdp: &[u8]) -> Option<SocketAddr> {
+    SessionDescription::parse(sdp)?.rtp_address()
+}
+
+/// An SDP body (RFC 4566) as its ordered `<type>=<value>` lines
+///
+/// Only what media anchoring needs is interpreted: connection addresses and
+/// media ports. Every other line is kept as-is.
+///
+/// # Examples
+///
+/// ```rust
+/// use rsipstack::dialog::sdp::Sdp;
+///
+/// let body = b"v=0\r\no=- 1 1 IN IP4 10.0.0.1\r\ns=-\r\nc=IN IP4 10.0.0.1\r\nt=0 0\r\nm=audio 4000 RTP/AVP 0\r\n";
+/// let mut sdp = Sdp::parse(body).unwrap();
+/// sdp.set_connection_address("203.0.113.5".parse().unwrap());
+/// sdp.set_media_port(0, 20000);
+/// let body = String::from_utf8(sdp.to_bytes()).unwrap();
+/// assert!(body.contains("c=IN IP4 203.0.113.5\r\n"));
+/// assert!(body.contains("m=audio 20000 RTP/AVP 0\r\n"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sdp {
+    pub lines: Vec<(char, String)>,
+}
+
+impl Sdp {
+    /// Parse a body starting with `v=`; `None` if any line is not `<type>=<value>`
+    pub fn parse(body: &[u8]) -> Option<Self> {
+        let mut lines = Vec::new();
+        for line in std::str::from_utf8(body).ok()?.lines().map(str::trim_end) {
+            if line.is_empty() {
+                continue;
+            }
+            let (kind, value) = line.split_once('=')?;
+            let mut kind = kind.chars();
+            match (kind.next(), kind.next()) {
+                (Some(kind), None) => lines.push((kind, value.to_string())),
+                _ => return None,
+            }
+        }
+        match lines.first() {
+            Some(('v', _)) => Some(Self { lines }),
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.lines
+            .iter()
+            .map(|(kind, value)| format!("{}={}\r\n", kind, value))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    /// Session-level connection address, or the first media-level one
+    pub fn connection_address(&self) -> Option<IpAddr> {
+        self.lines
+            .iter()
+            .filter(|(kind, _)| *kind == 'c')
+            .find_map(|(_, value)| connection_address(value))
+    }
+
+    /// Point every `c=` line, session and media level, at `addr`
+    pub fn set_connection_address(&mut self, addr: IpAddr) {
+        let addrtype = if addr.is_ipv4() { "IP4" } else { "IP6" };
+        for (_, value) in self.lines.iter_mut().filter(|(kind, _)| *kind == 'c') {
+            let nettype = value.split_whitespace().next().unwrap_or("IN").to_string();
+            *value = format!("{} {} {}", nettype, addrtype, addr);
+        }
+    }
+
+    /// Port of the `index`th `m=` line
+    pub fn media_port(&self, index: usize) -> Option<u16> {
+        let (_, media) = self
+            .lines
+            .iter()
+            .filter(|(kind, _)| *kind == 'm')
+            .nth(index)?;
+        media
+            .split_whitespace()
+            .nth(1)?
+            .split('/')
+            .next()?
+            .parse()
+            .ok()
+    }
+
+    /// Change the port of the `index`th `m=` line, keeping any port count;
+    /// `false` if there is no such media
+    pub fn set_media_port(&mut self, index: usize, port: u16) -> bool {
+        let Some((_, media)) = self
+            .lines
+            .iter_mut()
+            .filter(|(kind, _)| *kind == 'm')
+            .nth(index)
+        else {
+            return false;
+        };
+        let mut fields: Vec<String> = media.split_whitespace().map(str::to_string).collect();
+        let Some(current) = fields.get_mut(1) else {
+            return false;
+        };
+        *current = match current.split_once('/') {
+            Some((_, count)) => format!("{}/{}", port, count),
+            None => port.to_string(),
+        };
+        *media = fields.join(" ");
+        true
+    }
+}
+
+/// Media direction attribute (RFC 3264 §6.1), `sendrecv` when absent
+///
+/// Putting a call on hold offers `sendonly`, and `inactive` when the other
+/// side already holds it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    #[default]
+    SendRecv,
+    SendOnly,
+    RecvOnly,
+    Inactive,
+}
+
+impl Direction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "sendrecv" => Some(Direction::SendRecv),
+            "sendonly" => Some(Direction::SendOnly),
+            "recvonly" => Some(Direction::RecvOnly),
+            "inactive" => Some(Direction::Inactive),
+            _ => None,
+        }
+    }
+
+    /// The direction the other side answers with
+    pub fn reverse(&self) -> Self {
+        match self {
+            Direction::SendOnly => Direction::RecvOnly,
+            Direction::RecvOnly => Direction::SendOnly,
+            direction => *direction,
+        }
+    }
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::SendRecv => write!(f, "sendrecv"),
+            Direction::SendOnly => write!(f, "sendonly"),
+            Direction::RecvOnly => write!(f, "recvonly"),
+            Direction::Inactive => write!(f, "inactive"),
+        }
+    }
+}
+
+/// A payload format of a media line, from its `a=rtpmap` and `a=fmtp`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Codec {
+    pub payload_type: u8,
+    pub name: String,
+    pub clock_rate: u32,
+    pub channels: Option<u16>,
+    pub fmtp: Option<String>,
+}
+
+impl Codec {
+    pub fn new(payload_type: u8, name: &str, clock_rate: u32) -> Self {
+        Self {
+            payload_type,
+            name: name.to_string(),
+            clock_rate,
+            channels: None,
+            fmtp: None,
+        }
+    }
+
+    pub fn with_fmtp(mut self, fmtp: &str) -> Self {
+        self.fmtp = Some(fmtp.to_string());
+        self
+    }
+
+    pub fn pcmu() -> Self {
+        Self::new(0, "PCMU", 8000)
+    }
+
+    pub fn pcma() -> Self {
+        Self::new(8, "PCMA", 8000)
+    }
+
+    /// DTMF events (RFC 4733) on a dynamic payload type
+    pub fn telephone_event(payload_type: u8) -> Self {
+        Self::new(payload_type, "telephone-event", 8000).with_fmtp("0-16")
+    }
+
+    // static payload types that may come without `a=rtpmap` (RFC 3551)
+    fn from_static(payload_type: u8) -> Option<Self> {
+        let (name, clock_rate) = match payload_type {
+            0 => ("PCMU", 8000),
+            3 => ("GSM", 8000),
+            4 => ("G723", 8000),
+            8 => ("PCMA", 8000),
+            9 => ("G722", 8000),
+            18 => ("G729", 8000),
+            _ => return None,
+        };
+        Some(Self::new(payload_type, name, clock_rate))
+    }
+}
+
+/// One `m=` section of a [`SessionDescription`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaDescription {
+    /// `audio`, `video`, ...
+    pub media: String,
+    pub port: u16,
+    pub protocol: String,
+    /// In order of preference
+    pub codecs: Vec<Codec>,
+    /// Media-level `c=` address, overriding the session-level one
+    pub connection: Option<IpAddr>,
+    pub direction: Option<Direction>,
+    /// Other `a=` lines, without the `a=` prefix
+    pub attributes: Vec<String>,
+}
+
+impl MediaDescription {
+    /// An `RTP/AVP` audio stream on `port`
+    pub fn audio(port: u16, codecs: Vec<Codec>) -> Self {
+        Self {
+            media: "audio".to_string(),
+            port,
+            protocol: "RTP/AVP".to_string(),
+            codecs,
+            connection: None,
+            direction: None,
+            attributes: Vec::new(),
+        }
+    }
+
+    pub fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = Some(direction);
+        self
+    }
+
+    pub fn with_attribute(mut self, attribute: &str) -> Self {
+        self.attributes.push(attribute.to_string());
+        self
+    }
+}
+
+/// SDP offer or answer (RFC 4566) built from its parts
+///
+/// Builds the bodies for `InviteOption::offer` and answers, and reads the
+/// codec and RTP address the other side picked. It is read from and written
+/// to the lines of an [`Sdp`]; only the lines described here are modelled,
+/// others are dropped.
+///
+/// # Examples
+///
+/// ```rust
+/// use rsipstack::dialog::sdp::{Codec, Direction, MediaDescription, SessionDescription};
+///
+/// let offer = SessionDescription::new("192.0.2.10".parse().unwrap())
+///     .with_media(MediaDescription::audio(
+///         49170,
+///         vec![Codec::pcmu(), Codec::pcma(), Codec::telephone_event(101)],
+///     ))
+///     .to_bytes();
+///
+/// let answer = b"v=0\r\no=bob 1 1 IN IP4 198.51.100.7\r\ns=-\r\nc=IN IP4 198.51.100.7\r\nt=0 0\r\nm=audio 30000 RTP/AVP 8 101\r\na=rtpmap:101 telephone-event/8000\r\na=sendonly\r\n";
+/// let answer = SessionDescription::parse(answer).unwrap();
+/// assert_eq!(answer.negotiated_codec(), Some(&Codec::pcma()));
+/// assert_eq!(answer.rtp_address(), "198.51.100.7:30000".parse().ok());
+/// assert_eq!(answer.direction(), Direction::SendOnly);
+/// # assert!(!offer.is_empty());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionDescription {
+    pub username: String,
+    pub session_id: u64,
+    pub session_version: u64,
+    /// Address of the `o=` line and the session-level `c=` line
+    pub address: IpAddr,
+    pub session_name: String,
+    /// Session-level direction, for media without their own
+    pub direction: Option<Direction>,
+    pub media: Vec<MediaDescription>,
+}
+
+impl SessionDescription {
+    /// An empty session at `address`, with a time based session id
+    pub fn new(address: IpAddr) -> Self {
+        let session_id = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Self {
+            username: "-".to_string(),
+            session_id,
+            session_version: session_id,
+            address,
+            session_name: "-".to_string(),
+            direction: None,
+            media: Vec::new(),
+        }
+    }
+
+    pub fn with_origin(mut self, username: &str, session_id: u64, session_version: u64) -> Self {
+        self.username = username.to_string();
+        self.session_id = session_id;
+        self.session_version = session_version;
+        self
+    }
+
+    pub fn with_session_name(mut self, session_name: &str) -> Self {
+        self.session_name = session_name.to_string();
+        self
+    }
+
+    pub fn with_media(mut self, media: MediaDescription) -> Self {
+        self.media.push(media);
+        self
+    }
+
+    /// Set the direction of every media stream, e.g. `SendOnly` to hold
+    ///
+    /// The session version is bumped, as a re-offer has to (RFC 3264 §8).
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.direction = None;
+        for media in self.media.iter_mut() {
+            media.direction = Some(direction);
+        }
+        self.session_version += 1;
+    }
+
+    /// Direction of the first media stream, `sendrecv` if unspecified
+    pub fn direction(&self) -> Direction {
+        self.media
+            .first()
+            .and_then(|media| media.direction)
+            .or(self.direction)
+            .unwrap_or_default()
+    }
+
+    /// First codec of the first active media stream
+    ///
+    /// In an answer this is the codec the other side settled on.
+    pub fn negotiated_codec(&self) -> Option<&Codec> {
+        self.media
+            .iter()
+            .find(|media| media.port != 0)?
+            .codecs
+            .first()
+    }
+
+    /// Where to send RTP for the first active media stream
+    ///
+    /// A media-level `c=` address takes precedence over the session-level
+    /// one.
+    pub fn rtp_address(&self) -> Option<SocketAddr> {
+        let media = self.media.iter().find(|media| media.port != 0)?;
+        Some(SocketAddr::new(
+            media.connection.unwrap_or(self.address),
+            media.port,
+        ))
+    }
+
+    /// The body as [`Sdp`] lines
+    pub fn to_sdp(&self) -> Sdp {
+        let addrtype = |addr: &IpAddr| if addr.is_ipv4() { "IP4" } else { "IP6" };
+        let mut lines = vec![
+            ('v', "0".to_string()),
+            (
+                'o',
+                format!(
+                    "{} {} {} IN {} {}",
+                    self.username,
+                    self.session_id,
+                    self.session_version,
+                    addrtype(&self.address),
+                    self.address
+                ),
+            ),
+            ('s', self.session_name.clone()),
+            (
+                'c',
+                format!("IN {} {}", addrtype(&self.address), self.address),
+            ),
+            ('t', "0 0".to_string()),
+        ];
+        if let Some(direction) = self.direction {
+            lines.push(('a', direction.to_string()));
+        }
+        for media in self.media.iter() {
+            let formats = media
+                .codecs
+                .iter()
+                .map(|codec| codec.payload_type.to_string())
+                .collect::<Vec<_>>();
+            lines.push((
+                'm',
+                format!(
+                    "{} {} {} {}",
+                    media.media,
+                    media.port,
+                    media.protocol,
+                    formats.join(" ")
+                ),
+            ));
+            if let Some(addr) = &media.connection {
+                lines.push(('c', format!("IN {} {}", addrtype(addr), addr)));
+            }
+            for codec in media.codecs.iter() {
+                let mut rtpmap = format!(
+                    "rtpmap:{} {}/{}",
+                    codec.payload_type, codec.name, codec.clock_rate
+                );
+                if let Some(channels) = codec.channels {
+                    rtpmap.push_str(&format!("/{}", channels));
+                }
+                lines.push(('a', rtpmap));
+                if let Some(fmtp) = &codec.fmtp {
+                    lines.push(('a', format!("fmtp:{} {}", codec.payload_type, fmtp)));
+                }
+            }
+            lines.extend(media.attributes.iter().map(|a| ('a', a.clone())));
+            if let Some(direction) = media.direction {
+                lines.push(('a', direction.to_string()));
+            }
+        }
+        Sdp { lines }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_sdp().to_bytes()
+    }
+
+    /// Parse an offer or answer; `None` without an `o=` line or a usable
+    /// session address
+    pub fn parse(body: &[u8]) -> Option<Self> {
+        Self::from_sdp(&Sdp::parse(body)?)
+    }
+
+    /// Read the modelled lines of `sdp`, see [`Self::parse`]
+    pub fn from_sdp(sdp: &Sdp) -> Option<Self> {
+        let mut session: Option<Self> = None;
+        let mut session_addr = None;
+        // payload types of the current media line, filled from `a=` lines
+        let mut formats: Vec<(u8, Option<Codec>, Option<String>)> = Vec::new();
+        let mut media: Vec<MediaDescription> = Vec::new();
+        let finish = |media: &mut Vec<MediaDescription>,
+                      formats: &mut Vec<(u8, Option<Codec>, Option<String>)>| {
+            if let Some(current) = media.last_mut() {
+                current.codecs = formats
+                    .drain(..)
+                    .filter_map(|(pt, codec, fmtp)| {
+                        let mut codec = codec.or_else(|| Codec::from_static(pt))?;
+                        codec.fmtp = fmtp;
+                        Some(codec)
+                    })
+                    .collect();
+            }
+        };
+        for (kind, value) in sdp.lines.iter() {
+            match kind {
+                'o' => {
+                    let fields: Vec<&str> = value.split_whitespace().collect();
+                    let [username, id, version, _, _, address] = fields[..] else {
+                        return None;
+                    };
+                    let address = address.parse().ok();
+                    session_addr = session_addr.or(address);
+                    session = Some(Self {
+                        username: username.to_string(),
+                        session_id: id.parse().unwrap_or_default(),
+                        session_version: version.parse().unwrap_or_default(),
+                        address: address.unwrap_or(IpAddr::from([0, 0, 0, 0])),
+                        session_name: "-".to_string(),
+                        direction: None,
+                        media: Vec::new(),
+                    });
+                }
+                's' => {
+                    if let Some(session) = session.as_mut() {
+                        session.session_name = value.clone();
+                    }
+                }
+                'c' => {
+                    let addr = connection_address(value);
+                    match media.last_mut() {
+                        Some(current) => current.connection = addr,
+                        None => session_addr = addr.or(session_addr),
+                    }
+                }
+                'm' => {
+                    finish(&mut media, &mut formats);
+                    let mut fields = value.split_whitespace();
+                    let kind = fields.next()?.to_string();
+                    let port = fields.next()?.split('/').next()?.parse().ok()?;
+                    let protocol = fields.next()?.to_string();
+                    formats = fields
+                        .filter_map(|pt| pt.parse().ok())
+                        .map(|pt| (pt, None, None))
+                        .collect();
+                    let mut current = MediaDescription::audio(port, Vec::new());
+                    current.media = kind;
+                    current.protocol = protocol;
+                    media.push(current);
+                }
+                'a' => {
+                    let (name, rest) = value.split_once(':').unwrap_or((value.as_str(), ""));
+                    let direction = Direction::parse(name);
+                    match (name, media.last_mut()) {
+                        (_, None) if direction.is_some() => {
+                            if let Some(session) = session.as_mut() {
+                                session.direction = direction;
+                            }
+                        }
+                        (_, Some(current)) if direction.is_some() => {
+                            current.direction = direction;
+                        }
+                        ("rtpmap" | "fmtp", Some(_)) => {
+                            let Some((pt, params)) = rest.split_once(' ') else {
+                                continue;
+                            };
+                            let Some(format) = pt
+                                .parse::<u8>()
+                                .ok()
+                                .and_then(|pt| formats.iter_mut().find(|f| f.0 == pt))
+                            else {
+                                continue;
+                            };
+                            if name == "fmtp" {
+                                format.2 = Some(params.trim().to_string());
+                                continue;
+                            }
+                            let mut encoding = params.trim().split('/');
+                            let codec_name = encoding.next().unwrap_or_default();
+                            let clock_rate = encoding.next().and_then(|r| r.parse().ok());
+                            let channels = encoding.next().and_then(|c| c.parse().ok());
+                            if let Some(clock_rate) = clock_rate {
+                                let mut codec = Codec::new(format.0, codec_name, clock_rate);
+                                codec.channels = channels;
+                                format.1 = Some(codec);
+                            }
+                        }
+                        (_, Some(current)) => current.attributes.push(value.clone()),
+                        (_, None) => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        finish(&mut media, &mut formats);
+        let mut session = session?;
+        session.address = session_addr?;
+        session.media = media;
+        Some(session)
+    }
+}
+
+#[test]
+fn test_sdp_media_changed() {
+    let offer = "v=0\r\no=alice 2890844526 2890844526 IN IP4 192.168.1.100\r\ns=-\r\nc=IN IP4 192.168.1.100\r\nt=0 0\r\nm=audio 49170 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\n";
+    let refresh = offer.replace("2890844526 IN", "2890844527 IN");
+    let codec_change = offer
+        .replace("RTP/AVP 0", "RTP/AVP 8")
+        .replace("a=rtpmap:0 PCMU/8000", "a=rtpmap:8 PCMA/8000");
+
+    assert!(!sdp_media_changed(offer.as_bytes(), offer.as_bytes()));
+    assert!(!sdp_media_changed(offer.as_bytes(), refresh.as_bytes()));
+    assert!(sdp_media_changed(offer.as_bytes(), codec_change.as_bytes()));
+
+    assert_eq!(
+        sdp_rtp_target(offer.as_bytes()),
+        "192.168.1.100:49170".parse().ok()
+    );
+    let media_level = offer.replace("RTP/AVP 0\r\n", "RTP/AVP 0\r\nc=IN IP4 10.0.0.7\r\n");
+    assert_eq!(
+        sdp_rtp_target(media_level.as_bytes()),
+        "10.0.0.7:49170".parse().ok()
+    );
+    // a rejected stream is skipped
+    let rejected = offer.replace("m=audio", "m=video 0 RTP/AVP 31\r\nm=audio");
+    assert_eq!(
+        sdp_rtp_target(rejected.as_bytes()),
+        "192.168.1.100:49170".parse().ok()
+    );
+}
+
+#[test]
+fn test_session_description_round_trip() {
+    let offer = SessionDescription::new("192.0.2.10".parse().unwrap())
+        .with_origin("alice", 42, 1)
+        .with_session_name("call")
+        .with_media(
+            MediaDescription::audio(
+                49170,
+                vec![
+                    Codec::pcmu(),
+                    Codec::new(96, "opus", 48000).with_fmtp("useinbandfec=1"),
+                    Codec::telephone_event(101),
+                ],
+            )
+            .with_attribute("ptime:20"),
+        );
+    let body = String::from_utf8(offer.to_bytes()).unwrap();
+    assert_eq!(
+        body,
+        "v=0\r\no=alice 42 1 IN IP4 192.0.2.10\r\ns=call\r\nc=IN IP4 192.0.2.10\r\nt=0 0\r\n\
+         m=audio 49170 RTP/AVP 0 96 101\r\na=rtpmap:0 PCMU/8000\r\na=rtpmap:96 opus/48000\r\n\
+         a=fmtp:96 useinbandfec=1\r\na=rtpmap:101 telephone-event/8000\r\na=fmtp:101 0-16\r\n\
+         a=ptime:20\r\n"
+    );
+    assert_eq!(
+        SessionDescription::parse(body.as_bytes()),
+        Some(offer.clone())
+    );
+
+    // hold: sendonly, a newer version, and the direction survives parsing
+    let mut hold = offer.clone();
+    hold.set_direction(Direction::SendOnly);
+    let parsed = SessionDescription::parse(&hold.to_bytes()).unwrap();
+    assert_eq!(parsed.session_version, 2);
+    assert_eq!(parsed.direction(), Direction::SendOnly);
+    assert_eq!(parsed.direction().reverse(), Direction::RecvOnly);
+}
+
+#[test]
+fn test_session_description_answer() {
+    let answer = "v=0\r\no=- 7 7 IN IP4 198.51.100.7\r\ns=-\r\nc=IN IP4 198.51.100.7\r\nt=0 0\r\n\
+                  a=inactive\r\nm=video 0 RTP/AVP 31\r\nm=audio 30000 RTP/AVP 8 101\r\n\
+                  c=IN IP4 203.0.113.9\r\na=rtpmap:101 telephone-event/8000\r\n";
+    let answer = SessionDescription::parse(answer.as_bytes()).unwrap();
+    // the rejected video stream is skipped, PCMA needs no rtpmap
+    assert_eq!(answer.negotiated_codec(), Some(&Codec::pcma()));
+    assert_eq!(answer.rtp_address(), "203.0.113.9:30000".parse().ok());
+    assert_eq!(answer.direction(), Direction::Inactive);
+    assert_eq!(answer.media[1].codecs.len(), 2);
+
+    assert!(SessionDescription::parse(b"v=0\r\ns=-\r\n").is_none());
+}
@@ -1,12 +1,17 @@
-use super::dialog::{Dialog, DialogInnerRef, DialogState, TerminatedReason};
+use super::caller_preferences::{parse_accept_contact, parse_reject_contact, ContactPreference};
+use super::dialog::{Dialog, DialogInnerRef, DialogRequest, DialogState, TerminatedReason};
+use super::priority::{parse_priority, parse_resource_priority, Priority, ResourcePriority};
+use super::refer::{parse_replaces_header, ReplacesInfo};
 use super::DialogId;
-use crate::rsip_ext::parse_rack_header;
+use crate::rsip_ext::{parse_rack_header, reason_headers, Reason};
 use crate::{
     transaction::transaction::{Transaction, TransactionEvent},
     Result,
 };
 use rsip::{prelude::HeadersExt, Header, Request, SipMessage, StatusCode};
 use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, trace, warn};
 
@@ -19,7 +24,7 @@ use tracing::{debug, info, trace, warn};
 /// # Key Features
 ///
 /// * **Session Acceptance** - Accepts or rejects incoming INVITE requests
-/// * **In-dialog Requests** - Handles UPDATE, INFO, OPTIONS within established dialogs
+/// * **In-dialog Requests** - Handles UPDATE, INFO, OPTIONS, REFER within established dialogs
 /// * **Session Termination** - Handles BYE for ending sessions
 /// * **Re-INVITE Support** - Supports session modification via re-INVITE
 /// * **ACK Handling** - Properly handles ACK for 2xx responses
@@ -124,6 +129,36 @@ impl ServerInviteDialog {
             .clone()
     }
 
+    /// Caller preferences from the `Accept-Contact` headers of the INVITE
+    pub fn accept_contact(&self) -> Vec<ContactPreference> {
+        parse_accept_contact(&self.initial_request().headers)
+    }
+
+    /// Caller preferences from the `Reject-Contact` headers of the INVITE
+    pub fn reject_contact(&self) -> Vec<ContactPreference> {
+        parse_reject_contact(&self.initial_request().headers)
+    }
+
+    /// Dialog this INVITE takes over, from its `Replaces` header (RFC 3891)
+    ///
+    /// Look it up with [`DialogLayer::match_replaces`](super::dialog_layer::DialogLayer::match_replaces)
+    /// before answering, then hang up the replaced dialog.
+    pub fn replaces(&self) -> Option<ReplacesInfo> {
+        parse_replaces_header(&self.initial_request().headers)
+    }
+
+    /// Urgency from the `Priority` header of the INVITE
+    pub fn priority(&self) -> Option<Priority> {
+        parse_priority(&self.initial_request().headers)
+    }
+
+    /// Preemption levels from the `Resource-Priority` headers of the INVITE
+    ///
+    /// Values not in `namespace.priority` form are left out.
+    pub fn resource_priority(&self) -> Vec<ResourcePriority> {
+        parse_resource_priority(&self.initial_request().headers)
+    }
+
     pub fn ringing(&self, headers: Option<Vec<Header>>, body: Option<Vec<u8>>) -> Result<()> {
         if !self.inner.can_cancel() {
             return Ok(());
@@ -180,6 +215,7 @@ impl ServerInviteDialog {
         let resp =
             self.inner
                 .make_response(&self.initial_request(), rsip::StatusCode::OK, headers, body);
+        self.inner.set_local_sdp(&resp.body);
         self.inner
             .tu_sender
             .send(TransactionEvent::Respond(resp.clone()))?;
@@ -329,6 +365,17 @@ impl ServerInviteDialog {
     /// # }
     /// ```
     pub async fn bye(&self) -> Result<()> {
+        self.send_bye(None).await
+    }
+
+    /// Send a BYE carrying a `Reason` header (RFC 3326)
+    ///
+    /// Like [`Self::bye`], for carriers that record why a call ended.
+    pub async fn hangup_with_reason(&self, reason: Reason) -> Result<()> {
+        self.send_bye(Some(vec![reason.into()])).await
+    }
+
+    async fn send_bye(&self, headers: Option<Vec<Header>>) -> Result<()> {
         if !self.inner.is_confirmed() && !self.inner.waiting_ack() {
             return Ok(());
         }
@@ -338,7 +385,7 @@ impl ServerInviteDialog {
             rsip::Method::Bye,
             None,
             self.inner.build_vias_from_request()?,
-            None,
+            headers,
             None,
         )?;
 
@@ -366,9 +413,13 @@ impl ServerInviteDialog {
     ///
     /// # Returns
     ///
-    /// * `Ok(Some(Response))` - Response to the re-INVITE
+    /// * `Ok(Some(Response))` - Response to the re-INVITE; a 2xx is ACKed
     /// * `Ok(None)` - Dialog not confirmed, no request sent
-    /// * `Err(Error)` - Failed to send re-INVITE
+    /// * `Err(Error)` - Failed to send re-INVITE, or 491 Request Pending
+    ///   while another re-INVITE of this dialog is outstanding
+    ///
+    /// A 491 from the peer, whose re-INVITE crossed ours, is retried after
+    /// a random backoff (RFC 3261 §14.1).
     ///
     /// # Examples
     ///
@@ -390,31 +441,15 @@ impl ServerInviteDialog {
             return Ok(None);
         }
         info!(id=%self.id(), "sending re-invite request, body: \n{:?}", body);
-        let request = self.inner.make_request_with_vias(
-            rsip::Method::Invite,
-            None,
-            self.inner.build_vias_from_request()?,
-            headers,
-            body,
-        )?;
-        let resp = self.inner.do_request(request.clone()).await;
-        match resp {
-            Ok(Some(ref resp)) => {
-                if resp.status_code == StatusCode::OK {
-                    self.inner
-                        .transition(DialogState::Updated(self.id(), request))?;
-                }
-            }
-            _ => {}
-        }
-        resp
+        self.inner.send_reinvite(headers, body).await
     }
 
     /// Send an UPDATE request to modify session parameters
     ///
-    /// Sends an UPDATE request within an established dialog to modify
-    /// session parameters without the complexity of a re-INVITE.
-    /// This is typically used for smaller session modifications.
+    /// Sends an UPDATE request within an established or early dialog to
+    /// modify session parameters without the complexity of a re-INVITE.
+    /// In an early dialog this renegotiates media before the 200 OK
+    /// (RFC 3311).
     ///
     /// # Parameters
     ///
@@ -424,7 +459,7 @@ impl ServerInviteDialog {
     /// # Returns
     ///
     /// * `Ok(Some(Response))` - Response to the UPDATE
-    /// * `Ok(None)` - Dialog not confirmed, no request sent
+    /// * `Ok(None)` - Dialog neither confirmed nor early, no request sent
     /// * `Err(Error)` - Failed to send UPDATE
     ///
     /// # Examples
@@ -443,7 +478,7 @@ impl ServerInviteDialog {
         headers: Option<Vec<rsip::Header>>,
         body: Option<Vec<u8>>,
     ) -> Result<Option<rsip::Response>> {
-        if !self.inner.is_confirmed() {
+        if !self.inner.can_update() {
             return Ok(None);
         }
         info!(id=%self.id(), "sending update request, body: \n{:?}", body);
@@ -454,7 +489,25 @@ impl ServerInviteDialog {
             headers,
             body,
         )?;
-        self.inner.do_request(request.clone()).await
+        let resp = self.inner.do_request(request.clone()).await;
+        if let Ok(Some(ref resp)) = resp {
+            if resp.status_code.kind() == rsip::StatusCodeKind::Successful {
+                self.inner.set_local_sdp(&request.body);
+                self.inner.set_remote_sdp(&resp.body);
+            }
+        }
+        resp
+    }
+
+    /// Receive the BYE, INFO, UPDATE and re-INVITE requests of this dialog
+    /// to answer them
+    ///
+    /// Each arrives as a [`DialogRequest`] after the usual `DialogState`
+    /// update, and the dialog waits for its reply. A BYE answered with 2xx
+    /// terminates the dialog. Requests are answered 200 OK when no receiver
+    /// was taken or a `DialogRequest` is dropped unanswered.
+    pub fn incoming_requests(&self) -> UnboundedReceiver<DialogRequest> {
+        self.inner.incoming_requests()
     }
 
     /// Send an INFO request for mid-dialog information
@@ -508,6 +561,90 @@ impl ServerInviteDialog {
         self.inner.do_request(request.clone()).await
     }
 
+    /// Send an INFO carrying `body` of type `content_type`
+    pub async fn send_info(
+        &self,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> Result<Option<rsip::Response>> {
+        self.inner.send_info(content_type, body).await
+    }
+
+    /// Send a key press as an `application/dtmf-relay` INFO
+    ///
+    /// `digit` is one of `0`-`9`, `*`, `#` or `A`-`D`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::server_dialog::ServerInviteDialog;
+    /// # use std::time::Duration;
+    /// # async fn example() -> rsipstack::Result<()> {
+    /// # let dialog: ServerInviteDialog = todo!();
+    /// for digit in "1234#".chars() {
+    ///     dialog.send_dtmf(digit, Duration::from_millis(160)).await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_dtmf(
+        &self,
+        digit: char,
+        duration: Duration,
+    ) -> Result<Option<rsip::Response>> {
+        self.inner.send_dtmf(digit, duration).await
+    }
+
+    /// Ask the peer to transfer the call with an in-dialog REFER (RFC 3515)
+    ///
+    /// With `replaces` set the peer is asked to replace that dialog at the
+    /// transfer target (attended transfer, RFC 3891). Progress reported by
+    /// the peer arrives as `DialogState::ReferProgress`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Some(Response))` - Response to the REFER, 202 when accepted
+    /// * `Ok(None)` - Dialog not confirmed, no request sent
+    /// * `Err(Error)` - Failed to send REFER
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::dialog::server_dialog::ServerInviteDialog;
+    /// # async fn example() -> rsipstack::Result<()> {
+    /// # let dialog: ServerInviteDialog = todo!();
+    /// let target = rsip::Uri::try_from("sip:carol@example.com")?;
+    /// let response = dialog.refer(target, None).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn refer(
+        &self,
+        refer_to: rsip::Uri,
+        replaces: Option<ReplacesInfo>,
+    ) -> Result<Option<rsip::Response>> {
+        if !self.inner.is_confirmed() {
+            return Ok(None);
+        }
+        info!(id=%self.id(), %refer_to, "sending refer request");
+        self.inner
+            .send_refer(&refer_to, replaces.as_ref(), None)
+            .await
+    }
+
+    /// Report the progress of a transfer we were asked to make
+    ///
+    /// Sends a NOTIFY of the implicit REFER subscription with `status` as
+    /// its sipfrag, e.g. 100 Trying while calling the target and the final
+    /// answer of the target afterwards, which ends the subscription.
+    pub async fn notify_refer(&self, status: StatusCode) -> Result<Option<rsip::Response>> {
+        if !self.inner.is_confirmed() {
+            return Ok(None);
+        }
+        info!(id=%self.id(), %status, "sending refer notify");
+        self.inner.send_refer_notify(status).await
+    }
+
     /// Handle incoming transaction for this dialog
     ///
     /// Processes incoming SIP requests that are routed to this dialog.
@@ -529,7 +666,9 @@ impl ServerInviteDialog {
     /// * `BYE` - Terminates the dialog
     /// * `INFO` - Handles information exchange
     /// * `OPTIONS` - Handles capability queries
-    /// * `UPDATE` - Handles session updates
+    /// * `UPDATE` - Handles session updates, also in an early dialog
+    /// * `REFER` - Accepts a call transfer request
+    /// * `NOTIFY` - Handles transfer progress and other notifications
     /// * `INVITE` - Handles initial INVITE or re-INVITE
     pub async fn handle(&mut self, tx: &mut Transaction) -> Result<()> {
         debug!(
@@ -540,22 +679,20 @@ impl ServerInviteDialog {
         );
 
         let cseq = tx.original.cseq_header()?.seq()?;
-        let remote_seq = self.inner.remote_seq.load(Ordering::Relaxed);
-        if remote_seq > 0 && cseq < remote_seq {
+        if !self.inner.accept_remote_seq(cseq) {
             info!(
                 id=%self.id(),
                 "received old request {} remote_seq: {} > {}",
                 tx.original.method(),
-                remote_seq,
+                self.inner.remote_seq.load(Ordering::Relaxed),
                 cseq
             );
-            // discard old request
+            // an old ACK is dropped, anything else answered
+            if tx.original.method != rsip::Method::Ack {
+                tx.reply(rsip::StatusCode::ServerInternalError).await?;
+            }
             return Ok(());
         }
-        self.inner
-            .remote_seq
-            .compare_exchange(remote_seq, cseq, Ordering::Relaxed, Ordering::Relaxed)
-            .ok();
 
         if self.inner.is_confirmed() {
             match tx.original.method {
@@ -581,9 +718,11 @@ impl ServerInviteDialog {
                 rsip::Method::Invite => return self.handle_reinvite(tx).await,
                 rsip::Method::Bye => return self.handle_bye(tx).await,
                 rsip::Method::PRack => return self.handle_prack(tx).await,
-                rsip::Method::Info => return self.handle_info(tx).await,
+                rsip::Method::Info => return self.inner.handle_info(tx).await,
                 rsip::Method::Options => return self.handle_options(tx).await,
-                rsip::Method::Update => return self.handle_update(tx).await,
+                rsip::Method::Update => return self.inner.handle_update(tx).await,
+                rsip::Method::Refer => return self.inner.handle_refer(tx).await,
+                rsip::Method::Notify => return self.inner.handle_notify(tx).await,
                 _ => {
                     info!(id=%self.id(),"invalid request method: {:?}", tx.original.method);
                     tx.reply(rsip::StatusCode::MethodNotAllowed).await?;
@@ -599,10 +738,14 @@ impl ServerInviteDialog {
         match tx.original.method {
             rsip::Method::Invite => return self.handle_invite(tx).await,
             rsip::Method::PRack => return self.handle_prack(tx).await,
+            rsip::Method::Update if self.inner.can_update() => {
+                return self.inner.handle_update(tx).await
+            }
             rsip::Method::Ack => {
                 self.inner.tu_sender.send(TransactionEvent::Received(
                     tx.original.clone().into(),
                     tx.connection.clone(),
+                    tx.source.clone(),
                 ))?;
                 return Ok(());
             }
@@ -615,17 +758,11 @@ impl ServerInviteDialog {
 
     async fn handle_bye(&mut self, tx: &mut Transaction) -> Result<()> {
         info!(id = %self.id(), "received bye {}", tx.original.uri);
-        self.inner
-            .transition(DialogState::Terminated(self.id(), TerminatedReason::UacBye))?;
-        tx.reply(rsip::StatusCode::OK).await?;
-        Ok(())
-    }
-
-    async fn handle_info(&mut self, tx: &mut Transaction) -> Result<()> {
-        info!(id = %self.id(), "received info {}", tx.original.uri);
-        self.inner
-            .transition(DialogState::Info(self.id(), tx.original.clone()))?;
-        tx.reply(rsip::StatusCode::OK).await?;
+        let resp = self.inner.answer_request(tx).await?;
+        if resp.status_code.kind() == rsip::StatusCodeKind::Successful {
+            self.inner
+                .transition(DialogState::Terminated(self.id(), TerminatedReason::UacBye))?;
+        }
         Ok(())
     }
 
@@ -650,21 +787,29 @@ impl ServerInviteDialog {
         Ok(())
     }
 
-    async fn handle_update(&mut self, tx: &mut Transaction) -> Result<()> {
-        info!(id = %self.id(), "received update {}", tx.original.uri);
-        self.inner
-            .transition(DialogState::Updated(self.id(), tx.original.clone()))?;
-        tx.reply(rsip::StatusCode::OK).await?;
-        Ok(())
-    }
-
     async fn handle_reinvite(&mut self, tx: &mut Transaction) -> Result<()> {
         info!(id = %self.id(), "received re-invite {}", tx.original.uri);
+        // an INVITE of ours is still outstanding (RFC 3261 §14.2)
+        let Ok(_pending) = self.inner.begin_invite() else {
+            info!(id = %self.id(), "re-invite glare, replying 491");
+            tx.reply(rsip::StatusCode::RequestPending).await?;
+            return Ok(());
+        };
+        if self.inner.is_session_refresh(&tx.original) {
+            return self.inner.answer_session_refresh(tx).await;
+        }
+        self.inner.set_remote_sdp(&tx.original.body);
         self.inner
             .transition(DialogState::Updated(self.id(), tx.original.clone()))?;
 
-        if let Err(e) = tx.reply(rsip::StatusCode::OK).await {
-            warn!(id = %self.id(), "failed to send 200 OK for re-invite: {}", e);
+        match self.inner.answer_request(tx).await {
+            Ok(resp) if resp.status_code.kind() == rsip::StatusCodeKind::Successful => {
+                self.inner.set_local_sdp(&resp.body);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!(id = %self.id(), "failed to answer re-invite: {}", e);
+            }
         }
 
         while let Some(msg) = tx.receive().await {
@@ -712,9 +857,20 @@ impl ServerInviteDialog {
                             ))?;
                             break;
                         }
+                        rsip::Method::PRack => {
+                            // the transaction only passes up PRACKs matching its
+                            // unacknowledged reliable provisional response
+                            info!(id = %self.id(),"provisional response acknowledged {}", req.uri);
+                            self.inner.transition(DialogState::Prack(self.id(), req))?;
+                        }
                         rsip::Method::Cancel => {
                             info!(id = %self.id(),"received cancel {}", req.uri);
-                            tx.reply(rsip::StatusCode::RequestTerminated).await?;
+                            tx.reply_with(
+                                rsip::StatusCode::RequestTerminated,
+                                reason_headers(&req.headers),
+                                None,
+                            )
+                            .await?;
                             self.inner.transition(DialogState::Terminated(
                                 self.id(),
                                 TerminatedReason::UacCancel,
@@ -0,0 +1,82 @@
+use crate::rsip_ext::header_value_case_insensitive;
+use crate::transaction::key::{TransactionKey, TransactionRole};
+use crate::transaction::rebranch_top_via;
+use crate::transaction::transaction::Transaction;
+use crate::Result;
+use rsip::prelude::HeadersExt;
+use rsip::{Header, Response};
+
+fn is_header(header: &Header, name: &str) -> bool {
+    match header {
+        Header::Other(header_name, _) => header_name.eq_ignore_ascii_case(name),
+        _ => false,
+    }
+}
+
+fn delta_seconds(value: &str) -> Option<u32> {
+    // Session-Expires may carry a ;refresher parameter
+    value.split(';').next()?.trim().parse().ok()
+}
+
+pub fn session_expires_header(seconds: u32) -> Header {
+    Header::Other("Session-Expires".into(), seconds.to_string())
+}
+
+pub fn min_se_header(seconds: u32) -> Header {
+    Header::Other("Min-SE".into(), seconds.to_string())
+}
+
+/// Session interval requested by a message (`Session-Expires` or its compact form `x`)
+pub fn parse_session_expires(headers: &rsip::Headers) -> Option<u32> {
+    header_value_case_insensitive(headers, "Session-Expires")
+        .or_else(|| header_value_case_insensitive(headers, "x"))
+        .and_then(|value| delta_seconds(&value))
+}
+
+/// Smallest session interval the peer accepts, from `Min-SE`
+pub fn parse_min_se(headers: &rsip::Headers) -> Option<u32> {
+    header_value_case_insensitive(headers, "Min-SE").and_then(|value| delta_seconds(&value))
+}
+
+/// Retry an INVITE rejected with 422 Session Interval Too Small (RFC 4028 §7.4)
+///
+/// The new request raises `Session-Expires` to at least the `Min-SE` of the
+/// response and carries that `Min-SE` itself. Fails when the 422 names no
+/// `Min-SE`, as the negotiation then has nothing to converge on.
+pub fn handle_session_interval_too_small(
+    new_seq: u32,
+    tx: &Transaction,
+    resp: &Response,
+) -> Result<Transaction> {
+    let min_se = parse_min_se(&resp.headers).ok_or(crate::Error::DialogError(
+        "422 response without Min-SE".to_string(),
+        super::DialogId::try_from(&tx.original)?,
+        resp.status_code.clone(),
+    ))?;
+    let session_expires = parse_session_expires(&tx.original.headers)
+        .unwrap_or_default()
+        .max(min_se);
+
+    let mut new_req = tx.original.clone();
+    new_req.cseq_header_mut()?.mut_seq(new_seq)?;
+
+    rebranch_top_via(&mut new_req)?;
+
+    new_req.headers.retain(|h| {
+        !is_header(h, "Session-Expires") && !is_header(h, "x") && !is_header(h, "Min-SE")
+    });
+    new_req
+        .headers
+        .push(session_expires_header(session_expires));
+    new_req.headers.push(min_se_header(min_se));
+
+    let key = TransactionKey::from_request(&new_req, TransactionRole::Client)?;
+    let mut new_tx = Transaction::new_client(
+        key,
+        new_req,
+        tx.endpoint_inner.clone(),
+        tx.connection.clone(),
+    );
+    new_tx.destination = tx.destination.clone();
+    Ok(new_tx)
+}
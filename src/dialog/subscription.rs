@@ -0,0 +1,584 @@
+use super::{
+    authenticate::{handle_client_authenticate, Credential},
+    dialog::{
+        Dialog, DialogInner, DialogInnerRef, DialogState, DialogStateSender, TerminatedReason,
+    },
+    dialog_layer::DialogLayer,
+    DialogId,
+};
+use crate::{
+    rsip_ext::{extract_uri_from_contact, header_value_case_insensitive, RsipResponseExt},
+    transaction::{
+        key::{TransactionKey, TransactionRole},
+        make_tag,
+        transaction::Transaction,
+    },
+    transport::SipAddr,
+    Result,
+};
+use rsip::{
+    prelude::{HeadersExt, ToTypedHeader, UntypedHeader},
+    Header, Method, Request, Response, SipMessage, StatusCode, StatusCodeKind,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::{debug, info, warn};
+
+/// Expiration used when neither the request nor the 2xx carries one
+pub const DEFAULT_SUBSCRIBE_EXPIRES: u32 = 3600;
+
+/// SUBSCRIBE Request Options
+///
+/// # Fields
+///
+/// * `subscriber` - URI of the subscriber (From header)
+/// * `target` - URI of the resource subscribed to (To header and Request-URI)
+/// * `event` - Event package, e.g. `presence` or `dialog`
+/// * `accept` - Optional Accept header for the NOTIFY bodies
+/// * `expires` - Requested duration, the notifier picks one when `None`
+/// * `contact` - Contact URI NOTIFYs are sent to
+/// * `credential` - Optional authentication credentials
+/// * `headers` - Optional additional headers to include
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use rsipstack::dialog::subscription::SubscribeOption;
+/// # fn example() -> rsipstack::Result<()> {
+/// let subscribe_option = SubscribeOption {
+///     subscriber: "sip:alice@example.com".try_into()?,
+///     target: "sip:bob@example.com".try_into()?,
+///     event: "presence".to_string(),
+///     accept: Some("application/pidf+xml".to_string()),
+///     expires: Some(600),
+///     contact: "sip:alice@192.168.1.100:5060".try_into()?,
+///     ..Default::default()
+/// };
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default, Clone)]
+pub struct SubscribeOption {
+    pub subscriber: rsip::Uri,
+    pub target: rsip::Uri,
+    pub destination: Option<SipAddr>,
+    pub event: String,
+    pub accept: Option<String>,
+    pub expires: Option<u32>,
+    pub contact: rsip::Uri,
+    pub credential: Option<Credential>,
+    pub headers: Option<Vec<rsip::Header>>,
+    pub call_id: Option<String>,
+}
+
+/// Value of a `Subscription-State` header (RFC 6665 §8.2.3)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubscriptionState {
+    Active {
+        expires: Option<u32>,
+    },
+    Pending {
+        expires: Option<u32>,
+    },
+    Terminated {
+        reason: Option<String>,
+        retry_after: Option<u32>,
+    },
+}
+
+impl SubscriptionState {
+    /// Parse the `Subscription-State` header of a NOTIFY
+    ///
+    /// Unknown states are treated as `pending`, as RFC 6665 §4.1.3 asks.
+    pub fn from_headers(headers: &rsip::Headers) -> Option<Self> {
+        let value = header_value_case_insensitive(headers, "Subscription-State")?;
+        let mut parts = value.split(';').map(str::trim);
+        let state = parts.next()?.to_ascii_lowercase();
+        let mut expires = None;
+        let mut reason = None;
+        let mut retry_after = None;
+        for param in parts {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            let value = value.trim().trim_matches('"');
+            match name.trim().to_ascii_lowercase().as_str() {
+                "expires" => expires = value.parse().ok(),
+                "reason" => reason = Some(value.to_string()),
+                "retry-after" => retry_after = value.parse().ok(),
+                _ => {}
+            }
+        }
+        match state.as_str() {
+            "active" => Some(Self::Active { expires }),
+            "terminated" => Some(Self::Terminated {
+                reason,
+                retry_after,
+            }),
+            _ => Some(Self::Pending { expires }),
+        }
+    }
+}
+
+struct SubscriptionInner {
+    event: String,
+    expires: AtomicU32,
+    unsubscribed: AtomicBool,
+}
+
+/// Subscriber side of a SUBSCRIBE/NOTIFY dialog (RFC 6665)
+///
+/// Created by [`DialogLayer::do_subscribe`]. The subscription is refreshed
+/// before it expires until [`SubscribeDialog::unsubscribe`] is called or the
+/// notifier terminates it. NOTIFYs arrive as new server transactions; route
+/// them with [`DialogLayer::match_dialog`] and [`Dialog::handle`], which
+/// answer them and report the subscription state:
+///
+/// * `DialogState::Pending` - the notifier has not authorized the subscription yet
+/// * `DialogState::Active` - the subscription is active, the NOTIFY carries the event state
+/// * `DialogState::Terminated` - the subscription ended, with
+///   `TerminatedReason::SubscriptionTerminated` carrying the notifier's reason
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use rsipstack::dialog::dialog_layer::DialogLayer;
+/// # use rsipstack::dialog::subscription::SubscribeOption;
+/// # async fn example() -> rsipstack::Result<()> {
+/// # let dialog_layer: DialogLayer = todo!();
+/// # let subscribe_option: SubscribeOption = todo!();
+/// let (state_tx, mut state_rx) = tokio::sync::mpsc::unbounded_channel();
+/// let (dialog, resp) = dialog_layer.do_subscribe(subscribe_option, state_tx).await?;
+/// while let Some(state) = state_rx.recv().await {
+///     println!("subscription state: {}", state);
+///     if state.is_terminated() {
+///         break;
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SubscribeDialog {
+    pub(super) inner: DialogInnerRef,
+    subscription: Arc<SubscriptionInner>,
+}
+
+impl SubscribeDialog {
+    pub fn id(&self) -> DialogId {
+        self.inner.id.lock().unwrap().clone()
+    }
+
+    pub fn state(&self) -> DialogState {
+        self.inner.state.lock().unwrap().clone()
+    }
+
+    pub fn event(&self) -> &str {
+        &self.subscription.event
+    }
+
+    /// Duration of the subscription granted by the notifier, in seconds
+    pub fn expires(&self) -> u32 {
+        self.subscription.expires.load(Ordering::Relaxed)
+    }
+
+    pub fn cancel_token(&self) -> &tokio_util::sync::CancellationToken {
+        &self.inner.cancel_token
+    }
+
+    fn subscribe_headers(&self, expires: u32) -> Vec<Header> {
+        vec![
+            Header::Other("Event".into(), self.subscription.event.clone()),
+            rsip::headers::Expires::from(expires).into(),
+        ]
+    }
+
+    fn update_expires(&self, resp: &Response) {
+        if let Some(expires) = header_value_case_insensitive(&resp.headers, "Expires")
+            .and_then(|v| v.parse::<u32>().ok())
+            .filter(|expires| *expires > 0)
+        {
+            self.subscription.expires.store(expires, Ordering::Relaxed);
+        }
+    }
+
+    /// Refresh the subscription with an in-dialog SUBSCRIBE
+    ///
+    /// `expires` defaults to the duration currently granted.
+    pub async fn refresh(&self, expires: Option<u32>) -> Result<Option<Response>> {
+        let expires = expires.unwrap_or_else(|| self.expires());
+        let request = self.inner.make_request(
+            Method::Subscribe,
+            None,
+            None,
+            None,
+            Some(self.subscribe_headers(expires)),
+            None,
+        )?;
+        let resp = self.inner.do_request(request).await?;
+        match resp.as_ref() {
+            Some(resp) if resp.status_code.kind() == StatusCodeKind::Successful && expires > 0 => {
+                self.update_expires(resp);
+            }
+            Some(resp) if resp.status_code == StatusCode::CallTransactionDoesNotExist => {
+                // the notifier has lost the subscription (RFC 6665 §4.1.2.2)
+                self.inner.transition(DialogState::Terminated(
+                    self.id(),
+                    TerminatedReason::UasOther(resp.status_code.clone()),
+                ))?;
+            }
+            _ => {}
+        }
+        Ok(resp)
+    }
+
+    /// End the subscription with a SUBSCRIBE carrying `Expires: 0`
+    ///
+    /// Refreshing stops; the dialog terminates when the notifier's final
+    /// NOTIFY arrives.
+    pub async fn unsubscribe(&self) -> Result<Option<Response>> {
+        self.subscription
+            .unsubscribed
+            .store(true, Ordering::Relaxed);
+        if self.inner.is_terminated() {
+            return Ok(None);
+        }
+        self.refresh(Some(0)).await
+    }
+
+    /// Handle a NOTIFY of this subscription
+    pub async fn handle(&mut self, tx: &mut Transaction) -> Result<()> {
+        if tx.original.method != Method::Notify {
+            info!(id=%self.id(), "invalid request method: {:?}", tx.original.method);
+            tx.reply(StatusCode::MethodNotAllowed).await?;
+            return Err(crate::Error::DialogError(
+                "invalid request".to_string(),
+                self.id(),
+                StatusCode::MethodNotAllowed,
+            ));
+        }
+        let cseq = tx.original.cseq_header()?.seq()?;
+        if !self.inner.accept_remote_seq(cseq) {
+            info!(id=%self.id(),"received old notify remote_seq: {} > {}", self.inner.remote_seq.load(Ordering::Relaxed), cseq);
+            tx.reply(StatusCode::ServerInternalError).await?;
+            return Ok(());
+        }
+
+        let Some(state) = SubscriptionState::from_headers(&tx.original.headers) else {
+            info!(id=%self.id(), "notify without Subscription-State");
+            tx.reply(StatusCode::BadRequest).await?;
+            return Ok(());
+        };
+
+        // a NOTIFY may arrive before the 2xx of the SUBSCRIBE and
+        // establishes the dialog just as well (RFC 6665 §4.1.2.4)
+        if let Ok(Some(tag)) = tx.original.from_header()?.tag() {
+            if self.id().to_tag.is_empty() {
+                self.inner.update_remote_tag(tag.value())?;
+            }
+        }
+        if let Ok(contact) = tx.original.contact_header() {
+            if let Ok(uri) = extract_uri_from_contact(contact.value()) {
+                self.inner.set_remote_target(uri, Some(contact.clone()));
+            }
+        }
+        tx.reply(StatusCode::OK).await?;
+
+        debug!(id=%self.id(), ?state, "received notify");
+        let id = self.id();
+        match state {
+            SubscriptionState::Active { expires } | SubscriptionState::Pending { expires } => {
+                if let Some(expires) = expires.filter(|expires| *expires > 0) {
+                    self.subscription.expires.store(expires, Ordering::Relaxed);
+                }
+                let request = tx.original.clone();
+                match state {
+                    SubscriptionState::Active { .. } => {
+                        self.inner.transition(DialogState::Active(id, request))
+                    }
+                    _ => self.inner.transition(DialogState::Pending(id, request)),
+                }
+            }
+            SubscriptionState::Terminated { reason, .. } => {
+                self.inner.transition(DialogState::Terminated(
+                    id,
+                    TerminatedReason::SubscriptionTerminated(reason),
+                ))?;
+                self.inner.cancel_token.cancel();
+                Ok(())
+            }
+        }
+    }
+
+    pub(super) async fn process_subscribe(
+        &self,
+        mut tx: Transaction,
+    ) -> Result<(DialogId, Option<Response>)> {
+        let mut auth_sent = false;
+        tx.send().await?;
+        while let Some(msg) = tx.receive().await {
+            let SipMessage::Response(resp) = msg else {
+                continue;
+            };
+            if resp.status_code.kind() == StatusCodeKind::Provisional {
+                continue;
+            }
+            if matches!(
+                resp.status_code,
+                StatusCode::ProxyAuthenticationRequired | StatusCode::Unauthorized
+            ) && !auth_sent
+            {
+                if let Some(credential) = &self.inner.credential {
+                    auth_sent = true;
+                    tx = handle_client_authenticate(
+                        self.inner.increment_local_seq(),
+                        tx,
+                        resp,
+                        credential,
+                    )
+                    .await?;
+                    tx.send().await?;
+                    *self
+                        .inner
+                        .initial_request
+                        .lock()
+                        .expect("update initial request mutex poisoned") = tx.original.clone();
+                    continue;
+                }
+            }
+            if resp.status_code.kind() != StatusCodeKind::Successful {
+                info!(id=%self.id(), "subscribe rejected: {}", resp.status_code);
+                self.inner.transition(DialogState::Terminated(
+                    self.id(),
+                    TerminatedReason::UasOther(resp.status_code.clone()),
+                ))?;
+                return Ok((self.id(), Some(resp)));
+            }
+            if let Some(tag) = resp.to_header()?.tag()? {
+                self.inner.update_remote_tag(tag.value())?;
+            }
+            self.inner.update_route_set_from_response(&resp);
+            if let Ok(contact) = resp.contact_header() {
+                self.inner
+                    .remote_contact
+                    .lock()
+                    .unwrap()
+                    .replace(contact.clone());
+                *self.inner.remote_uri.lock().unwrap() =
+                    resp.remote_uri(tx.destination.as_ref())?;
+            }
+            self.update_expires(&resp);
+            return Ok((self.id(), Some(resp)));
+        }
+        Ok((self.id(), None))
+    }
+
+    /// Re-SUBSCRIBE when 90% of the granted duration has passed
+    pub(super) fn spawn_refresh(&self) {
+        let dialog = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let wait = Duration::from_millis(dialog.expires() as u64 * 900)
+                    .max(Duration::from_millis(500));
+                tokio::select! {
+                    _ = dialog.inner.cancel_token.cancelled() => break,
+                    _ = tokio::time::sleep(wait) => {}
+                }
+                if dialog.subscription.unsubscribed.load(Ordering::Relaxed)
+                    || dialog.inner.is_terminated()
+                {
+                    break;
+                }
+                match dialog.refresh(None).await {
+                    Ok(Some(resp)) if resp.status_code.kind() == StatusCodeKind::Successful => {
+                        debug!(id=%dialog.id(), expires = dialog.expires(), "subscription refreshed");
+                    }
+                    Ok(resp) => {
+                        warn!(
+                            id=%dialog.id(),
+                            status = ?resp.map(|r| r.status_code),
+                            "subscription refresh failed"
+                        );
+                        if dialog.inner.is_terminated() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        warn!(id=%dialog.id(), "subscription refresh error: {}", e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl DialogLayer {
+    pub fn make_subscribe_request(&self, opt: &SubscribeOption) -> Result<Request> {
+        let last_seq = self.increment_last_seq();
+        let to = rsip::typed::To {
+            display_name: None,
+            uri: opt.target.clone(),
+            params: vec![],
+        };
+        let recipient = to.uri.clone();
+        let from = rsip::typed::From {
+            display_name: None,
+            uri: opt.subscriber.clone(),
+            params: vec![],
+        }
+        .with_tag(make_tag());
+        let call_id = opt
+            .call_id
+            .as_ref()
+            .map(|id| rsip::headers::CallId::from(id.clone()));
+
+        let via = self.endpoint.get_via(None, None)?;
+        let mut request = self.endpoint.make_request(
+            Method::Subscribe,
+            recipient,
+            via,
+            from,
+            to,
+            last_seq,
+            call_id,
+            None,
+        );
+        let contact = rsip::typed::Contact {
+            display_name: None,
+            uri: opt.contact.clone(),
+            params: vec![],
+        };
+        request
+            .headers
+            .unique_push(rsip::Header::Contact(contact.into()));
+        request
+            .headers
+            .push(Header::Other("Event".into(), opt.event.clone()));
+        if let Some(accept) = &opt.accept {
+            request
+                .headers
+                .unique_push(Header::Accept(accept.clone().into()));
+        }
+        if let Some(expires) = opt.expires {
+            request
+                .headers
+                .unique_push(rsip::headers::Expires::from(expires).into());
+        }
+        if let Some(headers) = opt.headers.as_ref() {
+            for header in headers {
+                request.headers.unique_push(header.clone());
+            }
+        }
+        Ok(request)
+    }
+
+    pub fn create_subscribe_dialog(
+        &self,
+        opt: SubscribeOption,
+        state_sender: DialogStateSender,
+    ) -> Result<(SubscribeDialog, Transaction)> {
+        let request = self.make_subscribe_request(&opt)?;
+        let key = TransactionKey::from_request(&request, TransactionRole::Client)?;
+        let mut tx = Transaction::new_client(key, request.clone(), self.endpoint.clone(), None);
+        if let Some(destination) = opt.destination {
+            tx = tx.with_destination(destination);
+        } else if let Some(route) = tx.original.route_header() {
+            if let Some(first_route) = route.typed().ok().and_then(|r| r.uris().first().cloned()) {
+                tx.destination = SipAddr::try_from(&first_route.uri).ok();
+            }
+        }
+
+        let id = DialogId::try_from(&request)?;
+        let dlg_inner = DialogInner::new(
+            TransactionRole::Client,
+            id,
+            request,
+            self.endpoint.clone(),
+            state_sender,
+            opt.credential,
+            Some(opt.contact),
+            tx.tu_sender.clone(),
+        )?;
+        let dialog = SubscribeDialog {
+            inner: Arc::new(dlg_inner),
+            subscription: Arc::new(SubscriptionInner {
+                event: opt.event,
+                expires: AtomicU32::new(opt.expires.unwrap_or(DEFAULT_SUBSCRIBE_EXPIRES)),
+                unsubscribed: AtomicBool::new(false),
+            }),
+        };
+        Ok((dialog, tx))
+    }
+
+    /// Send a SUBSCRIBE and create the subscription dialog (RFC 6665)
+    ///
+    /// Returns once the SUBSCRIBE has a final response. On 2xx the dialog
+    /// stays in the layer and is refreshed automatically; NOTIFYs, including
+    /// one that overtakes the 2xx, are matched by [`DialogLayer::match_dialog`].
+    /// The subscription state is reported through `state_sender` as
+    /// `Pending`, `Active` and finally `Terminated`.
+    pub async fn do_subscribe(
+        &self,
+        opt: SubscribeOption,
+        state_sender: DialogStateSender,
+    ) -> Result<(SubscribeDialog, Option<Response>)> {
+        let (dialog, tx) = self.create_subscribe_dialog(opt, state_sender)?;
+        let initial_id = dialog.id();
+        self.inner
+            .dialogs
+            .write()
+            .unwrap()
+            .insert(initial_id.to_string(), Dialog::Subscribe(dialog.clone()));
+        info!(id=%initial_id, "subscribe dialog created");
+
+        let result = dialog.process_subscribe(tx).await;
+        let established = matches!(&result, Ok((_, Some(resp))) if resp.status_code.kind() == StatusCodeKind::Successful);
+        if !established {
+            // an early NOTIFY may have re-keyed the dialog already
+            self.remove_dialog(&initial_id);
+            if dialog.id() != initial_id {
+                self.remove_dialog(&dialog.id());
+            }
+        }
+        let (_, resp) = result?;
+        if !established {
+            return Ok((dialog, resp));
+        }
+
+        let id = dialog.id();
+        {
+            let mut dialogs = self.inner.dialogs.write().unwrap();
+            dialogs.remove(&initial_id.to_string());
+            dialogs.insert(id.to_string(), Dialog::Subscribe(dialog.clone()));
+        }
+        info!(%id, expires = dialog.expires(), "subscription established");
+        dialog.spawn_refresh();
+        Ok((dialog, resp))
+    }
+
+    /// Find the subscription a NOTIFY belongs to before the notifier's tag
+    /// is known, re-keying it under the full dialog id
+    pub(super) fn match_early_notify(&self, req: &Request, id: &DialogId) -> Option<Dialog> {
+        if req.method != Method::Notify {
+            return None;
+        }
+        let pending_id = DialogId {
+            call_id: id.call_id.clone(),
+            from_tag: id.to_tag.clone(),
+            to_tag: String::new(),
+        };
+        let mut dialogs = self.inner.dialogs.write().ok()?;
+        let dialog = match dialogs.get(&pending_id.to_string()) {
+            Some(Dialog::Subscribe(dialog)) => dialog.clone(),
+            _ => return None,
+        };
+        dialog.inner.update_remote_tag(&id.from_tag).ok()?;
+        dialogs.remove(&pending_id.to_string());
+        let dialog = Dialog::Subscribe(dialog);
+        dialogs.insert(dialog.id().to_string(), dialog.clone());
+        Some(dialog)
+    }
+}
@@ -1,6 +1,40 @@
+use crate::transaction::endpoint::Endpoint;
+use crate::transport::{loopback::LoopbackConnection, SipAddr, TransportLayer};
+use crate::EndpointBuilder;
+use tokio_util::sync::CancellationToken;
+
 mod test_authenticate;
 mod test_client_dialog;
 mod test_dialog_layer;
 mod test_dialog_states;
+mod test_loopback;
 mod test_prack;
+mod test_refer;
+mod test_registration;
 mod test_server_dialog;
+mod test_subscription;
+
+pub(super) fn loopback_addr(port: u16) -> SipAddr {
+    SipAddr::new(
+        rsip::Transport::Udp,
+        rsip::HostWithPort::try_from(format!("127.0.0.1:{}", port).as_str()).unwrap(),
+    )
+}
+
+/// Endpoint on `connection`, already serving
+pub(super) fn create_loopback_endpoint(
+    connection: LoopbackConnection,
+    token: &CancellationToken,
+) -> Endpoint {
+    let transport_layer = TransportLayer::new(token.child_token());
+    transport_layer.add_transport(connection.into());
+    let endpoint = EndpointBuilder::new()
+        .with_transport_layer(transport_layer)
+        .with_cancel_token(token.child_token())
+        .build();
+    let endpoint_inner = endpoint.inner.clone();
+    tokio::spawn(async move {
+        let _ = endpoint_inner.serve().await;
+    });
+    endpoint
+}
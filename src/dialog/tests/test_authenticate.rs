@@ -1,16 +1,18 @@
 //! Authentication tests
 //!
-//! Tests for SIP authentication handling, including Via header parameter updates
+//! Tests for SIP authentication handling, including Via header parameter
+//! updates, digest computation and challenge selection
 
-use crate::dialog::authenticate::{handle_client_authenticate, Credential};
+use crate::dialog::authenticate::{handle_client_authenticate, Credential, DigestInput};
 use crate::transaction::{
     endpoint::EndpointBuilder,
     key::{TransactionKey, TransactionRole},
     transaction::Transaction,
 };
 use crate::transport::TransportLayer;
+use rsip::headers::auth::{Algorithm, AuthQop};
 use rsip::headers::*;
-use rsip::prelude::{HeadersExt, ToTypedHeader};
+use rsip::prelude::{HeadersExt, ToTypedHeader, UntypedHeader};
 use rsip::{Request, Response, StatusCode};
 use tokio_util::sync::CancellationToken;
 
@@ -103,6 +105,7 @@ async fn test_authenticate_via_header_branch_update() -> crate::Result<()> {
         username: "alice".to_string(),
         password: "secret123".to_string(),
         realm: None,
+        algorithm: None,
     };
 
     // Call handle_client_authenticate
@@ -156,3 +159,141 @@ async fn test_authenticate_via_header_branch_update() -> crate::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_repeated_authenticate_keeps_one_rport() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let original_req = create_request_with_branch("z9hG4bKoriginal123");
+    let key = TransactionKey::from_request(&original_req, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, original_req, endpoint.inner.clone(), None);
+    let cred = Credential {
+        username: "alice".to_string(),
+        password: "secret123".to_string(),
+        realm: None,
+        algorithm: None,
+    };
+
+    for seq in 2..4 {
+        tx = handle_client_authenticate(seq, tx, create_401_response(), &cred).await?;
+    }
+
+    let via = tx.original.via_header()?.typed()?;
+    let rports = via
+        .params
+        .iter()
+        .filter(|p| matches!(p, rsip::Param::Other(key, _) if key.value().eq_ignore_ascii_case("rport")))
+        .count();
+    assert_eq!(rports, 1, "rport must not pile up: {}", via);
+    let branches = via
+        .params
+        .iter()
+        .filter(|p| matches!(p, rsip::Param::Branch(_)))
+        .count();
+    assert_eq!(branches, 1);
+    Ok(())
+}
+
+// RFC 7616 §3.9.1
+fn rfc7616_input<'a>(algorithm: Algorithm, qop: &'a AuthQop) -> DigestInput<'a> {
+    DigestInput {
+        algorithm,
+        username: "Mufasa",
+        password: "Circle of Life",
+        realm: "http-auth@example.org",
+        nonce: "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v",
+        method: "GET",
+        uri: "/dir/index.html",
+        qop: Some(qop),
+        body: &[],
+    }
+}
+
+#[test]
+fn test_digest_rfc7616_vectors() {
+    let qop = AuthQop::Auth {
+        cnonce: "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ".to_string(),
+        nc: 1,
+    };
+    assert_eq!(
+        rfc7616_input(Algorithm::Md5, &qop).compute(),
+        "8ca523f5e9506fed4657c9700eebdbec"
+    );
+    assert_eq!(
+        rfc7616_input(Algorithm::Sha256, &qop).compute(),
+        "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1"
+    );
+}
+
+#[test]
+fn test_digest_auth_int_hashes_body() {
+    let qop = AuthQop::AuthInt {
+        cnonce: "0a4f113b".to_string(),
+        nc: 1,
+    };
+    let input = DigestInput {
+        algorithm: Algorithm::Sha256Sess,
+        username: "alice",
+        password: "secret123",
+        realm: "example.com",
+        nonce: "abc",
+        method: "INVITE",
+        uri: "sip:bob@example.com",
+        qop: Some(&qop),
+        body: b"v=0\r\n",
+    };
+    assert_eq!(
+        input.compute(),
+        "20d467cb964af82e9ec2bbcffd498dd9b78cdcb31cd044010dbd652f0bc2c578"
+    );
+    let other_body = DigestInput {
+        body: b"v=1\r\n",
+        ..input
+    };
+    assert_ne!(
+        other_body.compute(),
+        "20d467cb964af82e9ec2bbcffd498dd9b78cdcb31cd044010dbd652f0bc2c578"
+    );
+}
+
+#[tokio::test]
+async fn test_authenticate_picks_strongest_algorithm() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let mut resp = create_401_response();
+    for algorithm in ["SHA-256", "SHA-512-256"] {
+        resp.headers.push(
+            WwwAuthenticate::new(format!(
+                r#"Digest realm="example.com", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", algorithm={}, qop="auth""#,
+                algorithm
+            ))
+            .into(),
+        );
+    }
+
+    for (preferred, expected) in [
+        (None, "SHA-512-256"),
+        (Some(Algorithm::Md5), "MD5"),
+        (Some(Algorithm::Sha256), "SHA-256"),
+        (Some(Algorithm::Sha256Sess), "SHA-512-256"),
+    ] {
+        let req = create_request_with_branch("z9hG4bKstrongest");
+        let key = TransactionKey::from_request(&req, TransactionRole::Client)?;
+        let tx = Transaction::new_client(key, req, endpoint.inner.clone(), None);
+        let cred = Credential {
+            username: "alice".to_string(),
+            password: "secret123".to_string(),
+            realm: None,
+            algorithm: preferred,
+        };
+        let new_tx = handle_client_authenticate(2, tx, resp.clone(), &cred).await?;
+        let auth = new_tx
+            .original
+            .authorization_header()
+            .expect("request should carry Authorization");
+        assert!(
+            auth.value().ends_with(&format!(", algorithm={}", expected)),
+            "{}",
+            auth.value()
+        );
+    }
+    Ok(())
+}
@@ -29,6 +29,31 @@ async fn create_test_endpoint() -> crate::Result<crate::transaction::endpoint::E
     Ok(endpoint)
 }
 
+/// Endpoint serving a UDP listener on a free local port, with that port
+async fn create_udp_endpoint(
+    token: &CancellationToken,
+) -> crate::Result<(crate::transaction::endpoint::Endpoint, u16)> {
+    let transport_layer = TransportLayer::new(token.child_token());
+    let udp = UdpConnection::create_connection(
+        "127.0.0.1:0".parse().unwrap(),
+        None,
+        Some(token.child_token()),
+    )
+    .await?;
+    let port = udp.get_addr().addr.port.map(u16::from).unwrap_or(0);
+    transport_layer.add_transport(udp.into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(transport_layer)
+        .with_cancel_token(token.child_token())
+        .build();
+    let endpoint_inner = endpoint.inner.clone();
+    tokio::spawn(async move {
+        let _ = endpoint_inner.serve().await;
+    });
+    Ok((endpoint, port))
+}
+
 fn create_invite_request(from_tag: &str, to_tag: &str, call_id: &str) -> Request {
     Request {
         method: rsip::Method::Invite,
@@ -324,7 +349,8 @@ async fn test_make_request_preserves_remote_target_and_route_order() -> crate::R
         *route_set = vec![
             Route::from("<sip:proxy2.example.com:5070;transport=tcp;lr>"),
             Route::from("<sip:proxy1.example.com:5060;transport=tcp;lr>"),
-        ];
+        ]
+        .into();
     }
 
     let outbound_addr =
@@ -657,34 +683,8 @@ async fn test_ack_sent_to_domain_name_from_contact() -> crate::Result<()> {
     use crate::dialog::{dialog_layer::DialogLayer, invitation::InviteOption};
 
     // ========== Create UAS endpoint ==========
-    let uas_token = CancellationToken::new();
-    let uas_transport_layer = TransportLayer::new(uas_token.child_token());
-
-    let uas_udp = UdpConnection::create_connection(
-        "127.0.0.1:0".parse().unwrap(),
-        None,
-        Some(uas_token.child_token()),
-    )
-    .await?;
-
-    let uas_port = uas_udp
-        .get_addr()
-        .addr
-        .port
-        .map(|p| u16::from(p))
-        .unwrap_or(0);
-    uas_transport_layer.add_transport(uas_udp.into());
-
-    let uas_endpoint = EndpointBuilder::new()
-        .with_user_agent("rsipstack-uas")
-        .with_transport_layer(uas_transport_layer)
-        .build();
-
-    uas_endpoint.inner.transport_layer.serve_listens().await?;
-    let uas_endpoint_inner = uas_endpoint.inner.clone();
-    tokio::spawn(async move {
-        let _ = uas_endpoint_inner.serve().await;
-    });
+    let token = CancellationToken::new();
+    let (uas_endpoint, uas_port) = create_udp_endpoint(&token).await?;
 
     // ========== Create UAC endpoint with mock resolver ==========
     let domain_target_addr = SipAddr {
@@ -805,7 +805,7 @@ async fn test_ack_sent_to_domain_name_from_contact() -> crate::Result<()> {
         "Dialog should be confirmed after 200 OK"
     );
 
-    uas_token.cancel();
+    token.cancel();
 
     Ok(())
 }
@@ -1009,3 +1009,292 @@ async fn test_ack_sent_to_websocket_channel_via_locator() -> crate::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_await_confirmed_resolves_on_ok_and_fails_on_busy() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (tu_sender, _tu_receiver) = unbounded_channel();
+
+    let dialog_id = DialogId {
+        call_id: "await-confirmed-ok".to_string(),
+        from_tag: "alice-tag".to_string(),
+        to_tag: "bob-tag".to_string(),
+    };
+    let dialog_inner = DialogInner::new(
+        TransactionRole::Client,
+        dialog_id.clone(),
+        create_invite_request("alice-tag", "", "await-confirmed-ok"),
+        endpoint.inner.clone(),
+        state_sender.clone(),
+        None,
+        Some(Uri::try_from("sip:alice@alice.example.com:5060").unwrap()),
+        tu_sender.clone(),
+    )?;
+    let client_dialog = ClientInviteDialog {
+        inner: Arc::new(dialog_inner),
+    };
+
+    let dialog_ref = client_dialog.clone();
+    let confirm_id = dialog_id.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        dialog_ref
+            .inner
+            .transition(DialogState::Confirmed(confirm_id, Response::default()))
+            .ok();
+    });
+    client_dialog
+        .await_confirmed(std::time::Duration::from_secs(1))
+        .await?;
+
+    let dialog_id = DialogId {
+        call_id: "await-confirmed-busy".to_string(),
+        from_tag: "alice-tag".to_string(),
+        to_tag: "bob-tag".to_string(),
+    };
+    let dialog_inner = DialogInner::new(
+        TransactionRole::Client,
+        dialog_id.clone(),
+        create_invite_request("alice-tag", "", "await-confirmed-busy"),
+        endpoint.inner.clone(),
+        state_sender,
+        None,
+        Some(Uri::try_from("sip:alice@alice.example.com:5060").unwrap()),
+        tu_sender,
+    )?;
+    let client_dialog = ClientInviteDialog {
+        inner: Arc::new(dialog_inner),
+    };
+
+    let dialog_ref = client_dialog.clone();
+    let busy_id = dialog_id.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        dialog_ref
+            .inner
+            .transition(DialogState::Terminated(
+                busy_id,
+                TerminatedReason::UasOther(StatusCode::BusyHere),
+            ))
+            .ok();
+    });
+    match client_dialog
+        .await_confirmed(std::time::Duration::from_secs(1))
+        .await
+    {
+        Err(crate::Error::DialogError(_, _, code)) => assert_eq!(code, StatusCode::BusyHere),
+        _ => panic!("expected busy dialog error"),
+    }
+
+    // an already terminated dialog fails without waiting for the timeout
+    assert!(client_dialog
+        .await_confirmed(std::time::Duration::from_millis(10))
+        .await
+        .is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_start_invite_handle_cancels_ringing_call() -> crate::Result<()> {
+    use crate::dialog::{dialog_layer::DialogLayer, invitation::InviteOption};
+
+    let token = CancellationToken::new();
+    let (uas_endpoint, uas_port) = create_udp_endpoint(&token).await?;
+    let (uac_endpoint, _) = create_udp_endpoint(&token).await?;
+
+    // UAS rings, then answers the CANCEL with 487 on the INVITE transaction
+    let mut uas_incoming = uas_endpoint.incoming_transactions()?;
+    tokio::spawn(async move {
+        let mut invite_tx = uas_incoming.recv().await.expect("failed to get the INVITE");
+        invite_tx
+            .reply(StatusCode::Ringing)
+            .await
+            .expect("failed to send 180");
+        while let Some(msg) = invite_tx.receive().await {
+            if let rsip::SipMessage::Request(req) = msg {
+                if req.method == rsip::Method::Cancel {
+                    invite_tx
+                        .reply(StatusCode::RequestTerminated)
+                        .await
+                        .expect("failed to send 487");
+                    break;
+                }
+            }
+        }
+    });
+
+    let uac_dialog_layer = DialogLayer::new(uac_endpoint.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@127.0.0.1:{};transport=udp", uas_port).as_str())?,
+        contact: Uri::try_from("sip:alice@alice.example.com:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, mut state_receiver) = unbounded_channel();
+    let (client_dialog, handle) = uac_dialog_layer.start_invite(invite_option, state_sender)?;
+
+    // start_invite returns right away, progress arrives on the state channel
+    tokio::time::timeout(std::time::Duration::from_secs(2), async {
+        while let Some(state) = state_receiver.recv().await {
+            if matches!(state, DialogState::Early(_, _)) {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("timeout waiting for ringing");
+
+    handle.cancel().await?;
+    let final_response =
+        tokio::time::timeout(std::time::Duration::from_secs(2), handle.await_final())
+            .await
+            .expect("timeout waiting for final response")?;
+    assert_eq!(
+        final_response.map(|r| r.status_code),
+        Some(StatusCode::RequestTerminated)
+    );
+    assert!(client_dialog.state().is_terminated());
+
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_repeated_422_terminates_with_session_timer_failure() -> crate::Result<()> {
+    use crate::dialog::{
+        dialog_layer::DialogLayer,
+        invitation::InviteOption,
+        session_timer::{min_se_header, parse_session_expires},
+    };
+
+    let token = CancellationToken::new();
+    let (uas_endpoint, uas_port) = create_udp_endpoint(&token).await?;
+    let (uac_endpoint, _) = create_udp_endpoint(&token).await?;
+
+    // UAS insists on a Min-SE larger than anything it will ever accept
+    let mut uas_incoming = uas_endpoint.incoming_transactions()?;
+    let uas = tokio::spawn(async move {
+        let mut session_expires = vec![];
+        for _ in 0..2 {
+            let mut invite_tx = uas_incoming.recv().await.expect("failed to get the INVITE");
+            session_expires.push(parse_session_expires(&invite_tx.original.headers));
+            invite_tx
+                .reply_with(
+                    StatusCode::SessionIntervalTooSmall,
+                    vec![min_se_header(1800)],
+                    None,
+                )
+                .await
+                .expect("failed to send 422");
+        }
+        session_expires
+    });
+
+    let uac_dialog_layer = DialogLayer::new(uac_endpoint.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@127.0.0.1:{};transport=udp", uas_port).as_str())?,
+        contact: Uri::try_from("sip:alice@alice.example.com:5060")?,
+        session_expires: Some(90),
+        ..Default::default()
+    };
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (client_dialog, final_response) = tokio::time::timeout(
+        std::time::Duration::from_secs(2),
+        uac_dialog_layer.do_invite(invite_option, state_sender),
+    )
+    .await
+    .expect("timeout waiting for the INVITE to fail")?;
+
+    assert_eq!(
+        final_response.map(|r| r.status_code),
+        Some(StatusCode::SessionIntervalTooSmall)
+    );
+    // the retry raised Session-Expires to the Min-SE of the first 422
+    assert_eq!(uas.await.unwrap(), vec![Some(90), Some(1800)]);
+    assert!(matches!(
+        client_dialog.state(),
+        DialogState::Terminated(_, TerminatedReason::SessionTimerNegotiationFailed)
+    ));
+
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_invite_scripted_over_mock_connection() -> crate::Result<()> {
+    use crate::dialog::{dialog_layer::DialogLayer, invitation::InviteOption};
+    use crate::transport::mock::MockConnection;
+    use rsip::prelude::ToTypedHeader;
+    use std::time::Duration;
+
+    let addr = |port: u16| {
+        SipAddr::new(
+            rsip::Transport::Udp,
+            rsip::HostWithPort::try_from(format!("127.0.0.1:{}", port).as_str()).unwrap(),
+        )
+    };
+    let token = CancellationToken::new();
+    let mock = MockConnection::new(addr(5060), addr(5062), Some(token.child_token()));
+    let transport_layer = TransportLayer::new(token.child_token());
+    transport_layer.add_transport(mock.clone().into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-uac")
+        .with_transport_layer(transport_layer)
+        .with_cancel_token(token.child_token())
+        .build();
+    let endpoint_inner = endpoint.inner.clone();
+    tokio::spawn(async move {
+        let _ = endpoint_inner.serve().await;
+    });
+    let next_request = || async {
+        let sent = tokio::time::timeout(Duration::from_secs(2), mock.next_sent())
+            .await
+            .expect("nothing was sent");
+        match sent.expect("mock connection closed") {
+            rsip::SipMessage::Request(req) => req,
+            rsip::SipMessage::Response(resp) => panic!("unexpected response {}", resp),
+        }
+    };
+
+    let dialog_layer = DialogLayer::new(endpoint.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from("sip:bob@127.0.0.1:5062")?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (client_dialog, handle) = dialog_layer.start_invite(invite_option, state_sender)?;
+
+    // play bob: 180 then 200 to the INVITE, and expect the ACK
+    let invite = next_request().await;
+    assert_eq!(invite.method, rsip::Method::Invite);
+    let to = invite.to_header()?.typed()?.with_tag("bob-tag".into());
+    for status in [StatusCode::Ringing, StatusCode::OK] {
+        let mut resp = endpoint.inner.make_response(&invite, status, None);
+        resp.headers
+            .unique_push(rsip::Header::To(to.clone().into()));
+        resp.headers
+            .push(rsip::Header::Contact("<sip:bob@127.0.0.1:5062>".into()));
+        mock.inject(resp).await?;
+    }
+    let final_response = tokio::time::timeout(Duration::from_secs(2), handle.await_final())
+        .await
+        .expect("no final response")?;
+    assert_eq!(final_response.map(|r| r.status_code), Some(StatusCode::OK));
+
+    let ack = next_request().await;
+    assert_eq!(ack.method, rsip::Method::Ack);
+    assert_eq!(ack.uri.to_string(), "sip:bob@127.0.0.1:5062");
+    assert_eq!(ack.cseq_header()?.seq()?, invite.cseq_header()?.seq()?);
+    assert!(matches!(
+        client_dialog.state(),
+        DialogState::Confirmed(_, _)
+    ));
+    assert!(mock.take_sent().is_empty());
+
+    token.cancel();
+    Ok(())
+}
@@ -2,13 +2,21 @@
 //!
 //! This module contains tests for dialog management and lifecycle
 
-use crate::dialog::{dialog_layer::DialogLayer, DialogId};
+use crate::dialog::{
+    caller_preferences::ContactPreference,
+    dialog_layer::DialogLayer,
+    invitation::InviteOption,
+    priority::{Priority, ResourcePriority},
+    DialogId,
+};
+use crate::rsip_ext::header_value_case_insensitive;
 use crate::transaction::{
     endpoint::EndpointBuilder,
     key::{TransactionKey, TransactionRole},
     transaction::Transaction,
 };
 use crate::transport::{udp::UdpConnection, TransportLayer};
+use rsip::prelude::{HeadersExt, ToTypedHeader};
 use rsip::{headers::*, Request};
 use tokio::sync::mpsc::unbounded_channel;
 use tokio_util::sync::CancellationToken;
@@ -17,6 +25,9 @@ use tokio_util::sync::CancellationToken;
 async fn create_test_endpoint() -> crate::Result<crate::transaction::endpoint::Endpoint> {
     let token = CancellationToken::new();
     let tl = TransportLayer::new(token.child_token());
+    // requests take their Via from a bound transport
+    let udp = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+    tl.add_transport(udp.into());
     let endpoint = EndpointBuilder::new()
         .with_user_agent("rsipstack-test")
         .with_transport_layer(tl)
@@ -390,3 +401,370 @@ async fn test_dialog_error_cases() -> crate::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_invite_emits_accept_contact_preferences() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let mock_conn = create_mock_connection().await?;
+    endpoint
+        .inner
+        .transport_layer
+        .add_transport(mock_conn.clone());
+    let dialog_layer = DialogLayer::new(endpoint.inner.clone());
+
+    let pref = ContactPreference::default()
+        .with_feature("audio", None)
+        .with_feature("methods", Some("\"INVITE,BYE\""))
+        .with_require()
+        .with_explicit();
+    let invite_option = InviteOption {
+        caller: rsip::Uri::try_from("sip:alice@example.com")?,
+        callee: rsip::Uri::try_from("sip:bob@example.com")?,
+        contact: rsip::Uri::try_from("sip:alice@alice.example.com:5060")?,
+        accept_contact: vec![pref.clone()],
+        ..Default::default()
+    };
+    let invite_req = dialog_layer.make_invite_request(&invite_option)?;
+
+    assert_eq!(
+        header_value_case_insensitive(&invite_req.headers, "Accept-Contact").as_deref(),
+        Some("*;audio;methods=\"INVITE,BYE\";require;explicit")
+    );
+
+    // the UAS parses the same preference back, ready to match registered contacts
+    let key = TransactionKey::from_request(&invite_req, TransactionRole::Server)?;
+    let tx = Transaction::new_server(key, invite_req, endpoint.inner.clone(), Some(mock_conn));
+    let (state_sender, _) = unbounded_channel();
+    let dialog = dialog_layer.get_or_create_server_invite(&tx, state_sender, None, None)?;
+    assert_eq!(dialog.accept_contact(), vec![pref.clone()]);
+    assert!(dialog.reject_contact().is_empty());
+
+    let contact_tags = vec![
+        ("audio".to_string(), None),
+        ("methods".to_string(), Some("\"invite,bye\"".to_string())),
+    ];
+    assert!(pref.matches(&contact_tags));
+    assert!(!pref.matches(&contact_tags[..1]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invite_emits_resource_priority() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let mock_conn = create_mock_connection().await?;
+    endpoint
+        .inner
+        .transport_layer
+        .add_transport(mock_conn.clone());
+    let dialog_layer = DialogLayer::new(endpoint.inner.clone());
+
+    let flash = ResourcePriority::new("dsn", "flash").expect("valid resource priority");
+    let mut invite_option = InviteOption {
+        caller: rsip::Uri::try_from("sip:alice@example.com")?,
+        callee: rsip::Uri::try_from("sip:bob@example.com")?,
+        contact: rsip::Uri::try_from("sip:alice@alice.example.com:5060")?,
+        priority: Some(Priority::Emergency),
+        resource_priority: vec![flash.clone()],
+        ..Default::default()
+    };
+    let invite_req = dialog_layer.make_invite_request(&invite_option)?;
+    assert_eq!(
+        header_value_case_insensitive(&invite_req.headers, "Resource-Priority").as_deref(),
+        Some("dsn.flash")
+    );
+    assert_eq!(
+        header_value_case_insensitive(&invite_req.headers, "Priority").as_deref(),
+        Some("emergency")
+    );
+
+    let key = TransactionKey::from_request(&invite_req, TransactionRole::Server)?;
+    let tx = Transaction::new_server(key, invite_req, endpoint.inner.clone(), Some(mock_conn));
+    let (state_sender, _) = unbounded_channel();
+    let dialog = dialog_layer.get_or_create_server_invite(&tx, state_sender, None, None)?;
+    assert_eq!(dialog.resource_priority(), vec![flash]);
+    assert_eq!(dialog.priority(), Some(Priority::Emergency));
+
+    // namespace.priority is required on both sides
+    assert!(ResourcePriority::parse("dsn").is_none());
+    assert!(ResourcePriority::parse("dsn.").is_none());
+    assert!(ResourcePriority::parse("dsn.fl ash").is_none());
+    invite_option.resource_priority = vec![ResourcePriority {
+        namespace: "dsn".to_string(),
+        priority: "flash override".to_string(),
+    }];
+    assert!(dialog_layer.make_invite_request(&invite_option).is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_anonymous_invite_keeps_asserted_identity() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let dialog_layer = DialogLayer::new(endpoint.inner.clone());
+
+    let invite_option = InviteOption {
+        caller: rsip::Uri::try_from("sip:alice@example.com")?,
+        caller_display_name: Some("Alice".to_string()),
+        callee: rsip::Uri::try_from("sip:bob@example.com")?,
+        contact: rsip::Uri::try_from("sip:alice@alice.example.com:5060")?,
+        asserted_identity: Some(rsip::Uri::try_from("sip:alice@example.com")?),
+        ..Default::default()
+    }
+    .anonymous();
+    let invite_req = dialog_layer.make_invite_request(&invite_option)?;
+
+    let from = invite_req.from_header()?.typed()?;
+    assert_eq!(from.display_name.as_deref(), Some("Anonymous"));
+    assert_eq!(from.uri.to_string(), "sip:anonymous@anonymous.invalid");
+    assert!(invite_req.from_header()?.tag()?.is_some());
+    let contact = invite_req.contact_header()?.typed()?;
+    assert_eq!(
+        contact.uri.to_string(),
+        "sip:anonymous@alice.example.com:5060"
+    );
+    assert_eq!(
+        header_value_case_insensitive(&invite_req.headers, "Privacy").as_deref(),
+        Some("id")
+    );
+    assert_eq!(
+        header_value_case_insensitive(&invite_req.headers, "P-Asserted-Identity").as_deref(),
+        Some("<sip:alice@example.com>")
+    );
+
+    // applying it twice does not duplicate Privacy
+    let invite_req = dialog_layer.make_invite_request(&invite_option.anonymous())?;
+    let privacy = invite_req
+        .headers
+        .iter()
+        .filter(|h| matches!(h, rsip::Header::Other(name, _) if name == "Privacy"))
+        .count();
+    assert_eq!(privacy, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_asserted_identity_and_privacy_sent_once() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let dialog_layer = DialogLayer::new(endpoint.inner.clone());
+
+    let invite_option = InviteOption {
+        caller: rsip::Uri::try_from("sip:alice@example.com")?,
+        callee: rsip::Uri::try_from("sip:bob@example.com")?,
+        contact: rsip::Uri::try_from("sip:alice@alice.example.com:5060")?,
+        asserted_identity: Some(rsip::Uri::try_from("sip:+15551234@trunk.example.com")?),
+        privacy: Some("id".to_string()),
+        headers: Some(vec![
+            rsip::Header::Other("Privacy".into(), "none".into()),
+            rsip::Header::Other(
+                "P-Asserted-Identity".into(),
+                "<sip:other@example.com>".into(),
+            ),
+        ]),
+        ..Default::default()
+    };
+    let invite_req = dialog_layer.make_invite_request(&invite_option)?;
+    for (name, value) in [
+        ("P-Asserted-Identity", "<sip:+15551234@trunk.example.com>"),
+        ("Privacy", "id"),
+    ] {
+        let values = invite_req
+            .headers
+            .iter()
+            .filter_map(|h| match h {
+                rsip::Header::Other(n, v) if n.eq_ignore_ascii_case(name) => Some(v.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![value], "{}", name);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_terminate_call_id_tears_down_all_dialogs_of_the_call() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let dialog_layer = DialogLayer::new(endpoint.inner.clone());
+    let mock_conn = create_mock_connection().await?;
+
+    let mut transactions = Vec::new();
+    let mut state_receivers = Vec::new();
+    for (from_tag, call_id, branch) in [
+        ("alice-leg-1", "stuck-call", "z9hG4bKleg1"),
+        ("alice-leg-2", "stuck-call", "z9hG4bKleg2"),
+        // a Call-ID that embeds the other one is a different call
+        ("carol-tag", "other_stuck-call_leg", "z9hG4bKother"),
+    ] {
+        let invite_req = create_invite_request(from_tag, "", call_id, branch);
+        let key = TransactionKey::from_request(&invite_req, TransactionRole::Server)?;
+        let tx = Transaction::new_server(
+            key,
+            invite_req,
+            endpoint.inner.clone(),
+            Some(mock_conn.clone()),
+        );
+        let (state_sender, state_receiver) = unbounded_channel();
+        dialog_layer.get_or_create_server_invite(&tx, state_sender, None, None)?;
+        transactions.push(tx);
+        state_receivers.push(state_receiver);
+    }
+    assert_eq!(dialog_layer.len(), 3);
+    assert_eq!(
+        endpoint
+            .inner
+            .get_transactions_by_call_id("stuck-call")
+            .len(),
+        2
+    );
+
+    let terminated = dialog_layer
+        .terminate_call_id("stuck-call", Some("admin teardown".to_string()))
+        .await;
+    assert_eq!(terminated, 2);
+    assert_eq!(dialog_layer.len(), 1);
+
+    // both legs were rejected, the other call is untouched
+    for state_receiver in &mut state_receivers[..2] {
+        let mut rejected = false;
+        while let Ok(state) = state_receiver.try_recv() {
+            rejected |= matches!(
+                state,
+                crate::dialog::dialog::DialogState::Terminated(
+                    _,
+                    crate::dialog::dialog::TerminatedReason::UasDecline
+                )
+            );
+        }
+        assert!(rejected);
+    }
+    assert!(state_receivers[2].try_recv().is_err());
+    assert_eq!(dialog_layer.terminate_call_id("stuck-call", None).await, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_default_contact_follows_bound_transport() -> crate::Result<()> {
+    use crate::transport::{SipAddr, TcpListenerConnection, TlsConfig, TlsListenerConnection};
+
+    let tl = TransportLayer::new(CancellationToken::new());
+    let udp = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+    let udp_port = udp.get_addr().port_or_default();
+    tl.add_transport(udp.into());
+    let tcp_addr = SipAddr::new(
+        rsip::transport::Transport::Tcp,
+        rsip::HostWithPort::try_from("127.0.0.1:5070")?,
+    );
+    tl.add_transport(TcpListenerConnection::new(tcp_addr, None).await?.into());
+    let tls_addr = SipAddr::new(
+        rsip::transport::Transport::Tls,
+        rsip::HostWithPort::try_from("127.0.0.1:5071")?,
+    );
+    tl.add_transport(
+        TlsListenerConnection::new(tls_addr, None, TlsConfig::default())
+            .await?
+            .into(),
+    );
+    let endpoint = EndpointBuilder::new().with_transport_layer(tl).build();
+    let dialog_layer = DialogLayer::new(endpoint.inner.clone());
+
+    let contact = dialog_layer.default_contact("alice")?;
+    assert_eq!(
+        contact.to_string(),
+        format!("sip:alice@127.0.0.1:{}", udp_port)
+    );
+
+    let target = rsip::Uri::try_from("sip:bob@example.com;transport=tcp")?;
+    let contact = dialog_layer.default_contact_for("alice", &target)?;
+    assert_eq!(contact.scheme, Some(rsip::Scheme::Sip));
+    assert_eq!(contact.host_with_port.to_string(), "127.0.0.1:5070");
+    assert_eq!(contact.transport(), Some(&rsip::transport::Transport::Tcp));
+
+    let target = rsip::Uri::try_from("sips:bob@example.com")?;
+    let contact = dialog_layer.default_contact_for("alice", &target)?;
+    assert_eq!(contact.scheme, Some(rsip::Scheme::Sips));
+    assert_eq!(contact.host_with_port.to_string(), "127.0.0.1:5071");
+    assert_eq!(contact.transport(), Some(&rsip::transport::Transport::Tls));
+
+    // no listener of the target's transport: the first one
+    let target = rsip::Uri::try_from("sip:bob@example.com;transport=ws")?;
+    let contact = dialog_layer.default_contact_for("alice", &target)?;
+    assert_eq!(contact.transport(), None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bye_for_unknown_dialog_is_answered_with_481() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let dialog_layer = DialogLayer::new(endpoint.inner.clone());
+    dialog_layer.route_in_dialog_requests();
+    let mut incoming = endpoint.incoming_transactions()?;
+    let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let peer_addr = peer.local_addr()?;
+    let conn = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+
+    let bye = Request {
+        method: rsip::Method::Bye,
+        uri: rsip::Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        headers: vec![
+            Via::new(format!(
+                "SIP/2.0/UDP {};branch=z9hG4bKunknownbye",
+                peer_addr
+            ))
+            .into(),
+            CSeq::new("2 BYE").into(),
+            From::new("Bob <sip:bob@restsend.com>;tag=bob-tag").into(),
+            To::new("Alice <sip:alice@restsend.com>;tag=alice-tag").into(),
+            CallId::new("unknown-call@restsend.com").into(),
+        ]
+        .into(),
+        version: rsip::Version::V2,
+        body: Default::default(),
+    };
+    endpoint
+        .inner
+        .on_received_message(bye.into(), conn.into(), &peer_addr.into())
+        .await?;
+
+    let mut buf = [0u8; 65535];
+    let (len, _) =
+        tokio::time::timeout(std::time::Duration::from_secs(1), peer.recv_from(&mut buf))
+            .await
+            .expect("no response to the bye")?;
+    let resp: rsip::Response = rsip::SipMessage::try_from(&buf[..len])?.try_into()?;
+    assert_eq!(
+        resp.status_code,
+        rsip::StatusCode::CallTransactionDoesNotExist
+    );
+    assert_eq!(resp.cseq_header()?.method()?, rsip::Method::Bye);
+    assert!(
+        incoming.try_recv().is_err(),
+        "no transaction is created for the bye"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invite_uses_the_given_from_tag() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let dialog_layer = DialogLayer::new(endpoint.inner.clone());
+    let invite_option = InviteOption {
+        caller: rsip::Uri::try_from("sip:alice@example.com")?,
+        callee: rsip::Uri::try_from("sip:bob@example.com")?,
+        contact: rsip::Uri::try_from("sip:alice@alice.example.com:5060")?,
+        from_tag: Some("alice-fixed-tag".to_string()),
+        ..Default::default()
+    };
+    let (state_sender, _) = unbounded_channel();
+    let (dialog, tx) = dialog_layer.create_client_invite_dialog(invite_option, state_sender)?;
+
+    let from_tag = tx
+        .original
+        .from_header()?
+        .tag()?
+        .map(|tag| tag.value().to_string());
+    assert_eq!(from_tag.as_deref(), Some("alice-fixed-tag"));
+    assert_eq!(dialog.id().from_tag, "alice-fixed-tag");
+    assert_eq!(
+        DialogId::try_from(&tx.original)?.from_tag,
+        "alice-fixed-tag"
+    );
+    Ok(())
+}
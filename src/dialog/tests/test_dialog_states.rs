@@ -9,6 +9,7 @@ use crate::dialog::{
 };
 use crate::transaction::{endpoint::EndpointBuilder, key::TransactionRole};
 use crate::transport::TransportLayer;
+use rsip::prelude::{ToTypedHeader, UntypedHeader};
 use rsip::{headers::*, Request, Response, StatusCode};
 use tokio::sync::mpsc::unbounded_channel;
 use tokio_util::sync::CancellationToken;
@@ -166,6 +167,52 @@ async fn test_dialog_state_transitions() -> crate::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_state_watch_shows_latest_state_to_late_subscribers() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (tu_sender, _tu_receiver) = unbounded_channel();
+    let dialog_id = DialogId {
+        call_id: "watch-call-id".to_string(),
+        from_tag: "alice-tag".to_string(),
+        to_tag: "bob-tag".to_string(),
+    };
+    let dialog_inner = DialogInner::new(
+        TransactionRole::Client,
+        dialog_id.clone(),
+        create_invite_request("alice-tag", "", "watch-call-id"),
+        endpoint.inner.clone(),
+        state_sender,
+        None,
+        Some(rsip::Uri::try_from("sip:alice@alice.example.com:5060")?),
+        tu_sender,
+    )?;
+
+    let mut early = dialog_inner.state_watch();
+    assert!(matches!(
+        *early.borrow_and_update(),
+        DialogState::Calling(_)
+    ));
+    dialog_inner.transition(DialogState::Confirmed(
+        dialog_id.clone(),
+        Response::default(),
+    ))?;
+    assert!(early.has_changed().unwrap());
+    assert!(matches!(
+        *early.borrow_and_update(),
+        DialogState::Confirmed(_, _)
+    ));
+
+    // informational states leave the stored state alone
+    let info = create_invite_request("alice-tag", "bob-tag", "watch-call-id");
+    dialog_inner.transition(DialogState::Info(dialog_id.clone(), info))?;
+    assert!(!early.has_changed().unwrap());
+
+    let late = dialog_inner.state_watch();
+    assert!(matches!(*late.borrow(), DialogState::Confirmed(_, _)));
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_server_dialog_state_transitions() -> crate::Result<()> {
     let endpoint = create_test_endpoint().await?;
@@ -484,3 +531,71 @@ async fn test_dialog_id_creation() -> crate::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_user_agent_and_server_headers() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (tu_sender, _tu_receiver) = unbounded_channel();
+    let invite_req = create_invite_request("alice-tag", "", "ua-call-id");
+    let dialog_inner = DialogInner::new(
+        TransactionRole::Client,
+        DialogId {
+            call_id: "ua-call-id".to_string(),
+            from_tag: "alice-tag".to_string(),
+            to_tag: "bob-tag".to_string(),
+        },
+        invite_req.clone(),
+        endpoint.inner.clone(),
+        state_sender,
+        None,
+        Some(rsip::Uri::try_from("sip:alice@alice.example.com:5060")?),
+        tu_sender,
+    )?;
+    let values = |headers: &rsip::Headers, server: bool| {
+        headers
+            .iter()
+            .filter_map(|h| match h {
+                rsip::Header::UserAgent(ua) if !server => Some(ua.value().to_string()),
+                rsip::Header::Server(s) if server => Some(s.value().to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // responses carry Server, not User-Agent
+    let resp = endpoint
+        .inner
+        .make_response(&invite_req, StatusCode::OK, None);
+    assert_eq!(values(&resp.headers, true), vec!["rsipstack-test"]);
+    assert!(values(&resp.headers, false).is_empty());
+
+    let via = Via::new("SIP/2.0/UDP alice.example.com:5060;branch=z9hG4bKua").typed()?;
+    let req = dialog_inner.make_request_with_vias(
+        rsip::Method::Options,
+        None,
+        vec![via.clone()],
+        None,
+        None,
+    )?;
+    assert_eq!(values(&req.headers, false), vec!["rsipstack-test"]);
+    let req = dialog_inner.make_request_with_vias(
+        rsip::Method::Options,
+        None,
+        vec![via],
+        Some(vec![rsip::Header::UserAgent("gateway/2.0".into())]),
+        None,
+    )?;
+    assert_eq!(values(&req.headers, false), vec!["gateway/2.0"]);
+
+    let resp = dialog_inner.make_response(&invite_req, StatusCode::OK, None, None);
+    assert_eq!(values(&resp.headers, true), vec!["rsipstack-test"]);
+    let resp = dialog_inner.make_response(
+        &invite_req,
+        StatusCode::OK,
+        Some(vec![rsip::Header::Server("gateway/2.0".into())]),
+        None,
+    );
+    assert_eq!(values(&resp.headers, true), vec!["gateway/2.0"]);
+    Ok(())
+}
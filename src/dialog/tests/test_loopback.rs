@@ -0,0 +1,1577 @@
+//! Loopback transport tests
+//!
+//! Complete calls between two in-process endpoints without sockets
+
+use super::{create_loopback_endpoint, loopback_addr};
+use crate::dialog::{
+    dialog::{Dialog, DialogState},
+    dialog_layer::DialogLayer,
+    invitation::InviteOption,
+    sdp::{Codec, Direction, MediaDescription, SessionDescription},
+    server_dialog::ServerInviteDialog,
+};
+use crate::transaction::endpoint::Endpoint;
+use crate::transport::{loopback::LoopbackConnection, SipAddr, TransportLayer};
+use crate::EndpointBuilder;
+use rsip::prelude::{HeadersExt, ToTypedHeader, UntypedHeader};
+use rsip::{StatusCode, Uri};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio_util::sync::CancellationToken;
+
+/// Answer every INVITE with 200 OK and hand in-dialog requests to their dialog,
+/// reporting the method of each request seen
+fn serve_uas(uas: &Endpoint) -> crate::Result<tokio::sync::mpsc::UnboundedReceiver<rsip::Method>> {
+    let mut incoming = uas.incoming_transactions()?;
+    let dialog_layer = DialogLayer::new(uas.inner.clone());
+    let (seen_sender, seen_receiver) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            seen_sender.send(tx.original.method).ok();
+            match tx.original.method {
+                rsip::Method::Invite => {
+                    let (state_sender, _) = unbounded_channel();
+                    let dialog = dialog_layer
+                        .get_or_create_server_invite(&tx, state_sender, None, None)
+                        .expect("failed to create dialog");
+                    // the dialog takes the ACK from the INVITE transaction
+                    let mut invite_dialog = dialog.clone();
+                    let seen_sender = seen_sender.clone();
+                    tokio::spawn(async move {
+                        invite_dialog.handle(&mut tx).await.ok();
+                        if invite_dialog.inner.is_confirmed() {
+                            seen_sender.send(rsip::Method::Ack).ok();
+                        }
+                    });
+                    dialog.accept(None, None).expect("accept failed");
+                }
+                _ => match dialog_layer.match_dialog(&tx.original) {
+                    Some(mut dialog) => {
+                        dialog.handle(&mut tx).await.ok();
+                    }
+                    None => {
+                        tx.reply(StatusCode::CallTransactionDoesNotExist).await.ok();
+                    }
+                },
+            }
+        }
+    });
+    Ok(seen_receiver)
+}
+
+async fn run_call(uac: &Endpoint, uas_addr: &SipAddr) -> crate::Result<Option<StatusCode>> {
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, mut state_receiver) = unbounded_channel();
+    let (client_dialog, resp) = dialog_layer.do_invite(invite_option, state_sender).await?;
+    assert!(client_dialog.inner.is_confirmed());
+    assert!(
+        client_dialog.cancel().await.is_err(),
+        "an answered INVITE cannot be cancelled"
+    );
+
+    client_dialog.bye().await?;
+    let mut terminated = false;
+    while let Ok(state) = state_receiver.try_recv() {
+        terminated |= matches!(state, DialogState::Terminated(_, _));
+    }
+    assert!(terminated, "BYE terminates the dialog");
+    Ok(resp.map(|r| r.status_code))
+}
+
+#[tokio::test]
+async fn test_loopback_invite_ok_ack_bye() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+    let mut seen = serve_uas(&uas)?;
+
+    let status = tokio::time::timeout(Duration::from_secs(2), run_call(&uac, &uas_addr))
+        .await
+        .expect("call timed out")?;
+    assert_eq!(status, Some(StatusCode::OK));
+
+    let mut methods = Vec::new();
+    while let Ok(method) = seen.try_recv() {
+        methods.push(method);
+    }
+    assert_eq!(
+        methods,
+        vec![rsip::Method::Invite, rsip::Method::Ack, rsip::Method::Bye]
+    );
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_loopback_loss_is_recovered_by_retransmission() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    // the first INVITE is lost and the first 200 OK arrives late
+    uac_conn.drop_next(1);
+    uas_conn.set_latency(Duration::from_millis(50));
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+    let _seen = serve_uas(&uas)?;
+
+    let started = tokio::time::Instant::now();
+    let status = tokio::time::timeout(Duration::from_secs(3), run_call(&uac, &uas_addr))
+        .await
+        .expect("call timed out")?;
+    assert_eq!(status, Some(StatusCode::OK));
+    // Timer A fires after T1 before the INVITE gets through
+    assert!(started.elapsed() >= Duration::from_millis(500));
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shutdown_hangs_up_confirmed_calls() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+    let mut seen = serve_uas(&uas)?;
+
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (client_dialog, _) = tokio::time::timeout(
+        Duration::from_secs(2),
+        dialog_layer.do_invite(invite_option, state_sender),
+    )
+    .await
+    .expect("call timed out")?;
+    assert!(client_dialog.inner.is_confirmed());
+
+    let remaining = dialog_layer.shutdown(Duration::from_secs(2)).await;
+    assert!(remaining.is_empty(), "{:?} still running", remaining);
+    assert!(client_dialog.inner.is_terminated());
+    assert_eq!(dialog_layer.len(), 0);
+
+    let mut methods = Vec::new();
+    while let Ok(method) = seen.try_recv() {
+        methods.push(method);
+    }
+    assert_eq!(
+        methods,
+        vec![rsip::Method::Invite, rsip::Method::Ack, rsip::Method::Bye]
+    );
+    token.cancel();
+    Ok(())
+}
+
+/// Ring, send two UPDATE offers in the early dialog, the second while the
+/// first is unanswered, then answer the INVITE, reporting the responses to
+/// the UPDATEs as they come
+fn serve_early_update_uas(
+    uas: &Endpoint,
+) -> crate::Result<tokio::sync::mpsc::UnboundedReceiver<Option<rsip::Response>>> {
+    let mut incoming = uas.incoming_transactions()?;
+    let dialog_layer = DialogLayer::new(uas.inner.clone());
+    let (update_sender, update_receiver) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            if tx.original.method != rsip::Method::Invite {
+                match dialog_layer.match_dialog(&tx.original) {
+                    Some(mut dialog) => {
+                        dialog.handle(&mut tx).await.ok();
+                    }
+                    None => {
+                        tx.reply(StatusCode::CallTransactionDoesNotExist).await.ok();
+                    }
+                }
+                continue;
+            }
+            let (state_sender, _) = unbounded_channel();
+            let contact = Uri::try_from("sip:bob@127.0.0.1:5062").ok();
+            let dialog = dialog_layer
+                .get_or_create_server_invite(&tx, state_sender, None, contact)
+                .expect("failed to create dialog");
+            let mut invite_dialog = dialog.clone();
+            tokio::spawn(async move {
+                invite_dialog.handle(&mut tx).await.ok();
+            });
+            // let `handle` take the INVITE before the dialog rings
+            tokio::task::yield_now().await;
+            dialog.ringing(None, None).expect("ringing failed");
+            // let the 180 reach the UAC so it knows the early dialog
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let headers = vec![rsip::Header::ContentType("application/sdp".into())];
+            let first = tokio::spawn({
+                let dialog = dialog.clone();
+                let headers = headers.clone();
+                let update_sender = update_sender.clone();
+                async move {
+                    let resp = dialog
+                        .update(Some(headers), Some(b"v=0 early offer".to_vec()))
+                        .await
+                        .expect("update failed");
+                    update_sender.send(resp).ok();
+                }
+            });
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let resp = dialog
+                .update(Some(headers), Some(b"v=0 overlapping offer".to_vec()))
+                .await
+                .expect("update failed");
+            update_sender.send(resp).ok();
+            first.await.ok();
+            dialog.accept(None, None).expect("accept failed");
+        }
+    });
+    Ok(update_receiver)
+}
+
+#[tokio::test]
+async fn test_loopback_early_update_is_answered_by_tu() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+    let mut update_responses = serve_early_update_uas(&uas)?;
+
+    let dialog_layer = Arc::new(DialogLayer::new(uac.inner.clone()));
+    let mut incoming = uac.incoming_transactions()?;
+    let uac_layer = dialog_layer.clone();
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            if let Some(mut dialog) = uac_layer.match_dialog(&tx.original) {
+                tokio::spawn(async move {
+                    dialog.handle(&mut tx).await.ok();
+                });
+            } else {
+                tx.reply(StatusCode::CallTransactionDoesNotExist).await.ok();
+            }
+        }
+    });
+
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (client_dialog, handle) = dialog_layer.start_invite(invite_option, state_sender)?;
+    let mut requests = client_dialog.incoming_requests();
+
+    let offer = tokio::time::timeout(Duration::from_secs(2), requests.recv())
+        .await
+        .expect("early update was not reported")
+        .expect("request channel closed");
+    assert_eq!(offer.method(), &rsip::Method::Update);
+    assert_eq!(offer.request.body, b"v=0 early offer".to_vec());
+    assert!(!client_dialog.inner.is_confirmed());
+
+    // a second offer before the first is answered is refused
+    let overlapping = update_responses
+        .recv()
+        .await
+        .flatten()
+        .expect("no response to the overlapping update");
+    assert_eq!(overlapping.status_code, StatusCode::RequestPending);
+
+    let headers = vec![rsip::Header::ContentType("application/sdp".into())];
+    offer.reply(
+        StatusCode::OK,
+        Some(headers),
+        Some(b"v=0 early answer".to_vec()),
+    );
+    let update_resp = update_responses
+        .recv()
+        .await
+        .flatten()
+        .expect("no response to the update");
+    assert_eq!(update_resp.status_code, StatusCode::OK);
+    assert_eq!(update_resp.body, b"v=0 early answer".to_vec());
+
+    let final_resp = tokio::time::timeout(Duration::from_secs(2), handle.await_final())
+        .await
+        .expect("invite timed out")?;
+    assert_eq!(final_resp.map(|r| r.status_code), Some(StatusCode::OK));
+    client_dialog.bye().await?;
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_next_state_yields_early_after_ringing() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+
+    // ring a little after the INVITE arrives and never answer
+    let mut incoming = uas.incoming_transactions()?;
+    let uas_layer = DialogLayer::new(uas.inner.clone());
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            if tx.original.method != rsip::Method::Invite {
+                continue;
+            }
+            let (state_sender, _) = unbounded_channel();
+            let contact = Uri::try_from("sip:bob@127.0.0.1:5062").ok();
+            let dialog = uas_layer
+                .get_or_create_server_invite(&tx, state_sender, None, contact)
+                .expect("failed to create dialog");
+            let mut invite_dialog = dialog.clone();
+            tokio::spawn(async move {
+                invite_dialog.handle(&mut tx).await.ok();
+            });
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            dialog.ringing(None, None).expect("ringing failed");
+        }
+    });
+
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (client_dialog, _handle) = dialog_layer.start_invite(invite_option, state_sender)?;
+
+    let timeout = tokio::time::sleep(Duration::from_secs(2));
+    tokio::pin!(timeout);
+    let ringing = loop {
+        tokio::select! {
+            state = client_dialog.next_state() => match state {
+                Some(DialogState::Early(_, resp)) => break resp,
+                Some(_) => continue,
+                None => panic!("dialog dropped"),
+            },
+            _ = &mut timeout => panic!("no early state"),
+        }
+    };
+    assert_eq!(ringing.status_code, StatusCode::Ringing);
+
+    // nothing else happens while the call rings
+    let idle = tokio::time::timeout(Duration::from_millis(50), client_dialog.next_state()).await;
+    assert!(idle.is_err());
+    token.cancel();
+    Ok(())
+}
+
+/// Reports the To tag of every ACK the endpoint receives
+struct Acks(tokio::sync::mpsc::UnboundedSender<rsip::Request>);
+
+impl crate::transaction::endpoint::MessageInspector for Acks {
+    fn before_send(&self, msg: rsip::SipMessage) -> rsip::SipMessage {
+        msg
+    }
+
+    fn after_received(&self, msg: rsip::SipMessage) -> rsip::SipMessage {
+        if let rsip::SipMessage::Request(ref req) = msg {
+            if req.method == rsip::Method::Ack {
+                self.0.send(req.clone()).ok();
+            }
+        }
+        msg
+    }
+}
+
+fn to_tag(req: &rsip::Request) -> Option<String> {
+    req.to_header()
+        .and_then(|to| to.tag())
+        .ok()
+        .flatten()
+        .map(|tag| tag.value().to_string())
+}
+
+/// Answer every INVITE from two branches of a forking proxy, `branch-a`
+/// ringing first and answering first, reporting the To tag of each BYE
+fn serve_forking_uas(
+    uas: &Endpoint,
+) -> crate::Result<tokio::sync::mpsc::UnboundedReceiver<String>> {
+    let mut incoming = uas.incoming_transactions()?;
+    let endpoint = uas.inner.clone();
+    let (bye_sender, bye_receiver) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            let req = tx.original.clone();
+            if req.method == rsip::Method::Bye {
+                if let Ok(Some(tag)) = req.to_header().and_then(|to| to.tag()) {
+                    bye_sender.send(tag.value().to_string()).ok();
+                }
+                tx.reply(StatusCode::OK).await.ok();
+                continue;
+            }
+            if req.method != rsip::Method::Invite {
+                continue;
+            }
+            let connection = tx.connection.clone().expect("no connection");
+            let destination = tx.destination.clone();
+            let endpoint = endpoint.clone();
+            tokio::spawn(async move {
+                let branch = |status: StatusCode, tag: &str| {
+                    let mut resp = endpoint.make_response(&req, status, None);
+                    let to = req.to_header().unwrap().typed().unwrap();
+                    resp.headers.retain(|h| !matches!(h, rsip::Header::To(_)));
+                    resp.headers
+                        .push(rsip::Header::To(to.with_tag(tag.into()).into()));
+                    let contact = format!("sip:{}@127.0.0.1:5062", tag);
+                    resp.headers.push(rsip::Header::Contact(
+                        rsip::typed::Contact::from(Uri::try_from(contact.as_str()).unwrap()).into(),
+                    ));
+                    resp
+                };
+                for (status, tag) in [
+                    (StatusCode::Ringing, "branch-a"),
+                    (StatusCode::Ringing, "branch-b"),
+                    (StatusCode::OK, "branch-a"),
+                    (StatusCode::OK, "branch-b"),
+                ] {
+                    endpoint
+                        .send_message(
+                            &connection,
+                            branch(status, tag).into(),
+                            destination.as_ref(),
+                        )
+                        .await
+                        .ok();
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                // keep the INVITE transaction around
+                while tx.receive().await.is_some() {}
+            });
+        }
+    });
+    Ok(bye_receiver)
+}
+
+#[tokio::test]
+async fn test_forked_invite_creates_a_dialog_per_branch() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+
+    let (ack_sender, mut acks) = unbounded_channel();
+    let transport_layer = TransportLayer::new(token.child_token());
+    transport_layer.add_transport(uas_conn.into());
+    let uas = EndpointBuilder::new()
+        .with_transport_layer(transport_layer)
+        .with_inspector(Box::new(Acks(ack_sender)))
+        .with_cancel_token(token.child_token())
+        .build();
+    let uas_inner = uas.inner.clone();
+    tokio::spawn(async move {
+        let _ = uas_inner.serve().await;
+    });
+    let mut bye_tags = serve_forking_uas(&uas)?;
+
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, mut state_receiver) = unbounded_channel();
+    let (client_dialog, resp) = dialog_layer.do_invite(invite_option, state_sender).await?;
+    assert_eq!(resp.map(|r| r.status_code), Some(StatusCode::OK));
+    assert_eq!(client_dialog.id().to_tag, "branch-a");
+    assert!(client_dialog.inner.is_confirmed());
+
+    let mut early = Vec::new();
+    let fork_id = tokio::time::timeout(Duration::from_secs(2), async {
+        while let Some(state) = state_receiver.recv().await {
+            match state {
+                DialogState::Early(id, _) => early.push(id.to_tag),
+                DialogState::Confirmed(id, _) if id.to_tag == "branch-b" => return Some(id),
+                _ => {}
+            }
+        }
+        None
+    })
+    .await
+    .expect("second branch was not confirmed")
+    .expect("state channel closed");
+    assert!(early.contains(&"branch-a".to_string()));
+    assert!(early.contains(&"branch-b".to_string()));
+
+    let mut acked = Vec::new();
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while acked.len() < 2 {
+            match acks.recv().await.map(|ack| to_tag(&ack)) {
+                Some(Some(tag)) if !acked.contains(&tag) => acked.push(tag),
+                Some(_) => {}
+                None => break,
+            }
+        }
+    })
+    .await
+    .expect("not every 2xx was acked");
+
+    // keep the first answer and hang up the other branch
+    let Some(Dialog::ClientInvite(fork)) = dialog_layer.get_dialog(&fork_id) else {
+        panic!("forked dialog is not registered");
+    };
+    assert_ne!(fork.id(), client_dialog.id());
+    fork.bye().await?;
+    let bye_tag = tokio::time::timeout(Duration::from_secs(2), bye_tags.recv())
+        .await
+        .expect("no bye for the second branch");
+    assert_eq!(bye_tag.as_deref(), Some("branch-b"));
+    assert!(client_dialog.inner.is_confirmed());
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cancel_matches_the_invite_branch() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+
+    // ring, then report the CANCEL that reaches the INVITE transaction
+    let mut incoming = uas.incoming_transactions()?;
+    let (cancel_sender, mut cancels) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            if tx.original.method != rsip::Method::Invite {
+                continue;
+            }
+            tx.reply(StatusCode::Ringing).await.expect("ringing failed");
+            let cancel_sender = cancel_sender.clone();
+            tokio::spawn(async move {
+                while let Some(msg) = tx.receive().await {
+                    if let rsip::SipMessage::Request(req) = msg {
+                        if req.method == rsip::Method::Cancel {
+                            cancel_sender.send(req).ok();
+                            tx.reply(StatusCode::RequestTerminated).await.ok();
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, mut state_receiver) = unbounded_channel();
+    let (client_dialog, handle) = dialog_layer.start_invite(invite_option, state_sender)?;
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while let Some(state) = state_receiver.recv().await {
+            if matches!(state, DialogState::Early(_, _)) {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("no early state");
+
+    client_dialog.cancel().await?;
+    let cancel = tokio::time::timeout(Duration::from_secs(2), cancels.recv())
+        .await
+        .expect("no cancel")
+        .expect("uas stopped");
+    let invite = client_dialog.inner.initial_request.lock().unwrap().clone();
+    assert_eq!(cancel.uri, invite.uri);
+    assert_eq!(
+        cancel.via_header()?.typed()?.branch(),
+        invite.via_header()?.typed()?.branch()
+    );
+    assert_eq!(cancel.cseq_header()?.seq()?, invite.cseq_header()?.seq()?);
+    assert_eq!(cancel.cseq_header()?.method()?, rsip::Method::Cancel);
+    assert_eq!(cancel.to_header()?.tag()?, None);
+
+    let final_resp = tokio::time::timeout(Duration::from_secs(2), handle.await_final())
+        .await
+        .expect("invite timed out")?;
+    assert_eq!(
+        final_resp.map(|r| r.status_code),
+        Some(StatusCode::RequestTerminated)
+    );
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_retransmitted_2xx_gets_the_same_ack() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+
+    let (ack_sender, mut acks) = unbounded_channel();
+    let transport_layer = TransportLayer::new(token.child_token());
+    transport_layer.add_transport(uas_conn.into());
+    let uas = EndpointBuilder::new()
+        .with_transport_layer(transport_layer)
+        .with_inspector(Box::new(Acks(ack_sender)))
+        .with_cancel_token(token.child_token())
+        .build();
+    let uas_inner = uas.inner.clone();
+    tokio::spawn(async move {
+        let _ = uas_inner.serve().await;
+    });
+
+    // answer the INVITE with a 200 OK sent twice, as if the first was lost
+    let mut incoming = uas.incoming_transactions()?;
+    let endpoint = uas.inner.clone();
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            let req = tx.original.clone();
+            if req.method != rsip::Method::Invite {
+                continue;
+            }
+            let mut resp = endpoint.make_response(&req, StatusCode::OK, None);
+            let to = req.to_header().unwrap().typed().unwrap();
+            resp.headers
+                .unique_push(rsip::Header::To(to.with_tag("uas-tag".into()).into()));
+            resp.headers
+                .push(rsip::Header::RecordRoute("<sip:127.0.0.1:5062;lr>".into()));
+            resp.headers.push(rsip::Header::Contact(
+                "<sip:bob@127.0.0.1:5062;transport=udp>".into(),
+            ));
+            let connection = tx.connection.clone().expect("no connection");
+            for _ in 0..2 {
+                endpoint
+                    .send_message(&connection, resp.clone().into(), tx.destination.as_ref())
+                    .await
+                    .ok();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            while tx.receive().await.is_some() {}
+        }
+    });
+
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (_, resp) = dialog_layer.do_invite(invite_option, state_sender).await?;
+    assert_eq!(resp.map(|r| r.status_code), Some(StatusCode::OK));
+
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while received.len() < 2 {
+            match acks.recv().await {
+                Some(ack) => received.push(ack),
+                None => break,
+            }
+        }
+    })
+    .await
+    .expect("retransmitted 2xx was not acked");
+
+    let ack = &received[0];
+    assert_eq!(ack.to_string(), received[1].to_string());
+    assert_eq!(
+        ack.uri,
+        Uri::try_from("sip:bob@127.0.0.1:5062;transport=udp")?
+    );
+    assert_eq!(to_tag(ack).as_deref(), Some("uas-tag"));
+    let routes = ack
+        .headers
+        .iter()
+        .filter_map(|h| match h {
+            rsip::Header::Route(route) => Some(route.value().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(routes, vec!["<sip:127.0.0.1:5062;lr>".to_string()]);
+    assert_eq!(ack.cseq_header()?.method()?, rsip::Method::Ack);
+    token.cancel();
+    Ok(())
+}
+
+/// Accept the first INVITE and answer each re-INVITE with the reverse of
+/// the offered direction, reporting the method and CSeq of every INVITE and
+/// ACK seen
+fn serve_hold_uas(
+    uas: &Endpoint,
+) -> crate::Result<tokio::sync::mpsc::UnboundedReceiver<(rsip::Method, u32)>> {
+    let mut incoming = uas.incoming_transactions()?;
+    let dialog_layer = DialogLayer::new(uas.inner.clone());
+    let endpoint = uas.inner.clone();
+    let (seen_sender, seen_receiver) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            if tx.original.method != rsip::Method::Invite {
+                if let Some(mut dialog) = dialog_layer.match_dialog(&tx.original) {
+                    dialog.handle(&mut tx).await.ok();
+                }
+                continue;
+            }
+            if dialog_layer.match_dialog(&tx.original).is_some() {
+                let offer =
+                    SessionDescription::parse(&tx.original.body).expect("re-INVITE without SDP");
+                let mut answer = offer.clone();
+                answer.set_direction(offer.direction().reverse());
+                let mut resp =
+                    endpoint.make_response(&tx.original, StatusCode::OK, Some(answer.to_bytes()));
+                resp.headers
+                    .push(rsip::Header::ContentType("application/sdp".into()));
+                tx.respond(resp).await.expect("failed to answer re-INVITE");
+            } else {
+                let (state_sender, _) = unbounded_channel();
+                let dialog = dialog_layer
+                    .get_or_create_server_invite(&tx, state_sender, None, None)
+                    .expect("failed to create dialog");
+                dialog.accept(None, None).expect("accept failed");
+            }
+            let seen_sender = seen_sender.clone();
+            tokio::spawn(async move {
+                let seq = |req: &rsip::Request| req.cseq_header().unwrap().seq().unwrap();
+                seen_sender
+                    .send((rsip::Method::Invite, seq(&tx.original)))
+                    .ok();
+                while let Some(msg) = tx.receive().await {
+                    if let rsip::SipMessage::Request(req) = msg {
+                        seen_sender.send((req.method, seq(&req))).ok();
+                    }
+                }
+            });
+        }
+    });
+    Ok(seen_receiver)
+}
+
+#[tokio::test]
+async fn test_reinvite_hold_then_resume() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+    let mut seen = serve_hold_uas(&uas)?;
+
+    let mut sdp = SessionDescription::new("127.0.0.1".parse().unwrap())
+        .with_media(MediaDescription::audio(4000, vec![Codec::pcmu()]));
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        content_type: Some("application/sdp".to_string()),
+        offer: Some(sdp.to_bytes()),
+        ..Default::default()
+    };
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (client_dialog, resp) = dialog_layer.do_invite(invite_option, state_sender).await?;
+    assert_eq!(resp.map(|r| r.status_code), Some(StatusCode::OK));
+
+    for direction in [Direction::SendOnly, Direction::SendRecv] {
+        sdp.set_direction(direction);
+        let resp = client_dialog
+            .reinvite_offer(sdp.to_bytes(), None)
+            .await?
+            .expect("dialog is confirmed");
+        assert_eq!(resp.status_code, StatusCode::OK);
+        let answer = SessionDescription::parse(&resp.body).expect("2xx without SDP answer");
+        assert_eq!(answer.direction(), direction.reverse());
+    }
+
+    // a second re-INVITE while the first is outstanding is refused
+    sdp.set_direction(Direction::Inactive);
+    let (first, second) = tokio::join!(
+        client_dialog.reinvite_offer(sdp.to_bytes(), None),
+        client_dialog.reinvite_offer(sdp.to_bytes(), None),
+    );
+    assert_eq!(first?.map(|r| r.status_code), Some(StatusCode::OK));
+    assert!(matches!(
+        second,
+        Err(crate::Error::DialogError(_, _, StatusCode::RequestPending))
+    ));
+
+    let mut requests = Vec::new();
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while requests.len() < 8 {
+            match seen.recv().await {
+                Some(request) => requests.push(request),
+                None => break,
+            }
+        }
+    })
+    .await
+    .expect("re-INVITEs were not all acked");
+    requests.sort_by_key(|(method, seq)| (*seq, *method != rsip::Method::Invite));
+
+    let initial_seq = requests[0].1;
+    let expected = (0..4)
+        .flat_map(|n| {
+            [
+                (rsip::Method::Invite, initial_seq + n),
+                (rsip::Method::Ack, initial_seq + n),
+            ]
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(requests, expected);
+    token.cancel();
+    Ok(())
+}
+
+/// Accept every new INVITE, handing its dialog to the test, and pass
+/// in-dialog requests, re-INVITEs included, to their dialog
+fn serve_dialogs(
+    uas: &Endpoint,
+    state_sender: crate::dialog::dialog::DialogStateSender,
+) -> crate::Result<tokio::sync::mpsc::UnboundedReceiver<ServerInviteDialog>> {
+    let mut incoming = uas.incoming_transactions()?;
+    let dialog_layer = DialogLayer::new(uas.inner.clone());
+    let (dialog_sender, dialog_receiver) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            let mut dialog = match dialog_layer.match_dialog(&tx.original) {
+                Some(dialog) => dialog,
+                None if tx.original.method == rsip::Method::Invite => {
+                    let dialog = dialog_layer
+                        .get_or_create_server_invite(&tx, state_sender.clone(), None, None)
+                        .expect("failed to create dialog");
+                    dialog.accept(None, None).expect("accept failed");
+                    dialog_sender.send(dialog.clone()).ok();
+                    Dialog::ServerInvite(dialog)
+                }
+                None => {
+                    tx.reply(StatusCode::CallTransactionDoesNotExist).await.ok();
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                dialog.handle(&mut tx).await.ok();
+            });
+        }
+    });
+    Ok(dialog_receiver)
+}
+
+/// Wait for the ACK of the initial INVITE to reach the server dialog
+async fn wait_confirmed(dialog: &ServerInviteDialog) {
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while !dialog.inner.is_confirmed() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("server dialog was not confirmed");
+}
+
+#[tokio::test]
+async fn test_reinvite_glare_is_retried_after_491() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+    let mut uas_dialogs = serve_dialogs(&uas, unbounded_channel().0)?;
+
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (client_dialog, resp) = dialog_layer.do_invite(invite_option, state_sender).await?;
+    assert_eq!(resp.map(|r| r.status_code), Some(StatusCode::OK));
+    let server_dialog = uas_dialogs.recv().await.expect("no server dialog");
+    wait_confirmed(&server_dialog).await;
+
+    // the UAS has a re-INVITE of its own outstanding for a while
+    let (held_sender, held) = tokio::sync::oneshot::channel();
+    let server_inner = server_dialog.inner.clone();
+    tokio::spawn(async move {
+        let _pending = server_inner
+            .begin_invite()
+            .expect("no INVITE outstanding yet");
+        held_sender.send(()).ok();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    });
+    held.await.expect("UAS INVITE was not marked outstanding");
+
+    let sdp = SessionDescription::new("127.0.0.1".parse().unwrap())
+        .with_media(MediaDescription::audio(4000, vec![Codec::pcmu()]));
+    let started = tokio::time::Instant::now();
+    let resp = tokio::time::timeout(
+        Duration::from_secs(6),
+        client_dialog.reinvite_offer(sdp.to_bytes(), None),
+    )
+    .await
+    .expect("re-INVITE was not retried")?;
+    assert_eq!(resp.map(|r| r.status_code), Some(StatusCode::OK));
+    // the owner of the Call-ID waits at least 2.1s after the 491
+    assert!(started.elapsed() >= Duration::from_millis(2100));
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_in_dialog_requests_are_answered_by_tu() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+    let mut uas_dialogs = serve_dialogs(&uas, unbounded_channel().0)?;
+
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (client_dialog, _) = dialog_layer.do_invite(invite_option, state_sender).await?;
+    let server_dialog = uas_dialogs.recv().await.expect("no server dialog");
+    wait_confirmed(&server_dialog).await;
+    let mut requests = server_dialog.incoming_requests();
+
+    // the TU rejects the INFO, and answers the BYE by dropping it
+    let (seen_sender, mut seen) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(request) = requests.recv().await {
+            seen_sender.send(*request.method()).ok();
+            if request.request.method == rsip::Method::Info {
+                request.reply(StatusCode::UnsupportedMediaType, None, None);
+            }
+        }
+    });
+
+    let headers = vec![rsip::Header::ContentType("application/x-unknown".into())];
+    let resp = client_dialog
+        .info(Some(headers), Some(b"hello".to_vec()))
+        .await?;
+    assert_eq!(
+        resp.map(|r| r.status_code),
+        Some(StatusCode::UnsupportedMediaType)
+    );
+    assert!(!server_dialog.inner.is_terminated());
+
+    client_dialog.bye().await?;
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while !server_dialog.inner.is_terminated() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("BYE did not terminate the server dialog");
+    assert!(matches!(
+        server_dialog.state(),
+        DialogState::Terminated(_, crate::dialog::dialog::TerminatedReason::UacBye)
+    ));
+
+    let mut methods = Vec::new();
+    while let Ok(method) = seen.try_recv() {
+        methods.push(method);
+    }
+    assert_eq!(methods, vec![rsip::Method::Info, rsip::Method::Bye]);
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dtmf_info_is_reported() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+    let (uas_state_sender, mut uas_states) = unbounded_channel();
+    let mut uas_dialogs = serve_dialogs(&uas, uas_state_sender)?;
+
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (client_dialog, _) = dialog_layer.do_invite(invite_option, state_sender).await?;
+    let server_dialog = uas_dialogs.recv().await.expect("no server dialog");
+    wait_confirmed(&server_dialog).await;
+
+    for digit in ['1', '#'] {
+        let resp = client_dialog
+            .send_dtmf(digit, Duration::from_millis(160))
+            .await?;
+        assert_eq!(resp.map(|r| r.status_code), Some(StatusCode::OK));
+    }
+    assert!(client_dialog
+        .send_dtmf('x', Duration::from_millis(160))
+        .await
+        .is_err());
+
+    let mut digits = Vec::new();
+    while let Ok(state) = uas_states.try_recv() {
+        if let DialogState::Dtmf(_, dtmf) = state {
+            assert_eq!(dtmf.duration, 160);
+            digits.push(dtmf.digit);
+        }
+    }
+    assert_eq!(digits, vec!['1', '#']);
+    token.cancel();
+    Ok(())
+}
+
+struct ForwardHandler(
+    tokio::sync::mpsc::UnboundedSender<crate::transaction::transaction::Transaction>,
+);
+
+#[async_trait::async_trait]
+impl crate::transaction::endpoint::IncomingRequestHandler for ForwardHandler {
+    async fn on_request(&self, tx: crate::transaction::transaction::Transaction) {
+        self.0.send(tx).ok();
+    }
+}
+
+#[tokio::test]
+async fn test_request_handler_gets_only_new_requests() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+
+    let (handled_sender, mut handled) = unbounded_channel();
+    let transport_layer = TransportLayer::new(token.child_token());
+    transport_layer.add_transport(uas_conn.into());
+    let uas = EndpointBuilder::new()
+        .with_user_agent("rsipstack-uas")
+        .with_transport_layer(transport_layer)
+        .with_cancel_token(token.child_token())
+        .with_handler(Box::new(ForwardHandler(handled_sender)))
+        .build();
+    let uas_inner = uas.inner.clone();
+    tokio::spawn(async move {
+        let _ = uas_inner.serve().await;
+    });
+    let uas_layer = DialogLayer::new(uas.inner.clone());
+    uas_layer.route_in_dialog_requests();
+
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let call =
+        tokio::spawn(async move { dialog_layer.do_invite(invite_option, state_sender).await });
+
+    let mut tx = tokio::time::timeout(Duration::from_secs(2), handled.recv())
+        .await
+        .expect("INVITE was not handed to the handler")
+        .expect("handler channel closed");
+    assert_eq!(tx.original.method, rsip::Method::Invite);
+    let server_dialog =
+        uas_layer.get_or_create_server_invite(&tx, unbounded_channel().0, None, None)?;
+    server_dialog.accept(None, None)?;
+    let mut dialog = server_dialog.clone();
+    tokio::spawn(async move {
+        dialog.handle(&mut tx).await.ok();
+    });
+
+    let (client_dialog, resp) = call.await.expect("call task failed")?;
+    assert_eq!(resp.map(|r| r.status_code), Some(StatusCode::OK));
+    wait_confirmed(&server_dialog).await;
+
+    // the BYE goes to the server dialog without passing the handler
+    client_dialog.bye().await?;
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while !server_dialog.inner.is_terminated() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("BYE did not terminate the server dialog");
+    assert!(handled.try_recv().is_err());
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_ping_terminates_unreachable_peer() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac_link = uac_conn.clone();
+    // pings time out after Timer F = 64*T1 = 320ms
+    let transport_layer = TransportLayer::new(token.child_token());
+    transport_layer.add_transport(uac_conn.into());
+    let uac = EndpointBuilder::new()
+        .with_user_agent("rsipstack-uac")
+        .with_transport_layer(transport_layer)
+        .with_cancel_token(token.child_token())
+        .with_option(crate::transaction::endpoint::EndpointOption {
+            t1: Duration::from_millis(5),
+            t1x64: Duration::from_millis(320),
+            ..Default::default()
+        })
+        .build();
+    let uac_inner = uac.inner.clone();
+    tokio::spawn(async move {
+        let _ = uac_inner.serve().await;
+    });
+    let uas = create_loopback_endpoint(uas_conn, &token);
+    let mut seen = serve_uas(&uas)?;
+
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (client_dialog, _) = dialog_layer.do_invite(invite_option, state_sender).await?;
+    client_dialog.enable_session_ping(Duration::from_millis(100), 2);
+
+    // answered pings keep the call up
+    tokio::time::sleep(Duration::from_millis(350)).await;
+    assert!(client_dialog.inner.is_confirmed());
+    let mut pings = 0;
+    while let Ok(method) = seen.try_recv() {
+        pings += (method == rsip::Method::Options) as usize;
+    }
+    assert!(pings >= 2, "pings answered: {}", pings);
+
+    // the peer vanishes: two pings in a row time out
+    uac_link.set_loss_rate(1.0);
+    tokio::time::timeout(Duration::from_secs(3), async {
+        while !client_dialog.inner.is_terminated() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("unanswered pings did not terminate the dialog");
+    assert!(matches!(
+        client_dialog.state(),
+        DialogState::Terminated(_, crate::dialog::dialog::TerminatedReason::PeerUnreachable)
+    ));
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dialog_reports_local_and_remote_addr() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+    let _seen = serve_uas(&uas)?;
+
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (client_dialog, _) = dialog_layer.do_invite(invite_option, state_sender).await?;
+
+    assert_eq!(client_dialog.local_addr(), Some(loopback_addr(5060)));
+    let remote = client_dialog.remote_addr().expect("remote addr");
+    assert_eq!(remote.addr, uas_addr.addr);
+    assert_eq!(remote.r#type, Some(rsip::Transport::Udp));
+    client_dialog.bye().await?;
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reinvite_repeats_asserted_identity_and_privacy() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+    let (uas_state_sender, mut uas_states) = unbounded_channel();
+    let mut uas_dialogs = serve_dialogs(&uas, uas_state_sender)?;
+
+    let mut sdp = SessionDescription::new("127.0.0.1".parse().unwrap())
+        .with_media(MediaDescription::audio(4000, vec![Codec::pcmu()]));
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        content_type: Some("application/sdp".to_string()),
+        offer: Some(sdp.to_bytes()),
+        asserted_identity: Some(Uri::try_from("sip:alice@example.com")?),
+        ..Default::default()
+    }
+    .anonymous();
+    let (state_sender, _state_receiver) = unbounded_channel();
+    let (client_dialog, _) = dialog_layer.do_invite(invite_option, state_sender).await?;
+    let server_dialog = uas_dialogs.recv().await.expect("no server dialog");
+    wait_confirmed(&server_dialog).await;
+
+    // a hold, so it is not taken for a session refresh
+    sdp.set_direction(Direction::SendOnly);
+    let resp = client_dialog.reinvite_offer(sdp.to_bytes(), None).await?;
+    assert_eq!(resp.map(|r| r.status_code), Some(StatusCode::OK));
+
+    let reinvite = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            match uas_states.recv().await {
+                Some(DialogState::Updated(_, request)) => return request,
+                Some(_) => continue,
+                None => panic!("no re-INVITE reached the server dialog"),
+            }
+        }
+    })
+    .await
+    .expect("re-INVITE was not reported");
+    for (name, value) in [
+        ("P-Asserted-Identity", "<sip:alice@example.com>"),
+        ("Privacy", "id"),
+    ] {
+        let values = reinvite
+            .headers
+            .iter()
+            .filter(|h| matches!(h, rsip::Header::Other(n, _) if n.eq_ignore_ascii_case(name)))
+            .map(|h| h.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![format!("{}: {}", name, value)]);
+    }
+    client_dialog.bye().await?;
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cancel_reason_is_repeated_on_the_487() -> crate::Result<()> {
+    use crate::rsip_ext::{header_value_case_insensitive, Reason};
+
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+
+    // ring and leave the CANCEL to the server dialog
+    let mut incoming = uas.incoming_transactions()?;
+    let uas_dialogs = DialogLayer::new(uas.inner.clone());
+    let (uas_state_sender, _uas_states) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            if tx.original.method != rsip::Method::Invite {
+                continue;
+            }
+            let dialog = uas_dialogs
+                .get_or_create_server_invite(&tx, uas_state_sender.clone(), None, None)
+                .expect("failed to create dialog");
+            dialog.ringing(None, None).expect("ringing failed");
+            let mut dialog = Dialog::ServerInvite(dialog);
+            tokio::spawn(async move {
+                dialog.handle(&mut tx).await.ok();
+            });
+        }
+    });
+
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, mut state_receiver) = unbounded_channel();
+    let (client_dialog, handle) = dialog_layer.start_invite(invite_option, state_sender)?;
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while let Some(state) = state_receiver.recv().await {
+            if matches!(state, DialogState::Early(_, _)) {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("no early state");
+
+    let reason = Reason::sip(200).with_text("Call completed elsewhere");
+    Dialog::ClientInvite(client_dialog)
+        .hangup_with_reason(reason.clone())
+        .await?;
+    let final_resp = tokio::time::timeout(Duration::from_secs(2), handle.await_final())
+        .await
+        .expect("invite timed out")?
+        .expect("no final response");
+    assert_eq!(final_resp.status_code, StatusCode::RequestTerminated);
+    let value = header_value_case_insensitive(&final_resp.headers, "Reason");
+    assert_eq!(value.as_deref().and_then(Reason::parse), Some(reason));
+    token.cancel();
+    Ok(())
+}
+
+/// Accept new INVITEs, first taking over the dialog a `Replaces` header
+/// names: the replaced dialog is hung up, and an INVITE replacing nothing
+/// is rejected with the status `match_replaces` gives
+fn serve_replacing_uas(uas: &Endpoint) -> crate::Result<()> {
+    let mut incoming = uas.incoming_transactions()?;
+    let dialog_layer = DialogLayer::new(uas.inner.clone());
+    let (state_sender, _) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            let mut dialog = match dialog_layer.match_dialog(&tx.original) {
+                Some(dialog) => dialog,
+                None if tx.original.method == rsip::Method::Invite => {
+                    let dialog = dialog_layer
+                        .get_or_create_server_invite(&tx, state_sender.clone(), None, None)
+                        .expect("failed to create dialog");
+                    match dialog.replaces().map(|r| dialog_layer.match_replaces(&r)) {
+                        Some(Ok(replaced)) => {
+                            dialog.accept(None, None).expect("accept failed");
+                            tokio::spawn(async move { replaced.hangup().await.ok() });
+                        }
+                        Some(Err(crate::Error::DialogError(_, _, status))) => {
+                            dialog.reject(Some(status), None).ok();
+                        }
+                        Some(Err(e)) => panic!("unexpected error {}", e),
+                        None => dialog.accept(None, None).expect("accept failed"),
+                    }
+                    Dialog::ServerInvite(dialog)
+                }
+                None => {
+                    tx.reply(StatusCode::CallTransactionDoesNotExist).await.ok();
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                dialog.handle(&mut tx).await.ok();
+            });
+        }
+    });
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invite_with_replaces_takes_over_the_dialog() -> crate::Result<()> {
+    use crate::dialog::refer::ReplacesInfo;
+
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+    serve_replacing_uas(&uas)?;
+
+    // the UAC hands the BYE of the replaced call to its dialog
+    let dialog_layer = Arc::new(DialogLayer::new(uac.inner.clone()));
+    let mut uac_incoming = uac.incoming_transactions()?;
+    let uac_layer = dialog_layer.clone();
+    tokio::spawn(async move {
+        while let Some(mut tx) = uac_incoming.recv().await {
+            if let Some(mut dialog) = uac_layer.match_dialog(&tx.original) {
+                tokio::spawn(async move { dialog.handle(&mut tx).await.ok() });
+            }
+        }
+    });
+    let invite_option = |replaces: Option<ReplacesInfo>| -> crate::Result<InviteOption> {
+        Ok(InviteOption {
+            caller: Uri::try_from("sip:alice@example.com")?,
+            callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+            contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+            replaces,
+            ..Default::default()
+        })
+    };
+    let call = |option: InviteOption| {
+        let dialog_layer = dialog_layer.clone();
+        async move {
+            let (state_sender, state_receiver) = unbounded_channel();
+            let (dialog, handle) = dialog_layer.start_invite(option, state_sender)?;
+            let status = tokio::time::timeout(Duration::from_secs(2), handle.await_final())
+                .await
+                .expect("invite timed out")?
+                .map(|resp| resp.status_code);
+            Ok::<_, crate::Error>((dialog, state_receiver, status))
+        }
+    };
+
+    let (first, mut first_states, status) = call(invite_option(None)?).await?;
+    assert_eq!(status, Some(StatusCode::OK));
+
+    // bob's tag is the to-tag of the dialog at bob
+    let first_id = first.id();
+    let replaces = ReplacesInfo {
+        call_id: first_id.call_id.clone(),
+        to_tag: first_id.to_tag.clone(),
+        from_tag: first_id.from_tag.clone(),
+        early_only: false,
+    };
+    let (_second, _, status) = call(invite_option(Some(replaces.clone()))?).await?;
+    assert_eq!(status, Some(StatusCode::OK));
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while let Some(state) = first_states.recv().await {
+            if matches!(state, DialogState::Terminated(_, _)) {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("replaced call was not hung up");
+
+    // the replaced dialog is gone, so replacing it again fails
+    let (_, _, status) = call(invite_option(Some(replaces))?).await?;
+    assert!(matches!(
+        status,
+        Some(StatusCode::CallTransactionDoesNotExist) | Some(StatusCode::Decline)
+    ));
+    token.cancel();
+    Ok(())
+}
+
+/// Redirect INVITEs for bob to carol at the same address, answering carol's
+/// with 200 OK, and report each INVITE
+fn serve_redirecting_uas(
+    uas: &Endpoint,
+    uas_addr: &SipAddr,
+) -> crate::Result<tokio::sync::mpsc::UnboundedReceiver<rsip::Request>> {
+    let mut incoming = uas.incoming_transactions()?;
+    let dialog_layer = DialogLayer::new(uas.inner.clone());
+    let carol = format!("<sip:carol@{}>;q=0.8", uas_addr.addr);
+    let (seen_sender, seen_receiver) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            if tx.original.method != rsip::Method::Invite {
+                if let Some(mut dialog) = dialog_layer.match_dialog(&tx.original) {
+                    tokio::spawn(async move { dialog.handle(&mut tx).await.ok() });
+                }
+                continue;
+            }
+            seen_sender.send(tx.original.clone()).ok();
+            if tx.original.uri.user() == Some("bob") {
+                let contact = rsip::headers::Contact::new(carol.clone());
+                tx.reply_with(StatusCode::MovedTemporarily, vec![contact.into()], None)
+                    .await
+                    .ok();
+                continue;
+            }
+            let (state_sender, _) = unbounded_channel();
+            let dialog = dialog_layer
+                .get_or_create_server_invite(&tx, state_sender, None, None)
+                .expect("failed to create dialog");
+            // the dialog is confirmed once `handle` sees the ACK
+            let mut invite_dialog = dialog.clone();
+            tokio::spawn(async move { invite_dialog.handle(&mut tx).await.ok() });
+            dialog.accept(None, None).expect("accept failed");
+        }
+    });
+    Ok(seen_receiver)
+}
+
+#[tokio::test]
+async fn test_redirect_is_followed_to_the_contact() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+    let mut seen = serve_redirecting_uas(&uas, &uas_addr)?;
+
+    let dialog_layer = DialogLayer::new(uac.inner.clone());
+    let invite_option = |follow_redirects: u8| -> crate::Result<InviteOption> {
+        Ok(InviteOption {
+            caller: Uri::try_from("sip:alice@example.com")?,
+            callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+            contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+            follow_redirects,
+            ..Default::default()
+        })
+    };
+
+    // without redirects to follow, the 302 is returned
+    let (state_sender, _) = unbounded_channel();
+    let (_, resp) = dialog_layer
+        .do_invite(invite_option(0)?, state_sender)
+        .await?;
+    assert_eq!(
+        resp.map(|r| r.status_code),
+        Some(StatusCode::MovedTemporarily)
+    );
+    let user = |req: &rsip::Request| req.uri.user().map(str::to_string);
+    assert_eq!(
+        seen.try_recv().ok().and_then(|r| user(&r)).as_deref(),
+        Some("bob")
+    );
+
+    let (state_sender, mut state_receiver) = unbounded_channel();
+    let (dialog, resp) = dialog_layer
+        .do_invite(invite_option(1)?, state_sender)
+        .await?;
+    assert_eq!(resp.map(|r| r.status_code), Some(StatusCode::OK));
+    assert!(dialog.inner.is_confirmed());
+    let redirected = seen.try_recv().expect("no INVITE to bob");
+    let retried = seen.try_recv().expect("no INVITE to carol");
+    assert_eq!(user(&redirected).as_deref(), Some("bob"));
+    assert_eq!(user(&retried).as_deref(), Some("carol"));
+    // the same call goes on: same From tag, higher CSeq
+    assert_eq!(
+        retried.from_header()?.tag()?,
+        redirected.from_header()?.tag()?
+    );
+    assert!(retried.cseq_header()?.seq()? > redirected.cseq_header()?.seq()?);
+
+    let mut redirected_to = None;
+    while let Ok(state) = state_receiver.try_recv() {
+        match state {
+            DialogState::Redirecting(_, target) => redirected_to = Some(target),
+            DialogState::Terminated(_, reason) => panic!("redirect terminated: {:?}", reason),
+            _ => {}
+        }
+    }
+    let target = redirected_to.expect("no Redirecting state");
+    assert_eq!(target.user(), Some("carol"));
+    assert_eq!(target.host_with_port, uas_addr.addr);
+
+    dialog.bye().await?;
+    token.cancel();
+    Ok(())
+}
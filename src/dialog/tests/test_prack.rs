@@ -1,5 +1,6 @@
 use super::test_dialog_states::{create_invite_request, create_test_endpoint};
 use crate::dialog::{dialog::DialogInner, server_dialog::ServerInviteDialog, DialogId};
+use crate::rsip_ext::{header_contains_token, parse_rseq_header};
 use crate::transaction::{
     key::{TransactionKey, TransactionRole},
     transaction::Transaction,
@@ -102,3 +103,84 @@ async fn server_dialog_handles_prack_request() -> crate::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn reliable_provisional_retransmits_until_prack() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+
+    let mut invite_req = create_invite_request("alice-tag", "bob-tag", "test-call-100rel");
+    invite_req
+        .headers
+        .push(Header::Other("Require".into(), "100rel".into()));
+    let key = TransactionKey::from_request(&invite_req, TransactionRole::Server)?;
+
+    let (_, incoming_rx) = unbounded_channel();
+    let (transport_tx, mut transport_rx) = unbounded_channel();
+    let sip_addr: SipAddr = rsip::HostWithPort::try_from("127.0.0.1:5060")?.into();
+    let channel =
+        ChannelConnection::create_connection(incoming_rx, transport_tx, sip_addr.clone(), None)
+            .await?;
+    let connection = SipConnection::Channel(channel);
+
+    let mut tx = Transaction::new_server(
+        key,
+        invite_req,
+        endpoint.inner.clone(),
+        Some(connection.clone()),
+    );
+    tx.destination = Some(sip_addr.clone());
+
+    tx.reply(StatusCode::Ringing).await?;
+    match transport_rx.recv().await {
+        Some(TransportEvent::Incoming(SipMessage::Response(resp), _, _)) => {
+            assert_eq!(resp.status_code, StatusCode::Ringing);
+            assert_eq!(parse_rseq_header(&resp.headers), Some(1));
+            assert!(header_contains_token(&resp.headers, "Require", "100rel"));
+        }
+        other => panic!("unexpected transport event: {other:?}"),
+    }
+    assert!(tx.timer_prack.is_some());
+
+    // only one reliable provisional response may be outstanding
+    assert!(tx.reply(StatusCode::SessionProgress).await.is_err());
+
+    let prack_request = Request {
+        method: Method::PRack,
+        uri: rsip::Uri::try_from("sip:bob@example.com:5060")?,
+        headers: vec![
+            Via::new("SIP/2.0/UDP 198.51.100.1:5060;branch=z9hG4bKprack02").into(),
+            CSeq::new("2 PRACK").into(),
+            From::new("Alice <sip:alice@example.com>;tag=alice-tag").into(),
+            To::new("Bob <sip:bob@example.com>;tag=bob-tag").into(),
+            CallId::new("test-call-100rel").into(),
+            Header::Other("RAck".into(), "1 1 INVITE".into()),
+            MaxForwards::new("70").into(),
+        ]
+        .into(),
+        version: rsip::Version::V2,
+        body: vec![],
+    };
+    endpoint
+        .inner
+        .on_received_message(prack_request.into(), connection, &sip_addr)
+        .await?;
+
+    // the INVITE transaction stops retransmitting and passes the PRACK up
+    match timeout(Duration::from_secs(1), tx.receive())
+        .await
+        .expect("timeout waiting for PRACK")
+    {
+        Some(SipMessage::Request(req)) => assert_eq!(req.method, Method::PRack),
+        other => panic!("unexpected message: {other:?}"),
+    }
+    assert!(tx.timer_prack.is_none());
+
+    tx.reply(StatusCode::SessionProgress).await?;
+    match transport_rx.recv().await {
+        Some(TransportEvent::Incoming(SipMessage::Response(resp), _, _)) => {
+            assert_eq!(parse_rseq_header(&resp.headers), Some(2));
+        }
+        other => panic!("unexpected transport event: {other:?}"),
+    }
+    Ok(())
+}
@@ -0,0 +1,183 @@
+//! REFER tests
+//!
+//! Refer-To encoding and a blind transfer between two loopback endpoints
+
+use super::{create_loopback_endpoint, loopback_addr};
+use crate::dialog::{
+    dialog::DialogState,
+    dialog_layer::DialogLayer,
+    invitation::InviteOption,
+    refer::{parse_refer_to, parse_sipfrag_status, refer_to_header, ReplacesInfo},
+    server_dialog::ServerInviteDialog,
+};
+use crate::transaction::endpoint::Endpoint;
+use crate::transport::loopback::LoopbackConnection;
+use rsip::{StatusCode, Uri};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio_util::sync::CancellationToken;
+
+/// Hand in-dialog requests to their dialog and answer the first INVITE,
+/// passing the new server dialog to the test
+fn serve(
+    endpoint: &Endpoint,
+    dialog_layer: Arc<DialogLayer>,
+    state_sender: crate::dialog::dialog::DialogStateSender,
+) -> crate::Result<UnboundedReceiver<ServerInviteDialog>> {
+    let mut incoming = endpoint.incoming_transactions()?;
+    let (dialog_sender, dialog_receiver) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            if tx.original.method == rsip::Method::Invite {
+                let mut dialog = dialog_layer
+                    .get_or_create_server_invite(&tx, state_sender.clone(), None, None)
+                    .expect("failed to create dialog");
+                dialog.accept(None, None).expect("accept failed");
+                dialog_sender.send(dialog.clone()).ok();
+                tokio::spawn(async move {
+                    dialog.handle(&mut tx).await.ok();
+                });
+                continue;
+            }
+            match dialog_layer.match_dialog(&tx.original) {
+                Some(mut dialog) => {
+                    dialog.handle(&mut tx).await.ok();
+                }
+                None => {
+                    tx.reply(StatusCode::CallTransactionDoesNotExist).await.ok();
+                }
+            }
+        }
+    });
+    Ok(dialog_receiver)
+}
+
+#[tokio::test]
+async fn test_refer_blind_transfer() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (uac_conn, uas_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let uas_addr = uas_conn.get_addr().clone();
+    let uac = create_loopback_endpoint(uac_conn, &token);
+    let uas = create_loopback_endpoint(uas_conn, &token);
+
+    let uac_layer = Arc::new(DialogLayer::new(uac.inner.clone()));
+    let (uac_state_sender, mut uac_states) = unbounded_channel();
+    serve(&uac, uac_layer.clone(), uac_state_sender.clone())?;
+    let uas_layer = Arc::new(DialogLayer::new(uas.inner.clone()));
+    let (uas_state_sender, mut uas_states) = unbounded_channel();
+    let mut uas_dialogs = serve(&uas, uas_layer, uas_state_sender)?;
+
+    let invite_option = InviteOption {
+        caller: Uri::try_from("sip:alice@example.com")?,
+        callee: Uri::try_from(format!("sip:bob@{}", uas_addr.addr).as_str())?,
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (client_dialog, _) = uac_layer.do_invite(invite_option, uac_state_sender).await?;
+    let server_dialog = uas_dialogs.recv().await.expect("no server dialog");
+
+    let timeout = Duration::from_secs(2);
+    tokio::time::timeout(timeout, async {
+        while let Some(state) = uas_states.recv().await {
+            if matches!(state, DialogState::Confirmed(_, _)) {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("server dialog was not confirmed");
+
+    let target = Uri::try_from("sip:carol@example.com")?;
+    let resp = client_dialog.refer(target.clone(), None).await?;
+    assert_eq!(resp.map(|r| r.status_code), Some(StatusCode::Accepted));
+
+    let refer_to = tokio::time::timeout(timeout, async {
+        while let Some(state) = uas_states.recv().await {
+            if let DialogState::Refer(_, refer_to, _) = state {
+                return Some(refer_to);
+            }
+        }
+        None
+    })
+    .await
+    .expect("refer was not reported")
+    .expect("state channel closed");
+    assert_eq!(refer_to.uri, target);
+    assert_eq!(refer_to.replaces, None);
+
+    for status in [StatusCode::Trying, StatusCode::OK] {
+        let resp = server_dialog.notify_refer(status).await?;
+        assert_eq!(resp.map(|r| r.status_code), Some(StatusCode::OK));
+    }
+
+    let mut progress = Vec::new();
+    tokio::time::timeout(timeout, async {
+        while let Some(state) = uac_states.recv().await {
+            if let DialogState::ReferProgress(_, status) = state {
+                progress.push(status);
+                if progress.len() == 2 {
+                    break;
+                }
+            }
+        }
+    })
+    .await
+    .expect("transfer progress was not reported");
+    assert_eq!(progress, vec![StatusCode::Trying, StatusCode::OK]);
+    token.cancel();
+    Ok(())
+}
+
+#[test]
+fn test_refer_to_with_replaces_round_trip() {
+    let replaces = ReplacesInfo {
+        call_id: "a84b4c76e66710@pc33.example.com".to_string(),
+        to_tag: "314159".to_string(),
+        from_tag: "9fxced76sl".to_string(),
+        early_only: true,
+    };
+    let target = Uri::try_from("sip:carol@example.com").unwrap();
+    let header = refer_to_header(&target, Some(&replaces));
+    let rsip::Header::Other(name, value) = &header else {
+        panic!("unexpected header {:?}", header);
+    };
+    assert_eq!(name, "Refer-To");
+    assert_eq!(
+        value,
+        "<sip:carol@example.com?Replaces=a84b4c76e66710%40pc33.example.com%3Bto-tag%3D314159%3Bfrom-tag%3D9fxced76sl%3Bearly-only>"
+    );
+
+    let headers: rsip::Headers = vec![header].into();
+    let refer_to = parse_refer_to(&headers).expect("Refer-To did not parse");
+    assert_eq!(refer_to.uri, target);
+    assert_eq!(refer_to.replaces, Some(replaces));
+
+    let headers: rsip::Headers = vec![rsip::Header::Other(
+        "r".into(),
+        "sip:dave@example.com".into(),
+    )]
+    .into();
+    let refer_to = parse_refer_to(&headers).expect("compact Refer-To did not parse");
+    assert_eq!(refer_to.uri, Uri::try_from("sip:dave@example.com").unwrap());
+    assert_eq!(refer_to.replaces, None);
+    assert!(parse_refer_to(&rsip::Headers::default()).is_none());
+}
+
+#[test]
+fn test_parse_sipfrag_status() {
+    assert_eq!(
+        parse_sipfrag_status(b"SIP/2.0 180 Ringing\r\n"),
+        Some(StatusCode::Ringing)
+    );
+    assert_eq!(
+        parse_sipfrag_status(b"SIP/2.0 603 Decline"),
+        Some(StatusCode::Decline)
+    );
+    assert_eq!(
+        parse_sipfrag_status(b"INVITE sip:carol@example.com SIP/2.0"),
+        None
+    );
+    assert_eq!(parse_sipfrag_status(b""), None);
+}
@@ -0,0 +1,756 @@
+//! Registration tests
+//!
+//! Tests for NAT discovery and Contact handling in client registrations
+
+use crate::dialog::registration::Registration;
+use crate::rsip_ext::RsipResponseExt;
+use crate::transaction::endpoint::EndpointBuilder;
+use crate::transport::TransportLayer;
+use rsip::{
+    headers::*,
+    prelude::{HeadersExt, ToTypedHeader},
+    Response, StatusCode,
+};
+use tokio_util::sync::CancellationToken;
+
+async fn create_test_endpoint() -> crate::Result<crate::transaction::endpoint::Endpoint> {
+    let token = CancellationToken::new();
+    let tl = TransportLayer::new(token.child_token());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(tl)
+        .build();
+    Ok(endpoint)
+}
+
+fn create_register_response(via: &str) -> Response {
+    Response {
+        status_code: StatusCode::OK,
+        version: rsip::Version::V2,
+        headers: vec![
+            Via::new(via).into(),
+            CSeq::new("1 REGISTER").into(),
+            From::new("<sip:alice@example.com>;tag=reg-tag").into(),
+            To::new("<sip:alice@example.com>;tag=server-tag").into(),
+            CallId::new("register-call-id").into(),
+            Contact::new("<sip:alice@192.168.1.100:5060>;expires=60").into(),
+        ]
+        .into(),
+        body: vec![],
+    }
+}
+
+#[tokio::test]
+async fn test_registration_updates_contact_from_received_rport() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let mut registration = Registration::new(endpoint.inner.clone(), None);
+
+    let resp = create_register_response(
+        "SIP/2.0/UDP 192.168.1.100:5060;branch=z9hG4bK-reg;received=203.0.113.7;rport=40123",
+    );
+    let (received, rport) = resp.via_received_rport().expect("received/rport");
+    assert_eq!(
+        received,
+        Some(rsip::Host::IpAddr("203.0.113.7".parse().unwrap()))
+    );
+    assert_eq!(rport, Some(40123));
+
+    registration.contact = Some(resp.contact_header()?.typed()?);
+    assert!(registration.update_contact_from_response(&resp));
+    let contact = registration.contact.as_ref().expect("contact");
+    assert_eq!(contact.uri.host_with_port.to_string(), "203.0.113.7:40123");
+    assert_eq!(registration.expires(), 60);
+
+    // applying the same mapping again is a no-op
+    assert!(!registration.update_contact_from_response(&resp));
+
+    // a response without received/rport leaves the Contact alone
+    let plain = create_register_response("SIP/2.0/UDP 192.168.1.100:5060;branch=z9hG4bK-reg");
+    assert!(plain.via_received_rport().is_none());
+    assert!(!registration.update_contact_from_response(&plain));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_registration_keeps_client_min_expires() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let mut registration = Registration::new(endpoint.inner.clone(), None);
+
+    // without a client minimum the registrar's Min-Expires wins
+    assert_eq!(registration.negotiate_expires(Some(30), Some(60)), Some(60));
+    assert_eq!(registration.negotiate_expires(None, None), None);
+
+    registration.min_expires = Some(120);
+    assert_eq!(registration.negotiate_expires(Some(30), None), Some(120));
+    // a lower server Min-Expires must not pull us under our own minimum
+    assert_eq!(
+        registration.negotiate_expires(Some(30), Some(60)),
+        Some(120)
+    );
+    assert_eq!(
+        registration.negotiate_expires(Some(30), Some(300)),
+        Some(300)
+    );
+    assert_eq!(
+        registration.negotiate_expires(Some(600), Some(60)),
+        Some(600)
+    );
+    Ok(())
+}
+
+/// Answer every REGISTER with 200 OK granting `expires`, returning the
+/// Expires header and Contact of each request seen
+async fn run_registrar(
+    socket: tokio::net::UdpSocket,
+    expires: u32,
+    seen: tokio::sync::mpsc::UnboundedSender<(Option<String>, String)>,
+) -> crate::Result<()> {
+    use rsip::prelude::{HasHeaders, HeadersExt, UntypedHeader};
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let (len, from) = socket.recv_from(&mut buf).await?;
+        let req: rsip::Request = rsip::SipMessage::try_from(&buf[..len])?.try_into()?;
+        let contact = req.contact_header()?.value().to_string();
+        seen.send((
+            crate::rsip_ext::header_value_case_insensitive(&req.headers, "Expires"),
+            contact.clone(),
+        ))
+        .ok();
+        let mut headers: Vec<rsip::Header> = req
+            .headers()
+            .iter()
+            .filter(|h| {
+                matches!(
+                    h,
+                    rsip::Header::Via(_)
+                        | rsip::Header::From(_)
+                        | rsip::Header::CallId(_)
+                        | rsip::Header::CSeq(_)
+                )
+            })
+            .cloned()
+            .collect();
+        headers.push(To::new(format!("{};tag=registrar", req.to_header()?.value())).into());
+        headers.push(Contact::new(format!("{};expires={}", contact, expires)).into());
+        headers.push(ContentLength::default().into());
+        let resp = Response {
+            status_code: StatusCode::OK,
+            version: rsip::Version::V2,
+            headers: headers.into(),
+            body: vec![],
+        };
+        socket
+            .send_to(rsip::SipMessage::from(resp).to_string().as_bytes(), from)
+            .await?;
+    }
+}
+
+#[tokio::test]
+async fn test_registration_serve_refreshes_and_unregisters() -> crate::Result<()> {
+    use crate::dialog::registration::RegistrationState;
+    use std::time::Duration;
+
+    let token = CancellationToken::new();
+    let tl = TransportLayer::new(token.child_token());
+    let conn = crate::transport::udp::UdpConnection::create_connection(
+        "127.0.0.1:0".parse()?,
+        None,
+        Some(token.child_token()),
+    )
+    .await?;
+    tl.add_transport(conn.into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(tl)
+        .with_cancel_token(token.child_token())
+        .build();
+    let endpoint_inner = endpoint.inner.clone();
+    tokio::spawn(async move { endpoint.serve().await });
+
+    let registrar = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let server = rsip::Uri::try_from(format!("sip:{}", registrar.local_addr()?).as_str())?;
+    let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(run_registrar(registrar, 1, seen_tx));
+
+    let registration = Registration::new(endpoint_inner, None);
+    let serve_token = CancellationToken::new();
+    let (state_tx, mut state_rx) = tokio::sync::mpsc::unbounded_channel();
+    let serving =
+        tokio::spawn(registration.serve(server, Some(1), serve_token.clone(), Some(state_tx)));
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    // registered, then refreshed at 90% of the granted second
+    assert_eq!(state_rx.try_recv(), Ok(RegistrationState::Registered(1)));
+    assert_eq!(state_rx.try_recv(), Ok(RegistrationState::Refreshing));
+    assert_eq!(state_rx.try_recv(), Ok(RegistrationState::Registered(1)));
+
+    serve_token.cancel();
+    tokio::time::timeout(Duration::from_secs(2), serving)
+        .await
+        .expect("serve exits on cancel")
+        .expect("join")?;
+
+    let mut requests = Vec::new();
+    while let Ok(request) = seen_rx.try_recv() {
+        requests.push(request);
+    }
+    assert!(requests.len() >= 3, "register, refresh, unregister");
+    assert!(requests[..requests.len() - 1]
+        .iter()
+        .all(|(expires, _)| expires.as_deref() == Some("1")));
+    let (expires, contact) = requests.last().unwrap();
+    assert_eq!(expires.as_deref(), Some("0"));
+    assert!(
+        contact.contains("expires=0") && !contact.contains("expires=1"),
+        "{}",
+        contact
+    );
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_registration_requests_endpoint_default_expires() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let tl = TransportLayer::new(token.child_token());
+    let conn = crate::transport::udp::UdpConnection::create_connection(
+        "127.0.0.1:0".parse()?,
+        None,
+        Some(token.child_token()),
+    )
+    .await?;
+    tl.add_transport(conn.into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(tl)
+        .with_cancel_token(token.child_token())
+        .with_default_register_expires(1800)
+        .build();
+    let endpoint_inner = endpoint.inner.clone();
+    tokio::spawn(async move { endpoint.serve().await });
+
+    let registrar = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let server = rsip::Uri::try_from(format!("sip:{}", registrar.local_addr()?).as_str())?;
+    let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(run_registrar(registrar, 1800, seen_tx));
+
+    let mut registration = Registration::new(endpoint_inner, None);
+    assert_eq!(registration.expires(), 1800);
+    let resp = registration.register(server, None).await?;
+    assert_eq!(resp.status_code, StatusCode::OK);
+
+    let (expires, contact) = seen_rx.try_recv().expect("REGISTER sent");
+    assert_eq!(expires.as_deref(), Some("1800"));
+    assert!(!contact.contains("expires"), "{}", contact);
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unregister_without_contact_removes_all_bindings() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let tl = TransportLayer::new(token.child_token());
+    let conn = crate::transport::udp::UdpConnection::create_connection(
+        "127.0.0.1:0".parse()?,
+        None,
+        Some(token.child_token()),
+    )
+    .await?;
+    tl.add_transport(conn.into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(tl)
+        .with_cancel_token(token.child_token())
+        .build();
+    let endpoint_inner = endpoint.inner.clone();
+    tokio::spawn(async move { endpoint.serve().await });
+
+    let registrar = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let server = rsip::Uri::try_from(format!("sip:{}", registrar.local_addr()?).as_str())?;
+    let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(run_registrar(registrar, 0, seen_tx));
+
+    let mut registration = Registration::new(endpoint_inner, None);
+    let resp = registration.unregister(server).await?;
+    assert_eq!(resp.status_code, StatusCode::OK);
+
+    let (expires, contact) = seen_rx.try_recv().expect("REGISTER sent");
+    assert_eq!(expires.as_deref(), Some("0"));
+    assert_eq!(contact, "*");
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_registration_tracks_expiry_per_binding() -> crate::Result<()> {
+    use rsip::prelude::ToTypedHeader;
+
+    let endpoint = create_test_endpoint().await?;
+    let mut registration = Registration::new(endpoint.inner.clone(), None);
+    registration.add_contact(Contact::new("<sip:alice@192.0.2.10:5060>").typed()?);
+    registration.add_contact(Contact::new("<sip:alice@192.0.2.10:5060;transport=tcp>").typed()?);
+
+    let mut resp = create_register_response("SIP/2.0/UDP 192.0.2.10:5060;branch=z9hG4bK-reg");
+    resp.headers
+        .retain(|h| !matches!(h, rsip::Header::Contact(_)));
+    // one header listing two bindings, plus a binding of another device
+    resp.headers.push(
+        Contact::new(
+            "<sip:alice@192.0.2.10:5060>;expires=600, \
+             \"Alice, TCP\" <sip:alice@192.0.2.10:5060;transport=tcp>;expires=300",
+        )
+        .into(),
+    );
+    resp.headers
+        .push(Contact::new("<sip:alice@198.51.100.7:5060>;expires=30").into());
+
+    registration.update_bindings_from_response(&resp);
+    let granted = registration
+        .contacts
+        .iter()
+        .map(|c| c.expires().map(|e| e.seconds().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(granted, vec![Some(600), Some(300)]);
+    assert_eq!(registration.expires(), 300);
+    Ok(())
+}
+
+#[test]
+fn test_response_retry_after() {
+    use std::time::Duration;
+
+    let mut resp = create_register_response("SIP/2.0/UDP 192.0.2.10:5060;branch=z9hG4bK-reg");
+    resp.status_code = StatusCode::ServiceUnavailable;
+    assert_eq!(resp.retry_after(), None);
+
+    resp.headers
+        .push(rsip::Header::Other("Retry-After".into(), "30".into()));
+    assert_eq!(resp.retry_after(), Some(Duration::from_secs(30)));
+
+    resp.headers
+        .retain(|h| !matches!(h, rsip::Header::Other(name, _) if name == "Retry-After"));
+    resp.headers.push(rsip::Header::Other(
+        "retry-after".into(),
+        "18000 (in maintenance);duration=3600".into(),
+    ));
+    assert_eq!(resp.retry_after(), Some(Duration::from_secs(18000)));
+}
+
+/// Answer the first `rejections` REGISTERs with 503 and `retry_after`, then
+/// behave like [`run_registrar`]
+async fn run_unavailable_registrar(
+    socket: tokio::net::UdpSocket,
+    rejections: usize,
+    retry_after: Option<&'static str>,
+    seen: tokio::sync::mpsc::UnboundedSender<(Option<String>, String)>,
+) -> crate::Result<()> {
+    use rsip::prelude::HasHeaders;
+    let mut buf = vec![0u8; 4096];
+    for _ in 0..rejections {
+        let (len, from) = socket.recv_from(&mut buf).await?;
+        let req: rsip::Request = rsip::SipMessage::try_from(&buf[..len])?.try_into()?;
+        let mut headers: Vec<rsip::Header> = req
+            .headers()
+            .iter()
+            .filter(|h| {
+                matches!(
+                    h,
+                    rsip::Header::Via(_)
+                        | rsip::Header::From(_)
+                        | rsip::Header::To(_)
+                        | rsip::Header::CallId(_)
+                        | rsip::Header::CSeq(_)
+                )
+            })
+            .cloned()
+            .collect();
+        if let Some(retry_after) = retry_after {
+            headers.push(rsip::Header::Other(
+                "Retry-After".into(),
+                retry_after.into(),
+            ));
+        }
+        headers.push(ContentLength::default().into());
+        let resp = Response {
+            status_code: StatusCode::ServiceUnavailable,
+            version: rsip::Version::V2,
+            headers: headers.into(),
+            body: vec![],
+        };
+        socket
+            .send_to(rsip::SipMessage::from(resp).to_string().as_bytes(), from)
+            .await?;
+    }
+    run_registrar(socket, 60, seen).await
+}
+
+async fn create_serving_endpoint(
+    token: &CancellationToken,
+) -> crate::Result<crate::transaction::endpoint::EndpointInnerRef> {
+    let tl = TransportLayer::new(token.child_token());
+    let conn = crate::transport::udp::UdpConnection::create_connection(
+        "127.0.0.1:0".parse()?,
+        None,
+        Some(token.child_token()),
+    )
+    .await?;
+    tl.add_transport(conn.into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(tl)
+        .with_cancel_token(token.child_token())
+        .build();
+    let endpoint_inner = endpoint.inner.clone();
+    tokio::spawn(async move { endpoint.serve().await });
+    Ok(endpoint_inner)
+}
+
+#[tokio::test]
+async fn test_registration_reports_retry_after() -> crate::Result<()> {
+    use std::time::Duration;
+
+    let token = CancellationToken::new();
+    let endpoint_inner = create_serving_endpoint(&token).await?;
+    let registrar = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let server = rsip::Uri::try_from(format!("sip:{}", registrar.local_addr()?).as_str())?;
+    let (seen_tx, _seen_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(run_unavailable_registrar(
+        registrar,
+        1,
+        Some("120 (upgrading);duration=600"),
+        seen_tx,
+    ));
+
+    let mut registration = Registration::new(endpoint_inner, None);
+    let resp = registration.register(server.clone(), Some(60)).await?;
+    assert_eq!(resp.status_code, StatusCode::ServiceUnavailable);
+    assert_eq!(registration.retry_after(), Some(Duration::from_secs(120)));
+
+    let resp = registration.register(server, Some(60)).await?;
+    assert_eq!(resp.status_code, StatusCode::OK);
+    assert_eq!(registration.retry_after(), None);
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_registration_serve_honors_retry_after() -> crate::Result<()> {
+    use crate::dialog::registration::RegistrationState;
+    use std::time::Duration;
+
+    let token = CancellationToken::new();
+    let endpoint_inner = create_serving_endpoint(&token).await?;
+    let registrar = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let server = rsip::Uri::try_from(format!("sip:{}", registrar.local_addr()?).as_str())?;
+    let (seen_tx, _seen_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(run_unavailable_registrar(registrar, 1, Some("1"), seen_tx));
+
+    let registration = Registration::new(endpoint_inner, None);
+    let serve_token = CancellationToken::new();
+    let (state_tx, mut state_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(registration.serve(server, Some(60), serve_token.clone(), Some(state_tx)));
+
+    let failed = tokio::time::timeout(Duration::from_secs(1), state_rx.recv())
+        .await
+        .expect("rejection not reported");
+    assert!(matches!(
+        failed,
+        Some(RegistrationState::Failed(
+            Some(StatusCode::ServiceUnavailable),
+            _
+        ))
+    ));
+    // the 5 second backoff would miss this deadline
+    let registered = tokio::time::timeout(Duration::from_secs(3), state_rx.recv())
+        .await
+        .expect("retry did not follow Retry-After");
+    assert_eq!(registered, Some(RegistrationState::Registered(60)));
+    serve_token.cancel();
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_registration_rewrite_contact_flag() -> crate::Result<()> {
+    let endpoint = create_test_endpoint().await?;
+    let mut registration = Registration::new(endpoint.inner.clone(), None);
+    let resp = create_register_response(
+        "SIP/2.0/UDP 192.168.1.100:5060;branch=z9hG4bK-reg;received=203.0.113.5;rport=40000",
+    );
+
+    registration.rewrite_contact = false;
+    registration.contact = Some(resp.contact_header()?.typed()?);
+    assert!(!registration.update_contact_from_response(&resp));
+    let contact = registration.contact.as_ref().expect("contact");
+    assert_eq!(contact.uri.host_with_port.to_string(), "192.168.1.100:5060");
+
+    registration.rewrite_contact = true;
+    assert!(registration.update_contact_from_response(&resp));
+    let contact = registration.contact.as_ref().expect("contact");
+    assert_eq!(contact.uri.host_with_port.to_string(), "203.0.113.5:40000");
+    Ok(())
+}
+
+/// Answer REGISTERs asking for less than `min_expires` with 423, and the
+/// others with a 200 that grants no expires at all
+async fn run_brief_registrar(
+    socket: tokio::net::UdpSocket,
+    min_expires: Option<u32>,
+    seen: tokio::sync::mpsc::UnboundedSender<(Option<String>, String)>,
+) -> crate::Result<()> {
+    use rsip::prelude::{HasHeaders, HeadersExt, UntypedHeader};
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let (len, from) = socket.recv_from(&mut buf).await?;
+        let req: rsip::Request = rsip::SipMessage::try_from(&buf[..len])?.try_into()?;
+        let expires = crate::rsip_ext::header_value_case_insensitive(&req.headers, "Expires");
+        let contact = req.contact_header()?.value().to_string();
+        seen.send((expires.clone(), contact.clone())).ok();
+        let mut headers: Vec<rsip::Header> = req
+            .headers()
+            .iter()
+            .filter(|h| {
+                matches!(
+                    h,
+                    rsip::Header::Via(_)
+                        | rsip::Header::From(_)
+                        | rsip::Header::CallId(_)
+                        | rsip::Header::CSeq(_)
+                )
+            })
+            .cloned()
+            .collect();
+        headers.push(To::new(format!("{};tag=registrar", req.to_header()?.value())).into());
+        let too_brief = match (min_expires, expires.and_then(|e| e.parse::<u32>().ok())) {
+            (Some(min), Some(requested)) => requested < min,
+            (Some(_), None) => true,
+            _ => false,
+        };
+        let status_code = if too_brief {
+            headers.push(rsip::Header::MinExpires(
+                min_expires.unwrap().to_string().into(),
+            ));
+            StatusCode::IntervalTooBrief
+        } else {
+            let contact = contact
+                .split(';')
+                .filter(|p| !p.trim().starts_with("expires="))
+                .collect::<Vec<_>>()
+                .join(";");
+            headers.push(Contact::new(contact).into());
+            StatusCode::OK
+        };
+        headers.push(ContentLength::default().into());
+        let resp = Response {
+            status_code,
+            version: rsip::Version::V2,
+            headers: headers.into(),
+            body: vec![],
+        };
+        socket
+            .send_to(rsip::SipMessage::from(resp).to_string().as_bytes(), from)
+            .await?;
+    }
+}
+
+#[tokio::test]
+async fn test_registration_retries_423_with_min_expires() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let endpoint_inner = create_serving_endpoint(&token).await?;
+    let registrar = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let server = rsip::Uri::try_from(format!("sip:{}", registrar.local_addr()?).as_str())?;
+    let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(run_brief_registrar(registrar, Some(3600), seen_tx));
+
+    let mut registration = Registration::new(endpoint_inner, None).with_expires(600);
+    assert_eq!(registration.expires(), 600);
+    let resp = registration.register(server, None).await?;
+    assert_eq!(resp.status_code, StatusCode::OK);
+
+    let (expires, contact) = seen_rx.try_recv().expect("REGISTER sent");
+    assert_eq!(expires.as_deref(), Some("600"));
+    assert!(contact.contains("expires=600"), "{}", contact);
+    let (expires, contact) = seen_rx.try_recv().expect("REGISTER retried");
+    assert_eq!(expires.as_deref(), Some("3600"));
+    assert!(
+        contact.contains("expires=3600") && !contact.contains("expires=600"),
+        "{}",
+        contact
+    );
+    // the registrar granted no expires, so the retried interval holds
+    assert_eq!(registration.expires(), 3600);
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_registration_expires_defaults_when_server_omits_it() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let endpoint_inner = create_serving_endpoint(&token).await?;
+    let default = endpoint_inner.option.default_register_expires;
+    let registrar = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let server = rsip::Uri::try_from(format!("sip:{}", registrar.local_addr()?).as_str())?;
+    let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(run_brief_registrar(registrar, None, seen_tx));
+
+    let mut registration = Registration::new(endpoint_inner, None);
+    let resp = registration.register(server, None).await?;
+    assert_eq!(resp.status_code, StatusCode::OK);
+    let (expires, contact) = seen_rx.try_recv().expect("REGISTER sent");
+    assert_eq!(expires, Some(default.to_string()));
+    assert!(!contact.contains("expires"), "{}", contact);
+    assert_eq!(registration.expires(), default);
+    token.cancel();
+    Ok(())
+}
+
+/// Read one request from a TCP stream, skipping keepalive CRLFs
+async fn read_stream_request(stream: &mut tokio::net::TcpStream) -> Option<rsip::Request> {
+    use tokio::io::AsyncReadExt;
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        while buf.starts_with(b"\r\n") {
+            buf.drain(..2);
+        }
+        if buf.windows(4).any(|w| w == b"\r\n\r\n") {
+            break;
+        }
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    rsip::SipMessage::try_from(buf.as_slice())
+        .ok()?
+        .try_into()
+        .ok()
+}
+
+#[tokio::test]
+async fn test_registration_serve_redials_closed_tcp_connection() -> crate::Result<()> {
+    use crate::dialog::registration::RegistrationState;
+    use rsip::prelude::{HasHeaders, HeadersExt, UntypedHeader};
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    let token = CancellationToken::new();
+    let endpoint_inner = create_serving_endpoint(&token).await?;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let server =
+        rsip::Uri::try_from(format!("sip:{};transport=tcp", listener.local_addr()?).as_str())?;
+
+    // answer REGISTERs, dropping the first connection after one answer
+    let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut connection = 0;
+        while let Ok((mut stream, _)) = listener.accept().await {
+            connection += 1;
+            while let Some(req) = read_stream_request(&mut stream).await {
+                seen_tx.send((connection, req.clone())).ok();
+                let mut headers: Vec<rsip::Header> = req
+                    .headers()
+                    .iter()
+                    .filter(|h| {
+                        matches!(
+                            h,
+                            rsip::Header::Via(_)
+                                | rsip::Header::From(_)
+                                | rsip::Header::CallId(_)
+                                | rsip::Header::CSeq(_)
+                        )
+                    })
+                    .cloned()
+                    .collect();
+                let to = req.to_header().unwrap().value().to_string();
+                headers.push(To::new(format!("{};tag=registrar", to)).into());
+                let contact = req.contact_header().unwrap().value().to_string();
+                headers.push(Contact::new(format!("{};expires=3600", contact)).into());
+                headers.push(ContentLength::default().into());
+                let resp = rsip::SipMessage::from(Response {
+                    status_code: StatusCode::OK,
+                    version: rsip::Version::V2,
+                    headers: headers.into(),
+                    body: vec![],
+                });
+                stream.write_all(resp.to_string().as_bytes()).await.ok();
+                if connection == 1 {
+                    break;
+                }
+            }
+        }
+    });
+
+    let registration = Registration::new(endpoint_inner, None);
+    let serve_token = CancellationToken::new();
+    let (state_tx, mut state_rx) = tokio::sync::mpsc::unbounded_channel();
+    let serving =
+        tokio::spawn(registration.serve(server, Some(3600), serve_token.clone(), Some(state_tx)));
+
+    let mut states = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while states.len() < 4 {
+            match state_rx.recv().await {
+                Some(state) => states.push(state),
+                None => break,
+            }
+        }
+    })
+    .await
+    .expect("registration was not restored");
+    assert_eq!(
+        states,
+        vec![
+            RegistrationState::Registered(3600),
+            RegistrationState::Reconnecting(1),
+            RegistrationState::Refreshing,
+            RegistrationState::Registered(3600),
+        ]
+    );
+
+    serve_token.cancel();
+    tokio::time::timeout(Duration::from_secs(2), serving)
+        .await
+        .expect("serve exits on cancel")
+        .expect("join")?;
+
+    let mut requests = Vec::new();
+    while let Ok(request) = seen_rx.try_recv() {
+        requests.push(request);
+    }
+    let connections = requests.iter().map(|(c, _)| *c).collect::<Vec<_>>();
+    assert_eq!(
+        connections,
+        vec![1, 2, 2],
+        "register, re-register, unregister"
+    );
+    // a fresh transaction on the new connection
+    let branch = |req: &rsip::Request| req.via_header().unwrap().value().to_string();
+    assert_ne!(branch(&requests[0].1), branch(&requests[1].1));
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_registration_with_destination_skips_dns() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let endpoint_inner = create_serving_endpoint(&token).await?;
+    let registrar = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let registrar_addr = registrar.local_addr()?;
+    let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(run_registrar(registrar, 60, seen_tx));
+
+    // the server name does not resolve, only the destination is used
+    let server = rsip::Uri::try_from("sip:registrar.invalid")?;
+    let mut registration =
+        Registration::new(endpoint_inner, None).with_destination(registrar_addr.into());
+    let resp = registration.register(server, None).await?;
+    assert_eq!(resp.status_code, StatusCode::OK);
+    assert!(
+        seen_rx.try_recv().is_ok(),
+        "REGISTER sent to the destination"
+    );
+    token.cancel();
+    Ok(())
+}
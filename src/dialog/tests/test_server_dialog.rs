@@ -5,13 +5,16 @@ use tokio::sync::mpsc::unbounded_channel;
 
 use crate::{
     dialog::{
-        dialog::DialogInner,
+        dialog::{DialogInner, DialogState, DialogStateReceiver},
         server_dialog::ServerInviteDialog,
         tests::test_dialog_states::{create_invite_request, create_test_endpoint},
         DialogId,
     },
-    transaction::{key::TransactionRole, transaction::TransactionEvent},
-    transport::SipAddr,
+    transaction::{
+        key::{TransactionKey, TransactionRole},
+        transaction::{Transaction, TransactionEvent},
+    },
+    transport::{udp::UdpConnection, SipAddr, SipConnection},
 };
 
 #[tokio::test]
@@ -159,3 +162,181 @@ async fn test_accept_with_public_contact_preserves_contact_header() -> crate::Re
 
     Ok(())
 }
+
+/// Build an in-dialog re-INVITE whose Via points back at `peer`
+fn create_reinvite_request(peer: std::net::SocketAddr, cseq: u32, body: &str) -> rsip::Request {
+    use rsip::headers::*;
+    rsip::Request {
+        method: rsip::Method::Invite,
+        uri: rsip::Uri::try_from("sip:bob@bob.example.com:5060").unwrap(),
+        headers: vec![
+            Via::new(format!(
+                "SIP/2.0/UDP {};branch=z9hG4bKreinvite{}",
+                peer, cseq
+            ))
+            .into(),
+            CSeq::new(format!("{} INVITE", cseq)).into(),
+            From::new("Alice <sip:alice@example.com>;tag=alice-tag-456").into(),
+            To::new("Bob <sip:bob@example.com>;tag=bob-tag-789").into(),
+            CallId::new("test-call-id-refresh").into(),
+            Contact::new("<sip:alice@alice.example.com:5060>").into(),
+            ContentType::new("application/sdp").into(),
+            MaxForwards::new("70").into(),
+        ]
+        .into(),
+        version: rsip::Version::V2,
+        body: body.as_bytes().to_vec(),
+    }
+}
+
+const REINVITE_OFFER: &str = "v=0\r\no=alice 2890844526 2890844526 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 49170 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\n";
+const REINVITE_ANSWER: &str = "v=0\r\no=bob 2890844730 2890844730 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 3456 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\n";
+
+/// Confirmed server dialog that answered `REINVITE_OFFER` with `REINVITE_ANSWER`
+async fn create_confirmed_server_dialog() -> crate::Result<(
+    crate::transaction::endpoint::Endpoint,
+    ServerInviteDialog,
+    DialogStateReceiver,
+)> {
+    let dialog_id = DialogId {
+        call_id: "test-call-id-refresh".to_string(),
+        from_tag: "alice-tag-456".to_string(),
+        to_tag: "bob-tag-789".to_string(),
+    };
+
+    let endpoint = create_test_endpoint().await?;
+    let (tu_sender, _tu_receiver) = unbounded_channel();
+    let (state_sender, mut state_receiver) = unbounded_channel();
+
+    let mut invite_req = create_invite_request("alice-tag-456", "", "test-call-id-refresh");
+    invite_req.body = REINVITE_OFFER.as_bytes().to_vec();
+    let dialog_inner = DialogInner::new(
+        TransactionRole::Server,
+        dialog_id.clone(),
+        invite_req,
+        endpoint.inner.clone(),
+        state_sender,
+        None,
+        None,
+        tu_sender,
+    )
+    .expect("Failed to create dialog inner");
+
+    let server_dialog = ServerInviteDialog {
+        inner: Arc::new(dialog_inner),
+    };
+    server_dialog.accept(None, Some(REINVITE_ANSWER.as_bytes().to_vec()))?;
+    server_dialog
+        .inner
+        .transition(DialogState::Confirmed(dialog_id, rsip::Response::default()))?;
+    while state_receiver.try_recv().is_ok() {}
+    Ok((endpoint, server_dialog, state_receiver))
+}
+
+/// Run a re-INVITE through the dialog, acknowledge it and return the response
+async fn exchange_reinvite(
+    endpoint: &crate::transaction::endpoint::Endpoint,
+    server_dialog: &mut ServerInviteDialog,
+    cseq: u32,
+    body: &str,
+) -> crate::Result<rsip::Response> {
+    let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let connection: SipConnection =
+        UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None)
+            .await?
+            .into();
+
+    let reinvite = create_reinvite_request(peer.local_addr()?, cseq, body);
+    let key = TransactionKey::from_request(&reinvite, TransactionRole::Server)?;
+    let mut tx = Transaction::new_server(
+        key,
+        reinvite.clone(),
+        endpoint.inner.clone(),
+        Some(connection),
+    );
+    let mut ack = reinvite.clone();
+    ack.method = rsip::Method::Ack;
+    ack.body.clear();
+    tx.tu_sender
+        .send(TransactionEvent::Received(ack.into(), None, None))
+        .expect("queue ack");
+
+    server_dialog.handle(&mut tx).await?;
+
+    let mut buf = vec![0u8; 4096];
+    let (len, _) = peer.recv_from(&mut buf).await?;
+    let resp_msg = std::str::from_utf8(&buf[..len]).unwrap();
+    let resp: rsip::Response = rsip::SipMessage::try_from(resp_msg)?.try_into()?;
+    Ok(resp)
+}
+
+#[tokio::test]
+async fn test_refresh_reinvite_auto_answered() -> crate::Result<()> {
+    let (endpoint, mut server_dialog, mut state_receiver) =
+        create_confirmed_server_dialog().await?;
+
+    // A session refresh only bumps the origin version: answered with the
+    // current SDP and never surfaced to the application
+    let refresh = REINVITE_OFFER.replace("2890844526 IN", "2890844527 IN");
+    let cases = [
+        (2, refresh, false),
+        (3, REINVITE_OFFER.replace("RTP/AVP 0", "RTP/AVP 8"), true),
+    ];
+    for (cseq, body, surfaced) in cases {
+        let resp = exchange_reinvite(&endpoint, &mut server_dialog, cseq, &body).await?;
+        assert_eq!(resp.status_code, rsip::StatusCode::OK);
+
+        let updated = matches!(state_receiver.try_recv(), Ok(DialogState::Updated(_, _)));
+        assert_eq!(updated, surfaced, "re-invite cseq {}", cseq);
+        if !surfaced {
+            assert_eq!(resp.body, REINVITE_ANSWER.as_bytes());
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reinvite_moving_connection_address_surfaces_media_target() -> crate::Result<()> {
+    let (endpoint, mut server_dialog, mut state_receiver) =
+        create_confirmed_server_dialog().await?;
+
+    let moved = REINVITE_OFFER.replace("c=IN IP4 127.0.0.1", "c=IN IP4 192.0.2.20");
+    exchange_reinvite(&endpoint, &mut server_dialog, 2, &moved).await?;
+
+    match state_receiver.try_recv() {
+        Ok(DialogState::MediaTarget(_, target)) => {
+            assert_eq!(target, "192.0.2.20:49170".parse()?);
+        }
+        Ok(other) => panic!("expected MediaTarget, got {}", other),
+        Err(_) => panic!("expected MediaTarget, got nothing"),
+    }
+    assert!(matches!(
+        state_receiver.try_recv(),
+        Ok(DialogState::Updated(_, _))
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reinvite_with_stale_cseq_is_rejected() -> crate::Result<()> {
+    let (endpoint, mut server_dialog, mut state_receiver) =
+        create_confirmed_server_dialog().await?;
+
+    let changed = REINVITE_OFFER.replace("RTP/AVP 0", "RTP/AVP 8");
+    let resp = exchange_reinvite(&endpoint, &mut server_dialog, 3, &changed).await?;
+    assert_eq!(resp.status_code, rsip::StatusCode::OK);
+    while state_receiver.try_recv().is_ok() {}
+
+    // a replayed re-INVITE older than the last one is not processed
+    let resp = exchange_reinvite(&endpoint, &mut server_dialog, 2, REINVITE_OFFER).await?;
+    assert_eq!(resp.status_code, rsip::StatusCode::ServerInternalError);
+    assert!(state_receiver.try_recv().is_err());
+    assert_eq!(
+        server_dialog
+            .inner
+            .remote_seq
+            .load(std::sync::atomic::Ordering::Relaxed),
+        3
+    );
+    Ok(())
+}
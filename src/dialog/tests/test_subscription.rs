@@ -0,0 +1,241 @@
+//! SUBSCRIBE/NOTIFY tests
+//!
+//! A subscriber and a scripted notifier talk over the loopback transport
+
+use super::{create_loopback_endpoint, loopback_addr};
+use crate::dialog::{
+    dialog::{DialogState, TerminatedReason},
+    dialog_layer::DialogLayer,
+    subscription::{SubscribeOption, SubscriptionState},
+};
+use crate::transaction::{
+    endpoint::{Endpoint, EndpointInnerRef},
+    key::{TransactionKey, TransactionRole},
+    transaction::Transaction,
+};
+use crate::transport::loopback::LoopbackConnection;
+use rsip::prelude::{HeadersExt, ToTypedHeader, UntypedHeader};
+use rsip::{Header, Method, Request, StatusCode, Uri};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+const NOTIFIER_TAG: &str = "notifier";
+
+/// Send a NOTIFY in the dialog created by `subscribe` and wait for its answer
+async fn send_notify(
+    endpoint: &EndpointInnerRef,
+    subscribe: &Request,
+    seq: u32,
+    state: &str,
+) -> crate::Result<Option<StatusCode>> {
+    let notifier = subscribe
+        .to_header()?
+        .typed()?
+        .with_tag(NOTIFIER_TAG.into());
+    let from = rsip::typed::From {
+        display_name: None,
+        uri: notifier.uri,
+        params: notifier.params,
+    };
+    let subscriber = subscribe.from_header()?.typed()?;
+    let to = rsip::typed::To {
+        display_name: None,
+        uri: subscriber.uri,
+        params: subscriber.params,
+    };
+    let target = crate::rsip_ext::extract_uri_from_contact(subscribe.contact_header()?.value())?;
+    let mut notify = endpoint.make_request(
+        Method::Notify,
+        target,
+        endpoint.get_via(None, None)?,
+        from,
+        to,
+        seq,
+        Some(subscribe.call_id_header()?.clone()),
+        None,
+    );
+    notify
+        .headers
+        .push(Header::Other("Event".into(), "presence".into()));
+    notify
+        .headers
+        .push(Header::Other("Subscription-State".into(), state.into()));
+    notify.headers.push(Header::Contact(
+        rsip::typed::Contact::from(Uri::try_from("sip:bob@127.0.0.1:5062")?).into(),
+    ));
+
+    let key = TransactionKey::from_request(&notify, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, notify, endpoint.clone(), None);
+    tx.send().await?;
+    while let Some(msg) = tx.receive().await {
+        if let rsip::SipMessage::Response(resp) = msg {
+            if resp.status_code.kind() != rsip::StatusCodeKind::Provisional {
+                return Ok(Some(resp.status_code));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Notifier that sends a pending NOTIFY before accepting the SUBSCRIBE,
+/// activates the subscription, and terminates it on the first refresh
+fn serve_notifier(endpoint: &Endpoint, seen: UnboundedSender<Request>) -> crate::Result<()> {
+    let mut incoming = endpoint.incoming_transactions()?;
+    let endpoint = endpoint.inner.clone();
+    tokio::spawn(async move {
+        let mut notify_seq = 0;
+        while let Some(mut tx) = incoming.recv().await {
+            let req = tx.original.clone();
+            seen.send(req.clone()).ok();
+            if req.method != Method::Subscribe {
+                tx.reply(StatusCode::MethodNotAllowed).await.ok();
+                continue;
+            }
+            let initial = req.to_header().unwrap().tag().unwrap().is_none();
+            if initial {
+                notify_seq += 1;
+                let status = send_notify(&endpoint, &req, notify_seq, "pending;expires=1")
+                    .await
+                    .unwrap();
+                assert_eq!(status, Some(StatusCode::OK));
+            }
+
+            let mut resp = endpoint.make_response(&req, StatusCode::Accepted, None);
+            let to = req.to_header().unwrap().typed().unwrap();
+            resp.headers.retain(|h| !matches!(h, Header::To(_)));
+            resp.headers
+                .push(Header::To(to.with_tag(NOTIFIER_TAG.into()).into()));
+            resp.headers.push(rsip::headers::Expires::from(1u32).into());
+            resp.headers.push(Header::Contact(
+                rsip::typed::Contact::from(Uri::try_from("sip:bob@127.0.0.1:5062").unwrap()).into(),
+            ));
+            tx.respond(resp).await.ok();
+
+            notify_seq += 1;
+            let state = if initial {
+                "active;expires=1"
+            } else {
+                "terminated;reason=noresource"
+            };
+            send_notify(&endpoint, &req, notify_seq, state).await.ok();
+        }
+    });
+    Ok(())
+}
+
+/// Hand every incoming request to its dialog
+fn serve_subscriber(endpoint: &Endpoint, dialog_layer: Arc<DialogLayer>) -> crate::Result<()> {
+    let mut incoming = endpoint.incoming_transactions()?;
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            match dialog_layer.match_dialog(&tx.original) {
+                Some(mut dialog) => {
+                    dialog.handle(&mut tx).await.ok();
+                }
+                None => {
+                    tx.reply(StatusCode::CallTransactionDoesNotExist).await.ok();
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subscribe_notify_pending_active_terminated() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let (subscriber_conn, notifier_conn) =
+        LoopbackConnection::pair(loopback_addr(5060), loopback_addr(5062), None);
+    let notifier_addr = notifier_conn.get_addr().clone();
+    let subscriber = create_loopback_endpoint(subscriber_conn, &token);
+    let notifier = create_loopback_endpoint(notifier_conn, &token);
+
+    let (seen_sender, mut seen) = unbounded_channel();
+    serve_notifier(&notifier, seen_sender)?;
+    let dialog_layer = Arc::new(DialogLayer::new(subscriber.inner.clone()));
+    serve_subscriber(&subscriber, dialog_layer.clone())?;
+
+    let opt = SubscribeOption {
+        subscriber: Uri::try_from("sip:alice@example.com")?,
+        target: Uri::try_from(format!("sip:bob@{}", notifier_addr.addr).as_str())?,
+        event: "presence".to_string(),
+        expires: Some(60),
+        contact: Uri::try_from("sip:alice@127.0.0.1:5060")?,
+        ..Default::default()
+    };
+    let (state_sender, mut state_receiver) = unbounded_channel();
+    let (dialog, resp) = dialog_layer.do_subscribe(opt, state_sender).await?;
+    assert_eq!(resp.map(|r| r.status_code), Some(StatusCode::Accepted));
+    assert_eq!(dialog.id().to_tag, NOTIFIER_TAG);
+    assert_eq!(dialog.expires(), 1, "the notifier grants 1 second");
+    assert!(dialog_layer.get_dialog(&dialog.id()).is_some());
+
+    let mut states = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(state) = state_receiver.recv().await {
+            let terminated = state.is_terminated();
+            states.push(state);
+            if terminated {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("subscription was not terminated");
+
+    assert!(matches!(states[0], DialogState::Pending(_, _)));
+    assert!(matches!(states[1], DialogState::Active(_, _)));
+    match states.last() {
+        Some(DialogState::Terminated(_, TerminatedReason::SubscriptionTerminated(reason))) => {
+            assert_eq!(reason.as_deref(), Some("noresource"));
+        }
+        _ => panic!("unexpected final state"),
+    }
+
+    let mut subscribes = Vec::new();
+    while let Ok(req) = seen.try_recv() {
+        subscribes.push(req);
+    }
+    assert_eq!(subscribes.len(), 2, "initial SUBSCRIBE and one refresh");
+    let refresh = &subscribes[1];
+    assert_eq!(
+        refresh.to_header()?.tag()?.map(|t| t.value().to_string()),
+        Some(NOTIFIER_TAG.to_string())
+    );
+    assert_eq!(
+        crate::rsip_ext::header_value_case_insensitive(&refresh.headers, "Event").as_deref(),
+        Some("presence")
+    );
+    token.cancel();
+    Ok(())
+}
+
+#[test]
+fn test_parse_subscription_state() {
+    let state = |value: &str| {
+        let headers: rsip::Headers =
+            vec![Header::Other("Subscription-State".into(), value.into())].into();
+        SubscriptionState::from_headers(&headers)
+    };
+    assert_eq!(
+        state("active;expires=600"),
+        Some(SubscriptionState::Active { expires: Some(600) })
+    );
+    assert_eq!(
+        state("Terminated; reason=timeout; retry-after=30"),
+        Some(SubscriptionState::Terminated {
+            reason: Some("timeout".to_string()),
+            retry_after: Some(30),
+        })
+    );
+    assert_eq!(
+        state("waiting"),
+        Some(SubscriptionState::Pending { expires: None })
+    );
+    assert_eq!(
+        SubscriptionState::from_headers(&rsip::Headers::default()),
+        None
+    );
+}
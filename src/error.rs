@@ -21,6 +21,9 @@ pub enum Error {
     #[error("Dialog error:{2}({0})")]
     DialogError(String, DialogId, rsip::StatusCode),
 
+    #[error("Too many hops: Max-Forwards reached 0")]
+    TooManyHops,
+
     #[error("I/O error: {0}")]
     IoError(#[from] std::io::Error),
 
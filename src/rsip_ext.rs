@@ -21,8 +21,12 @@ use std::str::FromStr;
 pub trait RsipResponseExt {
     fn reason_phrase(&self) -> Option<&str>;
     fn via_received(&self) -> Option<rsip::HostWithPort>;
+    fn via_received_rport(&self) -> Option<(Option<rsip::Host>, Option<u16>)>;
     fn content_type(&self) -> Option<rsip::headers::ContentType>;
     fn remote_uri(&self, destination: Option<&SipAddr>) -> Result<rsip::Uri>;
+    fn retry_after(&self) -> Option<std::time::Duration>;
+    /// The `Warning` headers of the response, in order
+    fn warnings(&self) -> Vec<Warning>;
 }
 
 impl RsipResponseExt for rsip::Response {
@@ -50,6 +54,34 @@ impl RsipResponseExt for rsip::Response {
             .map(|(_, host_with_port)| host_with_port)
             .ok()
     }
+    /// Extract the `received` and `rport` values from the top Via header
+    ///
+    /// Unlike `via_received`, this returns `None` when the server added
+    /// neither parameter, so callers can tell a NAT mapping from the address
+    /// they sent themselves.
+    fn via_received_rport(&self) -> Option<(Option<rsip::Host>, Option<u16>)> {
+        let via = self.via_header().ok()?.typed().ok()?;
+        let mut received = None;
+        let mut rport = None;
+        for param in via.params.iter() {
+            match param {
+                rsip::Param::Received(v) => {
+                    received = v.parse().ok().map(rsip::Host::IpAddr);
+                }
+                rsip::Param::Other(key, Some(value))
+                    if key.value().eq_ignore_ascii_case("rport") =>
+                {
+                    rport = value.value().parse::<u16>().ok();
+                }
+                _ => {}
+            }
+        }
+        if received.is_none() && rport.is_none() {
+            return None;
+        }
+        Some((received, rport))
+    }
+
     fn content_type(&self) -> Option<rsip::headers::ContentType> {
         let headers = self.headers();
         for header in headers.iter() {
@@ -88,6 +120,29 @@ impl RsipResponseExt for rsip::Response {
         }
         Ok(contact_uri)
     }
+
+    /// Delay from the `Retry-After` header (RFC 3261 §20.33)
+    ///
+    /// Only the leading delta-seconds count; a comment or parameters such as
+    /// `;duration=` after them are ignored.
+    fn retry_after(&self) -> Option<std::time::Duration> {
+        let value = header_value_case_insensitive(self.headers(), "Retry-After")?;
+        let value = value.trim_start();
+        let end = value
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(value.len());
+        value[..end]
+            .parse::<u64>()
+            .ok()
+            .map(std::time::Duration::from_secs)
+    }
+
+    fn warnings(&self) -> Vec<Warning> {
+        header_values_case_insensitive(self.headers(), "Warning")
+            .iter()
+            .flat_map(|value| Warning::parse_list(value))
+            .collect()
+    }
 }
 
 pub trait RsipHeadersExt {
@@ -160,6 +215,77 @@ fn apply_tokenizer_params(uri: &mut rsip::Uri, tokenizer: &CustomContactTokenize
     }
 }
 
+/// Rewrite bracketed Contact headers into the bare `sip:user@host` form
+///
+/// Some legacy SBCs reject `Contact: <sip:...>`. Only Contacts without
+/// display name, URI parameters or header parameters are rewritten, since the
+/// brackets are required to tell those apart (RFC 3261 §20.10).
+pub fn contact_without_brackets(headers: &mut rsip::Headers) {
+    let rewritten = headers
+        .iter()
+        .map(|header| match header {
+            rsip::Header::Contact(contact) => {
+                let bare = contact
+                    .value()
+                    .trim()
+                    .strip_prefix('<')
+                    .and_then(|v| v.strip_suffix('>'))
+                    .filter(|uri| !uri.contains([';', '?', ',', '<', '>']));
+                match bare {
+                    Some(uri) => rsip::Header::Contact(uri.into()),
+                    None => header.clone(),
+                }
+            }
+            _ => header.clone(),
+        })
+        .collect::<Vec<_>>();
+    *headers = rewritten.into();
+}
+
+/// Compact form of a header name (RFC 3261 §7.3.3, RFC 3265, RFC 3515,
+/// RFC 3892, RFC 4028), e.g. `v` for `Via`
+pub fn compact_header_name(name: &str) -> Option<&'static str> {
+    let compact = match name.to_ascii_lowercase().as_str() {
+        "call-id" => "i",
+        "contact" => "m",
+        "content-encoding" => "e",
+        "content-length" => "l",
+        "content-type" => "c",
+        "from" => "f",
+        "subject" => "s",
+        "supported" => "k",
+        "to" => "t",
+        "via" => "v",
+        "event" => "o",
+        "allow-events" => "u",
+        "refer-to" => "r",
+        "referred-by" => "b",
+        "session-expires" => "x",
+        _ => return None,
+    };
+    Some(compact)
+}
+
+/// Rewrite every header that has a compact form into it
+///
+/// For serialization only: the rewritten headers are `Header::Other` and
+/// no longer match typed accessors such as `via_header()`.
+pub fn compact_headers(headers: &mut rsip::Headers) {
+    let rewritten = headers
+        .iter()
+        .map(|header| {
+            let raw = header.to_string();
+            match split_header_line(&raw)
+                .and_then(|(name, value)| compact_header_name(name).map(|c| (c, value)))
+            {
+                Some((compact, value)) => rsip::Header::Other(compact.into(), value.into()),
+                None => header.clone(),
+            }
+        })
+        .collect::<Vec<_>>();
+    *headers = rewritten.into();
+}
+
 pub fn destination_from_request(request: &rsip::Request) -> Option<Cow<'_, rsip::Uri>> {
     request
         .headers
@@ -191,19 +317,80 @@ pub fn header_value_case_insensitive(headers: &rsip::Headers, name: &str) -> Opt
     })
 }
 
+/// Values of every header named `name`, in message order
+pub fn header_values_case_insensitive(headers: &rsip::Headers, name: &str) -> Vec<String> {
+    headers
+        .iter()
+        .filter_map(|header| {
+            let raw = header.to_string();
+            let (header_name, header_value) = split_header_line(&raw)?;
+            if header_name.eq_ignore_ascii_case(name) {
+                Some(header_value.to_string())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
 pub fn header_tokens_case_insensitive(headers: &rsip::Headers, name: &str) -> Vec<String> {
     header_value_case_insensitive(headers, name)
         .map(|value| {
-            value
-                .split(',')
-                .map(|token| token.trim())
-                .filter(|token| !token.is_empty())
-                .map(|token| token.to_string())
+            split_header_list(&value)
+                .into_iter()
+                .map(str::to_string)
                 .collect::<Vec<_>>()
         })
         .unwrap_or_default()
 }
 
+/// Split a header value listing several elements on `,`
+///
+/// Commas inside quoted strings (honouring `\` escapes) and inside `<...>`
+/// are kept. Elements are trimmed and empty ones dropped.
+///
+/// # Examples
+///
+/// ```rust
+/// use rsipstack::rsip_ext::split_header_list;
+///
+/// assert_eq!(
+///     split_header_list(r#""Bob, \"B\"" <sip:bob@a.com;x=1,2>;q=1 , <sip:b@c.com>,"#),
+///     vec![r#""Bob, \"B\"" <sip:bob@a.com;x=1,2>;q=1"#, "<sip:b@c.com>"]
+/// );
+/// ```
+pub fn split_header_list(value: &str) -> Vec<&str> {
+    split_unquoted(value, ',')
+}
+
+/// Split the `;` separated parameters of one header element, with the same
+/// quoting rules as [`split_header_list`]
+pub fn split_header_params(value: &str) -> Vec<&str> {
+    split_unquoted(value, ';')
+}
+
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut in_quotes, mut in_brackets, mut escaped) = (0, false, false, false);
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '<' if !in_quotes => in_brackets = true,
+            '>' if !in_quotes => in_brackets = false,
+            c if c == separator && !in_quotes && !in_brackets => {
+                parts.push(value[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(value[start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
 pub fn header_contains_token(headers: &rsip::Headers, name: &str, token: &str) -> bool {
     header_tokens_case_insensitive(headers, name)
         .into_iter()
@@ -226,6 +413,180 @@ pub fn parse_rack_header(headers: &rsip::Headers) -> Option<(u32, u32, Method)>
     Some((rseq, cseq, method))
 }
 
+/// Why a call is being torn down (RFC 3326)
+///
+/// Carried on CANCEL and BYE requests and on the 487 and 200 responses
+/// they trigger.
+///
+/// # Examples
+///
+/// ```rust
+/// use rsipstack::rsip_ext::Reason;
+///
+/// let reason = Reason::sip(200).with_text("Call completed elsewhere");
+/// assert_eq!(reason.to_string(), "SIP;cause=200;text=\"Call completed elsewhere\"");
+/// assert_eq!(Reason::parse("Q.850 ; cause=16"), Some(Reason::q850(16)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reason {
+    /// Protocol of the cause, `SIP` or `Q.850`
+    pub protocol: String,
+    pub cause: u16,
+    pub text: Option<String>,
+}
+
+impl Reason {
+    /// A SIP status code as the cause
+    pub fn sip(cause: u16) -> Self {
+        Self {
+            protocol: "SIP".to_string(),
+            cause,
+            text: None,
+        }
+    }
+
+    /// An ISDN (Q.850) cause value, as carriers report them
+    pub fn q850(cause: u16) -> Self {
+        Self {
+            protocol: "Q.850".to_string(),
+            cause,
+            text: None,
+        }
+    }
+
+    pub fn with_text(mut self, text: impl Into<String>) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    /// Parse a `Reason` header value
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut items = value.split(';').map(str::trim);
+        let protocol = items.next().filter(|p| !p.is_empty())?.to_string();
+        let mut cause = None;
+        let mut text = None;
+        for item in items {
+            let Some((name, value)) = item.split_once('=') else {
+                continue;
+            };
+            match name.trim().to_ascii_lowercase().as_str() {
+                "cause" => cause = value.trim().parse().ok(),
+                "text" => text = Some(value.trim().trim_matches('"').to_string()),
+                _ => {}
+            }
+        }
+        Some(Self {
+            protocol,
+            cause: cause?,
+            text,
+        })
+    }
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{};cause={}", self.protocol, self.cause)?;
+        if let Some(text) = &self.text {
+            write!(f, ";text=\"{}\"", text.replace('"', "'"))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Reason> for rsip::Header {
+    fn from(reason: Reason) -> Self {
+        rsip::Header::Other("Reason".into(), reason.to_string())
+    }
+}
+
+/// The `Reason` headers of a message, to repeat on the response it triggers
+pub fn reason_headers(headers: &rsip::Headers) -> Vec<rsip::Header> {
+    headers
+        .iter()
+        .filter(|header| {
+            split_header_line(&header.to_string())
+                .map(|(name, _)| name.eq_ignore_ascii_case("Reason"))
+                .unwrap_or(false)
+        })
+        .cloned()
+        .collect()
+}
+
+/// A `Warning` header value (RFC 3261 §20.43)
+///
+/// Tells the caller why a request failed beyond its status code, such as
+/// the `305 Incompatible media format` of a 488 rejecting an offer. Attach
+/// one to a response with `reply_with`, read them from a response with
+/// [`RsipResponseExt::warnings`].
+///
+/// # Examples
+///
+/// ```rust
+/// use rsipstack::rsip_ext::Warning;
+///
+/// let warning = Warning::new(305, "pbx.example.com", "Incompatible media format");
+/// assert_eq!(warning.to_string(), "305 pbx.example.com \"Incompatible media format\"");
+/// assert_eq!(
+///     Warning::parse_list("305 pbx.example.com \"Incompatible media format\", 399 - \"a, b\""),
+///     vec![warning, Warning::new(399, "-", "a, b")]
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// Three-digit warn-code, e.g. 305 or 399
+    pub code: u16,
+    /// Host or pseudonym of the agent adding the warning
+    pub agent: String,
+    pub text: String,
+}
+
+impl Warning {
+    pub fn new(code: u16, agent: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            code,
+            agent: agent.into(),
+            text: text.into(),
+        }
+    }
+
+    /// Parse one `code agent "text"` value
+    pub fn parse(value: &str) -> Option<Self> {
+        let (code, rest) = value.trim().split_once(char::is_whitespace)?;
+        let code = code
+            .parse::<u16>()
+            .ok()
+            .filter(|c| (100..1000).contains(c))?;
+        let (agent, text) = rest.trim_start().split_once(char::is_whitespace)?;
+        let text = text.trim().strip_prefix('"')?.strip_suffix('"')?;
+        Some(Self {
+            code,
+            agent: agent.to_string(),
+            text: text.replace("\\\"", "\"").replace("\\\\", "\\"),
+        })
+    }
+
+    /// Parse a header value listing several warnings, skipping invalid ones
+    pub fn parse_list(value: &str) -> Vec<Self> {
+        split_header_list(value)
+            .into_iter()
+            .filter_map(Self::parse)
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = self.text.replace('\\', "\\\\").replace('"', "\\\"");
+        write!(f, "{} {} \"{}\"", self.code, self.agent, text)
+    }
+}
+
+impl From<Warning> for rsip::Header {
+    fn from(warning: Warning) -> Self {
+        rsip::Header::Warning(warning.to_string().into())
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct CustomContactTokenizer<'a> {
     uri: &'a str,
@@ -351,3 +712,35 @@ fn test_rsip_headers_ext() {
         ]
     );
 }
+
+#[test]
+fn test_contact_without_brackets() {
+    use rsip::{Header, Headers};
+    let contact = rsip::typed::Contact {
+        display_name: None,
+        uri: rsip::Uri::try_from("sip:alice@192.168.1.100:5060").unwrap(),
+        params: vec![],
+    };
+    let mut headers: Headers = vec![Header::Contact(contact.clone().into())].into();
+    assert_eq!(
+        headers.to_string().trim(),
+        "Contact: <sip:alice@192.168.1.100:5060>"
+    );
+
+    contact_without_brackets(&mut headers);
+    assert_eq!(
+        headers.to_string().trim(),
+        "Contact: sip:alice@192.168.1.100:5060"
+    );
+
+    // params would change meaning without brackets, keep them
+    let mut headers: Headers = vec![Header::Contact(
+        "<sip:alice@192.168.1.100:5060;transport=tcp>;expires=60".into(),
+    )]
+    .into();
+    contact_without_brackets(&mut headers);
+    assert_eq!(
+        headers.to_string().trim(),
+        "Contact: <sip:alice@192.168.1.100:5060;transport=tcp>;expires=60"
+    );
+}
@@ -0,0 +1,214 @@
+use crate::rsip_ext::header_tokens_case_insensitive;
+use crate::{Error, Result};
+use flate2::{
+    read::{GzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use rsip::{headers::ContentLength, message::HasHeaders, Header, SipMessage, StatusCode};
+use std::io::{Read, Write};
+
+/// Default cap for a decompressed message body (1 MiB)
+pub const MAX_DECOMPRESSED_BODY_SIZE: usize = 1024 * 1024;
+
+/// Body encodings supported in `Content-Encoding`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Gzip,
+    Deflate,
+}
+
+impl ContentEncoding {
+    pub fn from_token(token: &str) -> Option<Self> {
+        let token = token.split(';').next().unwrap_or_default().trim();
+        if token.eq_ignore_ascii_case("gzip") || token.eq_ignore_ascii_case("x-gzip") {
+            Some(ContentEncoding::Gzip)
+        } else if token.eq_ignore_ascii_case("deflate") {
+            Some(ContentEncoding::Deflate)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
+}
+
+impl std::fmt::Display for ContentEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Pick the encoding to use toward a peer from its `Accept-Encoding` header
+///
+/// gzip is preferred when both are advertised.
+pub fn negotiate_encoding(headers: &rsip::Headers) -> Option<ContentEncoding> {
+    let accepted = header_tokens_case_insensitive(headers, "Accept-Encoding")
+        .iter()
+        .filter_map(|token| ContentEncoding::from_token(token))
+        .collect::<Vec<_>>();
+    if accepted.contains(&ContentEncoding::Gzip) {
+        Some(ContentEncoding::Gzip)
+    } else {
+        accepted.first().copied()
+    }
+}
+
+pub fn compress_body(body: &[u8], encoding: ContentEncoding) -> Result<Vec<u8>> {
+    let compressed = match encoding {
+        ContentEncoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()?
+        }
+        ContentEncoding::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()?
+        }
+    };
+    Ok(compressed)
+}
+
+/// Why [`decode_message_body`] could not decode a body
+#[derive(Debug)]
+pub enum BodyDecodeError {
+    /// `Content-Encoding` names an encoding other than gzip or deflate
+    UnsupportedEncoding(String),
+    /// The body decompresses to more than the allowed size
+    TooLarge(usize),
+    /// The body is not valid for its encoding
+    Corrupt(std::io::Error),
+}
+
+impl BodyDecodeError {
+    /// Response to a request whose body could not be decoded (RFC 3261
+    /// §8.2.3); a 415 also carries an `Accept-Encoding` header
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            BodyDecodeError::UnsupportedEncoding(_) => StatusCode::UnsupportedMediaType,
+            BodyDecodeError::TooLarge(_) => StatusCode::RequestEntityTooLarge,
+            BodyDecodeError::Corrupt(_) => StatusCode::BadRequest,
+        }
+    }
+}
+
+impl std::fmt::Display for BodyDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BodyDecodeError::UnsupportedEncoding(token) => {
+                write!(f, "unsupported content encoding {}", token)
+            }
+            BodyDecodeError::TooLarge(max_size) => {
+                write!(f, "decompressed body exceeds {} bytes", max_size)
+            }
+            BodyDecodeError::Corrupt(e) => write!(f, "corrupt compressed body: {}", e),
+        }
+    }
+}
+
+impl From<BodyDecodeError> for Error {
+    fn from(e: BodyDecodeError) -> Self {
+        Error::Error(e.to_string())
+    }
+}
+
+/// Decompress a body, failing when the output would exceed `max_size`
+///
+/// The limit guards against decompression bombs: a few kilobytes on the wire
+/// can expand to gigabytes.
+pub fn decompress_body(body: &[u8], encoding: ContentEncoding, max_size: usize) -> Result<Vec<u8>> {
+    Ok(inflate(body, encoding, max_size)?)
+}
+
+fn inflate(
+    body: &[u8],
+    encoding: ContentEncoding,
+    max_size: usize,
+) -> std::result::Result<Vec<u8>, BodyDecodeError> {
+    let reader: Box<dyn Read + '_> = match encoding {
+        ContentEncoding::Gzip => Box::new(GzDecoder::new(body)),
+        ContentEncoding::Deflate => Box::new(ZlibDecoder::new(body)),
+    };
+    let mut decompressed = Vec::new();
+    reader
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(BodyDecodeError::Corrupt)?;
+    if decompressed.len() > max_size {
+        return Err(BodyDecodeError::TooLarge(max_size));
+    }
+    Ok(decompressed)
+}
+
+fn is_content_encoding(header: &Header) -> bool {
+    match header {
+        Header::ContentEncoding(_) => true,
+        Header::Other(name, _) => {
+            name.eq_ignore_ascii_case("content-encoding") || name.eq_ignore_ascii_case("e")
+        }
+        _ => false,
+    }
+}
+
+fn message_body(msg: &SipMessage) -> &Vec<u8> {
+    match msg {
+        SipMessage::Request(req) => &req.body,
+        SipMessage::Response(resp) => &resp.body,
+    }
+}
+
+fn set_message_body(msg: &mut SipMessage, body: Vec<u8>) {
+    let content_length = Header::ContentLength(ContentLength::from(body.len() as u32));
+    msg.headers_mut().unique_push(content_length);
+    match msg {
+        SipMessage::Request(req) => req.body = body,
+        SipMessage::Response(resp) => resp.body = body,
+    }
+}
+
+/// Compress the body of `msg` and set `Content-Encoding`
+///
+/// Messages without a body or already carrying a `Content-Encoding` are left
+/// untouched.
+pub fn encode_message_body(msg: &mut SipMessage, encoding: ContentEncoding) -> Result<()> {
+    if message_body(msg).is_empty() || msg.headers().iter().any(is_content_encoding) {
+        return Ok(());
+    }
+    let compressed = compress_body(message_body(msg), encoding)?;
+    msg.headers_mut()
+        .push(Header::ContentEncoding(encoding.as_str().into()));
+    set_message_body(msg, compressed);
+    Ok(())
+}
+
+/// Replace a gzip/deflate encoded body with its decompressed form
+///
+/// `Content-Encoding` is removed and `Content-Length` updated, so the
+/// application only ever sees the plain body. Any other encoding but
+/// `identity` is an error, so an encoded body never reaches the TU.
+pub fn decode_message_body(
+    msg: &mut SipMessage,
+    max_size: usize,
+) -> std::result::Result<(), BodyDecodeError> {
+    let tokens = header_tokens_case_insensitive(msg.headers(), "Content-Encoding");
+    let Some(token) = tokens.first() else {
+        return Ok(());
+    };
+    let encoding = match ContentEncoding::from_token(token) {
+        Some(encoding) => encoding,
+        None if token.eq_ignore_ascii_case("identity") => return Ok(()),
+        None => return Err(BodyDecodeError::UnsupportedEncoding(token.to_string())),
+    };
+    if !message_body(msg).is_empty() {
+        let decompressed = inflate(message_body(msg), encoding, max_size)?;
+        set_message_body(msg, decompressed);
+    }
+    msg.headers_mut().retain(|h| !is_content_encoding(h));
+    Ok(())
+}
@@ -1,34 +1,65 @@
 use super::{
+    compression::{decode_message_body, MAX_DECOMPRESSED_BODY_SIZE},
     key::TransactionKey,
+    load_control::{overload_via, reduction_for_load, LoadControl, LoadSignal},
     make_via_branch,
+    pager::MessageHandler,
+    route_set::{consume_local_routes, RouteSet},
     timer::Timer,
     transaction::{Transaction, TransactionEvent, TransactionEventSender},
-    SipConnection, TransactionReceiver, TransactionSender, TransactionTimer,
+    via_branch, SipConnection, TransactionReceiver, TransactionSender, TransactionState,
+    TransactionTimer, TransactionType,
 };
 use crate::{
-    dialog::DialogId,
-    transport::{SipAddr, TransportEvent, TransportLayer},
+    dialog::{sdp::Sdp, DialogId},
+    rsip_ext::{compact_headers, split_header_list},
+    transport::{
+        capture::{CaptureDirection, CaptureSink, CapturedMessage},
+        SipAddr, TransportEvent, TransportLayer,
+    },
     Error, Result, VERSION,
 };
 use async_trait::async_trait;
-use rsip::{prelude::HeadersExt, SipMessage};
+use rsip::{
+    prelude::{HeadersExt, UntypedHeader},
+    SipMessage,
+};
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex, RwLock},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
     time::Duration,
 };
 use tokio::{
     select,
-    sync::mpsc::{error, unbounded_channel},
+    sync::{
+        broadcast,
+        mpsc::{error, unbounded_channel, Sender, UnboundedReceiver, UnboundedSender},
+    },
 };
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, trace, warn};
 
+const CLOSED_CONNECTIONS_CAPACITY: usize = 64;
+
 pub trait MessageInspector: Send + Sync {
     fn before_send(&self, msg: SipMessage) -> SipMessage;
     fn after_received(&self, msg: SipMessage) -> SipMessage;
 }
 
+/// Rewrites the SDP of responses before they are sent
+///
+/// Used by B2BUAs anchoring media, which point the `c=` and `m=` lines of an
+/// answer relayed to the other leg at their own relay. `Content-Length` is
+/// updated for the new body.
+pub trait SdpRewriter: Send + Sync {
+    /// The SDP to send instead of `sdp`, or `None` to leave the body as-is
+    fn rewrite(&self, response: &rsip::Response, sdp: Sdp) -> Option<Sdp>;
+}
+
 #[async_trait]
 pub trait TargetLocator: Send + Sync {
     async fn locate(&self, uri: &rsip::Uri) -> Result<SipAddr>;
@@ -39,22 +70,112 @@ pub trait TransportEventInspector: Send + Sync {
     async fn handle(&self, event: TransportEvent) -> Option<TransportEvent>;
 }
 
+/// Receives the server transaction of each new incoming request
+///
+/// Set with `EndpointBuilder::with_handler`; the serve loop then hands
+/// requests to it instead of `incoming_transactions`. ACKs go to their
+/// INVITE transaction. Once a
+/// [`DialogLayer`](crate::dialog::dialog_layer::DialogLayer) opts in with
+/// `route_in_dialog_requests`, in-dialog requests go to their dialog, so the
+/// handler only sees requests that start something new, such as an INVITE
+/// to answer with `get_or_create_server_invite`, and an in-dialog request
+/// no dialog matches is answered with `481`.
+#[async_trait]
+pub trait IncomingRequestHandler: Send + Sync {
+    async fn on_request(&self, tx: Transaction);
+}
+
+/// Hands in-dialog requests to their dialog, returning the transaction
+/// when none matches
+pub(crate) trait DialogRouter: Send + Sync {
+    fn route(&self, endpoint: &EndpointInnerRef, tx: Transaction) -> Option<Transaction>;
+    /// Whether an in-dialog request belongs to a known dialog
+    fn knows(&self, endpoint: &EndpointInnerRef, req: &rsip::Request) -> bool;
+}
+
 pub struct EndpointOption {
     pub t1: Duration,
+    /// Upper bound of the Timer E/G retransmission interval
+    pub t2: Duration,
     pub t4: Duration,
     pub t1x64: Duration,
     pub timerc: Duration,
     pub callid_suffix: Option<String>,
+    /// Compress response bodies at least this large when the request
+    /// advertised gzip/deflate in `Accept-Encoding`. `None` disables it.
+    pub compress_body_threshold: Option<usize>,
+    /// Upper bound for a body after `Content-Encoding` decompression; a
+    /// request over it is answered with 413
+    pub max_decompressed_body_size: usize,
+    /// Requests carrying more Via entries than this, counting each via-parm
+    /// of a comma-joined Via, are rejected with `483 Too Many Hops`,
+    /// regardless of Max-Forwards. `None` disables it.
+    pub max_via_headers: Option<usize>,
+    /// Interop mode for legacy servers: send `Contact: sip:...` instead of
+    /// `Contact: <sip:...>` when the Contact has no parameters.
+    pub contact_without_brackets: bool,
+    /// Preloaded route set (outbound proxy, Service-Route) added to every
+    /// out-of-dialog request built by the endpoint
+    pub route_set: RouteSet,
+    /// New out-of-dialog requests are rejected with `503 Service Unavailable`
+    /// while the load is above this. The load is the number of running
+    /// transactions unless a [`LoadSignal`] is set. `None` disables it.
+    pub overload_threshold: Option<usize>,
+    /// `Retry-After` seconds sent with overload rejections
+    pub overload_retry_after: u32,
+    /// Spread Timer A/E/G retransmissions randomly by up to this percentage
+    /// of their interval, so transactions started together don't retransmit
+    /// in lockstep. `None` disables it.
+    pub retransmission_jitter: Option<u32>,
+    /// Expires requested by a REGISTER when neither the caller nor the
+    /// Contact gives one, and assumed when the registrar grants none
+    pub default_register_expires: u32,
+    /// `Expires` sent with an INVITE whose `InviteOption` sets none.
+    /// `None` sends no `Expires`.
+    pub default_invite_expires: Option<u32>,
+    /// `Max-Forwards` of requests built by the endpoint and its dialogs
+    pub max_forwards: u8,
+    /// Requests larger than this many bytes that would go over UDP are sent
+    /// over TCP to the same address instead (RFC 3261 §18.1.1). `None`
+    /// always keeps UDP.
+    pub udp_mtu_threshold: Option<usize>,
+    /// Public address advertised in Via and Contact instead of the bound
+    /// one, e.g. a NAT mapping found with STUN. The sockets still bind
+    /// locally. See [`EndpointInner::set_external_addr`].
+    pub external_addr: Option<SocketAddr>,
+    /// Send headers in their compact form (`v:`, `f:`, `i:`, ...) for
+    /// bandwidth-sensitive or legacy gateways. Incoming compact headers are
+    /// always accepted.
+    pub use_compact_headers: bool,
+    /// Answer a new INVITE with `100 Trying` as soon as its transaction is
+    /// created, before the TU sees it, so upstream stops retransmitting
+    pub auto_100_trying: bool,
 }
 
 impl Default for EndpointOption {
     fn default() -> Self {
         EndpointOption {
             t1: Duration::from_millis(500),
+            t2: Duration::from_secs(4),
             t4: Duration::from_secs(5),
             t1x64: Duration::from_millis(64 * 500),
             timerc: Duration::from_secs(180),
             callid_suffix: None,
+            compress_body_threshold: None,
+            max_decompressed_body_size: MAX_DECOMPRESSED_BODY_SIZE,
+            max_via_headers: Some(70),
+            contact_without_brackets: false,
+            route_set: RouteSet::default(),
+            overload_threshold: None,
+            overload_retry_after: 5,
+            retransmission_jitter: None,
+            default_register_expires: 50,
+            default_invite_expires: None,
+            max_forwards: 70,
+            udp_mtu_threshold: Some(1300),
+            external_addr: None,
+            use_compact_headers: false,
+            auto_100_trying: true,
         }
     }
 }
@@ -65,6 +186,23 @@ pub struct EndpointStats {
     pub waiting_ack: usize,
 }
 
+/// Snapshot of the transaction layer, for metrics exporters
+///
+/// # Fields
+///
+/// * `by_type` - Running transactions per transaction type
+/// * `by_state` - Running transactions per transaction state
+/// * `retransmissions` - Requests (Timer A) and INVITE responses (Timer G)
+///   retransmitted since the endpoint started
+/// * `pending_timers` - Timers armed and not yet fired or cancelled
+#[derive(Debug, Clone, Default)]
+pub struct TransactionStats {
+    pub by_type: HashMap<TransactionType, usize>,
+    pub by_state: HashMap<TransactionState, usize>,
+    pub retransmissions: u64,
+    pub pending_timers: usize,
+}
+
 /// SIP Endpoint Core Implementation
 ///
 /// `EndpointInner` is the core implementation of a SIP endpoint that manages
@@ -90,11 +228,13 @@ pub struct EndpointStats {
 /// * `incoming_sender` - Channel for incoming transaction notifications
 /// * `cancel_token` - Cancellation token for graceful shutdown
 /// * `timer_interval` - Interval for timer processing
-/// * `t1`, `t4`, `t1x64` - SIP timer values as per RFC 3261
+/// * `t1`, `t2`, `t4`, `t1x64` - SIP timer values as per RFC 3261
 ///
 /// # Timer Values
 ///
 /// * `t1` - RTT estimate (default 500ms)
+/// * `t2` - Maximum retransmission interval for non-INVITE requests and
+///   INVITE responses (default 4s)
 /// * `t4` - Maximum duration a message will remain in the network (default 4s)
 /// * `t1x64` - Maximum retransmission timeout (default 32s)
 pub struct EndpointInner {
@@ -105,14 +245,36 @@ pub struct EndpointInner {
     pub finished_transactions: RwLock<HashMap<TransactionKey, Option<SipMessage>>>,
     pub transactions: RwLock<HashMap<TransactionKey, TransactionEventSender>>,
     pub waiting_ack: RwLock<HashMap<DialogId, TransactionKey>>,
+    pub waiting_prack: RwLock<HashMap<DialogId, TransactionKey>>,
+    // type and current state of each running transaction, for `transaction_stats`
+    transaction_states: RwLock<HashMap<TransactionKey, (TransactionType, TransactionState)>>,
+    // Call-ID of each running transaction, for `get_transactions_by_call_id`
+    transaction_call_ids: RwLock<HashMap<TransactionKey, String>>,
+    retransmissions: AtomicU64,
+    // see `EndpointOption::external_addr`, replaced at runtime by
+    // `set_external_addr`
+    external_addr: RwLock<Option<SocketAddr>>,
+    // set by `shutdown`, new out-of-dialog requests are rejected
+    draining: AtomicBool,
+    // remote addresses of stream connections that closed, see
+    // `subscribe_closed_connections`
+    closed_connections: broadcast::Sender<SipAddr>,
+    // responses and ACKs no transaction matched, see `stateless_messages`
+    stateless_sender: Mutex<Option<UnboundedSender<SipMessage>>>,
     incoming_sender: TransactionSender,
     incoming_receiver: Mutex<Option<TransactionReceiver>>,
     cancel_token: CancellationToken,
-    #[allow(dead_code)]
     timer_interval: Duration,
     pub(super) message_inspector: Option<Box<dyn MessageInspector>>,
     pub(super) locator: Option<Box<dyn TargetLocator>>,
     pub(super) transport_inspector: Option<Box<dyn TransportEventInspector>>,
+    pub(super) capture_sink: Option<CaptureSink>,
+    pub(super) sdp_rewriter: Option<Box<dyn SdpRewriter>>,
+    pub(super) load_signal: Option<Box<dyn LoadSignal>>,
+    pub(super) message_handler: Option<Box<dyn MessageHandler>>,
+    request_handler: Option<Box<dyn IncomingRequestHandler>>,
+    dialog_router: RwLock<Option<Box<dyn DialogRouter>>>,
+    pub load_control: LoadControl,
     pub option: EndpointOption,
 }
 pub type EndpointInnerRef = Arc<EndpointInner>;
@@ -142,9 +304,21 @@ pub struct EndpointBuilder {
     cancel_token: Option<CancellationToken>,
     timer_interval: Option<Duration>,
     option: Option<EndpointOption>,
-    message_inspector: Option<Box<dyn MessageInspector>>,
-    target_locator: Option<Box<dyn TargetLocator>>,
-    transport_inspector: Option<Box<dyn TransportEventInspector>>,
+    hooks: EndpointHooks,
+}
+
+/// Optional extension points of an endpoint, filled in by
+/// [`EndpointBuilder`]
+#[derive(Default)]
+pub struct EndpointHooks {
+    pub message_inspector: Option<Box<dyn MessageInspector>>,
+    pub target_locator: Option<Box<dyn TargetLocator>>,
+    pub transport_inspector: Option<Box<dyn TransportEventInspector>>,
+    pub capture_sink: Option<CaptureSink>,
+    pub sdp_rewriter: Option<Box<dyn SdpRewriter>>,
+    pub load_signal: Option<Box<dyn LoadSignal>>,
+    pub message_handler: Option<Box<dyn MessageHandler>>,
+    pub request_handler: Option<Box<dyn IncomingRequestHandler>>,
 }
 
 /// SIP Endpoint
@@ -197,7 +371,7 @@ pub struct EndpointBuilder {
 /// 1. Create endpoint using `EndpointBuilder`
 /// 2. Start serving with `serve()` method
 /// 3. Process incoming transactions via `incoming_transactions()`
-/// 4. Shutdown gracefully with `shutdown()`
+/// 4. Shutdown gracefully with `shutdown(grace)`
 pub struct Endpoint {
     pub inner: EndpointInnerRef,
 }
@@ -210,11 +384,10 @@ impl EndpointInner {
         timer_interval: Option<Duration>,
         allows: Vec<rsip::Method>,
         option: Option<EndpointOption>,
-        message_inspector: Option<Box<dyn MessageInspector>>,
-        locator: Option<Box<dyn TargetLocator>>,
-        transport_inspector: Option<Box<dyn TransportEventInspector>>,
+        hooks: EndpointHooks,
     ) -> Arc<Self> {
         let (incoming_sender, incoming_receiver) = unbounded_channel();
+        let option = option.unwrap_or_default();
         Arc::new(EndpointInner {
             allows: Mutex::new(Some(allows)),
             user_agent,
@@ -223,14 +396,29 @@ impl EndpointInner {
             transactions: RwLock::new(HashMap::new()),
             finished_transactions: RwLock::new(HashMap::new()),
             waiting_ack: RwLock::new(HashMap::new()),
+            waiting_prack: RwLock::new(HashMap::new()),
+            transaction_states: RwLock::new(HashMap::new()),
+            transaction_call_ids: RwLock::new(HashMap::new()),
+            retransmissions: AtomicU64::new(0),
+            external_addr: RwLock::new(option.external_addr),
+            draining: AtomicBool::new(false),
+            closed_connections: broadcast::channel(CLOSED_CONNECTIONS_CAPACITY).0,
+            stateless_sender: Mutex::new(None),
             timer_interval: timer_interval.unwrap_or(Duration::from_millis(20)),
             cancel_token,
             incoming_sender,
             incoming_receiver: Mutex::new(Some(incoming_receiver)),
-            option: option.unwrap_or_default(),
-            message_inspector,
-            locator,
-            transport_inspector,
+            option,
+            message_inspector: hooks.message_inspector,
+            locator: hooks.target_locator,
+            transport_inspector: hooks.transport_inspector,
+            capture_sink: hooks.capture_sink,
+            sdp_rewriter: hooks.sdp_rewriter,
+            load_signal: hooks.load_signal,
+            message_handler: hooks.message_handler,
+            request_handler: hooks.request_handler,
+            dialog_router: RwLock::new(None),
+            load_control: LoadControl::default(),
         })
     }
 
@@ -264,6 +452,11 @@ impl EndpointInner {
         };
 
         while let Some(mut event) = transport_rx.recv().await {
+            if let (Some(sink), TransportEvent::Incoming(msg, connection, from)) =
+                (&self.capture_sink, &event)
+            {
+                sink.capture(CaptureDirection::Inbound, msg, connection, Some(from));
+            }
             if let Some(transport_inspector) = &self.transport_inspector {
                 match transport_inspector.handle(event).await {
                     Some(e) => {
@@ -289,6 +482,13 @@ impl EndpointInner {
                 }
                 TransportEvent::Closed(t) => {
                     info!(addr=%t.get_addr(), "closed connection");
+                    self.on_connection_closed(t.get_addr());
+                }
+                TransportEvent::BadRequest(req, connection, from, reason) => {
+                    info!(addr=%from, %reason, "malformed request");
+                    if let Err(e) = self.reply_bad_request(req, connection).await {
+                        warn!(addr=%from, "reply_bad_request error: {}", e);
+                    }
                 }
             }
         }
@@ -350,6 +550,27 @@ impl EndpointInner {
         };
         match &msg {
             SipMessage::Request(req) => {
+                if let Some(max_via_headers) = self.option.max_via_headers {
+                    let via_count = req
+                        .headers
+                        .iter()
+                        .map(|h| match h {
+                            rsip::Header::Via(via) => split_header_list(via.value()).len(),
+                            _ => 0,
+                        })
+                        .sum::<usize>();
+                    if via_count > max_via_headers {
+                        info!(%key, via_count, "too many via headers, rejecting request");
+                        return self
+                            .reply_stateless(
+                                &connection,
+                                req,
+                                rsip::StatusCode::TooManyHops,
+                                vec![],
+                            )
+                            .await;
+                    }
+                }
                 match req.method() {
                     rsip::Method::Ack => match DialogId::try_from(req) {
                         Ok(dialog_id) => {
@@ -375,11 +596,14 @@ impl EndpointInner {
                     .flatten();
 
                 if let Some(last_message) = last_message {
-                    connection.send(last_message, None).await?;
+                    self.send_message(&connection, last_message, None).await?;
                     return Ok(());
                 }
             }
             SipMessage::Response(resp) => {
+                if let Ok(via) = resp.via_header() {
+                    self.load_control.record(&from.addr, via);
+                }
                 let last_message = self
                     .finished_transactions
                     .read()
@@ -404,7 +628,8 @@ impl EndpointInner {
                                     }
                                     rsip::StatusCodeKind::RequestFailure => {
                                         // for ACK to 487, send it where it came from
-                                        connection.send(last_message, Some(from)).await?;
+                                        self.send_message(&connection, last_message, Some(from))
+                                            .await?;
                                         return Ok(());
                                     }
                                     _ => {}
@@ -416,27 +641,57 @@ impl EndpointInner {
                         }
                         _ => {}
                     }
-                    connection.send(last_message, None).await?;
+                    self.send_message(&connection, last_message, None).await?;
                     return Ok(());
                 }
             }
         };
 
-        let msg = if let Some(inspector) = &self.message_inspector {
+        let mut msg = msg;
+        if let Err(e) = decode_message_body(&mut msg, self.option.max_decompressed_body_size) {
+            let SipMessage::Request(request) = msg else {
+                warn!(%key, "dropping response: {}", e);
+                return Ok(());
+            };
+            info!(%key, "rejecting request: {}", e);
+            let mut extra_headers = vec![];
+            if e.status_code() == rsip::StatusCode::UnsupportedMediaType {
+                extra_headers.push(rsip::Header::AcceptEncoding("gzip, deflate".into()));
+            }
+            return self
+                .reply_stateless(&connection, &request, e.status_code(), extra_headers)
+                .await;
+        }
+
+        let mut msg = if let Some(inspector) = &self.message_inspector {
             inspector.after_received(msg)
         } else {
             msg
         };
 
+        if let SipMessage::Request(req) = &mut msg {
+            consume_local_routes(req, |uri| self.is_local_uri(uri));
+            if req.method == rsip::Method::PRack {
+                self.route_prack(req);
+            }
+        }
+
         if let Some(tu) = self.transactions.read().unwrap().get(&key) {
-            tu.send(TransactionEvent::Received(msg, Some(connection)))
-                .map_err(|e| Error::TransactionError(e.to_string(), key))?;
+            tu.send(TransactionEvent::Received(
+                msg,
+                Some(connection),
+                Some(from.clone()),
+            ))
+            .map_err(|e| Error::TransactionError(e.to_string(), key))?;
             return Ok(());
         }
         // if the transaction is not exist, create a new transaction
         let request = match msg {
             SipMessage::Request(req) => req,
             SipMessage::Response(resp) => {
+                if self.deliver_stateless(&resp) {
+                    return Ok(());
+                }
                 if resp.cseq_header()?.method()? != rsip::Method::Cancel {
                     debug!(%key, "the transaction is not exist {}", resp);
                 }
@@ -446,30 +701,378 @@ impl EndpointInner {
 
         match request.method {
             rsip::Method::Cancel => {
-                let resp = self.make_response(
-                    &request,
-                    rsip::StatusCode::CallTransactionDoesNotExist,
-                    None,
-                );
-                let resp = if let Some(ref inspector) = self.message_inspector {
-                    inspector.before_send(resp.into())
-                } else {
-                    resp.into()
-                };
-                connection.send(resp, None).await?;
+                return self
+                    .reply_stateless(
+                        &connection,
+                        &request,
+                        rsip::StatusCode::CallTransactionDoesNotExist,
+                        vec![],
+                    )
+                    .await;
+            }
+            rsip::Method::Ack => {
+                self.deliver_stateless(&request);
                 return Ok(());
             }
-            rsip::Method::Ack => return Ok(()),
             _ => {}
         }
 
-        let tx =
+        if self.rejects_for_shutdown(&request) {
+            info!(%key, "shutting down, rejecting request");
+            return self
+                .reply_stateless(
+                    &connection,
+                    &request,
+                    rsip::StatusCode::ServiceUnavailable,
+                    vec![],
+                )
+                .await;
+        }
+
+        if let Some(extra_headers) = self.overload_headers(&request) {
+            info!(%key, "overloaded, rejecting request");
+            return self
+                .reply_stateless(
+                    &connection,
+                    &request,
+                    rsip::StatusCode::ServiceUnavailable,
+                    extra_headers,
+                )
+                .await;
+        }
+
+        if self.is_unknown_dialog(&request) {
+            info!(%key, "no dialog for in-dialog request");
+            return self
+                .reply_stateless(
+                    &connection,
+                    &request,
+                    rsip::StatusCode::CallTransactionDoesNotExist,
+                    vec![],
+                )
+                .await;
+        }
+
+        let mut tx =
             Transaction::new_server(key.clone(), request.clone(), self.clone(), Some(connection));
+        tx.source = Some(from.clone());
+        if self.option.auto_100_trying && tx.transaction_type == TransactionType::ServerInvite {
+            if let Err(e) = tx.send_trying().await {
+                warn!(%key, "failed to send 100 trying: {}", e);
+            }
+        }
+        let Some(tx) = self.dispatch_instant_message(tx) else {
+            return Ok(());
+        };
+        if self.request_handler.is_some() {
+            self.dispatch_request(tx);
+            return Ok(());
+        }
 
         self.incoming_sender.send(tx).ok();
         Ok(())
     }
 
+    /// Answer a request the transport dropped for broken framing with
+    /// `400 Bad Request`, when it identifies a transaction to answer
+    async fn reply_bad_request(&self, req: rsip::Request, connection: SipConnection) -> Result<()> {
+        TransactionKey::from_request(&req, super::key::TransactionRole::Server)?;
+        self.reply_stateless(&connection, &req, rsip::StatusCode::BadRequest, vec![])
+            .await
+    }
+
+    /// Answer `request` with `status` without a server transaction
+    ///
+    /// `extra_headers` are added to the response, except a Via, which
+    /// replaces the top Via to carry parameters added to it. An ACK gets no
+    /// response.
+    async fn reply_stateless(
+        &self,
+        connection: &SipConnection,
+        request: &rsip::Request,
+        status: rsip::StatusCode,
+        extra_headers: Vec<rsip::Header>,
+    ) -> Result<()> {
+        if request.method == rsip::Method::Ack {
+            return Ok(());
+        }
+        let mut resp = self.make_response(request, status, None);
+        for header in extra_headers {
+            match header {
+                rsip::Header::Via(via) => {
+                    let top = resp
+                        .headers
+                        .iter_mut()
+                        .find(|h| matches!(h, rsip::Header::Via(_)));
+                    if let Some(top) = top {
+                        *top = rsip::Header::Via(via);
+                    }
+                }
+                header => resp.headers.push(header),
+            }
+        }
+        let resp = match &self.message_inspector {
+            Some(inspector) => inspector.before_send(resp.into()),
+            None => resp.into(),
+        };
+        self.send_message(connection, resp, None).await
+    }
+
+    /// Route a new server transaction when a request handler is set, see
+    /// [`IncomingRequestHandler`]
+    fn dispatch_request(self: &Arc<Self>, tx: Transaction) {
+        let in_dialog = matches!(tx.original.to_header().and_then(|to| to.tag()), Ok(Some(_)));
+        let tx = match in_dialog {
+            true => match self.dialog_router.read().unwrap().as_ref() {
+                Some(router) => router.route(self, tx),
+                None => Some(tx),
+            },
+            false => Some(tx),
+        };
+        let Some(mut tx) = tx else {
+            return;
+        };
+        let inner = self.clone();
+        tokio::spawn(async move {
+            if in_dialog {
+                info!(key = %tx.key, "no dialog for in-dialog request");
+                tx.reply(rsip::StatusCode::CallTransactionDoesNotExist)
+                    .await
+                    .ok();
+                return;
+            }
+            if let Some(handler) = inner.request_handler.as_ref() {
+                handler.on_request(tx).await;
+            }
+        });
+    }
+
+    /// An in-dialog request (one with a To tag) for a dialog the dialog
+    /// router doesn't know, e.g. a BYE after a restart, is answered with
+    /// `481` without a transaction (RFC 3261 §12.2.2). Without a dialog
+    /// router the TU decides.
+    fn is_unknown_dialog(self: &Arc<Self>, req: &rsip::Request) -> bool {
+        if !matches!(req.to_header().and_then(|to| to.tag()), Ok(Some(_))) {
+            return false;
+        }
+        match self.dialog_router.read().unwrap().as_ref() {
+            Some(router) => !router.knows(self, req),
+            None => false,
+        }
+    }
+
+    /// Where `dispatch_request` sends in-dialog requests, replacing any
+    /// previous router; see [`crate::dialog::dialog_layer::DialogLayer::route_in_dialog_requests`]
+    pub(crate) fn set_dialog_router(&self, router: Box<dyn DialogRouter>) {
+        self.dialog_router.write().unwrap().replace(router);
+    }
+
+    // hand a message without a transaction to the stateless proxy, if any
+    fn deliver_stateless(&self, msg: &(impl Clone + Into<SipMessage>)) -> bool {
+        match self.stateless_sender.lock().unwrap().as_ref() {
+            Some(sender) => sender.send(msg.clone().into()).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Receive the responses and ACKs that match no transaction
+    ///
+    /// A stateless proxy relays them with
+    /// [`forward_response`](Self::forward_response) and
+    /// [`forward_request`](Self::forward_request). Until this is called they
+    /// are dropped; a later call replaces the previous receiver.
+    pub fn stateless_messages(&self) -> UnboundedReceiver<SipMessage> {
+        let (sender, receiver) = unbounded_channel();
+        self.stateless_sender.lock().unwrap().replace(sender);
+        receiver
+    }
+
+    /// Whether a request gets `503` because `shutdown` has started: new
+    /// out-of-dialog requests do, in-dialog requests still pass so that
+    /// running calls can end
+    fn rejects_for_shutdown(&self, request: &rsip::Request) -> bool {
+        self.draining.load(Ordering::Relaxed)
+            && !matches!(request.to_header().and_then(|to| to.tag()), Ok(Some(_)))
+    }
+
+    // transactions over the closed connection end; dialogs learn about it
+    // through `subscribe_closed_connections`
+    fn on_connection_closed(&self, addr: &SipAddr) {
+        if let Ok(transactions) = self.transactions.read() {
+            for tu in transactions.values() {
+                tu.send(TransactionEvent::ConnectionClosed(addr.clone()))
+                    .ok();
+            }
+        }
+        self.closed_connections.send(addr.clone()).ok();
+    }
+
+    /// Remote addresses of TCP/TLS/WebSocket connections as they close
+    ///
+    /// The dialog layer uses it to terminate the dialogs set up over a
+    /// connection with `TerminatedReason::ConnectionClosed`.
+    pub fn subscribe_closed_connections(&self) -> broadcast::Receiver<SipAddr> {
+        self.closed_connections.subscribe()
+    }
+
+    /// Reject new out-of-dialog requests with `503 Service Unavailable`
+    /// from now on, see [`Self::shutdown`]
+    pub fn stop_accepting(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    /// Transactions a graceful shutdown waits for: client transactions
+    /// still waiting for their final response, and server transactions that
+    /// have not sent one yet
+    ///
+    /// Past the final response a transaction only absorbs retransmissions
+    /// until its timer ends it.
+    fn unfinished_transactions(&self) -> Vec<TransactionKey> {
+        self.transaction_states
+            .read()
+            .map(|states| {
+                states
+                    .iter()
+                    .filter(|(_, (transaction_type, state))| match transaction_type {
+                        TransactionType::ClientInvite | TransactionType::ClientNonInvite => {
+                            matches!(
+                                state,
+                                TransactionState::Nothing
+                                    | TransactionState::Calling
+                                    | TransactionState::Trying
+                                    | TransactionState::Proceeding
+                            )
+                        }
+                        TransactionType::ServerInvite | TransactionType::ServerNonInvite => {
+                            matches!(
+                                state,
+                                TransactionState::Trying | TransactionState::Proceeding
+                            )
+                        }
+                    })
+                    .map(|(key, _)| key.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Stop taking new requests and give running transactions until `grace`
+    /// to terminate, then cancel the endpoint
+    ///
+    /// New out-of-dialog requests are rejected with `503 Service Unavailable`
+    /// from now on, and server transactions the TU has not answered yet
+    /// send a `503`. The wait covers transactions without a final response. Established calls are not torn down here:
+    /// `DialogLayer::shutdown` sends their BYEs before draining the
+    /// endpoint.
+    ///
+    /// Returns the keys of the transactions still unfinished at the deadline.
+    pub async fn shutdown(&self, grace: Duration) -> Vec<TransactionKey> {
+        self.stop_accepting();
+        if let Ok(transactions) = self.transactions.read() {
+            for tu in transactions.values() {
+                tu.send(TransactionEvent::Shutdown).ok();
+            }
+        }
+
+        let deadline = tokio::time::Instant::now() + grace;
+        let remaining = loop {
+            let running = self.unfinished_transactions();
+            let now = tokio::time::Instant::now();
+            if running.is_empty() || now >= deadline {
+                break running;
+            }
+            tokio::time::sleep(self.timer_interval.min(deadline - now)).await;
+        };
+        if !remaining.is_empty() {
+            info!(
+                running = remaining.len(),
+                "shutdown grace expired with unfinished transactions"
+            );
+        }
+        self.cancel_token.cancel();
+        remaining
+    }
+
+    /// Headers of the `503` for a new out-of-dialog request while overloaded
+    /// (RFC 3261 §21.5.4): `Retry-After`, and the top Via with RFC 7339 `oc`
+    /// parameters for clients supporting them
+    fn overload_headers(&self, request: &rsip::Request) -> Option<Vec<rsip::Header>> {
+        let threshold = self.option.overload_threshold?;
+        if request.to_header().ok()?.tag().ok().flatten().is_some() {
+            return None;
+        }
+        let load = match &self.load_signal {
+            Some(signal) => signal.load(),
+            None => self.transactions.read().map(|ts| ts.len()).unwrap_or(0),
+        };
+        if load <= threshold {
+            return None;
+        }
+
+        let retry_after = self.option.overload_retry_after;
+        let mut headers = vec![rsip::Header::Other(
+            "Retry-After".into(),
+            retry_after.to_string(),
+        )];
+        let validity = Duration::from_secs(retry_after.into());
+        let reduction = reduction_for_load(load, threshold);
+        if let Some(via) = request
+            .via_header()
+            .ok()
+            .and_then(|via| overload_via(via, reduction, validity))
+        {
+            headers.push(rsip::Header::Via(via));
+        }
+        Some(headers)
+    }
+
+    /// Send `msg` on `connection`, mirroring it to the capture sink if any
+    pub async fn send_message(
+        &self,
+        connection: &SipConnection,
+        msg: SipMessage,
+        destination: Option<&SipAddr>,
+    ) -> Result<()> {
+        let mut msg = msg;
+        let mut destination = destination.cloned();
+        let compact = self.option.use_compact_headers && !connection.is_in_memory();
+        if compact {
+            // UDP finds a response's destination in its Via, which is no
+            // longer typed once compacted
+            if destination.is_none() && matches!(connection, SipConnection::Udp(_)) {
+                destination = Some(SipConnection::get_destination(&msg)?.into());
+            }
+            match &mut msg {
+                SipMessage::Request(req) => compact_headers(&mut req.headers),
+                SipMessage::Response(resp) => compact_headers(&mut resp.headers),
+            }
+        }
+        let destination = destination.as_ref();
+        if let Some(sink) = &self.capture_sink {
+            sink.capture(CaptureDirection::Outbound, &msg, connection, destination);
+        }
+        connection.send(msg, destination).await
+    }
+
+    // a PRACK runs in its own transaction, but also has to reach the INVITE
+    // transaction retransmitting the reliable provisional response (RFC 3262)
+    fn route_prack(&self, req: &rsip::Request) {
+        let dialog_id = match DialogId::try_from(req) {
+            Ok(dialog_id) => dialog_id,
+            Err(_) => return,
+        };
+        let tx_key = self
+            .waiting_prack
+            .read()
+            .map(|wp| wp.get(&dialog_id).cloned());
+        if let Ok(Some(tx_key)) = tx_key {
+            if let Some(tu) = self.transactions.read().unwrap().get(&tx_key) {
+                tu.send(TransactionEvent::Received(req.clone().into(), None, None))
+                    .ok();
+            }
+        }
+    }
+
     pub fn attach_transaction(&self, key: &TransactionKey, tu_sender: TransactionEventSender) {
         trace!(%key, "attach transaction");
         self.transactions
@@ -486,6 +1089,16 @@ impl EndpointInner {
             .as_mut()
             .map(|ts| ts.remove(key))
             .ok();
+        self.transaction_states
+            .write()
+            .as_mut()
+            .map(|ts| ts.remove(key))
+            .ok();
+        self.transaction_call_ids
+            .write()
+            .as_mut()
+            .map(|ts| ts.remove(key))
+            .ok();
 
         if let Some(msg) = last_message {
             self.timers.timeout(
@@ -505,6 +1118,50 @@ impl EndpointInner {
         self.transport_layer.get_addrs()
     }
 
+    /// Advertise `addr` in Via and Contact instead of the bound address,
+    /// e.g. once STUN has found the NAT mapping. `None` goes back to the
+    /// bound address.
+    pub fn set_external_addr(&self, addr: Option<SocketAddr>) {
+        if let Ok(mut external_addr) = self.external_addr.write() {
+            *external_addr = addr;
+        }
+    }
+
+    pub fn external_addr(&self) -> Option<SocketAddr> {
+        self.external_addr.read().ok().and_then(|addr| *addr)
+    }
+
+    /// `addr` as others reach it: its host and port are replaced by the
+    /// external address, if any
+    pub fn advertised_addr(&self, addr: SipAddr) -> SipAddr {
+        match self.external_addr() {
+            Some(external) => SipAddr {
+                r#type: addr.r#type,
+                addr: external.into(),
+            },
+            None => addr,
+        }
+    }
+
+    /// Whether `uri` addresses one of the listening transports, or the
+    /// external address
+    pub fn is_local_uri(&self, uri: &rsip::Uri) -> bool {
+        let Ok(target) = SipAddr::try_from(uri) else {
+            return false;
+        };
+        let external = self
+            .external_addr()
+            .map(|addr| SipAddr::new(target.r#type.unwrap_or_default(), addr.into()));
+        self.transport_layer
+            .get_addrs()
+            .iter()
+            .chain(external.iter())
+            .any(|addr| {
+                addr.addr.host == target.addr.host
+                    && addr.port_or_default() == target.port_or_default()
+            })
+    }
+
     pub fn get_record_route(&self) -> Result<rsip::typed::RecordRoute> {
         let first_addr = self
             .transport_layer
@@ -524,20 +1181,24 @@ impl EndpointInner {
         addr: Option<crate::transport::SipAddr>,
         branch: Option<rsip::Param>,
     ) -> Result<rsip::typed::Via> {
+        if let Some(rsip::Param::Branch(branch)) = &branch {
+            via_branch(&branch.to_string())?;
+        }
         let first_addr = match addr {
             Some(addr) => addr,
-            None => self
-                .transport_layer
-                .get_addrs()
-                .first()
-                .ok_or(Error::EndpointError("not sipaddrs".to_string()))
-                .cloned()?,
+            None => self.advertised_addr(
+                self.transport_layer
+                    .get_addrs()
+                    .first()
+                    .ok_or(Error::EndpointError("not sipaddrs".to_string()))
+                    .cloned()?,
+            ),
         };
 
         let via = rsip::typed::Via {
             version: rsip::Version::V2,
             transport: first_addr.r#type.unwrap_or_default(),
-            uri: first_addr.addr.into(),
+            uri: crate::transport::sip_addr::bracket_ipv6(&first_addr.addr).into(),
             params: vec![
                 branch.unwrap_or_else(make_via_branch),
                 rsip::Param::Other("rport".into(), None),
@@ -553,6 +1214,48 @@ impl EndpointInner {
             .ok()
     }
 
+    pub(super) fn record_transaction_call_id(&self, key: &TransactionKey, call_id: &str) {
+        self.transaction_call_ids
+            .write()
+            .as_mut()
+            .map(|ts| ts.insert(key.clone(), call_id.to_string()))
+            .ok();
+    }
+
+    /// Running transactions whose Call-ID is exactly `call_id`
+    pub fn get_transactions_by_call_id(&self, call_id: &str) -> Vec<TransactionKey> {
+        self.transaction_call_ids
+            .read()
+            .map(|ts| {
+                ts.iter()
+                    .filter(|(_, id)| id.as_str() == call_id)
+                    .map(|(key, _)| key.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Abort the transactions `keys` that are still running, without sending
+    /// anything on the wire. Returns how many were aborted.
+    pub fn terminate_transactions(&self, keys: &[TransactionKey]) -> usize {
+        let senders = match self.transactions.read() {
+            Ok(ts) => keys
+                .iter()
+                .filter_map(|key| ts.get(key).map(|sender| (key.clone(), sender.clone())))
+                .collect::<Vec<_>>(),
+            Err(_) => return 0,
+        };
+        senders
+            .into_iter()
+            .filter(|(key, sender)| {
+                info!(%key, "aborting transaction");
+                sender
+                    .send(TransactionEvent::Terminate(key.clone()))
+                    .is_ok()
+            })
+            .count()
+    }
+
     pub fn get_stats(&self) -> EndpointStats {
         let waiting_ack = self
             .waiting_ack
@@ -576,6 +1279,38 @@ impl EndpointInner {
             waiting_ack,
         }
     }
+
+    pub(super) fn record_transaction_state(
+        &self,
+        key: &TransactionKey,
+        transaction_type: TransactionType,
+        state: TransactionState,
+    ) {
+        self.transaction_states
+            .write()
+            .as_mut()
+            .map(|ts| ts.insert(key.clone(), (transaction_type, state)))
+            .ok();
+    }
+
+    pub(super) fn record_retransmission(&self) {
+        self.retransmissions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn transaction_stats(&self) -> TransactionStats {
+        let mut stats = TransactionStats {
+            retransmissions: self.retransmissions.load(Ordering::Relaxed),
+            pending_timers: self.timers.len(),
+            ..Default::default()
+        };
+        if let Ok(states) = self.transaction_states.read() {
+            for (transaction_type, state) in states.values() {
+                *stats.by_type.entry(transaction_type.clone()).or_default() += 1;
+                *stats.by_state.entry(state.clone()).or_default() += 1;
+            }
+        }
+        stats
+    }
 }
 
 impl EndpointBuilder {
@@ -587,15 +1322,34 @@ impl EndpointBuilder {
             cancel_token: None,
             timer_interval: None,
             option: None,
-            message_inspector: None,
-            target_locator: None,
-            transport_inspector: None,
+            hooks: EndpointHooks::default(),
         }
     }
     pub fn with_option(&mut self, option: EndpointOption) -> &mut Self {
         self.option = Some(option);
         self
     }
+    /// See `EndpointOption::default_register_expires`
+    pub fn with_default_register_expires(&mut self, expires: u32) -> &mut Self {
+        self.option
+            .get_or_insert_with(EndpointOption::default)
+            .default_register_expires = expires;
+        self
+    }
+    /// See `EndpointOption::default_invite_expires`
+    pub fn with_default_invite_expires(&mut self, expires: Option<u32>) -> &mut Self {
+        self.option
+            .get_or_insert_with(EndpointOption::default)
+            .default_invite_expires = expires;
+        self
+    }
+    /// See `EndpointOption::external_addr`
+    pub fn with_external_addr(&mut self, addr: SocketAddr) -> &mut Self {
+        self.option
+            .get_or_insert_with(EndpointOption::default)
+            .external_addr = Some(addr);
+        self
+    }
     pub fn with_user_agent(&mut self, user_agent: &str) -> &mut Self {
         self.user_agent = user_agent.to_string();
         self
@@ -620,11 +1374,11 @@ impl EndpointBuilder {
         self
     }
     pub fn with_inspector(&mut self, inspector: Box<dyn MessageInspector>) -> &mut Self {
-        self.message_inspector = Some(inspector);
+        self.hooks.message_inspector = Some(inspector);
         self
     }
     pub fn with_target_locator(&mut self, locator: Box<dyn TargetLocator>) -> &mut Self {
-        self.target_locator = Some(locator);
+        self.hooks.target_locator = Some(locator);
         self
     }
 
@@ -632,7 +1386,38 @@ impl EndpointBuilder {
         &mut self,
         inspector: Box<dyn TransportEventInspector>,
     ) -> &mut Self {
-        self.transport_inspector = Some(inspector);
+        self.hooks.transport_inspector = Some(inspector);
+        self
+    }
+
+    /// Mirror every SIP message sent or received to `sender`, see [`CaptureSink`]
+    pub fn with_capture_sink(&mut self, sender: Sender<CapturedMessage>) -> &mut Self {
+        self.hooks.capture_sink = Some(CaptureSink::new(sender));
+        self
+    }
+
+    /// Load compared against `EndpointOption::overload_threshold`
+    pub fn with_load_signal(&mut self, signal: Box<dyn LoadSignal>) -> &mut Self {
+        self.hooks.load_signal = Some(signal);
+        self
+    }
+
+    /// Answer MESSAGE requests outside of a dialog, see [`MessageHandler`]
+    pub fn with_message_handler(&mut self, handler: Box<dyn MessageHandler>) -> &mut Self {
+        self.hooks.message_handler = Some(handler);
+        self
+    }
+
+    /// Handle new incoming requests instead of `incoming_transactions`,
+    /// see [`IncomingRequestHandler`]
+    pub fn with_handler(&mut self, handler: Box<dyn IncomingRequestHandler>) -> &mut Self {
+        self.hooks.request_handler = Some(handler);
+        self
+    }
+
+    /// Rewrite the SDP of every response sent, see [`SdpRewriter`]
+    pub fn with_sdp_rewriter(&mut self, rewriter: Box<dyn SdpRewriter>) -> &mut Self {
+        self.hooks.sdp_rewriter = Some(rewriter);
         self
     }
 
@@ -648,9 +1433,7 @@ impl EndpointBuilder {
         let user_agent = self.user_agent.to_owned();
         let timer_interval = self.timer_interval.to_owned();
         let option = self.option.take();
-        let message_inspector = self.message_inspector.take();
-        let locator = self.target_locator.take();
-        let transport_inspector = self.transport_inspector.take();
+        let hooks = std::mem::take(&mut self.hooks);
 
         let core = EndpointInner::new(
             user_agent,
@@ -659,9 +1442,7 @@ impl EndpointBuilder {
             timer_interval,
             allows,
             option,
-            message_inspector,
-            locator,
-            transport_inspector,
+            hooks,
         );
 
         Endpoint { inner: core }
@@ -681,9 +1462,11 @@ impl Endpoint {
         }
     }
 
-    pub fn shutdown(&self) {
-        info!("endpoint shutdown requested");
-        self.inner.cancel_token.cancel();
+    /// Drain running transactions for up to `grace`, then stop the
+    /// endpoint, see [`EndpointInner::shutdown`]
+    pub async fn shutdown(&self, grace: Duration) -> Vec<TransactionKey> {
+        info!(?grace, "endpoint shutdown requested");
+        self.inner.shutdown(grace).await
     }
 
     //
@@ -701,4 +1484,21 @@ impl Endpoint {
     pub fn get_addrs(&self) -> Vec<SipAddr> {
         self.inner.transport_layer.get_addrs()
     }
+
+    /// Send a response by its top Via without a server transaction, see
+    /// [`EndpointInner::send_stateless_response`]
+    pub async fn send_stateless_response(&self, resp: rsip::Response) -> Result<()> {
+        self.inner.send_stateless_response(resp).await
+    }
+
+    /// Forward a request without a client transaction, see
+    /// [`EndpointInner::forward_request`]
+    pub async fn forward_request(&self, req: rsip::Request, target: Option<SipAddr>) -> Result<()> {
+        self.inner.forward_request(req, target).await
+    }
+
+    /// Transaction counts, retransmissions and pending timers
+    pub fn stats(&self) -> TransactionStats {
+        self.inner.transaction_stats()
+    }
 }
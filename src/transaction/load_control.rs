@@ -0,0 +1,160 @@
+use crate::rsip_ext::split_header_list;
+use rand::Rng;
+use rsip::{
+    headers::Via,
+    param::OtherParamValue,
+    prelude::{ToTypedHeader, UntypedHeader},
+    HostWithPort, Param,
+};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::info;
+
+/// Default `oc-validity` (RFC 7339 §5.2) when a response carries none
+pub const DEFAULT_OC_VALIDITY: Duration = Duration::from_millis(500);
+const OC_ALGO_LOSS: &str = "loss";
+
+/// Current load of the application, compared against
+/// [`EndpointOption::overload_threshold`](super::endpoint::EndpointOption)
+/// instead of the number of running transactions
+pub trait LoadSignal: Send + Sync {
+    fn load(&self) -> usize;
+}
+
+/// Reads the raw Via text: rsip drops quoted parameter values such as
+/// `oc-algo="loss"` when it parses a typed Via
+fn via_param<'a>(via: &'a Via, name: &str) -> Option<Option<&'a str>> {
+    via.value().split(';').skip(1).find_map(|param| {
+        let (key, value) = match param.split_once('=') {
+            Some((key, value)) => (key, Some(value.trim().trim_matches('"'))),
+            None => (param, None),
+        };
+        key.trim().eq_ignore_ascii_case(name).then_some(value)
+    })
+}
+
+/// Whether the sender of a request supports the loss-based algorithm
+pub fn supports_oc_loss(via: &Via) -> bool {
+    via_param(via, "oc-algo")
+        .flatten()
+        .map(|algos| {
+            algos
+                .split(',')
+                .any(|algo| algo.trim().eq_ignore_ascii_case(OC_ALGO_LOSS))
+        })
+        .unwrap_or(false)
+}
+
+/// Traffic reduction a server asks for in the `oc` parameter of its Via,
+/// with how long it applies
+pub fn oc_reduction(via: &Via) -> Option<(u32, Duration)> {
+    let percent = via_param(via, "oc")??.parse::<u32>().ok()?.min(100);
+    let validity = via_param(via, "oc-validity")
+        .flatten()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_OC_VALIDITY);
+    Some((percent, validity))
+}
+
+/// The Via of a rejected request with the server's `oc`, `oc-algo`,
+/// `oc-validity` and `oc-seq` (RFC 7339 §5.2) set on its first via-parm
+///
+/// Further via-parms of a comma-joined Via are kept as they are. `None` when
+/// the sender does not support the loss-based algorithm.
+pub fn overload_via(via: &Via, reduction: u32, validity: Duration) -> Option<Via> {
+    let parts = split_header_list(via.value());
+    let (top, rest) = parts.split_first()?;
+    let top = Via::new(*top);
+    if !supports_oc_loss(&top) {
+        return None;
+    }
+    let mut typed = top.typed().ok()?;
+    typed.params.retain(|p| {
+        !matches!(p, Param::Other(name, _)
+            if ["oc", "oc-algo", "oc-validity", "oc-seq"]
+                .iter()
+                .any(|oc| name.value().eq_ignore_ascii_case(oc)))
+    });
+    let seq = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let param =
+        |name: &str, value: String| Param::Other(name.into(), Some(OtherParamValue::new(value)));
+    typed.params.extend([
+        param("oc", reduction.to_string()),
+        param("oc-algo", format!("\"{}\"", OC_ALGO_LOSS)),
+        param("oc-validity", validity.as_millis().to_string()),
+        param(
+            "oc-seq",
+            format!("{}.{:03}", seq.as_secs(), seq.subsec_millis()),
+        ),
+    ]);
+    let top = Via::from(typed);
+    let vias = std::iter::once(top.value())
+        .chain(rest.iter().copied())
+        .collect::<Vec<_>>();
+    Some(Via::new(vias.join(", ")))
+}
+
+/// Percentage of load above `threshold`, the share a client should shed
+pub fn reduction_for_load(load: usize, threshold: usize) -> u32 {
+    if load <= threshold {
+        return 0;
+    }
+    ((load - threshold) * 100).div_ceil(load).min(100) as u32
+}
+
+/// Client side of RFC 7339 overload control
+///
+/// Remembers the `oc` reduction each server returned and drops that share
+/// of the new requests sent to it until `oc-validity` runs out.
+#[derive(Default)]
+pub struct LoadControl {
+    /// Keyed by the peer's `host:port` text, `HostWithPort` is not `Hash`
+    reductions: Mutex<HashMap<String, (u32, Instant)>>,
+}
+
+impl LoadControl {
+    pub fn record(&self, peer: &HostWithPort, via: &Via) {
+        let Some((percent, validity)) = oc_reduction(via) else {
+            return;
+        };
+        if let Ok(mut reductions) = self.reductions.lock() {
+            let key = peer.to_string();
+            if percent == 0 || validity.is_zero() {
+                if reductions.remove(&key).is_some() {
+                    info!(%peer, "overload control ended");
+                }
+            } else {
+                info!(%peer, percent, ?validity, "overload control requested");
+                reductions.insert(key, (percent, Instant::now() + validity));
+            }
+        }
+    }
+
+    /// Reduction currently in force for `peer`
+    pub fn reduction(&self, peer: &HostWithPort) -> Option<u32> {
+        let mut reductions = self.reductions.lock().ok()?;
+        let key = peer.to_string();
+        match reductions.get(&key) {
+            Some((percent, until)) if Instant::now() < *until => Some(*percent),
+            Some(_) => {
+                reductions.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Whether a new request to `peer` may be sent
+    pub fn admit(&self, peer: &HostWithPort) -> bool {
+        match self.reduction(peer) {
+            Some(percent) => rand::rng().random_range(0..100) >= percent,
+            None => true,
+        }
+    }
+}
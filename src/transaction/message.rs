@@ -1,8 +1,8 @@
-use super::{endpoint::EndpointInner, make_call_id};
+use super::{endpoint::EndpointInner, make_call_id, route_set::RouteSet};
 use crate::{transaction::make_via_branch, Result};
 use rsip::{
     header,
-    headers::{ContentLength, Route},
+    headers::ContentLength,
     prelude::{ToTypedHeader, UntypedHeader},
     Error, Header, Request, Response, StatusCode,
 };
@@ -22,6 +22,8 @@ impl EndpointInner {
     /// * `from` - From header identifying the request originator
     /// * `to` - To header identifying the request target
     /// * `seq` - CSeq sequence number for the request
+    /// * `call_id` - Call-ID to use, a new one is made when `None`
+    /// * `max_forwards` - Overrides `EndpointOption::max_forwards`
     ///
     /// # Returns
     ///
@@ -35,8 +37,10 @@ impl EndpointInner {
     /// * **From** - Request originator with tag parameter
     /// * **To** - Request target (tag added by recipient)
     /// * **CSeq** - Command sequence with method and number
-    /// * **Max-Forwards** - Hop count limit (set to 70)
+    /// * **Max-Forwards** - Hop count limit (`EndpointOption::max_forwards`,
+    ///   70 unless configured)
     /// * **User-Agent** - Endpoint identification
+    /// * **Route** - The endpoint's preloaded route set, if any
     ///
     /// # Examples
     ///
@@ -64,6 +68,7 @@ impl EndpointInner {
     ///     to,
     ///     1,
     ///     None,
+    ///     None,
     /// );
     /// # Ok(())
     /// # }
@@ -98,17 +103,21 @@ impl EndpointInner {
         to: rsip::typed::To,
         seq: u32,
         call_id: Option<rsip::headers::CallId>,
+        max_forwards: Option<u8>,
     ) -> rsip::Request {
         let call_id = call_id.unwrap_or_else(|| make_call_id(self.option.callid_suffix.as_deref()));
-        let headers = vec![
+        let mut headers = vec![
             Header::Via(via.into()),
             Header::CallId(call_id),
             Header::From(from.into()),
             Header::To(to.into()),
             Header::CSeq(rsip::typed::CSeq { seq, method }.into()),
-            Header::MaxForwards(70.into()),
+            Header::MaxForwards(max_forwards_header(
+                max_forwards.unwrap_or(self.option.max_forwards),
+            )),
             Header::UserAgent(self.user_agent.clone().into()),
         ];
+        headers.extend(self.option.route_set.to_headers());
         rsip::Request {
             method,
             uri: req_uri,
@@ -138,7 +147,7 @@ impl EndpointInner {
     ///
     /// The method processes headers as follows:
     /// * **Copied from request**: Via, Call-ID, From, To, CSeq, Max-Forwards
-    /// * **Added by endpoint**: User-Agent
+    /// * **Added by endpoint**: Server
     /// * **Filtered out**: All other headers from the request
     ///
     /// Additional response-specific headers should be added after creation.
@@ -208,7 +217,8 @@ impl EndpointInner {
     /// * Call-ID is preserved (dialog/transaction identification)
     /// * From/To headers maintain dialog state
     /// * CSeq is copied for transaction matching
-    /// * User-Agent identifies the responding endpoint
+    /// * Record-Route headers are copied in order (route set, RFC 3261 §12.1.1)
+    /// * Server identifies the responding endpoint
     ///
     /// # Content Handling
     ///
@@ -230,12 +240,13 @@ impl EndpointInner {
                     | Header::From(_)
                     | Header::To(_)
                     | Header::CSeq(_)
+                    | Header::RecordRoute(_)
             )
         });
         headers.push(Header::ContentLength(
             body.as_ref().map_or(0u32, |b| b.len() as u32).into(),
         ));
-        headers.unique_push(Header::UserAgent(self.user_agent.clone().into()));
+        headers.unique_push(Header::Server(self.user_agent.clone().into()));
         Response {
             status_code,
             version: req.version().clone(),
@@ -261,14 +272,7 @@ impl EndpointInner {
             }
         }
         // update route set from Record-Route header
-        let mut route_set = Vec::new();
-        for header in resp.headers.iter() {
-            if let Header::RecordRoute(record_route) = header {
-                route_set.push(Header::Route(Route::from(record_route.value())));
-            }
-        }
-        route_set.reverse();
-        headers.extend(route_set);
+        headers.extend(RouteSet::from_record_route_reversed(&resp.headers).to_headers());
 
         headers.retain(|h| {
             matches!(
@@ -281,7 +285,9 @@ impl EndpointInner {
                     | Header::Route(_)
             )
         });
-        headers.push(Header::MaxForwards(70.into()));
+        headers.push(Header::MaxForwards(max_forwards_header(
+            self.option.max_forwards,
+        )));
         headers.iter_mut().for_each(|h| {
             if let Header::CSeq(cseq) = h {
                 cseq.mut_method(rsip::Method::Ack).ok();
@@ -297,4 +303,76 @@ impl EndpointInner {
             version: rsip::Version::V2,
         })
     }
+
+    /// Create a CANCEL for a pending INVITE (RFC 3261 §9.1)
+    ///
+    /// The CANCEL reuses the Request-URI, Call-ID, From, To, the CSeq number
+    /// and the top Via (same branch) of the INVITE, plus its Route headers.
+    pub fn make_cancel(&self, invite: &Request) -> Result<Request> {
+        let mut headers = invite.headers.clone();
+        let mut top_via_seen = false;
+        headers.retain(|h| match h {
+            Header::Via(_) if !top_via_seen => {
+                top_via_seen = true;
+                true
+            }
+            Header::CallId(_)
+            | Header::From(_)
+            | Header::To(_)
+            | Header::CSeq(_)
+            | Header::Route(_) => true,
+            _ => false,
+        });
+        if !top_via_seen {
+            return Err(Error::missing_header("Via").into());
+        }
+        header!(
+            headers.iter_mut(),
+            Header::CSeq,
+            Error::missing_header("CSeq")
+        )?
+        .mut_method(rsip::Method::Cancel)?;
+        headers.push(Header::MaxForwards(max_forwards_header(
+            self.option.max_forwards,
+        )));
+        headers.push(Header::UserAgent(self.user_agent.clone().into()));
+        headers.push(Header::ContentLength(ContentLength::default()));
+        Ok(Request {
+            method: rsip::Method::Cancel,
+            uri: invite.uri.clone(),
+            headers,
+            body: vec![],
+            version: rsip::Version::V2,
+        })
+    }
+}
+
+pub(crate) fn max_forwards_header(value: u8) -> rsip::headers::MaxForwards {
+    rsip::headers::MaxForwards::new(value.to_string())
+}
+
+/// Decrement `Max-Forwards` of a request before a proxy forwards it
+/// (RFC 3261 §16.6)
+///
+/// A request without `Max-Forwards` is given 70 first. Returns the new
+/// value, or fails with [`Error::TooManyHops`](crate::Error::TooManyHops)
+/// when the request arrived with `Max-Forwards: 0`; the proxy then answers
+/// it with `483 Too Many Hops` instead of forwarding.
+pub fn decrement_max_forwards(request: &mut Request) -> Result<u8> {
+    let current = request
+        .headers
+        .iter()
+        .find_map(|h| match h {
+            Header::MaxForwards(max_forwards) => Some(max_forwards.value().trim().parse::<u8>()),
+            _ => None,
+        })
+        .unwrap_or(Ok(70))
+        .map_err(|e| crate::Error::Error(format!("invalid Max-Forwards: {}", e)))?;
+    let Some(next) = current.checked_sub(1) else {
+        return Err(crate::Error::TooManyHops);
+    };
+    request
+        .headers
+        .unique_push(Header::MaxForwards(max_forwards_header(next)));
+    Ok(next)
 }
@@ -4,9 +4,14 @@ use std::time::Duration;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use transaction::Transaction;
 
+pub mod compression;
 pub mod endpoint;
 pub mod key;
+pub mod load_control;
 pub mod message;
+pub mod pager;
+pub mod route_set;
+pub mod stateless;
 mod timer;
 pub mod transaction;
 pub use endpoint::Endpoint;
@@ -18,6 +23,8 @@ pub const TO_TAG_LEN: usize = 8;
 pub const BRANCH_LEN: usize = 12;
 pub const CNONCE_LEN: usize = 8;
 pub const CALL_ID_LEN: usize = 22;
+/// Prefix of every RFC 3261 Via branch (§8.1.1.7)
+pub const BRANCH_MAGIC_COOKIE: &str = "z9hG4bK";
 pub struct IncomingRequest {
     pub request: rsip::Request,
     pub connection: SipConnection,
@@ -80,7 +87,7 @@ pub type TransactionSender = UnboundedSender<Transaction>;
 ///     TransactionState::Terminated => println!("Transaction complete"),
 /// }
 /// ```
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum TransactionState {
     Nothing,
     Calling,
@@ -154,7 +161,7 @@ impl std::fmt::Display for TransactionState {
 ///     }
 /// }
 /// ```
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum TransactionType {
     ClientInvite,
     ClientNonInvite,
@@ -182,8 +189,8 @@ impl std::fmt::Display for TransactionType {
 /// * `TimerA` - Retransmission timer for client transactions (unreliable transport)
 /// * `TimerB` - Transaction timeout timer for client transactions
 /// * `TimerD` - Wait timer for response retransmissions (client)
-/// * `TimerE` - Retransmission timer for non-INVITE server transactions
-/// * `TimerF` - Transaction timeout timer for non-INVITE server transactions
+/// * `TimerE` - Retransmission timer for non-INVITE client transactions (unreliable transport)
+/// * `TimerF` - Transaction timeout timer for non-INVITE client transactions
 /// * `TimerK` - Wait timer for ACK (server INVITE transactions)
 /// * `TimerG` - Retransmission timer for INVITE server transactions
 /// * `TimerCleanup` - Internal cleanup timer for transaction removal
@@ -195,13 +202,15 @@ impl std::fmt::Display for TransactionType {
 /// * T4 = 5s (maximum duration a message will remain in the network)
 ///
 /// ## Timer Calculations
-/// * Timer A: starts at T1, doubles each retransmission up to T2
+/// * Timer A: starts at T1, doubles each retransmission
 /// * Timer B: 64*T1 (32 seconds)
 /// * Timer D: 32 seconds for unreliable, 0 for reliable transports
 /// * Timer E: starts at T1, doubles up to T2
 /// * Timer F: 64*T1 (32 seconds)
 /// * Timer G: starts at T1, doubles up to T2
 /// * Timer K: T4 for unreliable, 0 for reliable transports
+/// * Timer PRACK: reliable provisional response retransmission (RFC 3262),
+///   starts at T1 and doubles until 64*T1
 ///
 /// # Examples
 ///
@@ -251,8 +260,11 @@ pub enum TransactionTimer {
     TimerB(TransactionKey),
     TimerC(TransactionKey),
     TimerD(TransactionKey),
+    TimerE(TransactionKey, Duration),
+    TimerF(TransactionKey),
     TimerK(TransactionKey),
     TimerG(TransactionKey, Duration),
+    TimerPrack(TransactionKey, Duration),
     TimerCleanup(TransactionKey),
 }
 
@@ -263,8 +275,11 @@ impl TransactionTimer {
             TransactionTimer::TimerB(key) => key,
             TransactionTimer::TimerC(key) => key,
             TransactionTimer::TimerD(key) => key,
+            TransactionTimer::TimerE(key, _) => key,
+            TransactionTimer::TimerF(key) => key,
             TransactionTimer::TimerG(key, _) => key,
             TransactionTimer::TimerK(key) => key,
+            TransactionTimer::TimerPrack(key, _) => key,
             TransactionTimer::TimerCleanup(key) => key,
         }
     }
@@ -279,17 +294,75 @@ impl std::fmt::Display for TransactionTimer {
             TransactionTimer::TimerB(key) => write!(f, "TimerB: {}", key),
             TransactionTimer::TimerC(key) => write!(f, "TimerC: {}", key),
             TransactionTimer::TimerD(key) => write!(f, "TimerD: {}", key),
+            TransactionTimer::TimerE(key, duration) => {
+                write!(f, "TimerE: {} {}", key, duration.as_millis())
+            }
+            TransactionTimer::TimerF(key) => write!(f, "TimerF: {}", key),
             TransactionTimer::TimerG(key, duration) => {
                 write!(f, "TimerG: {} {}", key, duration.as_millis())
             }
             TransactionTimer::TimerK(key) => write!(f, "TimerK: {}", key),
+            TransactionTimer::TimerPrack(key, duration) => {
+                write!(f, "TimerPrack: {} {}", key, duration.as_millis())
+            }
             TransactionTimer::TimerCleanup(key) => write!(f, "TimerCleanup: {}", key),
         }
     }
 }
 
 pub fn make_via_branch() -> rsip::Param {
-    rsip::Param::Branch(format!("z9hG4bK{}", random_text(BRANCH_LEN)).into())
+    rsip::Param::Branch(format!("{}{}", BRANCH_MAGIC_COOKIE, random_text(BRANCH_LEN)).into())
+}
+
+/// Give a resent request a new transaction: the top Via gets a fresh branch
+/// and asks for `rport` unless it already does
+pub(crate) fn rebranch_top_via(request: &mut rsip::Request) -> crate::Result<()> {
+    use rsip::prelude::{HeadersExt, ToTypedHeader};
+
+    let mut via = request.via_header()?.typed()?;
+    via.params.retain(|p| !matches!(p, rsip::Param::Branch(_)));
+    via.params.push(make_via_branch());
+    let has_rport = via.params.iter().any(
+        |p| matches!(p, rsip::Param::Other(name, _) if name.value().eq_ignore_ascii_case("rport")),
+    );
+    if !has_rport {
+        via.params.push(rsip::Param::Other("rport".into(), None));
+    }
+    if let Some(top) = request
+        .headers
+        .iter_mut()
+        .find(|h| matches!(h, rsip::Header::Via(_)))
+    {
+        *top = via.into();
+    }
+    Ok(())
+}
+
+/// Via branch parameter with a caller supplied value, e.g. to reproduce a
+/// transaction in tests or to keep the branch a proxy received
+///
+/// The value must start with the `z9hG4bK` magic cookie and carry a token
+/// after it.
+pub fn via_branch(value: &str) -> crate::Result<rsip::Param> {
+    let valid = value
+        .strip_prefix(BRANCH_MAGIC_COOKIE)
+        .is_some_and(|token| {
+            !token.is_empty()
+                && token.chars().all(|c| {
+                    c.is_ascii_alphanumeric()
+                        || matches!(
+                            c,
+                            '-' | '.' | '!' | '%' | '*' | '_' | '+' | '`' | '\'' | '~'
+                        )
+                })
+        });
+    if !valid {
+        return Err(crate::Error::Error(format!(
+            "invalid via branch: {}",
+            value
+        )));
+    }
+    Ok(rsip::Param::Branch(value.into()))
 }
 
 pub fn make_call_id(domain: Option<&str>) -> rsip::headers::CallId {
@@ -305,6 +378,17 @@ pub fn make_tag() -> rsip::param::Tag {
     random_text(TO_TAG_LEN).into()
 }
 
+/// `interval` moved randomly by up to `percent` percent either way, so
+/// retransmissions of transactions started together drift apart
+pub fn jitter_duration(interval: Duration, percent: u32) -> Duration {
+    use rand::Rng;
+    let spread = percent.min(100) as f64 / 100.0;
+    if spread == 0.0 {
+        return interval;
+    }
+    interval.mul_f64(1.0 + rand::rng().random_range(-spread..=spread))
+}
+
 #[cfg(not(target_family = "wasm"))]
 pub fn random_text(count: usize) -> String {
     use rand::Rng;
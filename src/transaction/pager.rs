@@ -0,0 +1,186 @@
+//! Pager-mode instant messaging (RFC 3428)
+//!
+//! A `MESSAGE` outside of any dialog carries one instant message in its
+//! body. [`Endpoint::send_message`] sends one and waits for the final
+//! response; incoming ones are handed to the endpoint's [`MessageHandler`].
+
+use super::{
+    endpoint::{Endpoint, EndpointInner},
+    key::{TransactionKey, TransactionRole},
+    make_tag,
+    transaction::Transaction,
+};
+use crate::dialog::authenticate::{handle_client_authenticate, Credential};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use rsip::prelude::{HeadersExt, ToTypedHeader, UntypedHeader};
+use rsip::{Header, Response, SipMessage, StatusCode, StatusCodeKind};
+use std::sync::Arc;
+use tracing::{debug, info};
+
+/// An incoming pager-mode MESSAGE
+#[derive(Debug, Clone)]
+pub struct InstantMessage {
+    pub from: rsip::Uri,
+    pub to: rsip::Uri,
+    pub content_type: Option<String>,
+    /// Body after any `Content-Encoding` was decoded
+    pub body: Vec<u8>,
+    pub request: rsip::Request,
+}
+
+/// Receives the MESSAGE requests that arrive outside of a dialog
+///
+/// Set with `EndpointBuilder::with_message_handler`; such requests then no
+/// longer show up in `incoming_transactions`. The returned status is sent as
+/// the final response, usually `200 OK` or `202 Accepted`.
+#[async_trait]
+pub trait MessageHandler: Send + Sync {
+    async fn on_message(&self, message: InstantMessage) -> StatusCode;
+}
+
+impl InstantMessage {
+    fn from_request(request: rsip::Request) -> Result<Self> {
+        let from = request.from_header()?.typed()?.uri;
+        let to = request.to_header()?.typed()?.uri;
+        let content_type = request.headers.iter().find_map(|header| match header {
+            Header::ContentType(content_type) => Some(content_type.value().trim().to_string()),
+            _ => None,
+        });
+        Ok(Self {
+            from,
+            to,
+            content_type,
+            body: request.body.clone(),
+            request,
+        })
+    }
+}
+
+impl EndpointInner {
+    /// Answer an out-of-dialog MESSAGE through the message handler, if one
+    /// is set; returns the transaction back otherwise
+    pub(super) fn dispatch_instant_message(
+        self: &Arc<Self>,
+        mut tx: Transaction,
+    ) -> Option<Transaction> {
+        if tx.original.method != rsip::Method::Message {
+            return Some(tx);
+        }
+        if let Ok(Some(_)) = tx.original.to_header().and_then(|to| to.tag()) {
+            return Some(tx);
+        }
+        if self.message_handler.is_none() {
+            return Some(tx);
+        }
+        let inner = self.clone();
+        tokio::spawn(async move {
+            let status = match InstantMessage::from_request(tx.original.clone()) {
+                Ok(message) => match inner.message_handler.as_ref() {
+                    Some(handler) => handler.on_message(message).await,
+                    None => StatusCode::NotImplemented,
+                },
+                Err(e) => {
+                    info!(key = %tx.key, "invalid message request: {}", e);
+                    StatusCode::BadRequest
+                }
+            };
+            if let Err(e) = tx.reply(status).await {
+                info!(key = %tx.key, "failed to answer message: {}", e);
+            }
+        });
+        None
+    }
+}
+
+impl Endpoint {
+    /// Send a pager-mode MESSAGE and wait for its final response
+    ///
+    /// A `401`/`407` challenge is answered once with `credential`, if given.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use rsipstack::transaction::endpoint::Endpoint;
+    /// # async fn example(endpoint: Endpoint) -> rsipstack::Result<()> {
+    /// let resp = endpoint
+    ///     .send_message(
+    ///         "sip:bob@example.com".try_into()?,
+    ///         "sip:alice@example.com".try_into()?,
+    ///         "text/plain",
+    ///         b"Watson, come here.".to_vec(),
+    ///         None,
+    ///     )
+    ///     .await?;
+    /// println!("message answered with {}", resp.status_code);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_message(
+        &self,
+        to: rsip::Uri,
+        from: rsip::Uri,
+        content_type: &str,
+        body: Vec<u8>,
+        credential: Option<&Credential>,
+    ) -> Result<Response> {
+        let mut seq = 1;
+        let via = self.inner.get_via(None, None)?;
+        let mut request = self.inner.make_request(
+            rsip::Method::Message,
+            to.clone(),
+            via,
+            rsip::typed::From {
+                display_name: None,
+                uri: from,
+                params: vec![],
+            }
+            .with_tag(make_tag()),
+            rsip::typed::To {
+                display_name: None,
+                uri: to,
+                params: vec![],
+            },
+            seq,
+            None,
+            None,
+        );
+        request
+            .headers
+            .push(Header::ContentType(content_type.into()));
+        request.body = body;
+
+        let key = TransactionKey::from_request(&request, TransactionRole::Client)?;
+        let mut tx = Transaction::new_client(key, request, self.inner.clone(), None);
+        tx.send().await?;
+        let mut auth_sent = false;
+        while let Some(msg) = tx.receive().await {
+            let SipMessage::Response(resp) = msg else {
+                break;
+            };
+            if resp.status_code.kind() == StatusCodeKind::Provisional {
+                continue;
+            }
+            let challenged = matches!(
+                resp.status_code,
+                StatusCode::Unauthorized | StatusCode::ProxyAuthenticationRequired
+            );
+            match credential {
+                Some(cred) if challenged && !auth_sent => {
+                    seq += 1;
+                    tx = handle_client_authenticate(seq, tx, resp, cred).await?;
+                    tx.send().await?;
+                    auth_sent = true;
+                }
+                _ => {
+                    debug!(key = %tx.key, status = %resp.status_code, "message answered");
+                    return Ok(resp);
+                }
+            }
+        }
+        Err(Error::TransactionError(
+            "message transaction terminated without a final response".to_string(),
+            tx.key.clone(),
+        ))
+    }
+}
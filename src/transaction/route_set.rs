@@ -0,0 +1,258 @@
+use rsip::{
+    headers::Route,
+    prelude::{ToTypedHeader, UntypedHeader},
+    Header,
+};
+
+/// Ordered SIP route set
+///
+/// Holds the `Route` entries a request has to traverse, next hop first.
+/// It is used both for the preloaded route set of an endpoint (outbound
+/// proxy, Service-Route, Path) and for the route set a dialog learns from
+/// `Record-Route` (RFC 3261 §12.1).
+///
+/// # Examples
+///
+/// ```rust
+/// use rsip::prelude::UntypedHeader;
+/// use rsipstack::transaction::route_set::RouteSet;
+///
+/// let mut route_set = RouteSet::new();
+/// route_set.push("<sip:edge.example.com;lr>".into());
+/// route_set.push("<sip:core.example.com;lr>".into());
+///
+/// // the first entry is the next hop, and the first Route header
+/// assert_eq!(route_set.next_hop().unwrap().value(), "<sip:edge.example.com;lr>");
+/// assert_eq!(route_set.to_headers().len(), 2);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteSet {
+    routes: Vec<Route>,
+}
+
+impl RouteSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Route set in `Record-Route` order, as a UAS builds it from a request
+    pub fn from_record_route(headers: &rsip::Headers) -> Self {
+        let routes = headers
+            .iter()
+            .filter_map(|header| match header {
+                Header::RecordRoute(rr) => Some(Route::from(rr.value())),
+                _ => None,
+            })
+            .collect();
+        Self { routes }
+    }
+
+    /// Route set in reverse `Record-Route` order, as a UAC builds it from a 2xx
+    pub fn from_record_route_reversed(headers: &rsip::Headers) -> Self {
+        let mut route_set = Self::from_record_route(headers);
+        route_set.routes.reverse();
+        route_set
+    }
+
+    /// Route set from the `Route` headers already present in a message
+    pub fn from_routes(headers: &rsip::Headers) -> Self {
+        let routes = headers
+            .iter()
+            .filter_map(|header| match header {
+                Header::Route(route) => Some(route.clone()),
+                _ => None,
+            })
+            .collect();
+        Self { routes }
+    }
+
+    /// Append a route after the existing entries
+    pub fn push(&mut self, route: Route) {
+        self.routes.push(route);
+    }
+
+    /// Insert a route before the existing entries, making it the next hop
+    pub fn push_front(&mut self, route: Route) {
+        self.routes.insert(0, route);
+    }
+
+    /// Remove and return the next hop
+    pub fn pop(&mut self) -> Option<Route> {
+        if self.routes.is_empty() {
+            None
+        } else {
+            Some(self.routes.remove(0))
+        }
+    }
+
+    pub fn next_hop(&self) -> Option<&Route> {
+        self.routes.first()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Route> {
+        self.routes.iter()
+    }
+
+    /// One `Route` header per entry, next hop first
+    pub fn to_headers(&self) -> Vec<Header> {
+        self.routes.iter().cloned().map(Header::Route).collect()
+    }
+
+    /// Replace any `Route` headers in `headers` with this route set
+    pub fn apply(&self, headers: &mut rsip::Headers) {
+        headers.retain(|h| !matches!(h, Header::Route(_)));
+        headers.extend(self.to_headers());
+    }
+}
+
+/// First URI of a `Route` value
+fn route_uri(route: &Route) -> Option<rsip::Uri> {
+    route.typed().ok()?.uris().first().map(|u| u.uri.clone())
+}
+
+/// Consume the `Route` entries of a received request that address this
+/// element (RFC 3261 §16.4)
+///
+/// A Request-URI without a user part that `is_local` accepts was put there
+/// by a strict router, which moved the real Request-URI to the last Route,
+/// so it is restored from there. A top Route that `is_local` accepts is
+/// then removed. Returns whether the request changed.
+pub fn consume_local_routes(
+    req: &mut rsip::Request,
+    is_local: impl Fn(&rsip::Uri) -> bool,
+) -> bool {
+    let mut route_set = RouteSet::from_routes(&req.headers);
+    if route_set.is_empty() {
+        return false;
+    }
+    let mut changed = false;
+    if req.uri.auth.is_none() && is_local(&req.uri) {
+        if let Some(target) = route_set.routes.last().and_then(route_uri) {
+            route_set.routes.pop();
+            req.uri = target;
+            changed = true;
+        }
+    }
+    if route_set
+        .next_hop()
+        .and_then(route_uri)
+        .is_some_and(|uri| is_local(&uri))
+    {
+        route_set.pop();
+        changed = true;
+    }
+    if changed {
+        route_set.apply(&mut req.headers);
+    }
+    changed
+}
+
+impl From<Vec<Route>> for RouteSet {
+    fn from(routes: Vec<Route>) -> Self {
+        Self { routes }
+    }
+}
+
+#[test]
+fn test_route_set_serialization_order() {
+    use rsip::headers::RecordRoute;
+
+    let mut route_set = RouteSet::new();
+    route_set.push(Route::from("<sip:proxy2.example.com:5070;lr>"));
+    route_set.push_front(Route::from("<sip:proxy1.example.com:5060;lr>"));
+
+    let mut headers: rsip::Headers = vec![
+        Header::Route(Route::from("<sip:stale.example.com;lr>")),
+        Header::MaxForwards(70.into()),
+    ]
+    .into();
+    route_set.apply(&mut headers);
+    let routes = headers
+        .iter()
+        .filter_map(|h| match h {
+            Header::Route(route) => Some(route.value().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        routes,
+        vec![
+            "<sip:proxy1.example.com:5060;lr>".to_string(),
+            "<sip:proxy2.example.com:5070;lr>".to_string(),
+        ]
+    );
+
+    // a UAC reverses Record-Route, a UAS keeps its order
+    let rr: rsip::Headers = vec![
+        Header::RecordRoute(RecordRoute::new("<sip:proxy2.example.com:5070;lr>")),
+        Header::RecordRoute(RecordRoute::new("<sip:proxy1.example.com:5060;lr>")),
+    ]
+    .into();
+    assert_eq!(RouteSet::from_record_route_reversed(&rr), route_set);
+    assert_eq!(
+        RouteSet::from_record_route(&rr)
+            .next_hop()
+            .map(|r| r.value()),
+        Some("<sip:proxy2.example.com:5070;lr>")
+    );
+
+    assert_eq!(
+        route_set.pop().map(|r| r.value().to_string()),
+        Some("<sip:proxy1.example.com:5060;lr>".to_string())
+    );
+    assert_eq!(route_set.len(), 1);
+}
+
+#[test]
+fn test_consume_local_routes() {
+    let is_local = |uri: &rsip::Uri| uri.host_with_port.to_string() == "192.0.2.10:5060";
+    let request = |uri: &str, routes: &[&str]| rsip::Request {
+        method: rsip::Method::Bye,
+        uri: rsip::Uri::try_from(uri).unwrap(),
+        headers: routes
+            .iter()
+            .map(|route| Header::Route(Route::from(*route)))
+            .collect::<Vec<_>>()
+            .into(),
+        version: rsip::Version::V2,
+        body: Default::default(),
+    };
+    let routes = |req: &rsip::Request| {
+        RouteSet::from_routes(&req.headers)
+            .iter()
+            .map(|route| route.value().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // loose routing: our own top Route is removed
+    let mut req = request(
+        "sip:bob@198.51.100.7",
+        &["<sip:192.0.2.10:5060;lr>", "<sip:proxy.example.com;lr>"],
+    );
+    assert!(consume_local_routes(&mut req, is_local));
+    assert_eq!(req.uri.to_string(), "sip:bob@198.51.100.7");
+    assert_eq!(routes(&req), vec!["<sip:proxy.example.com;lr>".to_string()]);
+
+    // strict routing: the Request-URI is restored from the last Route
+    let mut req = request(
+        "sip:192.0.2.10:5060",
+        &["<sip:proxy.example.com>", "<sip:bob@198.51.100.7>"],
+    );
+    assert!(consume_local_routes(&mut req, is_local));
+    assert_eq!(req.uri.to_string(), "sip:bob@198.51.100.7");
+    assert_eq!(routes(&req), vec!["<sip:proxy.example.com>".to_string()]);
+
+    // routes to somebody else, or none at all, are left alone
+    let mut req = request("sip:bob@192.0.2.10:5060", &["<sip:proxy.example.com;lr>"]);
+    assert!(!consume_local_routes(&mut req, is_local));
+    let mut req = request("sip:192.0.2.10:5060", &[]);
+    assert!(!consume_local_routes(&mut req, is_local));
+}
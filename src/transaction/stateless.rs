@@ -0,0 +1,153 @@
+//! Stateless proxy forwarding (RFC 3261 §16.11)
+//!
+//! A stateless proxy forwards requests and responses without creating a
+//! `Transaction`: requests get a Via whose branch is derived from the one
+//! they arrived with, so retransmissions and the matching CANCEL are
+//! forwarded with the same branch, and responses are routed by their Via.
+
+use super::{endpoint::EndpointInner, message::decrement_max_forwards};
+use crate::rsip_ext::{destination_from_request, RsipHeadersExt};
+use crate::transport::SipAddr;
+use crate::{Error, Result};
+use md5::{Digest, Md5};
+use rsip::prelude::{HeadersExt, ToTypedHeader, UntypedHeader};
+use rsip::{Request, Response, SipMessage};
+use tracing::debug;
+
+/// Where a response goes by its top Via (RFC 3261 §18.2.2)
+///
+/// `maddr` wins with the `sent-by` port. Otherwise the response goes to the
+/// source address of the request: `received` if present, else the `sent-by`
+/// host, at the `rport` value if present, else the `sent-by` port. Over
+/// TCP/TLS that is the connection the request arrived on, when still open.
+pub fn response_target(via: &rsip::typed::Via) -> SipAddr {
+    let mut addr = via.uri.host_with_port.clone();
+    let maddr = via.params.iter().find_map(|param| match param {
+        rsip::Param::Maddr(maddr) => rsip::HostWithPort::try_from(maddr.to_string().as_str())
+            .ok()
+            .map(|maddr| maddr.host),
+        _ => None,
+    });
+    if let Some(maddr) = maddr {
+        addr.host = maddr;
+    } else {
+        for param in via.params.iter() {
+            match param {
+                rsip::Param::Received(received) => {
+                    if let Ok(ip) = received.value().parse::<std::net::IpAddr>() {
+                        addr.host = ip.into();
+                    }
+                }
+                rsip::Param::Other(name, Some(value))
+                    if name.value().eq_ignore_ascii_case("rport") =>
+                {
+                    if let Ok(port) = value.value().parse::<u16>() {
+                        addr.port = Some(port.into());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    SipAddr {
+        r#type: Some(via.transport),
+        addr,
+    }
+}
+
+/// Branch for a request forwarded statelessly, derived from the top Via it
+/// arrived with
+fn stateless_branch(via: &rsip::headers::Via) -> rsip::Param {
+    let digest = Md5::digest(via.value().trim().as_bytes());
+    let hex = digest
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    rsip::Param::Branch(format!("z9hG4bK{}", hex).into())
+}
+
+impl EndpointInner {
+    /// Send `resp` to the address of its top Via, see [`response_target`]
+    ///
+    /// No transaction is created or looked up, and the Via is left as is;
+    /// use [`forward_response`](Self::forward_response) to relay a response
+    /// carrying this endpoint's Via on top.
+    pub async fn send_stateless_response(&self, resp: Response) -> Result<()> {
+        let via = resp.via_header()?.typed()?;
+        let target = response_target(&via);
+        let (connection, destination) = self.transport_layer.lookup_direct(&target).await?;
+        debug!(%target, status = %resp.status_code, "sending stateless response");
+        let msg = match &self.message_inspector {
+            Some(inspector) => inspector.before_send(resp.into()),
+            None => resp.into(),
+        };
+        self.send_message(&connection, msg, Some(&destination))
+            .await
+    }
+
+    /// Relay a response to a statelessly forwarded request
+    ///
+    /// Removes the top Via, which must be one of this endpoint's addresses,
+    /// then sends the response by the next Via.
+    pub async fn forward_response(&self, mut resp: Response) -> Result<()> {
+        let via = resp.via_header()?.typed()?;
+        let addrs = self.transport_layer.get_addrs();
+        let ours = addrs
+            .iter()
+            .cloned()
+            .map(|addr| self.advertised_addr(addr))
+            .chain(addrs.iter().cloned())
+            .any(|addr| addr.addr == via.uri.host_with_port);
+        if !ours {
+            return Err(Error::EndpointError(format!(
+                "top via {} is not ours",
+                via.uri.host_with_port
+            )));
+        }
+        let mut popped = false;
+        resp.headers.retain(|header| {
+            if !popped && matches!(header, rsip::Header::Via(_)) {
+                popped = true;
+                return false;
+            }
+            true
+        });
+        self.send_stateless_response(resp).await
+    }
+
+    /// Forward `req` statelessly to `target`
+    ///
+    /// `Max-Forwards` is decremented, failing with
+    /// [`Error::TooManyHops`](crate::Error::TooManyHops) for a request that
+    /// arrived with `Max-Forwards: 0`; answer it with `483 Too Many Hops`
+    /// instead. A Via of this endpoint is added on top. Without `target` the
+    /// request goes to its top Route, or else its Request-URI, through the
+    /// locator if one is set.
+    pub async fn forward_request(&self, mut req: Request, target: Option<SipAddr>) -> Result<()> {
+        decrement_max_forwards(&mut req)?;
+        let branch = stateless_branch(req.via_header()?);
+        let via = self.get_via(None, Some(branch))?;
+        req.headers.push_front(via.into());
+
+        let target = match target {
+            Some(target) => target,
+            None => {
+                let uri = destination_from_request(&req)
+                    .map(|uri| uri.into_owned())
+                    .unwrap_or_else(|| req.uri.clone());
+                match self.locator.as_ref() {
+                    Some(locator) => locator.locate(&uri).await?,
+                    None => SipAddr::try_from(&uri)?,
+                }
+            }
+        };
+        let (connection, destination) = self.transport_layer.lookup(&target, None).await?;
+        debug!(%target, method = %req.method, "forwarding stateless request");
+        let msg: SipMessage = match &self.message_inspector {
+            Some(inspector) => inspector.before_send(req.into()),
+            None => req.into(),
+        };
+        self.send_message(&connection, msg, Some(&destination))
+            .await
+    }
+}
@@ -1,11 +1,15 @@
 use super::{endpoint::Endpoint, EndpointBuilder};
 use crate::{
-    transport::{udp::UdpConnection, TransportLayer},
+    transport::{udp::UdpConnection, SipConnection, TransportLayer},
     Result,
 };
+use rsip::headers::*;
+use std::{net::SocketAddr, time::Duration};
+use tokio::net::UdpSocket;
 use tokio_util::sync::CancellationToken;
 
 mod test_client;
+mod test_compression;
 mod test_endpoint;
 mod test_server;
 mod test_transaction_states;
@@ -25,6 +29,59 @@ pub(super) async fn create_test_endpoint(addr: Option<&str>) -> Result<Endpoint>
         .build();
     Ok(endpoint)
 }
+/// UDP socket playing the remote party, and a connection of ours to hand
+/// what it sends to `on_received_message` with
+pub(super) async fn create_test_peer() -> Result<(UdpSocket, SipConnection)> {
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let conn = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+    Ok((peer, conn.into()))
+}
+
+/// Out-of-dialog request sent by `peer_addr`, which its Via names so that
+/// responses go back there; the Call-ID is made from `branch`
+pub(super) fn create_peer_request(
+    method: rsip::Method,
+    peer_addr: SocketAddr,
+    branch: &str,
+) -> rsip::Request {
+    rsip::Request {
+        method,
+        uri: rsip::Uri::try_from("sip:alice@restsend.com").unwrap(),
+        headers: vec![
+            Via::new(format!("SIP/2.0/UDP {};branch={}", peer_addr, branch)).into(),
+            CSeq::new(format!("1 {}", method)).into(),
+            From::new("Bob <sip:bob@restsend.com>;tag=peer-tag").into(),
+            To::new("<sip:alice@restsend.com>").into(),
+            CallId::new(format!("{}@restsend.com", branch)).into(),
+        ]
+        .into(),
+        version: rsip::Version::V2,
+        body: Default::default(),
+    }
+}
+
+/// Next response `peer` receives within `wait`, `None` when nothing came
+pub(super) async fn recv_response_within(
+    peer: &UdpSocket,
+    wait: Duration,
+) -> Result<Option<rsip::Response>> {
+    let mut buf = [0u8; 65535];
+    match tokio::time::timeout(wait, peer.recv_from(&mut buf)).await {
+        Ok(received) => {
+            let (len, _) = received?;
+            Ok(Some(rsip::SipMessage::try_from(&buf[..len])?.try_into()?))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+/// Next response `peer` receives, failing after a second
+pub(super) async fn recv_response(peer: &UdpSocket) -> Result<rsip::Response> {
+    recv_response_within(peer, Duration::from_secs(1))
+        .await?
+        .ok_or_else(|| crate::Error::Error("timeout waiting for response".to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
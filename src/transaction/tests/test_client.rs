@@ -1,13 +1,17 @@
 use crate::rsip_ext::RsipResponseExt;
+use crate::transaction::endpoint::{EndpointBuilder, EndpointOption};
 use crate::transaction::key::{TransactionKey, TransactionRole};
 use crate::transaction::transaction::Transaction;
+use crate::transaction::{TransactionState, TransactionType};
 use crate::transport::udp::UdpConnection;
-use crate::transport::SipAddr;
+use crate::transport::{SipAddr, TransportLayer};
 use crate::{transport::TransportEvent, Result};
+use rsip::prelude::{HeadersExt, ToTypedHeader};
 use rsip::{headers::*, Header, Response, SipMessage, Uri};
 use std::convert::TryFrom;
 use std::time::Duration;
 use tokio::{select, sync::mpsc::unbounded_channel, time::sleep};
+use tokio_util::sync::CancellationToken;
 use tracing::info;
 
 #[tokio::test]
@@ -191,3 +195,438 @@ Content-Length: 0\r\n\r\n";
     assert_eq!(ack.uri, expected_uri, "ACK must target the remote Contact");
     Ok(())
 }
+
+#[tokio::test]
+async fn test_timer_c_cancels_proceeding_invite() -> Result<()> {
+    let token = CancellationToken::new();
+    let tl = TransportLayer::new(token.child_token());
+    let local = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+    tl.add_transport(local.into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(tl)
+        .with_option(EndpointOption {
+            timerc: Duration::from_millis(300),
+            ..Default::default()
+        })
+        .build();
+
+    let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let peer_addr = peer.local_addr()?;
+    let invite = endpoint.inner.make_request(
+        rsip::Method::Invite,
+        Uri::try_from(format!("sip:bob@{}", peer_addr).as_str())?,
+        endpoint.inner.get_via(None, None)?,
+        rsip::typed::From {
+            display_name: None,
+            uri: Uri::try_from("sip:alice@example.com")?,
+            params: vec![rsip::Param::Tag("timer-c".into())],
+        },
+        rsip::typed::To {
+            display_name: None,
+            uri: Uri::try_from("sip:bob@example.com")?,
+            params: vec![],
+        },
+        1,
+        None,
+        None,
+    );
+
+    // the peer rings forever and only reports the CANCEL it gets
+    let ringing = endpoint
+        .inner
+        .make_response(&invite, rsip::StatusCode::Ringing, None);
+    let peer_loop = tokio::spawn(async move {
+        let mut buf = vec![0u8; 4096];
+        let mut rang = false;
+        loop {
+            let (len, from) = peer.recv_from(&mut buf).await.expect("peer recv");
+            let msg = SipMessage::try_from(std::str::from_utf8(&buf[..len]).unwrap())
+                .expect("parse request");
+            match msg {
+                SipMessage::Request(req) if req.method == rsip::Method::Invite && !rang => {
+                    rang = true;
+                    peer.send_to(ringing.to_string().as_bytes(), from)
+                        .await
+                        .expect("send ringing");
+                }
+                SipMessage::Request(req) if req.method == rsip::Method::Cancel => return req,
+                _ => {}
+            }
+        }
+    });
+
+    let key = TransactionKey::from_request(&invite, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, invite.clone(), endpoint.inner.clone(), None);
+    let recv_loop = async {
+        tx.send().await.expect("send invite");
+        let mut statuses = vec![];
+        while let Some(SipMessage::Response(resp)) = tx.receive().await {
+            statuses.push(resp.status_code.clone());
+            if resp.status_code == rsip::StatusCode::RequestTimeout {
+                break;
+            }
+        }
+        statuses
+    };
+
+    let statuses = select! {
+        statuses = recv_loop => statuses,
+        _ = endpoint.serve() => panic!("endpoint stopped"),
+        _ = sleep(Duration::from_secs(3)) => panic!("timer C never fired"),
+    };
+    assert_eq!(
+        statuses,
+        vec![rsip::StatusCode::Ringing, rsip::StatusCode::RequestTimeout]
+    );
+
+    let cancel = tokio::time::timeout(Duration::from_secs(1), peer_loop)
+        .await
+        .expect("peer never saw a CANCEL")
+        .expect("peer task");
+    assert_eq!(cancel.uri, invite.uri);
+    assert_eq!(
+        cancel.via_header()?.typed()?.branch(),
+        invite.via_header()?.typed()?.branch()
+    );
+    assert_eq!(cancel.cseq_header()?.method()?, rsip::Method::Cancel);
+    Ok(())
+}
+
+#[test]
+fn test_retransmission_jitter_stays_within_bound() {
+    assert_eq!(EndpointOption::default().retransmission_jitter, None);
+
+    let interval = Duration::from_millis(500);
+    assert_eq!(crate::transaction::jitter_duration(interval, 0), interval);
+
+    let samples = (0..200)
+        .map(|_| crate::transaction::jitter_duration(interval, 20))
+        .collect::<Vec<_>>();
+    for delay in &samples {
+        assert!(
+            *delay >= Duration::from_millis(400) && *delay <= Duration::from_millis(600),
+            "{:?} outside ±20% of {:?}",
+            delay,
+            interval
+        );
+    }
+    assert!(
+        samples.iter().any(|delay| *delay != samples[0]),
+        "jittered intervals must vary"
+    );
+}
+
+#[tokio::test]
+async fn test_transaction_stats_count_retransmissions() -> Result<()> {
+    let token = CancellationToken::new();
+    let tl = TransportLayer::new(token.child_token());
+    let local = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+    tl.add_transport(local.into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(tl)
+        .with_option(EndpointOption {
+            t1: Duration::from_millis(50),
+            ..Default::default()
+        })
+        .build();
+
+    // the peer never answers, so Timer E keeps resending the OPTIONS
+    let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let options = endpoint.inner.make_request(
+        rsip::Method::Options,
+        Uri::try_from(format!("sip:bob@{}", peer.local_addr()?).as_str())?,
+        endpoint.inner.get_via(None, None)?,
+        rsip::typed::From {
+            display_name: None,
+            uri: Uri::try_from("sip:alice@example.com")?,
+            params: vec![rsip::Param::Tag("stats".into())],
+        },
+        rsip::typed::To {
+            display_name: None,
+            uri: Uri::try_from("sip:bob@example.com")?,
+            params: vec![],
+        },
+        1,
+        None,
+        None,
+    );
+    let key = TransactionKey::from_request(&options, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, options, endpoint.inner.clone(), None);
+    assert_eq!(
+        endpoint.stats().by_state.get(&TransactionState::Nothing),
+        Some(&1)
+    );
+    tx.send().await?;
+
+    // timers reach the transaction while its TU receives
+    select! {
+        _ = endpoint.serve() => panic!("endpoint stopped"),
+        _ = async { while tx.receive().await.is_some() {} } => panic!("transaction ended"),
+        _ = sleep(Duration::from_millis(400)) => {}
+    }
+    let stats = endpoint.stats();
+    assert_eq!(
+        stats.by_type.get(&TransactionType::ClientNonInvite),
+        Some(&1)
+    );
+    assert_eq!(stats.by_state.get(&TransactionState::Calling), Some(&1));
+    assert!(
+        stats.retransmissions >= 2,
+        "retransmissions: {}",
+        stats.retransmissions
+    );
+    assert!(stats.pending_timers >= 2, "Timer E and Timer F are armed");
+
+    drop(tx);
+    let stats = endpoint.stats();
+    assert!(stats.by_type.is_empty());
+    assert!(stats.by_state.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_register_retransmits_with_timer_e() -> Result<()> {
+    let token = CancellationToken::new();
+    let tl = TransportLayer::new(token.child_token());
+    let local = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+    tl.add_transport(local.into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(tl)
+        .with_timer_interval(Duration::from_millis(5))
+        .with_option(EndpointOption {
+            t1: Duration::from_millis(50),
+            t2: Duration::from_millis(200),
+            ..Default::default()
+        })
+        .build();
+
+    // the registrar never answers and records when each copy arrives
+    let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let register = endpoint.inner.make_request(
+        rsip::Method::Register,
+        Uri::try_from(format!("sip:{}", peer.local_addr()?).as_str())?,
+        endpoint.inner.get_via(None, None)?,
+        rsip::typed::From {
+            display_name: None,
+            uri: Uri::try_from("sip:alice@example.com")?,
+            params: vec![rsip::Param::Tag("timer-e".into())],
+        },
+        rsip::typed::To {
+            display_name: None,
+            uri: Uri::try_from("sip:alice@example.com")?,
+            params: vec![],
+        },
+        1,
+        None,
+        None,
+    );
+    let peer_loop = async {
+        let mut buf = vec![0u8; 4096];
+        let mut arrivals = vec![];
+        while arrivals.len() < 6 {
+            peer.recv_from(&mut buf).await.expect("peer recv");
+            arrivals.push(std::time::Instant::now());
+        }
+        arrivals
+    };
+
+    let key = TransactionKey::from_request(&register, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, register, endpoint.inner.clone(), None);
+    tx.send().await?;
+    let arrivals = select! {
+        arrivals = peer_loop => arrivals,
+        _ = endpoint.serve() => panic!("endpoint stopped"),
+        _ = async { while tx.receive().await.is_some() {} } => panic!("transaction ended"),
+        _ = sleep(Duration::from_secs(3)) => panic!("REGISTER was not retransmitted"),
+    };
+
+    // T1, 2*T1, then capped at T2
+    let expected = [50, 100, 200, 200, 200];
+    for (i, pair) in arrivals.windows(2).enumerate() {
+        let interval = (pair[1] - pair[0]).as_millis() as i64;
+        assert!(
+            (interval - expected[i]).abs() <= 40,
+            "retransmission {} after {}ms, expected {}ms",
+            i + 1,
+            interval,
+            expected[i]
+        );
+    }
+    Ok(())
+}
+
+fn large_options(
+    endpoint: &crate::transaction::endpoint::Endpoint,
+    target: std::net::SocketAddr,
+) -> Result<rsip::Request> {
+    let mut options = endpoint.inner.make_request(
+        rsip::Method::Options,
+        Uri::try_from(format!("sip:bob@{}", target).as_str())?,
+        endpoint.inner.get_via(None, None)?,
+        rsip::typed::From {
+            display_name: None,
+            uri: Uri::try_from("sip:alice@example.com")?,
+            params: vec![rsip::Param::Tag("mtu".into())],
+        },
+        rsip::typed::To {
+            display_name: None,
+            uri: Uri::try_from("sip:bob@example.com")?,
+            params: vec![],
+        },
+        1,
+        None,
+        None,
+    );
+    for i in 0..20 {
+        options
+            .headers
+            .push(Header::Other(format!("X-Padding-{}", i), "x".repeat(64)));
+    }
+    assert!(options.to_string().len() > 1300);
+    Ok(options)
+}
+
+#[tokio::test]
+async fn test_large_request_switches_from_udp_to_tcp() -> Result<()> {
+    let endpoint = super::create_test_endpoint(Some("127.0.0.1:0")).await?;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let target = listener.local_addr()?;
+    let udp_peer = tokio::net::UdpSocket::bind(target).await?;
+
+    let options = large_options(&endpoint, target)?;
+    let key = TransactionKey::from_request(&options, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, options, endpoint.inner.clone(), None);
+    tx.send().await?;
+    assert!(tx.connection.as_ref().unwrap().is_reliable());
+
+    let (mut stream, _) = tokio::time::timeout(Duration::from_secs(1), listener.accept())
+        .await
+        .expect("large request did not open a tcp connection")?;
+    let mut buf = vec![0u8; 8192];
+    let n = tokio::time::timeout(
+        Duration::from_secs(1),
+        tokio::io::AsyncReadExt::read(&mut stream, &mut buf),
+    )
+    .await
+    .expect("no request on the tcp connection")?;
+    let received = rsip::Request::try_from(&buf[..n])?;
+    let via = received.via_header()?.typed()?;
+    assert_eq!(via.transport, rsip::transport::Transport::Tcp);
+    // no tcp listener, so the sent-by is the local end of the connection
+    assert_eq!(via.uri.host_with_port, stream.peer_addr()?.into());
+
+    let mut buf = [0u8; 2048];
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), udp_peer.recv_from(&mut buf))
+            .await
+            .is_err(),
+        "large request was also sent over udp"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_large_request_stays_on_udp_without_tcp() -> Result<()> {
+    let endpoint = super::create_test_endpoint(Some("127.0.0.1:0")).await?;
+    let udp_peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let target = udp_peer.local_addr()?;
+
+    let options = large_options(&endpoint, target)?;
+    let key = TransactionKey::from_request(&options, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, options, endpoint.inner.clone(), None);
+    tx.send().await?;
+    assert!(!tx.connection.as_ref().unwrap().is_reliable());
+
+    let mut buf = [0u8; 8192];
+    let (n, _) = tokio::time::timeout(Duration::from_secs(1), udp_peer.recv_from(&mut buf))
+        .await
+        .expect("large request was not sent over udp")?;
+    let received = rsip::Request::try_from(&buf[..n])?;
+    let via = received.via_header()?.typed()?;
+    assert_eq!(via.transport, rsip::transport::Transport::Udp);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transaction_terminates_when_connection_closes() -> Result<()> {
+    let endpoint = super::create_test_endpoint(Some("127.0.0.1:0")).await?;
+    let endpoint_inner = endpoint.inner.clone();
+    tokio::spawn(async move { endpoint_inner.serve().await });
+    let mut closed = endpoint.inner.subscribe_closed_connections();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let target = listener.local_addr()?;
+    let _udp_peer = tokio::net::UdpSocket::bind(target).await?;
+
+    let options = large_options(&endpoint, target)?;
+    let key = TransactionKey::from_request(&options, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, options, endpoint.inner.clone(), None);
+    tx.send().await?;
+    let addr = tx.connection.as_ref().unwrap().get_addr().clone();
+
+    let (stream, _) = tokio::time::timeout(Duration::from_secs(1), listener.accept())
+        .await
+        .expect("request did not open a tcp connection")?;
+    drop(stream);
+
+    let received = tokio::time::timeout(Duration::from_secs(1), tx.receive())
+        .await
+        .expect("transaction outlived its connection");
+    assert!(received.is_none());
+    assert_eq!(tx.state, TransactionState::Terminated);
+    let closed_addr = tokio::time::timeout(Duration::from_secs(1), closed.recv())
+        .await
+        .expect("closed connection was not reported")
+        .expect("closed connection channel ended");
+    assert_eq!(closed_addr, addr);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_with_destination_sets_the_via_transport() -> Result<()> {
+    let endpoint = super::create_test_endpoint(Some("127.0.0.1:0")).await?;
+    let options = endpoint.inner.make_request(
+        rsip::Method::Options,
+        Uri::try_from("sip:bob@proxy.invalid")?,
+        endpoint.inner.get_via(None, None)?,
+        rsip::typed::From {
+            display_name: None,
+            uri: Uri::try_from("sip:alice@example.com")?,
+            params: vec![rsip::Param::Tag("destination".into())],
+        },
+        rsip::typed::To {
+            display_name: None,
+            uri: Uri::try_from("sip:bob@example.com")?,
+            params: vec![],
+        },
+        1,
+        None,
+        None,
+    );
+    let key = TransactionKey::from_request(&options, TransactionRole::Client)?;
+    let proxy = SipAddr::new(
+        rsip::transport::Transport::Tcp,
+        rsip::HostWithPort::try_from("192.0.2.10:5060")?,
+    );
+    let tx = Transaction::new_client(key, options, endpoint.inner.clone(), None)
+        .with_destination(proxy.clone());
+    assert_eq!(tx.destination, Some(proxy));
+    assert_eq!(
+        tx.original.via_header()?.typed()?.transport,
+        rsip::transport::Transport::Tcp
+    );
+
+    // without a transport of its own, the destination follows the Via
+    let key = TransactionKey::from_request(&tx.original, TransactionRole::Client)?;
+    let bare = SipAddr::try_from(&Uri::try_from("sip:192.0.2.10:5060")?)?;
+    let tx = Transaction::new_client(key, tx.original.clone(), endpoint.inner.clone(), None)
+        .with_destination(bare);
+    assert_eq!(
+        tx.destination.as_ref().and_then(|d| d.r#type),
+        Some(rsip::transport::Transport::Tcp)
+    );
+    Ok(())
+}
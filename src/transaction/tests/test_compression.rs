@@ -0,0 +1,144 @@
+use crate::transaction::compression::{
+    compress_body, decode_message_body, decompress_body, encode_message_body, BodyDecodeError,
+    ContentEncoding, MAX_DECOMPRESSED_BODY_SIZE,
+};
+use rsip::{
+    headers::*,
+    prelude::{HasHeaders, HeadersExt, UntypedHeader},
+    SipMessage,
+};
+
+fn has_content_encoding(msg: &SipMessage) -> bool {
+    msg.headers()
+        .iter()
+        .any(|h| matches!(h, rsip::Header::ContentEncoding(_)))
+}
+
+fn body_of(msg: &SipMessage) -> &[u8] {
+    match msg {
+        SipMessage::Request(req) => &req.body,
+        SipMessage::Response(resp) => &resp.body,
+    }
+}
+
+fn encoded_options(encoding: &str, body: Vec<u8>, sent_by: std::net::SocketAddr) -> rsip::Request {
+    let mut request = super::create_peer_request(rsip::Method::Options, sent_by, "z9hG4bKencoded");
+    request
+        .headers
+        .push(ContentType::new("application/sdp").into());
+    request
+        .headers
+        .push(rsip::headers::ContentEncoding::new(encoding).into());
+    request.body = body;
+    request
+}
+
+#[test]
+fn test_gzip_body_roundtrip() -> crate::Result<()> {
+    let body = "v=0\r\na=candidate:1 1 UDP 2130706431 192.168.1.100 9000 typ host\r\n".repeat(64);
+    let mut msg: SipMessage = rsip::Response {
+        status_code: rsip::StatusCode::OK,
+        version: rsip::Version::V2,
+        headers: vec![
+            Via::new("SIP/2.0/UDP alice.example.com:5060;branch=z9hG4bKnashds").into(),
+            CSeq::new("1 INVITE").into(),
+            CallId::new("compression-call").into(),
+            ContentType::new("application/sdp").into(),
+        ]
+        .into(),
+        body: body.as_bytes().to_vec(),
+    }
+    .into();
+
+    encode_message_body(&mut msg, ContentEncoding::Gzip)?;
+    assert!(body_of(&msg).len() < body.len());
+    assert!(has_content_encoding(&msg));
+
+    decode_message_body(&mut msg, MAX_DECOMPRESSED_BODY_SIZE)?;
+    assert_eq!(body_of(&msg), body.as_bytes());
+    assert!(!has_content_encoding(&msg));
+
+    // the cap rejects bodies that expand beyond it
+    let compressed = compress_body(body.as_bytes(), ContentEncoding::Gzip)?;
+    assert!(decompress_body(&compressed, ContentEncoding::Gzip, 16).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_decode_message_body_errors() -> crate::Result<()> {
+    let body = b"v=0\r\n".repeat(64);
+    let compressed = compress_body(&body, ContentEncoding::Gzip)?;
+    let sent_by = "127.0.0.1:5060".parse()?;
+
+    let mut msg = encoded_options("gzip", compressed.clone(), sent_by).into();
+    assert!(matches!(
+        decode_message_body(&mut msg, 16),
+        Err(BodyDecodeError::TooLarge(16))
+    ));
+
+    let mut msg = encoded_options("gzip", b"not gzip at all".to_vec(), sent_by).into();
+    assert!(matches!(
+        decode_message_body(&mut msg, MAX_DECOMPRESSED_BODY_SIZE),
+        Err(BodyDecodeError::Corrupt(_))
+    ));
+
+    let mut msg = encoded_options("br", compressed, sent_by).into();
+    let e = decode_message_body(&mut msg, MAX_DECOMPRESSED_BODY_SIZE).unwrap_err();
+    assert_eq!(e.status_code(), rsip::StatusCode::UnsupportedMediaType);
+
+    let mut msg = encoded_options("identity", body.clone(), sent_by).into();
+    decode_message_body(&mut msg, MAX_DECOMPRESSED_BODY_SIZE)?;
+    assert_eq!(body_of(&msg), body.as_slice());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_undecodable_request_body_is_answered() -> crate::Result<()> {
+    let endpoint = super::create_test_endpoint(None).await?;
+    let (peer, conn) = super::create_test_peer().await?;
+    let peer_addr = peer.local_addr()?;
+    let mut incoming = endpoint.incoming_transactions()?;
+
+    let cases = [
+        (
+            "br",
+            b"v=0\r\n".to_vec(),
+            rsip::StatusCode::UnsupportedMediaType,
+        ),
+        (
+            "gzip",
+            b"not gzip at all".to_vec(),
+            rsip::StatusCode::BadRequest,
+        ),
+    ];
+    for (encoding, body, status_code) in cases {
+        endpoint
+            .inner
+            .on_received_message(
+                encoded_options(encoding, body, peer_addr).into(),
+                conn.clone(),
+                &peer_addr.into(),
+            )
+            .await?;
+
+        let resp = super::recv_response(&peer).await?;
+        assert_eq!(resp.status_code, status_code, "{}", encoding);
+        if status_code == rsip::StatusCode::UnsupportedMediaType {
+            let accept_encoding = resp
+                .headers
+                .iter()
+                .find_map(|h| match h {
+                    rsip::Header::AcceptEncoding(v) => Some(v.value().to_string()),
+                    _ => None,
+                })
+                .expect("415 carries Accept-Encoding");
+            assert_eq!(accept_encoding, "gzip, deflate");
+        }
+        assert_eq!(resp.cseq_header()?.value(), "1 OPTIONS");
+    }
+    assert!(
+        incoming.try_recv().is_err(),
+        "request must not reach the TU"
+    );
+    Ok(())
+}
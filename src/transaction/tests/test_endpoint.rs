@@ -27,7 +27,7 @@ async fn test_endpoint_serve() {
     select! {
         _ = async {
             sleep(Duration::from_millis(100)).await;
-            endpoint.shutdown();
+            endpoint.shutdown(Duration::ZERO).await;
         } => {
         }
         _ = async {
@@ -39,7 +39,7 @@ async fn test_endpoint_serve() {
             assert!(false, "must not reach here");
         }
     }
-    endpoint.shutdown();
+    endpoint.shutdown(Duration::ZERO).await;
 }
 
 #[tokio::test]
@@ -112,3 +112,814 @@ async fn test_endpoint_recvrequests() {
         }
     }
 }
+
+#[tokio::test]
+async fn test_too_many_via_headers_rejected_with_483() -> crate::Result<()> {
+    let endpoint = super::create_test_endpoint(None).await?;
+    let max_via_headers = endpoint
+        .inner
+        .option
+        .max_via_headers
+        .expect("via cap enabled by default");
+    let (peer, conn) = super::create_test_peer().await?;
+    let peer_addr = peer.local_addr()?;
+
+    let mut options_req =
+        super::create_peer_request(rsip::Method::Options, peer_addr, "z9hG4bKloop0");
+    for i in 1..=max_via_headers {
+        options_req
+            .headers
+            .push(Via::new(format!("SIP/2.0/UDP {};branch=z9hG4bKloop{}", peer_addr, i)).into());
+    }
+
+    let mut incoming = endpoint.incoming_transactions()?;
+    endpoint
+        .inner
+        .on_received_message(options_req.into(), conn.clone(), &peer_addr.into())
+        .await?;
+
+    let resp = super::recv_response(&peer).await?;
+    assert_eq!(resp.status_code, rsip::StatusCode::TooManyHops);
+
+    // the same hops folded into one comma-joined Via count alike
+    let mut folded_req =
+        super::create_peer_request(rsip::Method::Options, peer_addr, "z9hG4bKfold0");
+    let hops = (1..=max_via_headers)
+        .map(|i| format!("SIP/2.0/UDP {};branch=z9hG4bKfold{}", peer_addr, i))
+        .collect::<Vec<_>>();
+    folded_req.headers.push(Via::new(hops.join(", ")).into());
+    endpoint
+        .inner
+        .on_received_message(folded_req.into(), conn, &peer_addr.into())
+        .await?;
+
+    let resp = super::recv_response(&peer).await?;
+    assert_eq!(resp.status_code, rsip::StatusCode::TooManyHops);
+    assert!(
+        incoming.try_recv().is_err(),
+        "request must not reach the TU"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_capture_sink_mirrors_traffic() -> crate::Result<()> {
+    use crate::transport::capture::CaptureDirection;
+
+    let token = tokio_util::sync::CancellationToken::new();
+    let tl = crate::transport::TransportLayer::new(token.child_token());
+    let conn =
+        crate::transport::udp::UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None)
+            .await?;
+    tl.add_transport(conn.into());
+    let (capture_tx, mut capture_rx) = tokio::sync::mpsc::channel(16);
+    let endpoint = crate::EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(tl)
+        .with_capture_sink(capture_tx)
+        .build();
+    let addr = endpoint
+        .get_addrs()
+        .first()
+        .expect("must has connection")
+        .to_owned();
+
+    let (peer, _) = super::create_test_peer().await?;
+    let peer_addr = peer.local_addr()?;
+    let options_req =
+        super::create_peer_request(rsip::Method::Options, peer_addr, "z9hG4bKcapture");
+    let buf = options_req.to_string();
+    peer.send_to(buf.as_bytes(), addr.get_socketaddr()?).await?;
+
+    let mut incoming = endpoint.incoming_transactions()?;
+    select! {
+        _ = endpoint.serve() => panic!("serve must not exit"),
+        _ = sleep(Duration::from_secs(1)) => panic!("timeout waiting for OPTIONS"),
+        tx = incoming.recv() => {
+            let mut tx = tx.expect("incoming");
+            tx.reply(rsip::StatusCode::OK).await?;
+        }
+    }
+
+    let inbound = capture_rx.try_recv().expect("inbound captured");
+    assert_eq!(inbound.direction, CaptureDirection::Inbound);
+    assert_eq!(inbound.connection, addr);
+    assert_eq!(
+        inbound
+            .remote
+            .and_then(|remote| remote.get_socketaddr().ok()),
+        Some(peer_addr)
+    );
+    assert!(matches!(inbound.message, rsip::SipMessage::Request(_)));
+
+    let outbound = capture_rx.try_recv().expect("outbound captured");
+    assert_eq!(outbound.direction, CaptureDirection::Outbound);
+    assert_eq!(outbound.transport, rsip::Transport::Udp);
+    assert_eq!(
+        outbound
+            .remote
+            .and_then(|remote| remote.get_socketaddr().ok()),
+        Some(peer_addr)
+    );
+    match outbound.message {
+        rsip::SipMessage::Response(resp) => assert_eq!(resp.status_code, rsip::StatusCode::OK),
+        _ => panic!("expected a response"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_make_response_keeps_record_route_order() -> crate::Result<()> {
+    let endpoint = super::create_test_endpoint(None).await?;
+    let invite_req = rsip::Request {
+        method: rsip::Method::Invite,
+        uri: rsip::Uri::try_from("sip:alice@restsend.com")?,
+        headers: vec![
+            Via::new("SIP/2.0/UDP 127.0.0.1:5060;branch=z9hG4bKrr").into(),
+            RecordRoute::new("<sip:proxy2.restsend.com;lr>").into(),
+            CSeq::new("1 INVITE").into(),
+            RecordRoute::new("<sip:proxy1.restsend.com;lr>").into(),
+            From::new("Bob <sip:bob@restsend.com>;tag=rr-tag").into(),
+            To::new("<sip:alice@restsend.com>").into(),
+            CallId::new("record-route@restsend.com").into(),
+        ]
+        .into(),
+        version: rsip::Version::V2,
+        body: Default::default(),
+    };
+
+    let resp = endpoint
+        .inner
+        .make_response(&invite_req, rsip::StatusCode::OK, None);
+    let record_routes: Vec<String> = resp
+        .headers
+        .iter()
+        .filter_map(|h| match h {
+            rsip::Header::RecordRoute(rr) => {
+                Some(rsip::prelude::UntypedHeader::value(rr).to_string())
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        record_routes,
+        vec![
+            "<sip:proxy2.restsend.com;lr>",
+            "<sip:proxy1.restsend.com;lr>"
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_overload_rejects_new_requests_with_503() -> crate::Result<()> {
+    use crate::transaction::load_control::{LoadControl, LoadSignal};
+
+    struct FixedLoad(usize);
+    impl LoadSignal for FixedLoad {
+        fn load(&self) -> usize {
+            self.0
+        }
+    }
+
+    let option = crate::transaction::endpoint::EndpointOption {
+        overload_threshold: Some(100),
+        // oc-validity in milliseconds no longer fits a u32
+        overload_retry_after: 5_000_000,
+        ..Default::default()
+    };
+    let endpoint = crate::EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_option(option)
+        .with_load_signal(Box::new(FixedLoad(125)))
+        .build();
+
+    let (peer, conn) = super::create_test_peer().await?;
+    let peer_addr = peer.local_addr()?;
+    let invite = |to: &str, branch: &str| {
+        let mut invite = super::create_peer_request(rsip::Method::Invite, peer_addr, branch);
+        invite.headers.unique_push(
+            Via::new(format!(
+                "SIP/2.0/UDP {};branch={};oc-algo=\"loss\", SIP/2.0/UDP proxy.example.com;branch=z9hG4bKproxy",
+                peer_addr, branch
+            ))
+            .into(),
+        );
+        invite.headers.unique_push(To::new(to).into());
+        invite
+    };
+
+    let mut incoming = endpoint.incoming_transactions()?;
+    endpoint
+        .inner
+        .on_received_message(
+            invite("<sip:alice@restsend.com>", "z9hG4bKnew").into(),
+            conn.clone(),
+            &peer_addr.into(),
+        )
+        .await?;
+
+    let resp = super::recv_response(&peer).await?;
+    assert_eq!(resp.status_code, rsip::StatusCode::ServiceUnavailable);
+    assert_eq!(
+        crate::rsip_ext::header_value_case_insensitive(&resp.headers, "Retry-After").as_deref(),
+        Some("5000000")
+    );
+    // 25 of 125 above the threshold: the client is asked to shed 20%
+    let via = rsip::prelude::HeadersExt::via_header(&resp)?.clone();
+    assert_eq!(
+        crate::transaction::load_control::oc_reduction(&via),
+        Some((20, Duration::from_secs(5_000_000)))
+    );
+    // the oc parameters go on the first via-parm, the proxy's one is kept
+    let vias = crate::rsip_ext::split_header_list(via.value());
+    assert_eq!(vias.len(), 2);
+    assert!(vias[0].contains(";oc=20;"), "{}", vias[0]);
+    assert_eq!(vias[1], "SIP/2.0/UDP proxy.example.com;branch=z9hG4bKproxy");
+    assert!(
+        incoming.try_recv().is_err(),
+        "request must not reach the TU"
+    );
+
+    // in-dialog requests still pass
+    endpoint
+        .inner
+        .on_received_message(
+            invite("<sip:alice@restsend.com>;tag=alice-tag", "z9hG4bKreinvite").into(),
+            conn,
+            &peer_addr.into(),
+        )
+        .await?;
+    let tx = incoming
+        .try_recv()
+        .expect("in-dialog request reaches the TU");
+    assert_eq!(tx.original.method, rsip::Method::Invite);
+
+    // and a client honors the reduction it is given
+    let load_control = LoadControl::default();
+    let server: crate::transport::SipAddr = peer_addr.into();
+    load_control.record(&server.addr, &via);
+    assert_eq!(load_control.reduction(&server.addr), Some(20));
+    let full = Via::new(format!(
+        "SIP/2.0/UDP {};branch=z9hG4bKfull;oc=100;oc-validity=1000",
+        peer_addr
+    ));
+    load_control.record(&server.addr, &full);
+    assert!(!load_control.admit(&server.addr));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shutdown_rejects_pending_and_new_requests() -> crate::Result<()> {
+    use rsip::prelude::HeadersExt;
+
+    let endpoint = super::create_test_endpoint(None).await?;
+    let endpoint_inner = endpoint.inner.clone();
+    tokio::spawn(async move {
+        endpoint_inner.serve().await.ok();
+    });
+
+    let (peer, conn) = super::create_test_peer().await?;
+    let peer_addr = peer.local_addr()?;
+    let invite = |branch: &str| super::create_peer_request(rsip::Method::Invite, peer_addr, branch);
+
+    // an INVITE the TU is still thinking about
+    let mut incoming = endpoint.incoming_transactions()?;
+    endpoint
+        .inner
+        .on_received_message(
+            invite("z9hG4bKpending").into(),
+            conn.clone(),
+            &peer_addr.into(),
+        )
+        .await?;
+    let resp = super::recv_response(&peer).await?;
+    assert_eq!(resp.status_code, rsip::StatusCode::Trying);
+    let mut tx = incoming.try_recv().expect("invite reaches the TU");
+    tokio::spawn(async move { while tx.receive().await.is_some() {} });
+
+    let started = tokio::time::Instant::now();
+    let shutdown = {
+        let endpoint_inner = endpoint.inner.clone();
+        tokio::spawn(async move { endpoint_inner.shutdown(Duration::from_millis(300)).await })
+    };
+    let resp = super::recv_response(&peer).await?;
+    assert_eq!(resp.status_code, rsip::StatusCode::ServiceUnavailable);
+    assert_eq!(resp.cseq_header()?.method()?, rsip::Method::Invite);
+
+    // no new call is taken while draining
+    endpoint
+        .inner
+        .on_received_message(invite("z9hG4bKnew").into(), conn, &peer_addr.into())
+        .await?;
+    let resp = super::recv_response(&peer).await?;
+    assert_eq!(resp.status_code, rsip::StatusCode::ServiceUnavailable);
+    assert!(
+        incoming.try_recv().is_err(),
+        "request must not reach the TU"
+    );
+
+    // the rejected INVITE only waits for its ACK, which shutdown does not
+    let remaining = shutdown.await.expect("shutdown panicked");
+    assert!(remaining.is_empty(), "{:?} still waited on", remaining);
+    assert!(started.elapsed() < Duration::from_millis(300));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invite_is_answered_with_100_trying_before_the_tu() -> crate::Result<()> {
+    let (peer, conn) = super::create_test_peer().await?;
+    let peer_addr = peer.local_addr()?;
+
+    for auto_100_trying in [true, false] {
+        let endpoint = crate::EndpointBuilder::new()
+            .with_option(crate::transaction::endpoint::EndpointOption {
+                auto_100_trying,
+                ..Default::default()
+            })
+            .build();
+        let mut incoming = endpoint.incoming_transactions()?;
+        for (method, branch) in [
+            (
+                rsip::Method::Options,
+                format!("z9hG4bKoptions{}", auto_100_trying),
+            ),
+            (
+                rsip::Method::Invite,
+                format!("z9hG4bKinvite{}", auto_100_trying),
+            ),
+        ] {
+            endpoint
+                .inner
+                .on_received_message(
+                    super::create_peer_request(method, peer_addr, &branch).into(),
+                    conn.clone(),
+                    &peer_addr.into(),
+                )
+                .await?;
+        }
+        let options = incoming.try_recv().expect("options reaches the TU");
+        assert_eq!(options.original.method, rsip::Method::Options);
+        let mut invite = incoming.try_recv().expect("invite reaches the TU");
+
+        let received = super::recv_response_within(&peer, Duration::from_millis(200)).await?;
+        if !auto_100_trying {
+            assert!(received.is_none(), "nothing is sent unless asked for");
+            continue;
+        }
+        let resp = received.expect("no 100 trying");
+        assert_eq!(resp.status_code, rsip::StatusCode::Trying);
+        assert_eq!(
+            rsip::prelude::HeadersExt::cseq_header(&resp)?.method()?,
+            rsip::Method::Invite
+        );
+        assert_eq!(
+            invite.last_response.as_ref().map(|r| r.status_code.clone()),
+            Some(rsip::StatusCode::Trying)
+        );
+
+        // a TU sending its own 100 does not repeat it
+        invite.send_trying().await?;
+        assert!(
+            super::recv_response_within(&peer, Duration::from_millis(100))
+                .await?
+                .is_none()
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_transaction_knows_the_observed_source() -> crate::Result<()> {
+    let (peer, conn) = super::create_test_peer().await?;
+    let peer_addr = peer.local_addr()?;
+    let endpoint = crate::EndpointBuilder::new().build();
+    let mut incoming = endpoint.incoming_transactions()?;
+
+    // the Via names the address behind the NAT, the datagram comes from its
+    // public mapping
+    let options = super::create_peer_request(
+        rsip::Method::Options,
+        "10.0.0.5:5060".parse()?,
+        "z9hG4bKsource",
+    );
+    endpoint
+        .inner
+        .on_received_message(options.into(), conn, &peer_addr.into())
+        .await?;
+
+    let tx = incoming.try_recv().expect("options reaches the TU");
+    let source = tx.source.clone().expect("source of the options");
+    assert_eq!(source.get_socketaddr()?, peer_addr);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_make_request_uses_configured_max_forwards() -> crate::Result<()> {
+    use rsip::prelude::UntypedHeader;
+
+    let endpoint = crate::EndpointBuilder::new()
+        .with_option(crate::transaction::endpoint::EndpointOption {
+            max_forwards: 3,
+            ..Default::default()
+        })
+        .build();
+    let max_forwards = |req: &rsip::Request| {
+        req.headers.iter().find_map(|h| match h {
+            rsip::Header::MaxForwards(mf) => Some(mf.value().to_string()),
+            _ => None,
+        })
+    };
+    // no transport is bound, the Via names its address itself
+    let via_addr = crate::transport::SipAddr::new(
+        rsip::Transport::Udp,
+        rsip::HostWithPort::try_from("127.0.0.1:5060")?,
+    );
+    let make_request = |max_forwards: Option<u8>| -> crate::Result<rsip::Request> {
+        let uri = rsip::Uri::try_from("sip:bob@restsend.com")?;
+        Ok(endpoint.inner.make_request(
+            rsip::Method::Options,
+            uri.clone(),
+            endpoint.inner.get_via(Some(via_addr.clone()), None)?,
+            rsip::typed::From {
+                display_name: None,
+                uri: uri.clone(),
+                params: vec![],
+            },
+            rsip::typed::To {
+                display_name: None,
+                uri,
+                params: vec![],
+            },
+            1,
+            None,
+            max_forwards,
+        ))
+    };
+    assert_eq!(max_forwards(&make_request(None)?).as_deref(), Some("3"));
+    assert_eq!(max_forwards(&make_request(Some(1))?).as_deref(), Some("1"));
+    Ok(())
+}
+
+#[test]
+fn test_decrement_max_forwards_stops_at_zero() {
+    use crate::transaction::message::decrement_max_forwards;
+    use rsip::prelude::UntypedHeader;
+
+    let mut request = rsip::Request {
+        method: rsip::Method::Options,
+        uri: rsip::Uri::try_from("sip:bob@restsend.com").unwrap(),
+        headers: vec![MaxForwards::new("1").into()].into(),
+        version: rsip::Version::V2,
+        body: Default::default(),
+    };
+    assert_eq!(decrement_max_forwards(&mut request).unwrap(), 0);
+    let values = request
+        .headers
+        .iter()
+        .filter_map(|h| match h {
+            rsip::Header::MaxForwards(mf) => Some(mf.value().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(values, vec!["0".to_string()]);
+
+    // a request that arrives with 0 must not be forwarded
+    assert!(matches!(
+        decrement_max_forwards(&mut request),
+        Err(crate::Error::TooManyHops)
+    ));
+    assert_eq!(request.headers.iter().count(), 1);
+
+    // without Max-Forwards the request is treated as a fresh one
+    request.headers = rsip::Headers::default();
+    assert_eq!(decrement_max_forwards(&mut request).unwrap(), 69);
+}
+
+#[test]
+fn test_stateless_response_target_follows_via() {
+    use crate::transaction::stateless::response_target;
+    use rsip::prelude::ToTypedHeader;
+
+    let target = |via: &str| {
+        let via = Via::new(via).typed().expect("via");
+        response_target(&via).to_string()
+    };
+    assert_eq!(
+        target("SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK1"),
+        "UDP 192.0.2.1:5060"
+    );
+    assert_eq!(
+        target("SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK1;received=198.51.100.7;rport=40000"),
+        "UDP 198.51.100.7:40000"
+    );
+    assert_eq!(
+        target("SIP/2.0/TCP 192.0.2.1:5070;branch=z9hG4bK1;received=198.51.100.7"),
+        "TCP 198.51.100.7:5070"
+    );
+    assert_eq!(
+        target(
+            "SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK1;maddr=239.255.255.1;received=198.51.100.7"
+        ),
+        "UDP 239.255.255.1:5060"
+    );
+}
+
+#[tokio::test]
+async fn test_stateless_forwarding() -> crate::Result<()> {
+    use rsip::prelude::{HeadersExt, UntypedHeader};
+
+    let endpoint = super::create_test_endpoint(Some("127.0.0.1:0")).await?;
+    let proxy_addr = endpoint.get_addrs()[0].clone();
+    let upstream = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let downstream = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+
+    let request = rsip::Request {
+        method: rsip::Method::Options,
+        uri: rsip::Uri::try_from(format!("sip:bob@{}", downstream.local_addr()?).as_str())?,
+        headers: vec![
+            Via::new(format!(
+                "SIP/2.0/UDP {};branch=z9hG4bKupstream",
+                upstream.local_addr()?
+            ))
+            .into(),
+            MaxForwards::new("70").into(),
+            CSeq::new("1 OPTIONS").into(),
+            From::new("<sip:alice@example.com>;tag=stateless").into(),
+            To::new("<sip:bob@example.com>").into(),
+            CallId::new("stateless-forwarding").into(),
+            ContentLength::default().into(),
+        ]
+        .into(),
+        version: rsip::Version::V2,
+        body: vec![],
+    };
+
+    let mut buf = vec![0u8; 4096];
+    let mut branches = Vec::new();
+    let mut forwarded = None;
+    // a retransmission is forwarded with the same branch
+    for _ in 0..2 {
+        endpoint.forward_request(request.clone(), None).await?;
+        let (len, _) = tokio::time::timeout(Duration::from_secs(1), downstream.recv_from(&mut buf))
+            .await
+            .expect("request was not forwarded")?;
+        let req: rsip::Request = rsip::SipMessage::try_from(&buf[..len])?.try_into()?;
+        let vias = req
+            .headers
+            .iter()
+            .filter(|h| matches!(h, rsip::Header::Via(_)))
+            .count();
+        assert_eq!(vias, 2);
+        assert_eq!(req.max_forwards_header()?.value(), "69");
+        let via = req.via_header()?.typed()?;
+        assert_eq!(via.uri.host_with_port, proxy_addr.addr);
+        branches.push(via.branch().map(|b| b.to_string()));
+        forwarded = Some(req);
+    }
+    assert_eq!(branches[0], branches[1]);
+    assert_ne!(branches[0].as_deref(), Some("z9hG4bKupstream"));
+
+    let forwarded = forwarded.unwrap();
+    let response = endpoint
+        .inner
+        .make_response(&forwarded, rsip::StatusCode::OK, None);
+    endpoint.inner.forward_response(response.clone()).await?;
+    let resp = super::recv_response(&upstream).await?;
+    assert_eq!(resp.status_code, rsip::StatusCode::OK);
+    assert_eq!(
+        resp.via_header()?.typed()?.branch().map(|b| b.to_string()),
+        Some("z9hG4bKupstream".to_string())
+    );
+
+    // the upstream Via is not this endpoint's
+    let mut relayed = response;
+    relayed.headers.retain(|h| {
+        !matches!(h, rsip::Header::Via(via) if via.value().contains(&proxy_addr.addr.to_string()))
+    });
+    assert!(endpoint.inner.forward_response(relayed).await.is_err());
+    Ok(())
+}
+
+struct RecordingMessageHandler {
+    sender: tokio::sync::mpsc::UnboundedSender<crate::transaction::pager::InstantMessage>,
+}
+
+#[async_trait::async_trait]
+impl crate::transaction::pager::MessageHandler for RecordingMessageHandler {
+    async fn on_message(
+        &self,
+        message: crate::transaction::pager::InstantMessage,
+    ) -> rsip::StatusCode {
+        self.sender.send(message).ok();
+        rsip::StatusCode::Accepted
+    }
+}
+
+#[tokio::test]
+async fn test_pager_message_reaches_handler() -> crate::Result<()> {
+    use crate::transport::{udp::UdpConnection, TransportLayer};
+    use tokio_util::sync::CancellationToken;
+
+    let token = CancellationToken::new();
+    let create_endpoint = |handler: Option<RecordingMessageHandler>| {
+        let token = token.clone();
+        async move {
+            let tl = TransportLayer::new(token.child_token());
+            let conn = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+            tl.add_transport(conn.into());
+            let mut builder = crate::EndpointBuilder::new();
+            builder
+                .with_transport_layer(tl)
+                .with_cancel_token(token.child_token());
+            if let Some(handler) = handler {
+                builder.with_message_handler(Box::new(handler));
+            }
+            let endpoint = Arc::new(builder.build());
+            let serving = endpoint.clone();
+            tokio::spawn(async move { serving.serve().await });
+            crate::Result::Ok(endpoint)
+        }
+    };
+    let (sender, mut messages) = tokio::sync::mpsc::unbounded_channel();
+    let receiver = create_endpoint(Some(RecordingMessageHandler { sender })).await?;
+    let mut incoming = receiver.incoming_transactions()?;
+    let sender = create_endpoint(None).await?;
+
+    let to = rsip::Uri::try_from(format!("sip:bob@{}", receiver.get_addrs()[0].addr).as_str())?;
+    let from = rsip::Uri::try_from("sip:alice@example.com")?;
+    let resp = sender
+        .send_message(
+            to.clone(),
+            from.clone(),
+            "text/plain",
+            b"Watson, come here.".to_vec(),
+            None,
+        )
+        .await?;
+    assert_eq!(resp.status_code, rsip::StatusCode::Accepted);
+
+    let message = messages.try_recv().expect("message was not handed over");
+    assert_eq!(message.from, from);
+    assert_eq!(message.to, to);
+    assert_eq!(message.content_type.as_deref(), Some("text/plain"));
+    assert_eq!(message.body, b"Watson, come here.");
+    assert!(
+        incoming.try_recv().is_err(),
+        "handled message also surfaced as a transaction"
+    );
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_injected_call_id_and_branch() -> crate::Result<()> {
+    use crate::transaction::key::{TransactionKey, TransactionRole};
+    use crate::transaction::transaction::Transaction;
+    use crate::transaction::via_branch;
+    use rsip::prelude::{HeadersExt, ToTypedHeader, UntypedHeader};
+
+    assert!(via_branch("z9hG4bK").is_err());
+    assert!(via_branch("abc123").is_err());
+    assert!(via_branch("z9hG4bK;evil").is_err());
+
+    let endpoint = super::create_test_endpoint(Some("127.0.0.1:0")).await?;
+    assert!(endpoint
+        .inner
+        .get_via(None, Some(rsip::Param::Branch("1234".into())))
+        .is_err());
+
+    let make_request = || -> crate::Result<rsip::Request> {
+        let uri = rsip::Uri::try_from("sip:bob@restsend.com")?;
+        let via = endpoint
+            .inner
+            .get_via(None, Some(via_branch("z9hG4bKfixed-branch")?))?;
+        Ok(endpoint.inner.make_request(
+            rsip::Method::Options,
+            uri.clone(),
+            via,
+            rsip::typed::From {
+                display_name: None,
+                uri: uri.clone(),
+                params: vec![rsip::Param::Tag("fixed-tag".into())],
+            },
+            rsip::typed::To {
+                display_name: None,
+                uri,
+                params: vec![],
+            },
+            1,
+            Some(CallId::new("fixed-call-id@restsend.com")),
+            None,
+        ))
+    };
+    let first = make_request()?;
+    let second = make_request()?;
+    assert_eq!(
+        first.call_id_header()?.value(),
+        "fixed-call-id@restsend.com"
+    );
+    assert_eq!(
+        first.via_header()?.typed()?.branch().map(|b| b.to_string()),
+        Some("z9hG4bKfixed-branch".to_string())
+    );
+    let key = TransactionKey::from_request(&first, TransactionRole::Client)?;
+    assert_eq!(
+        key,
+        TransactionKey::from_request(&second, TransactionRole::Client)?
+    );
+    assert!(key.to_string().contains("z9hG4bKfixed-branch"));
+    let _tx = Transaction::new_client(key.clone(), first, endpoint.inner.clone(), None);
+    assert_eq!(
+        endpoint
+            .inner
+            .get_transactions_by_call_id("fixed-call-id@restsend.com"),
+        vec![key]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_external_addr_in_via_and_contact() -> crate::Result<()> {
+    use crate::dialog::dialog_layer::DialogLayer;
+
+    let endpoint = super::create_test_endpoint(Some("127.0.0.1:0")).await?;
+    let bound = endpoint.get_addrs();
+    let external: std::net::SocketAddr = "203.0.113.5:40000".parse().unwrap();
+    endpoint.inner.set_external_addr(Some(external));
+
+    let via = endpoint.inner.get_via(None, None)?;
+    assert_eq!(via.uri.host_with_port, external.into());
+    let contact = DialogLayer::new(endpoint.inner.clone()).default_contact("alice")?;
+    assert_eq!(contact.host_with_port, external.into());
+    assert!(endpoint
+        .inner
+        .is_local_uri(&rsip::Uri::try_from("sip:203.0.113.5:40000")?));
+    // the socket keeps its local address
+    assert_eq!(endpoint.get_addrs(), bound);
+
+    endpoint.inner.set_external_addr(None);
+    let via = endpoint.inner.get_via(None, None)?;
+    assert_eq!(via.uri.host_with_port, bound[0].addr);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_compact_headers_on_the_wire() -> crate::Result<()> {
+    use crate::transport::{udp::UdpConnection, SipConnection, TransportLayer};
+    use tokio_util::sync::CancellationToken;
+
+    let tl = TransportLayer::new(CancellationToken::new());
+    let udp = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+    tl.add_transport(udp.clone().into());
+    let endpoint = crate::EndpointBuilder::new()
+        .with_transport_layer(tl)
+        .with_option(crate::transaction::endpoint::EndpointOption {
+            use_compact_headers: true,
+            ..Default::default()
+        })
+        .build();
+    let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let peer_addr = peer.local_addr()?;
+
+    let uri = rsip::Uri::try_from(format!("sip:bob@{}", peer_addr).as_str())?;
+    let mut request = endpoint.inner.make_request(
+        rsip::Method::Options,
+        uri.clone(),
+        endpoint.inner.get_via(None, None)?,
+        rsip::typed::From {
+            display_name: None,
+            uri: uri.clone(),
+            params: vec![rsip::Param::Tag("compact".into())],
+        },
+        rsip::typed::To {
+            display_name: None,
+            uri,
+            params: vec![],
+        },
+        1,
+        None,
+        None,
+    );
+    // added by the transaction when sent through one
+    request.headers.push(rsip::Header::ContentLength(0.into()));
+    let connection = SipConnection::Udp(udp);
+    endpoint
+        .inner
+        .send_message(&connection, request.into(), Some(&peer_addr.into()))
+        .await?;
+
+    let mut buf = vec![0u8; 4096];
+    let (len, _) = tokio::time::timeout(Duration::from_secs(1), peer.recv_from(&mut buf))
+        .await
+        .expect("request was not sent")?;
+    let text = String::from_utf8_lossy(&buf[..len]).to_string();
+    for header in ["\r\nv: ", "\r\ni: ", "\r\nf: ", "\r\nt: ", "\r\nl: 0"] {
+        assert!(text.contains(header), "{} missing in {}", header, text);
+    }
+    assert!(!text.contains("\r\nVia:"));
+    // still a valid message for the peer
+    let received = rsip::SipMessage::try_from(text.as_str())?;
+    assert!(received.is_request());
+    Ok(())
+}
@@ -1,10 +1,14 @@
-use crate::transport::SipConnection;
+use crate::dialog::sdp::Sdp;
+use crate::transaction::endpoint::SdpRewriter;
+use crate::transport::{SipAddr, SipConnection, TcpListenerConnection};
 use crate::{
     transport::{udp::UdpConnection, TransportLayer},
     EndpointBuilder,
 };
 use rsip::headers::*;
+use rsip::prelude::UntypedHeader;
 use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::{select, time::sleep};
 use tokio_util::sync::CancellationToken;
 use tracing::info;
@@ -109,3 +113,397 @@ async fn test_server_transaction() {
         }
     }
 }
+
+#[tokio::test]
+async fn test_response_follows_via_transport() {
+    let token = CancellationToken::new();
+    let udp_conn =
+        UdpConnection::create_connection("127.0.0.1:0".parse().expect("parse addr"), None, None)
+            .await
+            .expect("create_connection");
+    let tcp_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|l| l.local_addr())
+        .expect("free tcp port")
+        .port();
+    let tcp_addr = SipAddr::new(
+        rsip::transport::Transport::Tcp,
+        rsip::HostWithPort::try_from(format!("127.0.0.1:{}", tcp_port).as_str()).unwrap(),
+    );
+    let tcp_listener = TcpListenerConnection::new(tcp_addr.clone(), None)
+        .await
+        .expect("tcp listener");
+
+    let tl = TransportLayer::new(token.child_token());
+    tl.add_transport(udp_conn.into());
+    tl.add_transport(tcp_listener.into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(tl)
+        .with_cancel_token(token.child_token())
+        .build();
+    let endpoint_inner = endpoint.inner.clone();
+    tokio::spawn(async move {
+        let _ = endpoint_inner.serve().await;
+    });
+    let mut incoming = endpoint
+        .incoming_transactions()
+        .expect("incoming_transactions");
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            tx.reply(rsip::StatusCode::OK).await.expect("reply");
+        }
+    });
+
+    // the client sends over TCP but asks for responses over UDP
+    let client_udp = tokio::net::UdpSocket::bind("127.0.0.1:0")
+        .await
+        .expect("bind client udp");
+    let client_udp_addr = client_udp.local_addr().expect("local addr");
+    sleep(Duration::from_millis(50)).await;
+    let mut client_tcp = tokio::net::TcpStream::connect(("127.0.0.1", tcp_port))
+        .await
+        .expect("connect tcp");
+    let options = format!(
+        "OPTIONS sip:bob@127.0.0.1:{tcp_port};transport=tcp SIP/2.0\r\n\
+         Via: SIP/2.0/UDP {client_udp_addr};branch=z9hG4bKasymmetric\r\n\
+         From: <sip:alice@127.0.0.1>;tag=asym\r\n\
+         To: <sip:bob@127.0.0.1>\r\n\
+         Call-ID: asymmetric-transport@127.0.0.1\r\n\
+         CSeq: 1 OPTIONS\r\n\
+         Max-Forwards: 70\r\n\
+         Content-Length: 0\r\n\r\n"
+    );
+    client_tcp
+        .write_all(options.as_bytes())
+        .await
+        .expect("send over tcp");
+
+    let mut buf = vec![0u8; 4096];
+    let (len, from) = tokio::time::timeout(Duration::from_secs(2), client_udp.recv_from(&mut buf))
+        .await
+        .expect("response over udp")
+        .expect("recv udp");
+    let response = String::from_utf8_lossy(&buf[..len]);
+    assert!(response.starts_with("SIP/2.0 200"), "{}", response);
+    assert!(response.contains("z9hG4bKasymmetric"));
+    assert_ne!(from.port(), tcp_port, "sent from the udp transport");
+
+    let mut tcp_buf = [0u8; 64];
+    let over_tcp =
+        tokio::time::timeout(Duration::from_millis(200), client_tcp.read(&mut tcp_buf)).await;
+    assert!(
+        !matches!(over_tcp, Ok(Ok(n)) if n > 0),
+        "no response over the receiving connection"
+    );
+    token.cancel();
+}
+
+/// Anchors media at 203.0.113.10:30000
+struct AnchorMedia;
+
+impl SdpRewriter for AnchorMedia {
+    fn rewrite(&self, _response: &rsip::Response, mut sdp: Sdp) -> Option<Sdp> {
+        sdp.set_connection_address("203.0.113.10".parse().unwrap());
+        sdp.set_media_port(0, 30000);
+        Some(sdp)
+    }
+}
+
+#[tokio::test]
+async fn test_sdp_rewriter_updates_body_and_content_length() {
+    let token = CancellationToken::new();
+    let udp_conn =
+        UdpConnection::create_connection("127.0.0.1:0".parse().expect("parse addr"), None, None)
+            .await
+            .expect("create_connection");
+    let server_addr = udp_conn.get_addr().addr.clone();
+    let tl = TransportLayer::new(token.child_token());
+    tl.add_transport(udp_conn.into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(tl)
+        .with_sdp_rewriter(Box::new(AnchorMedia))
+        .with_cancel_token(token.child_token())
+        .build();
+    let endpoint_inner = endpoint.inner.clone();
+    tokio::spawn(async move {
+        let _ = endpoint_inner.serve().await;
+    });
+
+    let answer = "v=0\r\no=bob 1 1 IN IP4 10.1.1.1\r\ns=-\r\nc=IN IP4 10.1.1.1\r\nt=0 0\r\nm=audio 4000 RTP/AVP 0\r\n";
+    let mut incoming = endpoint
+        .incoming_transactions()
+        .expect("incoming_transactions");
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            let mut resp =
+                tx.endpoint_inner
+                    .make_response(&tx.original, rsip::StatusCode::OK, None);
+            resp.headers
+                .push(rsip::Header::ContentType("application/sdp".into()));
+            resp.headers
+                .unique_push(rsip::Header::ContentLength((answer.len() as u32).into()));
+            resp.body = answer.as_bytes().to_vec();
+            tx.respond(resp).await.expect("respond");
+        }
+    });
+
+    let client = tokio::net::UdpSocket::bind("127.0.0.1:0")
+        .await
+        .expect("bind client udp");
+    let client_addr = client.local_addr().expect("local addr");
+    let invite = format!(
+        "INVITE sip:bob@{server_addr} SIP/2.0\r\n\
+         Via: SIP/2.0/UDP {client_addr};branch=z9hG4bKanchor\r\n\
+         From: <sip:alice@127.0.0.1>;tag=anchor\r\n\
+         To: <sip:bob@127.0.0.1>\r\n\
+         Call-ID: anchor@127.0.0.1\r\n\
+         CSeq: 1 INVITE\r\n\
+         Contact: <sip:alice@{client_addr}>\r\n\
+         Max-Forwards: 70\r\n\
+         Content-Length: 0\r\n\r\n"
+    );
+    client
+        .send_to(invite.as_bytes(), server_addr.to_string())
+        .await
+        .expect("send invite");
+
+    // skip the 100 Trying sent ahead of the answer
+    let mut buf = vec![0u8; 4096];
+    let resp = loop {
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buf))
+            .await
+            .expect("no response")
+            .expect("recv udp");
+        let resp = rsip::Response::try_from(std::str::from_utf8(&buf[..len]).expect("utf8"))
+            .expect("parse response");
+        if resp.status_code.kind() != rsip::StatusCodeKind::Provisional {
+            break resp;
+        }
+    };
+    let body = String::from_utf8(resp.body.clone()).expect("utf8 body");
+    assert!(body.contains("c=IN IP4 203.0.113.10\r\n"), "{}", body);
+    assert!(body.contains("m=audio 30000 RTP/AVP 0\r\n"), "{}", body);
+    assert!(!body.contains("10.1.1.1\r\nt="));
+    let content_length = resp
+        .headers
+        .iter()
+        .find_map(|h| match h {
+            rsip::Header::ContentLength(len) => Some(len.value().to_string()),
+            _ => None,
+        })
+        .expect("content length");
+    assert_eq!(content_length, body.len().to_string());
+    assert_ne!(body.len(), answer.len());
+    token.cancel();
+}
+
+#[tokio::test]
+async fn test_server_transaction_stream_ends_on_terminated() -> crate::Result<()> {
+    use crate::transaction::endpoint::EndpointOption;
+    use crate::transaction::transaction::TransactionEvent;
+    use futures::StreamExt;
+
+    let token = CancellationToken::new();
+    let local = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+    let addr = local.get_addr().addr.clone();
+    let tl = TransportLayer::new(token.child_token());
+    tl.add_transport(local.into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(tl)
+        .with_option(EndpointOption {
+            t4: Duration::from_millis(100),
+            ..Default::default()
+        })
+        .build();
+    let mut incoming = endpoint.incoming_transactions()?;
+
+    let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let headers = format!(
+        "Via: SIP/2.0/UDP {};branch=z9hG4bKstream\r\n\
+         From: <sip:alice@example.com>;tag=stream\r\n\
+         To: <sip:bob@example.com>\r\n\
+         Call-ID: stream@example.com\r\n",
+        peer.local_addr()?
+    );
+    let invite = format!(
+        "INVITE sip:bob@{addr} SIP/2.0\r\n{headers}CSeq: 1 INVITE\r\nMax-Forwards: 70\r\nContent-Length: 0\r\n\r\n"
+    );
+    peer.send_to(invite.as_bytes(), addr.to_string()).await?;
+
+    let test_loop = async {
+        let tx = incoming.recv().await.expect("incoming INVITE");
+        let tu_sender = tx.tu_sender.clone();
+        let busy = endpoint
+            .inner
+            .make_response(&tx.original, rsip::StatusCode::BusyHere, None);
+        let mut stream = tx.into_stream();
+        tu_sender.send(TransactionEvent::Respond(busy)).unwrap();
+
+        // the peer ACKs the 486 once it arrives, after the 100 Trying
+        let peer_loop = async {
+            let mut buf = vec![0u8; 4096];
+            loop {
+                let (len, _) = peer.recv_from(&mut buf).await.expect("peer recv");
+                let resp = std::str::from_utf8(&buf[..len]).unwrap();
+                if !resp.starts_with("SIP/2.0 100") {
+                    assert!(resp.starts_with("SIP/2.0 486"), "{}", resp);
+                    break;
+                }
+            }
+            let ack = format!(
+                "ACK sip:bob@{addr} SIP/2.0\r\n{headers}CSeq: 1 ACK\r\nMax-Forwards: 70\r\nContent-Length: 0\r\n\r\n"
+            );
+            peer.send_to(ack.as_bytes(), addr.to_string())
+                .await
+                .expect("send ACK");
+        };
+        // the ACK of a non-2xx stays in the transaction, then Timer K
+        // terminates it, which ends the stream
+        let (_, first) = tokio::join!(peer_loop, stream.next());
+        assert!(
+            first.is_none(),
+            "expected the end of the stream, got {:?}",
+            first.map(|m| m.to_string())
+        );
+    };
+
+    select! {
+        _ = test_loop => {}
+        _ = endpoint.serve() => panic!("endpoint stopped"),
+        _ = sleep(Duration::from_secs(2)) => panic!("stream did not end"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unframed_stream_request_gets_400() {
+    let token = CancellationToken::new();
+    let tcp_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|l| l.local_addr())
+        .expect("free tcp port")
+        .port();
+    let tcp_addr = SipAddr::new(
+        rsip::transport::Transport::Tcp,
+        rsip::HostWithPort::try_from(format!("127.0.0.1:{}", tcp_port).as_str()).unwrap(),
+    );
+    let tcp_listener = TcpListenerConnection::new(tcp_addr, None)
+        .await
+        .expect("tcp listener");
+    let tl = TransportLayer::new(token.child_token());
+    tl.add_transport(tcp_listener.into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent("rsipstack-test")
+        .with_transport_layer(tl)
+        .with_cancel_token(token.child_token())
+        .build();
+    let endpoint_inner = endpoint.inner.clone();
+    tokio::spawn(async move {
+        let _ = endpoint_inner.serve().await;
+    });
+    let mut incoming = endpoint
+        .incoming_transactions()
+        .expect("incoming_transactions");
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            tx.reply(rsip::StatusCode::OK).await.expect("reply");
+        }
+    });
+
+    sleep(Duration::from_millis(50)).await;
+    let mut client = tokio::net::TcpStream::connect(("127.0.0.1", tcp_port))
+        .await
+        .expect("connect tcp");
+    let head = |method: &str, branch: &str, cseq: u32| {
+        format!(
+            "{method} sip:bob@127.0.0.1:{tcp_port};transport=tcp SIP/2.0\r\n\
+             Via: SIP/2.0/TCP 127.0.0.1:5060;branch={branch}\r\n\
+             From: <sip:alice@127.0.0.1>;tag=truncated\r\n\
+             To: <sip:bob@127.0.0.1>\r\n\
+             Call-ID: truncated-body@127.0.0.1\r\n\
+             CSeq: {cseq} {method}\r\n\
+             Max-Forwards: 70\r\n"
+        )
+    };
+    // the MESSAGE has no Content-Length, the OPTIONS follows its body
+    let data = format!(
+        "{}\r\nHello{}Content-Length: 0\r\n\r\n",
+        head("MESSAGE", "z9hG4bKtruncated", 1),
+        head("OPTIONS", "z9hG4bKvalid", 2),
+    );
+    client
+        .write_all(data.as_bytes())
+        .await
+        .expect("send over tcp");
+
+    let mut received = String::new();
+    let mut buf = vec![0u8; 4096];
+    while !(received.contains("SIP/2.0 400") && received.contains("SIP/2.0 200")) {
+        let n = tokio::time::timeout(Duration::from_secs(2), client.read(&mut buf))
+            .await
+            .expect("responses over tcp")
+            .expect("read tcp");
+        assert!(n > 0, "connection closed: {}", received);
+        received.push_str(&String::from_utf8_lossy(&buf[..n]));
+    }
+    let bad_request = received.find("SIP/2.0 400").unwrap();
+    let ok = received.find("SIP/2.0 200").unwrap();
+    assert!(bad_request < ok, "{}", received);
+    assert!(received[bad_request..ok].contains("z9hG4bKtruncated"));
+    assert!(received[ok..].contains("z9hG4bKvalid"));
+    token.cancel();
+}
+
+#[tokio::test]
+async fn test_reply_with_warning_reaches_the_client() -> crate::Result<()> {
+    use crate::rsip_ext::{RsipResponseExt, Warning};
+
+    let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let peer_addr = peer.local_addr()?;
+    let conn = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+    let endpoint = EndpointBuilder::new().build();
+    let mut incoming = endpoint.incoming_transactions()?;
+    let invite = rsip::Request {
+        method: rsip::Method::Invite,
+        uri: rsip::Uri::try_from("sip:bob@restsend.com")?,
+        headers: vec![
+            Via::new(format!("SIP/2.0/UDP {};branch=z9hG4bKwarning", peer_addr)).into(),
+            CSeq::new("1 INVITE").into(),
+            From::new("Alice <sip:alice@restsend.com>;tag=warning-tag").into(),
+            To::new("<sip:bob@restsend.com>").into(),
+            CallId::new("warning@restsend.com").into(),
+        ]
+        .into(),
+        version: rsip::Version::V2,
+        body: Default::default(),
+    };
+    endpoint
+        .inner
+        .on_received_message(invite.into(), conn.into(), &peer_addr.into())
+        .await?;
+
+    // the offer has no codec in common
+    let warning = Warning::new(305, "bob.restsend.com", "Incompatible media format");
+    let mut tx = incoming.try_recv().expect("invite reaches the TU");
+    tx.reply_with(
+        rsip::StatusCode::NotAcceptableHere,
+        vec![warning.clone().into()],
+        None,
+    )
+    .await?;
+
+    let mut buf = [0u8; 65535];
+    let resp = loop {
+        let (len, _) = tokio::time::timeout(Duration::from_secs(1), peer.recv_from(&mut buf))
+            .await
+            .expect("no final response")?;
+        let resp: rsip::Response = rsip::SipMessage::try_from(&buf[..len])?.try_into()?;
+        if resp.status_code != rsip::StatusCode::Trying {
+            break resp;
+        }
+    };
+    assert_eq!(resp.status_code, rsip::StatusCode::NotAcceptableHere);
+    assert_eq!(resp.warnings(), vec![warning]);
+    Ok(())
+}
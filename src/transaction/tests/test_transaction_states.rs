@@ -6,10 +6,12 @@
 use super::create_test_endpoint;
 use crate::transaction::{
     key::{TransactionKey, TransactionRole},
-    transaction::Transaction,
+    transaction::{TimerConfig, Transaction},
     TransactionState, TransactionType,
 };
+use crate::transport::{udp::UdpConnection, SipConnection};
 use rsip::headers::*;
+use std::time::Duration;
 
 /// Test helper to create a mock request
 fn create_test_request(method: rsip::Method, branch: &str) -> rsip::Request {
@@ -162,3 +164,214 @@ async fn test_transaction_types() -> crate::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_send_and_respond_after_termination_fail() -> crate::Result<()> {
+    let endpoint = create_test_endpoint(Some("127.0.0.1:0")).await?;
+
+    let register_req = create_test_request(rsip::Method::Register, "z9hG4bKterminated");
+    let key = TransactionKey::from_request(&register_req, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, register_req.clone(), endpoint.inner.clone(), None);
+    tx.state = TransactionState::Terminated;
+    match tx.send().await {
+        Err(crate::Error::TransactionError(reason, _)) => assert_eq!(reason, "already terminated"),
+        _ => panic!("send on a terminated transaction must fail"),
+    }
+    assert!(tx.timer_a.is_none() && tx.timer_b.is_none());
+
+    let key = TransactionKey::from_request(&register_req, TransactionRole::Server)?;
+    let mut tx = Transaction::new_server(key, register_req, endpoint.inner.clone(), None);
+    tx.state = TransactionState::Terminated;
+    let response = endpoint
+        .inner
+        .make_response(&tx.original, rsip::StatusCode::OK, None);
+    match tx.respond(response).await {
+        Err(crate::Error::TransactionError(reason, _)) => assert_eq!(reason, "already terminated"),
+        _ => panic!("respond on a terminated transaction must fail"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_per_transaction_timer_overrides() -> crate::Result<()> {
+    let endpoint = create_test_endpoint(Some("127.0.0.1:0")).await?;
+    let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let connection: SipConnection =
+        UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None)
+            .await?
+            .into();
+
+    let fast = TimerConfig {
+        t1: Duration::from_millis(100),
+        t2: Duration::from_millis(400),
+        t4: Duration::from_secs(1),
+        timer_b: Duration::from_secs(2),
+        timer_f: Duration::from_secs(2),
+    };
+    let slow = TimerConfig {
+        t1: Duration::from_secs(1),
+        t2: Duration::from_secs(4),
+        t4: Duration::from_secs(5),
+        timer_b: Duration::from_secs(32),
+        timer_f: Duration::from_secs(16),
+    };
+
+    let mut deadlines = vec![];
+    for (branch, timers) in [("z9hG4bKfast", fast), ("z9hG4bKslow", slow)] {
+        let options_req = create_test_request(rsip::Method::Options, branch);
+        let key = TransactionKey::from_request(&options_req, TransactionRole::Client)?;
+        let mut tx = Transaction::new_client(
+            key,
+            options_req,
+            endpoint.inner.clone(),
+            Some(connection.clone()),
+        )
+        .with_timers(timers);
+        tx.destination = Some(peer.local_addr()?.into());
+        assert_eq!(tx.timer_config(), timers);
+
+        let sent_at = std::time::Instant::now();
+        tx.send().await?;
+        let timers = &endpoint.inner.timers;
+        let timer_a = timers.execute_at(tx.timer_a.expect("timer A")).unwrap();
+        let timer_f = timers.execute_at(tx.timer_b.expect("timer F")).unwrap();
+        deadlines.push((timer_a - sent_at, timer_f - sent_at));
+    }
+
+    let ((fast_a, fast_f), (slow_a, slow_f)) = (deadlines[0], deadlines[1]);
+    assert!(fast_a <= Duration::from_millis(200), "timer A {:?}", fast_a);
+    assert!(slow_a >= Duration::from_secs(1), "timer A {:?}", slow_a);
+    assert!(fast_f <= Duration::from_secs(3), "timer F {:?}", fast_f);
+    assert!(slow_f >= Duration::from_secs(16), "timer F {:?}", slow_f);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_state_watch_follows_transitions() -> crate::Result<()> {
+    let endpoint = create_test_endpoint(Some("127.0.0.1:0")).await?;
+    let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let connection: SipConnection =
+        UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None)
+            .await?
+            .into();
+
+    let options_req = create_test_request(rsip::Method::Options, "z9hG4bKwatch");
+    let key = TransactionKey::from_request(&options_req, TransactionRole::Server)?;
+    let mut tx =
+        Transaction::new_server(key, options_req, endpoint.inner.clone(), Some(connection));
+    tx.destination = Some(peer.local_addr()?.into());
+
+    let mut watch = tx.state_watch();
+    assert_eq!(*watch.borrow_and_update(), TransactionState::Trying);
+    // a final response ends a server non-INVITE transaction at once
+    tx.reply(rsip::StatusCode::OK).await?;
+    assert!(watch.has_changed().unwrap());
+    assert_eq!(*watch.borrow_and_update(), TransactionState::Terminated);
+    // a late subscriber sees the current state without waiting
+    assert_eq!(*tx.state_watch().borrow(), TransactionState::Terminated);
+
+    drop(tx);
+    assert!(watch.changed().await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_timer_g_queued_behind_ack_sends_nothing() -> crate::Result<()> {
+    use crate::transaction::{transaction::TransactionEvent, TransactionTimer};
+    use crate::transport::{mock::MockConnection, SipAddr};
+
+    let endpoint = create_test_endpoint(Some("127.0.0.1:0")).await?;
+    let mock = MockConnection::new(
+        SipAddr::new(
+            rsip::Transport::Udp,
+            rsip::HostWithPort::try_from("127.0.0.1:5060").unwrap(),
+        ),
+        SipAddr::new(
+            rsip::Transport::Udp,
+            rsip::HostWithPort::try_from("127.0.0.1:5062").unwrap(),
+        ),
+        None,
+    );
+    let invite_req = create_test_request(rsip::Method::Invite, "z9hG4bKtimerg");
+    let key = TransactionKey::from_request(&invite_req, TransactionRole::Server)?;
+    let mut tx = Transaction::new_server(
+        key.clone(),
+        invite_req,
+        endpoint.inner.clone(),
+        Some(mock.clone().into()),
+    );
+    tx.reply(rsip::StatusCode::BusyHere).await?;
+    assert_eq!(tx.state, TransactionState::Completed);
+    assert_eq!(mock.take_sent().len(), 1);
+
+    // Timer G fires on both sides of the ACK
+    let t1 = Duration::from_millis(500);
+    let ack = create_test_request(rsip::Method::Ack, "z9hG4bKtimerg");
+    tx.tu_sender
+        .send(TransactionEvent::Timer(TransactionTimer::TimerG(
+            key.clone(),
+            t1,
+        )))
+        .unwrap();
+    tx.tu_sender
+        .send(TransactionEvent::Received(ack.into(), None, None))
+        .unwrap();
+    tx.tu_sender
+        .send(TransactionEvent::Timer(TransactionTimer::TimerG(
+            key,
+            t1 * 2,
+        )))
+        .unwrap();
+
+    // the ACK of the 486 stays in the transaction
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), tx.receive())
+            .await
+            .is_err(),
+        "ack passed up"
+    );
+    assert_eq!(tx.state, TransactionState::Confirmed);
+    assert!(tx.timer_g.is_none());
+    assert_eq!(mock.take_sent().len(), 1, "one resend before the ack");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ack_of_2xx_reaches_the_tu() -> crate::Result<()> {
+    use crate::transaction::transaction::TransactionEvent;
+    use crate::transport::{mock::MockConnection, SipAddr};
+
+    let endpoint = create_test_endpoint(Some("127.0.0.1:0")).await?;
+    let mock = MockConnection::new(
+        SipAddr::new(
+            rsip::Transport::Udp,
+            rsip::HostWithPort::try_from("127.0.0.1:5060").unwrap(),
+        ),
+        SipAddr::new(
+            rsip::Transport::Udp,
+            rsip::HostWithPort::try_from("127.0.0.1:5062").unwrap(),
+        ),
+        None,
+    );
+    let invite_req = create_test_request(rsip::Method::Invite, "z9hG4bKack2xx");
+    let key = TransactionKey::from_request(&invite_req, TransactionRole::Server)?;
+    let mut tx =
+        Transaction::new_server(key, invite_req, endpoint.inner.clone(), Some(mock.into()));
+    tx.reply(rsip::StatusCode::OK).await?;
+    assert_eq!(tx.state, TransactionState::Completed);
+
+    // the ACK of a 2xx is end-to-end and belongs to the dialog
+    let ack = create_test_request(rsip::Method::Ack, "z9hG4bKack2xxack");
+    tx.tu_sender
+        .send(TransactionEvent::Received(ack.into(), None, None))
+        .unwrap();
+    let received = tokio::time::timeout(Duration::from_millis(100), tx.receive())
+        .await
+        .expect("ack not passed up")
+        .expect("transaction ended");
+    assert!(
+        matches!(received, rsip::SipMessage::Request(ref req) if req.method == rsip::Method::Ack)
+    );
+    assert_eq!(tx.state, TransactionState::Confirmed);
+    Ok(())
+}
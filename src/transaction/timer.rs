@@ -2,12 +2,15 @@ use std::{
     collections::{BTreeMap, HashMap},
     sync::{
         atomic::{AtomicU64, Ordering},
-        Condvar, Mutex, MutexGuard,
+        Arc, Condvar, Mutex, MutexGuard,
     },
     time::{Duration, Instant},
 };
 
-use tokio::sync::Notify;
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    Notify,
+};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 struct TimerKey {
@@ -29,6 +32,17 @@ impl PartialOrd for TimerKey {
     }
 }
 
+/// Deadline queue of values, ordered by `execute_at`
+///
+/// Values come out once their deadline has passed, in deadline order. There
+/// are three ways to take them:
+///
+/// * [`Timer::spawn_driver`] (preferred) - a task keeps one sleep armed for
+///   the earliest deadline and sends each value through a channel as it
+///   fires, re-arming whenever `timeout_at` or `cancel` change the head
+/// * [`Timer::wait_for_ready`] - await the next batch of fired values
+/// * [`Timer::poll`] - collect fired values at `now`; the caller has to call
+///   it repeatedly, so timers fire late by up to the polling interval
 pub struct Timer<T> {
     state: Mutex<TimerState<T>>,
     condvar: Condvar,
@@ -50,6 +64,11 @@ impl<T> Timer<T> {
         self.lock_state().tasks.len()
     }
 
+    /// Deadline of a pending task
+    pub fn execute_at(&self, task_id: u64) -> Option<Instant> {
+        self.lock_state().id_to_tasks.get(&task_id).copied()
+    }
+
     pub fn timeout(&self, duration: Duration, value: T) -> u64 {
         self.timeout_at(Instant::now() + duration, value)
     }
@@ -115,6 +134,10 @@ impl<T> Timer<T> {
         removed
     }
 
+    /// Take every value due at `now`
+    ///
+    /// Kept for callers with their own loop; prefer [`Timer::spawn_driver`],
+    /// which fires at the deadline instead of at the next poll.
     pub fn poll(&self, now: Instant) -> Vec<T> {
         let mut state = self.lock_state();
         Self::collect_ready(&mut state, now)
@@ -164,6 +187,59 @@ impl<T> Timer<T> {
     }
 }
 
+impl<T: Send + 'static> Timer<T> {
+    /// Fire values from a spawned task and deliver them through a channel
+    ///
+    /// The task stops once the receiver is dropped.
+    pub fn spawn_driver(self: &Arc<Self>) -> UnboundedReceiver<T> {
+        let (sender, receiver) = unbounded_channel();
+        let timer = self.clone();
+        tokio::spawn(async move { timer.drive(sender).await });
+        receiver
+    }
+
+    async fn drive(&self, sender: UnboundedSender<T>) {
+        let sleep = tokio::time::sleep(Duration::ZERO);
+        tokio::pin!(sleep);
+        loop {
+            // register before reading the head so no change is missed
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let (ready, next_deadline) = {
+                let mut state = self.lock_state();
+                let ready = Self::collect_ready(&mut state, Instant::now());
+                (ready, state.tasks.keys().next().map(|key| key.execute_at))
+            };
+            for value in ready {
+                if sender.send(value).is_err() {
+                    return;
+                }
+            }
+
+            match next_deadline {
+                Some(deadline) => {
+                    sleep
+                        .as_mut()
+                        .reset(tokio::time::Instant::from_std(deadline));
+                    tokio::select! {
+                        _ = &mut sleep => {}
+                        _ = &mut notified => {}
+                        _ = sender.closed() => return,
+                    }
+                }
+                None => {
+                    tokio::select! {
+                        _ = &mut notified => {}
+                        _ = sender.closed() => return,
+                    }
+                }
+            }
+        }
+    }
+}
+
 #[test]
 fn test_timer() {
     use std::time::Duration;
@@ -216,6 +292,31 @@ async fn wait_for_ready_async_wakes_on_new_timer() {
     assert_eq!(ready, vec!["early"]);
 }
 
+#[tokio::test]
+async fn spawn_driver_fires_in_deadline_order() {
+    let timer = Arc::new(Timer::new());
+    let mut fired = timer.spawn_driver();
+
+    timer.timeout(Duration::from_secs(5), "late");
+    let cancelled = timer.timeout(Duration::from_millis(100), "cancelled");
+    // a new head re-arms the driver's sleep
+    timer.timeout(Duration::from_millis(50), "early");
+    timer.timeout(Duration::from_millis(150), "next");
+    assert_eq!(timer.cancel(cancelled), Some("cancelled"));
+
+    let started = Instant::now();
+    let first = tokio::time::timeout(Duration::from_secs(1), fired.recv())
+        .await
+        .expect("driver did not fire");
+    assert_eq!(first, Some("early"));
+    assert!(started.elapsed() < Duration::from_millis(140));
+    let second = tokio::time::timeout(Duration::from_secs(1), fired.recv())
+        .await
+        .expect("driver did not fire");
+    assert_eq!(second, Some("next"));
+    assert_eq!(timer.len(), 1);
+}
+
 impl<T> Timer<T> {
     fn lock_state(&self) -> MutexGuard<'_, TimerState<T>> {
         match self.state.lock() {
@@ -1,17 +1,34 @@
-use super::endpoint::EndpointInnerRef;
-use super::key::TransactionKey;
+use super::compression::{encode_message_body, negotiate_encoding};
+use super::endpoint::{EndpointInnerRef, EndpointOption};
+use super::key::{TransactionKey, TransactionRole};
 use super::{SipConnection, TransactionState, TransactionTimer, TransactionType};
-use crate::dialog::DialogId;
-use crate::rsip_ext::{destination_from_request, RsipResponseExt};
-use crate::transaction::make_tag;
+use crate::dialog::{sdp::Sdp, DialogId};
+use crate::rsip_ext::{
+    contact_without_brackets, destination_from_request, header_contains_token,
+    header_value_case_insensitive, parse_rack_header, parse_rseq_header, reason_headers,
+    RsipResponseExt,
+};
+use crate::transaction::{jitter_duration, make_tag};
 use crate::transport::SipAddr;
 use crate::{Error, Result};
+use futures::Stream;
 use rsip::headers::ContentLength;
 use rsip::message::HasHeaders;
-use rsip::prelude::HeadersExt;
+use rsip::prelude::{HeadersExt, ToTypedHeader, UntypedHeader};
 use rsip::{Header, Method, Request, Response, SipMessage, StatusCode, StatusCodeKind};
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
-use tracing::{debug, info, trace};
+use std::borrow::Cow;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    watch,
+};
+use tracing::{debug, info, trace, warn};
+
+/// How long a request over `udp_mtu_threshold` waits for a TCP connection
+/// before it is sent over UDP anyway
+const LARGE_REQUEST_TCP_TIMEOUT: Duration = Duration::from_secs(2);
 
 pub type TransactionEventReceiver = UnboundedReceiver<TransactionEvent>;
 pub type TransactionEventSender = UnboundedSender<TransactionEvent>;
@@ -24,10 +41,15 @@ pub type TransactionEventSender = UnboundedSender<TransactionEvent>;
 ///
 /// # Events
 ///
-/// * `Received` - A SIP message was received for this transaction
+/// * `Received` - A SIP message was received for this transaction, with the
+///   connection and the peer address it came from when known
 /// * `Timer` - A transaction timer has fired
 /// * `Respond` - Request to send a response (server transactions only)
 /// * `Terminate` - Request to terminate the transaction
+/// * `Shutdown` - The endpoint is shutting down; a server transaction
+///   without a final response answers `503 Service Unavailable`
+/// * `ConnectionClosed` - The stream connection to this address closed; a
+///   transaction running over it terminates
 ///
 /// # Examples
 ///
@@ -37,7 +59,7 @@ pub type TransactionEventSender = UnboundedSender<TransactionEvent>;
 ///
 /// # fn handle_event(event: TransactionEvent) {
 /// match event {
-///     TransactionEvent::Received(msg, conn) => {
+///     TransactionEvent::Received(msg, conn, source) => {
 ///         // Process received SIP message
 ///     },
 ///     TransactionEvent::Timer(timer) => {
@@ -49,14 +71,22 @@ pub type TransactionEventSender = UnboundedSender<TransactionEvent>;
 ///     TransactionEvent::Terminate(key) => {
 ///         // Clean up transaction
 ///     }
+///     TransactionEvent::Shutdown => {
+///         // Reject the request if not answered yet
+///     }
+///     TransactionEvent::ConnectionClosed(addr) => {
+///         // Give up if the transaction runs over this connection
+///     }
 /// }
 /// # }
 /// ```
 pub enum TransactionEvent {
-    Received(SipMessage, Option<SipConnection>),
+    Received(SipMessage, Option<SipConnection>, Option<SipAddr>),
     Timer(TransactionTimer),
     Respond(Response),
     Terminate(TransactionKey),
+    Shutdown,
+    ConnectionClosed(SipAddr),
 }
 
 /// SIP Transaction
@@ -148,6 +178,23 @@ pub enum TransactionEvent {
 /// * Timer F: Non-INVITE transaction timeout
 /// * Timer G: INVITE response retransmission timer
 /// * Timer K: Wait time for ACK
+/// * Timer PRACK: Reliable provisional response retransmission timer
+///
+/// # Reliable Provisional Responses
+///
+/// A server INVITE transaction sends 1xx responses (except 100) reliably
+/// (RFC 3262) when the INVITE carries `Require: 100rel`, or when
+/// `reliable_provisional` is set and the INVITE lists `100rel` in
+/// `Supported`. Such a response gets `Require: 100rel` and an `RSeq` header
+/// and is retransmitted until the matching PRACK arrives, which is then
+/// passed to the TU by `receive`.
+///
+/// # ACK for 2xx
+///
+/// A client INVITE transaction ACKs a non-2xx final response itself. A 2xx
+/// is ACKed by the transaction too, unless `tu_acks_2xx` is set: the TU then
+/// receives every 2xx, retransmissions included, and sends its own ACK by
+/// setting `last_ack` and calling `send_ack` (RFC 3261 §13.2.2.4).
 pub struct Transaction {
     pub transaction_type: TransactionType,
     pub key: TransactionKey,
@@ -156,6 +203,10 @@ pub struct Transaction {
     pub state: TransactionState,
     pub endpoint_inner: EndpointInnerRef,
     pub connection: Option<SipConnection>,
+    /// Peer address the last message of this transaction was received
+    /// from, as seen by the transport; for UDP behind NAT this can differ
+    /// from the Via and Contact
+    pub source: Option<SipAddr>,
     pub last_response: Option<Response>,
     pub last_ack: Option<Request>,
     pub tu_receiver: TransactionEventReceiver,
@@ -164,9 +215,42 @@ pub struct Transaction {
     pub timer_b: Option<u64>,
     pub timer_c: Option<u64>,
     pub timer_d: Option<u64>,
-    pub timer_k: Option<u64>, // server invite only
-    pub timer_g: Option<u64>, // server invite only
+    pub timer_k: Option<u64>,     // server invite only
+    pub timer_g: Option<u64>,     // server invite only
+    pub timer_prack: Option<u64>, // server invite only
+    pub timers: Option<TimerConfig>,
+    pub reliable_provisional: bool,
+    pub tu_acks_2xx: bool,
+    rseq: u32,
+    unacked_provisional: Option<(u32, Response)>,
     is_cleaned_up: bool,
+    state_current: watch::Sender<TransactionState>,
+}
+
+/// Timer values for a single transaction
+///
+/// Overrides the endpoint-wide `EndpointOption` timers for one transaction,
+/// e.g. a short Timer F for an OPTIONS keepalive next to long-lived INVITEs.
+/// `t2` caps the Timer E/Timer G retransmission interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerConfig {
+    pub t1: Duration,
+    pub t2: Duration,
+    pub t4: Duration,
+    pub timer_b: Duration,
+    pub timer_f: Duration,
+}
+
+impl From<&EndpointOption> for TimerConfig {
+    fn from(option: &EndpointOption) -> Self {
+        Self {
+            t1: option.t1,
+            t2: option.t2,
+            t4: option.t4,
+            timer_b: option.t1x64,
+            timer_f: option.t1x64,
+        }
+    }
 }
 
 impl Transaction {
@@ -187,6 +271,7 @@ impl Transaction {
             TransactionState::Nothing
         };
         trace!(%key, %state, "transaction created");
+        let (state_current, _) = watch::channel(state.clone());
         let tx = Self {
             transaction_type,
             endpoint_inner,
@@ -197,18 +282,35 @@ impl Transaction {
             state,
             last_response: None,
             last_ack: None,
+            source: None,
             timer_a: None,
             timer_b: None,
             timer_c: None,
             timer_d: None,
             timer_k: None,
             timer_g: None,
+            timer_prack: None,
+            timers: None,
+            reliable_provisional: false,
+            tu_acks_2xx: false,
+            rseq: 0,
+            unacked_provisional: None,
             tu_receiver,
             tu_sender,
             is_cleaned_up: false,
+            state_current,
         };
         tx.endpoint_inner
             .attach_transaction(&tx.key, tx.tu_sender.clone());
+        if let Ok(call_id) = tx.original.call_id_header() {
+            tx.endpoint_inner
+                .record_transaction_call_id(&tx.key, call_id.value());
+        }
+        tx.endpoint_inner.record_transaction_state(
+            &tx.key,
+            tx.transaction_type.clone(),
+            tx.state.clone(),
+        );
         tx
     }
 
@@ -237,6 +339,50 @@ impl Transaction {
         };
         Transaction::new(tx_type, key, original, connection, endpoint_inner)
     }
+
+    /// State of the transaction, updated on every transition
+    ///
+    /// A new receiver sees the current state right away, without taking
+    /// messages from [`Self::receive`]. The sender closes when the
+    /// transaction is dropped.
+    pub fn state_watch(&self) -> watch::Receiver<TransactionState> {
+        self.state_current.subscribe()
+    }
+
+    /// Use `timers` instead of the endpoint defaults for this transaction
+    pub fn with_timers(mut self, timers: TimerConfig) -> Self {
+        self.timers = Some(timers);
+        self
+    }
+
+    /// Send to `destination` as is, without DNS or a locator
+    ///
+    /// For a client transaction, e.g. a registrar known by IP or a fixed
+    /// outbound proxy. The top Via takes the transport of the destination,
+    /// and a destination without one uses the transport of the Via.
+    pub fn with_destination(mut self, mut destination: SipAddr) -> Self {
+        if let Ok(mut via) = self.original.via_header().and_then(|via| via.typed()) {
+            match destination.r#type {
+                Some(transport) if transport != via.transport => {
+                    via.transport = transport;
+                    if let Ok(header) = self.original.via_header_mut() {
+                        *header = via.into();
+                    }
+                }
+                Some(_) => {}
+                None => destination.r#type = Some(via.transport),
+            }
+        }
+        self.destination = Some(destination);
+        self
+    }
+
+    /// Timers in effect: the per-transaction override or the endpoint defaults
+    pub fn timer_config(&self) -> TimerConfig {
+        self.timers
+            .unwrap_or_else(|| TimerConfig::from(&self.endpoint_inner.option))
+    }
+
     // send client request
     pub async fn send(&mut self) -> Result<()> {
         match self.transaction_type {
@@ -248,15 +394,29 @@ impl Transaction {
                 ));
             }
         }
+        self.ensure_not_terminated()?;
+
+        let content_length_header =
+            Header::ContentLength(ContentLength::from(self.original.body().len() as u32));
+        self.original
+            .headers_mut()
+            .unique_push(content_length_header);
+        if self.endpoint_inner.option.contact_without_brackets {
+            contact_without_brackets(&mut self.original.headers);
+        }
 
+        let mut lookup_target = None;
         if self.connection.is_none() {
             let target_uri = match &self.destination {
                 Some(addr) => addr,
                 None => {
+                    // a preloaded Route takes precedence over the Request-URI
+                    let target = destination_from_request(&self.original)
+                        .unwrap_or(Cow::Borrowed(&self.original.uri));
                     if let Some(locator) = self.endpoint_inner.locator.as_ref() {
-                        &locator.locate(&self.original.uri).await?
+                        &locator.locate(&target).await?
                     } else {
-                        &SipAddr::try_from(&self.original.uri)?
+                        &SipAddr::try_from(&*target)?
                     }
                 }
             };
@@ -266,33 +426,171 @@ impl Transaction {
                 .transport_layer
                 .lookup(target_uri, Some(&self.key))
                 .await?;
+            lookup_target = Some(target_uri.clone());
             // For UDP, we need to store the resolved destination address
             if !connection.is_reliable() {
                 self.destination.replace(resolved_addr);
             }
             self.connection.replace(connection);
+            self.follow_listener_address()?;
+            self.switch_large_request_to_tcp().await?;
         }
 
         let connection = self.connection.as_ref().ok_or(Error::TransactionError(
             "no connection found".to_string(),
             self.key.clone(),
         ))?;
-        let content_length_header =
-            Header::ContentLength(ContentLength::from(self.original.body().len() as u32));
-        self.original
-            .headers_mut()
-            .unique_push(content_length_header);
-
+        // RFC 7339: shed the share of new requests the peer asked us to
+        let out_of_dialog = self.original.method != rsip::Method::Cancel
+            && self
+                .original
+                .to_header()
+                .ok()
+                .and_then(|to| to.tag().ok().flatten())
+                .is_none();
+        let peer = &self
+            .destination
+            .as_ref()
+            .unwrap_or(connection.get_addr())
+            .addr;
+        if out_of_dialog && !self.endpoint_inner.load_control.admit(peer) {
+            return Err(Error::TransactionError(
+                "request dropped by overload control".to_string(),
+                self.key.clone(),
+            ));
+        }
         let message = if let Some(ref inspector) = self.endpoint_inner.message_inspector {
             inspector.before_send(self.original.to_owned().into())
         } else {
             self.original.to_owned().into()
         };
 
-        connection.send(message, self.destination.as_ref()).await?;
+        let sent = self
+            .endpoint_inner
+            .send_message(connection, message, self.destination.as_ref())
+            .await;
+        if let Some(target) = lookup_target {
+            self.endpoint_inner
+                .transport_layer
+                .record_send_result(&target, sent.is_ok());
+        }
+        sent?;
         self.transition(TransactionState::Calling).map(|_| ())
     }
 
+    /// Move a request over `udp_mtu_threshold` from UDP to TCP towards the
+    /// same address, rewriting the top Via to match (RFC 3261 §18.1.1)
+    ///
+    /// The sent-by moves to our TCP listener, or to the local end of the
+    /// new connection when there is none. The request stays on UDP, with a
+    /// warning, when no TCP connection can be made within
+    /// [`LARGE_REQUEST_TCP_TIMEOUT`].
+    async fn switch_large_request_to_tcp(&mut self) -> Result<()> {
+        let Some(threshold) = self.endpoint_inner.option.udp_mtu_threshold else {
+            return Ok(());
+        };
+        // only unreliable connections keep a destination
+        let Some(destination) = self.destination.as_ref() else {
+            return Ok(());
+        };
+        let size = self.original.to_string().len();
+        if size <= threshold {
+            return Ok(());
+        }
+        let target = SipAddr {
+            r#type: Some(rsip::transport::Transport::Tcp),
+            addr: destination.addr.clone(),
+        };
+        let lookup = tokio::time::timeout(
+            LARGE_REQUEST_TCP_TIMEOUT,
+            self.endpoint_inner
+                .transport_layer
+                .lookup(&target, Some(&self.key)),
+        )
+        .await;
+        match lookup {
+            Ok(Ok((connection, _))) if connection.is_reliable() => {
+                debug!(key=%self.key, size, "sending large request over tcp");
+                let mut via = self.original.via_header()?.typed()?;
+                via.transport = rsip::transport::Transport::Tcp;
+                if let Some(sent_by) = self.tcp_sent_by(&via.uri.host_with_port, &connection) {
+                    via.uri.host_with_port = sent_by;
+                }
+                *self.original.via_header_mut()? = via.into();
+                self.destination = None;
+                self.connection.replace(connection);
+            }
+            Ok(Ok(_)) => {
+                warn!(key=%self.key, size, "no tcp route for large request, sending over udp");
+            }
+            Ok(Err(e)) => {
+                warn!(key=%self.key, size, "no tcp connection for large request, sending over udp: {}", e);
+            }
+            Err(_) => {
+                warn!(key=%self.key, size, "tcp connection for large request timed out, sending over udp");
+            }
+        }
+        Ok(())
+    }
+
+    /// Sent-by for a request moved to TCP, `None` to keep `current`
+    ///
+    /// Only a Via naming one of our listeners is rewritten, so an advertised
+    /// external address stays. A TCP listener on the same host wins over any
+    /// other TCP listener, then over the local end of `connection`.
+    fn tcp_sent_by(
+        &self,
+        current: &rsip::HostWithPort,
+        connection: &SipConnection,
+    ) -> Option<rsip::HostWithPort> {
+        let listeners = self.endpoint_inner.transport_layer.get_addrs();
+        if !listeners.iter().any(|addr| addr.addr == *current) {
+            return None;
+        }
+        let tcp_listeners = listeners
+            .iter()
+            .filter(|addr| addr.r#type == Some(rsip::transport::Transport::Tcp))
+            .collect::<Vec<_>>();
+        tcp_listeners
+            .iter()
+            .find(|addr| addr.addr.host == current.host)
+            .or(tcp_listeners.first())
+            .map(|addr| addr.addr.clone())
+            .or_else(|| connection.local_addr().map(|addr| addr.addr))
+    }
+
+    /// Point the Via at the listener the request leaves from
+    ///
+    /// On a dual-homed endpoint the transport layer may pick a listener
+    /// other than the one the Via was built with. Only a Via naming one of
+    /// our listeners is rewritten, so an advertised external address stays.
+    fn follow_listener_address(&mut self) -> Result<()> {
+        let Some(connection) = self.connection.as_ref() else {
+            return Ok(());
+        };
+        if connection.is_reliable() {
+            return Ok(());
+        }
+        let source = connection.get_addr().clone();
+        let mut via = self.original.via_header()?.typed()?;
+        if via.uri.host_with_port == source.addr {
+            return Ok(());
+        }
+        let ours = self
+            .endpoint_inner
+            .transport_layer
+            .get_addrs()
+            .iter()
+            .any(|addr| addr.r#type == source.r#type && addr.addr == via.uri.host_with_port);
+        if !ours {
+            return Ok(());
+        }
+        debug!(key=%self.key, %source, "sending from the listener facing the destination");
+        via.uri.host_with_port = source.addr;
+        *self.original.via_header_mut()? = via.into();
+        Ok(())
+    }
+
     pub async fn reply_with(
         &mut self,
         status_code: StatusCode,
@@ -300,7 +598,8 @@ impl Transaction {
         body: Option<Vec<u8>>,
     ) -> Result<()> {
         match status_code.kind() {
-            rsip::StatusCodeKind::Provisional => {}
+            // a reliable provisional response creates an early dialog
+            rsip::StatusCodeKind::Provisional if !self.sends_reliably(&status_code) => {}
             _ => {
                 let to = self.original.to_header()?;
                 if to.tag()?.is_none() {
@@ -313,7 +612,14 @@ impl Transaction {
         let mut resp = self
             .endpoint_inner
             .make_response(&self.original, status_code, body);
-        resp.headers.extend(headers);
+        for header in headers {
+            match header {
+                rsip::Header::Server(_) | rsip::Header::UserAgent(_) => {
+                    resp.headers.unique_push(header)
+                }
+                _ => resp.headers.push(header),
+            }
+        }
         self.respond(resp).await
     }
     /// Quick reply with status code
@@ -331,6 +637,7 @@ impl Transaction {
                 ));
             }
         }
+        self.ensure_not_terminated()?;
 
         let new_state = match response.status_code.kind() {
             rsip::StatusCodeKind::Provisional => match response.status_code {
@@ -344,16 +651,34 @@ impl Transaction {
         };
         // check an transition to new state
         self.can_transition(&new_state)?;
+        let response = self.rewrite_sdp(response);
+        let (response, reliable) = self.prepare_reliable_provisional(response)?;
 
+        self.follow_via_transport().await;
         let connection = self.connection.as_ref().ok_or(Error::TransactionError(
             "no connection found".to_string(),
             self.key.clone(),
         ))?;
 
+        let compress = self
+            .endpoint_inner
+            .option
+            .compress_body_threshold
+            .is_some_and(|threshold| response.body.len() >= threshold);
+        let mut response: SipMessage = response.into();
+        if self.endpoint_inner.option.contact_without_brackets {
+            contact_without_brackets(response.headers_mut());
+        }
+        if compress {
+            if let Some(encoding) = negotiate_encoding(&self.original.headers) {
+                encode_message_body(&mut response, encoding)?;
+            }
+        }
+
         let response = if let Some(ref inspector) = self.endpoint_inner.message_inspector {
-            inspector.before_send(response.clone().to_owned().into())
+            inspector.before_send(response)
         } else {
-            response.to_owned().into()
+            response
         };
         trace!(key = %self.key, "responding with {}", response);
 
@@ -361,10 +686,125 @@ impl Transaction {
             SipMessage::Response(resp) => self.last_response.replace(resp),
             _ => None,
         };
-        connection.send(response, self.destination.as_ref()).await?;
+        self.endpoint_inner
+            .send_message(connection, response, self.destination.as_ref())
+            .await?;
+        if let Some(reliable) = reliable {
+            self.start_reliable_provisional(reliable);
+        }
         self.transition(new_state).map(|_| ())
     }
 
+    // hand an SDP body to the endpoint's rewriter, if any
+    fn rewrite_sdp(&self, mut response: Response) -> Response {
+        let Some(rewriter) = self.endpoint_inner.sdp_rewriter.as_ref() else {
+            return response;
+        };
+        if response.body.is_empty() {
+            return response;
+        }
+        let is_sdp = header_value_case_insensitive(&response.headers, "Content-Type")
+            .map(|content_type| content_type.trim().to_ascii_lowercase())
+            .is_some_and(|content_type| content_type.starts_with("application/sdp"));
+        let Some(sdp) = is_sdp.then(|| Sdp::parse(&response.body)).flatten() else {
+            return response;
+        };
+        if let Some(sdp) = rewriter.rewrite(&response, sdp) {
+            response.body = sdp.to_bytes();
+            response
+                .headers
+                .unique_push(Header::ContentLength(ContentLength::from(
+                    response.body.len() as u32,
+                )));
+        }
+        response
+    }
+
+    fn sends_reliably(&self, status_code: &StatusCode) -> bool {
+        self.transaction_type == TransactionType::ServerInvite
+            && status_code.kind() == StatusCodeKind::Provisional
+            && *status_code != StatusCode::Trying
+            && (header_contains_token(&self.original.headers, "Require", "100rel")
+                || (self.reliable_provisional
+                    && header_contains_token(&self.original.headers, "Supported", "100rel")))
+    }
+
+    // Add Require/RSeq to a new reliable provisional response. Returns the
+    // response to track, or None for unreliable responses and retransmissions
+    fn prepare_reliable_provisional(
+        &mut self,
+        mut response: Response,
+    ) -> Result<(Response, Option<Response>)> {
+        if !self.sends_reliably(&response.status_code)
+            || parse_rseq_header(&response.headers).is_some()
+        {
+            return Ok((response, None));
+        }
+        // RFC 3262 §3: only one unacknowledged reliable provisional at a time
+        if self.unacked_provisional.is_some() {
+            return Err(Error::TransactionError(
+                "reliable provisional response not yet acknowledged".to_string(),
+                self.key.clone(),
+            ));
+        }
+        self.rseq += 1;
+        response
+            .headers
+            .push(Header::Other("Require".into(), "100rel".into()));
+        response
+            .headers
+            .push(Header::Other("RSeq".into(), self.rseq.to_string()));
+        Ok((response.clone(), Some(response)))
+    }
+
+    fn start_reliable_provisional(&mut self, response: Response) {
+        if let Ok(dialog_id) = DialogId::try_from(&response) {
+            self.endpoint_inner
+                .waiting_prack
+                .write()
+                .as_mut()
+                .map(|wp| wp.insert(dialog_id, self.key.clone()))
+                .ok();
+        }
+        let t1 = self.timer_config().t1;
+        let timer_prack = self
+            .endpoint_inner
+            .timers
+            .timeout(t1, TransactionTimer::TimerPrack(self.key.clone(), t1));
+        self.timer_prack.replace(timer_prack);
+        self.unacked_provisional.replace((self.rseq, response));
+    }
+
+    fn clear_unacked_provisional(&mut self) {
+        self.timer_prack
+            .take()
+            .map(|id| self.endpoint_inner.timers.cancel(id));
+        if let Some((_, response)) = self.unacked_provisional.take() {
+            if let Ok(dialog_id) = DialogId::try_from(&response) {
+                self.endpoint_inner
+                    .waiting_prack
+                    .write()
+                    .as_mut()
+                    .map(|wp| wp.remove(&dialog_id))
+                    .ok();
+            }
+        }
+    }
+
+    // PRACK matching the unacknowledged reliable provisional response stops
+    // its retransmission and is passed to the TU
+    fn on_received_prack(&mut self, req: Request) -> Option<SipMessage> {
+        let (rseq, cseq, method) = parse_rack_header(&req.headers)?;
+        let (pending_rseq, _) = self.unacked_provisional.as_ref()?;
+        let invite_cseq = self.original.cseq_header().ok()?.seq().ok()?;
+        if rseq != *pending_rseq || cseq != invite_cseq || method != Method::Invite {
+            debug!(key=%self.key, rseq, cseq, "PRACK does not match reliable provisional response");
+            return None;
+        }
+        self.clear_unacked_provisional();
+        Some(req.into())
+    }
+
     fn can_transition(&self, target: &TransactionState) -> Result<()> {
         match (&self.state, target) {
             (&TransactionState::Nothing, &TransactionState::Calling)
@@ -380,6 +820,7 @@ impl Transaction {
             | (&TransactionState::Trying, &TransactionState::Completed)
             | (&TransactionState::Trying, &TransactionState::Confirmed)
             | (&TransactionState::Trying, &TransactionState::Terminated)
+            | (&TransactionState::Proceeding, &TransactionState::Proceeding) // further 1xx
             | (&TransactionState::Proceeding, &TransactionState::Completed)
             | (&TransactionState::Proceeding, &TransactionState::Confirmed)
             | (&TransactionState::Proceeding, &TransactionState::Terminated)
@@ -415,7 +856,9 @@ impl Transaction {
                         cancel.to_owned().into()
                     };
 
-                    connection.send(cancel, self.destination.as_ref()).await?;
+                    self.endpoint_inner
+                        .send_message(connection, cancel, self.destination.as_ref())
+                        .await?;
                 }
                 self.transition(TransactionState::Completed).map(|_| ())
             }
@@ -506,16 +949,48 @@ impl Transaction {
             _ => None,
         };
         if let Some(conn) = connection {
-            conn.send(ack, self.destination.as_ref()).await?;
+            self.endpoint_inner
+                .send_message(&conn, ack, self.destination.as_ref())
+                .await?;
+        }
+        // a 2xx keeps the transaction in Completed until Timer D, so that
+        // 2xx retransmissions and other forks are ACKed as well
+        if self
+            .last_response
+            .as_ref()
+            .is_some_and(|resp| resp.status_code.kind() == StatusCodeKind::Successful)
+        {
+            return Ok(());
         }
         // client send ack and transition to Terminated
         self.transition(TransactionState::Terminated).map(|_| ())
     }
 
+    /// A response to a client INVITE whose To tag differs from the previous
+    /// one: another UAS answered a forked request
+    fn is_forked_response(&self, resp: &Response) -> bool {
+        if self.transaction_type != TransactionType::ClientInvite {
+            return false;
+        }
+        let to_tag = |resp: &Response| {
+            resp.to_header()
+                .ok()
+                .and_then(|to| to.tag().ok().flatten())
+                .map(|tag| tag.value().to_string())
+        };
+        match (self.last_response.as_ref().and_then(to_tag), to_tag(resp)) {
+            (Some(last), Some(tag)) => last != tag,
+            _ => false,
+        }
+    }
+
     pub async fn receive(&mut self) -> Option<SipMessage> {
         while let Some(event) = self.tu_receiver.recv().await {
             match event {
-                TransactionEvent::Received(msg, connection) => {
+                TransactionEvent::Received(msg, connection, source) => {
+                    if source.is_some() {
+                        self.source = source;
+                    }
                     if let Some(msg) = match msg {
                         SipMessage::Request(req) => self.on_received_request(req, connection).await,
                         SipMessage::Response(resp) => {
@@ -538,12 +1013,49 @@ impl Transaction {
                     info!(%key, "received terminate event");
                     return None;
                 }
+                TransactionEvent::Shutdown => {
+                    let unanswered = matches!(
+                        self.state,
+                        TransactionState::Trying | TransactionState::Proceeding
+                    );
+                    if unanswered
+                        && matches!(
+                            self.transaction_type,
+                            TransactionType::ServerInvite | TransactionType::ServerNonInvite
+                        )
+                    {
+                        let response = self.endpoint_inner.make_response(
+                            &self.original,
+                            rsip::StatusCode::ServiceUnavailable,
+                            None,
+                        );
+                        self.respond(response).await.ok();
+                    }
+                }
+                TransactionEvent::ConnectionClosed(addr) => {
+                    let closed = self
+                        .connection
+                        .as_ref()
+                        .is_some_and(|c| c.is_reliable() && c.get_addr() == &addr);
+                    if closed {
+                        info!(key=%self.key, %addr, "connection closed");
+                        self.transition(TransactionState::Terminated).ok();
+                        return None;
+                    }
+                }
             }
         }
         None
     }
 
+    /// Send `100 Trying`, unless a response was already sent
+    ///
+    /// With [`EndpointOption::auto_100_trying`] the endpoint has already
+    /// sent it for a new INVITE, so calling this again is harmless.
     pub async fn send_trying(&mut self) -> Result<()> {
+        if self.last_response.is_some() {
+            return Ok(());
+        }
         let response =
             self.endpoint_inner
                 .make_response(&self.original, rsip::StatusCode::Trying, None);
@@ -553,6 +1065,52 @@ impl Transaction {
     pub fn is_terminated(&self) -> bool {
         self.state == TransactionState::Terminated
     }
+
+    /// Turn the transaction into a [`Stream`] of what `receive` returns
+    ///
+    /// Several transactions can then be merged or polled with other streams.
+    /// The stream ends when the transaction terminates, and dropping it
+    /// drops the transaction. To answer a server transaction after this,
+    /// keep a clone of `tu_sender` and send it `TransactionEvent::Respond`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use futures::StreamExt;
+    /// use rsipstack::transaction::transaction::Transaction;
+    ///
+    /// # async fn example(a: Transaction, b: Transaction) {
+    /// let mut messages = futures::stream::select(a.into_stream(), b.into_stream());
+    /// while let Some(msg) = messages.next().await {
+    ///     println!("{}", msg);
+    /// }
+    /// # }
+    /// ```
+    pub fn into_stream(self) -> TransactionStream {
+        let inner = futures::stream::unfold(self, |mut tx| async move {
+            if tx.is_terminated() {
+                return None;
+            }
+            let msg = tx.receive().await?;
+            Some((msg, tx))
+        });
+        TransactionStream {
+            inner: Box::pin(inner),
+        }
+    }
+}
+
+/// Messages of a transaction as a [`Stream`], see [`Transaction::into_stream`]
+pub struct TransactionStream {
+    inner: Pin<Box<dyn Stream<Item = SipMessage> + Send>>,
+}
+
+impl Stream for TransactionStream {
+    type Item = SipMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<SipMessage>> {
+        self.inner.as_mut().poll_next(cx)
+    }
 }
 
 impl Transaction {
@@ -561,6 +1119,7 @@ impl Transaction {
             .send(TransactionEvent::Received(
                 SipMessage::Response(response),
                 None,
+                None,
             ))
             .map_err(|e| Error::TransactionError(e.to_string(), self.key.clone()))
     }
@@ -578,15 +1137,21 @@ impl Transaction {
         if self.connection.is_none() && connection.is_some() {
             self.connection = connection;
         }
+        if req.method == Method::PRack {
+            return self.on_received_prack(req);
+        }
         if req.method == Method::Cancel {
             match self.state {
                 TransactionState::Proceeding
                 | TransactionState::Trying
                 | TransactionState::Completed => {
                     if let Some(connection) = &self.connection {
-                        let resp = self
-                            .endpoint_inner
-                            .make_response(&req, StatusCode::OK, None);
+                        let mut resp =
+                            self.endpoint_inner
+                                .make_response(&req, StatusCode::OK, None);
+                        for reason in reason_headers(&req.headers) {
+                            resp.headers.push(reason);
+                        }
 
                         let resp =
                             if let Some(ref inspector) = self.endpoint_inner.message_inspector {
@@ -595,7 +1160,10 @@ impl Transaction {
                                 resp.into()
                             };
 
-                        connection.send(resp, self.destination.as_ref()).await.ok();
+                        self.endpoint_inner
+                            .send_message(connection, resp, self.destination.as_ref())
+                            .await
+                            .ok();
                     }
                     return Some(req.into()); // into dialog
                 }
@@ -612,7 +1180,10 @@ impl Transaction {
                             } else {
                                 resp.into()
                             };
-                        connection.send(resp, self.destination.as_ref()).await.ok();
+                        self.endpoint_inner
+                            .send_message(connection, resp, self.destination.as_ref())
+                            .await
+                            .ok();
                     }
                 }
             };
@@ -628,9 +1199,27 @@ impl Transaction {
             }
             TransactionState::Completed | TransactionState::Confirmed => {
                 if req.method == Method::Ack {
+                    // cancels Timer G before the ACK reaches the TU
                     self.transition(TransactionState::Confirmed).ok();
+                    // RFC 3261 §17.2.1: the ACK of a non-2xx is part of
+                    // this transaction, only the ACK of a 2xx goes to the
+                    // dialog
+                    let acks_2xx = self
+                        .last_response
+                        .as_ref()
+                        .map(|resp| resp.status_code.kind() == StatusCodeKind::Successful)
+                        .unwrap_or(false);
+                    if !acks_2xx {
+                        debug!(key=%self.key, "ack of a non-2xx absorbed");
+                        return None;
+                    }
                     return Some(req.into());
                 }
+                // RFC 3261 §17.2.1: a retransmitted INVITE gets the final
+                // response again, until the ACK confirms it
+                if req.method == self.original.method {
+                    self.resend_final_response().await.ok();
+                }
             }
             _ => {}
         }
@@ -663,6 +1252,36 @@ impl Transaction {
             }
         };
 
+        if new_state == TransactionState::Proceeding
+            && self.transaction_type == TransactionType::ClientInvite
+        {
+            self.restart_timer_c();
+        }
+        let forked = self.is_forked_response(&resp);
+        if self.transaction_type == TransactionType::ClientInvite && self.state == new_state {
+            match new_state {
+                // a provisional response from another branch of a forked
+                // INVITE starts a new early dialog
+                TransactionState::Proceeding if forked => {
+                    self.last_response.replace(resp.clone());
+                    return Some(SipMessage::Response(resp));
+                }
+                // every 2xx is ACKed, retransmitted or forked (RFC 3261 §13.2.2.4),
+                // but only the first from each branch reaches the TU
+                TransactionState::Completed
+                    if resp.status_code.kind() == StatusCodeKind::Successful =>
+                {
+                    self.last_response.replace(resp.clone());
+                    if self.tu_acks_2xx {
+                        return Some(SipMessage::Response(resp));
+                    }
+                    self.last_ack.take();
+                    self.send_ack(connection).await.ok();
+                    return forked.then_some(SipMessage::Response(resp));
+                }
+                _ => {}
+            }
+        }
         self.can_transition(&new_state).ok()?;
         if self.state == new_state {
             // ignore duplicate response
@@ -671,7 +1290,9 @@ impl Transaction {
 
         self.last_response.replace(resp.clone());
         self.transition(new_state).ok();
-        self.send_ack(connection).await.ok(); // send ACK for client invite
+        if !(self.tu_acks_2xx && resp.status_code.kind() == StatusCodeKind::Successful) {
+            self.send_ack(connection).await.ok(); // send ACK for client invite
+        }
         Some(SipMessage::Response(resp))
     }
 
@@ -683,70 +1304,59 @@ impl Transaction {
                     TransactionType::ClientInvite | TransactionType::ClientNonInvite
                 ) {
                     if let TransactionTimer::TimerA(key, duration) = timer {
-                        // Resend the INVITE request
-                        if let Some(connection) = &self.connection {
-                            let retry_message = if let Some(ref inspector) =
-                                self.endpoint_inner.message_inspector
-                            {
-                                inspector.before_send(self.original.to_owned().into())
-                            } else {
-                                self.original.to_owned().into()
-                            };
-                            connection
-                                .send(retry_message, self.destination.as_ref())
-                                .await?;
-                        }
-                        // Restart Timer A with an upper limit
-                        let duration = (duration * 2).min(self.endpoint_inner.option.t1x64);
-                        let timer_a = self
-                            .endpoint_inner
-                            .timers
-                            .timeout(duration, TransactionTimer::TimerA(key, duration));
+                        // Resend the INVITE request, Timer A keeps doubling
+                        self.retransmit_request().await?;
+                        let duration = duration * 2;
+                        let timer_a = self.endpoint_inner.timers.timeout(
+                            self.retransmission_delay(duration),
+                            TransactionTimer::TimerA(key, duration),
+                        );
                         self.timer_a.replace(timer_a);
-                    } else if let TransactionTimer::TimerB(_) = timer {
+                    } else if let TransactionTimer::TimerE(key, duration) = timer {
+                        self.on_timer_e(key, (duration * 2).min(self.timer_config().t2))
+                            .await?;
+                    } else if let TransactionTimer::TimerB(_) | TransactionTimer::TimerF(_) = timer
+                    {
                         let timeout_response = self.endpoint_inner.make_response(
                             &self.original,
                             rsip::StatusCode::RequestTimeout,
                             None,
                         );
                         self.inform_tu_response(timeout_response)?;
+                    } else if let TransactionTimer::TimerC(_) = timer {
+                        self.on_timer_c()?;
                     }
                 }
             }
             TransactionState::Proceeding => {
                 if let TransactionTimer::TimerC(_) = timer {
-                    // Inform TU about timeout
-                    let timeout_response = self.endpoint_inner.make_response(
-                        &self.original,
-                        rsip::StatusCode::RequestTimeout,
-                        None,
-                    );
-                    self.inform_tu_response(timeout_response)?;
+                    self.on_timer_c()?;
+                } else if let TransactionTimer::TimerPrack(key, duration) = timer {
+                    self.on_timer_prack(key, duration).await?;
+                } else if let TransactionTimer::TimerE(key, _) = timer {
+                    // RFC 3261 §17.1.2.2: after a provisional response
+                    // the request is resent every T2
+                    self.on_timer_e(key, self.timer_config().t2).await?;
+                } else if let TransactionTimer::TimerF(_) = timer {
+                    if self.transaction_type == TransactionType::ClientNonInvite {
+                        let timeout_response = self.endpoint_inner.make_response(
+                            &self.original,
+                            rsip::StatusCode::RequestTimeout,
+                            None,
+                        );
+                        self.inform_tu_response(timeout_response)?;
+                    }
                 }
             }
             TransactionState::Completed => {
                 if let TransactionTimer::TimerG(key, duration) = timer {
-                    // resend the response
-                    if let Some(last_response) = &self.last_response {
-                        if let Some(connection) = &self.connection {
-                            let last_response = if let Some(ref inspector) =
-                                self.endpoint_inner.message_inspector
-                            {
-                                inspector.before_send(last_response.to_owned().into())
-                            } else {
-                                last_response.to_owned().into()
-                            };
-                            connection
-                                .send(last_response, self.destination.as_ref())
-                                .await?;
-                        }
-                    }
+                    self.resend_final_response().await?;
                     // restart Timer G with an upper limit
-                    let duration = (duration * 2).min(self.endpoint_inner.option.t1x64);
-                    let timer_g = self
-                        .endpoint_inner
-                        .timers
-                        .timeout(duration, TransactionTimer::TimerG(key, duration));
+                    let duration = (duration * 2).min(self.timer_config().t2);
+                    let timer_g = self.endpoint_inner.timers.timeout(
+                        self.retransmission_delay(duration),
+                        TransactionTimer::TimerG(key, duration),
+                    );
                     self.timer_g.replace(timer_g);
                 } else if let TransactionTimer::TimerD(_) = timer {
                     self.transition(TransactionState::Terminated)?;
@@ -755,6 +1365,8 @@ impl Transaction {
                 }
             }
             TransactionState::Confirmed => {
+                // a Timer G already queued when the ACK arrived lands here
+                // and is dropped: nothing is resent once confirmed
                 if let TransactionTimer::TimerK(_) = timer {
                     self.transition(TransactionState::Terminated)?;
                 }
@@ -764,6 +1376,214 @@ impl Transaction {
         Ok(())
     }
 
+    /// Resend the final response of a server INVITE awaiting its ACK
+    async fn resend_final_response(&self) -> Result<()> {
+        if self.state != TransactionState::Completed {
+            return Ok(());
+        }
+        let (Some(last_response), Some(connection)) = (&self.last_response, &self.connection)
+        else {
+            return Ok(());
+        };
+        let last_response = if let Some(ref inspector) = self.endpoint_inner.message_inspector {
+            inspector.before_send(last_response.to_owned().into())
+        } else {
+            last_response.to_owned().into()
+        };
+        self.endpoint_inner
+            .send_message(connection, last_response, self.destination.as_ref())
+            .await?;
+        self.endpoint_inner.record_retransmission();
+        Ok(())
+    }
+
+    async fn retransmit_request(&self) -> Result<()> {
+        if let Some(connection) = &self.connection {
+            let retry_message = if let Some(ref inspector) = self.endpoint_inner.message_inspector {
+                inspector.before_send(self.original.to_owned().into())
+            } else {
+                self.original.to_owned().into()
+            };
+            self.endpoint_inner
+                .send_message(connection, retry_message, self.destination.as_ref())
+                .await?;
+            self.endpoint_inner.record_retransmission();
+        }
+        Ok(())
+    }
+
+    // Resend the non-INVITE request and restart Timer E with the next interval
+    async fn on_timer_e(&mut self, key: TransactionKey, duration: Duration) -> Result<()> {
+        if self.transaction_type != TransactionType::ClientNonInvite {
+            return Ok(());
+        }
+        self.retransmit_request().await?;
+        let timer_e = self.endpoint_inner.timers.timeout(
+            self.retransmission_delay(duration),
+            TransactionTimer::TimerE(key, duration),
+        );
+        self.timer_a.replace(timer_e);
+        Ok(())
+    }
+
+    // RFC 3262 §3: retransmit the reliable provisional response with the
+    // interval doubling from T1; without a PRACK after 64*T1 reject the INVITE
+    async fn on_timer_prack(&mut self, key: TransactionKey, duration: Duration) -> Result<()> {
+        let response = match &self.unacked_provisional {
+            Some((_, response)) => response.clone(),
+            None => return Ok(()),
+        };
+        if duration * 2 >= self.timer_config().t1 * 64 {
+            info!(key=%self.key, "reliable provisional response not acknowledged, rejecting");
+            self.clear_unacked_provisional();
+            return self.reply(StatusCode::ServerInternalError).await;
+        }
+        if let Some(connection) = &self.connection {
+            let response = if let Some(ref inspector) = self.endpoint_inner.message_inspector {
+                inspector.before_send(response.into())
+            } else {
+                response.into()
+            };
+            self.endpoint_inner
+                .send_message(connection, response, self.destination.as_ref())
+                .await?;
+        }
+        let duration = duration * 2;
+        let timer_prack = self
+            .endpoint_inner
+            .timers
+            .timeout(duration, TransactionTimer::TimerPrack(key, duration));
+        self.timer_prack.replace(timer_prack);
+        Ok(())
+    }
+
+    // RFC 3261 §16.8: no final response in time, cancel the INVITE and
+    // report 408 to the TU. The CANCEL runs in its own client transaction.
+    fn on_timer_c(&mut self) -> Result<()> {
+        if self.transaction_type != TransactionType::ClientInvite {
+            return Ok(());
+        }
+        info!(key=%self.key, "timer C fired, cancelling invite");
+        let cancel = self.endpoint_inner.make_cancel(&self.original)?;
+        let key = TransactionKey::from_request(&cancel, TransactionRole::Client)?;
+        let mut cancel_tx = Transaction::new_client(
+            key,
+            cancel,
+            self.endpoint_inner.clone(),
+            self.connection.clone(),
+        );
+        cancel_tx.destination = self.destination.clone();
+        tokio::spawn(async move {
+            if cancel_tx.send().await.is_err() {
+                return;
+            }
+            while let Some(msg) = cancel_tx.receive().await {
+                if let SipMessage::Response(resp) = msg {
+                    if resp.status_code.kind() != StatusCodeKind::Provisional {
+                        break;
+                    }
+                }
+            }
+        });
+
+        let timeout_response = self.endpoint_inner.make_response(
+            &self.original,
+            rsip::StatusCode::RequestTimeout,
+            None,
+        );
+        self.inform_tu_response(timeout_response)
+    }
+
+    // a provisional response other than 100 proves the callee is alive
+    fn restart_timer_c(&mut self) {
+        if let Some(id) = self.timer_c.take() {
+            self.endpoint_inner.timers.cancel(id);
+            let timer_c = self.endpoint_inner.timers.timeout(
+                self.endpoint_inner.option.timerc,
+                TransactionTimer::TimerC(self.key.clone()),
+            );
+            self.timer_c.replace(timer_c);
+        }
+    }
+
+    // Timer A/E/G keep doubling the nominal interval; only the delay
+    // actually waited is jittered
+    fn retransmission_delay(&self, interval: Duration) -> Duration {
+        match self.endpoint_inner.option.retransmission_jitter {
+            Some(percent) => jitter_duration(interval, percent),
+            None => interval,
+        }
+    }
+
+    /// Answer over the transport the top Via asks for (RFC 3261 §18.2.2)
+    ///
+    /// A request received over TCP with `SIP/2.0/UDP` in its top Via, or the
+    /// other way round, is answered over the Via's transport to its `sent-by`
+    /// (with `received` applied) instead of over the connection it came in on.
+    async fn follow_via_transport(&mut self) {
+        let Some(connection) = self.connection.as_ref() else {
+            return;
+        };
+        if connection.is_in_memory() {
+            return;
+        }
+        let Some(via) = self
+            .original
+            .via_header()
+            .ok()
+            .and_then(|via| via.typed().ok())
+        else {
+            return;
+        };
+        if Some(via.transport) == connection.get_addr().r#type {
+            return;
+        }
+        let mut addr = via.uri.host_with_port.clone();
+        // rport is the source port of the other transport, only the address carries over
+        if let Some(received) = via.params.iter().find_map(|param| match param {
+            rsip::Param::Received(received) => received.parse().ok(),
+            _ => None,
+        }) {
+            addr.host = received.into();
+        }
+        let target = SipAddr {
+            r#type: Some(via.transport),
+            addr,
+        };
+        match self
+            .endpoint_inner
+            .transport_layer
+            .lookup(&target, Some(&self.key))
+            .await
+        {
+            Ok((connection, destination)) => {
+                debug!(key = %self.key, %target, "responding over the via transport");
+                self.connection = Some(connection);
+                self.destination = Some(destination);
+            }
+            Err(e) => {
+                info!(
+                    key = %self.key,
+                    %target,
+                    "no connection for the via transport, using the receiving one: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    // a terminated transaction is detached from the endpoint, so sending on it
+    // would re-arm timers for a key nobody owns anymore
+    fn ensure_not_terminated(&self) -> Result<()> {
+        if self.state == TransactionState::Terminated {
+            return Err(Error::TransactionError(
+                "already terminated".to_string(),
+                self.key.clone(),
+            ));
+        }
+        Ok(())
+    }
+
     fn transition(&mut self, state: TransactionState) -> Result<TransactionState> {
         if self.state == state {
             return Ok(self.state.clone());
@@ -780,26 +1600,41 @@ impl Transaction {
                     self.transaction_type,
                     TransactionType::ClientInvite | TransactionType::ClientNonInvite
                 ) {
+                    let timers = self.timer_config();
+                    let is_invite = self.transaction_type == TransactionType::ClientInvite;
+                    // Timer A/B for INVITE, Timer E/F for non-INVITE; the
+                    // retransmit timer lives in `timer_a`, the timeout in `timer_b`
                     if !connection.is_reliable() {
-                        let timer_a = self.endpoint_inner.timers.timeout(
-                            self.endpoint_inner.option.t1,
-                            TransactionTimer::TimerA(
-                                self.key.clone(),
-                                self.endpoint_inner.option.t1,
-                            ),
-                        );
+                        let retransmit = if is_invite {
+                            TransactionTimer::TimerA(self.key.clone(), timers.t1)
+                        } else {
+                            TransactionTimer::TimerE(self.key.clone(), timers.t1)
+                        };
+                        let timer_a = self
+                            .endpoint_inner
+                            .timers
+                            .timeout(self.retransmission_delay(timers.t1), retransmit);
                         self.timer_a.replace(timer_a);
                     }
-                    self.timer_b.replace(self.endpoint_inner.timers.timeout(
-                        self.endpoint_inner.option.t1x64,
-                        TransactionTimer::TimerB(self.key.clone()),
-                    ));
+                    let timeout = if is_invite {
+                        self.endpoint_inner
+                            .timers
+                            .timeout(timers.timer_b, TransactionTimer::TimerB(self.key.clone()))
+                    } else {
+                        self.endpoint_inner
+                            .timers
+                            .timeout(timers.timer_f, TransactionTimer::TimerF(self.key.clone()))
+                    };
+                    self.timer_b.replace(timeout);
                 }
             }
             TransactionState::Trying | TransactionState::Proceeding => {
-                self.timer_a
-                    .take()
-                    .map(|id| self.endpoint_inner.timers.cancel(id));
+                // Timer E keeps running for non-INVITE until a final response
+                if self.transaction_type != TransactionType::ClientNonInvite {
+                    self.timer_a
+                        .take()
+                        .map(|id| self.endpoint_inner.timers.cancel(id));
+                }
                 if matches!(self.transaction_type, TransactionType::ClientInvite) {
                     self.timer_b
                         .take()
@@ -824,6 +1659,8 @@ impl Transaction {
                 self.timer_c
                     .take()
                     .map(|id| self.endpoint_inner.timers.cancel(id));
+                // a final response ends reliable provisional retransmission
+                self.clear_unacked_provisional();
 
                 if self.transaction_type == TransactionType::ServerInvite {
                     // start Timer G for server invite only
@@ -831,32 +1668,28 @@ impl Transaction {
                         "no connection found".to_string(),
                         self.key.clone(),
                     ))?;
+                    let t1 = self.timer_config().t1;
                     if !connection.is_reliable() {
                         let timer_g = self.endpoint_inner.timers.timeout(
-                            self.endpoint_inner.option.t1,
-                            TransactionTimer::TimerG(
-                                self.key.clone(),
-                                self.endpoint_inner.option.t1,
-                            ),
+                            self.retransmission_delay(t1),
+                            TransactionTimer::TimerG(self.key.clone(), t1),
                         );
                         self.timer_g.replace(timer_g);
                     }
                     info!(key=%self.key, last = self.last_response.is_none(), "entered confirmed state, waiting for ACK");
-                    match self.last_response {
-                        Some(ref resp) => {
-                            let dialog_id = DialogId::try_from(resp)?;
-                            self.endpoint_inner
-                                .waiting_ack
-                                .write()
-                                .as_mut()
-                                .map(|wa| wa.insert(dialog_id, self.key.clone()))
-                                .ok();
-                        }
-                        _ => {}
+                    // a final response without a To tag has no dialog to ACK
+                    if let Some(Ok(dialog_id)) = self.last_response.as_ref().map(DialogId::try_from)
+                    {
+                        self.endpoint_inner
+                            .waiting_ack
+                            .write()
+                            .as_mut()
+                            .map(|wa| wa.insert(dialog_id, self.key.clone()))
+                            .ok();
                     }
                     // start Timer K, wait for ACK
                     let timer_k = self.endpoint_inner.timers.timeout(
-                        self.endpoint_inner.option.t4,
+                        self.timer_config().t4,
                         TransactionTimer::TimerK(self.key.clone()),
                     );
                     self.timer_k.replace(timer_k);
@@ -871,7 +1704,7 @@ impl Transaction {
             TransactionState::Confirmed => {
                 self.cleanup_timer();
                 let timer_k = self.endpoint_inner.timers.timeout(
-                    self.endpoint_inner.option.t4,
+                    self.timer_config().t4,
                     TransactionTimer::TimerK(self.key.clone()),
                 );
                 self.timer_k.replace(timer_k);
@@ -887,6 +1720,14 @@ impl Transaction {
             key = %self.key,
             "transition: {:?} -> {:?}", self.state, state
         );
+        if state != TransactionState::Terminated {
+            self.endpoint_inner.record_transaction_state(
+                &self.key,
+                self.transaction_type.clone(),
+                state.clone(),
+            );
+        }
+        self.state_current.send_replace(state.clone());
         self.state = state;
         Ok(self.state.clone())
     }
@@ -910,6 +1751,9 @@ impl Transaction {
         self.timer_g
             .take()
             .map(|id| self.endpoint_inner.timers.cancel(id));
+        self.timer_prack
+            .take()
+            .map(|id| self.endpoint_inner.timers.cancel(id));
     }
 
     fn cleanup(&mut self) {
@@ -917,6 +1761,7 @@ impl Transaction {
             return;
         }
         self.is_cleaned_up = true;
+        self.clear_unacked_provisional();
         self.cleanup_timer();
 
         match self.last_response {
@@ -0,0 +1,84 @@
+use super::{SipAddr, SipConnection};
+use rsip::SipMessage;
+use std::time::SystemTime;
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+use tracing::debug;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureDirection {
+    Inbound,
+    Outbound,
+}
+
+/// A SIP message seen on the wire, for pcap or HEP/EEP (Homer) export
+///
+/// `connection` is the address of the connection that carried the message:
+/// the local bind address for UDP, the peer for connection oriented
+/// transports. `remote` is the peer the message came from or went to, when
+/// it is known.
+#[derive(Debug, Clone)]
+pub struct CapturedMessage {
+    pub timestamp: SystemTime,
+    pub direction: CaptureDirection,
+    pub transport: rsip::Transport,
+    pub connection: SipAddr,
+    pub remote: Option<SipAddr>,
+    pub message: SipMessage,
+}
+
+/// Mirrors SIP traffic into a bounded channel
+///
+/// Capturing never waits: when the channel is full or closed the record is
+/// dropped, so a slow collector can't stall signaling.
+///
+/// # Examples
+///
+/// ```rust
+/// use rsipstack::EndpointBuilder;
+///
+/// let (capture_tx, mut capture_rx) = tokio::sync::mpsc::channel(1024);
+/// let endpoint = EndpointBuilder::new().with_capture_sink(capture_tx).build();
+/// // every message sent or received by `endpoint` now shows up on `capture_rx`
+/// ```
+#[derive(Clone)]
+pub struct CaptureSink {
+    sender: Sender<CapturedMessage>,
+}
+
+impl CaptureSink {
+    pub fn new(sender: Sender<CapturedMessage>) -> Self {
+        Self { sender }
+    }
+
+    pub fn capture(
+        &self,
+        direction: CaptureDirection,
+        message: &SipMessage,
+        connection: &SipConnection,
+        remote: Option<&SipAddr>,
+    ) {
+        let remote = match remote {
+            Some(remote) => Some(remote.clone()),
+            None if connection.is_reliable() => Some(connection.get_addr().clone()),
+            // UDP without a destination goes where the Via points
+            None => SipConnection::get_destination(message)
+                .ok()
+                .map(SipAddr::from),
+        };
+        let record = CapturedMessage {
+            timestamp: SystemTime::now(),
+            direction,
+            transport: connection.get_addr().r#type.unwrap_or_default(),
+            connection: connection.get_addr().clone(),
+            remote,
+            message: message.clone(),
+        };
+        match self.sender.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                debug!("capture sink full, dropping captured message");
+            }
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use super::SipAddr;
+use crate::Result;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// State of the circuit for one target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests flow normally; `failures` consecutive failures so far
+    Closed { failures: u32 },
+    /// Requests fast-fail until the cool-down ends
+    Open { until: Instant },
+    /// Cool-down elapsed; the next request probes the target
+    HalfOpen,
+}
+
+impl Default for CircuitState {
+    fn default() -> Self {
+        CircuitState::Closed { failures: 0 }
+    }
+}
+
+/// Per-target circuit breaker for DNS and transport failures
+///
+/// After `failure_threshold` consecutive failures to a target, new requests
+/// to it fail immediately for `cool_down`. Once the cool-down has elapsed the
+/// next request is let through as a probe: success closes the circuit again,
+/// failure reopens it for another cool-down.
+///
+/// # Examples
+///
+/// ```rust
+/// use rsipstack::transport::circuit_breaker::{CircuitBreaker, CircuitState};
+/// use rsipstack::transport::SipAddr;
+/// use std::time::Duration;
+///
+/// let breaker = CircuitBreaker::new(3, Duration::from_secs(30));
+/// let registrar: SipAddr = rsip::HostWithPort::try_from("192.0.2.10:5060").unwrap().into();
+///
+/// breaker.record_failure(&registrar);
+/// assert_eq!(breaker.state(&registrar), CircuitState::Closed { failures: 1 });
+/// assert!(breaker.check(&registrar).is_ok());
+/// ```
+pub struct CircuitBreaker {
+    pub failure_threshold: u32,
+    pub cool_down: Duration,
+    circuits: Mutex<HashMap<SipAddr, CircuitState>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cool_down: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cool_down,
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fail fast if the circuit for `target` is open
+    pub fn check(&self, target: &SipAddr) -> Result<()> {
+        let mut circuits = match self.circuits.lock() {
+            Ok(circuits) => circuits,
+            Err(e) => {
+                warn!("Failed to lock circuits: {:?}", e);
+                return Ok(());
+            }
+        };
+        if let Some(state) = circuits.get_mut(target) {
+            if let CircuitState::Open { until } = *state {
+                if Instant::now() < until {
+                    return Err(crate::Error::TransportLayerError(
+                        "circuit open".to_string(),
+                        target.to_owned(),
+                    ));
+                }
+                info!(%target, "circuit half-open, probing");
+                *state = CircuitState::HalfOpen;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record_success(&self, target: &SipAddr) {
+        if let Ok(mut circuits) = self.circuits.lock() {
+            if let Some(CircuitState::HalfOpen) = circuits.remove(target) {
+                info!(%target, "circuit closed");
+            }
+        }
+    }
+
+    pub fn record_failure(&self, target: &SipAddr) {
+        if let Ok(mut circuits) = self.circuits.lock() {
+            let state = circuits.entry(target.to_owned()).or_default();
+            let failures = match *state {
+                CircuitState::Closed { failures } => failures + 1,
+                _ => self.failure_threshold,
+            };
+            *state = if failures >= self.failure_threshold {
+                warn!(%target, failures, cool_down = ?self.cool_down, "circuit open");
+                CircuitState::Open {
+                    until: Instant::now() + self.cool_down,
+                }
+            } else {
+                CircuitState::Closed { failures }
+            };
+        }
+    }
+
+    pub fn state(&self, target: &SipAddr) -> CircuitState {
+        self.circuits
+            .lock()
+            .ok()
+            .and_then(|circuits| circuits.get(target).copied())
+            .unwrap_or_default()
+    }
+
+    /// Targets with at least one recorded failure and their circuit state
+    pub fn states(&self) -> Vec<(SipAddr, CircuitState)> {
+        match self.circuits.lock() {
+            Ok(circuits) => circuits
+                .iter()
+                .map(|(target, state)| (target.to_owned(), *state))
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
@@ -1,18 +1,19 @@
 use super::{sip_addr::SipAddr, stream::StreamConnection, tcp::TcpConnection, udp::UdpConnection};
 use crate::transport::channel::ChannelConnection;
+#[cfg(any(test, feature = "test-util"))]
+use crate::transport::loopback::LoopbackConnection;
 use crate::transport::websocket::{WebSocketConnection, WebSocketListenerConnection};
 use crate::transport::{
     tcp_listener::TcpListenerConnection,
     tls::{TlsConnection, TlsListenerConnection},
 };
 use crate::Result;
-use get_if_addrs::IfAddr;
 use rsip::{
-    prelude::{HeadersExt, ToTypedHeader},
+    prelude::{HeadersExt, ToTypedHeader, UntypedHeader},
     Param, SipMessage,
 };
-use std::net::{IpAddr, Ipv4Addr};
-use std::{fmt, net::SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::{fmt, net::SocketAddr, time::Duration};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio_util::sync::CancellationToken;
 use tracing::debug;
@@ -29,6 +30,8 @@ use tracing::debug;
 /// * `Incoming` - A SIP message was received from the network
 /// * `New` - A new connection has been established
 /// * `Closed` - An existing connection has been closed
+/// * `BadRequest` - A request was dropped for broken framing, with the
+///   reason; the endpoint answers it with `400 Bad Request`
 ///
 /// # Examples
 ///
@@ -48,6 +51,9 @@ use tracing::debug;
 ///     TransportEvent::Closed(connection) => {
 ///         // Handle connection closure
 ///         println!("Connection closed");
+///     },
+///     TransportEvent::BadRequest(request, connection, source, reason) => {
+///         println!("Malformed {} from {}: {}", request.method, source, reason);
 ///     }
 /// }
 /// # }
@@ -57,6 +63,7 @@ pub enum TransportEvent {
     Incoming(SipMessage, SipConnection, SipAddr),
     New(SipConnection),
     Closed(SipConnection),
+    BadRequest(rsip::Request, SipConnection, SipAddr, String),
 }
 
 pub type TransportReceiver = UnboundedReceiver<TransportEvent>;
@@ -64,6 +71,9 @@ pub type TransportSender = UnboundedSender<TransportEvent>;
 
 pub const KEEPALIVE_REQUEST: &[u8] = b"\r\n\r\n";
 pub const KEEPALIVE_RESPONSE: &[u8] = b"\r\n";
+/// How long a stream connection waits for the pong to its keepalive ping
+/// (RFC 5626 §4.4.1)
+pub const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
 pub const MAX_UDP_BUF_SIZE: usize = 8192;
 
 /// SIP Connection
@@ -76,6 +86,8 @@ pub const MAX_UDP_BUF_SIZE: usize = 8192;
 ///
 /// * `Udp` - UDP transport for connectionless communication
 /// * `Channel` - In-memory channel for testing and local communication
+/// * `Loopback` - In-memory datagram link between two endpoints, for tests
+///   (`test-util` feature)
 /// * `Tcp` - TCP transport for reliable connection-oriented communication
 /// * `Tls` - TLS transport for secure communication over TCP
 /// * `WebSocket` - WebSocket transport for web-based SIP clients
@@ -148,6 +160,8 @@ pub const MAX_UDP_BUF_SIZE: usize = 8192;
 #[derive(Clone, Debug)]
 pub enum SipConnection {
     Channel(ChannelConnection),
+    #[cfg(any(test, feature = "test-util"))]
+    Loopback(LoopbackConnection),
     Udp(UdpConnection),
     Tcp(TcpConnection),
     TcpListener(TcpListenerConnection),
@@ -165,13 +179,40 @@ impl SipConnection {
     pub fn is_reliable(&self) -> bool {
         match self {
             SipConnection::Udp(_) => false,
+            #[cfg(any(test, feature = "test-util"))]
+            SipConnection::Loopback(_) => false,
             _ => true,
         }
     }
 
+    /// Whether messages stay inside the process instead of going on the
+    /// wire, as for the channel and loopback transports
+    pub fn is_in_memory(&self) -> bool {
+        match self {
+            SipConnection::Channel(_) => true,
+            #[cfg(any(test, feature = "test-util"))]
+            SipConnection::Loopback(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Keepalive ping interval of a TCP or TLS connection, see
+    /// [`StreamConnectionInner::set_keepalive_interval`](super::stream::StreamConnectionInner::set_keepalive_interval).
+    /// Other transports ignore it.
+    pub fn set_keepalive_interval(&self, interval: Duration) {
+        match self {
+            SipConnection::Tcp(transport) => transport.inner.set_keepalive_interval(interval),
+            #[cfg(feature = "rustls")]
+            SipConnection::Tls(transport) => transport.set_keepalive_interval(interval),
+            _ => {}
+        }
+    }
+
     pub fn cancel_token(&self) -> Option<CancellationToken> {
         match self {
             SipConnection::Channel(transport) => transport.cancel_token(),
+            #[cfg(any(test, feature = "test-util"))]
+            SipConnection::Loopback(transport) => transport.cancel_token(),
             SipConnection::Udp(transport) => transport.cancel_token(),
             SipConnection::Tcp(transport) => transport.cancel_token(),
             #[cfg(feature = "rustls")]
@@ -184,6 +225,8 @@ impl SipConnection {
     pub fn get_addr(&self) -> &SipAddr {
         match self {
             SipConnection::Channel(transport) => transport.get_addr(),
+            #[cfg(any(test, feature = "test-util"))]
+            SipConnection::Loopback(transport) => transport.get_addr(),
             SipConnection::Udp(transport) => transport.get_addr(),
             SipConnection::Tcp(transport) => transport.get_addr(),
             SipConnection::TcpListener(transport) => transport.get_addr(),
@@ -197,9 +240,43 @@ impl SipConnection {
             SipConnection::WebSocketListener(transport) => transport.get_addr(),
         }
     }
+    /// Address this side of the connection is bound to
+    ///
+    /// For TCP and TLS this is the local end of the socket pair, while
+    /// `get_addr` is the remote one. `None` for WebSocket connections,
+    /// which don't track it.
+    pub fn local_addr(&self) -> Option<SipAddr> {
+        match self {
+            SipConnection::Tcp(transport) => Some(transport.inner.local_addr.clone()),
+            #[cfg(feature = "rustls")]
+            SipConnection::Tls(transport) => Some(transport.local_addr().clone()),
+            #[cfg(feature = "websocket")]
+            SipConnection::WebSocket(_) => None,
+            _ => Some(self.get_addr().clone()),
+        }
+    }
+
+    /// Peer of a connection-oriented transport or of a loopback link
+    ///
+    /// `None` for UDP, where each message names its own destination.
+    pub fn remote_addr(&self) -> Option<SipAddr> {
+        match self {
+            #[cfg(any(test, feature = "test-util"))]
+            SipConnection::Loopback(transport) => Some(transport.peer_addr().clone()),
+            SipConnection::Tcp(_) => Some(self.get_addr().clone()),
+            #[cfg(feature = "rustls")]
+            SipConnection::Tls(_) => Some(self.get_addr().clone()),
+            #[cfg(feature = "websocket")]
+            SipConnection::WebSocket(_) => Some(self.get_addr().clone()),
+            _ => None,
+        }
+    }
+
     pub async fn send(&self, msg: rsip::SipMessage, destination: Option<&SipAddr>) -> Result<()> {
         match self {
             SipConnection::Channel(transport) => transport.send(msg).await,
+            #[cfg(any(test, feature = "test-util"))]
+            SipConnection::Loopback(transport) => transport.send(msg).await,
             SipConnection::Udp(transport) => transport.send(msg, destination).await,
             SipConnection::Tcp(transport) => transport.send_message(msg).await,
             SipConnection::TcpListener(_) => {
@@ -225,6 +302,8 @@ impl SipConnection {
     pub async fn serve_loop(&self, sender: TransportSender) -> Result<()> {
         match self {
             SipConnection::Channel(transport) => transport.serve_loop(sender).await,
+            #[cfg(any(test, feature = "test-util"))]
+            SipConnection::Loopback(transport) => transport.serve_loop(sender).await,
             SipConnection::Udp(transport) => transport.serve_loop(sender).await,
             SipConnection::Tcp(transport) => transport.serve_loop(sender).await,
             SipConnection::TcpListener(_) => {
@@ -251,7 +330,9 @@ impl SipConnection {
     pub async fn close(&self) -> Result<()> {
         match self {
             SipConnection::Channel(transport) => transport.close().await,
-            SipConnection::Udp(_) => Ok(()), // UDP has no connection state
+            SipConnection::Udp(_) => Ok(()), // no connection state
+            #[cfg(any(test, feature = "test-util"))]
+            SipConnection::Loopback(_) => Ok(()),
             SipConnection::Tcp(transport) => transport.close().await,
             SipConnection::TcpListener(transport) => transport.close().await,
             #[cfg(feature = "rustls")]
@@ -266,6 +347,68 @@ impl SipConnection {
     }
 }
 
+/// Append `params` to the topmost value of a raw Via header
+///
+/// `received` and `rport` already present are dropped; all other text,
+/// including whitespace, parameter order and case, is kept as received.
+fn stamp_raw_via(raw: &str, params: &[Param]) -> String {
+    let (top, rest) = raw.split_at(raw.find(',').unwrap_or(raw.len()));
+    let top_trimmed = top.trim_end();
+    let mut stamped = top_trimmed
+        .split(';')
+        .enumerate()
+        .filter(|(i, segment)| {
+            let name = segment.split('=').next().unwrap_or_default().trim();
+            *i == 0
+                || !(name.eq_ignore_ascii_case("received") || name.eq_ignore_ascii_case("rport"))
+        })
+        .map(|(_, segment)| segment)
+        .collect::<Vec<_>>()
+        .join(";");
+    for param in params {
+        stamped.push_str(&param.to_string());
+    }
+    stamped.push_str(&top[top_trimmed.len()..]);
+    stamped.push_str(rest);
+    stamped
+}
+
+// lower is better; `None` for addresses a Contact cannot carry
+fn interface_address_rank(ip: &IpAddr) -> Option<u8> {
+    match ip {
+        _ if ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() => None,
+        IpAddr::V4(v4) if v4.is_link_local() => Some(2),
+        IpAddr::V4(_) => Some(0),
+        // link-local needs a zone index, which a SIP URI has no room for
+        IpAddr::V6(v6) if v6.segments()[0] & 0xffc0 == 0xfe80 => None,
+        // unique local, fc00::/7
+        IpAddr::V6(v6) if v6.segments()[0] & 0xfe00 == 0xfc00 => Some(2),
+        IpAddr::V6(_) => Some(1),
+    }
+}
+
+/// Best address among `candidates` to advertise in Via and Contact
+///
+/// IPv4 addresses come first so dual-stack hosts keep their IPv4 Contact,
+/// then global IPv6 addresses, then unique local and IPv4 link-local ones.
+/// Loopback, unspecified, multicast and IPv6 link-local addresses are
+/// never picked.
+pub fn preferred_interface_address(candidates: impl IntoIterator<Item = IpAddr>) -> Option<IpAddr> {
+    candidates
+        .into_iter()
+        .filter_map(|ip| interface_address_rank(&ip).map(|rank| (rank, ip)))
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, ip)| ip)
+}
+
+/// Preferred non-loopback address of this host, IPv4 or IPv6, see
+/// [`preferred_interface_address`]
+pub fn get_first_non_loopback_interface() -> Result<IpAddr> {
+    let interfaces = get_if_addrs::get_if_addrs()?;
+    preferred_interface_address(interfaces.iter().map(|interface| interface.ip()))
+        .ok_or_else(|| crate::Error::Error("No non-loopback interface found".to_string()))
+}
+
 impl SipConnection {
     pub fn update_msg_received(
         msg: SipMessage,
@@ -282,28 +425,25 @@ impl SipConnection {
         }
     }
 
+    /// Replace an unspecified bind address (`0.0.0.0` or `::`) with the
+    /// preferred interface address of the same family, see
+    /// [`preferred_interface_address`]
     pub fn resolve_bind_address(addr: SocketAddr) -> SocketAddr {
         let ip = addr.ip();
         if ip.is_unspecified() {
-            // 0.0.0.0 or ::
             let interfaces = match get_if_addrs::get_if_addrs() {
                 Ok(interfaces) => interfaces,
                 Err(_) => return addr,
             };
-            for interface in interfaces {
-                if interface.is_loopback() {
-                    continue;
-                }
-                match interface.addr {
-                    IfAddr::V4(v4addr) => {
-                        return SocketAddr::new(IpAddr::V4(v4addr.ip), addr.port());
-                    }
-                    //TODO: don't support ipv6 for now
-                    _ => continue,
-                }
-            }
-            // fallback to loopback
-            return SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), addr.port());
+            let candidates = interfaces
+                .iter()
+                .map(|interface| interface.ip())
+                .filter(|candidate| candidate.is_ipv4() == ip.is_ipv4());
+            let ip = preferred_interface_address(candidates).unwrap_or(match ip {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+            return SocketAddr::new(ip, addr.port());
         }
         addr
     }
@@ -313,20 +453,17 @@ impl SipConnection {
         transport: rsip::transport::Transport,
     ) -> Result<()> {
         let received = addr.into();
-        let mut typed_via = via.typed()?;
-
-        typed_via.params.retain(|param| {
-            if let Param::Other(key, _) = param {
-                !key.value().eq_ignore_ascii_case("rport")
-            } else if matches!(param, Param::Received(_)) {
-                false
-            } else {
-                true
-            }
-        });
+        let typed_via = via.typed()?;
+
+        // a UDP client sending an empty `rport` (RFC 3581) always gets
+        // `received` and `rport`, so responses follow its NAT binding
+        let rport_requested = transport == rsip::transport::Transport::Udp
+            && typed_via.params.iter().any(|p| {
+                matches!(p, Param::Other(key, None) if key.value().eq_ignore_ascii_case("rport"))
+            });
 
         // Only add received parameter if the source address differs from Via header
-        if typed_via.uri.host_with_port == received {
+        if typed_via.uri.host_with_port == received && !rport_requested {
             return Ok(());
         }
 
@@ -343,19 +480,21 @@ impl SipConnection {
             return Ok(());
         }
 
+        let mut params = Vec::new();
         if transport != rsip::transport::Transport::Udp && typed_via.transport != transport {
-            typed_via.params.push(Param::Transport(transport));
+            params.push(Param::Transport(transport));
         }
+        params.push(Param::Received(rsip::param::Received::new(
+            received.host.to_string(),
+        )));
+        params.push(Param::Other(
+            rsip::param::OtherParam::new("rport"),
+            Some(rsip::param::OtherParamValue::new(addr.port().to_string())),
+        ));
 
-        *via = typed_via
-            .with_param(Param::Received(rsip::param::Received::new(
-                received.host.to_string(),
-            )))
-            .with_param(Param::Other(
-                rsip::param::OtherParam::new("rport"),
-                Some(rsip::param::OtherParamValue::new(addr.port().to_string())),
-            ))
-            .into();
+        // splice into the raw value instead of reserializing the typed Via, so
+        // proxies relay everything the sender wrote byte-for-byte
+        *via = rsip::headers::Via::new(stamp_raw_via(via.value(), &params));
         Ok(())
     }
 
@@ -400,6 +539,8 @@ impl fmt::Display for SipConnection {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             SipConnection::Channel(t) => write!(f, "{}", t),
+            #[cfg(any(test, feature = "test-util"))]
+            SipConnection::Loopback(t) => write!(f, "LOOPBACK {}", t),
             SipConnection::Udp(t) => write!(f, "UDP {}", t),
             SipConnection::Tcp(t) => write!(f, "TCP {}", t),
             SipConnection::TcpListener(t) => write!(f, "TCP LISTEN {}", t),
@@ -421,6 +562,13 @@ impl From<ChannelConnection> for SipConnection {
     }
 }
 
+#[cfg(any(test, feature = "test-util"))]
+impl From<LoopbackConnection> for SipConnection {
+    fn from(connection: LoopbackConnection) -> Self {
+        SipConnection::Loopback(connection)
+    }
+}
+
 impl From<UdpConnection> for SipConnection {
     fn from(connection: UdpConnection) -> Self {
         SipConnection::Udp(connection)
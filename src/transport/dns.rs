@@ -0,0 +1,105 @@
+//! RFC 3263 server location
+//!
+//! A domain without an explicit port is looked up as NAPTR records naming
+//! the transports it supports, then SRV records per transport, then the
+//! A/AAAA records of each SRV target. The helpers here put NAPTR and SRV
+//! records in the order their candidates are tried.
+
+use rand::Rng;
+use rsip::Transport;
+
+/// One SRV record (RFC 2782)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvTarget {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+/// One NAPTR record (RFC 3403)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NaptrService {
+    pub order: u16,
+    pub preference: u16,
+    pub flags: String,
+    pub service: String,
+    pub replacement: String,
+}
+
+/// SRV owner name for `domain` over `transport`, e.g. `_sip._udp.example.com`
+pub fn srv_name(transport: Transport, domain: &str) -> String {
+    let (scheme, proto) = match transport {
+        Transport::Udp => ("sip", "udp"),
+        Transport::Tcp => ("sip", "tcp"),
+        Transport::Tls => ("sips", "tcp"),
+        Transport::Ws => ("sip", "ws"),
+        Transport::Wss => ("sips", "ws"),
+        _ => ("sip", "sctp"),
+    };
+    format!("_{}._{}.{}", scheme, proto, domain.trim_end_matches('.'))
+}
+
+/// Transport of a NAPTR service field such as `SIP+D2U`
+pub fn naptr_transport(service: &str) -> Option<Transport> {
+    match service.to_ascii_uppercase().as_str() {
+        "SIP+D2U" => Some(Transport::Udp),
+        "SIP+D2T" => Some(Transport::Tcp),
+        "SIPS+D2T" => Some(Transport::Tls),
+        "SIP+D2W" => Some(Transport::Ws),
+        "SIPS+D2W" => Some(Transport::Wss),
+        _ => None,
+    }
+}
+
+/// SRV names to query, in order, from the NAPTR records of a domain
+///
+/// Only `S` records of a known SIP service are kept, sorted by order and
+/// then preference.
+pub fn naptr_srv_names(mut records: Vec<NaptrService>) -> Vec<(Transport, String)> {
+    records.sort_by_key(|record| (record.order, record.preference));
+    records
+        .into_iter()
+        .filter(|record| record.flags.eq_ignore_ascii_case("s"))
+        .filter_map(|record| {
+            naptr_transport(&record.service).map(|transport| {
+                (
+                    transport,
+                    record.replacement.trim_end_matches('.').to_string(),
+                )
+            })
+        })
+        .collect()
+}
+
+/// SRV records in the order they are tried (RFC 2782)
+///
+/// Lower priorities come first. Within a priority each pick is random,
+/// weighted by the records' weights, with zero-weight records given a
+/// small chance of coming first.
+pub fn order_srv_targets(mut targets: Vec<SrvTarget>, rng: &mut impl Rng) -> Vec<SrvTarget> {
+    targets.sort_by_key(|target| (target.priority, target.weight != 0));
+    let mut ordered = Vec::with_capacity(targets.len());
+    while !targets.is_empty() {
+        let priority = targets[0].priority;
+        let end = targets
+            .iter()
+            .position(|target| target.priority != priority)
+            .unwrap_or(targets.len());
+        let mut group = targets.drain(..end).collect::<Vec<_>>();
+        while !group.is_empty() {
+            let total = group.iter().map(|target| target.weight as u32).sum::<u32>();
+            let pick = rng.random_range(0..=total);
+            let mut running = 0;
+            let index = group
+                .iter()
+                .position(|target| {
+                    running += target.weight as u32;
+                    running >= pick
+                })
+                .unwrap_or(0);
+            ordered.push(group.remove(index));
+        }
+    }
+    ordered
+}
@@ -0,0 +1,216 @@
+use super::capture::{CaptureDirection, CapturedMessage};
+use crate::Result;
+use rsip::prelude::{HeadersExt, UntypedHeader};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::UNIX_EPOCH;
+use tokio::{net::UdpSocket, sync::mpsc::Receiver};
+use tracing::{debug, info};
+
+const HEP3_MAGIC: &[u8] = b"HEP3";
+const HEP_CHUNK_HEADER_LEN: usize = 6;
+
+// generic chunk types, vendor 0x0000
+pub const CHUNK_IP_FAMILY: u16 = 0x0001;
+pub const CHUNK_IP_PROTOCOL: u16 = 0x0002;
+pub const CHUNK_IPV4_SRC: u16 = 0x0003;
+pub const CHUNK_IPV4_DST: u16 = 0x0004;
+pub const CHUNK_IPV6_SRC: u16 = 0x0005;
+pub const CHUNK_IPV6_DST: u16 = 0x0006;
+pub const CHUNK_SRC_PORT: u16 = 0x0007;
+pub const CHUNK_DST_PORT: u16 = 0x0008;
+pub const CHUNK_TIMESTAMP_SEC: u16 = 0x0009;
+pub const CHUNK_TIMESTAMP_USEC: u16 = 0x000a;
+pub const CHUNK_PROTOCOL_TYPE: u16 = 0x000b;
+pub const CHUNK_CAPTURE_ID: u16 = 0x000c;
+pub const CHUNK_AUTH_KEY: u16 = 0x000e;
+pub const CHUNK_PAYLOAD: u16 = 0x000f;
+pub const CHUNK_CORRELATION_ID: u16 = 0x0011;
+pub const CHUNK_NODE_NAME: u16 = 0x0013;
+
+const IP_FAMILY_V4: u8 = 2;
+const IP_FAMILY_V6: u8 = 10;
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+const PROTOCOL_TYPE_SIP: u8 = 1;
+
+/// Settings of a HEP3 (Homer Encapsulation Protocol) exporter
+#[derive(Debug, Clone)]
+pub struct HepConfig {
+    /// Homer collector (heplify-server, homer-app) to send the packets to
+    pub collector: SocketAddr,
+    /// Capture agent id carried in every packet
+    pub capture_id: u32,
+    /// Optional capture password expected by the collector
+    pub auth_key: Option<String>,
+    /// Optional capture agent name
+    pub node_name: Option<String>,
+}
+
+impl HepConfig {
+    pub fn new(collector: SocketAddr) -> Self {
+        Self {
+            collector,
+            capture_id: 2001,
+            auth_key: None,
+            node_name: None,
+        }
+    }
+}
+
+fn push_chunk(buf: &mut Vec<u8>, chunk_type: u16, payload: &[u8]) {
+    buf.extend_from_slice(&0u16.to_be_bytes()); // vendor id
+    buf.extend_from_slice(&chunk_type.to_be_bytes());
+    buf.extend_from_slice(&((HEP_CHUNK_HEADER_LEN + payload.len()) as u16).to_be_bytes());
+    buf.extend_from_slice(payload);
+}
+
+fn socket_addr(addr: Option<&super::SipAddr>) -> Option<SocketAddr> {
+    addr.and_then(|addr| addr.get_socketaddr().ok())
+}
+
+/// Encode a captured message as a HEP3 packet
+///
+/// For UDP the local side of the packet is the bind address of the
+/// connection. Stream connections only know their peer, so the local side is
+/// sent as the unspecified address with port 0.
+pub fn encode_hep3(captured: &CapturedMessage, config: &HepConfig) -> Result<Vec<u8>> {
+    let remote = socket_addr(captured.remote.as_ref())
+        .or_else(|| socket_addr(Some(&captured.connection)))
+        .ok_or(crate::Error::Error(
+            "captured message without a remote address".to_string(),
+        ))?;
+    let local = match captured.transport {
+        rsip::Transport::Udp => socket_addr(Some(&captured.connection)),
+        _ => None,
+    }
+    .unwrap_or_else(|| match remote {
+        SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+        SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+    });
+    let (src, dst) = match captured.direction {
+        CaptureDirection::Inbound => (remote, local),
+        CaptureDirection::Outbound => (local, remote),
+    };
+
+    let mut buf = Vec::with_capacity(128);
+    buf.extend_from_slice(HEP3_MAGIC);
+    buf.extend_from_slice(&0u16.to_be_bytes()); // total length, patched below
+
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            push_chunk(&mut buf, CHUNK_IP_FAMILY, &[IP_FAMILY_V4]);
+            push_chunk(&mut buf, CHUNK_IPV4_SRC, &src_ip.octets());
+            push_chunk(&mut buf, CHUNK_IPV4_DST, &dst_ip.octets());
+        }
+        (src_ip, dst_ip) => {
+            let to_v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            push_chunk(&mut buf, CHUNK_IP_FAMILY, &[IP_FAMILY_V6]);
+            push_chunk(&mut buf, CHUNK_IPV6_SRC, &to_v6(src_ip).octets());
+            push_chunk(&mut buf, CHUNK_IPV6_DST, &to_v6(dst_ip).octets());
+        }
+    }
+    let ip_protocol = match captured.transport {
+        rsip::Transport::Udp => IP_PROTOCOL_UDP,
+        _ => IP_PROTOCOL_TCP,
+    };
+    push_chunk(&mut buf, CHUNK_IP_PROTOCOL, &[ip_protocol]);
+    push_chunk(&mut buf, CHUNK_SRC_PORT, &src.port().to_be_bytes());
+    push_chunk(&mut buf, CHUNK_DST_PORT, &dst.port().to_be_bytes());
+
+    let since_epoch = captured
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    push_chunk(
+        &mut buf,
+        CHUNK_TIMESTAMP_SEC,
+        &(since_epoch.as_secs() as u32).to_be_bytes(),
+    );
+    push_chunk(
+        &mut buf,
+        CHUNK_TIMESTAMP_USEC,
+        &since_epoch.subsec_micros().to_be_bytes(),
+    );
+    push_chunk(&mut buf, CHUNK_PROTOCOL_TYPE, &[PROTOCOL_TYPE_SIP]);
+    push_chunk(&mut buf, CHUNK_CAPTURE_ID, &config.capture_id.to_be_bytes());
+    if let Some(auth_key) = &config.auth_key {
+        push_chunk(&mut buf, CHUNK_AUTH_KEY, auth_key.as_bytes());
+    }
+    if let Some(node_name) = &config.node_name {
+        push_chunk(&mut buf, CHUNK_NODE_NAME, node_name.as_bytes());
+    }
+    let call_id = match &captured.message {
+        rsip::SipMessage::Request(req) => req.call_id_header().ok().map(|h| h.value().to_string()),
+        rsip::SipMessage::Response(resp) => {
+            resp.call_id_header().ok().map(|h| h.value().to_string())
+        }
+    };
+    if let Some(call_id) = call_id {
+        push_chunk(&mut buf, CHUNK_CORRELATION_ID, call_id.as_bytes());
+    }
+    push_chunk(
+        &mut buf,
+        CHUNK_PAYLOAD,
+        captured.message.to_string().as_bytes(),
+    );
+
+    let total_len = u16::try_from(buf.len())
+        .map_err(|_| crate::Error::Error("HEP3 packet too large".to_string()))?;
+    buf[4..6].copy_from_slice(&total_len.to_be_bytes());
+    Ok(buf)
+}
+
+/// Sends captured messages to a Homer collector as HEP3 over UDP
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use rsipstack::transport::hep::{HepConfig, HepExporter};
+/// use rsipstack::EndpointBuilder;
+///
+/// # async fn example() -> rsipstack::Result<()> {
+/// let (capture_tx, capture_rx) = tokio::sync::mpsc::channel(1024);
+/// let endpoint = EndpointBuilder::new().with_capture_sink(capture_tx).build();
+///
+/// let mut config = HepConfig::new("192.0.2.50:9060".parse()?);
+/// config.capture_id = 42;
+/// config.node_name = Some("edge-1".to_string());
+/// let exporter = HepExporter::bind(config).await?;
+/// tokio::spawn(exporter.serve(capture_rx));
+/// # Ok(())
+/// # }
+/// ```
+pub struct HepExporter {
+    config: HepConfig,
+    socket: UdpSocket,
+}
+
+impl HepExporter {
+    pub async fn bind(config: HepConfig) -> Result<Self> {
+        let bind_addr: SocketAddr = match config.collector {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(bind_addr).await?;
+        Ok(Self { config, socket })
+    }
+
+    pub async fn export(&self, captured: &CapturedMessage) -> Result<()> {
+        let packet = encode_hep3(captured, &self.config)?;
+        self.socket.send_to(&packet, self.config.collector).await?;
+        Ok(())
+    }
+
+    /// Export everything received on `receiver` until all senders are gone
+    pub async fn serve(self, mut receiver: Receiver<CapturedMessage>) {
+        info!(collector = %self.config.collector, "hep exporter started");
+        while let Some(captured) = receiver.recv().await {
+            if let Err(e) = self.export(&captured).await {
+                debug!("failed to export captured message: {}", e);
+            }
+        }
+    }
+}
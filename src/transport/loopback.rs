@@ -0,0 +1,229 @@
+use super::{
+    connection::{TransportEvent, TransportSender},
+    SipAddr, SipConnection,
+};
+use crate::Result;
+use rand::Rng;
+use rsip::SipMessage;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+/// Impairments applied to the messages one side of a loopback link sends
+#[derive(Debug, Clone, Default)]
+struct LinkImpairment {
+    latency: Duration,
+    loss_rate: f64,
+    drop_next: usize,
+}
+
+struct LoopbackInner {
+    addr: SipAddr,
+    incoming_tx: UnboundedSender<SipMessage>,
+    incoming_rx: Mutex<Option<UnboundedReceiver<SipMessage>>>,
+    impairment: Mutex<LinkImpairment>,
+}
+
+impl LoopbackInner {
+    fn new(addr: SipAddr) -> Arc<Self> {
+        let (incoming_tx, incoming_rx) = unbounded_channel();
+        Arc::new(Self {
+            addr,
+            incoming_tx,
+            incoming_rx: Mutex::new(Some(incoming_rx)),
+            impairment: Mutex::new(LinkImpairment::default()),
+        })
+    }
+}
+
+/// In-memory datagram link between two endpoints in the same process
+///
+/// Each side is added to its endpoint's [`TransportLayer`](super::TransportLayer)
+/// with `add_transport` and behaves like a UDP socket bound to its own
+/// address: it shows up in the Via, is unreliable so the transaction timers
+/// run, and whatever it sends is delivered to the other side. Latency and
+/// loss can be injected per direction to exercise retransmissions without
+/// touching the network.
+///
+/// # Examples
+///
+/// ```rust
+/// use rsipstack::transport::loopback::LoopbackConnection;
+/// use rsipstack::transport::{SipAddr, TransportLayer};
+/// use std::time::Duration;
+/// use tokio_util::sync::CancellationToken;
+///
+/// let alice_addr = SipAddr::new(
+///     rsip::Transport::Udp,
+///     rsip::HostWithPort::try_from("127.0.0.1:5060").unwrap(),
+/// );
+/// let bob_addr = SipAddr::new(
+///     rsip::Transport::Udp,
+///     rsip::HostWithPort::try_from("127.0.0.1:5062").unwrap(),
+/// );
+/// let (alice, bob) = LoopbackConnection::pair(alice_addr, bob_addr, None);
+/// alice.set_latency(Duration::from_millis(20));
+/// alice.drop_next(1);
+///
+/// let alice_tl = TransportLayer::new(CancellationToken::new());
+/// alice_tl.add_transport(alice.into());
+/// let bob_tl = TransportLayer::new(CancellationToken::new());
+/// bob_tl.add_transport(bob.into());
+/// ```
+#[derive(Clone)]
+pub struct LoopbackConnection {
+    local: Arc<LoopbackInner>,
+    peer: Arc<LoopbackInner>,
+    cancel_token: Option<CancellationToken>,
+}
+
+impl LoopbackConnection {
+    /// Create both sides of a link, bound to `a` and `b`
+    pub fn pair(a: SipAddr, b: SipAddr, cancel_token: Option<CancellationToken>) -> (Self, Self) {
+        let a = LoopbackInner::new(a);
+        let b = LoopbackInner::new(b);
+        (
+            Self {
+                local: a.clone(),
+                peer: b.clone(),
+                cancel_token: cancel_token.clone(),
+            },
+            Self {
+                local: b,
+                peer: a,
+                cancel_token,
+            },
+        )
+    }
+
+    /// Delay every message sent from this side by `latency`
+    pub fn set_latency(&self, latency: Duration) {
+        if let Ok(mut impairment) = self.local.impairment.lock() {
+            impairment.latency = latency;
+        }
+    }
+
+    /// Drop each message sent from this side with probability `loss_rate`
+    /// (0.0 to 1.0)
+    pub fn set_loss_rate(&self, loss_rate: f64) {
+        if let Ok(mut impairment) = self.local.impairment.lock() {
+            impairment.loss_rate = loss_rate.clamp(0.0, 1.0);
+        }
+    }
+
+    /// Drop the next `count` messages sent from this side, for deterministic
+    /// retransmission tests
+    pub fn drop_next(&self, count: usize) {
+        if let Ok(mut impairment) = self.local.impairment.lock() {
+            impairment.drop_next = count;
+        }
+    }
+
+    pub async fn send(&self, msg: SipMessage) -> Result<()> {
+        let latency = match self.local.impairment.lock() {
+            Ok(mut impairment) => {
+                if impairment.drop_next > 0 {
+                    impairment.drop_next -= 1;
+                    debug!(addr = %self.local.addr, "loopback dropping message");
+                    return Ok(());
+                }
+                if impairment.loss_rate > 0.0 && rand::rng().random_bool(impairment.loss_rate) {
+                    debug!(addr = %self.local.addr, "loopback losing message");
+                    return Ok(());
+                }
+                impairment.latency
+            }
+            Err(_) => Duration::ZERO,
+        };
+        if latency.is_zero() {
+            self.peer.incoming_tx.send(msg).ok();
+        } else {
+            let peer = self.peer.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(latency).await;
+                peer.incoming_tx.send(msg).ok();
+            });
+        }
+        Ok(())
+    }
+
+    pub fn get_addr(&self) -> &SipAddr {
+        &self.local.addr
+    }
+
+    /// Address of the other side of the link
+    pub fn peer_addr(&self) -> &SipAddr {
+        &self.peer.addr
+    }
+
+    /// Receiver of what the other side sends, taken once by whoever reads
+    /// this side
+    pub(super) fn take_incoming(&self) -> Option<UnboundedReceiver<SipMessage>> {
+        match self.local.incoming_rx.lock() {
+            Ok(mut incoming) => incoming.take(),
+            Err(_) => None,
+        }
+    }
+
+    pub async fn serve_loop(&self, sender: TransportSender) -> Result<()> {
+        let mut incoming = self.take_incoming().ok_or(crate::Error::Error(
+            "LoopbackConnection::serve_loop called twice".to_string(),
+        ))?;
+        let source = self.peer.addr.clone();
+        let cancelled = async {
+            match &self.cancel_token {
+                Some(token) => token.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(cancelled);
+        loop {
+            let msg = tokio::select! {
+                _ = &mut cancelled => return Ok(()),
+                msg = incoming.recv() => match msg {
+                    Some(msg) => msg,
+                    None => return Ok(()),
+                },
+            };
+            let msg = match source.get_socketaddr() {
+                Ok(addr) => match SipConnection::update_msg_received(
+                    msg,
+                    addr,
+                    self.local.addr.r#type.unwrap_or_default(),
+                ) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        debug!(addr = %self.local.addr, "loopback dropping bad message: {}", e);
+                        continue;
+                    }
+                },
+                Err(_) => msg,
+            };
+            sender.send(TransportEvent::Incoming(
+                msg,
+                SipConnection::Loopback(self.clone()),
+                source.clone(),
+            ))?;
+        }
+    }
+
+    pub fn cancel_token(&self) -> Option<CancellationToken> {
+        self.cancel_token.clone()
+    }
+}
+
+impl std::fmt::Display for LoopbackConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} <-> {}", self.local.addr, self.peer.addr)
+    }
+}
+
+impl std::fmt::Debug for LoopbackConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
@@ -0,0 +1,106 @@
+use super::{loopback::LoopbackConnection, SipAddr, SipConnection};
+use crate::Result;
+use rsip::SipMessage;
+use std::sync::Arc;
+use tokio::sync::{mpsc::UnboundedReceiver, Mutex};
+use tokio_util::sync::CancellationToken;
+
+/// Scripted transport for deterministic tests without sockets
+///
+/// The endpoint's side is one end of a [`LoopbackConnection`] pair, added to
+/// a [`TransportLayer`](super::TransportLayer) with `add_transport`. The test
+/// plays the other end, bound to the remote address, one message at a time:
+/// [`Self::next_sent`] returns what the endpoint sent and [`Self::inject`]
+/// delivers a message as received from the remote address.
+///
+/// # Examples
+///
+/// ```rust
+/// use rsipstack::transport::mock::MockConnection;
+/// use rsipstack::transport::{SipAddr, TransportLayer};
+/// use tokio_util::sync::CancellationToken;
+///
+/// let addr = |port: u16| {
+///     SipAddr::new(
+///         rsip::Transport::Udp,
+///         rsip::HostWithPort::try_from(format!("127.0.0.1:{}", port).as_str()).unwrap(),
+///     )
+/// };
+/// let mock = MockConnection::new(addr(5060), addr(5062), None);
+/// let transport_layer = TransportLayer::new(CancellationToken::new());
+/// transport_layer.add_transport(mock.clone().into());
+/// assert!(mock.take_sent().is_empty());
+/// ```
+#[derive(Clone)]
+pub struct MockConnection {
+    connection: LoopbackConnection,
+    remote: LoopbackConnection,
+    sent: Arc<Mutex<UnboundedReceiver<SipMessage>>>,
+}
+
+impl MockConnection {
+    /// Endpoint side bound to `addr`, scripted side bound to `remote`
+    pub fn new(addr: SipAddr, remote: SipAddr, cancel_token: Option<CancellationToken>) -> Self {
+        let (connection, remote) = LoopbackConnection::pair(addr, remote, cancel_token);
+        let sent = remote
+            .take_incoming()
+            .expect("a new loopback side has its receiver");
+        Self {
+            connection,
+            remote,
+            sent: Arc::new(Mutex::new(sent)),
+        }
+    }
+
+    /// Deliver `msg` to the endpoint as received from the remote address
+    pub async fn inject(&self, msg: impl Into<SipMessage>) -> Result<()> {
+        self.remote.send(msg.into()).await
+    }
+
+    /// Wait for the next message the endpoint sends
+    pub async fn next_sent(&self) -> Option<SipMessage> {
+        self.sent.lock().await.recv().await
+    }
+
+    /// Messages sent so far and not yet taken, oldest first
+    pub fn take_sent(&self) -> Vec<SipMessage> {
+        let mut taken = Vec::new();
+        if let Ok(mut sent) = self.sent.try_lock() {
+            while let Ok(msg) = sent.try_recv() {
+                taken.push(msg);
+            }
+        }
+        taken
+    }
+
+    /// The endpoint's side of the link
+    pub fn connection(&self) -> &LoopbackConnection {
+        &self.connection
+    }
+
+    pub fn get_addr(&self) -> &SipAddr {
+        self.connection.get_addr()
+    }
+
+    pub fn remote_addr(&self) -> &SipAddr {
+        self.remote.get_addr()
+    }
+}
+
+impl From<MockConnection> for SipConnection {
+    fn from(mock: MockConnection) -> Self {
+        SipConnection::Loopback(mock.connection)
+    }
+}
+
+impl std::fmt::Display for MockConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.connection)
+    }
+}
+
+impl std::fmt::Debug for MockConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
@@ -1,7 +1,16 @@
+pub mod capture;
 pub mod channel;
+pub mod circuit_breaker;
 pub mod connection;
+pub mod dns;
+pub mod hep;
+#[cfg(any(test, feature = "test-util"))]
+pub mod loopback;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
 pub mod sip_addr;
 pub mod stream;
+pub mod stun;
 pub mod tcp;
 pub mod tcp_listener;
 pub mod tls;
@@ -1,6 +1,11 @@
 use crate::Result;
 use rsip::{host_with_port, HostWithPort, Transport};
-use std::{fmt, hash::Hash, net::SocketAddr};
+use std::{
+    fmt,
+    hash::Hash,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
 
 /// SIP Address
 ///
@@ -59,6 +64,7 @@ use std::{fmt, hash::Hash, net::SocketAddr};
 /// * `SocketAddr` (for IP addresses only)
 /// * `rsip::Uri` (SIP URI format)
 /// * `rsip::HostWithPort` (host/port only)
+/// * its display form, e.g. `UDP [2001:db8::1]:5060`, through `FromStr`
 #[derive(Debug, Eq, PartialEq, Clone, Default)]
 pub struct SipAddr {
     pub r#type: Option<rsip::transport::Transport>,
@@ -67,16 +73,86 @@ pub struct SipAddr {
 
 impl fmt::Display for SipAddr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            SipAddr {
-                r#type: Some(r#type),
-                addr,
-            } => write!(f, "{} {}", r#type, addr),
-            SipAddr { r#type: None, addr } => write!(f, "{}", addr),
+        if let Some(r#type) = &self.r#type {
+            write!(f, "{} ", r#type)?;
         }
+        match &self.addr.host {
+            host_with_port::Host::IpAddr(IpAddr::V6(ip)) => write!(f, "[{}]", ip)?,
+            host => write!(f, "{}", host)?,
+        }
+        match &self.addr.port {
+            Some(port) => write!(f, ":{}", port),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parse the [`Display`](fmt::Display) form of a `SipAddr`, e.g.
+/// `TCP [2001:db8::1]:5060`, `127.0.0.1:5060` or `example.com`
+///
+/// IPv6 literals with a port must be bracketed; a bare IPv6 literal is
+/// taken as an address without port.
+impl FromStr for SipAddr {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (r#type, addr) = match s.split_once(' ') {
+            Some((r#type, addr)) => (Some(parse_transport(r#type)?), addr.trim()),
+            None => (None, s),
+        };
+        let invalid = || crate::Error::Error(format!("invalid sip address: {}", s));
+        let (host, port) = if let Some(rest) = addr.strip_prefix('[') {
+            let (host, rest) = rest.split_once(']').ok_or_else(invalid)?;
+            let ip = host.parse::<Ipv6Addr>().map_err(|_| invalid())?;
+            let port = match rest {
+                "" => None,
+                _ => Some(rest.strip_prefix(':').ok_or_else(invalid)?),
+            };
+            (host_with_port::Host::IpAddr(ip.into()), port)
+        } else if let Ok(ip) = addr.parse::<Ipv6Addr>() {
+            (host_with_port::Host::IpAddr(ip.into()), None)
+        } else {
+            let (host, port) = match addr.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (addr, None),
+            };
+            if host.is_empty() {
+                return Err(invalid());
+            }
+            let host = match host.parse::<IpAddr>() {
+                Ok(ip) => host_with_port::Host::IpAddr(ip),
+                Err(_) => host_with_port::Host::Domain(host.into()),
+            };
+            (host, port)
+        };
+        let port = port
+            .map(|port| port.parse::<u16>().map_err(|_| invalid()))
+            .transpose()?;
+        Ok(SipAddr {
+            r#type,
+            addr: HostWithPort {
+                host,
+                port: port.map(Into::into),
+            },
+        })
     }
 }
 
+fn parse_transport(value: &str) -> Result<Transport> {
+    let transport = match value.to_ascii_uppercase().as_str() {
+        "UDP" => Transport::Udp,
+        "TCP" => Transport::Tcp,
+        "TLS" => Transport::Tls,
+        "SCTP" => Transport::Sctp,
+        "TLS-SCTP" => Transport::TlsSctp,
+        "WS" => Transport::Ws,
+        "WSS" => Transport::Wss,
+        _ => return Err(crate::Error::Error(format!("unknown transport: {}", value))),
+    };
+    Ok(transport)
+}
+
 impl Hash for SipAddr {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.r#type.hash(state);
@@ -88,6 +164,27 @@ impl Hash for SipAddr {
     }
 }
 
+/// Port used when a URI omits it
+///
+/// 5060 for UDP/TCP, 5061 for TLS (RFC 3261 §19.1.2) and the HTTP upgrade
+/// ports for WebSocket transports (RFC 7118).
+pub fn default_port(transport: Option<Transport>) -> u16 {
+    match transport {
+        Some(Transport::Tls) | Some(Transport::TlsSctp) => 5061,
+        Some(Transport::Ws) => 80,
+        Some(Transport::Wss) => 443,
+        _ => 5060,
+    }
+}
+
+// an explicit transport parameter wins, `sips:` implies TLS
+fn uri_transport(uri: &rsip::Uri) -> Option<Transport> {
+    uri.transport().cloned().or(match uri.scheme {
+        Some(rsip::Scheme::Sips) => Some(Transport::Tls),
+        _ => None,
+    })
+}
+
 impl SipAddr {
     pub fn new(transport: rsip::transport::Transport, addr: HostWithPort) -> Self {
         SipAddr {
@@ -96,6 +193,13 @@ impl SipAddr {
         }
     }
 
+    /// The explicit port, or the default one for the transport
+    pub fn port_or_default(&self) -> u16 {
+        self.addr
+            .port
+            .map_or(default_port(self.r#type), |p| p.value().to_owned())
+    }
+
     pub fn get_socketaddr(&self) -> Result<SocketAddr> {
         match &self.addr.host {
             host_with_port::Host::Domain(domain) => Err(crate::Error::Error(format!(
@@ -103,13 +207,44 @@ impl SipAddr {
                 domain
             ))),
             host_with_port::Host::IpAddr(ip_addr) => {
-                let port = self.addr.port.map_or(5060, |p| p.value().to_owned());
-                Ok(SocketAddr::new(ip_addr.to_owned(), port))
+                Ok(SocketAddr::new(ip_addr.to_owned(), self.port_or_default()))
             }
         }
     }
 }
 
+/// `addr` as written in a URI or Via, an IPv6 literal in brackets
+///
+/// rsip prints an IPv6 host bare, so the bracketed literal is carried as a
+/// domain; [`SipAddr::try_from`] on the URI turns it back into the address.
+pub fn bracket_ipv6(addr: &HostWithPort) -> HostWithPort {
+    match &addr.host {
+        host_with_port::Host::IpAddr(IpAddr::V6(ip)) => HostWithPort {
+            host: host_with_port::Host::Domain(format!("[{}]", ip).into()),
+            port: addr.port,
+        },
+        _ => addr.clone(),
+    }
+}
+
+// the inverse of `bracket_ipv6`
+fn unbracket_ipv6(host: host_with_port::Host) -> host_with_port::Host {
+    match &host {
+        host_with_port::Host::Domain(domain) => {
+            let domain = domain.to_string();
+            match domain
+                .strip_prefix('[')
+                .and_then(|d| d.strip_suffix(']'))
+                .and_then(|d| d.parse::<Ipv6Addr>().ok())
+            {
+                Some(ip) => host_with_port::Host::IpAddr(ip.into()),
+                None => host,
+            }
+        }
+        _ => host,
+    }
+}
+
 impl From<SipAddr> for rsip::HostWithPort {
     fn from(val: SipAddr) -> Self {
         val.addr
@@ -141,7 +276,7 @@ impl From<&SipAddr> for rsip::Uri {
         };
         rsip::Uri {
             scheme: Some(scheme),
-            host_with_port: addr.addr.clone(),
+            host_with_port: bracket_ipv6(&addr.addr),
             params,
             ..Default::default()
         }
@@ -170,14 +305,32 @@ impl From<rsip::host_with_port::HostWithPort> for SipAddr {
     }
 }
 
+/// Host of the `maddr` URI parameter, which overrides the URI host as the
+/// address to send to (RFC 3261 §19.1.1)
+fn uri_maddr(uri: &rsip::Uri) -> Option<host_with_port::Host> {
+    uri.params.iter().find_map(|param| match param {
+        rsip::Param::Maddr(maddr) => HostWithPort::try_from(maddr.to_string().as_str())
+            .ok()
+            .map(|maddr| maddr.host),
+        _ => None,
+    })
+}
+
+/// Target of a URI: its `maddr` or host with the URI port, over the
+/// transport of its `transport` parameter or scheme
 impl TryFrom<&rsip::Uri> for SipAddr {
     type Error = crate::Error;
 
     fn try_from(uri: &rsip::Uri) -> Result<Self> {
-        let transport = uri.transport().cloned();
+        let transport = uri_transport(uri);
+        let mut addr = uri.host_with_port.clone();
+        if let Some(maddr) = uri_maddr(uri) {
+            addr.host = maddr;
+        }
+        addr.host = unbracket_ipv6(addr.host);
         Ok(SipAddr {
             r#type: transport,
-            addr: uri.host_with_port.clone(),
+            addr,
         })
     }
 }
@@ -186,11 +339,7 @@ impl TryFrom<rsip::Uri> for SipAddr {
     type Error = crate::Error;
 
     fn try_from(uri: rsip::Uri) -> Result<Self> {
-        let transport = uri.transport().cloned();
-        Ok(SipAddr {
-            r#type: transport,
-            addr: uri.host_with_port,
-        })
+        SipAddr::try_from(&uri)
     }
 }
 
@@ -1,15 +1,19 @@
 use crate::{
     transport::{
-        connection::{TransportSender, KEEPALIVE_REQUEST, KEEPALIVE_RESPONSE},
+        connection::{TransportSender, KEEPALIVE_REQUEST, KEEPALIVE_RESPONSE, KEEPALIVE_TIMEOUT},
         SipAddr, SipConnection, TransportEvent,
     },
     Result,
 };
 use bytes::{Buf, BytesMut};
 use rsip::SipMessage;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 use tokio::{
     io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    select,
     sync::Mutex,
+    time::{sleep_until, Instant},
 };
 use tokio_util::codec::{Decoder, Encoder};
 use tracing::{debug, info, warn};
@@ -18,11 +22,51 @@ pub(super) const MAX_SIP_MESSAGE_SIZE: usize = 65535;
 const CL_FULL_NAME: &[u8] = b"content-length";
 const CL_SHORT_NAME: &[u8] = b"l";
 
-pub struct SipCodec {}
+/// Frames SIP messages on a stream transport by their Content-Length
+///
+/// Content-Length is authoritative (RFC 3261 §18.3): whatever the body
+/// holds, even text that looks like another message, belongs to it. A
+/// message whose framing is broken (no start line, a missing or absurd
+/// Content-Length, framed bytes that do not parse) is dropped with an
+/// error, and decoding resumes at the start of the next message. What was dropped is
+/// available from [`take_dropped`](Self::take_dropped) so a request can
+/// still be answered with `400 Bad Request`.
+pub struct SipCodec {
+    dropped: Option<DroppedMessage>,
+    // skip to the next start line before decoding again
+    resync: bool,
+}
+
+/// A message [`SipCodec`] dropped for broken framing
+#[derive(Debug, Clone)]
+pub struct DroppedMessage {
+    /// Start line and headers of the message, when they parse
+    pub head: Option<SipMessage>,
+}
 
 impl SipCodec {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            dropped: None,
+            resync: false,
+        }
+    }
+
+    /// The message dropped by the last failed `decode`, if the stream could
+    /// be resynchronized after it. `None` after an error means the stream
+    /// is unusable and should be closed.
+    pub fn take_dropped(&mut self) -> Option<DroppedMessage> {
+        self.dropped.take()
+    }
+
+    // drop the head of a badly framed message and look for the next one
+    fn drop_message(&mut self, src: &mut BytesMut, len: usize, reason: String) -> crate::Error {
+        let head = src.split_to(len);
+        self.dropped = Some(DroppedMessage {
+            head: SipMessage::try_from(&head[..]).ok(),
+        });
+        self.resync = true;
+        crate::Error::Error(reason)
     }
 }
 
@@ -49,11 +93,149 @@ impl std::fmt::Display for SipCodecType {
     }
 }
 
+fn is_content_length(name: &[u8]) -> bool {
+    let name = name.trim_ascii();
+    name.eq_ignore_ascii_case(CL_FULL_NAME) || name.eq_ignore_ascii_case(CL_SHORT_NAME)
+}
+
+/// Content-Length of the header block `headers`, without UTF-8 conversion
+///
+/// The name matches case-insensitively in full or compact (`l`) form, with
+/// any whitespace around the colon (`HCOLON`, RFC 3261 §25.1).
+pub(super) fn parse_content_length(headers: &[u8]) -> Result<Option<usize>> {
+    for line in headers.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        // folded continuation of the previous header
+        if line.first().is_some_and(|b| *b == b' ' || *b == b'\t') {
+            continue;
+        }
+        let Some(colon) = line.iter().position(|&b| b == b':') else {
+            continue;
+        };
+        if !is_content_length(&line[..colon]) {
+            continue;
+        }
+        let content_length = std::str::from_utf8(&line[colon + 1..])
+            .map_err(|_| crate::Error::Error("Invalid Content-Length".to_string()))?
+            .trim()
+            .parse()
+            .map_err(|_| crate::Error::Error("Invalid Content-Length value".to_string()))?;
+        return Ok(Some(content_length));
+    }
+    Ok(None)
+}
+
+/// `msg` with the whitespace between each header name and its colon
+/// removed, rsip does not tokenize `Content-Length : 42`
+fn tighten_hcolon(msg: &[u8], header_len: usize) -> std::borrow::Cow<'_, [u8]> {
+    let (headers, body) = msg.split_at(header_len);
+    let loose = |line: &[u8]| {
+        !line.first().is_some_and(|b| *b == b' ' || *b == b'\t')
+            && line
+                .iter()
+                .position(|&b| b == b':')
+                .is_some_and(|colon| colon > 0 && matches!(line[colon - 1], b' ' | b'\t'))
+    };
+    if !headers.split(|&b| b == b'\n').skip(1).any(loose) {
+        return std::borrow::Cow::Borrowed(msg);
+    }
+    let mut tight = Vec::with_capacity(msg.len());
+    for (i, line) in headers.split_inclusive(|&b| b == b'\n').enumerate() {
+        match line.iter().position(|&b| b == b':') {
+            Some(colon) if i > 0 && loose(line) => {
+                tight.extend_from_slice(line[..colon].trim_ascii_end());
+                tight.extend_from_slice(&line[colon..]);
+            }
+            _ => tight.extend_from_slice(line),
+        }
+    }
+    tight.extend_from_slice(body);
+    std::borrow::Cow::Owned(tight)
+}
+
+const METHODS: [&[u8]; 14] = [
+    b"INVITE",
+    b"ACK",
+    b"BYE",
+    b"CANCEL",
+    b"OPTIONS",
+    b"REGISTER",
+    b"PRACK",
+    b"SUBSCRIBE",
+    b"NOTIFY",
+    b"PUBLISH",
+    b"INFO",
+    b"REFER",
+    b"MESSAGE",
+    b"UPDATE",
+];
+
+/// Offset in `line` where a Request-Line or Status-Line starts (RFC 3261
+/// §7.1, §7.2)
+///
+/// Bytes left over from a cut short body may come right before it on the
+/// same line, so a request's method is also found at the end of its first
+/// word.
+fn start_line_offset(line: &[u8]) -> Option<usize> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if let Some(pos) = line.windows(8).position(|w| w == b"SIP/2.0 ") {
+        let code = &line[pos + 8..];
+        if code.len() >= 3 && code[..3].iter().all(u8::is_ascii_digit) {
+            return Some(pos);
+        }
+    }
+    let mut parts = line.split(|&b| b == b' ');
+    let (Some(method), Some(uri), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if !uri.contains(&b':') || !version.eq_ignore_ascii_case(b"SIP/2.0") {
+        return None;
+    }
+    METHODS
+        .iter()
+        .filter(|known| method.ends_with(known))
+        .map(|known| method.len() - known.len())
+        .min()
+        .or_else(|| {
+            // an extension method
+            (!method.is_empty() && method.iter().all(u8::is_ascii_uppercase)).then_some(0)
+        })
+}
+
+/// Offset of the first start line among the complete lines of `data`
+fn find_start_line(data: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(end) = data[offset..].iter().position(|&b| b == b'\n') {
+        if let Some(start) = start_line_offset(&data[offset..offset + end]) {
+            return Some(offset + start);
+        }
+        offset += end + 1;
+    }
+    None
+}
+
 impl Decoder for SipCodec {
     type Item = SipCodecType;
     type Error = crate::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
+        if self.resync {
+            match find_start_line(src) {
+                Some(start) => {
+                    src.advance(start);
+                    self.resync = false;
+                }
+                None => {
+                    // keep an incomplete last line, it may be a start line
+                    let complete = src.iter().rposition(|&b| b == b'\n').map_or(0, |n| n + 1);
+                    src.advance(complete);
+                    return Ok(None);
+                }
+            }
+        }
+
         if src.len() >= 4 && &src[0..4] == KEEPALIVE_REQUEST {
             src.advance(4);
             return Ok(Some(SipCodecType::KeepaliveRequest));
@@ -65,67 +247,44 @@ impl Decoder for SipCodec {
         }
 
         if let Some(headers_end) = src.windows(4).position(|w| w == b"\r\n\r\n") {
-            let headers = &src[..headers_end + 4]; // include CRLFCRLF
-
-            // Parse Content-Length as u32 without UTF-8 conversion
-            let mut content_length: usize = 0;
-            let mut start = 0;
-            while start < headers.len() {
-                // find end of line
-                let mut end = start;
-                while end < headers.len() && headers[end] != b'\n' {
-                    end += 1;
+            // on a stream the body is framed by Content-Length alone, a blank
+            // line inside the body must not end the message (RFC 3261 §18.3)
+            let header_len = headers_end + 4; // include CRLFCRLF
+            let first_line = src[..headers_end]
+                .split(|&b| b == b'\n')
+                .next()
+                .unwrap_or_default();
+            if start_line_offset(first_line) != Some(0) {
+                let reason = "Message does not start with a start line".to_string();
+                return Err(self.drop_message(src, header_len, reason));
+            }
+            let content_length = match parse_content_length(&src[..header_len]) {
+                Ok(Some(content_length)) if header_len + content_length <= MAX_SIP_MESSAGE_SIZE => {
+                    content_length
                 }
-
-                let mut line = &headers[start..end];
-                if let Some(&b'\r') = line.last() {
-                    line = &line[..line.len().saturating_sub(1)];
+                Ok(Some(content_length)) => {
+                    let reason = format!("Content-Length {} too large", content_length);
+                    return Err(self.drop_message(src, header_len, reason));
                 }
-
-                if let Some(colon) = line.iter().position(|&b| b == b':') {
-                    let header = &line[..colon];
-                    let is_cl = if header.len() == CL_FULL_NAME.len()
-                        && header
-                            .iter()
-                            .zip(CL_FULL_NAME.iter())
-                            .all(|(&a, &b)| a.to_ascii_lowercase() == b)
-                    {
-                        true
-                    } else if header.len() == CL_SHORT_NAME.len()
-                        && header
-                            .iter()
-                            .zip(CL_SHORT_NAME.iter())
-                            .all(|(&a, &b)| a.to_ascii_lowercase() == b)
-                    {
-                        true
-                    } else {
-                        false
-                    };
-
-                    if is_cl {
-                        // parse value
-                        let value_buf = &line[colon + 1..];
-                        content_length = std::str::from_utf8(value_buf)
-                            .map_err(|_| crate::Error::Error("Invalid Content-Length".to_string()))?
-                            .trim()
-                            .parse()
-                            .map_err(|_| {
-                                crate::Error::Error("Invalid Content-Length value".to_string())
-                            })?;
-                        break;
-                    }
+                Ok(None) => {
+                    let reason = "Missing Content-Length".to_string();
+                    return Err(self.drop_message(src, header_len, reason));
                 }
-
-                start = if end < headers.len() { end + 1 } else { end };
-            }
-
-            let total_len = headers_end + 4 + content_length;
+                Err(e) => return Err(self.drop_message(src, header_len, e.to_string())),
+            };
+            let total_len = header_len + content_length;
 
             if src.len() >= total_len {
                 let msg_data = src.split_to(total_len); // consume full message
-                let msg = SipMessage::try_from(&msg_data[..])?;
+                let msg_data = tighten_hcolon(&msg_data, header_len);
+                let msg = SipMessage::try_from(&msg_data[..]).inspect_err(|_| {
+                    self.dropped = Some(DroppedMessage { head: None });
+                    self.resync = true;
+                })?;
                 return Ok(Some(SipCodecType::Message(msg)));
             }
+            src.reserve(total_len - src.len());
+            return Ok(None);
         }
 
         if src.len() > MAX_SIP_MESSAGE_SIZE {
@@ -133,6 +292,20 @@ impl Decoder for SipCodec {
         }
         Ok(None)
     }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
+        if let Some(item) = self.decode(src)? {
+            return Ok(Some(item));
+        }
+        if self.resync || src.iter().all(|b| matches!(b, b'\r' | b'\n')) {
+            return Ok(None);
+        }
+        let reason = match src.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(_) => "connection closed before the end of the body: shorter than Content-Length",
+            None => "connection closed in the middle of a message header",
+        };
+        Err(crate::Error::Error(reason.to_string()))
+    }
 }
 
 impl Encoder<SipMessage> for SipCodec {
@@ -154,6 +327,7 @@ where
     pub remote_addr: SipAddr,
     pub read_half: Mutex<Option<R>>,
     pub write_half: Mutex<W>,
+    keepalive_interval_ms: AtomicU64,
 }
 
 impl<R, W> StreamConnectionInner<R, W>
@@ -167,9 +341,24 @@ where
             remote_addr,
             read_half: Mutex::new(Some(read_half)),
             write_half: Mutex::new(write_half),
+            keepalive_interval_ms: AtomicU64::new(0),
         }
     }
 
+    /// Send a double-CRLF ping after `interval` without traffic, and close
+    /// the connection when no pong follows (RFC 5626 §4.4.1)
+    ///
+    /// The pong is awaited for [`KEEPALIVE_TIMEOUT`] or `interval`,
+    /// whichever is shorter. `Duration::ZERO` disables pings.
+    pub fn set_keepalive_interval(&self, interval: Duration) {
+        self.keepalive_interval_ms
+            .store(interval.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn keepalive_interval(&self) -> Duration {
+        Duration::from_millis(self.keepalive_interval_ms.load(Ordering::Relaxed))
+    }
+
     pub async fn send_message(&self, msg: SipMessage) -> Result<()> {
         send_to_stream(&self.write_half, msg).await
     }
@@ -197,18 +386,63 @@ where
         let mut buffer = BytesMut::with_capacity(MAX_SIP_MESSAGE_SIZE);
         let mut read_buf = BytesMut::with_capacity(MAX_SIP_MESSAGE_SIZE);
         read_buf.resize(MAX_SIP_MESSAGE_SIZE, 0);
-        loop {
+        let mut last_activity = Instant::now();
+        let mut pong_deadline: Option<Instant> = None;
+        'read: loop {
             use tokio::io::AsyncReadExt;
-            match read_half.read(&mut read_buf).await {
+            let interval = self.keepalive_interval();
+            let keepalive_at = match pong_deadline {
+                Some(deadline) => Some(deadline),
+                None if !interval.is_zero() => Some(last_activity + interval),
+                None => None,
+            };
+            let read = select! {
+                read = read_half.read(&mut read_buf) => read,
+                _ = sleep_until(keepalive_at.unwrap_or_else(Instant::now)), if keepalive_at.is_some() => {
+                    if pong_deadline.is_some() {
+                        warn!("No keepalive pong from {}, closing connection", remote_addr);
+                        break;
+                    }
+                    self.send_raw(KEEPALIVE_REQUEST).await?;
+                    pong_deadline = Some(Instant::now() + interval.min(KEEPALIVE_TIMEOUT));
+                    continue;
+                }
+            };
+            last_activity = Instant::now();
+            match read {
                 Ok(0) => {
                     info!("Connection closed: {}", self.local_addr);
+                    if let Err(e) = codec.decode_eof(&mut buffer) {
+                        warn!("Incomplete message from {}: {}", remote_addr, e);
+                    }
                     break;
                 }
                 Ok(n) => {
                     buffer.extend_from_slice(&read_buf[0..n]);
 
                     loop {
-                        match codec.decode(&mut buffer)? {
+                        let decoded = match codec.decode(&mut buffer) {
+                            Ok(decoded) => decoded,
+                            Err(e) => {
+                                let Some(dropped) = codec.take_dropped() else {
+                                    warn!("Framing error from {}, closing: {}", remote_addr, e);
+                                    break 'read;
+                                };
+                                warn!("Dropped message from {}: {}", remote_addr, e);
+                                if let Some(SipMessage::Request(req)) = dropped.head {
+                                    sender
+                                        .send(TransportEvent::BadRequest(
+                                            req,
+                                            connection.clone(),
+                                            remote_addr.clone(),
+                                            e.to_string(),
+                                        ))
+                                        .ok();
+                                }
+                                continue;
+                            }
+                        };
+                        match decoded {
                             Some(msg) => match msg {
                                 SipCodecType::Message(sip_msg) => {
                                     debug!("Received message from {}: {}", remote_addr, sip_msg);
@@ -231,7 +465,9 @@ where
                                 SipCodecType::KeepaliveRequest => {
                                     self.send_raw(KEEPALIVE_RESPONSE).await?;
                                 }
-                                SipCodecType::KeepaliveResponse => {}
+                                SipCodecType::KeepaliveResponse => {
+                                    pong_deadline = None;
+                                }
                             },
                             None => {
                                 // Need more data
@@ -0,0 +1,69 @@
+//! STUN binding requests on the SIP socket
+//!
+//! ICE-lite agents and connectivity checks send STUN (RFC 5389) binding
+//! requests to the same UDP port SIP listens on. They are told apart from
+//! SIP by their first two bits being zero and the magic cookie, and are
+//! answered with the source address as seen by this socket.
+
+use std::net::{IpAddr, SocketAddr};
+
+/// Fixed value of bytes 4..8 of every RFC 5389 message
+pub const MAGIC_COOKIE: u32 = 0x2112_A442;
+
+const HEADER_LEN: usize = 20;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS_RESPONSE: u16 = 0x0101;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+/// Whether a datagram is a STUN message rather than SIP
+///
+/// A SIP message starts with a letter, so its first two bits are never zero.
+pub fn is_stun_message(data: &[u8]) -> bool {
+    if data.len() < HEADER_LEN || data[0] & 0xC0 != 0 {
+        return false;
+    }
+    let length = u16::from_be_bytes([data[2], data[3]]) as usize;
+    data[4..8] == MAGIC_COOKIE.to_be_bytes()
+        && length.is_multiple_of(4)
+        && HEADER_LEN + length == data.len()
+}
+
+/// Binding success response to a binding request received from `source`
+///
+/// The response carries `source` as its XOR-MAPPED-ADDRESS. Returns `None`
+/// for anything but a binding request.
+pub fn binding_response(request: &[u8], source: SocketAddr) -> Option<Vec<u8>> {
+    if !is_stun_message(request) || u16::from_be_bytes([request[0], request[1]]) != BINDING_REQUEST
+    {
+        return None;
+    }
+    let transaction_id = &request[8..HEADER_LEN];
+    let cookie = MAGIC_COOKIE.to_be_bytes();
+
+    // XOR-MAPPED-ADDRESS, RFC 5389 §15.2
+    let port = source.port() ^ (MAGIC_COOKIE >> 16) as u16;
+    let mut value = vec![0];
+    match source.ip() {
+        IpAddr::V4(ip) => {
+            value.push(0x01);
+            value.extend_from_slice(&port.to_be_bytes());
+            value.extend(ip.octets().iter().zip(cookie).map(|(b, k)| b ^ k));
+        }
+        IpAddr::V6(ip) => {
+            value.push(0x02);
+            value.extend_from_slice(&port.to_be_bytes());
+            let key = cookie.iter().chain(transaction_id);
+            value.extend(ip.octets().iter().zip(key).map(|(b, k)| b ^ k));
+        }
+    }
+
+    let mut response = Vec::with_capacity(HEADER_LEN + 4 + value.len());
+    response.extend_from_slice(&BINDING_SUCCESS_RESPONSE.to_be_bytes());
+    response.extend_from_slice(&((4 + value.len()) as u16).to_be_bytes());
+    response.extend_from_slice(&cookie);
+    response.extend_from_slice(transaction_id);
+    response.extend_from_slice(&XOR_MAPPED_ADDRESS.to_be_bytes());
+    response.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    response.extend_from_slice(&value);
+    Some(response)
+}
@@ -1,5 +1,13 @@
+pub mod test_circuit_breaker;
+pub mod test_connection_pool;
+pub mod test_hep;
 pub mod test_listener_api;
+pub mod test_resolver;
 pub mod test_sipaddr;
 pub mod test_stream_encoding;
+pub mod test_stream_keepalive;
+pub mod test_tls;
 pub mod test_udp;
 pub mod test_via_received;
+#[cfg(feature = "websocket")]
+pub mod test_websocket;
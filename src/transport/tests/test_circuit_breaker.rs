@@ -0,0 +1,55 @@
+use crate::transport::{
+    circuit_breaker::{CircuitBreaker, CircuitState},
+    SipAddr, TransportLayer,
+};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+#[tokio::test]
+async fn test_circuit_breaker_fast_fails_until_cool_down() -> crate::Result<()> {
+    // a TCP port nothing listens on: every connect is refused
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    let target = SipAddr {
+        r#type: Some(rsip::transport::Transport::Tcp),
+        addr: rsip::HostWithPort::try_from(format!("127.0.0.1:{}", port).as_str())?,
+    };
+
+    let transport_layer = TransportLayer::new(CancellationToken::new());
+    transport_layer.set_circuit_breaker(CircuitBreaker::new(2, Duration::from_millis(300)));
+    let breaker = transport_layer.circuit_breaker().expect("circuit breaker");
+
+    let is_circuit_open = |result: &crate::Result<_>| matches!(result, Err(crate::Error::TransportLayerError(reason, _)) if reason == "circuit open");
+
+    for failures in 1..=2 {
+        let result = transport_layer.lookup(&target, None).await;
+        assert!(result.is_err() && !is_circuit_open(&result));
+        if failures < 2 {
+            assert_eq!(breaker.state(&target), CircuitState::Closed { failures });
+        }
+    }
+    assert!(matches!(breaker.state(&target), CircuitState::Open { .. }));
+    assert_eq!(breaker.states().len(), 1);
+
+    // threshold reached: fast-fail without touching the network
+    let result = transport_layer.lookup(&target, None).await;
+    assert!(is_circuit_open(&result));
+
+    // after the cool-down the next lookup probes the target again
+    tokio::time::sleep(Duration::from_millis(350)).await;
+    let result = transport_layer.lookup(&target, None).await;
+    assert!(result.is_err() && !is_circuit_open(&result));
+    assert!(matches!(breaker.state(&target), CircuitState::Open { .. }));
+    assert!(is_circuit_open(
+        &transport_layer.lookup(&target, None).await
+    ));
+
+    // a successful probe closes the circuit
+    tokio::time::sleep(Duration::from_millis(350)).await;
+    assert!(breaker.check(&target).is_ok());
+    assert_eq!(breaker.state(&target), CircuitState::HalfOpen);
+    transport_layer.record_send_result(&target, true);
+    assert_eq!(breaker.state(&target), CircuitState::Closed { failures: 0 });
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use crate::{
+    transport::{SipAddr, TransportLayer},
+    Result,
+};
+use std::time::Duration;
+use tokio::{net::TcpListener, time::timeout};
+use tokio_util::sync::CancellationToken;
+
+#[tokio::test]
+async fn test_tcp_lookups_share_one_connection() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let target = SipAddr::new(
+        rsip::transport::Transport::Tcp,
+        listener.local_addr()?.into(),
+    );
+    let tl = TransportLayer::new(CancellationToken::new());
+
+    let (first, second) = tokio::join!(tl.lookup(&target, None), tl.lookup(&target, None));
+    let (first, _) = first?;
+    let (second, _) = second?;
+    assert_eq!(first.get_addr(), &target);
+    assert_eq!(second.get_addr(), &target);
+    assert_eq!(tl.connection_count(), 1);
+
+    let (stream, _) = listener.accept().await?;
+    assert!(
+        timeout(Duration::from_millis(100), listener.accept())
+            .await
+            .is_err(),
+        "lookups dialed more than one connection"
+    );
+    let (third, _) = tl.lookup(&target, None).await?;
+    assert_eq!(third.get_addr(), &target);
+    assert_eq!(tl.connection_count(), 1);
+
+    // the peer closing the connection prunes it from the pool
+    drop(stream);
+    timeout(Duration::from_secs(1), async {
+        while tl.connection_count() > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("closed connection was not pruned");
+    Ok(())
+}
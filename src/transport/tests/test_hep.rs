@@ -0,0 +1,86 @@
+use crate::transport::{
+    capture::{CaptureDirection, CapturedMessage},
+    hep::*,
+    SipAddr,
+};
+use std::collections::HashMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Split a HEP3 packet into its chunks, keyed by chunk type
+fn decode_chunks(packet: &[u8]) -> HashMap<u16, Vec<u8>> {
+    assert_eq!(&packet[0..4], b"HEP3");
+    assert_eq!(
+        u16::from_be_bytes([packet[4], packet[5]]) as usize,
+        packet.len()
+    );
+    let mut chunks = HashMap::new();
+    let mut pos = 6;
+    while pos < packet.len() {
+        let vendor = u16::from_be_bytes([packet[pos], packet[pos + 1]]);
+        let chunk_type = u16::from_be_bytes([packet[pos + 2], packet[pos + 3]]);
+        let len = u16::from_be_bytes([packet[pos + 4], packet[pos + 5]]) as usize;
+        assert_eq!(vendor, 0);
+        assert!(
+            len >= 6 && pos + len <= packet.len(),
+            "chunk overruns packet"
+        );
+        chunks.insert(chunk_type, packet[pos + 6..pos + len].to_vec());
+        pos += len;
+    }
+    chunks
+}
+
+#[test]
+fn test_captured_invite_encodes_hep3() -> crate::Result<()> {
+    let invite = "INVITE sip:bob@example.com SIP/2.0\r\n\
+                  Via: SIP/2.0/UDP 10.0.0.1:5060;branch=z9hG4bK-hep\r\n\
+                  From: <sip:alice@example.com>;tag=hep\r\n\
+                  To: <sip:bob@example.com>\r\n\
+                  Call-ID: hep-call-id\r\n\
+                  CSeq: 1 INVITE\r\n\
+                  Content-Length: 0\r\n\r\n";
+    let captured = CapturedMessage {
+        timestamp: UNIX_EPOCH + Duration::new(1_700_000_000, 250_000_000),
+        direction: CaptureDirection::Outbound,
+        transport: rsip::Transport::Udp,
+        connection: SipAddr::from("10.0.0.1:5060".parse::<std::net::SocketAddr>()?),
+        remote: Some(SipAddr::from(
+            "192.0.2.7:5080".parse::<std::net::SocketAddr>()?,
+        )),
+        message: rsip::SipMessage::try_from(invite)?,
+    };
+    let mut config = HepConfig::new("127.0.0.1:9060".parse()?);
+    config.capture_id = 42;
+    config.auth_key = Some("secret".to_string());
+    config.node_name = Some("edge-1".to_string());
+
+    let chunks = decode_chunks(&encode_hep3(&captured, &config)?);
+    assert_eq!(chunks[&CHUNK_IP_FAMILY], [2]);
+    assert_eq!(chunks[&CHUNK_IP_PROTOCOL], [17]);
+    assert_eq!(chunks[&CHUNK_IPV4_SRC], [10, 0, 0, 1]);
+    assert_eq!(chunks[&CHUNK_IPV4_DST], [192, 0, 2, 7]);
+    assert_eq!(chunks[&CHUNK_SRC_PORT], 5060u16.to_be_bytes());
+    assert_eq!(chunks[&CHUNK_DST_PORT], 5080u16.to_be_bytes());
+    assert_eq!(chunks[&CHUNK_TIMESTAMP_SEC], 1_700_000_000u32.to_be_bytes());
+    assert_eq!(chunks[&CHUNK_TIMESTAMP_USEC], 250_000u32.to_be_bytes());
+    assert_eq!(chunks[&CHUNK_PROTOCOL_TYPE], [1]);
+    assert_eq!(chunks[&CHUNK_CAPTURE_ID], 42u32.to_be_bytes());
+    assert_eq!(chunks[&CHUNK_AUTH_KEY], b"secret");
+    assert_eq!(chunks[&CHUNK_NODE_NAME], b"edge-1");
+    assert_eq!(chunks[&CHUNK_CORRELATION_ID], b"hep-call-id");
+    assert_eq!(
+        chunks[&CHUNK_PAYLOAD],
+        captured.message.to_string().as_bytes()
+    );
+
+    // inbound swaps source and destination
+    let inbound = CapturedMessage {
+        direction: CaptureDirection::Inbound,
+        ..captured
+    };
+    let chunks = decode_chunks(&encode_hep3(&inbound, &HepConfig::new(config.collector))?);
+    assert_eq!(chunks[&CHUNK_IPV4_SRC], [192, 0, 2, 7]);
+    assert_eq!(chunks[&CHUNK_DST_PORT], 5060u16.to_be_bytes());
+    assert!(!chunks.contains_key(&CHUNK_AUTH_KEY));
+    Ok(())
+}
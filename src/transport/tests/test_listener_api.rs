@@ -49,3 +49,159 @@ async fn test_websocket_listener_connection_api() -> Result<()> {
 
     Ok(())
 }
+
+fn mock_listener(addr: &str) -> crate::transport::mock::MockConnection {
+    let addr = SipAddr::new(
+        rsip::transport::Transport::Udp,
+        rsip::HostWithPort::try_from(addr).unwrap(),
+    );
+    let remote = SipAddr::new(
+        rsip::transport::Transport::Udp,
+        rsip::HostWithPort::try_from("127.0.0.1:5999").unwrap(),
+    );
+    crate::transport::mock::MockConnection::new(addr, remote, None)
+}
+
+#[tokio::test]
+async fn test_add_listener_binds_and_lists_sockets() -> Result<()> {
+    use crate::transport::TransportLayer;
+    use tokio_util::sync::CancellationToken;
+
+    let transport_layer = TransportLayer::new(CancellationToken::new());
+    let udp = transport_layer
+        .add_listener(SipAddr::new(
+            rsip::transport::Transport::Udp,
+            "127.0.0.1:0".parse::<std::net::SocketAddr>()?.into(),
+        ))
+        .await?;
+    assert_ne!(udp.get_addr().get_socketaddr()?.port(), 0);
+    let tcp = transport_layer
+        .add_listener(SipAddr::new(
+            rsip::transport::Transport::Tcp,
+            "127.0.0.1:0".parse::<std::net::SocketAddr>()?.into(),
+        ))
+        .await?;
+
+    let listeners = transport_layer
+        .listeners()
+        .iter()
+        .map(|listener| listener.get_addr().clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        listeners,
+        vec![udp.get_addr().clone(), tcp.get_addr().clone()]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_select_source_follows_the_route() {
+    use crate::transport::transport_layer::select_source;
+
+    let listeners: Vec<crate::transport::SipConnection> = vec![
+        mock_listener("10.0.0.5:5060").into(),
+        mock_listener("127.0.0.1:5060").into(),
+        mock_listener("0.0.0.0:5070").into(),
+    ];
+    let pick = |destination: &str| {
+        let destination = SipAddr::new(
+            rsip::transport::Transport::Udp,
+            rsip::HostWithPort::try_from(destination).unwrap(),
+        );
+        select_source(
+            listeners.iter(),
+            rsip::transport::Transport::Udp,
+            &destination,
+        )
+        .map(|listener| listener.get_addr().addr.to_string())
+    };
+    // the OS sends to the loopback network from 127.0.0.1
+    assert_eq!(pick("127.0.0.1:5062").as_deref(), Some("127.0.0.1:5060"));
+    // no listener on the routed address, or nothing to route: the first one
+    assert_eq!(pick("[::1]:5060").as_deref(), Some("10.0.0.5:5060"));
+    assert_eq!(pick("sip.example.com").as_deref(), Some("10.0.0.5:5060"));
+    assert!(select_source(
+        listeners.iter(),
+        rsip::transport::Transport::Tcp,
+        &SipAddr::new(
+            rsip::transport::Transport::Tcp,
+            rsip::HostWithPort::try_from("127.0.0.1:5062").unwrap(),
+        ),
+    )
+    .is_none());
+}
+
+#[tokio::test]
+async fn test_request_leaves_from_the_listener_facing_the_destination() -> Result<()> {
+    use crate::transaction::{
+        key::{TransactionKey, TransactionRole},
+        transaction::Transaction,
+    };
+    use crate::transport::TransportLayer;
+    use rsip::prelude::{HeadersExt, ToTypedHeader};
+    use std::time::Duration;
+    use tokio_util::sync::CancellationToken;
+
+    let token = CancellationToken::new();
+    let transport_layer = TransportLayer::new(token.child_token());
+    let unrouted = mock_listener("10.0.0.5:5060");
+    let routed = mock_listener("127.0.0.1:5060");
+    transport_layer.add_transport(unrouted.clone().into());
+    transport_layer.add_transport(routed.clone().into());
+    let endpoint = crate::EndpointBuilder::new()
+        .with_transport_layer(transport_layer)
+        .with_cancel_token(token.child_token())
+        .build();
+
+    let uri = rsip::Uri::try_from("sip:bob@127.0.0.1:5062")?;
+    let options = endpoint.inner.make_request(
+        rsip::Method::Options,
+        uri.clone(),
+        endpoint.inner.get_via(None, None)?,
+        rsip::typed::From {
+            display_name: None,
+            uri: rsip::Uri::try_from("sip:alice@example.com")?,
+            params: vec![rsip::Param::Tag("dual".into())],
+        },
+        rsip::typed::To {
+            display_name: None,
+            uri,
+            params: vec![],
+        },
+        1,
+        None,
+        None,
+    );
+    // the Via starts out naming the first listener
+    assert_eq!(
+        options
+            .via_header()?
+            .typed()?
+            .uri
+            .host_with_port
+            .to_string(),
+        "10.0.0.5:5060"
+    );
+    let key = TransactionKey::from_request(&options, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, options, endpoint.inner.clone(), None);
+    tx.send().await?;
+
+    let request = tokio::time::timeout(Duration::from_secs(1), routed.next_sent())
+        .await
+        .expect("nothing left the routed listener");
+    let Some(rsip::SipMessage::Request(request)) = request else {
+        panic!("expected a request");
+    };
+    assert_eq!(
+        request
+            .via_header()?
+            .typed()?
+            .uri
+            .host_with_port
+            .to_string(),
+        "127.0.0.1:5060"
+    );
+    assert!(unrouted.take_sent().is_empty());
+    token.cancel();
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use crate::transport::{
+    dns::{naptr_srv_names, order_srv_targets, srv_name, NaptrService, SrvTarget},
+    transport_layer::{DomainResolver, StaticResolver},
+    udp::UdpConnection,
+    SipAddr, TransportLayer,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use tokio_util::sync::CancellationToken;
+
+#[tokio::test]
+async fn test_static_resolver_lookup() -> crate::Result<()> {
+    let token = CancellationToken::new();
+    let udp = UdpConnection::create_connection(
+        "127.0.0.1:0".parse().unwrap(),
+        None,
+        Some(token.child_token()),
+    )
+    .await?;
+    let local_addr = udp.get_addr().clone();
+
+    let resolver = StaticResolver::new().with(
+        "SIP.example.com",
+        vec![SipAddr {
+            r#type: None,
+            addr: local_addr.addr.clone(),
+        }],
+    );
+    let target = SipAddr::new(
+        rsip::Transport::Udp,
+        rsip::HostWithPort::try_from("sip.example.com")?,
+    );
+    let candidates = resolver.resolve_all(&target).await?;
+    assert_eq!(candidates, vec![local_addr.clone()]);
+
+    let transport_layer =
+        TransportLayer::new_with_domain_resolver(token.child_token(), Box::new(resolver));
+    transport_layer.add_transport(udp.into());
+    let (connection, destination) = transport_layer.lookup(&target, None).await?;
+    assert_eq!(destination, local_addr);
+    assert_eq!(connection.get_addr(), &local_addr);
+
+    let unknown = SipAddr::new(
+        rsip::Transport::Udp,
+        rsip::HostWithPort::try_from("other.example.com")?,
+    );
+    assert!(matches!(
+        transport_layer.lookup(&unknown, None).await,
+        Err(crate::Error::DnsResolutionError(_))
+    ));
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_lookup_fails_over_to_next_candidate() -> crate::Result<()> {
+    // nothing listens on the first candidate, the second accepts
+    let refused = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let accepting = listener.local_addr()?;
+    let tcp_addr = |addr: std::net::SocketAddr| SipAddr {
+        r#type: Some(rsip::Transport::Tcp),
+        addr: addr.into(),
+    };
+
+    let token = CancellationToken::new();
+    let resolver = StaticResolver::new().with(
+        "sip.example.com",
+        vec![tcp_addr(refused), tcp_addr(accepting)],
+    );
+    let transport_layer =
+        TransportLayer::new_with_domain_resolver(token.child_token(), Box::new(resolver));
+    let target = SipAddr::new(
+        rsip::Transport::Tcp,
+        rsip::HostWithPort::try_from("sip.example.com")?,
+    );
+    assert_eq!(
+        transport_layer.resolve_candidates(&target).await?,
+        vec![tcp_addr(refused), tcp_addr(accepting)]
+    );
+
+    let (_, destination) = transport_layer.lookup(&target, None).await?;
+    assert_eq!(destination, tcp_addr(accepting));
+    token.cancel();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_lookup_fails_over_past_unresponsive_candidate() -> crate::Result<()> {
+    // the first candidate takes the TCP connection but never answers the
+    // TLS handshake, the second accepts
+    let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let candidates = vec![
+        SipAddr {
+            r#type: Some(rsip::Transport::Tls),
+            addr: silent.local_addr()?.into(),
+        },
+        SipAddr {
+            r#type: Some(rsip::Transport::Tcp),
+            addr: listener.local_addr()?.into(),
+        },
+    ];
+
+    let token = CancellationToken::new();
+    let resolver = StaticResolver::new().with("sip.example.com", candidates.clone());
+    let transport_layer =
+        TransportLayer::new_with_domain_resolver(token.child_token(), Box::new(resolver));
+    transport_layer.set_connect_timeout(std::time::Duration::from_millis(400));
+    let target = SipAddr::new(
+        rsip::Transport::Tcp,
+        rsip::HostWithPort::try_from("sip.example.com")?,
+    );
+    let (_, destination) = tokio::time::timeout(
+        std::time::Duration::from_secs(2),
+        transport_layer.lookup(&target, None),
+    )
+    .await
+    .expect("lookup stuck on the unresponsive candidate")?;
+    assert_eq!(destination, candidates[1]);
+    token.cancel();
+    Ok(())
+}
+
+#[test]
+fn test_naptr_srv_names_order() {
+    let naptr = |order, preference, flags: &str, service: &str, replacement: &str| NaptrService {
+        order,
+        preference,
+        flags: flags.to_string(),
+        service: service.to_string(),
+        replacement: replacement.to_string(),
+    };
+    let records = vec![
+        naptr(50, 50, "s", "SIP+D2U", "_sip._udp.example.com."),
+        naptr(90, 50, "s", "SIP+D2T", "_sip._tcp.example.com."),
+        naptr(50, 10, "s", "SIPS+D2T", "_sips._tcp.example.com."),
+        naptr(10, 10, "u", "E2U+sip", "!^.*$!sip:info@example.com!"),
+        naptr(20, 10, "s", "SIP+D2X", "_sip._x.example.com."),
+    ];
+    assert_eq!(
+        naptr_srv_names(records),
+        vec![
+            (rsip::Transport::Tls, "_sips._tcp.example.com".to_string()),
+            (rsip::Transport::Udp, "_sip._udp.example.com".to_string()),
+            (rsip::Transport::Tcp, "_sip._tcp.example.com".to_string()),
+        ]
+    );
+    assert_eq!(
+        srv_name(rsip::Transport::Tls, "example.com"),
+        "_sips._tcp.example.com"
+    );
+}
+
+#[test]
+fn test_order_srv_targets_by_priority_and_weight() {
+    let srv = |priority, weight, target: &str| SrvTarget {
+        priority,
+        weight,
+        port: 5060,
+        target: target.to_string(),
+    };
+    let records = vec![
+        srv(20, 0, "backup.example.com"),
+        srv(10, 90, "big.example.com"),
+        srv(10, 10, "small.example.com"),
+    ];
+    let mut rng = StdRng::seed_from_u64(3263);
+    let mut big_first = 0;
+    for _ in 0..1000 {
+        let ordered = order_srv_targets(records.clone(), &mut rng);
+        assert_eq!(ordered.len(), 3);
+        assert_eq!(ordered[2].target, "backup.example.com");
+        if ordered[0].target == "big.example.com" {
+            big_first += 1;
+        }
+    }
+    // the heavier record comes first about 90% of the time
+    assert!((820..=970).contains(&big_first), "big first {}", big_first);
+}
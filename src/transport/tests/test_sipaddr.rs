@@ -1,5 +1,12 @@
-use crate::transport::{SipAddr, SipConnection};
-use rsip::{headers::*, prelude::HeadersExt, HostWithPort, SipMessage};
+use crate::rsip_ext::destination_from_request;
+use crate::transport::{connection::preferred_interface_address, SipAddr, SipConnection};
+use crate::EndpointBuilder;
+use rsip::{
+    headers::*,
+    prelude::{HeadersExt, UntypedHeader},
+    HostWithPort, SipMessage, Transport,
+};
+use std::net::IpAddr;
 
 #[test]
 fn test_via_received() {
@@ -61,3 +68,164 @@ fn test_sipaddr() {
         }
     );
 }
+
+#[test]
+fn test_sipaddr_uri_maddr_and_transport() {
+    let cases = [
+        (
+            "sip:bob@example.com;transport=tcp",
+            Some(Transport::Tcp),
+            "example.com",
+        ),
+        ("sip:bob@example.com;maddr=10.0.0.5", None, "10.0.0.5"),
+        (
+            "sip:bob@example.com:5070;transport=tcp;maddr=10.0.0.5",
+            Some(Transport::Tcp),
+            "10.0.0.5:5070",
+        ),
+        (
+            "sips:bob@example.com;maddr=10.0.0.5",
+            Some(Transport::Tls),
+            "10.0.0.5",
+        ),
+    ];
+    for (uri, transport, addr) in cases {
+        let uri = rsip::Uri::try_from(uri).expect("parse uri");
+        let sipaddr = SipAddr::try_from(&uri).expect("SipAddr::try_from");
+        assert_eq!(sipaddr.r#type, transport, "{}", uri);
+        assert_eq!(
+            sipaddr.addr,
+            HostWithPort::try_from(addr).unwrap(),
+            "{}",
+            uri
+        );
+    }
+}
+
+#[test]
+fn test_route_is_preferred_over_request_uri() {
+    let mut request = rsip::Request {
+        method: rsip::Method::Invite,
+        uri: rsip::Uri::try_from("sip:bob@example.com;transport=tcp;maddr=10.0.0.5").unwrap(),
+        headers: Default::default(),
+        version: rsip::Version::V2,
+        body: Default::default(),
+    };
+    let target = destination_from_request(&request).expect("no destination");
+    let target = SipAddr::try_from(target).expect("SipAddr::try_from");
+    assert_eq!(target.r#type, Some(Transport::Tcp));
+    assert_eq!(target.addr, HostWithPort::try_from("10.0.0.5").unwrap());
+
+    // loose and strict routes both take the request to the first Route
+    for route in [
+        "<sip:proxy.example.com:5070;lr>",
+        "<sip:proxy.example.com:5070>",
+    ] {
+        request.headers = vec![rsip::Header::Route(route.into())].into();
+        let target = destination_from_request(&request).expect("no destination");
+        let target = SipAddr::try_from(target).expect("SipAddr::try_from");
+        assert_eq!(target.r#type, None);
+        assert_eq!(
+            target.addr,
+            HostWithPort::try_from("proxy.example.com:5070").unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_portless_uri_default_ports() {
+    let cases = [
+        ("sips:bob@127.0.0.1", 5061),
+        ("sip:bob@127.0.0.1", 5060),
+        ("sip:bob@127.0.0.1;transport=tcp", 5060),
+        ("sip:bob@127.0.0.1;transport=tls", 5061),
+        ("sip:bob@127.0.0.1;transport=ws", 80),
+        ("sip:bob@127.0.0.1;transport=wss", 443),
+        ("sips:bob@127.0.0.1:5071", 5071),
+    ];
+    for (uri, port) in cases {
+        let uri = rsip::Uri::try_from(uri).expect("parse uri");
+        let addr = SipAddr::try_from(&uri).expect("sip addr");
+        assert_eq!(
+            addr.get_socketaddr().expect("socket addr").port(),
+            port,
+            "{}",
+            uri
+        );
+    }
+}
+
+#[test]
+fn test_sipaddr_display_parse_round_trip() {
+    let cases = [
+        ("UDP [::1]:5060", "[::1]:5060"),
+        ("TLS [2001:db8::10]:5061", "[2001:db8::10]:5061"),
+        ("TCP 127.0.0.1:5060", "127.0.0.1:5060"),
+        ("example.com", "example.com"),
+        ("[2001:db8::10]", "[2001:db8::10]"),
+    ];
+    for (text, host_port) in cases {
+        let addr: SipAddr = text.parse().expect("parse sip addr");
+        assert_eq!(addr.to_string(), text);
+        assert!(addr.to_string().ends_with(host_port));
+    }
+
+    let addr: SipAddr = "2001:db8::10".parse().expect("bare ipv6 literal");
+    assert_eq!(addr.addr.port, None);
+    assert_eq!(addr.to_string(), "[2001:db8::10]");
+
+    let socket_addr: std::net::SocketAddr = "[::1]:5060".parse().unwrap();
+    let mut addr = SipAddr::from(socket_addr);
+    addr.r#type = Some(rsip::transport::Transport::Udp);
+    assert_eq!(addr.to_string().parse::<SipAddr>().unwrap(), addr);
+    assert_eq!(addr.get_socketaddr().unwrap(), socket_addr);
+
+    assert!("[::1".parse::<SipAddr>().is_err());
+    assert!("[::1]5060".parse::<SipAddr>().is_err());
+    assert!("FOO 127.0.0.1:5060".parse::<SipAddr>().is_err());
+}
+
+#[tokio::test]
+async fn test_ipv6_via_and_contact_are_bracketed() -> crate::Result<()> {
+    let endpoint = EndpointBuilder::new().build();
+    let addr: SipAddr = "UDP [2001:db8::10]:5060".parse()?;
+
+    let via = endpoint.inner.get_via(Some(addr.clone()), None)?;
+    let via: Via = via.into();
+    assert!(
+        via.value().starts_with("SIP/2.0/UDP [2001:db8::10]:5060;"),
+        "{}",
+        via
+    );
+
+    let contact: Contact = rsip::typed::Contact {
+        display_name: None,
+        uri: rsip::Uri::from(&addr),
+        params: vec![],
+    }
+    .into();
+    assert_eq!(contact.value(), "<sip:[2001:db8::10]:5060>");
+    Ok(())
+}
+
+#[test]
+fn test_preferred_interface_address() {
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    let v6_only = [ip("::1"), ip("fe80::1"), ip("fd00::1"), ip("2001:db8::1")];
+    assert_eq!(
+        preferred_interface_address(v6_only),
+        Some(ip("2001:db8::1"))
+    );
+    assert_eq!(
+        preferred_interface_address([ip("fe80::1"), ip("fd00::1")]),
+        Some(ip("fd00::1"))
+    );
+    assert_eq!(
+        preferred_interface_address([ip("2001:db8::1"), ip("192.168.1.2")]),
+        Some(ip("192.168.1.2"))
+    );
+    assert_eq!(
+        preferred_interface_address([ip("127.0.0.1"), ip("::1"), ip("fe80::1")]),
+        None
+    );
+}
@@ -328,3 +328,232 @@ fn test_sip_codec_multiple_messages_with_bodies() {
         "Buffer should be empty after consuming all messages"
     );
 }
+
+/// Test SipCodec framing a large body by Content-Length across several reads
+#[test]
+fn test_sip_codec_large_body_split_reads() {
+    let mut codec = SipCodec::new();
+    let mut buffer = BytesMut::new();
+
+    // a blank line inside the body must not end the message
+    let mut sdp =
+        "v=0\r\no=alice 1 1 IN IP4 127.0.0.1\r\ns=-\r\n\r\nc=IN IP4 127.0.0.1\r\n".to_string();
+    while sdp.len() < 2000 {
+        sdp.push_str("a=fmtp:101 0-15\r\n");
+    }
+    sdp.truncate(2000);
+    let message = format!(
+        "INVITE sip:bob@example.com SIP/2.0\r\n\
+         Via: SIP/2.0/TCP 127.0.0.1:5060;branch=z9hG4bK-large\r\n\
+         From: <sip:alice@example.com>;tag=large\r\n\
+         To: <sip:bob@example.com>\r\n\
+         Call-ID: test-call-id-large\r\n\
+         CSeq: 1 INVITE\r\n\
+         Content-Type: application/sdp\r\n\
+         Content-Length: {}\r\n\r\n{}",
+        sdp.len(),
+        sdp
+    );
+    let keepalive_after = [message.as_bytes(), KEEPALIVE_REQUEST].concat();
+
+    let mut decoded = None;
+    for chunk in keepalive_after.chunks(700) {
+        buffer.extend_from_slice(chunk);
+        if let Some(msg) = codec.decode(&mut buffer).expect("decode should not fail") {
+            decoded = Some(msg);
+            break;
+        }
+    }
+    match decoded {
+        Some(crate::transport::stream::SipCodecType::Message(SipMessage::Request(req))) => {
+            assert_eq!(req.method, rsip::Method::Invite);
+            assert_eq!(req.body, sdp.as_bytes());
+        }
+        _ => panic!("Expected request message"),
+    }
+
+    // the keepalive that followed the body is framed on its own
+    assert!(matches!(
+        codec.decode(&mut buffer).expect("decode should succeed"),
+        Some(crate::transport::stream::SipCodecType::KeepaliveRequest)
+    ));
+    assert_eq!(buffer.len(), 0);
+}
+
+/// Test SipCodec rejecting stream messages without a usable Content-Length
+#[test]
+fn test_sip_codec_requires_content_length() {
+    let headers = "MESSAGE sip:example.com SIP/2.0\r\n\
+                   Via: SIP/2.0/TCP 127.0.0.1:5060;branch=z9hG4bK-test\r\n\
+                   From: <sip:alice@example.com>;tag=test\r\n\
+                   To: <sip:alice@example.com>\r\n\
+                   Call-ID: test-call-id\r\n\
+                   CSeq: 1 MESSAGE\r\n";
+
+    for content_length in ["", "Content-Length: abc\r\n", "l: -1\r\n"] {
+        let mut codec = SipCodec::new();
+        let mut buffer = BytesMut::new();
+        buffer.extend_from_slice(format!("{}{}\r\nHello", headers, content_length).as_bytes());
+        assert!(
+            codec.decode(&mut buffer).is_err(),
+            "Should error on {:?}",
+            content_length
+        );
+    }
+
+    // compact form is accepted
+    let mut codec = SipCodec::new();
+    let mut buffer = BytesMut::new();
+    buffer.extend_from_slice(format!("{}l: 5\r\n\r\nHello", headers).as_bytes());
+    assert!(codec.decode(&mut buffer).expect("decode").is_some());
+}
+
+/// Test SipCodec framing on Content-Length spelled by non-compliant peers
+#[test]
+fn test_sip_codec_content_length_variants() {
+    let body = "Hello Bob,\r\n\r\nthis body is 42 bytes long\r\n";
+    assert_eq!(body.len(), 42);
+    let headers = "MESSAGE sip:example.com SIP/2.0\r\n\
+                   Via: SIP/2.0/TCP 127.0.0.1:5060;branch=z9hG4bK-test\r\n\
+                   From: <sip:alice@example.com>;tag=test\r\n\
+                   To: <sip:alice@example.com>\r\n\
+                   Call-ID: test-call-id\r\n\
+                   CSeq: 1 MESSAGE\r\n";
+
+    for content_length in [
+        "l: 42",
+        "Content-Length:42",
+        "Content-Length : 42",
+        "content-length:\t42",
+        "CONTENT-LENGTH  :  42 ",
+        "cOnTeNt-LeNgTh: 42",
+        "L:42",
+    ] {
+        let mut codec = SipCodec::new();
+        let mut buffer = BytesMut::new();
+        let message = format!("{}{}\r\n\r\n{}", headers, content_length, body);
+        // a second message right behind the body proves where framing ended
+        buffer.extend_from_slice(message.as_bytes());
+        buffer.extend_from_slice(message.as_bytes());
+
+        for _ in 0..2 {
+            match codec.decode(&mut buffer) {
+                Ok(Some(crate::transport::stream::SipCodecType::Message(SipMessage::Request(
+                    req,
+                )))) => {
+                    assert_eq!(req.body, body.as_bytes(), "{:?}", content_length)
+                }
+                _ => panic!("{:?} was not framed", content_length),
+            }
+        }
+        assert_eq!(buffer.len(), 0, "{:?}", content_length);
+    }
+}
+
+/// Test SipCodec resynchronizing after a framing error
+#[test]
+fn test_sip_codec_resyncs_after_framing_error() {
+    let mut codec = SipCodec::new();
+    let mut buffer = BytesMut::new();
+
+    // no Content-Length, so the body runs into the next request
+    let unframed = "MESSAGE sip:bob@example.com SIP/2.0\r\n\
+                    Via: SIP/2.0/TCP 127.0.0.1:5060;branch=z9hG4bK-unframed\r\n\
+                    From: <sip:alice@example.com>;tag=unframed\r\n\
+                    To: <sip:bob@example.com>\r\n\
+                    Call-ID: unframed\r\n\
+                    CSeq: 1 MESSAGE\r\n\r\nHello";
+    let valid = "OPTIONS sip:bob@example.com SIP/2.0\r\n\
+                 Via: SIP/2.0/TCP 127.0.0.1:5060;branch=z9hG4bK-valid\r\n\
+                 From: <sip:alice@example.com>;tag=valid\r\n\
+                 To: <sip:bob@example.com>\r\n\
+                 Call-ID: valid\r\n\
+                 CSeq: 2 OPTIONS\r\n\
+                 Content-Length: 0\r\n\r\n";
+    buffer.extend_from_slice(unframed.as_bytes());
+    buffer.extend_from_slice(valid.as_bytes());
+
+    assert!(codec.decode(&mut buffer).is_err());
+    match codec.take_dropped().expect("recoverable").head {
+        Some(SipMessage::Request(req)) => assert_eq!(req.method, rsip::Method::Message),
+        _ => panic!("dropped request must keep its head"),
+    }
+    match codec.decode(&mut buffer) {
+        Ok(Some(crate::transport::stream::SipCodecType::Message(SipMessage::Request(req)))) => {
+            assert_eq!(req.method, rsip::Method::Options);
+        }
+        _ => panic!("Expected the valid request after the unframed one"),
+    }
+    assert_eq!(buffer.len(), 0);
+
+    // an absurd Content-Length drops the head, and its body is skipped
+    let absurd = unframed.replace(
+        "CSeq: 1 MESSAGE\r\n",
+        "CSeq: 1 MESSAGE\r\nContent-Length: 99999999\r\n",
+    );
+    buffer.extend_from_slice(absurd.as_bytes());
+    buffer.extend_from_slice(valid.as_bytes());
+    assert!(codec.decode(&mut buffer).is_err());
+    assert!(codec.take_dropped().is_some());
+    assert!(matches!(
+        codec.decode(&mut buffer),
+        Ok(Some(crate::transport::stream::SipCodecType::Message(_)))
+    ));
+
+    // a head that does not start with a start line is skipped
+    buffer.extend_from_slice(b"garbage\r\nContent-Length: 0\r\n\r\n");
+    buffer.extend_from_slice(valid.as_bytes());
+    assert!(codec.decode(&mut buffer).is_err());
+    assert!(matches!(
+        codec.decode(&mut buffer),
+        Ok(Some(crate::transport::stream::SipCodecType::Message(_)))
+    ));
+    assert_eq!(buffer.len(), 0);
+
+    // the connection closing mid-body is reported
+    let truncated = absurd.replace("Content-Length: 99999999", "Content-Length: 100");
+    buffer.extend_from_slice(truncated.as_bytes());
+    assert!(codec
+        .decode(&mut buffer)
+        .expect("waits for the body")
+        .is_none());
+    assert!(codec.decode_eof(&mut buffer).is_err());
+}
+
+/// Test SipCodec keeping a body that quotes another message intact
+#[test]
+fn test_sip_codec_body_quoting_a_message() {
+    let quoted = "OPTIONS sip:carol@example.com SIP/2.0\r\n\
+                  Via: SIP/2.0/TCP 10.0.0.1:5060;branch=z9hG4bK-quoted\r\n\
+                  Call-ID: quoted\r\n\
+                  CSeq: 7 OPTIONS\r\n\r\n";
+    let message = format!(
+        "MESSAGE sip:bob@example.com SIP/2.0\r\n\
+         Via: SIP/2.0/TCP 127.0.0.1:5060;branch=z9hG4bK-trace\r\n\
+         From: <sip:alice@example.com>;tag=trace\r\n\
+         To: <sip:bob@example.com>\r\n\
+         Call-ID: trace\r\n\
+         CSeq: 1 MESSAGE\r\n\
+         Content-Type: text/plain\r\n\
+         Content-Length: {}\r\n\r\n{}",
+        quoted.len(),
+        quoted
+    );
+    let mut codec = SipCodec::new();
+    let mut buffer = BytesMut::new();
+    // a partially received body is not scanned either
+    buffer.extend_from_slice(&message.as_bytes()[..message.len() - 10]);
+    assert!(codec
+        .decode(&mut buffer)
+        .expect("waits for the body")
+        .is_none());
+    buffer.extend_from_slice(&message.as_bytes()[message.len() - 10..]);
+    match codec.decode(&mut buffer) {
+        Ok(Some(crate::transport::stream::SipCodecType::Message(SipMessage::Request(req)))) => {
+            assert_eq!(req.method, rsip::Method::Message);
+            assert_eq!(req.body, quoted.as_bytes());
+        }
+        _ => panic!("Expected the MESSAGE with its quoted body"),
+    }
+    assert_eq!(buffer.len(), 0);
+}
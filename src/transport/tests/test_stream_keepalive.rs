@@ -0,0 +1,87 @@
+use crate::{
+    transport::{
+        connection::{KEEPALIVE_REQUEST, KEEPALIVE_RESPONSE},
+        SipAddr, TransportEvent, TransportLayer,
+    },
+    Result,
+};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+use tokio_util::sync::CancellationToken;
+
+async fn connect_with_keepalive(interval: Duration) -> Result<(TransportLayer, TcpStream)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let target = SipAddr::new(
+        rsip::transport::Transport::Tcp,
+        listener.local_addr()?.into(),
+    );
+    let tl = TransportLayer::new(CancellationToken::new());
+    tl.set_keepalive_interval(interval);
+    tl.lookup(&target, None).await?;
+    let (stream, _) = listener.accept().await?;
+    Ok((tl, stream))
+}
+
+async fn read_ping(stream: &mut TcpStream) {
+    let mut buf = [0u8; 4];
+    timeout(Duration::from_secs(1), stream.read_exact(&mut buf))
+        .await
+        .expect("no keepalive ping")
+        .expect("read ping");
+    assert_eq!(&buf, KEEPALIVE_REQUEST);
+}
+
+#[tokio::test]
+async fn test_tcp_keepalive_ping_answered() -> Result<()> {
+    let (tl, mut stream) = connect_with_keepalive(Duration::from_millis(50)).await?;
+
+    for _ in 0..3 {
+        read_ping(&mut stream).await;
+        stream.write_all(KEEPALIVE_RESPONSE).await?;
+    }
+    assert_eq!(tl.connection_count(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tcp_keepalive_closes_without_pong() -> Result<()> {
+    let (tl, mut stream) = connect_with_keepalive(Duration::from_millis(50)).await?;
+    let mut events = tl
+        .inner
+        .transport_rx
+        .lock()
+        .unwrap()
+        .take()
+        .expect("transport receiver");
+
+    read_ping(&mut stream).await;
+    timeout(Duration::from_secs(1), async {
+        while let Some(event) = events.recv().await {
+            if let TransportEvent::Closed(_) = event {
+                return;
+            }
+        }
+    })
+    .await
+    .expect("connection was not closed");
+    assert_eq!(tl.connection_count(), 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tcp_keepalive_disabled_by_default() -> Result<()> {
+    let (tl, mut stream) = connect_with_keepalive(Duration::ZERO).await?;
+    let mut buf = [0u8; 4];
+    assert!(
+        timeout(Duration::from_millis(200), stream.read(&mut buf))
+            .await
+            .is_err(),
+        "keepalive sent while disabled"
+    );
+    assert_eq!(tl.connection_count(), 1);
+    Ok(())
+}
@@ -1,7 +1,8 @@
 use crate::{
     transport::{
         connection::{KEEPALIVE_REQUEST, KEEPALIVE_RESPONSE},
-        udp::UdpConnection,
+        stun::is_stun_message,
+        udp::{split_datagram, UdpConnection},
         TransportEvent,
     },
     Result,
@@ -85,3 +86,175 @@ async fn test_udp_recv_sip_message() -> Result<()> {
     };
     Ok(())
 }
+
+#[tokio::test]
+async fn test_udp_recv_coalesced_messages() -> Result<()> {
+    let peer_bob = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+    let peer_alice = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+    let (bob_tx, mut bob_rx) = unbounded_channel();
+
+    let ack = "ACK sip:bob@restsend.com SIP/2.0\r\nVia: SIP/2.0/UDP 127.0.0.1:5061;branch=z9hG4bKack1\r\nCall-ID: coalesced\r\nFrom: <sip:alice@restsend.com>;tag=alice\r\nTo: <sip:bob@restsend.com>;tag=bob\r\nCSeq: 1 ACK\r\nContent-Length: 0\r\n\r\n";
+    let bye = "BYE sip:bob@restsend.com SIP/2.0\r\nVia: SIP/2.0/UDP 127.0.0.1:5061;branch=z9hG4bKbye1\r\nCall-ID: coalesced\r\nFrom: <sip:alice@restsend.com>;tag=alice\r\nTo: <sip:bob@restsend.com>;tag=bob\r\nCSeq: 2 BYE\r\nContent-Length: 0\r\n\r\n";
+    let datagram = format!("{}{}", ack, bye);
+    assert_eq!(split_datagram(datagram.as_bytes()).len(), 2);
+
+    let receive = async {
+        sleep(Duration::from_millis(20)).await; // wait for serve_loop to start
+        peer_alice
+            .send_raw(datagram.as_bytes(), peer_bob.get_addr())
+            .await
+            .expect("send_raw");
+        let mut methods = vec![];
+        while methods.len() < 2 {
+            match bob_rx.recv().await {
+                Some(TransportEvent::Incoming(rsip::SipMessage::Request(req), _, _)) => {
+                    assert!(req.body.is_empty());
+                    methods.push(req.method);
+                }
+                _ => panic!("unexpected event"),
+            }
+        }
+        methods
+    };
+
+    select! {
+        _ = peer_bob.serve_loop(bob_tx) => {
+            panic!("bob serve_loop exited");
+        }
+        methods = receive => {
+            assert_eq!(methods, vec![rsip::Method::Ack, rsip::Method::Bye]);
+        }
+        _ = sleep(Duration::from_millis(500)) => {
+            panic!("timeout waiting for both messages");
+        }
+    };
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_udp_answers_stun_binding_request() -> Result<()> {
+    use stun_rs::{
+        attributes::stun::XorMappedAddress, methods::BINDING, MessageClass, MessageDecoderBuilder,
+        MessageEncoderBuilder, StunMessageBuilder,
+    };
+
+    let peer_bob = UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+    let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let (bob_tx, mut bob_rx) = unbounded_channel();
+
+    let msg = StunMessageBuilder::new(BINDING, MessageClass::Request).build();
+    let mut request = [0u8; 150];
+    let len = MessageEncoderBuilder::default()
+        .build()
+        .encode(&mut request, &msg)
+        .expect("encode binding request");
+    let request = &request[..len];
+    assert!(is_stun_message(request));
+
+    let options = "OPTIONS sip:bob@restsend.com SIP/2.0\r\nVia: SIP/2.0/UDP 127.0.0.1:5061;branch=z9hG4bKstun1\r\nCall-ID: stun\r\nFrom: <sip:alice@restsend.com>;tag=alice\r\nTo: <sip:bob@restsend.com>\r\nCSeq: 1 OPTIONS\r\nContent-Length: 0\r\n\r\n";
+    assert!(!is_stun_message(options.as_bytes()));
+
+    let exchange = async {
+        sleep(Duration::from_millis(20)).await; // wait for serve_loop to start
+        let bob_addr = peer_bob.get_addr().get_socketaddr().expect("bob addr");
+        client.send_to(request, bob_addr).await.expect("send stun");
+        let mut buf = [0u8; 1500];
+        let (len, _) = client.recv_from(&mut buf).await.expect("recv stun");
+        let (resp, _) = MessageDecoderBuilder::default()
+            .build()
+            .decode(&buf[..len])
+            .expect("decode binding response");
+        let mapped = *resp
+            .get::<XorMappedAddress>()
+            .expect("XOR-MAPPED-ADDRESS")
+            .as_xor_mapped_address()
+            .expect("xor mapped address")
+            .socket_address();
+        assert_eq!(mapped, client.local_addr().expect("client addr"));
+
+        client
+            .send_to(options.as_bytes(), bob_addr)
+            .await
+            .expect("send sip");
+        match bob_rx.recv().await {
+            Some(TransportEvent::Incoming(rsip::SipMessage::Request(req), _, _)) => req.method,
+            _ => panic!("unexpected event"),
+        }
+    };
+
+    select! {
+        _ = peer_bob.serve_loop(bob_tx) => {
+            panic!("bob serve_loop exited");
+        }
+        method = exchange => {
+            assert_eq!(method, rsip::Method::Options);
+        }
+        _ = sleep(Duration::from_millis(500)) => {
+            panic!("timeout waiting for the stun response");
+        }
+    };
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_udp_response_follows_observed_source_port() -> Result<()> {
+    use crate::transaction::endpoint::EndpointBuilder;
+    use crate::transport::TransportLayer;
+    use rsip::prelude::{HeadersExt, ToTypedHeader};
+    use tokio_util::sync::CancellationToken;
+
+    let token = CancellationToken::new();
+    let transport_layer = TransportLayer::new(token.child_token());
+    let server =
+        UdpConnection::create_connection("127.0.0.1:0".parse()?, None, Some(token.child_token()))
+            .await?;
+    let server_addr = server.get_addr().get_socketaddr()?;
+    transport_layer.add_transport(server.into());
+    let endpoint = EndpointBuilder::new()
+        .with_transport_layer(transport_layer)
+        .with_cancel_token(token.child_token())
+        .build();
+    let mut incoming = endpoint.incoming_transactions()?;
+    let endpoint_inner = endpoint.inner.clone();
+    tokio::spawn(async move { endpoint_inner.serve().await });
+    tokio::spawn(async move {
+        while let Some(mut tx) = incoming.recv().await {
+            tx.reply(rsip::StatusCode::OK).await.ok();
+        }
+    });
+
+    // behind a NAT: the Via names a port the client is not reachable on
+    let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let client_port = client.local_addr()?.port();
+    let advertised_port = if client_port == 5099 { 5098 } else { 5099 };
+    let options = format!(
+        "OPTIONS sip:bob@restsend.com SIP/2.0\r\n\
+         Via: SIP/2.0/UDP 127.0.0.1:{};branch=z9hG4bKnat1;rport\r\n\
+         Call-ID: nat@restsend.com\r\n\
+         From: <sip:alice@restsend.com>;tag=alice\r\n\
+         To: <sip:bob@restsend.com>\r\n\
+         CSeq: 1 OPTIONS\r\n\
+         Content-Length: 0\r\n\r\n",
+        advertised_port
+    );
+    client.send_to(options.as_bytes(), server_addr).await?;
+
+    let mut buf = [0u8; 2048];
+    let (len, _) = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf))
+        .await
+        .expect("response was not sent to the observed port")?;
+    let resp: rsip::Response = rsip::SipMessage::try_from(&buf[..len])?.try_into()?;
+    assert_eq!(resp.status_code, rsip::StatusCode::OK);
+    let via = resp.via_header()?.typed()?;
+    assert!(via.params.iter().any(|p| matches!(
+        p,
+        rsip::Param::Received(r) if r.parse().ok() == Some(std::net::IpAddr::from([127, 0, 0, 1]))
+    )));
+    assert!(via.params.iter().any(|p| matches!(
+        p,
+        rsip::Param::Other(key, Some(port))
+            if key.value().eq_ignore_ascii_case("rport") && port.value() == client_port.to_string()
+    )));
+    token.cancel();
+    Ok(())
+}
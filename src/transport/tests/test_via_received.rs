@@ -1,5 +1,9 @@
 use crate::transport::SipConnection;
-use rsip::{headers::*, prelude::HeadersExt, SipMessage, Transport};
+use rsip::{
+    headers::*,
+    prelude::{HasHeaders, HeadersExt, UntypedHeader},
+    SipMessage, Transport,
+};
 use std::net::SocketAddr;
 
 /// Test Via received parameter handling for different transport protocols
@@ -179,3 +183,98 @@ fn create_test_request(via_proto: &str) -> rsip::message::Request {
         body: Default::default(),
     }
 }
+
+#[test]
+fn test_via_received_keeps_raw_header_text() {
+    let raw = "INVITE sip:bob@example.com SIP/2.0\r\n\
+               Via: SIP/2.0/UDP 10.0.0.5:5060;BRANCH=z9hG4bK-Raw;rport;x-Token=AbC\r\n\
+               Via: SIP/2.0/TCP  proxy.example.com:5060 ;branch=z9hG4bK-relay;  received=10.0.0.9\r\n\
+               From: \"Alice\"  <sip:alice@example.com>;tag=Tag-1\r\n\
+               To: <sip:bob@example.com>\r\n\
+               Call-ID: MixedCase-Call-ID@Example.COM\r\n\
+               CSeq: 1 INVITE\r\n\
+               Content-Length: 0\r\n\r\n";
+    let addr: SocketAddr = "192.168.1.100:5070".parse().unwrap();
+
+    let msg = SipConnection::update_msg_received(
+        SipMessage::try_from(raw).expect("parse"),
+        addr,
+        Transport::Udp,
+    )
+    .expect("update_msg_received");
+    let relayed = SipMessage::try_from(msg.to_string()).expect("reparse");
+
+    let vias: Vec<String> = relayed
+        .headers()
+        .iter()
+        .filter_map(|h| match h {
+            rsip::Header::Via(via) => Some(via.value().to_string()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        vias,
+        vec![
+            "SIP/2.0/UDP 10.0.0.5:5060;BRANCH=z9hG4bK-Raw;x-Token=AbC;received=192.168.1.100;rport=5070",
+            "SIP/2.0/TCP  proxy.example.com:5060 ;branch=z9hG4bK-relay;  received=10.0.0.9",
+        ]
+    );
+    match relayed {
+        SipMessage::Request(req) => {
+            assert_eq!(
+                req.call_id_header().expect("call-id").value(),
+                "MixedCase-Call-ID@Example.COM"
+            );
+            assert_eq!(
+                req.from_header().expect("from").value(),
+                "\"Alice\"  <sip:alice@example.com>;tag=Tag-1"
+            );
+        }
+        _ => panic!("Expected request message"),
+    }
+}
+
+#[test]
+fn test_via_received_udp_bare_rport_replaced() {
+    let mut register_req = create_test_request("SIP/2.0/UDP");
+    *register_req.via_header_mut().expect("via header") =
+        Via::new("SIP/2.0/UDP 127.0.0.1:5060;branch=z9hG4bK-test;rport");
+    let addr: SocketAddr = "127.0.0.1:5060".parse().unwrap(); // Same as Via header
+
+    let msg = SipConnection::update_msg_received(register_req.into(), addr, Transport::Udp)
+        .expect("update_msg_received for UDP");
+
+    match msg {
+        SipMessage::Request(req) => {
+            let via_header = req.via_header().expect("via header");
+            // The bare rport is answered even when the address matches, and
+            // never left next to the filled-in one
+            assert_eq!(
+                via_header.value(),
+                "SIP/2.0/UDP 127.0.0.1:5060;branch=z9hG4bK-test;received=127.0.0.1;rport=5060"
+            );
+        }
+        _ => panic!("Expected request message"),
+    }
+}
+
+#[test]
+fn test_via_received_udp_without_rport() {
+    let register_req = create_test_request("SIP/2.0/UDP");
+    let addr: SocketAddr = "127.0.0.1:5060".parse().unwrap(); // Same as Via header
+
+    let msg = SipConnection::update_msg_received(register_req.into(), addr, Transport::Udp)
+        .expect("update_msg_received for UDP");
+
+    match msg {
+        SipMessage::Request(req) => {
+            let via_header = req.via_header().expect("via header");
+            // Without rport and with a matching address the Via is left alone
+            assert_eq!(
+                via_header.value(),
+                "SIP/2.0/UDP 127.0.0.1:5060;branch=z9hG4bK-test"
+            );
+        }
+        _ => panic!("Expected request message"),
+    }
+}
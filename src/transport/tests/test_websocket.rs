@@ -0,0 +1,90 @@
+use crate::{
+    transport::{SipAddr, TransportEvent, TransportLayer, WebSocketListenerConnection},
+    Result,
+};
+use futures_util::{SinkExt, StreamExt};
+use rsip::prelude::{HeadersExt, ToTypedHeader};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_util::sync::CancellationToken;
+
+#[tokio::test]
+async fn test_websocket_frames_and_keepalives() -> Result<()> {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    let socket_addr: std::net::SocketAddr = format!("127.0.0.1:{}", port).parse()?;
+    let server = TransportLayer::new(CancellationToken::new());
+    server.add_transport(
+        WebSocketListenerConnection::new(
+            SipAddr::new(rsip::transport::Transport::Ws, socket_addr.into()),
+            None,
+            false,
+        )
+        .await?
+        .into(),
+    );
+    server.serve_listens().await?;
+    let mut server_rx = server
+        .inner
+        .transport_rx
+        .lock()
+        .unwrap()
+        .take()
+        .expect("transport receiver");
+
+    let (mut client, _) =
+        tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}/", port)).await?;
+
+    // the double-CRLF keepalive is answered within a frame, a ping with a pong
+    client.send(Message::Text("\r\n\r\n".into())).await?;
+    match client.next().await {
+        Some(Ok(Message::Text(text))) => assert_eq!(text.as_str(), "\r\n"),
+        other => panic!("expected keepalive response, got {:?}", other),
+    }
+    client.send(Message::Ping(b"ka".to_vec().into())).await?;
+    match client.next().await {
+        Some(Ok(Message::Pong(data))) => assert_eq!(&data[..], b"ka"),
+        other => panic!("expected pong, got {:?}", other),
+    }
+
+    // a browser uses a made-up Via host: received and rport come from the socket
+    let register = "REGISTER sip:example.com SIP/2.0\r\n\
+                    Via: SIP/2.0/WS df7jal23ls0d.invalid;branch=z9hG4bK-ws;rport\r\n\
+                    From: <sip:alice@example.com>;tag=ws\r\n\
+                    To: <sip:alice@example.com>\r\n\
+                    Call-ID: ws-call-id\r\n\
+                    CSeq: 1 REGISTER\r\n\
+                    Content-Length: 0\r\n\r\n";
+    for frame in [
+        Message::Text(register.into()),
+        Message::Binary(register.as_bytes().to_vec().into()),
+    ] {
+        client.send(frame).await?;
+        let event = loop {
+            let event = tokio::time::timeout(Duration::from_secs(2), server_rx.recv())
+                .await
+                .expect("timeout waiting for REGISTER")
+                .expect("transport event");
+            if let TransportEvent::Incoming(msg, _, from) = event {
+                break (msg, from);
+            }
+        };
+        let (msg, from) = event;
+        assert_eq!(from.r#type, Some(rsip::transport::Transport::Ws));
+        let req = match msg {
+            rsip::SipMessage::Request(req) => req,
+            _ => panic!("expected a request"),
+        };
+        let via = req.via_header()?.typed()?;
+        assert_eq!(
+            via.received()?,
+            Some(std::net::IpAddr::from([127, 0, 0, 1]))
+        );
+        assert!(via.params.iter().any(|p| matches!(
+            p, rsip::Param::Other(key, Some(port)) if key.value().eq_ignore_ascii_case("rport")
+                && port.value() == from.addr.port.as_ref().unwrap().to_string()
+        )));
+    }
+    Ok(())
+}
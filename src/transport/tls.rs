@@ -7,7 +7,7 @@ use super::{
 use crate::{error::Error, transport::transport_layer::TransportLayerInnerRef, Result};
 use rsip::SipMessage;
 use rustls::client::danger::ServerCertVerifier;
-use std::{fmt, net::SocketAddr, sync::Arc};
+use std::{fmt, net::SocketAddr, sync::Arc, time::Duration};
 use tokio::net::{TcpListener, TcpStream};
 use tokio_rustls::{
     rustls::{pki_types, ClientConfig, RootCertStore, ServerConfig},
@@ -31,6 +31,56 @@ pub struct TlsConfig {
     pub ca_certs: Option<Vec<u8>>,
 }
 
+fn load_certs(pem: &[u8]) -> Result<Vec<pki_types::CertificateDer<'static>>> {
+    let mut reader = std::io::BufReader::new(pem);
+    rustls_pemfile::certs(&mut reader)
+        .collect::<std::result::Result<Vec<_>, std::io::Error>>()
+        .map_err(|e| Error::Error(format!("Failed to parse certificate: {}", e)))
+}
+
+fn load_private_key(pem: &[u8]) -> Result<pki_types::PrivateKeyDer<'static>> {
+    let mut reader = std::io::BufReader::new(pem);
+    // Try PKCS8 format first
+    let keys = rustls_pemfile::pkcs8_private_keys(&mut reader)
+        .collect::<std::result::Result<Vec<_>, std::io::Error>>()
+        .map_err(|e| Error::Error(format!("Failed to parse PKCS8 key: {}", e)))?;
+    if let Some(key) = keys.first() {
+        return Ok(pki_types::PrivateKeyDer::Pkcs8(key.clone_key()));
+    }
+
+    // Try PKCS1 format
+    let mut reader = std::io::BufReader::new(pem);
+    let keys = rustls_pemfile::rsa_private_keys(&mut reader)
+        .collect::<std::result::Result<Vec<_>, std::io::Error>>()
+        .map_err(|e| Error::Error(format!("Failed to parse RSA key: {}", e)))?;
+    match keys.first() {
+        Some(key) => Ok(pki_types::PrivateKeyDer::Pkcs1(key.clone_key())),
+        None => Err(Error::Error("No valid private key found".to_string())),
+    }
+}
+
+impl TlsConfig {
+    /// Client side configuration: server certificates are verified against
+    /// `ca_certs`, and `client_cert`/`client_key` are presented when both are set
+    pub fn client_config(&self) -> Result<ClientConfig> {
+        let mut root_store = RootCertStore::empty();
+        if let Some(ca_certs) = &self.ca_certs {
+            for cert in load_certs(ca_certs)? {
+                root_store
+                    .add(cert)
+                    .map_err(|e| Error::Error(format!("Invalid CA certificate: {}", e)))?;
+            }
+        }
+        let builder = ClientConfig::builder().with_root_certificates(root_store);
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => builder
+                .with_client_auth_cert(load_certs(cert)?, load_private_key(key)?)
+                .map_err(|e| Error::Error(format!("TLS configuration error: {}", e))),
+            _ => Ok(builder.with_no_client_auth()),
+        }
+    }
+}
+
 // TLS Listener Connection Structure
 pub struct TlsListenerConnectionInner {
     pub local_addr: SipAddr,
@@ -134,44 +184,12 @@ impl TlsListenerConnection {
     }
 
     async fn create_acceptor(config: &TlsConfig) -> Result<TlsAcceptor> {
-        // Load certificate chain
         let certs = match &config.cert {
-            Some(cert_data) => {
-                let mut reader = std::io::BufReader::new(cert_data.as_slice());
-                rustls_pemfile::certs(&mut reader)
-                    .collect::<std::result::Result<Vec<_>, std::io::Error>>()
-                    .map_err(|e| Error::Error(format!("Failed to parse certificate: {}", e)))?
-            }
+            Some(cert_data) => load_certs(cert_data)?,
             None => return Err(Error::Error("No certificate provided".to_string())),
         };
-
-        // Load private key
         let key = match &config.key {
-            Some(key_data) => {
-                let mut reader = std::io::BufReader::new(key_data.as_slice());
-                // Try PKCS8 format first
-                let keys = rustls_pemfile::pkcs8_private_keys(&mut reader)
-                    .collect::<std::result::Result<Vec<_>, std::io::Error>>()
-                    .map_err(|e| Error::Error(format!("Failed to parse PKCS8 key: {}", e)))?;
-
-                if !keys.is_empty() {
-                    let key_der = pki_types::PrivatePkcs8KeyDer::from(keys[0].clone_key());
-                    pki_types::PrivateKeyDer::Pkcs8(key_der)
-                } else {
-                    // Try PKCS1 format
-                    let mut reader = std::io::BufReader::new(key_data.as_slice());
-                    let keys = rustls_pemfile::rsa_private_keys(&mut reader)
-                        .collect::<std::result::Result<Vec<_>, std::io::Error>>()
-                        .map_err(|e| Error::Error(format!("Failed to parse RSA key: {}", e)))?;
-
-                    if !keys.is_empty() {
-                        let key_der = pki_types::PrivatePkcs1KeyDer::from(keys[0].clone_key());
-                        pki_types::PrivateKeyDer::Pkcs1(key_der)
-                    } else {
-                        return Err(Error::Error("No valid private key found".to_string()));
-                    }
-                }
-            }
+            Some(key_data) => load_private_key(key_data)?,
             None => return Err(Error::Error("No private key provided".to_string())),
         };
 
@@ -208,6 +226,7 @@ type TlsServerStream = tokio_rustls::server::TlsStream<TcpStream>;
 #[derive(Clone)]
 pub struct TlsConnection {
     inner: TlsConnectionInner,
+    peer_name: Option<String>,
     pub cancel_token: Option<CancellationToken>,
 }
 
@@ -238,11 +257,29 @@ impl TlsConnection {
         custom_verifier: Option<Arc<dyn ServerCertVerifier>>,
         cancel_token: Option<CancellationToken>,
     ) -> Result<Self> {
-        let root_store = RootCertStore::empty();
+        Self::connect_with_config(
+            remote_addr,
+            None,
+            &TlsConfig::default(),
+            custom_verifier,
+            cancel_token,
+        )
+        .await
+    }
 
-        let mut config = ClientConfig::builder()
-            .with_root_certificates(root_store)
-            .with_no_client_auth();
+    /// Connect to a remote TLS server, verifying it against `config`
+    ///
+    /// `server_name` is the name sent as SNI and checked against the server
+    /// certificate. It defaults to the host of `remote_addr`; pass the domain
+    /// of the `sips:` URI when `remote_addr` has already been resolved to an IP.
+    pub async fn connect_with_config(
+        remote_addr: &SipAddr,
+        server_name: Option<&str>,
+        config: &TlsConfig,
+        custom_verifier: Option<Arc<dyn ServerCertVerifier>>,
+        cancel_token: Option<CancellationToken>,
+    ) -> Result<Self> {
+        let mut config = config.client_config()?;
 
         match custom_verifier {
             Some(verifier) => {
@@ -263,9 +300,10 @@ impl TlsConnection {
             }
         };
 
-        let domain_string = match &remote_addr.addr.host {
-            rsip::host_with_port::Host::Domain(domain) => domain.to_string(),
-            rsip::host_with_port::Host::IpAddr(ip) => ip.to_string(),
+        let domain_string = match (server_name, &remote_addr.addr.host) {
+            (Some(server_name), _) => server_name.to_string(),
+            (None, rsip::host_with_port::Host::Domain(domain)) => domain.to_string(),
+            (None, rsip::host_with_port::Host::IpAddr(ip)) => ip.to_string(),
         };
 
         let server_name = pki_types::ServerName::try_from(domain_string.as_str())
@@ -288,11 +326,12 @@ impl TlsConnection {
                 read_half,
                 write_half,
             ))),
+            peer_name: Some(domain_string),
             cancel_token,
         };
         info!(
-            "Created TLS client connection: {} -> {}",
-            local_addr, remote_addr
+            peer_name = connection.peer_name(),
+            "Created TLS client connection: {} -> {}", local_addr, remote_addr
         );
 
         Ok(connection)
@@ -309,6 +348,11 @@ impl TlsConnection {
             addr: stream.get_ref().0.local_addr()?.into(),
         };
 
+        let peer_name = match &remote_addr.addr.host {
+            rsip::host_with_port::Host::Domain(domain) => Some(domain.to_string()),
+            rsip::host_with_port::Host::IpAddr(_) => None,
+        };
+
         // Split stream into read and write halves
         let (read_half, write_half) = tokio::io::split(stream);
 
@@ -320,6 +364,7 @@ impl TlsConnection {
                 read_half,
                 write_half,
             ))),
+            peer_name,
             cancel_token,
         };
 
@@ -343,6 +388,9 @@ impl TlsConnection {
             addr: stream.get_ref().0.local_addr()?.into(),
         };
 
+        // SNI the client asked for, if any
+        let peer_name = stream.get_ref().1.server_name().map(str::to_string);
+
         // Split stream into read and write halves
         let (read_half, write_half) = tokio::io::split(stream);
 
@@ -354,6 +402,7 @@ impl TlsConnection {
                 read_half,
                 write_half,
             ))),
+            peer_name,
             cancel_token,
         };
 
@@ -369,6 +418,29 @@ impl TlsConnection {
     pub fn cancel_token(&self) -> Option<CancellationToken> {
         self.cancel_token.clone()
     }
+
+    /// Local end of the TLS connection
+    pub fn local_addr(&self) -> &SipAddr {
+        match &self.inner {
+            TlsConnectionInner::Client(inner) => &inner.local_addr,
+            TlsConnectionInner::Server(inner) => &inner.local_addr,
+        }
+    }
+
+    /// See [`StreamConnectionInner::set_keepalive_interval`]
+    pub fn set_keepalive_interval(&self, interval: Duration) {
+        match &self.inner {
+            TlsConnectionInner::Client(inner) => inner.set_keepalive_interval(interval),
+            TlsConnectionInner::Server(inner) => inner.set_keepalive_interval(interval),
+        }
+    }
+
+    /// Server name of the session: the name verified against the server
+    /// certificate on outbound connections, the SNI sent by the client on
+    /// accepted ones
+    pub fn peer_name(&self) -> Option<&str> {
+        self.peer_name.as_deref()
+    }
 }
 
 // Implement StreamConnection trait for TlsConnection
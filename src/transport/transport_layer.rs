@@ -1,26 +1,122 @@
-use super::tls::TlsConnection;
+use super::circuit_breaker::CircuitBreaker;
+use super::tls::{TlsConfig, TlsConnection};
 use super::websocket::WebSocketConnection;
-use super::{connection::TransportSender, sip_addr::SipAddr, tcp::TcpConnection, SipConnection};
+use super::{
+    connection::TransportSender, sip_addr::SipAddr, tcp::TcpConnection,
+    tcp_listener::TcpListenerConnection, udp::UdpConnection, SipConnection,
+};
 use crate::transaction::key::TransactionKey;
 use crate::transport::connection::TransportReceiver;
 use crate::{transport::TransportEvent, Result};
 use async_trait::async_trait;
 
 #[cfg(feature = "rsip-dns")]
-use rsip_dns::trust_dns_resolver::TokioAsyncResolver;
+use super::dns::{naptr_srv_names, order_srv_targets, srv_name, NaptrService, SrvTarget};
 #[cfg(feature = "rsip-dns")]
-use rsip_dns::ResolvableExt;
-
+use rsip_dns::trust_dns_resolver::{
+    proto::{
+        rr::{RData, RecordType},
+        xfer::DnsRequestOptions,
+    },
+    TokioAsyncResolver,
+};
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::{Mutex, RwLock};
+use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
 use tokio::select;
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
 
+/// 64*T1, the time a transaction has before Timer B/F gives up on it
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(32);
+
+/// Turns a domain target into transport addresses
+///
+/// The transport layer consults it for every lookup of a domain, so
+/// registration, requests and responses sent along the Via all resolve
+/// through it. Use [`TransportLayer::new_with_domain_resolver`] to replace
+/// the system DNS, e.g. with a [`StaticResolver`] in tests or a resolver
+/// for split-horizon DNS.
 #[async_trait]
 pub trait DomainResolver: Send + Sync {
     async fn resolve(&self, target: &SipAddr) -> Result<SipAddr>;
+
+    /// All candidates for `target`, most preferred first
+    async fn resolve_all(&self, target: &SipAddr) -> Result<Vec<SipAddr>> {
+        Ok(vec![self.resolve(target).await?])
+    }
+}
+
+/// Resolver answering from a fixed table of domains
+///
+/// An address without a transport or port takes the one of the target.
+///
+/// # Examples
+///
+/// ```rust
+/// use rsipstack::transport::{transport_layer::StaticResolver, SipAddr};
+///
+/// let addr = SipAddr::new(
+///     rsip::Transport::Udp,
+///     rsip::HostWithPort::try_from("192.0.2.10:5060").unwrap(),
+/// );
+/// let resolver = StaticResolver::new().with("sip.example.com", vec![addr]);
+/// ```
+#[derive(Default, Clone)]
+pub struct StaticResolver {
+    entries: HashMap<String, Vec<SipAddr>>,
+}
+
+impl StaticResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer `domain`, matched case-insensitively, with `addrs`
+    pub fn with(mut self, domain: &str, addrs: Vec<SipAddr>) -> Self {
+        self.insert(domain, addrs);
+        self
+    }
+
+    pub fn insert(&mut self, domain: &str, addrs: Vec<SipAddr>) {
+        self.entries.insert(domain.to_ascii_lowercase(), addrs);
+    }
+}
+
+#[async_trait]
+impl DomainResolver for StaticResolver {
+    async fn resolve(&self, target: &SipAddr) -> Result<SipAddr> {
+        self.resolve_all(target)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| crate::Error::DnsResolutionError(target.addr.to_string()))
+    }
+
+    async fn resolve_all(&self, target: &SipAddr) -> Result<Vec<SipAddr>> {
+        let addrs = match &target.addr.host {
+            rsip::Host::Domain(domain) => {
+                self.entries.get(&domain.to_string().to_ascii_lowercase())
+            }
+            rsip::Host::IpAddr(_) => None,
+        };
+        match addrs {
+            Some(addrs) if !addrs.is_empty() => Ok(addrs
+                .iter()
+                .map(|addr| SipAddr {
+                    r#type: addr.r#type.or(target.r#type),
+                    addr: rsip::HostWithPort {
+                        host: addr.addr.host.clone(),
+                        port: addr.addr.port.or(target.addr.port),
+                    },
+                })
+                .collect()),
+            _ => Err(crate::Error::DnsResolutionError(target.addr.to_string())),
+        }
+    }
 }
 
 pub struct DefaultDomainResolver {}
@@ -34,7 +130,7 @@ impl DefaultDomainResolver {
                 return Err(crate::Error::DnsResolutionError(target.addr.to_string()));
             }
         };
-        let port = target.addr.port.unwrap_or(5060.into());
+        let port = target.port_or_default();
         let lookup_str = format!("{}:{}", host, port);
         let addrs = tokio::net::lookup_host(lookup_str).await?;
         for addr in addrs {
@@ -49,43 +145,104 @@ impl DefaultDomainResolver {
         Err(crate::Error::DnsResolutionError(target.addr.to_string()))
     }
 
+    /// Candidates for `target` in RFC 3263 order
+    ///
+    /// A target with a port is only looked up as A/AAAA. Otherwise the SRV
+    /// records of its transport, or of the transports its NAPTR records
+    /// name, are tried first.
     #[cfg(feature = "rsip-dns")]
-    pub async fn resolve_with_rsip_dns(&self, target: &SipAddr) -> Result<SipAddr> {
-        let params = target
-            .r#type
-            .filter(|&t| !matches!(t, rsip::Transport::Udp))
-            .map(rsip::Param::Transport)
-            .into_iter()
-            .collect();
-        let scheme = target.r#type.map(|t| match t {
-            rsip::Transport::Tls | rsip::Transport::Wss => rsip::Scheme::Sips,
-            _ => rsip::Scheme::Sip,
-        });
-        let target_for_lookup = rsip::uri::Uri {
-            scheme,
-            host_with_port: target.addr.clone(),
-            params,
-            ..Default::default()
+    pub async fn resolve_all_with_dns(&self, target: &SipAddr) -> Result<Vec<SipAddr>> {
+        let domain = match &target.addr.host {
+            rsip::Host::Domain(domain) => domain.to_string(),
+            rsip::Host::IpAddr(_) => return Ok(vec![target.clone()]),
         };
-        let context = rsip_dns::Context::initialize_from(
-            target_for_lookup,
-            rsip_dns::AsyncTrustDnsClient::new(
-                TokioAsyncResolver::tokio(Default::default(), Default::default()).unwrap(),
-            ),
-            rsip_dns::SupportedTransports::any(),
-        )?;
+        let resolver = TokioAsyncResolver::tokio(Default::default(), Default::default())
+            .map_err(|e| crate::Error::DnsResolutionError(format!("{}: {}", domain, e)))?;
+        let transport = target.r#type.unwrap_or(rsip::Transport::Udp);
+        let mut candidates = Vec::new();
+
+        if target.addr.port.is_none() {
+            let srv_names = match target.r#type {
+                Some(transport) => vec![(transport, srv_name(transport, &domain))],
+                None => {
+                    let records = match resolver
+                        .lookup(
+                            domain.as_str(),
+                            RecordType::NAPTR,
+                            DnsRequestOptions::default(),
+                        )
+                        .await
+                    {
+                        Ok(lookup) => lookup
+                            .iter()
+                            .filter_map(|rdata| match rdata {
+                                RData::NAPTR(naptr) => Some(NaptrService {
+                                    order: naptr.order(),
+                                    preference: naptr.preference(),
+                                    flags: String::from_utf8_lossy(naptr.flags()).to_string(),
+                                    service: String::from_utf8_lossy(naptr.services()).to_string(),
+                                    replacement: naptr.replacement().to_utf8(),
+                                }),
+                                _ => None,
+                            })
+                            .collect(),
+                        Err(_) => Vec::new(),
+                    };
+                    let srv_names = naptr_srv_names(records);
+                    if srv_names.is_empty() {
+                        [
+                            rsip::Transport::Udp,
+                            rsip::Transport::Tcp,
+                            rsip::Transport::Tls,
+                        ]
+                        .into_iter()
+                        .map(|transport| (transport, srv_name(transport, &domain)))
+                        .collect()
+                    } else {
+                        srv_names
+                    }
+                }
+            };
+            for (transport, name) in srv_names {
+                let Ok(lookup) = resolver.srv_lookup(name.as_str()).await else {
+                    continue;
+                };
+                let targets = lookup
+                    .iter()
+                    .map(|srv| SrvTarget {
+                        priority: srv.priority(),
+                        weight: srv.weight(),
+                        port: srv.port(),
+                        target: srv.target().to_utf8(),
+                    })
+                    .collect();
+                let ordered = order_srv_targets(targets, &mut rand::rng());
+                for srv in ordered {
+                    let Ok(ips) = resolver.lookup_ip(srv.target.as_str()).await else {
+                        continue;
+                    };
+                    candidates.extend(ips.iter().map(|ip| SipAddr {
+                        r#type: Some(transport),
+                        addr: rsip::HostWithPort::from(core::net::SocketAddr::new(ip, srv.port)),
+                    }));
+                }
+            }
+        }
 
-        let mut lookup = rsip_dns::Lookup::from(context);
-        match lookup.resolve_next().await {
-            Some(result) => Ok(SipAddr {
-                r#type: Some(result.transport),
-                addr: rsip::HostWithPort::from(core::net::SocketAddr::new(
-                    result.ip_addr,
-                    u16::from(result.port),
-                )),
-            }),
-            None => Err(crate::Error::DnsResolutionError(target.addr.to_string())),
+        if candidates.is_empty() {
+            let port = target.port_or_default();
+            if let Ok(ips) = resolver.lookup_ip(domain.as_str()).await {
+                candidates.extend(ips.iter().map(|ip| SipAddr {
+                    r#type: Some(transport),
+                    addr: rsip::HostWithPort::from(core::net::SocketAddr::new(ip, port)),
+                }));
+            }
+        }
+        if candidates.is_empty() {
+            return Err(crate::Error::DnsResolutionError(target.addr.to_string()));
         }
+        debug!("resolved {} -> {:?}", target, candidates);
+        Ok(candidates)
     }
 }
 
@@ -93,20 +250,38 @@ impl DefaultDomainResolver {
 impl DomainResolver for DefaultDomainResolver {
     async fn resolve(&self, target: &SipAddr) -> Result<SipAddr> {
         #[cfg(feature = "rsip-dns")]
-        return self.resolve_with_rsip_dns(target).await;
+        return self
+            .resolve_all_with_dns(target)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| crate::Error::DnsResolutionError(target.addr.to_string()));
 
         #[cfg(not(feature = "rsip-dns"))]
         return self.resolve_with_lookup(target).await;
     }
+
+    async fn resolve_all(&self, target: &SipAddr) -> Result<Vec<SipAddr>> {
+        #[cfg(feature = "rsip-dns")]
+        return self.resolve_all_with_dns(target).await;
+
+        #[cfg(not(feature = "rsip-dns"))]
+        return Ok(vec![self.resolve_with_lookup(target).await?]);
+    }
 }
 
 pub struct TransportLayerInner {
     pub(crate) cancel_token: CancellationToken,
     listens: Arc<RwLock<Vec<SipConnection>>>, // listening transports
     connections: Arc<RwLock<HashMap<SipAddr, SipConnection>>>, // outbound/inbound connections
+    dialing: Mutex<HashMap<SipAddr, Arc<tokio::sync::Mutex<()>>>>, // in-flight outbound dials
     pub(crate) transport_tx: TransportSender,
     pub(crate) transport_rx: Mutex<Option<TransportReceiver>>,
     pub domain_resolver: Box<dyn DomainResolver>,
+    circuit_breaker: RwLock<Option<Arc<CircuitBreaker>>>,
+    tls_config: RwLock<TlsConfig>,
+    keepalive_interval: RwLock<Duration>,
+    connect_timeout: RwLock<Duration>,
 }
 pub(crate) type TransportLayerInnerRef = Arc<TransportLayerInner>;
 
@@ -125,9 +300,14 @@ impl TransportLayer {
             cancel_token,
             listens: Arc::new(RwLock::new(Vec::new())),
             connections: Arc::new(RwLock::new(HashMap::new())),
+            dialing: Mutex::new(HashMap::new()),
             transport_tx,
             transport_rx: Mutex::new(Some(transport_rx)),
             domain_resolver,
+            circuit_breaker: RwLock::new(None),
+            tls_config: RwLock::new(TlsConfig::default()),
+            keepalive_interval: RwLock::new(Duration::ZERO),
+            connect_timeout: RwLock::new(DEFAULT_CONNECT_TIMEOUT),
         };
         Self {
             outbound: None,
@@ -156,6 +336,19 @@ impl TransportLayer {
         self.inner.del_connection(addr)
     }
 
+    /// Number of live stream connections, inbound and outbound
+    ///
+    /// Connections are keyed by their remote address, so lookups towards
+    /// the same peer share one connection; it leaves the pool when its
+    /// `serve_loop` ends.
+    pub fn connection_count(&self) -> usize {
+        self.inner
+            .connections
+            .read()
+            .map(|connections| connections.len())
+            .unwrap_or_default()
+    }
+
     pub async fn lookup(
         &self,
         target: &SipAddr,
@@ -164,6 +357,87 @@ impl TransportLayer {
         self.inner.lookup(target, self.outbound.as_ref(), key).await
     }
 
+    /// Addresses a lookup of `target` tries, in order
+    pub async fn resolve_candidates(&self, target: &SipAddr) -> Result<Vec<SipAddr>> {
+        match target.addr.host {
+            rsip::Host::Domain(_) => self.inner.domain_resolver.resolve_all(target).await,
+            rsip::Host::IpAddr(_) => Ok(vec![target.clone()]),
+        }
+    }
+
+    /// Like [`lookup`](Self::lookup), without going through the outbound
+    /// proxy, for responses sent back along the Via
+    pub async fn lookup_direct(&self, target: &SipAddr) -> Result<(SipConnection, SipAddr)> {
+        self.inner.lookup(target, None, None).await
+    }
+
+    /// Fail fast on targets that keep failing, see [`CircuitBreaker`]
+    pub fn set_circuit_breaker(&self, circuit_breaker: CircuitBreaker) {
+        match self.inner.circuit_breaker.write() {
+            Ok(mut breaker) => {
+                breaker.replace(Arc::new(circuit_breaker));
+            }
+            Err(e) => {
+                warn!("Failed to write circuit breaker: {:?}", e);
+            }
+        }
+    }
+
+    pub fn circuit_breaker(&self) -> Option<Arc<CircuitBreaker>> {
+        self.inner.circuit_breaker()
+    }
+
+    /// Root store and client identity used for outbound `sips:` connections
+    pub fn set_tls_config(&self, tls_config: TlsConfig) {
+        match self.inner.tls_config.write() {
+            Ok(mut config) => *config = tls_config,
+            Err(e) => {
+                warn!("Failed to write tls config: {:?}", e);
+            }
+        }
+    }
+
+    /// Time the resolved candidates of a domain have to connect, shared
+    /// evenly among them, so one that never answers cannot hold up the
+    /// failover to the next. 64*T1 by default.
+    pub fn set_connect_timeout(&self, timeout: Duration) {
+        match self.inner.connect_timeout.write() {
+            Ok(mut connect_timeout) => *connect_timeout = timeout,
+            Err(e) => {
+                warn!("Failed to write connect timeout: {:?}", e);
+            }
+        }
+    }
+
+    /// Keepalive ping interval of every TCP and TLS connection, current and
+    /// future, see [`SipConnection::set_keepalive_interval`].
+    /// `Duration::ZERO` disables pings, the default.
+    pub fn set_keepalive_interval(&self, interval: Duration) {
+        match self.inner.keepalive_interval.write() {
+            Ok(mut keepalive_interval) => *keepalive_interval = interval,
+            Err(e) => {
+                warn!("Failed to write keepalive interval: {:?}", e);
+            }
+        }
+        if let Ok(connections) = self.inner.connections.read() {
+            for connection in connections.values() {
+                connection.set_keepalive_interval(interval);
+            }
+        }
+    }
+
+    /// Feed the outcome of sending to `target` into the circuit breaker
+    pub fn record_send_result(&self, target: &SipAddr, success: bool) {
+        if let Some(breaker) = self.inner.circuit_breaker() {
+            let target = self.outbound.as_ref().unwrap_or(target);
+            if success {
+                breaker.record_success(target);
+            } else {
+                breaker.record_failure(target);
+            }
+        }
+    }
+
     pub async fn serve_listens(&self) -> Result<()> {
         let listens = match self.inner.listens.read() {
             Ok(listens) => listens.clone(),
@@ -186,6 +460,66 @@ impl TransportLayer {
         Ok(())
     }
 
+    /// Bind a listening socket on `addr` and add it to the layer
+    ///
+    /// UDP binds right away, so a port of 0 comes back as the one the
+    /// system picked; TCP binds when served. A dual-homed host adds one
+    /// listener per interface, and requests leave through the one facing
+    /// their destination, see [`Self::select_listener`]. Call before
+    /// [`Self::serve_listens`].
+    ///
+    /// "Facing" is guessed from the address alone: the listener sharing the
+    /// most leading bits with the destination wins, as the layer knows no
+    /// netmasks or routes. This suits interfaces in distinct private ranges,
+    /// e.g. `10.0.0.5` and `192.168.1.5`, but may pick the wrong one for
+    /// interfaces in overlapping ranges. Ties go to the first listener
+    /// added.
+    pub async fn add_listener(&self, addr: SipAddr) -> Result<SipConnection> {
+        let connection: SipConnection = match addr.r#type {
+            Some(rsip::transport::Transport::Udp) | None => UdpConnection::create_connection(
+                addr.get_socketaddr()?,
+                None,
+                Some(self.inner.cancel_token.child_token()),
+            )
+            .await?
+            .into(),
+            Some(rsip::transport::Transport::Tcp) => {
+                TcpListenerConnection::new(addr, None).await?.into()
+            }
+            _ => {
+                return Err(crate::Error::TransportLayerError(
+                    format!("unsupported listener transport: {:?}", addr.r#type),
+                    addr,
+                ))
+            }
+        };
+        self.inner.add_listener(connection.clone());
+        Ok(connection)
+    }
+
+    /// Listening sockets, in the order they were added
+    pub fn listeners(&self) -> Vec<SipConnection> {
+        match self.inner.listens.read() {
+            Ok(listens) => listens.clone(),
+            Err(e) => {
+                warn!("Failed to read listens: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Listener of `transport` to send to `destination` from
+    ///
+    /// See [`select_source`].
+    pub fn select_listener(
+        &self,
+        transport: rsip::transport::Transport,
+        destination: &SipAddr,
+    ) -> Option<SipConnection> {
+        let listens = self.inner.listens.read().ok()?;
+        select_source(listens.iter(), transport, destination).cloned()
+    }
+
     pub fn get_addrs(&self) -> Vec<SipAddr> {
         match self.inner.listens.read() {
             Ok(listens) => listens.iter().map(|t| t.get_addr().to_owned()).collect(),
@@ -223,6 +557,9 @@ impl TransportLayerInner {
     pub(super) fn add_connection(&self, connection: SipConnection) {
         match self.connections.write() {
             Ok(mut connections) => {
+                if let Ok(interval) = self.keepalive_interval.read() {
+                    connection.set_keepalive_interval(*interval);
+                }
                 connections.insert(connection.get_addr().to_owned(), connection.clone());
                 self.serve_connection(connection);
             }
@@ -243,33 +580,86 @@ impl TransportLayerInner {
         }
     }
 
+    pub(super) fn circuit_breaker(&self) -> Option<Arc<CircuitBreaker>> {
+        self.circuit_breaker
+            .read()
+            .ok()
+            .and_then(|breaker| breaker.clone())
+    }
+
     async fn lookup(
         &self,
         destination: &SipAddr,
         outbound: Option<&SipAddr>,
         key: Option<&TransactionKey>,
     ) -> Result<(SipConnection, SipAddr)> {
+        let breaker = self.circuit_breaker();
         let target = outbound.unwrap_or(destination);
-        let target = if matches!(target.addr.host, rsip::Host::Domain(_)) {
-            &self.domain_resolver.resolve(target).await?
-        } else {
-            target
-        };
+        if let Some(breaker) = &breaker {
+            breaker.check(target)?;
+        }
+        let result = self.lookup_connection(destination, target, key).await;
+        if let (Some(breaker), Err(_)) = (&breaker, &result) {
+            breaker.record_failure(target);
+        }
+        result
+    }
 
-        debug!(?key, "lookup target: {} -> {}", destination, target);
-        match self.connections.read() {
-            Ok(connections) => {
-                if let Some(transport) = connections.get(&target) {
-                    return Ok((transport.clone(), target.clone()));
+    async fn lookup_connection(
+        &self,
+        destination: &SipAddr,
+        target: &SipAddr,
+        key: Option<&TransactionKey>,
+    ) -> Result<(SipConnection, SipAddr)> {
+        // certificates are issued for the domain, not the address it resolves to
+        let server_name = match &target.addr.host {
+            rsip::Host::Domain(domain) => Some(domain.to_string()),
+            rsip::Host::IpAddr(_) => None,
+        };
+        if !matches!(target.addr.host, rsip::Host::Domain(_)) {
+            return self
+                .connect(destination, target, server_name.as_deref(), key)
+                .await;
+        }
+        // a reliable transport fails over to the next candidate
+        let candidates = self.domain_resolver.resolve_all(target).await?;
+        let connect_timeout = self
+            .connect_timeout
+            .read()
+            .map(|timeout| *timeout)
+            .unwrap_or(DEFAULT_CONNECT_TIMEOUT)
+            / candidates.len().max(1) as u32;
+        let mut last_error = None;
+        for candidate in &candidates {
+            let connect = self.connect(destination, candidate, server_name.as_deref(), key);
+            match tokio::time::timeout(connect_timeout, connect)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(crate::Error::TransportLayerError(
+                        format!("connect timed out after {:?}", connect_timeout),
+                        candidate.clone(),
+                    ))
+                }) {
+                Ok(found) => return Ok(found),
+                Err(e) => {
+                    info!(?key, "lookup candidate {} failed: {}", candidate, e);
+                    last_error = Some(e);
                 }
             }
-            Err(e) => {
-                warn!("Failed to read connections: {:?}", e);
-                return Err(crate::Error::Error(format!(
-                    "Failed to read connections: {:?}",
-                    e
-                )));
-            }
+        }
+        Err(last_error.unwrap_or_else(|| crate::Error::DnsResolutionError(target.addr.to_string())))
+    }
+
+    async fn connect(
+        &self,
+        destination: &SipAddr,
+        target: &SipAddr,
+        server_name: Option<&str>,
+        key: Option<&TransactionKey>,
+    ) -> Result<(SipConnection, SipAddr)> {
+        debug!(?key, "lookup target: {} -> {}", destination, target);
+        if let Some(transport) = self.get_connection(target)? {
+            return Ok((transport, target.clone()));
         }
         match target.r#type {
             Some(
@@ -278,39 +668,28 @@ impl TransportLayerInner {
                 | rsip::transport::Transport::Ws
                 | rsip::transport::Transport::Wss,
             ) => {
-                let sip_connection = match target.r#type {
-                    Some(rsip::transport::Transport::Tcp) => {
-                        let connection =
-                            TcpConnection::connect(target, Some(self.cancel_token.child_token()))
-                                .await?;
-                        SipConnection::Tcp(connection)
-                    }
-                    Some(rsip::transport::Transport::Tls) => {
-                        let connection = TlsConnection::connect(
-                            target,
-                            None,
-                            Some(self.cancel_token.child_token()),
-                        )
-                        .await?;
-                        SipConnection::Tls(connection)
-                    }
-                    Some(rsip::transport::Transport::Ws | rsip::transport::Transport::Wss) => {
-                        let connection = WebSocketConnection::connect(
-                            target,
-                            Some(self.cancel_token.child_token()),
-                        )
-                        .await?;
-                        SipConnection::WebSocket(connection)
-                    }
-                    _ => {
-                        return Err(crate::Error::TransportLayerError(
-                            format!("unsupported transport type: {:?}", target.r#type),
-                            target.to_owned(),
-                        ));
-                    }
-                };
-                self.add_connection(sip_connection.clone());
-                return Ok((sip_connection, target.clone()));
+                // concurrent lookups of the same target wait for a single dial
+                let dial_lock = self
+                    .dialing
+                    .lock()
+                    .map_err(|e| crate::Error::Error(format!("Failed to lock dialing: {:?}", e)))?
+                    .entry(target.clone())
+                    .or_default()
+                    .clone();
+                let _dialing = dial_lock.lock().await;
+                if let Some(transport) = self.get_connection(target)? {
+                    return Ok((transport, target.clone()));
+                }
+                // register the connection before releasing the dial, so a
+                // lookup arriving in between finds it instead of dialing again
+                let sip_connection = self.dial(target, server_name).await;
+                if let Ok(sip_connection) = sip_connection.as_ref() {
+                    self.add_connection(sip_connection.clone());
+                }
+                if let Ok(mut dialing) = self.dialing.lock() {
+                    dialing.remove(target);
+                }
+                return Ok((sip_connection?, target.clone()));
             }
             _ => {}
         }
@@ -324,18 +703,13 @@ impl TransportLayerInner {
                 )));
             }
         };
-        let mut first_udp = None;
-        for transport in listens.iter() {
-            let addr = transport.get_addr();
-            if addr.r#type == Some(rsip::transport::Transport::Udp) && first_udp.is_none() {
-                first_udp = Some(transport.clone());
-            }
-            if addr == target {
-                return Ok((transport.clone(), target.clone()));
-            }
+        if let Some(transport) = listens.iter().find(|t| t.get_addr() == target) {
+            return Ok((transport.clone(), target.clone()));
         }
-        if let Some(transport) = first_udp {
-            return Ok((transport, target.clone()));
+        if let Some(transport) =
+            select_source(listens.iter(), rsip::transport::Transport::Udp, target)
+        {
+            return Ok((transport.clone(), target.clone()));
         }
         Err(crate::Error::TransportLayerError(
             format!("unsupported transport type: {:?}", target.r#type),
@@ -343,6 +717,55 @@ impl TransportLayerInner {
         ))
     }
 
+    fn get_connection(&self, addr: &SipAddr) -> Result<Option<SipConnection>> {
+        match self.connections.read() {
+            Ok(connections) => Ok(connections.get(addr).cloned()),
+            Err(e) => {
+                warn!("Failed to read connections: {:?}", e);
+                Err(crate::Error::Error(format!(
+                    "Failed to read connections: {:?}",
+                    e
+                )))
+            }
+        }
+    }
+
+    async fn dial(&self, target: &SipAddr, server_name: Option<&str>) -> Result<SipConnection> {
+        match target.r#type {
+            Some(rsip::transport::Transport::Tcp) => {
+                let connection =
+                    TcpConnection::connect(target, Some(self.cancel_token.child_token())).await?;
+                Ok(SipConnection::Tcp(connection))
+            }
+            Some(rsip::transport::Transport::Tls) => {
+                let tls_config = self
+                    .tls_config
+                    .read()
+                    .map(|config| config.clone())
+                    .unwrap_or_default();
+                let connection = TlsConnection::connect_with_config(
+                    target,
+                    server_name,
+                    &tls_config,
+                    None,
+                    Some(self.cancel_token.child_token()),
+                )
+                .await?;
+                Ok(SipConnection::Tls(connection))
+            }
+            Some(rsip::transport::Transport::Ws | rsip::transport::Transport::Wss) => {
+                let connection =
+                    WebSocketConnection::connect(target, Some(self.cancel_token.child_token()))
+                        .await?;
+                Ok(SipConnection::WebSocket(connection))
+            }
+            _ => Err(crate::Error::TransportLayerError(
+                format!("unsupported transport type: {:?}", target.r#type),
+                target.to_owned(),
+            )),
+        }
+    }
+
     pub(super) async fn serve_listener(self: Arc<Self>, transport: SipConnection) -> Result<()> {
         let sender = self.transport_tx.clone();
         match transport {
@@ -350,6 +773,11 @@ impl TransportLayerInner {
                 tokio::spawn(async move { transport.serve_loop(sender).await });
                 Ok(())
             }
+            #[cfg(any(test, feature = "test-util"))]
+            SipConnection::Loopback(transport) => {
+                tokio::spawn(async move { transport.serve_loop(sender).await });
+                Ok(())
+            }
             SipConnection::TcpListener(connection) => connection.serve_listener(self.clone()).await,
             #[cfg(feature = "rustls")]
             SipConnection::TlsListener(connection) => connection.serve_listener(self.clone()).await,
@@ -371,6 +799,7 @@ impl TransportLayerInner {
     pub fn serve_connection(&self, transport: SipConnection) {
         let sub_token = self.cancel_token.child_token();
         let sender_clone = self.transport_tx.clone();
+        let connections = self.connections.clone();
         tokio::spawn(async move {
             match sender_clone.send(TransportEvent::New(transport.clone())) {
                 Ok(()) => {}
@@ -385,11 +814,54 @@ impl TransportLayerInner {
                 }
             }
             info!(addr=%transport.get_addr(), "transport serve_loop exited");
+            if let Ok(mut connections) = connections.write() {
+                connections.remove(transport.get_addr());
+            }
             transport.close().await.ok();
             sender_clone.send(TransportEvent::Closed(transport)).ok();
         });
     }
 }
+
+/// Local address the OS would send to `destination` from
+///
+/// Connecting a UDP socket only looks up the route, nothing is sent.
+fn routed_source_ip(destination: SocketAddr) -> Option<IpAddr> {
+    let unspecified = match destination.ip() {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = std::net::UdpSocket::bind((unspecified, 0)).ok()?;
+    socket.connect(destination).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// Pick the listener of `transport` to send to `destination` from
+///
+/// The OS routing table decides: the listener bound to the address it would
+/// send from wins, so a dual-homed host answers an internal peer from its
+/// internal interface. When no listener is bound to that address, or the
+/// destination is not an IP address, the first listener added is used.
+pub fn select_source<'a>(
+    listeners: impl Iterator<Item = &'a SipConnection>,
+    transport: rsip::transport::Transport,
+    destination: &SipAddr,
+) -> Option<&'a SipConnection> {
+    let source_ip = destination.get_socketaddr().ok().and_then(routed_source_ip);
+    let mut first = None;
+    for listener in listeners {
+        let addr = listener.get_addr();
+        if addr.r#type.unwrap_or_default() != transport {
+            continue;
+        }
+        if source_ip.is_some_and(|ip| addr.addr.host == rsip::Host::IpAddr(ip)) {
+            return Some(listener);
+        }
+        first = first.or(Some(listener));
+    }
+    first
+}
+
 impl Drop for TransportLayer {
     fn drop(&mut self) {
         self.inner.cancel_token.cancel();
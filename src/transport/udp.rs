@@ -2,7 +2,8 @@ use super::{connection::TransportSender, SipAddr, SipConnection};
 use crate::{
     transport::{
         connection::{KEEPALIVE_REQUEST, KEEPALIVE_RESPONSE, MAX_UDP_BUF_SIZE},
-        TransportEvent,
+        stream::{parse_content_length, MAX_SIP_MESSAGE_SIZE},
+        stun, TransportEvent,
     },
     Result,
 };
@@ -11,6 +12,46 @@ use std::{net::SocketAddr, sync::Arc};
 use tokio::net::UdpSocket;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
+/// Split a datagram into the SIP messages it carries
+///
+/// One message per datagram is the norm, but some peers and tunnels
+/// coalesce several; each one is framed by its Content-Length. Without a
+/// Content-Length, or with one running past the datagram, the message takes
+/// the rest of the datagram (RFC 3261 §18.3). Messages over the maximum
+/// message size end the split.
+pub(crate) fn split_datagram(mut data: &[u8]) -> Vec<&[u8]> {
+    let mut messages = vec![];
+    loop {
+        let start = data
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .unwrap_or(data.len());
+        data = &data[start..];
+        if data.is_empty() {
+            break;
+        }
+        let total_len = data
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .and_then(|headers_end| {
+                let header_len = headers_end + 4;
+                parse_content_length(&data[..header_len])
+                    .ok()
+                    .flatten()
+                    .map(|content_length| header_len + content_length)
+            })
+            .filter(|total_len| *total_len <= data.len())
+            .unwrap_or(data.len());
+        if total_len > MAX_SIP_MESSAGE_SIZE {
+            break;
+        }
+        let (message, rest) = data.split_at(total_len);
+        messages.push(message);
+        data = rest;
+    }
+    messages
+}
+
 pub struct UdpInner {
     pub conn: UdpSocket,
     pub addr: SipAddr,
@@ -92,6 +133,13 @@ impl UdpConnection {
                 }
             };
 
+            if stun::is_stun_message(&buf[..len]) {
+                if let Some(response) = stun::binding_response(&buf[..len], addr) {
+                    self.inner.conn.send_to(&response, addr).await.ok();
+                }
+                continue;
+            }
+
             match &buf[..len] {
                 KEEPALIVE_REQUEST => {
                     self.inner.conn.send_to(KEEPALIVE_RESPONSE, addr).await.ok();
@@ -105,58 +153,55 @@ impl UdpConnection {
                 }
             }
 
-            let undecoded = match std::str::from_utf8(&buf[..len]) {
-                Ok(s) => s,
-                Err(e) => {
-                    debug!(
-                        "decoding text from: {} error: {} buf: {:?}",
-                        addr,
-                        e,
-                        &buf[..len]
-                    );
-                    continue;
-                }
-            };
+            for data in split_datagram(&buf[..len]) {
+                let undecoded = match std::str::from_utf8(data) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        debug!("decoding text from: {} error: {} buf: {:?}", addr, e, data);
+                        continue;
+                    }
+                };
 
-            let msg = match rsip::SipMessage::try_from(undecoded) {
-                Ok(msg) => msg,
-                Err(e) => {
-                    info!(
-                        "error parsing SIP message from: {} error: {} buf: {}",
-                        addr, e, undecoded
-                    );
-                    continue;
-                }
-            };
+                let msg = match rsip::SipMessage::try_from(undecoded) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        info!(
+                            "error parsing SIP message from: {} error: {} buf: {}",
+                            addr, e, undecoded
+                        );
+                        continue;
+                    }
+                };
 
-            let msg = match SipConnection::update_msg_received(
-                msg,
-                addr,
-                rsip::transport::Transport::Udp,
-            ) {
-                Ok(msg) => msg,
-                Err(e) => {
-                    info!(
-                        "error updating SIP via from: {} error: {:?} buf: {}",
-                        addr, e, undecoded
-                    );
-                    continue;
-                }
-            };
+                let msg = match SipConnection::update_msg_received(
+                    msg,
+                    addr,
+                    rsip::transport::Transport::Udp,
+                ) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        info!(
+                            "error updating SIP via from: {} error: {:?} buf: {}",
+                            addr, e, undecoded
+                        );
+                        continue;
+                    }
+                };
+
+                debug!(
+                    len = data.len(), src=%addr,dest=%self.get_addr(), message=undecoded,
+                    "udp received"
+                );
 
-            debug!(
-                len, src=%addr,dest=%self.get_addr(), message=undecoded,
-                "udp received"
-            );
-
-            sender.send(TransportEvent::Incoming(
-                msg,
-                SipConnection::Udp(self.clone()),
-                SipAddr {
-                    r#type: Some(rsip::transport::Transport::Udp),
-                    addr: addr.into(),
-                },
-            ))?;
+                sender.send(TransportEvent::Incoming(
+                    msg,
+                    SipConnection::Udp(self.clone()),
+                    SipAddr {
+                        r#type: Some(rsip::transport::Transport::Udp),
+                        addr: addr.into(),
+                    },
+                ))?;
+            }
         }
     }
 
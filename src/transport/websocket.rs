@@ -1,7 +1,7 @@
 use crate::{
     transport::{
         connection::{TransportSender, KEEPALIVE_REQUEST, KEEPALIVE_RESPONSE},
-        sip_addr::SipAddr,
+        sip_addr::{default_port, SipAddr},
         stream::StreamConnection,
         transport_layer::TransportLayerInnerRef,
         SipConnection, TransportEvent,
@@ -190,9 +190,9 @@ impl WebSocketConnection {
         remote: &SipAddr,
         cancel_token: Option<CancellationToken>,
     ) -> Result<Self> {
-        let scheme = match remote.r#type {
-            Some(rsip::transport::Transport::Wss) => "wss",
-            _ => "ws",
+        let (scheme, transport) = match remote.r#type {
+            Some(rsip::transport::Transport::Wss) => ("wss", rsip::transport::Transport::Wss),
+            _ => ("ws", rsip::transport::Transport::Ws),
         };
 
         let host = match &remote.addr.host {
@@ -200,7 +200,11 @@ impl WebSocketConnection {
             rsip::host_with_port::Host::IpAddr(ip) => ip.to_string(),
         };
 
-        let port = remote.addr.port.as_ref().map_or(5060, |p| *p.value());
+        let port = remote
+            .addr
+            .port
+            .as_ref()
+            .map_or(default_port(Some(transport)), |p| *p.value());
 
         let url = format!("{}://{}:{}/", scheme, host, port);
         let mut request = url.into_client_request()?;
@@ -231,6 +235,17 @@ impl WebSocketConnection {
     pub fn cancel_token(&self) -> Option<CancellationToken> {
         self.cancel_token.clone()
     }
+
+    async fn send_keepalive_response(&self) -> Result<()> {
+        let mut sink = self.inner.ws_sink.lock().await;
+        sink.send(Message::Text(
+            String::from_utf8_lossy(KEEPALIVE_RESPONSE)
+                .into_owned()
+                .into(),
+        ))
+        .await?;
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -249,7 +264,13 @@ impl StreamConnection for WebSocketConnection {
 
     async fn send_raw(&self, data: &[u8]) -> Result<()> {
         let mut sink = self.inner.ws_sink.lock().await;
-        sink.send(Message::Binary(data.to_vec().into())).await?;
+        // WebSocket has its own keepalive, the double-CRLF ping is for raw streams
+        let message = if data == KEEPALIVE_REQUEST {
+            Message::Ping(Vec::new().into())
+        } else {
+            Message::Binary(data.to_vec().into())
+        };
+        sink.send(message).await?;
         Ok(())
     }
 
@@ -266,58 +287,20 @@ impl StreamConnection for WebSocketConnection {
         };
         while let Some(msg) = ws_read.next().await {
             debug!(?remote_addr, "WebSocket message: {:?}", msg);
-            match msg {
-                Ok(Message::Text(text)) => match SipMessage::try_from(text.as_str()) {
-                    Ok(sip_msg) => {
-                        let remote_socket_addr = remote_addr.get_socketaddr()?;
-                        let sip_msg = SipConnection::update_msg_received(
-                            sip_msg,
-                            remote_socket_addr,
-                            remote_addr.r#type.unwrap_or_default(),
-                        )?;
-
-                        if let Err(e) = sender.send(TransportEvent::Incoming(
-                            sip_msg,
-                            sip_connection.clone(),
-                            remote_addr.clone(),
-                        )) {
-                            warn!("Error sending incoming message: {:?}", e);
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        warn!("Error parsing SIP message: {}", e);
-                    }
-                },
-                Ok(Message::Binary(bin)) => {
-                    if bin == *KEEPALIVE_REQUEST {
-                        if let Err(e) = self.send_raw(KEEPALIVE_RESPONSE).await {
-                            warn!("Error sending keepalive response: {:?}", e);
-                        }
-                        continue;
-                    }
-                    match SipMessage::try_from(bin) {
-                        Ok(sip_msg) => {
-                            if let Err(e) = sender.send(TransportEvent::Incoming(
-                                sip_msg,
-                                sip_connection.clone(),
-                                remote_addr.clone(),
-                            )) {
-                                warn!("Error sending incoming message: {:?}", e);
-                                break;
-                            }
-                        }
-                        Err(e) => {
-                            warn!("Error parsing SIP message: {}", e);
-                        }
-                    }
-                }
+            let data = match msg {
+                Ok(Message::Text(text)) => text.as_str().as_bytes().to_vec(),
+                Ok(Message::Binary(bin)) => bin.to_vec(),
                 Ok(Message::Ping(data)) => {
                     let mut sink = self.inner.ws_sink.lock().await;
                     if let Err(e) = sink.send(Message::Pong(data)).await {
                         warn!("Error sending pong: {}", e);
                         break;
                     }
+                    continue;
+                }
+                Ok(Message::Pong(_)) => {
+                    debug!(?remote_addr, "WebSocket keepalive pong");
+                    continue;
                 }
                 Ok(Message::Close(_)) => {
                     debug!("WebSocket connection closed by peer");
@@ -327,7 +310,41 @@ impl StreamConnection for WebSocketConnection {
                     warn!("WebSocket error: {}", e);
                     break;
                 }
-                _ => {}
+                _ => continue,
+            };
+
+            // some clients still send the raw stream keepalive inside a frame
+            if data == KEEPALIVE_REQUEST {
+                if let Err(e) = self.send_keepalive_response().await {
+                    warn!("Error sending keepalive response: {:?}", e);
+                }
+                continue;
+            }
+            if data == KEEPALIVE_RESPONSE {
+                continue;
+            }
+
+            let sip_msg = match SipMessage::try_from(data.as_slice()) {
+                Ok(sip_msg) => sip_msg,
+                Err(e) => {
+                    warn!("Error parsing SIP message: {}", e);
+                    continue;
+                }
+            };
+            let remote_socket_addr = remote_addr.get_socketaddr()?;
+            let sip_msg = SipConnection::update_msg_received(
+                sip_msg,
+                remote_socket_addr,
+                remote_addr.r#type.unwrap_or_default(),
+            )?;
+
+            if let Err(e) = sender.send(TransportEvent::Incoming(
+                sip_msg,
+                sip_connection.clone(),
+                remote_addr.clone(),
+            )) {
+                warn!("Error sending incoming message: {:?}", e);
+                break;
             }
         }
 